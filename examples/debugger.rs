@@ -0,0 +1,54 @@
+//! Drives the processor the way a debugger does: a breakpoint on a loop,
+//! and what each trip round the loop changed.
+//!
+//! `cargo run --example debugger`
+
+use intel_8080_emu::asm::{self, Assembly};
+use intel_8080_emu::disasm;
+use intel_8080_emu::monitor::StateDiff;
+use intel_8080_emu::processor::{Processor, ProcessorBuilder, RunOutcome, Snapshot};
+
+/// Adds 5 + 4 + 3 + 2 + 1, keeping the running total in memory.
+const SUM: &str = "
+        org 0
+        lxi sp, 1000h
+        mvi a, 0
+        mvi b, 5
+loop:   add b
+        sta total
+        dcr b
+        jnz loop
+        hlt
+total:  db 0
+";
+
+fn main() {
+    let assembly: Assembly = asm::assemble(SUM).expect("bundled program assembles");
+    let breakpoint: u16 = assembly.labels["loop"];
+    let total: u16 = assembly.labels["total"];
+
+    let mut processor: Processor = ProcessorBuilder::new().build();
+    processor.load_program(&assembly.bytes);
+    processor.add_breakpoint(breakpoint);
+    processor.set_write_journal(true);
+
+    let mut before: Snapshot = processor.snapshot();
+    loop {
+        let outcome: RunOutcome = processor.run();
+        let after: Snapshot = processor.snapshot();
+        let diff = StateDiff::between(&before, &after, &processor.take_write_journal());
+        match outcome {
+            RunOutcome::Breakpoint { pc } => {
+                let line = disasm::decode(&after.memory[pc as usize..], pc);
+                println!("break at {}", line);
+            },
+            other => println!("stopped: {:?}", other),
+        }
+        println!("  {}", diff);
+        if processor.is_halted() {
+            break;
+        }
+        before = after;
+    }
+    println!("total = {}", processor.peek(total));
+}
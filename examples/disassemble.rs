@@ -0,0 +1,46 @@
+//! Disassembles an image and checks that the listing assembles back to the
+//! same bytes.
+//!
+//! `cargo run --example disassemble [image.bin]` lists a raw image loaded
+//! at 0, or a bundled routine if no image is given.
+
+use std::env;
+use std::fs;
+
+use intel_8080_emu::asm;
+use intel_8080_emu::disasm::{self, Line};
+
+/// Copies a block of memory, with some data after it.
+const MEMCPY: &str = "
+        org 0
+memcpy: mov a, b
+        ora c
+        rz
+        ldax d
+        mov m, a
+        inx d
+        inx h
+        dcx b
+        jmp memcpy
+        db 0ddh, 0feh
+        dw 1234h
+";
+
+fn main() {
+    let image: Vec<u8> = match env::args().nth(1) {
+        Some(path) => fs::read(&path).unwrap_or_else(|err| panic!("could not read {}: {}", path, err)),
+        None => asm::assemble(MEMCPY).expect("bundled program assembles").bytes,
+    };
+
+    let lines: Vec<Line> = disasm::disassemble(&image, 0);
+    for line in &lines {
+        println!("{}", line);
+    }
+
+    let source: String = disasm::to_source(&lines, 0);
+    match asm::assemble(&source) {
+        Ok(assembly) if assembly.bytes == image => println!("; listing reassembles to the same {} bytes", image.len()),
+        Ok(_) => println!("; listing reassembles to different bytes"),
+        Err(err) => println!("; listing does not reassemble: {}", err),
+    }
+}
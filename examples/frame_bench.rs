@@ -0,0 +1,51 @@
+#![allow(clippy::needless_return)]
+
+//! Times drawing Space Invaders frames into an RGB buffer, as a frontend
+//! does 60 times a second, through `video::capture`, which copies the
+//! picture out a byte at a time, and through `video::FrameView`, which
+//! reads video memory in place, both pixel by pixel and a row at a time.
+//!
+//! `cargo run --release --example frame_bench`
+
+use std::time::{Duration, Instant};
+
+use intel_8080_emu::processor::Processor;
+use intel_8080_emu::video::{self, FrameView, SCREEN_HEIGHT, SCREEN_WIDTH, VRAM};
+
+const FRAMES: u32 = 600;
+
+/// Draws `FRAMES` frames with `draw` and returns how long they took.
+fn time(label: &str, mut draw: impl FnMut(&mut [u8])) -> Duration {
+    let mut rgb: Vec<u8> = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        draw(&mut rgb);
+    }
+    let elapsed: Duration = start.elapsed();
+    println!("{:<8} {:>8.1} us/frame", label, elapsed.as_secs_f64() * 1e6 / FRAMES as f64);
+    return elapsed;
+}
+
+fn fill(rgb: &mut [u8], pixels: impl Iterator<Item = bool>) {
+    for (pixel, lit) in rgb.chunks_exact_mut(3).zip(pixels) {
+        pixel.fill(if lit { 0xff } else { 0 });
+    }
+}
+
+fn main() {
+    let mut processor: Processor = Processor::default();
+    let pattern: Vec<u8> = (0..VRAM.len()).map(|offset| (offset * 37 % 251) as u8).collect();
+    processor.load_at(VRAM.start, &pattern);
+
+    let copying: Duration = time("capture", |rgb| fill(rgb, video::capture(|addr| processor.peek(addr)).into_iter()));
+    let viewing: Duration = time("view", |rgb| fill(rgb, FrameView::of(&processor).expect("flat memory").pixels()));
+    let by_row: Duration = time("view/row", |rgb| {
+        let view = FrameView::of(&processor).expect("flat memory");
+        for (y, row) in rgb.chunks_exact_mut(SCREEN_WIDTH * 3).enumerate() {
+            fill(row, view.row(y));
+        }
+    });
+    for (label, elapsed) in [("view", viewing), ("view/row", by_row)] {
+        println!("{} takes {:.2}x the time of capture", label, elapsed.as_secs_f64() / copying.as_secs_f64());
+    }
+}
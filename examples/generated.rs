@@ -0,0 +1,71 @@
+#![allow(clippy::needless_return)]
+
+//! Builds a program in Rust instead of assembling text: an unrolled table
+//! of the first Fibonacci numbers, with each entry worked out at generation
+//! time, then a loop that adds the table up.
+//!
+//! `cargo run --example generated`
+
+use intel_8080_emu::instruction::{Condition, Instruction, Reg, RegPair};
+use intel_8080_emu::processor::{Processor, ProcessorBuilder, RunOutcome};
+
+const ORIGIN: u16 = 0x0100;
+
+/// Where the generated code puts the table.
+const TABLE: u16 = 0x0200;
+
+/// Fibonacci numbers that fit in a byte.
+const ENTRIES: u8 = 13;
+
+/// Bytes `instructions` take up once encoded.
+fn encoded_len(instructions: &[Instruction]) -> u16 {
+    return instructions.iter().map(|instruction| instruction.encode().unwrap().len() as u16).sum();
+}
+
+fn generate() -> Vec<Instruction> {
+    let mut program: Vec<Instruction> = vec![Instruction::Lxi(RegPair::H, TABLE)];
+    let (mut previous, mut current): (u8, u8) = (0, 1);
+    for _ in 0..ENTRIES {
+        program.push(Instruction::Mvi(Reg::M, current));
+        program.push(Instruction::Inx(RegPair::H));
+        // The sum after the last entry overflows, but is never stored.
+        (previous, current) = (current, previous.wrapping_add(current));
+    }
+
+    // Sum the table into DE, counting down in C.
+    program.extend([
+        Instruction::Lxi(RegPair::H, TABLE),
+        Instruction::Lxi(RegPair::D, 0),
+        Instruction::Mvi(Reg::C, ENTRIES),
+    ]);
+    let sum_loop: u16 = ORIGIN + encoded_len(&program);
+    program.extend([
+        Instruction::Mov(Reg::A, Reg::E),
+        Instruction::Add(Reg::M),
+        Instruction::Mov(Reg::E, Reg::A),
+        Instruction::Mvi(Reg::A, 0),
+        Instruction::Adc(Reg::D),
+        Instruction::Mov(Reg::D, Reg::A),
+        Instruction::Inx(RegPair::H),
+        Instruction::Dcr(Reg::C),
+        Instruction::Jcond(Condition::Nz, sum_loop),
+        Instruction::Hlt,
+    ]);
+    return program;
+}
+
+fn main() {
+    let program: Vec<Instruction> = generate();
+    let mut processor: Processor = ProcessorBuilder::new().build();
+    let end: u16 = processor.load_instructions(&program, ORIGIN).expect("generated program encodes");
+    println!("{} instructions, {} bytes at {:04x}h", program.len(), end - ORIGIN, ORIGIN);
+
+    processor.set_pc(ORIGIN);
+    assert_eq!(processor.run(), RunOutcome::Halted);
+
+    let table: Vec<u8> = (0..ENTRIES as u16).map(|offset| processor.peek(TABLE + offset)).collect();
+    let sum: u16 = processor.registers().de();
+    println!("table: {:?}", table);
+    println!("sum: {}", sum);
+    assert_eq!(sum, table.iter().map(|value| *value as u16).sum::<u16>());
+}
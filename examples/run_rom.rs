@@ -0,0 +1,49 @@
+//! Runs a guest program with a console on port 1 and reports what it
+//! printed and how long it took.
+//!
+//! `cargo run --example run_rom [image.bin]` runs a raw image loaded at 0, or
+//! a bundled greeting program if no image is given.
+
+use std::env;
+use std::fs;
+
+use intel_8080_emu::asm;
+use intel_8080_emu::devices::Console;
+use intel_8080_emu::processor::{Metrics, Processor, ProcessorBuilder, RunOutcome};
+
+const GREETING: &str = "
+        org 0
+        lxi sp, 1000h
+        lxi h, message
+next:   mov a, m
+        cpi 0
+        jz done
+        out 1
+        inx h
+        jmp next
+done:   hlt
+message:
+        db 48h, 65h, 6ch, 6ch, 6fh, 2ch, 20h, 38h, 30h, 38h, 30h, 0ah, 0
+";
+
+const MAX_INSTRUCTIONS: u64 = 10_000_000;
+
+fn main() {
+    let image: Vec<u8> = match env::args().nth(1) {
+        Some(path) => fs::read(&path).unwrap_or_else(|err| panic!("could not read {}: {}", path, err)),
+        None => asm::assemble(GREETING).expect("bundled program assembles").bytes,
+    };
+
+    let console = Console::new();
+    let mut processor: Processor = ProcessorBuilder::new()
+        .device(&[1], Box::new(console.clone()))
+        .build();
+    processor.load_program(&image);
+
+    let outcome: RunOutcome = processor.run_with_limit(MAX_INSTRUCTIONS);
+    let metrics: Metrics = processor.metrics_snapshot();
+
+    print!("{}", console.text());
+    println!("stopped: {:?}", outcome);
+    println!("{} instructions, {} cycles, {} bytes written to ports", metrics.instructions, metrics.cycles, metrics.output_bytes);
+}
@@ -0,0 +1,69 @@
+//! Runs a guest that draws into Space Invaders video memory and renders the
+//! first video frame as text, with no window.
+//!
+//! `cargo run --example space_invaders_headless`
+
+use std::sync::{Arc, Mutex};
+
+use intel_8080_emu::asm;
+use intel_8080_emu::processor::{Processor, ProcessorBuilder};
+use intel_8080_emu::video::{self, CYCLES_PER_FRAME};
+
+/// Frames the screen with a border and draws a block in the middle. Each
+/// column of video memory is 32 bytes, bottom pixel first.
+const PICTURE: &str = "
+        org 0
+        lxi sp, 2400h
+        lxi h, 2400h
+        lxi d, 31
+        mvi c, 224
+edges:  mvi m, 01h
+        dad d
+        mvi m, 80h
+        inx h
+        dcr c
+        jnz edges
+        lxi h, 2400h
+        call column
+        lxi h, 3fe0h
+        call column
+        lxi h, 300ch
+        mvi c, 32
+block:  mvi b, 8
+row:    mvi m, 0ffh
+        inx h
+        dcr b
+        jnz row
+        lxi d, 24
+        dad d
+        dcr c
+        jnz block
+done:   jmp done
+column: mvi b, 32
+fill:   mvi m, 0ffh
+        inx h
+        dcr b
+        jnz fill
+        ret
+";
+
+/// Enough for one frame and then some.
+const MAX_INSTRUCTIONS: u64 = 100_000;
+
+fn main() {
+    let program: Vec<u8> = asm::assemble(PICTURE).expect("bundled program assembles").bytes;
+    let mut processor: Processor = ProcessorBuilder::new().build();
+    processor.load_program(&program);
+
+    let first_frame: Arc<Mutex<Option<Vec<bool>>>> = Arc::new(Mutex::new(None));
+    let sink = first_frame.clone();
+    processor.set_frame_callback(CYCLES_PER_FRAME, Some(Box::new(move |frame, processor: &Processor| {
+        if frame == 1 {
+            *sink.lock().unwrap() = Some(video::capture(|addr| processor.peek(addr)));
+        }
+    })));
+    processor.run_with_limit(MAX_INSTRUCTIONS);
+
+    let pixels: Vec<bool> = first_frame.lock().unwrap().take().expect("the guest ran for a whole frame");
+    print!("{}", video::to_ascii(&pixels, 4, 8));
+}
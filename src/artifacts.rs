@@ -0,0 +1,423 @@
+//! Collecting what is needed to debug a run that ended badly, without
+//! running it again. When a run faults, fails its verdict or runs out of
+//! time or instructions, an `ArtifactCollector` writes the state, a
+//! snapshot, the fault report, the last traced instructions, the event log
+//! and memory dumps into a fresh directory named after the time, with a
+//! `manifest.json` listing them. Collection is best effort: an artifact
+//! that cannot be written is noted in the manifest and the rest still are.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::checkpoint;
+use crate::devices::DateTime;
+use crate::formats::{self, EventRecord, StateDump};
+use crate::monitor;
+use crate::processor::{EmuError, EventLog, Processor, Registers, RunOutcome, TraceRecord, Tracer};
+
+/// Traced instructions a `TraceTail` keeps unless told otherwise.
+pub const DEFAULT_TRACE_TAIL: usize = 256;
+
+/// Bytes in the stack and code memory dumps.
+const DUMP_LEN: u16 = 256;
+
+/// How a run ended badly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Failure {
+    /// A diagnostic stopped the run, e.g. strict mode on an invalid opcode.
+    Fault,
+    /// The run's verdict, such as `--assert`, failed it.
+    Postcondition,
+    /// The wall-clock deadline passed.
+    Deadline,
+    /// The instruction budget ran out, the watchdog for runs that never
+    /// halt.
+    InstructionLimit,
+    /// The program halted with interrupts enabled and nothing to wake it.
+    Stuck,
+}
+
+impl Failure {
+    pub const ALL: [Failure; 5] = [Failure::Fault, Failure::Postcondition, Failure::Deadline, Failure::InstructionLimit,
+                                   Failure::Stuck];
+
+    /// How `outcome` and its `verdict` failed, if they did. A fault wins
+    /// over the verdict it fails.
+    pub fn of(outcome: &RunOutcome, verdict: &Result<(), EmuError>) -> Option<Failure> {
+        return match (outcome, verdict) {
+            (RunOutcome::Fault(_), _) => Some(Failure::Fault),
+            (_, Err(_)) => Some(Failure::Postcondition),
+            (RunOutcome::DeadlineExceeded { .. }, _) => Some(Failure::Deadline),
+            (RunOutcome::InstructionLimit { .. }, _) => Some(Failure::InstructionLimit),
+            (RunOutcome::Stuck { .. }, _) => Some(Failure::Stuck),
+            _ => None,
+        };
+    }
+
+    pub fn name(self) -> &'static str {
+        return match self {
+            Failure::Fault => "fault",
+            Failure::Postcondition => "postcondition",
+            Failure::Deadline => "deadline",
+            Failure::InstructionLimit => "instruction-limit",
+            Failure::Stuck => "stuck",
+        };
+    }
+}
+
+/// Something to collect, and the file it goes in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Artifact {
+    /// `state.json`: the registers and counters as `--json` prints them.
+    State,
+    /// `snapshot.json`: the whole machine, for `resume`.
+    Snapshot,
+    /// `fault.txt`: the fault report, for faults and failed verdicts.
+    FaultReport,
+    /// `trace.txt`: the last instructions a `TraceTail` saw.
+    TraceTail,
+    /// `events.jsonl`: the event log.
+    Events,
+    /// `memory-stack.txt`: memory from SP up.
+    StackMemory,
+    /// `memory-code.txt`: memory around the PC.
+    CodeMemory,
+    /// `memory-<name>.txt`: `len` bytes from `start`.
+    Memory { name: String, start: u16, len: u16 },
+}
+
+impl Artifact {
+    pub fn file_name(&self) -> String {
+        return match self {
+            Artifact::State => "state.json".to_string(),
+            Artifact::Snapshot => "snapshot.json".to_string(),
+            Artifact::FaultReport => "fault.txt".to_string(),
+            Artifact::TraceTail => "trace.txt".to_string(),
+            Artifact::Events => "events.jsonl".to_string(),
+            Artifact::StackMemory => "memory-stack.txt".to_string(),
+            Artifact::CodeMemory => "memory-code.txt".to_string(),
+            Artifact::Memory { name, .. } => format!("memory-{}.txt", name),
+        };
+    }
+}
+
+/// What to collect for each way a run can fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactPolicy {
+    rules: BTreeMap<Failure, Vec<Artifact>>,
+}
+
+impl ArtifactPolicy {
+    /// Collects nothing; add to it with `collect`.
+    pub fn new() -> ArtifactPolicy {
+        return ArtifactPolicy { rules: BTreeMap::new() };
+    }
+
+    /// Also collects `artifacts` when a run fails with `failure`.
+    pub fn collect(mut self, failure: Failure, artifacts: &[Artifact]) -> ArtifactPolicy {
+        let rule: &mut Vec<Artifact> = self.rules.entry(failure).or_default();
+        for artifact in artifacts {
+            if !rule.contains(artifact) {
+                rule.push(artifact.clone());
+            }
+        }
+        return self;
+    }
+
+    pub fn artifacts_for(&self, failure: Failure) -> &[Artifact] {
+        return self.rules.get(&failure).map_or(&[], Vec::as_slice);
+    }
+}
+
+impl Default for ArtifactPolicy {
+    /// Everything, for every failure.
+    fn default() -> ArtifactPolicy {
+        let everything: [Artifact; 7] = [Artifact::State, Artifact::Snapshot, Artifact::FaultReport, Artifact::TraceTail,
+                                         Artifact::Events, Artifact::StackMemory, Artifact::CodeMemory];
+        return Failure::ALL.iter().fold(ArtifactPolicy::new(), |policy, failure| policy.collect(*failure, &everything));
+    }
+}
+
+/// Keeps the last traced instructions as `--trace` lines. Install
+/// `tracer()` on the processor and keep this to read them back.
+#[derive(Debug, Clone)]
+pub struct TraceTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl TraceTail {
+    pub fn new(capacity: usize) -> TraceTail {
+        return TraceTail { lines: Arc::new(Mutex::new(VecDeque::new())), capacity: capacity.max(1) };
+    }
+
+    /// A tracer feeding this tail, passing each instruction on to
+    /// `forward` too if given.
+    pub fn tracer(&self, forward: Option<Box<dyn Tracer>>) -> Box<dyn Tracer> {
+        return Box::new(TailTracer { tail: self.clone(), forward });
+    }
+
+    /// The kept lines, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        return self.lines.lock().unwrap().iter().cloned().collect();
+    }
+}
+
+#[derive(Debug)]
+struct TailTracer {
+    tail: TraceTail,
+    forward: Option<Box<dyn Tracer>>,
+}
+
+impl Tracer for TailTracer {
+    fn trace(&mut self, record: &TraceRecord, after: &Registers) {
+        let mut lines = self.tail.lines.lock().unwrap();
+        if lines.len() == self.tail.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(record.with_flag_delta(after));
+        drop(lines);
+        if let Some(forward) = &mut self.forward {
+            forward.trace(record, after);
+        }
+    }
+}
+
+/// One line of the manifest: the file written, or why it was not.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestEntry {
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// `manifest.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Manifest {
+    pub failure: &'static str,
+    /// The fault or failed verdict, or how the run stopped.
+    pub reason: String,
+    pub created: String,
+    pub artifacts: Vec<ManifestEntry>,
+}
+
+/// Writes artifacts for failed runs into a new directory under `dir`.
+#[derive(Debug)]
+pub struct ArtifactCollector {
+    dir: PathBuf,
+    policy: ArtifactPolicy,
+    trace: Option<TraceTail>,
+    events: Option<EventLog>,
+}
+
+impl ArtifactCollector {
+    pub fn new(dir: impl Into<PathBuf>, policy: ArtifactPolicy) -> ArtifactCollector {
+        return ArtifactCollector { dir: dir.into(), policy, trace: None, events: None };
+    }
+
+    /// Where `Artifact::TraceTail` comes from.
+    pub fn trace_tail(mut self, tail: TraceTail) -> ArtifactCollector {
+        self.trace = Some(tail);
+        return self;
+    }
+
+    /// Where `Artifact::Events` comes from.
+    pub fn events(mut self, log: EventLog) -> ArtifactCollector {
+        self.events = Some(log);
+        return self;
+    }
+
+    /// Collects what the policy asks for if the run failed, returning the
+    /// directory written. None if the run did not fail or the policy wants
+    /// nothing for how it failed; an error only if the directory itself
+    /// could not be made.
+    pub fn collect(&self, processor: &Processor, outcome: &RunOutcome, verdict: &Result<(), EmuError>)
+                   -> Option<io::Result<PathBuf>> {
+        let failure: Failure = Failure::of(outcome, verdict)?;
+        let artifacts: &[Artifact] = self.policy.artifacts_for(failure);
+        if artifacts.is_empty() {
+            return None;
+        }
+        return Some(self.write_all(processor, outcome, verdict, failure, artifacts));
+    }
+
+    fn write_all(&self, processor: &Processor, outcome: &RunOutcome, verdict: &Result<(), EmuError>, failure: Failure,
+                 artifacts: &[Artifact]) -> io::Result<PathBuf> {
+        let seconds: u64 = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+        let created: DateTime = DateTime::from_unix(seconds);
+        let path: PathBuf = self.create_dir(&created, failure)?;
+        let error: Option<&EmuError> = match (outcome, verdict) {
+            (RunOutcome::Fault(error), _) | (_, Err(error)) => Some(error),
+            _ => None,
+        };
+        let entries: Vec<ManifestEntry> = artifacts.iter().map(|artifact| ManifestEntry {
+            file: artifact.file_name(),
+            error: self.write(&path.join(artifact.file_name()), artifact, processor, error).err(),
+        }).collect();
+        let manifest = Manifest {
+            failure: failure.name(),
+            reason: error.map_or_else(|| format!("{:?}", outcome), EmuError::to_string),
+            created: created.to_string(),
+            artifacts: entries,
+        };
+        fs::write(path.join("manifest.json"), serde_json::to_string_pretty(&manifest).expect("manifests always serialize"))?;
+        return Ok(path);
+    }
+
+    /// `dir/20261016T104512Z-fault`, or with `-2` and so on after it if
+    /// that is taken.
+    fn create_dir(&self, created: &DateTime, failure: Failure) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let name: String = format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z-{}", created.year, created.month, created.day,
+                                   created.hour, created.minute, created.second, failure.name());
+        for attempt in 1.. {
+            let path: PathBuf = match attempt {
+                1 => self.dir.join(&name),
+                _ => self.dir.join(format!("{}-{}", name, attempt)),
+            };
+            match fs::create_dir(&path) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                result => return result.map(|()| path),
+            }
+        }
+        unreachable!("some attempt gets a fresh name");
+    }
+
+    fn write(&self, path: &Path, artifact: &Artifact, processor: &Processor, error: Option<&EmuError>) -> Result<(), String> {
+        let text: String = match artifact {
+            Artifact::State => formats::to_json_line(&StateDump::of(processor)),
+            Artifact::Snapshot => return checkpoint::save(&processor.snapshot(), path).map_err(|err| err.to_string()),
+            Artifact::FaultReport => match (error, processor.fault_report()) {
+                (Some(EmuError::PostconditionFailed { report: Some(report), .. }), _) => report.to_string(),
+                (Some(_), Some(report)) => report.to_string(),
+                _ => return Err("no fault report: the run did not fault".to_string()),
+            },
+            Artifact::TraceTail => match &self.trace {
+                Some(tail) => tail.lines().iter().map(|line| format!("{}\n", line)).collect(),
+                None => return Err("no trace tail was kept".to_string()),
+            },
+            Artifact::Events => match &self.events {
+                Some(log) => log.events().iter().map(|event| format!("{}\n", formats::to_json_line(&EventRecord::of(event)))).collect(),
+                None => return Err("no event log was kept".to_string()),
+            },
+            Artifact::StackMemory => dump(processor, processor.registers().sp, DUMP_LEN),
+            Artifact::CodeMemory => dump(processor, processor.pc().saturating_sub(DUMP_LEN / 2) & 0xfff0, DUMP_LEN),
+            Artifact::Memory { start, len, .. } => dump(processor, *start, *len),
+        };
+        return fs::write(path, text).map_err(|err| err.to_string());
+    }
+}
+
+fn dump(processor: &Processor, start: u16, len: u16) -> String {
+    let mut text: Vec<u8> = Vec::new();
+    monitor::dump_memory(processor, start, len, &mut text).expect("writing to a Vec cannot fail");
+    return String::from_utf8(text).expect("dumps are ASCII");
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.name());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    use crate::processor::ProcessorBuilder;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir: PathBuf = env::temp_dir().join(format!("artifacts-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        return dir;
+    }
+
+    /// Runs into the undocumented opcode 0DDh under strict mode, with the
+    /// trace tail and an event log kept.
+    fn faulting_run() -> (Processor, RunOutcome, TraceTail, EventLog) {
+        let tail = TraceTail::new(4);
+        let log = EventLog::default();
+        let mut processor: Processor = ProcessorBuilder::new().strict(true).build();
+        processor.set_tracer(Some(tail.tracer(None)));
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        processor.load_program(&[
+            0x31, 0x00, 0x20, // 0000 LXI SP,2000h
+            0x3e, 0x42,       // 0003 MVI A,42h
+            0xd3, 0x01,       // 0005 OUT 1
+            0xcd, 0x0c, 0x00, // 0007 CALL 000Ch
+            0x76,             // 000a HLT
+            0x00,
+            0x3c,             // 000c INR A
+            0xdd, 0x00, 0x00, // 000d CALL 0000h alias
+        ]);
+        let outcome: RunOutcome = processor.run();
+        return (processor, outcome, tail, log);
+    }
+
+    #[test]
+    fn test_strict_fault_collects_everything() {
+        let dir: PathBuf = scratch_dir("fault");
+        let (processor, outcome, tail, log) = faulting_run();
+        let collector = ArtifactCollector::new(&dir, ArtifactPolicy::default()).trace_tail(tail).events(log);
+
+        let path: PathBuf = collector.collect(&processor, &outcome, &Ok(())).unwrap().unwrap();
+
+        assert!(path.file_name().unwrap().to_str().unwrap().ends_with("Z-fault"));
+        let read = |name: &str| fs::read_to_string(path.join(name)).unwrap();
+        assert!(read("fault.txt").starts_with("fault: invalid opcode 0xdd at 0x000d (call 0000h alias)\n"));
+        assert!(read("fault.txt").contains("  #0 000c called from 0007"));
+        assert!(read("state.json").contains("\"a\":67"));
+        assert_eq!(checkpoint::load(&path.join("snapshot.json")).unwrap(), processor.snapshot());
+        let trace: String = read("trace.txt");
+        assert_eq!(trace.lines().count(), 4);
+        assert!(trace.lines().next().unwrap().contains("out 01h"));
+        assert!(trace.lines().last().unwrap().contains("db 0ddh"));
+        assert!(read("events.jsonl").contains("\"kind\":\"port_write\""));
+        assert!(read("memory-stack.txt").starts_with("1ffe: "));
+        assert!(read("memory-code.txt").starts_with("0000: 31 00 20 3e 42"));
+
+        let manifest: serde_json::Value = serde_json::from_str(&read("manifest.json")).unwrap();
+        assert_eq!(manifest["failure"], "fault");
+        assert_eq!(manifest["reason"], "invalid opcode 0xdd at 0x000d (call 0000h alias)");
+        let files: Vec<&str> = manifest["artifacts"].as_array().unwrap().iter().map(|entry| entry["file"].as_str().unwrap()).collect();
+        assert_eq!(files, ["state.json", "snapshot.json", "fault.txt", "trace.txt", "events.jsonl", "memory-stack.txt",
+                           "memory-code.txt"]);
+        assert!(manifest["artifacts"].as_array().unwrap().iter().all(|entry| entry.get("error").is_none()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_sources_are_noted_not_fatal() {
+        let dir: PathBuf = scratch_dir("limit");
+        let mut processor: Processor = Processor::default();
+        processor.load_program(&[0xc3, 0x00, 0x00]); // JMP 0
+        let outcome: RunOutcome = processor.run_with_limit(10);
+        let policy = ArtifactPolicy::new().collect(Failure::InstructionLimit, &[Artifact::TraceTail, Artifact::FaultReport,
+            Artifact::Memory { name: "zero".to_string(), start: 0, len: 3 }]);
+        let collector = ArtifactCollector::new(&dir, policy);
+
+        let path: PathBuf = collector.collect(&processor, &outcome, &Ok(())).unwrap().unwrap();
+        let again: PathBuf = collector.collect(&processor, &outcome, &Ok(())).unwrap().unwrap();
+
+        assert_ne!(path, again);
+        assert_eq!(fs::read_to_string(path.join("memory-zero.txt")).unwrap(), "0000: c3 00 00\n");
+        assert!(!path.join("trace.txt").exists());
+        let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(path.join("manifest.json")).unwrap()).unwrap();
+        assert_eq!(manifest["artifacts"][0]["error"], "no trace tail was kept");
+        assert_eq!(manifest["artifacts"][1]["error"], "no fault report: the run did not fault");
+        assert_eq!(manifest["reason"], "InstructionLimit { instructions: 10 }");
+
+        assert!(collector.collect(&processor, &RunOutcome::Halted, &Ok(())).is_none());
+        assert!(collector.collect(&processor, &RunOutcome::DeadlineExceeded { instructions: 10 }, &Ok(())).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
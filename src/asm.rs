@@ -0,0 +1,456 @@
+//! A small two-pass 8080 assembler for test programs.
+//!
+//! Accepts the syntax used by the fixtures in `tests/` and emitted by
+//! `disasm`: one statement per line, optional `label:` prefix, `;` comments,
+//! case-insensitive mnemonics, `bc`/`de`/`hl` as aliases for `b`/`d`/`h`, and
+//! numbers in decimal, `0x..`, or with an `h`, `d`, `o`/`q`, or `b` suffix.
+//! Directives are `org`, `db`, `dw`, `ds`, and `name equ value`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::disasm::MNEMONICS;
+use crate::word::{hi, lo};
+
+/// An assembled image and the labels it defines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub labels: HashMap<String, u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "line {}: {}", self.line, self.message);
+    }
+}
+
+impl Error for AsmError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Immediate {
+    None,
+    Byte,
+    Word,
+}
+
+/// Maps an instruction shape like `mvi b, {n}` to its opcode.
+fn opcode_table() -> HashMap<String, (u8, Immediate)> {
+    let mut table: HashMap<String, (u8, Immediate)> = HashMap::new();
+    for (opcode, template) in MNEMONICS.iter().enumerate() {
+        if template.is_empty() {
+            continue;
+        }
+        let immediate: Immediate = if template.contains("{8}") {
+            Immediate::Byte
+        } else if template.contains("{16}") {
+            Immediate::Word
+        } else {
+            Immediate::None
+        };
+        let key: String = template.replace("{8}", "{n}").replace("{16}", "{n}");
+        table.insert(key, (opcode as u8, immediate));
+    }
+    return table;
+}
+
+fn register_name(operand: &str) -> Option<&'static str> {
+    return match operand.to_ascii_lowercase().as_str() {
+        "a" => Some("a"),
+        "b" | "bc" => Some("b"),
+        "c" => Some("c"),
+        "d" | "de" => Some("d"),
+        "e" => Some("e"),
+        "h" | "hl" => Some("h"),
+        "l" => Some("l"),
+        "m" => Some("m"),
+        "sp" => Some("sp"),
+        "psw" => Some("psw"),
+        _ => None,
+    };
+}
+
+/// Removes a `;` comment, ignoring semicolons inside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &line[..index],
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            _ => {},
+        }
+    }
+    return line;
+}
+
+/// Splits operands on commas outside quotes.
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands: Vec<String> = Vec::new();
+    let mut current: String = String::new();
+    let mut quote: Option<char> = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (None, ',') => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            },
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            _ => {},
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !operands.is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    return operands;
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    return match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' || first == '.' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        },
+        _ => false,
+    };
+}
+
+fn parse_literal(text: &str) -> Option<i64> {
+    let lower: String = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        return i64::from_str_radix(hex, 16).ok();
+    }
+    let (digits, radix): (&str, u32) = match lower.chars().last()? {
+        'h' => (&lower[..lower.len() - 1], 16),
+        'd' => (&lower[..lower.len() - 1], 10),
+        'o' | 'q' => (&lower[..lower.len() - 1], 8),
+        'b' => (&lower[..lower.len() - 1], 2),
+        _ => (lower.as_str(), 10),
+    };
+    return i64::from_str_radix(digits, radix).ok();
+}
+
+struct Assembler {
+    opcodes: HashMap<String, (u8, Immediate)>,
+    labels: HashMap<String, u16>,
+    /// Set on the final pass, when every label must be defined.
+    resolve: bool,
+    origin: Option<u16>,
+    pc: u16,
+    bytes: Vec<u8>,
+    line: usize,
+}
+
+impl Assembler {
+    fn error(&self, message: String) -> AsmError {
+        return AsmError { line: self.line, message };
+    }
+
+    fn term(&self, text: &str) -> Result<i64, AsmError> {
+        let text: &str = text.trim();
+        if text == "$" {
+            return Ok(self.pc as i64);
+        }
+        let chars: Vec<char> = text.chars().collect();
+        if chars.len() == 3 && (chars[0] == '\'' || chars[0] == '"') && chars[2] == chars[0] {
+            return Ok(chars[1] as i64);
+        }
+        if text.starts_with(|c: char| c.is_ascii_digit()) {
+            return parse_literal(text).ok_or_else(|| self.error(format!("invalid number '{}'", text)));
+        }
+        if is_identifier(text) {
+            return match self.labels.get(text) {
+                Some(value) => Ok(*value as i64),
+                None if !self.resolve => Ok(0),
+                None => Err(self.error(format!("undefined label '{}'", text))),
+            };
+        }
+        return Err(self.error(format!("invalid operand '{}'", text)));
+    }
+
+    /// Evaluates terms joined by `+` and `-`.
+    fn expression(&self, text: &str) -> Result<i64, AsmError> {
+        let text: &str = text.trim();
+        if text.is_empty() {
+            return Err(self.error("missing operand".to_string()));
+        }
+        let mut total: i64 = 0;
+        let mut sign: i64 = 1;
+        let mut start: usize = 0;
+        let mut quote: Option<char> = None;
+        for (index, c) in text.char_indices() {
+            match (quote, c) {
+                (None, '\'') | (None, '"') => quote = Some(c),
+                (Some(open), c) if c == open => quote = None,
+                (None, '+') | (None, '-') => {
+                    let term: &str = &text[start..index];
+                    if !term.trim().is_empty() {
+                        total += sign * self.term(term)?;
+                    } else if index != 0 {
+                        return Err(self.error(format!("invalid expression '{}'", text)));
+                    }
+                    sign = if c == '-' { -1 } else { 1 };
+                    start = index + 1;
+                },
+                _ => {},
+            }
+        }
+        total += sign * self.term(&text[start..])?;
+        return Ok(total);
+    }
+
+    fn byte(&self, text: &str) -> Result<u8, AsmError> {
+        let value: i64 = self.expression(text)?;
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(format!("'{}' does not fit in a byte", text)));
+        }
+        return Ok(value as u8);
+    }
+
+    fn word(&self, text: &str) -> Result<u16, AsmError> {
+        let value: i64 = self.expression(text)?;
+        if !(-32768..=65535).contains(&value) {
+            return Err(self.error(format!("'{}' does not fit in a word", text)));
+        }
+        return Ok(value as u16);
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        if self.origin.is_none() {
+            self.origin = Some(self.pc);
+        }
+        self.bytes.extend_from_slice(bytes);
+        self.pc = self.pc.wrapping_add(bytes.len() as u16);
+    }
+
+    fn define(&mut self, label: &str, value: u16) -> Result<(), AsmError> {
+        if !is_identifier(label) {
+            return Err(self.error(format!("invalid label '{}'", label)));
+        }
+        if !self.resolve && self.labels.insert(label.to_string(), value).is_some() {
+            return Err(self.error(format!("label '{}' defined twice", label)));
+        }
+        return Ok(());
+    }
+
+    fn statement(&mut self, text: &str) -> Result<(), AsmError> {
+        let mut text: &str = strip_comment(text).trim();
+
+        if let Some((label, rest)) = text.split_once(':') {
+            if is_identifier(label.trim()) {
+                self.define(label.trim(), self.pc)?;
+                text = rest.trim();
+            }
+        }
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let (mnemonic, rest): (&str, &str) = match text.find(char::is_whitespace) {
+            Some(index) => (&text[..index], text[index..].trim()),
+            None => (text, ""),
+        };
+        if let Some((directive, value)) = rest.split_once(char::is_whitespace) {
+            if directive.eq_ignore_ascii_case("equ") {
+                let value: u16 = self.word(value)?;
+                return self.define(mnemonic, value);
+            }
+        }
+
+        let mnemonic: String = mnemonic.to_ascii_lowercase();
+        let operands: Vec<String> = split_operands(rest);
+        return match mnemonic.as_str() {
+            "org" => {
+                let origin: u16 = self.word(rest)?;
+                if self.origin.is_some() {
+                    if origin < self.pc {
+                        return Err(self.error("org moves backwards".to_string()));
+                    }
+                    self.emit(&vec![0; (origin - self.pc) as usize]);
+                }
+                self.pc = origin;
+                Ok(())
+            },
+            "db" => {
+                for operand in &operands {
+                    let quoted: bool = operand.len() >= 2
+                        && (operand.starts_with('\'') || operand.starts_with('"'))
+                        && operand.ends_with(&operand[..1]);
+                    if quoted && operand.len() != 3 {
+                        let bytes: Vec<u8> = operand[1..operand.len() - 1].bytes().collect();
+                        self.emit(&bytes);
+                    } else {
+                        let byte: u8 = self.byte(operand)?;
+                        self.emit(&[byte]);
+                    }
+                }
+                Ok(())
+            },
+            "dw" => {
+                for operand in &operands {
+                    let word: u16 = self.word(operand)?;
+                    self.emit(&[lo(word), hi(word)]);
+                }
+                Ok(())
+            },
+            "ds" => {
+                let size: u16 = self.word(rest)?;
+                self.emit(&vec![0; size as usize]);
+                Ok(())
+            },
+            _ => self.instruction(&mnemonic, &operands),
+        };
+    }
+
+    fn instruction(&mut self, mnemonic: &str, operands: &[String]) -> Result<(), AsmError> {
+        if mnemonic == "rst" && operands.len() == 1 {
+            let vector: i64 = self.expression(&operands[0])?;
+            if !(0..8).contains(&vector) {
+                return Err(self.error(format!("rst vector {} out of range", vector)));
+            }
+            self.emit(&[0xc7 | ((vector as u8) << 3)]);
+            return Ok(());
+        }
+
+        let mut immediate: Option<&str> = None;
+        let shape: Vec<&str> = operands.iter().map(|operand| {
+            return match register_name(operand) {
+                Some(register) => register,
+                None => {
+                    immediate = Some(operand);
+                    "{n}"
+                },
+            };
+        }).collect();
+        let key: String = if shape.is_empty() {
+            mnemonic.to_string()
+        } else {
+            format!("{} {}", mnemonic, shape.join(", "))
+        };
+
+        let (opcode, kind): (u8, Immediate) = match self.opcodes.get(&key) {
+            Some(entry) => *entry,
+            None => return Err(self.error(format!("unknown instruction '{}'", key))),
+        };
+        match (kind, immediate) {
+            (Immediate::Byte, Some(operand)) => {
+                let byte: u8 = self.byte(operand)?;
+                self.emit(&[opcode, byte]);
+            },
+            (Immediate::Word, Some(operand)) => {
+                let word: u16 = self.word(operand)?;
+                self.emit(&[opcode, lo(word), hi(word)]);
+            },
+            _ => self.emit(&[opcode]),
+        }
+        return Ok(());
+    }
+
+    fn pass(&mut self, source: &str) -> Result<(), AsmError> {
+        self.origin = None;
+        self.pc = 0;
+        self.bytes.clear();
+        for (index, text) in source.lines().enumerate() {
+            self.line = index + 1;
+            self.statement(text)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Assembles `source`. The image starts at the first `org`, or 0.
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let mut assembler = Assembler {
+        opcodes: opcode_table(),
+        labels: HashMap::new(),
+        resolve: false,
+        origin: None,
+        pc: 0,
+        bytes: Vec::new(),
+        line: 0,
+    };
+    assembler.pass(source)?;
+    assembler.resolve = true;
+    assembler.pass(source)?;
+
+    return Ok(Assembly {
+        origin: assembler.origin.unwrap_or(assembler.pc),
+        bytes: assembler.bytes,
+        labels: assembler.labels,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_assembles_fixtures() {
+        for name in ["add_test", "call_test", "capitalize", "dcr_test", "inr_test", "jump", "mem_test",
+                     "memcpy", "mov_test"] {
+            let source: String = fs::read_to_string(format!("tests/{}.asm", name)).unwrap();
+            let expected: Vec<u8> = fs::read(format!("tests/{}.bin", name)).unwrap();
+
+            let assembly: Assembly = assemble(&source).unwrap_or_else(|err| panic!("{}: {}", name, err));
+
+            assert_eq!(assembly.bytes, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_numbers_and_expressions() {
+        let source: &str = "
+            org 100h
+        start:
+            mvi a, 254d
+            mvi b, 0x7f
+            mvi c, 1010b
+            mvi d, 17q
+            mvi e, 'A'
+            mvi h, -1
+            lxi hl, start + 2
+            jmp $
+        size equ $ - start
+            dw size
+        ";
+
+        let assembly: Assembly = assemble(source).unwrap();
+
+        assert_eq!(assembly.origin, 0x100);
+        assert_eq!(assembly.labels["start"], 0x100);
+        assert_eq!(assembly.bytes, vec![
+            0x3e, 0xfe, 0x06, 0x7f, 0x0e, 0x0a, 0x16, 0x0f, 0x1e, 0x41, 0x26, 0xff,
+            0x21, 0x02, 0x01, 0xc3, 0x0f, 0x01, 0x12, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn test_db_strings_keep_commas_and_semicolons() {
+        let assembly: Assembly = assemble("db 'a,b;c', 0 ; trailing").unwrap();
+
+        assert_eq!(assembly.bytes, b"a,b;c\0".to_vec());
+    }
+
+    #[test]
+    fn test_errors_name_the_line() {
+        assert_eq!(assemble("nop\njmp nowhere").unwrap_err(), AsmError {
+            line: 2,
+            message: "undefined label 'nowhere'".to_string(),
+        });
+        assert_eq!(assemble("mov a, q").unwrap_err().message, "unknown instruction 'mov a, {n}'");
+        assert_eq!(assemble("mvi a, 300").unwrap_err().message, "'300' does not fit in a byte");
+        assert_eq!(assemble("frob a").unwrap_err().message, "unknown instruction 'frob a'");
+    }
+}
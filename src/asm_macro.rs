@@ -0,0 +1,309 @@
+//! `i8080_asm!`, for short test programs written as source but built at
+//! compile time. It knows every documented instruction, but has no labels,
+//! directives or expressions beyond what Rust evaluates in a `const`, so
+//! anything longer belongs in `asm`.
+
+/// Assembles 8080 source into a `[u8; N]` at compile time. Statements are
+/// separated by `;`, mnemonics and registers are lowercase, and operands
+/// are integer constants. An unknown mnemonic or register, or an operand
+/// too big for its field, is a compile error.
+///
+/// ```
+/// use intel_8080_emu::i8080_asm;
+/// use intel_8080_emu::processor::{ProcessorBuilder, RunOutcome};
+///
+/// const PROGRAM: [u8; 4] = i8080_asm! { mvi a, 5; inr a; hlt };
+///
+/// let mut processor = ProcessorBuilder::new().build();
+/// processor.load_program(&PROGRAM);
+/// assert_eq!(processor.run(), RunOutcome::Halted);
+/// assert_eq!(processor.registers().a, 6);
+/// ```
+///
+/// Mistakes stop the build:
+///
+/// ```compile_fail
+/// let program = intel_8080_emu::i8080_asm! { mvi a, 256 };
+/// ```
+///
+/// ```compile_fail
+/// let program = intel_8080_emu::i8080_asm! { ldi a, 1 };
+/// ```
+///
+/// ```compile_fail
+/// let program = intel_8080_emu::i8080_asm! { inr x };
+/// ```
+///
+/// Each statement is one step of macro recursion, so a program longer than
+/// about a hundred instructions needs `#![recursion_limit]` raised.
+#[macro_export]
+macro_rules! i8080_asm {
+    (@munch [$($b:expr,)*]) => {
+        const { $crate::asm_macro::bytes([$($crate::asm_macro::byte($b)),*]) }
+    };
+    (@munch [$($b:expr,)*] ; $($rest:tt)*) => {
+        $crate::i8080_asm!(@munch [$($b,)*] $($rest)*)
+    };
+
+    (@munch [$($b:expr,)*] mov $d:ident , $s:ident ; $($rest:tt)*) => {
+        $crate::i8080_asm!(@munch [$($b,)* $crate::i8080_asm!(@mov $d $s),] $($rest)*)
+    };
+    (@munch [$($b:expr,)*] mvi $r:ident , $n:expr ; $($rest:tt)*) => {
+        $crate::i8080_asm!(@munch [$($b,)* 0x06 | ($crate::i8080_asm!(@r $r) << 3), $n,] $($rest)*)
+    };
+    (@munch [$($b:expr,)*] lxi $rp:ident , $n:expr ; $($rest:tt)*) => {
+        $crate::i8080_asm!(@munch [$($b,)* 0x01 | $crate::i8080_asm!(@rp $rp), $crate::asm_macro::lo($n), $crate::asm_macro::hi($n),] $($rest)*)
+    };
+    (@munch [$($b:expr,)*] rst $n:expr ; $($rest:tt)*) => {
+        $crate::i8080_asm!(@munch [$($b,)* 0xc7 | ($crate::asm_macro::vector($n) << 3),] $($rest)*)
+    };
+
+    (@munch [$($b:expr,)*] adi $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm8 [$($b,)*] 0xc6, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] aci $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm8 [$($b,)*] 0xce, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] sui $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm8 [$($b,)*] 0xd6, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] sbi $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm8 [$($b,)*] 0xde, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] ani $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm8 [$($b,)*] 0xe6, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] xri $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm8 [$($b,)*] 0xee, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] ori $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm8 [$($b,)*] 0xf6, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] cpi $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm8 [$($b,)*] 0xfe, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] in $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm8 [$($b,)*] 0xdb, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] out $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm8 [$($b,)*] 0xd3, $n ; $($rest)*) };
+
+    (@munch [$($b:expr,)*] jmp $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xc3, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] jnz $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xc2, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] jz $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xca, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] jnc $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xd2, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] jc $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xda, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] jpo $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xe2, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] jpe $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xea, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] jp $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xf2, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] jm $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xfa, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] call $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xcd, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] cnz $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xc4, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] cz $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xcc, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] cnc $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xd4, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] cc $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xdc, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] cpo $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xe4, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] cpe $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xec, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] cp $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xf4, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] cm $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0xfc, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] lda $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0x3a, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] sta $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0x32, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] lhld $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0x2a, $n ; $($rest)*) };
+    (@munch [$($b:expr,)*] shld $n:expr ; $($rest:tt)*) => { $crate::i8080_asm!(@imm16 [$($b,)*] 0x22, $n ; $($rest)*) };
+
+    (@munch [$($b:expr,)*] $op:ident $r:ident ; $($rest:tt)*) => {
+        $crate::i8080_asm!(@munch [$($b,)* $crate::i8080_asm!(@unary $op $r),] $($rest)*)
+    };
+    (@munch [$($b:expr,)*] $op:ident ; $($rest:tt)*) => {
+        $crate::i8080_asm!(@munch [$($b,)* $crate::i8080_asm!(@none $op),] $($rest)*)
+    };
+    (@munch [$($b:expr,)*] $op:tt $($rest:tt)*) => {
+        compile_error!(concat!("i8080_asm: cannot assemble `", stringify!($op), " ...`"))
+    };
+
+    (@imm8 [$($b:expr,)*] $code:expr, $n:expr ; $($rest:tt)*) => {
+        $crate::i8080_asm!(@munch [$($b,)* $code, $n,] $($rest)*)
+    };
+    (@imm16 [$($b:expr,)*] $code:expr, $n:expr ; $($rest:tt)*) => {
+        $crate::i8080_asm!(@munch [$($b,)* $code, $crate::asm_macro::lo($n), $crate::asm_macro::hi($n),] $($rest)*)
+    };
+
+    (@mov m m) => { compile_error!("i8080_asm: `mov m, m` is not an instruction; its opcode is hlt") };
+    (@mov $d:ident $s:ident) => { 0x40 | ($crate::i8080_asm!(@r $d) << 3) | $crate::i8080_asm!(@r $s) };
+
+    (@unary inr $r:ident) => { 0x04 | ($crate::i8080_asm!(@r $r) << 3) };
+    (@unary dcr $r:ident) => { 0x05 | ($crate::i8080_asm!(@r $r) << 3) };
+    (@unary add $r:ident) => { 0x80 | $crate::i8080_asm!(@r $r) };
+    (@unary adc $r:ident) => { 0x88 | $crate::i8080_asm!(@r $r) };
+    (@unary sub $r:ident) => { 0x90 | $crate::i8080_asm!(@r $r) };
+    (@unary sbb $r:ident) => { 0x98 | $crate::i8080_asm!(@r $r) };
+    (@unary ana $r:ident) => { 0xa0 | $crate::i8080_asm!(@r $r) };
+    (@unary xra $r:ident) => { 0xa8 | $crate::i8080_asm!(@r $r) };
+    (@unary ora $r:ident) => { 0xb0 | $crate::i8080_asm!(@r $r) };
+    (@unary cmp $r:ident) => { 0xb8 | $crate::i8080_asm!(@r $r) };
+    (@unary inx $rp:ident) => { 0x03 | $crate::i8080_asm!(@rp $rp) };
+    (@unary dad $rp:ident) => { 0x09 | $crate::i8080_asm!(@rp $rp) };
+    (@unary dcx $rp:ident) => { 0x0b | $crate::i8080_asm!(@rp $rp) };
+    (@unary stax $rp:ident) => { 0x02 | $crate::i8080_asm!(@bd $rp) };
+    (@unary ldax $rp:ident) => { 0x0a | $crate::i8080_asm!(@bd $rp) };
+    (@unary pop $rp:ident) => { 0xc1 | $crate::i8080_asm!(@stack $rp) };
+    (@unary push $rp:ident) => { 0xc5 | $crate::i8080_asm!(@stack $rp) };
+    (@unary $op:ident $r:ident) => {
+        compile_error!(concat!("i8080_asm: unknown mnemonic `", stringify!($op), "` or missing operand"))
+    };
+
+    (@none nop) => { 0x00 };
+    (@none rlc) => { 0x07 };
+    (@none rrc) => { 0x0f };
+    (@none ral) => { 0x17 };
+    (@none rar) => { 0x1f };
+    (@none daa) => { 0x27 };
+    (@none cma) => { 0x2f };
+    (@none stc) => { 0x37 };
+    (@none cmc) => { 0x3f };
+    (@none hlt) => { 0x76 };
+    (@none rnz) => { 0xc0 };
+    (@none rz) => { 0xc8 };
+    (@none ret) => { 0xc9 };
+    (@none rnc) => { 0xd0 };
+    (@none rc) => { 0xd8 };
+    (@none rpo) => { 0xe0 };
+    (@none xthl) => { 0xe3 };
+    (@none rpe) => { 0xe8 };
+    (@none pchl) => { 0xe9 };
+    (@none xchg) => { 0xeb };
+    (@none rp) => { 0xf0 };
+    (@none di) => { 0xf3 };
+    (@none rm) => { 0xf8 };
+    (@none sphl) => { 0xf9 };
+    (@none ei) => { 0xfb };
+    (@none $op:ident) => {
+        compile_error!(concat!("i8080_asm: unknown mnemonic `", stringify!($op), "` or missing operand"))
+    };
+
+    (@r b) => { 0 };
+    (@r c) => { 1 };
+    (@r d) => { 2 };
+    (@r e) => { 3 };
+    (@r h) => { 4 };
+    (@r l) => { 5 };
+    (@r m) => { 6 };
+    (@r a) => { 7 };
+    (@r $r:ident) => { compile_error!(concat!("i8080_asm: `", stringify!($r), "` is not a register")) };
+
+    (@rp b) => { 0x00 };
+    (@rp d) => { 0x10 };
+    (@rp h) => { 0x20 };
+    (@rp sp) => { 0x30 };
+    (@rp $rp:ident) => { compile_error!(concat!("i8080_asm: `", stringify!($rp), "` is not b, d, h or sp")) };
+
+    (@bd b) => { 0x00 };
+    (@bd d) => { 0x10 };
+    (@bd $rp:ident) => { compile_error!(concat!("i8080_asm: `", stringify!($rp), "` is not b or d")) };
+
+    (@stack b) => { 0x00 };
+    (@stack d) => { 0x10 };
+    (@stack h) => { 0x20 };
+    (@stack psw) => { 0x30 };
+    (@stack $rp:ident) => { compile_error!(concat!("i8080_asm: `", stringify!($rp), "` is not b, d, h or psw")) };
+
+    ($($source:tt)*) => {
+        $crate::i8080_asm!(@munch [] $($source)* ;)
+    };
+}
+
+/// The assembled program, typed even when it is empty.
+#[doc(hidden)]
+pub const fn bytes<const N: usize>(bytes: [u8; N]) -> [u8; N] {
+    return bytes;
+}
+
+/// `value` as a byte. Evaluated in a `const`, so an operand out of range
+/// stops the build.
+#[doc(hidden)]
+pub const fn byte(value: u32) -> u8 {
+    assert!(value <= 0xff, "i8080_asm: operand does not fit in a byte");
+    return value as u8;
+}
+
+#[doc(hidden)]
+pub const fn lo(value: u32) -> u32 {
+    assert!(value <= 0xffff, "i8080_asm: operand does not fit in a word");
+    return value & 0xff;
+}
+
+#[doc(hidden)]
+pub const fn hi(value: u32) -> u32 {
+    assert!(value <= 0xffff, "i8080_asm: operand does not fit in a word");
+    return value >> 8;
+}
+
+#[doc(hidden)]
+pub const fn vector(value: u32) -> u32 {
+    assert!(value <= 7, "i8080_asm: rst vector must be 0 to 7");
+    return value;
+}
+
+#[cfg(all(test, feature = "asm"))]
+mod tests {
+    use crate::asm;
+
+    /// Asserts that the macro and the assembler agree on `source`.
+    /// `stringify!` may break lines anywhere, so only `;` ends a statement.
+    macro_rules! agree {
+        ($($source:tt)*) => {
+            let bytes: &[u8] = &crate::i8080_asm!($($source)*);
+            let text: String = stringify!($($source)*).split_whitespace().collect::<Vec<&str>>().join(" ").replace(';', "\n");
+            assert_eq!(bytes, asm::assemble(&text).unwrap().bytes, "{}", text);
+        };
+    }
+
+    #[test]
+    fn test_no_operands() {
+        agree! {
+            nop; rlc; rrc; ral; rar; daa; cma; stc; cmc; hlt; ret; xthl; pchl; xchg; di; ei; sphl;
+            rnz; rz; rnc; rc; rpo; rpe; rp; rm
+        }
+    }
+
+    #[test]
+    fn test_mov() {
+        agree! {
+            mov b, b; mov b, c; mov b, d; mov b, e; mov b, h; mov b, l; mov b, m; mov b, a;
+            mov c, b; mov c, c; mov c, d; mov c, e; mov c, h; mov c, l; mov c, m; mov c, a;
+            mov d, b; mov d, c; mov d, d; mov d, e; mov d, h; mov d, l; mov d, m; mov d, a;
+            mov e, b; mov e, c; mov e, d; mov e, e; mov e, h; mov e, l; mov e, m; mov e, a;
+            mov h, b; mov h, c; mov h, d; mov h, e; mov h, h; mov h, l; mov h, m; mov h, a;
+            mov l, b; mov l, c; mov l, d; mov l, e; mov l, h; mov l, l; mov l, m; mov l, a;
+            mov m, b; mov m, c; mov m, d; mov m, e; mov m, h; mov m, l;           mov m, a;
+            mov a, b; mov a, c; mov a, d; mov a, e; mov a, h; mov a, l; mov a, m; mov a, a;
+        }
+    }
+
+    #[test]
+    fn test_register_operands() {
+        agree! {
+            inr b; inr c; inr d; inr e; inr h; inr l; inr m; inr a;
+            dcr b; dcr c; dcr d; dcr e; dcr h; dcr l; dcr m; dcr a;
+            add b; adc c; sub d; sbb e; ana h; xra l; ora m; cmp a;
+            add a; adc a; sub a; sbb a; ana a; xra a; ora a; cmp m;
+        }
+    }
+
+    #[test]
+    fn test_register_pair_operands() {
+        agree! {
+            inx b; inx d; inx h; inx sp; dcx b; dcx d; dcx h; dcx sp;
+            dad b; dad d; dad h; dad sp; ldax b; ldax d; stax b; stax d;
+            push b; push d; push h; push psw; pop b; pop d; pop h; pop psw;
+        }
+    }
+
+    #[test]
+    fn test_immediates() {
+        agree! {
+            mvi b, 0; mvi c, 1; mvi d, 0x7f; mvi e, 0x80; mvi h, 200; mvi l, 0xff; mvi m, 0x5a; mvi a, 5;
+            adi 1; aci 2; sui 3; sbi 4; ani 0x0f; xri 0xf0; ori 0x80; cpi 0xff; in 1; out 0xfe;
+            lxi b, 0; lxi d, 0x1234; lxi h, 0x2000; lxi sp, 0xffff;
+            rst 0; rst 1; rst 2; rst 3; rst 4; rst 5; rst 6; rst 7;
+        }
+    }
+
+    #[test]
+    fn test_addresses() {
+        agree! {
+            jmp 0x0100; jnz 0x1234; jz 0; jnc 0xffff; jc 0x00ff; jpo 0x0100; jpe 0x8000; jp 0x4000; jm 0x2000;
+            call 0x0005; cnz 1; cz 2; cnc 3; cc 4; cpo 5; cpe 6; cp 7; cm 8;
+            lda 0x2000; sta 0x2001; lhld 0x3000; shld 0x3002;
+        }
+    }
+
+    #[test]
+    fn test_empty_statements_and_constants() {
+        const PORT: u32 = 0x10;
+
+        assert_eq!(crate::i8080_asm! { ; mvi a, 1;; out PORT; }, [0x3e, 0x01, 0xd3, 0x10]);
+        assert_eq!(crate::i8080_asm! {}, [0u8; 0]);
+    }
+}
@@ -0,0 +1,158 @@
+//! Running a processor inside an async host without blocking its executor.
+//! `AsyncRunner` runs a fixed number of instructions at a time and awaits
+//! a yield between them. The yield is whatever the host passes in, such as
+//! `tokio::task::yield_now`, so the core depends on no runtime; `yield_now`
+//! here works on any executor.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::processor::{Processor, RunOutcome};
+
+/// Pending the first time it is polled, after waking its task so the
+/// executor polls it again once others have had a turn.
+#[derive(Debug, Default)]
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        return Poll::Pending;
+    }
+}
+
+/// Gives other tasks a turn, on any executor.
+pub fn yield_now() -> YieldNow {
+    return YieldNow::default();
+}
+
+/// A processor run in budgets of instructions, yielding to the executor
+/// in between through `yield_fn`.
+#[derive(Debug)]
+pub struct AsyncRunner<Y> {
+    processor: Processor,
+    yield_fn: Y,
+}
+
+impl<Y, F> AsyncRunner<Y>
+where
+    Y: FnMut() -> F,
+    F: Future<Output = ()>,
+{
+    pub fn new(processor: Processor, yield_fn: Y) -> AsyncRunner<Y> {
+        return AsyncRunner { processor, yield_fn };
+    }
+
+    pub fn processor(&self) -> &Processor {
+        return &self.processor;
+    }
+
+    pub fn processor_mut(&mut self) -> &mut Processor {
+        return &mut self.processor;
+    }
+
+    pub fn into_processor(self) -> Processor {
+        return self.processor;
+    }
+
+    /// Runs until the program stops for any reason other than running out
+    /// of budget, yielding after every `budget_per_yield` instructions (at
+    /// least 1). Dropping the future between budgets leaves the processor
+    /// where it stopped, so a later run carries on from there.
+    pub async fn run_to_completion(&mut self, budget_per_yield: u32) -> RunOutcome {
+        let budget: u64 = budget_per_yield.max(1) as u64;
+        loop {
+            match self.processor.run_with_limit(budget) {
+                RunOutcome::InstructionLimit { .. } => (self.yield_fn)().await,
+                outcome => return outcome,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::task::Waker;
+    use crate::processor::make_processor;
+
+    /// Counts BC down from 0ffffh, about 260000 instructions, then halts.
+    const COUNTDOWN: [u8; 9] = [
+        0x01, 0xff, 0xff, // 0000 LXI B,0ffffh
+        0x0b,             // 0003 DCX B
+        0x78,             // 0004 MOV A,B
+        0xb1,             // 0005 ORA C
+        0xc2, 0x03, 0x00, // 0006 JNZ 0003h
+    ];
+
+    /// Polls each future in turn until all are done, as a single-threaded
+    /// executor would.
+    fn run_all(mut futures: Vec<Pin<Box<dyn Future<Output = ()> + '_>>>) {
+        let mut cx = Context::from_waker(Waker::noop());
+        while !futures.is_empty() {
+            futures.retain_mut(|future| future.as_mut().poll(&mut cx).is_pending());
+        }
+    }
+
+    #[test]
+    fn test_guest_and_another_task_interleave() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&COUNTDOWN);
+        processor.load_at(COUNTDOWN.len() as u16, &[0x76]); // HLT
+        let yields: Rc<Cell<u32>> = Rc::new(Cell::new(0));
+        let counted: Rc<Cell<u32>> = yields.clone();
+        let mut runner = AsyncRunner::new(processor, move || {
+            counted.set(counted.get() + 1);
+            return yield_now();
+        });
+        let log: RefCell<Vec<&str>> = RefCell::new(Vec::new());
+        let outcome: RefCell<Option<RunOutcome>> = RefCell::new(None);
+        let done: Cell<bool> = Cell::new(false);
+
+        run_all(vec![
+            Box::pin(async {
+                *outcome.borrow_mut() = Some(runner.run_to_completion(10_000).await);
+                log.borrow_mut().push("guest done");
+                done.set(true);
+            }),
+            Box::pin(async {
+                while !done.get() {
+                    log.borrow_mut().push("other");
+                    yield_now().await;
+                }
+            }),
+        ]);
+
+        assert_eq!(outcome.into_inner(), Some(RunOutcome::Halted));
+        assert_eq!(runner.processor().registers().bc(), 0);
+        let instructions: u64 = runner.processor().instruction_count();
+        assert_eq!(yields.get() as u64, instructions / 10_000);
+        let log: Vec<&str> = log.into_inner();
+        let others: usize = log.iter().take_while(|entry| **entry == "other").count();
+        assert_eq!(others, yields.get() as usize);
+        assert_eq!(log[others..], ["guest done"]);
+    }
+
+    #[test]
+    fn test_stops_without_yielding_when_the_program_does() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0x76]); // HLT
+        let mut runner = AsyncRunner::new(processor, || -> YieldNow { panic!("nothing to yield for") });
+
+        let mut future = Box::pin(runner.run_to_completion(0));
+        let poll = future.as_mut().poll(&mut Context::from_waker(Waker::noop()));
+
+        assert_eq!(poll, Poll::Ready(RunOutcome::Halted));
+    }
+}
@@ -0,0 +1,209 @@
+//! Periodic snapshots written to a directory, so a long run that is killed
+//! or faults can be resumed from the latest one instead of from scratch.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::processor::{Processor, RunOutcome, Snapshot};
+
+/// Instructions between checkpoints unless configured otherwise.
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 100_000_000;
+
+/// Checkpoints kept on disk unless configured otherwise.
+pub const DEFAULT_CHECKPOINT_KEEP: usize = 3;
+
+const PREFIX: &str = "checkpoint-";
+const SUFFIX: &str = ".json";
+
+/// Writes `snapshot` as JSON. The file is written under a temporary name and
+/// renamed, so a killed process never leaves a half-written checkpoint.
+pub fn save(snapshot: &Snapshot, path: &Path) -> io::Result<()> {
+    let json: Vec<u8> = serde_json::to_vec(snapshot).map_err(io::Error::other)?;
+    let partial: PathBuf = path.with_extension("partial");
+    fs::write(&partial, json)?;
+    return fs::rename(&partial, path);
+}
+
+/// Reads a checkpoint, upgrading one saved by an earlier version.
+pub fn load(path: &Path) -> io::Result<Snapshot> {
+    let json: Vec<u8> = fs::read(path)?;
+    return Snapshot::from_json(&json).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err));
+}
+
+/// Names checkpoint files after their instruction count, zero-padded so
+/// they sort in run order.
+fn file_name(instructions: u64) -> String {
+    return format!("{}{:020}{}", PREFIX, instructions, SUFFIX);
+}
+
+fn is_checkpoint(path: &Path) -> bool {
+    return path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(PREFIX) && name.ends_with(SUFFIX));
+}
+
+/// Saves checkpoints into one directory, deleting the oldest beyond `keep`.
+#[derive(Debug)]
+pub struct Checkpointer {
+    dir: PathBuf,
+    keep: usize,
+    saved: VecDeque<PathBuf>,
+}
+
+impl Checkpointer {
+    /// Creates `dir` if needed. Checkpoints already there count towards
+    /// `keep`, so a resumed run keeps pruning the same set.
+    pub fn new(dir: impl Into<PathBuf>, keep: usize) -> io::Result<Checkpointer> {
+        let dir: PathBuf = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut saved: Vec<PathBuf> = fs::read_dir(&dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<PathBuf>>>()?;
+        saved.retain(|path| is_checkpoint(path));
+        saved.sort();
+        return Ok(Checkpointer { dir, keep: keep.max(1), saved: saved.into() });
+    }
+
+    /// Snapshots `processor` and returns the file written.
+    pub fn checkpoint(&mut self, processor: &Processor) -> io::Result<PathBuf> {
+        let path: PathBuf = self.dir.join(file_name(processor.instruction_count()));
+        save(&processor.snapshot(), &path)?;
+        self.saved.retain(|saved| *saved != path);
+        self.saved.push_back(path.clone());
+        while self.saved.len() > self.keep {
+            if let Some(oldest) = self.saved.pop_front() {
+                fs::remove_file(oldest)?;
+            }
+        }
+        return Ok(path);
+    }
+
+    /// The most recent checkpoint on disk.
+    pub fn latest(&self) -> Option<&Path> {
+        return self.saved.back().map(PathBuf::as_path);
+    }
+}
+
+/// Runs like `run_with_limit` (or `run` when `max` is `None`), writing a
+/// checkpoint every `interval` instructions.
+pub fn run_with_checkpoints(processor: &mut Processor, checkpointer: &mut Checkpointer, interval: u64,
+                            max: Option<u64>) -> io::Result<RunOutcome> {
+    let interval: u64 = interval.max(1);
+    let mut executed: u64 = 0;
+    loop {
+        let chunk: u64 = match max {
+            Some(max) => interval.min(max.saturating_sub(executed)),
+            None => interval,
+        };
+        let before: u64 = processor.instruction_count();
+        let outcome: RunOutcome = processor.run_with_limit(chunk);
+        executed += processor.instruction_count() - before;
+
+        let RunOutcome::InstructionLimit { .. } = outcome else {
+            return Ok(outcome);
+        };
+        checkpointer.checkpoint(processor)?;
+        if max.is_some_and(|max| executed >= max) {
+            return Ok(RunOutcome::InstructionLimit { instructions: executed });
+        }
+    }
+}
+
+#[cfg(all(test, feature = "asm", feature = "devices"))]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::devices::PerfCounters;
+    use crate::processor::ProcessorBuilder;
+    use std::env;
+    use std::process;
+
+    /// Never halts. Keeps a running sum in memory and samples the perf
+    /// counters, so the result depends on memory, registers, and device
+    /// latches all surviving a resume. Instruction 1000 falls between the
+    /// `out 14h` that latches and the `in 14h` that reads the latch.
+    const PROGRAM: &str = "
+            lxi sp, 3000h
+            lxi hl, 2000h
+            mvi b, 0
+            mvi c, 0
+    loop:   mov a, m
+            adi 3
+            mov m, a
+            inr l
+            in 10h
+            out 14h
+            in 14h
+            add m
+            mov m, a
+            jmp loop
+    ";
+
+    fn processor() -> Processor {
+        let counters = PerfCounters::new(0x10);
+        let mut processor = ProcessorBuilder::new().strict(true).device(&counters.ports(), Box::new(counters)).build();
+        processor.load_at(0, &asm::assemble(PROGRAM).unwrap().bytes);
+        return processor;
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("i8080-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        return dir;
+    }
+
+    #[test]
+    fn test_resume_matches_uninterrupted_run() {
+        let dir = temp_dir("resume");
+        let mut uninterrupted = processor();
+        uninterrupted.run_with_limit(2000);
+
+        let mut first = processor();
+        let mut checkpointer = Checkpointer::new(&dir, 3).unwrap();
+        run_with_checkpoints(&mut first, &mut checkpointer, 1000, Some(1000)).unwrap();
+        let path = checkpointer.latest().unwrap().to_path_buf();
+        drop(first);
+
+        let mut resumed = processor();
+        resumed.restore(&load(&path).unwrap()).unwrap();
+        assert_eq!(resumed.instruction_count(), 1000);
+        resumed.run_with_limit(1000);
+
+        assert_eq!(resumed.snapshot().state_hash(), uninterrupted.snapshot().state_hash());
+        assert_eq!(resumed.snapshot(), uninterrupted.snapshot());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_keeps_only_the_latest_checkpoints() {
+        let dir = temp_dir("keep");
+        let mut processor = processor();
+        let mut checkpointer = Checkpointer::new(&dir, 2).unwrap();
+
+        let outcome = run_with_checkpoints(&mut processor, &mut checkpointer, 100, Some(450)).unwrap();
+
+        assert_eq!(outcome, RunOutcome::InstructionLimit { instructions: 450 });
+        let mut names: Vec<String> = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![file_name(400), file_name(450)]);
+        assert_eq!(Checkpointer::new(&dir, 2).unwrap().latest(), Some(dir.join(file_name(450)).as_path()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_json_round_trip() {
+        let mut processor = processor();
+        processor.run_with_limit(37);
+        let snapshot = processor.snapshot();
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+
+        assert_eq!(serde_json::from_str::<Snapshot>(&json).unwrap(), snapshot);
+        assert_eq!(snapshot.devices.len(), 1);
+        assert!(snapshot.strict_initialized.is_some());
+    }
+}
@@ -0,0 +1,950 @@
+use std::ops::Range;
+use std::path::Path;
+
+use intel_8080_emu::devices::{DateTime, DeviceRegistry};
+use intel_8080_emu::disasm;
+use intel_8080_emu::dip::{self, DipOption};
+use intel_8080_emu::expr::Expr;
+use intel_8080_emu::formats;
+use intel_8080_emu::invaders;
+use intel_8080_emu::patchsets;
+use intel_8080_emu::processor::{self, RegisterPair, TraceFilter};
+use intel_8080_emu::style::ColorChoice;
+use intel_8080_emu::word::parse_number as parse_addr;
+
+use crate::config;
+
+pub const USAGE: &str = "\
+usage: intel_8080_emu [options] <program.bin>
+       intel_8080_emu resume [options] <checkpoint.json>
+       intel_8080_emu run [options] <package.tar>
+       intel_8080_emu schema <state|trace|event|stats>
+       intel_8080_emu snapshot-diff <a.json> <b.json>
+       intel_8080_emu repro [--window <n>] [--max-instructions <n>] [--out <case.json>] <checkpoint.json>
+       intel_8080_emu disasm [--origin <addr>] [--root <addr>]... [--format listing|asm] <program.bin>
+       intel_8080_emu variant-check [--origin <addr>] [--root <addr>]... [--run [--max-instructions <n>]] <program.bin>
+       intel_8080_emu --selftest
+       intel_8080_emu --compare-profiles [--max-instructions <n>] <program.bin>
+  --config <file>          read options from a TOML file whose keys mirror these flags, e.g.
+                           max_instructions = 500; flags given here override the file
+  --no-<switch>            turn off a switch such as --strict or --json that the config file turns on
+  --progress               show a progress line on stderr
+  --max-instructions <n>   stop after n instructions
+  --mhz <n>                run no faster than n MHz, e.g. 2 or 1.79
+  --strict                 stop with a fault report on likely guest bugs such as invalid opcodes
+  --fast-forward-loops     skip countdown delay loops (DCR r; JNZ) in one step
+  --debug                  start in the monitor instead of running
+  --debug-script <file>    run monitor commands from file, then continue interactively
+  --origin <addr>          load the program at addr and start there (default 0)
+  --sp <addr>              start with SP at addr
+  --poke <addr>=<byte>     store byte at addr before running (repeatable)
+  --relocate <from>:<to>   relocate a program assembled at <from> and run it at <to>
+  --reloc-data <start>:<end>
+                           treat [start, end) as data when relocating (repeatable)
+  --patchset <name>        patch the program before loading it (repeatable): cpudiag-no-daa
+                           skips cpudiag.bin's DAA test, cpudiag-stack moves its stack
+  --checkpoint-dir <dir>   save a snapshot to dir every checkpoint interval
+  --checkpoint-interval <n>
+                           instructions between checkpoints (default 100000000)
+  --checkpoint-keep <n>    checkpoints to keep (default 3)
+  --prescan                list unsupported opcodes, ports, and RST vectors in the image before running
+  --gif <file>             record video memory at 2400h (Space Invaders layout) as an animated GIF
+  --gif-frames <n>         put every nth 60 Hz frame in the GIF (default 1)
+  --stats                  print instruction and cycle counts, a memory usage map and
+                           interrupt latency and handler times after the run
+  --track-allocator <pair>:<start>:<end>
+                           follow bc, de or hl as a bump allocator in [start, end), warning in --events
+                           when it runs past the end or into the stack; --stats shows its peak (repeatable)
+  --unattached-ports       note IN from ports with no device attached, which reads 0; --stats lists
+                           each port with the first PC and read count, and --strict stops at the first
+  --usage-json <file>      write the memory usage map to file as JSON
+  --history <addr>         journal memory writes and list those to addr after the run (repeatable)
+  --events <file>          write the latest port, interrupt, and watchpoint events to file on stop
+  --watch-expr <expr>      add an event to --events whenever expr changes, e.g. 'hl - sp' (repeatable)
+  --assert <expr>          fail with a report and exit status 1 unless expr is nonzero when the
+                           run halts, e.g. 'a == 42h' or '[2000h] == 1' (repeatable)
+  --artifacts-dir <dir>    when the run faults, fails --assert, hits --max-instructions or halts
+                           stuck with interrupts enabled, save its state, snapshot, fault report,
+                           last instructions, events and stack and code memory to a new
+                           timestamped directory in dir
+  --color <when>           colour the monitor and trace: auto (on a terminal unless NO_COLOR
+                           is set, the default), always, or never
+  --json                   write the final state, --stats, --trace and --events as JSON
+                           documents, one per line; `schema` prints their JSON Schema
+  --trace                  print each instruction to stderr before it executes
+  --trace-range <start>:<end>
+                           trace only PCs from start to end inclusive (repeatable, implies --trace)
+  --trace-class <classes>  trace only flow, io, stack, or data instructions, comma separated
+                           (implies --trace)
+  --trace-flags            add the flags each instruction changed to trace lines, e.g. F:+Z-C (implies --trace)
+  --trace-every <n>        trace every nth instruction the other filters let through (implies --trace)
+  --rtc-epoch <time>       attach a real-time clock at ports 70h-71h starting at time,
+                           e.g. 1983-06-01T09:00:00Z
+  --guest-log              attach the guest log device at ports e0h-e1h; its messages go to --events
+  --seed <n>               attach the entropy device at port 50h, drawing random bytes seeded with n;
+                           every draw is an IN, so --events records it
+  --device <name>[:<key>=<value>,...]
+                           attach a device by name, e.g. timer:port=0x20,tick_ms=10 (repeatable);
+                           console, timer, rtc, guest_log, perf_counters, dma, entropy, invaders
+  --rom <file>             the program to run, as an alternative to giving it last
+  --machine invaders       run the program as a Space Invaders ROM in a window
+                           (needs a build with --features sdl)
+  --option <name>=<value>  set a machine option, e.g. lives=5 (repeatable); for invaders:
+                           lives 3-6, bonus_at 1000|1500, coin_info on|off
+  --scale <n>              window size as a multiple of the screen (default 2)
+  --samples <dir>          play sounds from 0.wav-9.wav in dir instead of generated tones";
+
+/// Hardware to run the program in, rather than a bare processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Machine {
+    Invaders,
+}
+
+impl Machine {
+    pub fn parse(name: &str) -> Option<Machine> {
+        return match name {
+            "invaders" => Some(Machine::Invaders),
+            _ => None,
+        };
+    }
+
+    /// The options `--option` can set on the machine.
+    pub fn options(self) -> &'static [DipOption] {
+        return match self {
+            Machine::Invaders => invaders::OPTIONS,
+        };
+    }
+}
+
+/// What to run and how. Switches and `color` are None unless given, so a
+/// config file only fills in those the command line leaves alone.
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub program: String,
+    /// The `--config` file the other options were read over.
+    pub config: Option<String>,
+    /// Where to load the program and start, if not 0.
+    pub origin: Option<u16>,
+    pub sp: Option<u16>,
+    /// Bytes to store as address and value, in order, once the program is
+    /// loaded.
+    pub pokes: Vec<(u16, u8)>,
+    pub progress: Option<bool>,
+    pub max_instructions: Option<u64>,
+    pub mhz: Option<f64>,
+    pub fast_forward_loops: Option<bool>,
+    pub strict: Option<bool>,
+    pub debug: Option<bool>,
+    pub debug_script: Option<String>,
+    pub relocate: Option<(u16, u16)>,
+    pub reloc_data: Vec<Range<u16>>,
+    /// Names of patch sets to apply, in order.
+    pub patchsets: Vec<String>,
+    /// `program` is a checkpoint to resume rather than a binary to load.
+    pub resume: bool,
+    /// `program` is a package whose manifest sets the machine up.
+    pub package: bool,
+    pub checkpoint_dir: Option<String>,
+    pub checkpoint_interval: Option<u64>,
+    pub checkpoint_keep: Option<usize>,
+    pub events: Option<String>,
+    pub artifacts_dir: Option<String>,
+    /// Expressions to watch, as written.
+    pub watch_exprs: Vec<String>,
+    /// Expressions that must be nonzero when the run ends, as written.
+    pub asserts: Vec<String>,
+    pub history: Vec<u16>,
+    pub prescan: Option<bool>,
+    pub stats: Option<bool>,
+    pub allocators: Vec<(RegisterPair, Range<u16>)>,
+    pub unattached_ports: Option<bool>,
+    pub usage_json: Option<String>,
+    pub rtc_epoch: Option<DateTime>,
+    pub guest_log: Option<bool>,
+    /// `--seed`: attaches the entropy device with this seed.
+    pub seed: Option<u64>,
+    /// `--device` specs, in order.
+    pub devices: Vec<String>,
+    pub gif: Option<String>,
+    pub gif_frames: Option<u64>,
+    /// Which instructions to trace, if tracing.
+    pub trace: Option<TraceFilter>,
+    /// Show which flags each traced instruction changed.
+    pub trace_flags: Option<bool>,
+    /// Write the state dump, stats, trace and events as JSON.
+    pub json: Option<bool>,
+    pub color: Option<ColorChoice>,
+    pub machine: Option<Machine>,
+    /// `--option` settings, in order, as name and value.
+    pub machine_options: Vec<(String, String)>,
+    pub scale: Option<u32>,
+    pub samples: Option<String>,
+}
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Box<Options>),
+    /// `roundtrip <rom> [--origin <addr>] [--root <addr>]... [--format asm]`:
+    /// not in the usage text, it checks that disassembling and reassembling
+    /// an image is lossless, as plain source or with `--format asm` as
+    /// `disasm` writes it.
+    Roundtrip(DisasmArgs),
+    /// `disasm <rom>`: prints the image as a listing or, with `--format
+    /// asm`, as source that assembles back to it.
+    Disasm(DisasmArgs),
+    /// `schema <document>`: prints the JSON Schema for one of the `--json`
+    /// outputs.
+    Schema { name: String },
+    /// `snapshot-diff <a> <b>`: prints how two checkpoints differ.
+    SnapshotDiff { a: String, b: String },
+    /// `repro <checkpoint>`: resumes the checkpoint in strict mode and cuts
+    /// the fault it runs into down to a test case.
+    Repro(ReproArgs),
+    /// `variant-check <rom>`: lists the instructions that behave or time
+    /// differently on the 8080 and the 8085, found in the image or, with
+    /// `--run`, as they execute.
+    VariantCheck(VariantCheckArgs),
+    /// `--selftest`: runs the built-in test programs and reports each.
+    SelfTest,
+    /// `--compare-profiles <rom>`: runs the program under every quirk
+    /// profile and shows which agree.
+    CompareProfiles { program: String, max_instructions: Option<u64> },
+}
+
+fn parse_number(flag: &str, value: Option<&String>) -> Result<u64, String> {
+    let value = value.ok_or(format!("{} needs a value", flag))?;
+    return value.parse::<u64>().map_err(|_| format!("{}: invalid number '{}'", flag, value));
+}
+
+/// Parses `<a>:<b>` where both halves are addresses.
+fn parse_addr_pair(flag: &str, value: Option<&String>) -> Result<(u16, u16), String> {
+    let value = value.ok_or(format!("{} needs a value", flag))?;
+    let invalid = || format!("{}: expected <addr>:<addr>, got '{}'", flag, value);
+    let (first, second) = value.split_once(':').ok_or_else(invalid)?;
+    return match (parse_addr(first), parse_addr(second)) {
+        (Some(first), Some(second)) => Ok((first, second)),
+        _ => Err(invalid()),
+    };
+}
+
+/// Parses `<addr>=<byte>`.
+fn parse_poke(value: Option<&String>) -> Result<(u16, u8), String> {
+    let value = value.ok_or("--poke needs <addr>=<byte>")?;
+    let invalid = || format!("--poke: expected <addr>=<byte>, got '{}'", value);
+    let (addr, byte) = value.split_once('=').ok_or_else(invalid)?;
+    let byte = parse_addr(byte).and_then(|byte| u8::try_from(byte).ok()).ok_or_else(invalid)?;
+    return Ok((parse_addr(addr).ok_or_else(invalid)?, byte));
+}
+
+/// Parses the command line, not including the program name. A `--config`
+/// file is read first and the flags override it; see `config::overlay`.
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options: Options = parse_flags(args)?;
+    if let Some(path) = options.config.clone() {
+        let file = config::load(Path::new(&path)).map_err(|err| format!("--config {}: {}", path, err))?;
+        let base: Options = parse_flags(&file.to_args()).map_err(|err| format!("--config {}: {}", path, err))?;
+        options = config::overlay(base, options);
+    }
+
+    if options.program.is_empty() {
+        return Err("no program given".to_string());
+    }
+    if options.origin.is_some() && options.relocate.is_some() {
+        return Err("--origin cannot be used with --relocate, which loads at <to>".to_string());
+    }
+    if options.gif.is_none() && options.gif_frames.is_some() {
+        return Err("--gif-frames needs --gif".to_string());
+    }
+    if options.machine.is_none() && (options.scale.is_some() || options.samples.is_some()) {
+        return Err("--scale and --samples need --machine".to_string());
+    }
+    for (name, value) in &options.machine_options {
+        let machine = options.machine.ok_or("--option needs --machine")?;
+        dip::find(machine.options(), name).and_then(|option| option.apply(0, value)).map_err(|err| format!("--option: {}", err))?;
+    }
+    if options.checkpoint_dir.is_none() && (options.checkpoint_interval.is_some() || options.checkpoint_keep.is_some()) {
+        return Err("--checkpoint-interval and --checkpoint-keep need --checkpoint-dir".to_string());
+    }
+    return Ok(options);
+}
+
+/// Parses flags into options without checking that they go together. The
+/// program is left empty if none is given.
+fn parse_flags(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut program: Option<String> = None;
+    let mut trace_classes: Option<u8> = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if let Some((switch, on)) = switch_mut(&mut options, arg) {
+            *switch = Some(on);
+            continue;
+        }
+        match arg.as_str() {
+            "--config" => options.config = Some(iter.next().ok_or("--config needs a file")?.clone()),
+            "--origin" => {
+                let value = iter.next().ok_or("--origin needs an address")?;
+                options.origin = Some(parse_addr(value).ok_or(format!("--origin: invalid address '{}'", value))?);
+            },
+            "--sp" => {
+                let value = iter.next().ok_or("--sp needs an address")?;
+                options.sp = Some(parse_addr(value).ok_or(format!("--sp: invalid address '{}'", value))?);
+            },
+            "--poke" => options.pokes.push(parse_poke(iter.next())?),
+            "--max-instructions" => options.max_instructions = Some(parse_number(arg, iter.next())?),
+            "--mhz" => {
+                let value = iter.next().ok_or("--mhz needs a value")?;
+                let mhz = value.parse::<f64>().ok().filter(|mhz| *mhz > 0.0);
+                options.mhz = Some(mhz.ok_or(format!("--mhz: invalid speed '{}'", value))?);
+            },
+            "--debug-script" => {
+                options.debug_script = Some(iter.next().ok_or("--debug-script needs a file")?.clone());
+                options.debug = Some(true);
+            },
+            "--relocate" => options.relocate = Some(parse_addr_pair(arg, iter.next())?),
+            "--track-allocator" => {
+                let value = iter.next().ok_or("--track-allocator needs a value")?;
+                let invalid = || format!("--track-allocator: expected <bc|de|hl>:<start>:<end>, got '{}'", value);
+                let (pair, arena) = value.split_once(':').ok_or_else(invalid)?;
+                let pair = RegisterPair::parse(pair).ok_or_else(invalid)?;
+                let (start, end) = parse_addr_pair(arg, Some(&arena.to_string())).map_err(|_| invalid())?;
+                options.allocators.push((pair, start..end));
+            },
+            "--reloc-data" => {
+                let (start, end) = parse_addr_pair(arg, iter.next())?;
+                options.reloc_data.push(start..end);
+            },
+            "--patchset" => {
+                let name = iter.next().ok_or("--patchset needs a name")?;
+                if patchsets::find(name).is_none() {
+                    let names: Vec<&str> = patchsets::PATCHSETS.iter().map(|patchset| patchset.name).collect();
+                    return Err(format!("--patchset: unknown patch set '{}', expected one of {}", name, names.join(", ")));
+                }
+                options.patchsets.push(name.clone());
+            },
+            "--checkpoint-dir" => {
+                options.checkpoint_dir = Some(iter.next().ok_or("--checkpoint-dir needs a directory")?.clone());
+            },
+            "--checkpoint-interval" => options.checkpoint_interval = Some(parse_number(arg, iter.next())?),
+            "--checkpoint-keep" => options.checkpoint_keep = Some(parse_number(arg, iter.next())? as usize),
+            "--seed" => options.seed = Some(parse_number(arg, iter.next())?),
+            "--device" => {
+                let spec = iter.next().ok_or("--device needs a device name")?;
+                DeviceRegistry::builtin().check(spec).map_err(|err| format!("--device: {}", err))?;
+                options.devices.push(spec.clone());
+            },
+            "--color" => {
+                let value = iter.next().ok_or("--color needs auto, always or never")?;
+                options.color = Some(ColorChoice::parse(value).ok_or(format!("--color: expected auto, always or never, got '{}'", value))?);
+            },
+            "--usage-json" => options.usage_json = Some(iter.next().ok_or("--usage-json needs a file")?.clone()),
+            "--history" => {
+                let value = iter.next().ok_or("--history needs an address")?;
+                options.history.push(parse_addr(value).ok_or(format!("--history: invalid address '{}'", value))?);
+            },
+            "--rtc-epoch" => {
+                let value = iter.next().ok_or("--rtc-epoch needs a time")?;
+                options.rtc_epoch = Some(DateTime::parse_iso8601(value)
+                    .ok_or(format!("--rtc-epoch: expected YYYY-MM-DDTHH:MM:SS, got '{}'", value))?);
+            },
+            "--gif" => options.gif = Some(iter.next().ok_or("--gif needs a file")?.clone()),
+            "--gif-frames" => options.gif_frames = Some(parse_number(arg, iter.next())?.max(1)),
+            "--events" => options.events = Some(iter.next().ok_or("--events needs a file")?.clone()),
+            "--artifacts-dir" => {
+                options.artifacts_dir = Some(iter.next().ok_or("--artifacts-dir needs a directory")?.clone());
+            },
+            "--watch-expr" => {
+                let text = iter.next().ok_or("--watch-expr needs an expression")?;
+                Expr::parse(text).map_err(|err| format!("--watch-expr: {}", err))?;
+                options.watch_exprs.push(text.clone());
+            },
+            "--assert" => {
+                let text = iter.next().ok_or("--assert needs an expression")?;
+                Expr::parse(text).map_err(|err| format!("--assert: {}", err))?;
+                options.asserts.push(text.clone());
+            },
+            "--trace" => {
+                options.trace.get_or_insert_with(TraceFilter::new);
+            },
+            "--trace-flags" => {
+                options.trace.get_or_insert_with(TraceFilter::new);
+                options.trace_flags = Some(true);
+            },
+            "--no-trace-flags" => options.trace_flags = Some(false),
+            "--trace-range" => {
+                let (start, end) = parse_addr_pair(arg, iter.next())?;
+                options.trace.get_or_insert_with(TraceFilter::new).ranges.push(start..=end);
+            },
+            "--trace-class" => {
+                let classes: u8 = parse_trace_classes(iter.next())?;
+                trace_classes = Some(trace_classes.unwrap_or(0) | classes);
+            },
+            "--trace-every" => {
+                let every: u64 = parse_number(arg, iter.next())?;
+                options.trace = Some(options.trace.take().unwrap_or_default().every(every));
+            },
+            "--machine" => {
+                let name = iter.next().ok_or("--machine needs a value")?;
+                options.machine = Some(Machine::parse(name).ok_or(format!("--machine: unknown machine '{}'", name))?);
+            },
+            "--option" => {
+                let text = iter.next().ok_or("--option needs name=value")?;
+                let setting = dip::parse_setting(text).ok_or(format!("--option: expected name=value, got '{}'", text))?;
+                options.machine_options.push(setting);
+            },
+            "--scale" => options.scale = Some(parse_number(arg, iter.next())?.clamp(1, 8) as u32),
+            "--samples" => options.samples = Some(iter.next().ok_or("--samples needs a directory")?.clone()),
+            flag if flag.starts_with("--") && flag != "--rom" => return Err(format!("unknown option {}", flag)),
+            path => {
+                let path: &String = if path == "--rom" { iter.next().ok_or("--rom needs a file")? } else { arg };
+                if program.is_some() {
+                    return Err(format!("unexpected argument {}", path));
+                }
+                program = Some(path.to_string());
+            },
+        }
+    }
+
+    options.program = program.unwrap_or_default();
+    if let Some(classes) = trace_classes {
+        options.trace = Some(options.trace.take().unwrap_or_default().classes(classes));
+    }
+    return Ok(options);
+}
+
+/// The switch `flag` turns on, or off if it is the `--no-` form.
+fn switch_mut<'a>(options: &'a mut Options, flag: &str) -> Option<(&'a mut Option<bool>, bool)> {
+    let (name, on): (&str, bool) = match flag.strip_prefix("--no-") {
+        Some(name) => (name, false),
+        None => (flag.strip_prefix("--")?, true),
+    };
+    let switch: &mut Option<bool> = match name {
+        "progress" => &mut options.progress,
+        "fast-forward-loops" => &mut options.fast_forward_loops,
+        "strict" => &mut options.strict,
+        "debug" => &mut options.debug,
+        "prescan" => &mut options.prescan,
+        "stats" => &mut options.stats,
+        "unattached-ports" => &mut options.unattached_ports,
+        "guest-log" => &mut options.guest_log,
+        "json" => &mut options.json,
+        _ => return None,
+    };
+    return Some((switch, on));
+}
+
+/// Parses a comma-separated list of trace class names into `TRACE_*` bits.
+fn parse_trace_classes(value: Option<&String>) -> Result<u8, String> {
+    let value = value.ok_or("--trace-class needs a value")?;
+    let mut classes: u8 = 0;
+    for name in value.split(',') {
+        classes |= processor::parse_class(name.trim()).ok_or(format!("--trace-class: unknown class '{}'", name))?;
+    }
+    return Ok(classes);
+}
+
+/// What `disasm` and `roundtrip` disassemble, and how.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DisasmArgs {
+    pub path: String,
+    pub origin: u16,
+    /// Where code starts, for following control flow. Empty means a linear
+    /// sweep for a listing and the origin alone for `--format asm`.
+    pub roots: Vec<u16>,
+    pub format: disasm::Format,
+}
+
+fn parse_disasm_args(args: &[String]) -> Result<DisasmArgs, String> {
+    let mut path: Option<String> = None;
+    let mut parsed = DisasmArgs::default();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--origin" => {
+                let value = iter.next().ok_or("--origin needs a value")?;
+                parsed.origin = parse_addr(value).ok_or(format!("--origin: invalid address '{}'", value))?;
+            },
+            "--root" => {
+                let value = iter.next().ok_or("--root needs an address")?;
+                parsed.roots.push(parse_addr(value).ok_or(format!("--root: invalid address '{}'", value))?);
+            },
+            "--format" => {
+                let value = iter.next().ok_or("--format needs listing or asm")?;
+                parsed.format = disasm::Format::parse(value).ok_or(format!("--format: expected listing or asm, got '{}'", value))?;
+            },
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            other if path.is_none() => path = Some(other.to_string()),
+            other => return Err(format!("unexpected argument {}", other)),
+        }
+    }
+
+    parsed.path = path.ok_or("no program given")?;
+    return Ok(parsed);
+}
+
+/// Where `repro` starts and what it keeps.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReproArgs {
+    pub checkpoint: String,
+    /// Instructions to keep before the fault; `repro::DEFAULT_WINDOW`
+    /// unless given.
+    pub window: Option<u64>,
+    pub max_instructions: Option<u64>,
+    /// The case file; `tests/repro/fault-<instruction>.json` unless given.
+    pub out: Option<String>,
+}
+
+fn parse_repro_args(args: &[String]) -> Result<ReproArgs, String> {
+    let mut checkpoint: Option<String> = None;
+    let mut parsed = ReproArgs::default();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--window" => parsed.window = Some(parse_number(arg, iter.next())?),
+            "--max-instructions" => parsed.max_instructions = Some(parse_number(arg, iter.next())?),
+            "--out" => parsed.out = Some(iter.next().ok_or("--out needs a file")?.clone()),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            other if checkpoint.is_none() => checkpoint = Some(other.to_string()),
+            other => return Err(format!("unexpected argument {}", other)),
+        }
+    }
+
+    parsed.checkpoint = checkpoint.ok_or("no checkpoint given")?;
+    return Ok(parsed);
+}
+
+/// What `variant-check` looks at.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VariantCheckArgs {
+    pub path: String,
+    pub origin: u16,
+    /// Where code starts, for following control flow. Empty means a linear
+    /// sweep.
+    pub roots: Vec<u16>,
+    /// Run the program from the origin instead of scanning it.
+    pub run: bool,
+    pub max_instructions: Option<u64>,
+}
+
+fn parse_variant_args(args: &[String]) -> Result<VariantCheckArgs, String> {
+    let mut path: Option<String> = None;
+    let mut parsed = VariantCheckArgs::default();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--origin" => {
+                let value = iter.next().ok_or("--origin needs a value")?;
+                parsed.origin = parse_addr(value).ok_or(format!("--origin: invalid address '{}'", value))?;
+            },
+            "--root" => {
+                let value = iter.next().ok_or("--root needs an address")?;
+                parsed.roots.push(parse_addr(value).ok_or(format!("--root: invalid address '{}'", value))?);
+            },
+            "--run" => parsed.run = true,
+            "--max-instructions" => parsed.max_instructions = Some(parse_number(arg, iter.next())?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            other if path.is_none() => path = Some(other.to_string()),
+            other => return Err(format!("unexpected argument {}", other)),
+        }
+    }
+
+    if parsed.run && !parsed.roots.is_empty() {
+        return Err("--root is for scanning and cannot be used with --run".to_string());
+    }
+    if !parsed.run && parsed.max_instructions.is_some() {
+        return Err("--max-instructions needs --run".to_string());
+    }
+    parsed.path = path.ok_or("no program given")?;
+    return Ok(parsed);
+}
+
+fn parse_compare_args(args: &[String]) -> Result<Command, String> {
+    let mut program: Option<String> = None;
+    let mut max_instructions: Option<u64> = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--max-instructions" => max_instructions = Some(parse_number(arg, iter.next())?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            other if program.is_none() => program = Some(other.to_string()),
+            other => return Err(format!("unexpected argument {}", other)),
+        }
+    }
+
+    let program: String = program.ok_or("no program given")?;
+    return Ok(Command::CompareProfiles { program, max_instructions });
+}
+
+/// Parses the command line, including subcommands.
+pub fn parse_command(args: &[String]) -> Result<Command, String> {
+    match args.first().map(String::as_str) {
+        Some("roundtrip") => return parse_disasm_args(&args[1..]).map(Command::Roundtrip),
+        Some("disasm") => return parse_disasm_args(&args[1..]).map(Command::Disasm),
+        Some("schema") => return match &args[1..] {
+            [name] if formats::SCHEMAS.contains(&name.as_str()) => Ok(Command::Schema { name: name.clone() }),
+            _ => Err(format!("schema needs one of {}", formats::SCHEMAS.join(", "))),
+        },
+        Some("snapshot-diff") => return match &args[1..] {
+            [a, b] => Ok(Command::SnapshotDiff { a: a.clone(), b: b.clone() }),
+            _ => Err("snapshot-diff needs two checkpoint files".to_string()),
+        },
+        Some("repro") => return parse_repro_args(&args[1..]).map(Command::Repro),
+        Some("variant-check") => return parse_variant_args(&args[1..]).map(Command::VariantCheck),
+        Some("--selftest") => return match args.len() {
+            1 => Ok(Command::SelfTest),
+            _ => Err("--selftest takes no other arguments".to_string()),
+        },
+        Some("--compare-profiles") => return parse_compare_args(&args[1..]),
+        Some("resume") => {
+            let mut options = parse_args(&args[1..])?;
+            if options.relocate.is_some() || options.origin.is_some() || !options.patchsets.is_empty() {
+                return Err("--relocate, --origin and --patchset cannot be used with resume".to_string());
+            }
+            options.resume = true;
+            return Ok(Command::Run(Box::new(options)));
+        },
+        Some("run") => {
+            let mut options = parse_args(&args[1..])?;
+            if options.relocate.is_some() || options.origin.is_some() || !options.patchsets.is_empty() {
+                return Err("--relocate, --origin and --patchset cannot be used with run; the package says how to load".to_string());
+            }
+            options.package = true;
+            return Ok(Command::Run(Box::new(options)));
+        },
+        _ => {},
+    }
+    return parse_args(args).map(|options| Command::Run(Box::new(options)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        return list.iter().map(|arg| arg.to_string()).collect();
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(&args(&["--progress", "--max-instructions", "500", "rom.bin"])).unwrap();
+
+        assert_eq!(options, Options {
+            program: "rom.bin".to_string(),
+            progress: Some(true),
+            max_instructions: Some(500),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_start_options() {
+        let options = parse_args(&args(&["--origin", "100h", "--sp", "0x2400", "--poke", "2000h=5", "--poke", "2001h=0ffh", "rom.bin"])).unwrap();
+
+        assert_eq!((options.origin, options.sp), (Some(0x0100), Some(0x2400)));
+        assert_eq!(options.pokes, vec![(0x2000, 5), (0x2001, 0xff)]);
+        assert!(parse_args(&args(&["--poke", "2000h=100h", "rom.bin"])).is_err());
+        assert!(parse_args(&args(&["--origin", "100h", "--relocate", "0:4000h", "rom.bin"])).is_err());
+        assert!(parse_command(&args(&["resume", "--origin", "100h", "ckpt.json"])).is_err());
+    }
+
+    #[test]
+    fn test_relocate_options() {
+        let options = parse_args(&args(&[
+            "--relocate", "0:0x4000", "--reloc-data", "11h:13h", "--reloc-data", "0x20:0x30", "rom.bin",
+        ])).unwrap();
+
+        assert_eq!(options.relocate, Some((0, 0x4000)));
+        assert_eq!(options.reloc_data, vec![0x11..0x13, 0x20..0x30]);
+        assert!(parse_args(&args(&["--relocate", "0x4000", "rom.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_patchset_names() {
+        let options = parse_args(&args(&["--patchset", "cpudiag-no-daa", "--patchset", "cpudiag-stack", "cpudiag.bin"])).unwrap();
+
+        assert_eq!(options.patchsets, vec!["cpudiag-no-daa", "cpudiag-stack"]);
+        assert!(parse_args(&args(&["--patchset", "cpudiag-no-ac", "cpudiag.bin"])).is_err());
+        assert!(parse_command(&args(&["resume", "--patchset", "cpudiag-stack", "ckpt/checkpoint-1.json"])).is_err());
+    }
+
+    #[test]
+    fn test_mhz() {
+        assert_eq!(parse_args(&args(&["--mhz", "1.79", "rom.bin"])).unwrap().mhz, Some(1.79));
+        assert_eq!(parse_args(&args(&["--fast-forward-loops", "rom.bin"])).unwrap().fast_forward_loops, Some(true));
+        assert_eq!(parse_args(&args(&["--strict", "rom.bin"])).unwrap().strict, Some(true));
+        assert_eq!(parse_args(&args(&["--unattached-ports", "rom.bin"])).unwrap().unattached_ports, Some(true));
+    }
+
+    #[test]
+    fn test_events() {
+        assert_eq!(parse_args(&args(&["--events", "events.log", "rom.bin"])).unwrap().events.as_deref(), Some("events.log"));
+        assert!(parse_args(&args(&["rom.bin", "--events"])).is_err());
+    }
+
+    #[test]
+    fn test_artifacts_dir() {
+        let options = parse_args(&args(&["--artifacts-dir", "failures", "rom.bin"])).unwrap();
+        assert_eq!(options.artifacts_dir.as_deref(), Some("failures"));
+        assert_eq!(parse_args(&args(&["rom.bin", "--artifacts-dir"])).unwrap_err(), "--artifacts-dir needs a directory");
+    }
+
+    #[test]
+    fn test_watch_exprs() {
+        let options = parse_args(&args(&["--watch-expr", "hl - sp", "--watch-expr", "flags.carry", "rom.bin"])).unwrap();
+        assert_eq!(options.watch_exprs, vec!["hl - sp", "flags.carry"]);
+        assert_eq!(parse_args(&args(&["--watch-expr", "hl -", "rom.bin"])).unwrap_err(), "--watch-expr: expression ends early");
+    }
+
+    #[test]
+    fn test_color() {
+        assert_eq!(parse_args(&args(&["rom.bin"])).unwrap().color, None);
+        assert_eq!(parse_args(&args(&["--color", "always", "rom.bin"])).unwrap().color, Some(ColorChoice::Always));
+        assert_eq!(parse_args(&args(&["--color", "blue", "rom.bin"])).unwrap_err(), "--color: expected auto, always or never, got 'blue'");
+    }
+
+    #[test]
+    fn test_asserts() {
+        let options = parse_args(&args(&["--assert", "a == 42h", "--assert", "[2000h] == 1", "rom.bin"])).unwrap();
+        assert_eq!(options.asserts, vec!["a == 42h", "[2000h] == 1"]);
+        assert_eq!(parse_args(&args(&["--assert", "a ==", "rom.bin"])).unwrap_err(), "--assert: expression ends early");
+    }
+
+    #[test]
+    fn test_history_addresses() {
+        let options = parse_args(&args(&["--history", "0x2f3a", "--history", "2f3bh", "rom.bin"])).unwrap();
+
+        assert_eq!(options.history, vec![0x2f3a, 0x2f3b]);
+        assert!(parse_args(&args(&["--history", "nowhere", "rom.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_track_allocator() {
+        let options = parse_args(&args(&["--track-allocator", "hl:3000h:0x8000", "rom.bin"])).unwrap();
+
+        assert_eq!(options.allocators, vec![(RegisterPair::HL, 0x3000..0x8000)]);
+        assert!(parse_args(&args(&["--track-allocator", "sp:3000h:8000h", "rom.bin"])).is_err());
+        assert!(parse_args(&args(&["--track-allocator", "hl:3000h", "rom.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_stats_options() {
+        let options = parse_args(&args(&["--stats", "--usage-json", "usage.json", "rom.bin"])).unwrap();
+
+        assert_eq!(options.stats, Some(true));
+        assert_eq!(options.usage_json.as_deref(), Some("usage.json"));
+        assert_eq!(parse_args(&args(&["--prescan", "rom.bin"])).unwrap().prescan, Some(true));
+    }
+
+    #[test]
+    fn test_guest_log() {
+        assert_eq!(parse_args(&args(&["--guest-log", "rom.bin"])).unwrap().guest_log, Some(true));
+        assert_eq!(parse_args(&args(&["rom.bin"])).unwrap().guest_log, None);
+        assert_eq!(parse_args(&args(&["--guest-log", "--no-guest-log", "rom.bin"])).unwrap().guest_log, Some(false));
+    }
+
+    #[test]
+    fn test_devices() {
+        let options = parse_args(&args(&["--device", "timer:port=0x20,tick_ms=10", "--device", "console", "rom.bin"])).unwrap();
+
+        assert_eq!(options.devices, ["timer:port=0x20,tick_ms=10", "console"]);
+        assert_eq!(parse_args(&args(&["--device", "timer:tick=10", "rom.bin"])).unwrap_err(),
+                   "--device: timer: unknown setting 'tick'; it takes port, tick_ms");
+        assert!(parse_args(&args(&["--device", "floppy", "rom.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_rtc_epoch() {
+        let options = parse_args(&args(&["--rtc-epoch", "1983-06-01T09:00:00Z", "rom.bin"])).unwrap();
+
+        assert_eq!(options.rtc_epoch.map(|epoch| epoch.to_string()).as_deref(), Some("1983-06-01T09:00:00Z"));
+        assert!(parse_args(&args(&["--rtc-epoch", "1983-06-31T09:00:00", "rom.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_seed() {
+        assert_eq!(parse_args(&args(&["--seed", "494", "rom.bin"])).unwrap().seed, Some(494));
+        assert!(parse_args(&args(&["--seed", "lucky", "rom.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_gif_options() {
+        let options = parse_args(&args(&["--gif", "run.gif", "--gif-frames", "4", "rom.bin"])).unwrap();
+
+        assert_eq!(options.gif.as_deref(), Some("run.gif"));
+        assert_eq!(options.gif_frames, Some(4));
+        assert!(parse_args(&args(&["--gif-frames", "4", "rom.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_trace_options() {
+        let options = parse_args(&args(&[
+            "--trace-range", "0x0300:0x04FF", "--trace-class", "flow", "--trace-class", "io,stack", "rom.bin",
+        ])).unwrap();
+
+        assert_eq!(options.trace, Some(TraceFilter::new().range(0x0300..=0x04ff)
+            .classes(processor::TRACE_FLOW | processor::TRACE_IO | processor::TRACE_STACK)));
+        assert_eq!(parse_args(&args(&["--trace", "rom.bin"])).unwrap().trace, Some(TraceFilter::new()));
+        assert_eq!(parse_args(&args(&["--trace-every", "10", "rom.bin"])).unwrap().trace, Some(TraceFilter::new().every(10)));
+        assert_eq!(parse_args(&args(&["rom.bin"])).unwrap().trace, None);
+        assert!(parse_args(&args(&["--trace-class", "jumps", "rom.bin"])).is_err());
+        let options = parse_args(&args(&["--trace-flags", "rom.bin"])).unwrap();
+        assert_eq!((options.trace, options.trace_flags), (Some(TraceFilter::new()), Some(true)));
+    }
+
+    #[test]
+    fn test_machine_options() {
+        let options = parse_args(&args(&["--machine", "invaders", "--scale", "3", "--rom", "invaders.rom"])).unwrap();
+
+        assert_eq!(options.machine, Some(Machine::Invaders));
+        assert_eq!(options.scale, Some(3));
+        assert_eq!(options.program, "invaders.rom");
+        assert!(parse_args(&args(&["--machine", "galaxian", "rom.bin"])).is_err());
+        assert!(parse_args(&args(&["--samples", "sounds", "rom.bin"])).is_err());
+        assert!(parse_args(&args(&["--rom", "a.bin", "b.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_machine_option_settings() {
+        let options = parse_args(&args(&["--machine", "invaders", "--option", "lives=5", "--option", "coin_info=off", "rom.bin"])).unwrap();
+
+        assert_eq!(options.machine_options, [("lives".to_string(), "5".to_string()), ("coin_info".to_string(), "off".to_string())]);
+        assert_eq!(parse_args(&args(&["--machine", "invaders", "--option", "lives=9", "rom.bin"])).unwrap_err(),
+                   "--option: lives: invalid value '9'; expected one of 3, 4, 5, 6");
+        assert_eq!(parse_args(&args(&["--machine", "invaders", "--option", "speed=2", "rom.bin"])).unwrap_err(),
+                   "--option: unknown option 'speed'; expected one of lives, bonus_at, coin_info");
+        assert!(parse_args(&args(&["--machine", "invaders", "--option", "lives", "rom.bin"])).is_err());
+        assert!(parse_args(&args(&["--option", "lives=5", "rom.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_debug_script_implies_debug() {
+        let options = parse_args(&args(&["--debug-script", "cmds.txt", "rom.bin"])).unwrap();
+
+        assert_eq!(options.debug, Some(true));
+        assert_eq!(options.debug_script.as_deref(), Some("cmds.txt"));
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        assert!(parse_args(&args(&[])).is_err());
+        assert!(parse_args(&args(&["--max-instructions", "lots", "rom.bin"])).is_err());
+        assert!(parse_args(&args(&["--bogus", "rom.bin"])).is_err());
+        assert!(parse_args(&args(&["--mhz", "0", "rom.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip_subcommand() {
+        assert_eq!(parse_command(&args(&["roundtrip", "rom.bin", "--origin", "0x100"])).unwrap(),
+                   Command::Roundtrip(DisasmArgs { path: "rom.bin".to_string(), origin: 0x100, ..Default::default() }));
+        assert_eq!(parse_command(&args(&["rom.bin"])).unwrap(),
+                   Command::Run(Box::new(Options { program: "rom.bin".to_string(), ..Default::default() })));
+        assert!(parse_command(&args(&["roundtrip"])).is_err());
+    }
+
+    #[test]
+    fn test_disasm_subcommand() {
+        assert_eq!(parse_command(&args(&["disasm", "--root", "0x100", "--root", "0x200", "--format", "asm", "rom.bin"])).unwrap(),
+                   Command::Disasm(DisasmArgs {
+                       path: "rom.bin".to_string(),
+                       origin: 0,
+                       roots: vec![0x100, 0x200],
+                       format: disasm::Format::Asm,
+                   }));
+        assert_eq!(parse_command(&args(&["disasm", "--format", "intel", "rom.bin"])).unwrap_err(),
+                   "--format: expected listing or asm, got 'intel'");
+    }
+
+    #[test]
+    fn test_variant_check_subcommand() {
+        assert_eq!(parse_command(&args(&["variant-check", "--origin", "0x100", "--root", "0x100", "rom.bin"])).unwrap(),
+                   Command::VariantCheck(VariantCheckArgs {
+                       path: "rom.bin".to_string(),
+                       origin: 0x100,
+                       roots: vec![0x100],
+                       ..Default::default()
+                   }));
+        assert_eq!(parse_command(&args(&["variant-check", "--run", "--max-instructions", "500", "rom.bin"])).unwrap(),
+                   Command::VariantCheck(VariantCheckArgs {
+                       path: "rom.bin".to_string(),
+                       run: true,
+                       max_instructions: Some(500),
+                       ..Default::default()
+                   }));
+        assert_eq!(parse_command(&args(&["variant-check", "--max-instructions", "500", "rom.bin"])).unwrap_err(),
+                   "--max-instructions needs --run");
+        assert!(parse_command(&args(&["variant-check", "--run", "--root", "0", "rom.bin"])).is_err());
+        assert!(parse_command(&args(&["variant-check"])).is_err());
+    }
+
+    #[test]
+    fn test_schema_subcommand() {
+        assert_eq!(parse_command(&args(&["schema", "event"])).unwrap(), Command::Schema { name: "event".to_string() });
+        assert!(parse_command(&args(&["schema", "registers"])).is_err());
+        assert!(parse_command(&args(&["schema"])).is_err());
+        assert_eq!(parse_args(&args(&["--json", "rom.bin"])).unwrap().json, Some(true));
+    }
+
+    #[test]
+    fn test_snapshot_diff_subcommand() {
+        assert_eq!(parse_command(&args(&["snapshot-diff", "a.json", "b.json"])).unwrap(),
+                   Command::SnapshotDiff { a: "a.json".to_string(), b: "b.json".to_string() });
+        assert!(parse_command(&args(&["snapshot-diff", "a.json"])).is_err());
+    }
+
+    #[test]
+    fn test_repro_subcommand() {
+        assert_eq!(parse_command(&args(&["repro", "--window", "8", "ckpt/checkpoint-1.json", "--out", "case.json"])).unwrap(),
+                   Command::Repro(ReproArgs {
+                       checkpoint: "ckpt/checkpoint-1.json".to_string(),
+                       window: Some(8),
+                       max_instructions: None,
+                       out: Some("case.json".to_string()),
+                   }));
+        assert!(parse_command(&args(&["repro"])).is_err());
+    }
+
+    #[test]
+    fn test_selftest_flag() {
+        assert_eq!(parse_command(&args(&["--selftest"])).unwrap(), Command::SelfTest);
+        assert!(parse_command(&args(&["--selftest", "rom.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_compare_profiles_command() {
+        assert_eq!(parse_command(&args(&["--compare-profiles", "--max-instructions", "500", "rom.bin"])).unwrap(),
+                   Command::CompareProfiles { program: "rom.bin".to_string(), max_instructions: Some(500) });
+        assert!(parse_command(&args(&["--compare-profiles"])).is_err());
+        assert!(parse_command(&args(&["--compare-profiles", "--trace", "rom.bin"])).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_options_and_resume() {
+        let options = parse_args(&args(&["--checkpoint-dir", "ckpt", "--checkpoint-interval", "5000000", "rom.bin"])).unwrap();
+        assert_eq!(options.checkpoint_dir.as_deref(), Some("ckpt"));
+        assert_eq!(options.checkpoint_interval, Some(5_000_000));
+        assert!(!options.resume);
+        assert!(parse_args(&args(&["--checkpoint-keep", "2", "rom.bin"])).is_err());
+
+        let Command::Run(options) = parse_command(&args(&["resume", "ckpt/checkpoint-1.json"])).unwrap() else {
+            panic!("expected a run");
+        };
+        assert!(options.resume);
+        assert_eq!(options.program, "ckpt/checkpoint-1.json");
+    }
+
+    #[test]
+    fn test_run_package() {
+        let Command::Run(options) = parse_command(&args(&["run", "--max-instructions", "10", "session.tar"])).unwrap() else {
+            panic!("expected a run");
+        };
+        assert!(options.package && !options.resume);
+        assert_eq!((options.program.as_str(), options.max_instructions), ("session.tar", Some(10)));
+        assert!(parse_command(&args(&["run", "--patchset", "cpudiag-stack", "session.tar"])).is_err());
+    }
+}
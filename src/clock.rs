@@ -0,0 +1,95 @@
+//! Time as the emulator sees it. Everything that needs the time (deadlines,
+//! progress, throttling, timer devices) asks a `Clock` instead of calling
+//! `Instant::now` directly, so tests can use a `ManualClock` and never
+//! really sleep.
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub trait Clock: Debug + Send {
+    /// Time since a fixed point chosen by the clock.
+    fn now(&self) -> Duration;
+
+    fn sleep(&self, duration: Duration);
+}
+
+/// The real monotonic clock. `now` counts from when it was created.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    origin: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        return SystemClock { origin: Instant::now() };
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        return SystemClock::new();
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        return self.origin.elapsed();
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+impl Default for Box<dyn Clock> {
+    fn default() -> Box<dyn Clock> {
+        return Box::new(SystemClock::new());
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a
+/// test can keep one and hand another to the code under test. `sleep`
+/// advances the time instantly.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        return ManualClock::default();
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        return *self.now.lock().unwrap();
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_is_shared_between_clones() {
+        let clock = ManualClock::new();
+        let handle: Box<dyn Clock> = Box::new(clock.clone());
+
+        clock.advance(Duration::from_millis(5));
+        handle.sleep(Duration::from_millis(10));
+
+        assert_eq!(clock.now(), Duration::from_millis(15));
+        assert_eq!(handle.now(), Duration::from_millis(15));
+    }
+}
@@ -0,0 +1,382 @@
+//! A compact binary format for full-run traces. Each record is stored as
+//! its difference from the one before: the PC is usually the previous one
+//! plus the instruction's length, the instruction bytes at a PC usually
+//! match the last time it ran, and most registers are unchanged. A full
+//! keyframe every so often keeps any damage local and gives readers places
+//! to start from.
+//!
+//! The file is `MAGIC` and a version byte, then one entry per record. An
+//! entry starts with a byte of `CHANGED_*` bits saying what follows; the
+//! cycle delta always does. A `KEYFRAME` entry holds the whole record and
+//! forgets the instruction bytes seen so far.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{self, Read, Write};
+
+use crate::processor::{instruction_length, ConditionBits, Registers, TraceRecord, Tracer};
+
+pub const MAGIC: [u8; 4] = *b"I8TR";
+pub const VERSION: u8 = 1;
+
+/// Records between keyframes unless configured otherwise.
+pub const DEFAULT_KEYFRAME_INTERVAL: u64 = 4096;
+
+const KEYFRAME: u8 = 0x80;
+/// Instructions did not advance by one: a varint delta follows.
+const CHANGED_INSTRUCTIONS: u8 = 0x01;
+/// The PC is not after the previous instruction: a word follows.
+const CHANGED_PC: u8 = 0x02;
+/// The bytes at the PC are new or have changed: three bytes follow.
+const CHANGED_BYTES: u8 = 0x04;
+/// A mask of changed registers, A to L in bits 0-6, then their values.
+const CHANGED_REGISTERS: u8 = 0x08;
+const CHANGED_SP: u8 = 0x10;
+const CHANGED_FLAGS: u8 = 0x20;
+/// The registers' PC differs from the record's.
+const CHANGED_REGISTER_PC: u8 = 0x40;
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn byte_registers(registers: &Registers) -> [u8; 7] {
+    return [registers.a, registers.b, registers.c, registers.d, registers.e, registers.h, registers.l];
+}
+
+fn set_byte_registers(registers: &mut Registers, values: [u8; 7]) {
+    [registers.a, registers.b, registers.c, registers.d, registers.e, registers.h, registers.l] = values;
+}
+
+/// Where execution goes after `record` if it does not jump.
+fn next_pc(record: &TraceRecord) -> u16 {
+    return record.pc.wrapping_add(instruction_length(record.bytes[0]) as u16);
+}
+
+/// Writes `TraceRecord`s in the compact format. Also a `Tracer`, which
+/// keeps the first write error for `finish`.
+#[derive(Debug)]
+pub struct CompactTraceWriter<W: Write> {
+    out: W,
+    previous: Option<TraceRecord>,
+    /// The instruction bytes last written for each PC since the keyframe.
+    known: HashMap<u16, [u8; 3]>,
+    keyframe_interval: u64,
+    since_keyframe: u64,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> CompactTraceWriter<W> {
+    pub fn new(mut out: W) -> io::Result<CompactTraceWriter<W>> {
+        out.write_all(&MAGIC)?;
+        out.write_all(&[VERSION])?;
+        return Ok(CompactTraceWriter {
+            out,
+            previous: None,
+            known: HashMap::new(),
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+            since_keyframe: 0,
+            error: None,
+        });
+    }
+
+    pub fn keyframe_interval(mut self, interval: u64) -> CompactTraceWriter<W> {
+        self.keyframe_interval = interval.max(1);
+        return self;
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let mut entry: Vec<u8> = Vec::with_capacity(16);
+        match self.previous.filter(|_| self.since_keyframe < self.keyframe_interval) {
+            Some(previous) => self.encode_delta(&mut entry, &previous, record),
+            None => self.encode_keyframe(&mut entry, record),
+        }
+        self.previous = Some(*record);
+        return self.out.write_all(&entry);
+    }
+
+    /// Flushes and hands back the output, or the first error `trace` hit.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.out.flush()?;
+        return Ok(self.out);
+    }
+
+    fn encode_keyframe(&mut self, entry: &mut Vec<u8>, record: &TraceRecord) {
+        entry.push(KEYFRAME);
+        write_varint(entry, record.instructions);
+        write_varint(entry, record.cycles);
+        write_u16(entry, record.pc);
+        entry.extend_from_slice(&record.bytes);
+        entry.extend_from_slice(&byte_registers(&record.registers));
+        write_u16(entry, record.registers.sp);
+        write_u16(entry, record.registers.pc);
+        entry.push(record.registers.flags.convert_to_flags());
+        self.known.clear();
+        self.known.insert(record.pc, record.bytes);
+        self.since_keyframe = 1;
+    }
+
+    fn encode_delta(&mut self, entry: &mut Vec<u8>, previous: &TraceRecord, record: &TraceRecord) {
+        let (before, after) = (&previous.registers, &record.registers);
+        let mut changed: u8 = 0;
+        let mut body: Vec<u8> = Vec::new();
+        write_varint(&mut body, record.cycles.wrapping_sub(previous.cycles));
+        if record.instructions != previous.instructions.wrapping_add(1) {
+            changed |= CHANGED_INSTRUCTIONS;
+            write_varint(&mut body, record.instructions.wrapping_sub(previous.instructions));
+        }
+        if record.pc != next_pc(previous) {
+            changed |= CHANGED_PC;
+            write_u16(&mut body, record.pc);
+        }
+        if self.known.get(&record.pc) != Some(&record.bytes) {
+            changed |= CHANGED_BYTES;
+            body.extend_from_slice(&record.bytes);
+            self.known.insert(record.pc, record.bytes);
+        }
+        let (old, new) = (byte_registers(before), byte_registers(after));
+        let register_mask: u8 = (0..7).filter(|i| old[*i] != new[*i]).fold(0, |mask, i| mask | 1 << i);
+        if register_mask != 0 {
+            changed |= CHANGED_REGISTERS;
+            body.push(register_mask);
+            body.extend((0..7).filter(|i| register_mask & 1 << i != 0).map(|i| new[i]));
+        }
+        if after.sp != before.sp {
+            changed |= CHANGED_SP;
+            write_u16(&mut body, after.sp);
+        }
+        if after.flags != before.flags {
+            changed |= CHANGED_FLAGS;
+            body.push(after.flags.convert_to_flags());
+        }
+        if after.pc != record.pc {
+            changed |= CHANGED_REGISTER_PC;
+            write_u16(&mut body, after.pc);
+        }
+        entry.push(changed);
+        entry.append(&mut body);
+        self.since_keyframe += 1;
+    }
+}
+
+impl<W: Write + Debug + Send> Tracer for CompactTraceWriter<W> {
+    fn trace(&mut self, record: &TraceRecord, _after: &Registers) {
+        if self.error.is_none() {
+            self.error = self.write(record).err();
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+}
+
+/// Reads back what `CompactTraceWriter` wrote, one record at a time.
+#[derive(Debug)]
+pub struct CompactTraceReader<R: Read> {
+    input: R,
+    previous: Option<TraceRecord>,
+    known: HashMap<u16, [u8; 3]>,
+}
+
+impl<R: Read> CompactTraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<CompactTraceReader<R>> {
+        let mut header: [u8; 5] = [0; 5];
+        input.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(invalid("not a compact trace"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid(&format!("unsupported compact trace version {}", header[4])));
+        }
+        return Ok(CompactTraceReader { input, previous: None, known: HashMap::new() });
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut byte: [u8; 1] = [0];
+        self.input.read_exact(&mut byte)?;
+        return Ok(byte[0]);
+    }
+
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes: [u8; N] = [0; N];
+        self.input.read_exact(&mut bytes)?;
+        return Ok(bytes);
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        return Ok(u16::from_le_bytes(self.bytes()?));
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte: u8 = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        return Err(invalid("varint too long"));
+    }
+
+    fn read_keyframe(&mut self) -> io::Result<TraceRecord> {
+        let instructions: u64 = self.varint()?;
+        let cycles: u64 = self.varint()?;
+        let pc: u16 = self.u16()?;
+        let bytes: [u8; 3] = self.bytes()?;
+        let mut registers = Registers::default();
+        set_byte_registers(&mut registers, self.bytes()?);
+        registers.sp = self.u16()?;
+        registers.pc = self.u16()?;
+        registers.flags = ConditionBits::from_psw_byte(self.byte()?);
+        self.known.clear();
+        self.known.insert(pc, bytes);
+        return Ok(TraceRecord { instructions, cycles, pc, bytes, registers });
+    }
+
+    fn read_delta(&mut self, changed: u8, previous: TraceRecord) -> io::Result<TraceRecord> {
+        let mut record: TraceRecord = previous;
+        record.cycles = previous.cycles.wrapping_add(self.varint()?);
+        record.instructions = match changed & CHANGED_INSTRUCTIONS {
+            0 => previous.instructions.wrapping_add(1),
+            _ => previous.instructions.wrapping_add(self.varint()?),
+        };
+        record.pc = if changed & CHANGED_PC != 0 { self.u16()? } else { next_pc(&previous) };
+        record.bytes = match changed & CHANGED_BYTES {
+            0 => *self.known.get(&record.pc).ok_or_else(|| invalid("instruction bytes missing"))?,
+            _ => self.bytes()?,
+        };
+        self.known.insert(record.pc, record.bytes);
+        if changed & CHANGED_REGISTERS != 0 {
+            let mask: u8 = self.byte()?;
+            let mut values: [u8; 7] = byte_registers(&record.registers);
+            for (i, value) in values.iter_mut().enumerate() {
+                if mask & 1 << i != 0 {
+                    *value = self.byte()?;
+                }
+            }
+            set_byte_registers(&mut record.registers, values);
+        }
+        if changed & CHANGED_SP != 0 {
+            record.registers.sp = self.u16()?;
+        }
+        if changed & CHANGED_FLAGS != 0 {
+            record.registers.flags = ConditionBits::from_psw_byte(self.byte()?);
+        }
+        record.registers.pc = if changed & CHANGED_REGISTER_PC != 0 { self.u16()? } else { record.pc };
+        return Ok(record);
+    }
+
+    fn read_record(&mut self, changed: u8) -> io::Result<TraceRecord> {
+        return match self.previous {
+            _ if changed == KEYFRAME => self.read_keyframe(),
+            Some(previous) if changed & KEYFRAME == 0 => self.read_delta(changed, previous),
+            Some(_) => Err(invalid("unknown entry")),
+            None => Err(invalid("trace does not start with a keyframe")),
+        };
+    }
+}
+
+impl<R: Read> Iterator for CompactTraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<io::Result<TraceRecord>> {
+        let changed: u8 = match self.byte() {
+            Ok(changed) => changed,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err)),
+        };
+        let record = self.read_record(changed);
+        if let Ok(record) = &record {
+            self.previous = Some(*record);
+        }
+        return Some(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use crate::processor::{ProcessorBuilder, RunOutcome};
+
+    #[derive(Debug, Clone, Default)]
+    struct RecordingTracer {
+        records: Arc<Mutex<Vec<TraceRecord>>>,
+    }
+
+    impl Tracer for RecordingTracer {
+        fn trace(&mut self, record: &TraceRecord, _after: &Registers) {
+            self.records.lock().unwrap().push(*record);
+        }
+    }
+
+    fn compress(records: &[TraceRecord], keyframe_interval: u64) -> Vec<u8> {
+        let mut writer = CompactTraceWriter::new(Vec::new()).unwrap().keyframe_interval(keyframe_interval);
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        return writer.finish().unwrap();
+    }
+
+    fn decompress(bytes: &[u8]) -> Vec<TraceRecord> {
+        return CompactTraceReader::new(bytes).unwrap().collect::<io::Result<Vec<TraceRecord>>>().unwrap();
+    }
+
+    #[test]
+    fn test_memcpy_trace_round_trips_ten_times_smaller() {
+        let tracer = RecordingTracer::default();
+        let mut processor = ProcessorBuilder::new().tracer(Box::new(tracer.clone())).build();
+        processor.load_program(&fs::read("tests/memcpy.bin").unwrap());
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        let records: Vec<TraceRecord> = tracer.records.lock().unwrap().clone();
+
+        let compact: Vec<u8> = compress(&records, DEFAULT_KEYFRAME_INTERVAL);
+        let text: usize = records.iter().map(|record| record.to_string().len() + 1).sum();
+
+        assert!(compact.len() * 10 <= text, "{} bytes compact, {} as text", compact.len(), text);
+        assert_eq!(decompress(&compact), records);
+        assert_eq!(decompress(&compress(&records, 7)), records);
+    }
+
+    #[test]
+    fn test_jumps_rewritten_code_and_odd_registers_round_trip() {
+        let record = |instructions: u64, cycles: u64, pc: u16, bytes: [u8; 3]| -> TraceRecord {
+            let registers = Registers { a: instructions as u8, sp: 0x2000 - cycles as u16, pc, ..Registers::default() };
+            return TraceRecord { instructions, cycles, pc, bytes, registers };
+        };
+        let mut records: Vec<TraceRecord> = vec![
+            record(0, 0, 0x0100, [0x3e, 0x01, 0x00]),
+            record(1, 7, 0x0102, [0xc3, 0x00, 0x01]),
+            record(2, 17, 0x0100, [0x3e, 0x02, 0x00]), // MVI operand patched
+            record(40, 3, 0x0100, [0x3e, 0x02, 0x00]), // put back to earlier
+        ];
+        records[2].registers.flags.carry = true;
+        records[3].registers.pc = 0x1234;
+
+        assert_eq!(decompress(&compress(&records, 2)), records);
+        assert_eq!(decompress(&compress(&records, DEFAULT_KEYFRAME_INTERVAL)), records);
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        assert_eq!(CompactTraceReader::new(&b"GIF89a"[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut delta_first: Vec<u8> = MAGIC.to_vec();
+        delta_first.extend([VERSION, 0x00, 0x04]);
+        assert!(CompactTraceReader::new(&delta_first[..]).unwrap().next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,616 @@
+//! `--config <file>`: the run flags as a TOML file. Every key mirrors a
+//! flag, so `max_instructions = 500` is `--max-instructions 500`, and the
+//! file is read by turning it back into flags, which `cli` checks as it
+//! would on the command line. `[rom]` is a ROM entry as a package manifest
+//! writes it: the program, where it loads and the patch sets to apply.
+//!
+//! Flags on the command line override the file: a value replaces the
+//! file's, a repeatable flag replaces the file's whole list, and a switch
+//! wins either way: `--strict` turns on what the file leaves off, and
+//! `--no-strict` turns off what the file turns on.
+//!
+//! ```toml
+//! sp = 0x2400
+//! asserts = ["a == 42h"]
+//!
+//! [rom]
+//! file = "cpudiag.bin"
+//! load_addr = 0x0100
+//! patchsets = ["cpudiag-stack"]
+//!
+//! [trace]
+//! classes = ["flow", "io"]
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use intel_8080_emu::package::RomEntry;
+
+use crate::cli::Options;
+
+/// Every key a config file may hold, a table's keys as `table.key`.
+pub const KEYS: &[&str] = &[
+    "rom", "rom.file", "rom.load_addr", "rom.patchsets",
+    "sp",
+    "pokes", "pokes.addr", "pokes.value",
+    "relocate", "relocate.from", "relocate.to", "relocate.data", "relocate.data.start", "relocate.data.end",
+    "machine", "machine.name", "machine.options", "machine.scale", "machine.samples",
+    "devices", "rtc_epoch", "guest_log", "seed",
+    "max_instructions", "mhz", "strict", "fast_forward_loops", "unattached_ports",
+    "allocators", "allocators.pair", "allocators.start", "allocators.end",
+    "progress", "debug", "debug_script", "prescan", "stats", "usage_json", "history",
+    "events", "watch_exprs", "asserts", "artifacts_dir",
+    "checkpoint", "checkpoint.dir", "checkpoint.interval", "checkpoint.keep",
+    "gif", "gif.file", "gif.frames",
+    "trace", "trace.ranges", "trace.ranges.start", "trace.ranges.end", "trace.classes", "trace.flags", "trace.every",
+    "json", "color",
+];
+
+/// Tables whose keys are names the file chooses, such as machine options.
+const OPEN_TABLES: &[&str] = &["machine.options"];
+
+/// A byte to store before the run: `--poke <addr>=<value>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Poke {
+    pub addr: u16,
+    pub value: u8,
+}
+
+/// `start` to `end`: exclusive for `relocate.data`, inclusive for
+/// `trace.ranges`, as with the flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddrRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+/// `--relocate <from>:<to>` and its `--reloc-data` ranges.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Relocation {
+    pub from: u16,
+    pub to: u16,
+    #[serde(default)]
+    pub data: Vec<AddrRange>,
+}
+
+/// `--machine` and the flags that need it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MachineConfig {
+    pub name: String,
+    /// DIP switch settings by option name, as in a package manifest.
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    #[serde(default)]
+    pub scale: Option<u32>,
+    #[serde(default)]
+    pub samples: Option<String>,
+}
+
+/// `--track-allocator <pair>:<start>:<end>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Allocator {
+    pub pair: String,
+    pub start: u16,
+    pub end: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointConfig {
+    pub dir: String,
+    #[serde(default)]
+    pub interval: Option<u64>,
+    #[serde(default)]
+    pub keep: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GifConfig {
+    pub file: String,
+    #[serde(default)]
+    pub frames: Option<u64>,
+}
+
+/// The `--trace` flags. An empty `[trace]` traces everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TraceConfig {
+    pub ranges: Vec<AddrRange>,
+    pub classes: Vec<String>,
+    pub flags: bool,
+    pub every: Option<u64>,
+}
+
+/// A config file. Keys left out are flags not given.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub rom: Option<RomEntry>,
+    pub sp: Option<u16>,
+    pub pokes: Vec<Poke>,
+    pub relocate: Option<Relocation>,
+    pub machine: Option<MachineConfig>,
+    /// `--device` specs, e.g. `timer:port=0x20,tick_ms=10`.
+    pub devices: Vec<String>,
+    pub rtc_epoch: Option<String>,
+    pub guest_log: bool,
+    pub seed: Option<u64>,
+    pub max_instructions: Option<u64>,
+    pub mhz: Option<f64>,
+    pub strict: bool,
+    pub fast_forward_loops: bool,
+    pub unattached_ports: bool,
+    pub allocators: Vec<Allocator>,
+    pub progress: bool,
+    pub debug: bool,
+    pub debug_script: Option<String>,
+    pub prescan: bool,
+    pub stats: bool,
+    pub usage_json: Option<String>,
+    pub history: Vec<u16>,
+    pub events: Option<String>,
+    pub watch_exprs: Vec<String>,
+    pub asserts: Vec<String>,
+    pub artifacts_dir: Option<String>,
+    pub checkpoint: Option<CheckpointConfig>,
+    pub gif: Option<GifConfig>,
+    pub trace: Option<TraceConfig>,
+    pub json: bool,
+    /// auto, always or never.
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    Io { message: String },
+    /// Not TOML, or a value of the wrong type. The message says where.
+    Parse { message: String },
+    /// `key` is not in `KEYS`; `nearest` is the one it most looks like.
+    UnknownKey { key: String, nearest: Option<String> },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            ConfigError::Io { message } => write!(f, "{}", message),
+            ConfigError::Parse { message } => write!(f, "{}", message.trim_end()),
+            ConfigError::UnknownKey { key, nearest: Some(nearest) } => write!(f, "unknown key '{}'; did you mean '{}'?", key, nearest),
+            ConfigError::UnknownKey { key, nearest: None } => write!(f, "unknown key '{}'", key),
+        };
+    }
+}
+
+/// Parses a config file's text, rejecting keys not in `KEYS`.
+pub fn parse(text: &str) -> Result<Config, ConfigError> {
+    let table: toml::Table = text.parse().map_err(|err: toml::de::Error| ConfigError::Parse { message: err.to_string() })?;
+    check_keys(&table, "")?;
+    return toml::from_str(text).map_err(|err| ConfigError::Parse { message: err.to_string() });
+}
+
+pub fn load(path: &Path) -> Result<Config, ConfigError> {
+    let text: String = fs::read_to_string(path).map_err(|err| ConfigError::Io { message: err.to_string() })?;
+    return parse(&text);
+}
+
+/// Checks the keys of `table`, found at `prefix`, and of the tables in it.
+fn check_keys(table: &toml::Table, prefix: &str) -> Result<(), ConfigError> {
+    for (name, value) in table {
+        let key: String = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+        if !KEYS.contains(&key.as_str()) {
+            return Err(ConfigError::UnknownKey { nearest: nearest_key(&key).map(str::to_string), key });
+        }
+        if OPEN_TABLES.contains(&key.as_str()) {
+            continue;
+        }
+        match value {
+            toml::Value::Table(inner) => check_keys(inner, &key)?,
+            toml::Value::Array(items) => for item in items {
+                if let toml::Value::Table(inner) = item {
+                    check_keys(inner, &key)?;
+                }
+            },
+            _ => {},
+        }
+    }
+    return Ok(());
+}
+
+/// The key in `KEYS` closest to `key` by edit distance, if it is close
+/// enough to be a likely typo.
+fn nearest_key(key: &str) -> Option<&'static str> {
+    let key: String = key.replace('-', "_");
+    let (distance, nearest) = KEYS.iter().map(|candidate| (edit_distance(&key, candidate), *candidate)).min()?;
+    return (distance <= (key.len() / 3).max(2)).then_some(nearest);
+}
+
+/// Levenshtein distance, counting insertions, deletions and substitutions.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal: usize = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substituted: usize = diagonal + (a_char != *b_char) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    return row[b.len()];
+}
+
+impl Config {
+    /// The command-line flags that say what the file says.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Args::default();
+        if let Some(rom) = &self.rom {
+            args.value("--rom", &rom.file);
+            if rom.load_addr != 0 {
+                args.value("--origin", format!("{:#06x}", rom.load_addr));
+            }
+            args.each("--patchset", &rom.patchsets);
+        }
+        args.option("--sp", self.sp.map(|sp| format!("{:#06x}", sp)));
+        args.each("--poke", self.pokes.iter().map(|poke| format!("{:#06x}={:#04x}", poke.addr, poke.value)));
+        if let Some(relocate) = &self.relocate {
+            args.value("--relocate", format!("{:#06x}:{:#06x}", relocate.from, relocate.to));
+            args.each("--reloc-data", relocate.data.iter().map(|range| format!("{:#06x}:{:#06x}", range.start, range.end)));
+        }
+        if let Some(machine) = &self.machine {
+            args.value("--machine", &machine.name);
+            args.each("--option", machine.options.iter().map(|(name, value)| format!("{}={}", name, value)));
+            args.option("--scale", machine.scale);
+            args.option("--samples", machine.samples.as_ref());
+        }
+        args.each("--device", &self.devices);
+        args.option("--rtc-epoch", self.rtc_epoch.as_ref());
+        args.switch("--guest-log", self.guest_log);
+        args.option("--seed", self.seed);
+        args.option("--max-instructions", self.max_instructions);
+        args.option("--mhz", self.mhz);
+        args.switch("--strict", self.strict);
+        args.switch("--fast-forward-loops", self.fast_forward_loops);
+        args.switch("--unattached-ports", self.unattached_ports);
+        args.each("--track-allocator", self.allocators.iter()
+            .map(|allocator| format!("{}:{:#06x}:{:#06x}", allocator.pair, allocator.start, allocator.end)));
+        args.switch("--progress", self.progress);
+        args.switch("--debug", self.debug);
+        args.option("--debug-script", self.debug_script.as_ref());
+        args.switch("--prescan", self.prescan);
+        args.switch("--stats", self.stats);
+        args.option("--usage-json", self.usage_json.as_ref());
+        args.each("--history", self.history.iter().map(|addr| format!("{:#06x}", addr)));
+        args.option("--events", self.events.as_ref());
+        args.each("--watch-expr", &self.watch_exprs);
+        args.each("--assert", &self.asserts);
+        args.option("--artifacts-dir", self.artifacts_dir.as_ref());
+        if let Some(checkpoint) = &self.checkpoint {
+            args.value("--checkpoint-dir", &checkpoint.dir);
+            args.option("--checkpoint-interval", checkpoint.interval);
+            args.option("--checkpoint-keep", checkpoint.keep);
+        }
+        if let Some(gif) = &self.gif {
+            args.value("--gif", &gif.file);
+            args.option("--gif-frames", gif.frames);
+        }
+        if let Some(trace) = &self.trace {
+            args.switch("--trace", true);
+            args.each("--trace-range", trace.ranges.iter().map(|range| format!("{:#06x}:{:#06x}", range.start, range.end)));
+            if !trace.classes.is_empty() {
+                args.value("--trace-class", trace.classes.join(","));
+            }
+            args.switch("--trace-flags", trace.flags);
+            args.option("--trace-every", trace.every);
+        }
+        args.switch("--json", self.json);
+        args.option("--color", self.color.as_ref());
+        return args.0;
+    }
+}
+
+/// Flags as `Config::to_args` builds them.
+#[derive(Default)]
+struct Args(Vec<String>);
+
+impl Args {
+    fn switch(&mut self, flag: &str, on: bool) {
+        if on {
+            self.0.push(flag.to_string());
+        }
+    }
+
+    fn value(&mut self, flag: &str, value: impl fmt::Display) {
+        self.0.push(flag.to_string());
+        self.0.push(value.to_string());
+    }
+
+    fn option(&mut self, flag: &str, value: Option<impl fmt::Display>) {
+        if let Some(value) = value {
+            self.value(flag, value);
+        }
+    }
+
+    fn each<T: fmt::Display>(&mut self, flag: &str, values: impl IntoIterator<Item = T>) {
+        for value in values {
+            self.value(flag, value);
+        }
+    }
+}
+
+/// A list from the command line if it has one, else the file's.
+fn list<T>(base: Vec<T>, flags: Vec<T>) -> Vec<T> {
+    return if flags.is_empty() { base } else { flags };
+}
+
+/// The options the command line gives in `flags` read over those a config
+/// file gives in `base`.
+pub fn overlay(base: Options, flags: Options) -> Options {
+    let Options {
+        program, config, origin, sp, pokes, progress, max_instructions, mhz, fast_forward_loops, strict, debug, debug_script,
+        relocate, reloc_data, patchsets, resume, package, checkpoint_dir, checkpoint_interval, checkpoint_keep, events,
+        artifacts_dir, watch_exprs, asserts, history, prescan, stats, allocators, unattached_ports, usage_json, rtc_epoch,
+        guest_log, seed, devices, gif, gif_frames, trace, trace_flags, json, color, machine, machine_options, scale, samples,
+    } = flags;
+    return Options {
+        program: if program.is_empty() { base.program } else { program },
+        config,
+        origin: origin.or(base.origin),
+        sp: sp.or(base.sp),
+        pokes: list(base.pokes, pokes),
+        progress: progress.or(base.progress),
+        max_instructions: max_instructions.or(base.max_instructions),
+        mhz: mhz.or(base.mhz),
+        fast_forward_loops: fast_forward_loops.or(base.fast_forward_loops),
+        strict: strict.or(base.strict),
+        debug: debug.or(base.debug),
+        debug_script: debug_script.or(base.debug_script),
+        relocate: relocate.or(base.relocate),
+        reloc_data: list(base.reloc_data, reloc_data),
+        patchsets: list(base.patchsets, patchsets),
+        resume: resume || base.resume,
+        package: package || base.package,
+        checkpoint_dir: checkpoint_dir.or(base.checkpoint_dir),
+        checkpoint_interval: checkpoint_interval.or(base.checkpoint_interval),
+        checkpoint_keep: checkpoint_keep.or(base.checkpoint_keep),
+        events: events.or(base.events),
+        artifacts_dir: artifacts_dir.or(base.artifacts_dir),
+        watch_exprs: list(base.watch_exprs, watch_exprs),
+        asserts: list(base.asserts, asserts),
+        history: list(base.history, history),
+        prescan: prescan.or(base.prescan),
+        stats: stats.or(base.stats),
+        allocators: list(base.allocators, allocators),
+        unattached_ports: unattached_ports.or(base.unattached_ports),
+        usage_json: usage_json.or(base.usage_json),
+        rtc_epoch: rtc_epoch.or(base.rtc_epoch),
+        guest_log: guest_log.or(base.guest_log),
+        seed: seed.or(base.seed),
+        devices: list(base.devices, devices),
+        gif: gif.or(base.gif),
+        gif_frames: gif_frames.or(base.gif_frames),
+        trace: trace.or(base.trace),
+        trace_flags: trace_flags.or(base.trace_flags),
+        json: json.or(base.json),
+        color: color.or(base.color),
+        machine: machine.or(base.machine),
+        machine_options: list(base.machine_options, machine_options),
+        scale: scale.or(base.scale),
+        samples: samples.or(base.samples),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    use intel_8080_emu::processor::{Processor, ProcessorBuilder, RunOutcome};
+    use intel_8080_emu::style::ColorChoice;
+
+    use crate::cli::parse_args;
+
+    /// A scratch directory for one test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir: PathBuf = env::temp_dir().join(format!("i8080-config-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    fn args(dir: &Path, list: &[&str]) -> Vec<String> {
+        return list.iter().map(|arg| arg.replace("$DIR", &dir.display().to_string())).collect();
+    }
+
+    /// Every key path in `value`, found at `prefix`.
+    fn key_paths(value: &toml::Value, prefix: &str, paths: &mut Vec<String>) {
+        let table = match value {
+            toml::Value::Table(table) => table,
+            toml::Value::Array(items) => return items.iter().for_each(|item| key_paths(item, prefix, paths)),
+            _ => return,
+        };
+        for (name, inner) in table {
+            let key: String = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+            if !paths.contains(&key) {
+                paths.push(key.clone());
+            }
+            if !OPEN_TABLES.contains(&key.as_str()) {
+                key_paths(inner, &key, paths);
+            }
+        }
+    }
+
+    #[test]
+    fn test_keys_match_the_structs() {
+        let range = AddrRange { start: 0, end: 1 };
+        let config = Config {
+            rom: Some(RomEntry { file: "rom.bin".to_string(), load_addr: 0, patchsets: vec![] }),
+            sp: Some(0),
+            pokes: vec![Poke { addr: 0, value: 0 }],
+            relocate: Some(Relocation { from: 0, to: 0, data: vec![range] }),
+            machine: Some(MachineConfig {
+                name: "invaders".to_string(),
+                options: BTreeMap::from([("lives".to_string(), "5".to_string())]),
+                scale: Some(1),
+                samples: Some(String::new()),
+            }),
+            seed: Some(0),
+            rtc_epoch: Some(String::new()),
+            max_instructions: Some(0),
+            mhz: Some(2.0),
+            allocators: vec![Allocator { pair: "hl".to_string(), start: 0, end: 1 }],
+            debug_script: Some(String::new()),
+            usage_json: Some(String::new()),
+            events: Some(String::new()),
+            artifacts_dir: Some(String::new()),
+            checkpoint: Some(CheckpointConfig { dir: String::new(), interval: Some(1), keep: Some(1) }),
+            gif: Some(GifConfig { file: String::new(), frames: Some(1) }),
+            trace: Some(TraceConfig { ranges: vec![range], classes: vec![], flags: false, every: Some(1) }),
+            color: Some(String::new()),
+            ..Default::default()
+        };
+
+        let mut paths: Vec<String> = Vec::new();
+        key_paths(&toml::Value::try_from(&config).unwrap(), "", &mut paths);
+
+        paths.sort();
+        let mut keys: Vec<&str> = KEYS.to_vec();
+        keys.sort();
+        assert_eq!(paths, keys);
+    }
+
+    #[test]
+    fn test_unknown_keys_name_the_nearest() {
+        let unknown = |text: &str| match parse(text) {
+            Err(ConfigError::UnknownKey { key, nearest }) => (key, nearest),
+            other => panic!("expected an unknown key, got {:?}", other),
+        };
+
+        assert_eq!(unknown("max_instruction = 5"), ("max_instruction".to_string(), Some("max_instructions".to_string())));
+        assert_eq!(unknown("max-instructions = 5"), ("max-instructions".to_string(), Some("max_instructions".to_string())));
+        assert_eq!(unknown("[trace]\nflgs = true"), ("trace.flgs".to_string(), Some("trace.flags".to_string())));
+        assert_eq!(unknown("trace_flags = true"), ("trace_flags".to_string(), Some("trace.flags".to_string())));
+        assert_eq!(unknown("[[pokes]]\naddr = 1\nvalu = 2"), ("pokes.valu".to_string(), Some("pokes.value".to_string())));
+        assert_eq!(unknown("frobnicate = 1"), ("frobnicate".to_string(), None));
+        assert_eq!(parse("strcit = true").unwrap_err().to_string(), "unknown key 'strcit'; did you mean 'strict'?");
+        assert!(parse("[machine]\nname = \"invaders\"\noptions = { lives = \"5\" }").is_ok());
+        assert!(matches!(parse("sp = \"top\""), Err(ConfigError::Parse { .. })));
+    }
+
+    #[test]
+    fn test_flags_override_the_file() {
+        let dir: PathBuf = scratch("precedence");
+        fs::write(dir.join("run.toml"), concat!(
+            "max_instructions = 500\n",
+            "strict = true\n",
+            "devices = [\"timer:port=0x20,tick_ms=10\", \"console\"]\n",
+            "sp = 0x2400\n",
+            "[rom]\n",
+            "file = \"file.bin\"\n",
+            "load_addr = 0x0100\n",
+        )).unwrap();
+
+        let from_file: Options = parse_args(&args(&dir, &["--config", "$DIR/run.toml"])).unwrap();
+        let overridden: Options = parse_args(&args(&dir, &[
+            "--config", "$DIR/run.toml", "--max-instructions", "9", "--device", "guest_log", "--origin", "0", "flag.bin",
+        ])).unwrap();
+
+        assert_eq!(from_file.program, "file.bin");
+        assert_eq!((from_file.max_instructions, from_file.origin, from_file.sp), (Some(500), Some(0x0100), Some(0x2400)));
+        assert_eq!(from_file.devices, ["timer:port=0x20,tick_ms=10", "console"]);
+        assert_eq!(overridden.program, "flag.bin");
+        assert_eq!((overridden.max_instructions, overridden.origin, overridden.sp), (Some(9), Some(0), Some(0x2400)));
+        assert_eq!(overridden.devices, ["guest_log"]);
+        assert_eq!(overridden.strict, Some(true));
+        let err: String = parse_args(&args(&dir, &["--config", "$DIR/missing.toml", "rom.bin"])).unwrap_err();
+        assert!(err.starts_with("--config "), "{}", err);
+        fs::write(dir.join("bad.toml"), "devices = [\"flux\"]\n").unwrap();
+        let err: String = parse_args(&args(&dir, &["--config", "$DIR/bad.toml", "rom.bin"])).unwrap_err();
+        assert!(err.contains("bad.toml: --device:"), "{}", err);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_flags_turn_off_and_reset_what_the_file_sets() {
+        let dir: PathBuf = scratch("switches");
+        fs::write(dir.join("run.toml"), concat!(
+            "strict = true\n",
+            "stats = true\n",
+            "json = true\n",
+            "color = \"never\"\n",
+            "[rom]\n",
+            "file = \"file.bin\"\n",
+            "load_addr = 0\n",
+            "[trace]\n",
+            "flags = true\n",
+        )).unwrap();
+
+        let from_file: Options = parse_args(&args(&dir, &["--config", "$DIR/run.toml"])).unwrap();
+        let overridden: Options = parse_args(&args(&dir, &[
+            "--config", "$DIR/run.toml", "--no-strict", "--no-json", "--no-trace-flags", "--color", "auto",
+        ])).unwrap();
+
+        assert_eq!((from_file.strict, from_file.json, from_file.trace_flags), (Some(true), Some(true), Some(true)));
+        assert_eq!(from_file.color, Some(ColorChoice::Never));
+        assert_eq!((overridden.strict, overridden.json, overridden.trace_flags), (Some(false), Some(false), Some(false)));
+        assert_eq!(overridden.color, Some(ColorChoice::Auto));
+        assert_eq!(overridden.stats, Some(true));
+        assert_eq!(parse_args(&args(&dir, &["--no-strikt", "rom.bin"])).unwrap_err(), "unknown option --no-strikt");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Runs the program as `main` would without devices or machines, and
+    /// hashes where it ends.
+    fn state_hash(options: &Options) -> u64 {
+        let mut processor: Processor = ProcessorBuilder::new().strict(options.strict == Some(true)).build();
+        crate::load(&mut processor, options);
+        crate::apply_start(&mut processor, options);
+        assert_eq!(processor.run_with_limit(options.max_instructions.unwrap()), RunOutcome::Halted);
+        return processor.snapshot().state_hash();
+    }
+
+    #[test]
+    fn test_file_alone_reproduces_a_flag_run() {
+        let dir: PathBuf = scratch("round-trip");
+        fs::write(dir.join("add.bin"), [
+            0x3a, 0x00, 0x20, // 0100 LDA 2000h
+            0x21, 0x01, 0x20, // 0103 LXI H,2001h
+            0x86,             // 0106 ADD M
+            0x32, 0x02, 0x20, // 0107 STA 2002h
+            0xf5,             // 010a PUSH PSW
+            0x76,             // 010b HLT
+        ]).unwrap();
+        fs::write(dir.join("add.toml"), concat!(
+            "sp = 0x3000\n",
+            "pokes = [{ addr = 0x2000, value = 0x20 }, { addr = 0x2001, value = 0x22 }]\n",
+            "max_instructions = 100\n",
+            "strict = true\n",
+            "asserts = [\"[2002h] == 42h\"]\n",
+            "artifacts_dir = \"artifacts\"\n",
+            "[rom]\n",
+            "file = \"$DIR/add.bin\"\n",
+            "load_addr = 0x0100\n",
+            "[trace]\n",
+            "ranges = [{ start = 0x0100, end = 0x0107 }]\n",
+            "classes = [\"data\"]\n",
+        ).replace("$DIR", &dir.display().to_string())).unwrap();
+
+        let by_flags: Options = parse_args(&args(&dir, &[
+            "--origin", "100h", "--sp", "3000h", "--poke", "2000h=20h", "--poke", "2001h=22h",
+            "--max-instructions", "100", "--strict", "--assert", "[2002h] == 42h", "--artifacts-dir", "artifacts",
+            "--trace-range", "100h:107h", "--trace-class", "data", "$DIR/add.bin",
+        ])).unwrap();
+        let by_file: Options = parse_args(&args(&dir, &["--config", "$DIR/add.toml"])).unwrap();
+
+        let hash: u64 = state_hash(&by_flags);
+        assert_eq!(state_hash(&by_file), hash);
+        assert_eq!(Options { config: None, ..by_file }, by_flags);
+        let bare: Options = parse_args(&args(&dir, &["--max-instructions", "100", "--origin", "100h", "$DIR/add.bin"])).unwrap();
+        assert_ne!(state_hash(&bare), hash);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+//! Deterministic generator of small, valid 8080 programs for regression
+//! runs. Every program halts within `Program::max_instructions` and only
+//! writes to `SCRATCH` and `STACK`.
+
+use std::ops::Range;
+
+use crate::asm;
+
+/// Memory the generated block operations read and write.
+pub const SCRATCH: Range<u16> = 0x2000..0x2100;
+
+/// Generated programs set SP to `STACK.end`; pushes land in this band.
+pub const STACK: Range<u16> = 0x2f00..0x3000;
+
+/// Registers straight-line code uses. H and L are left alone so that every
+/// memory access goes through a pointer a fragment set up itself.
+const REGISTERS: [&str; 5] = ["a", "b", "c", "d", "e"];
+
+/// Register operations; ALU ones take their operand from a register and
+/// only write A.
+const OPERATIONS: [&str; 12] = ["add", "adc", "sub", "sbb", "ana", "xra", "ora", "cmp", "mov", "mvi", "inr", "dcr"];
+
+const IMMEDIATES: [&str; 8] = ["adi", "aci", "sui", "sbi", "ani", "xri", "ori", "cpi"];
+
+const ACCUMULATOR_OPS: [&str; 7] = ["rlc", "rrc", "ral", "rar", "cma", "stc", "cmc"];
+
+/// A generated program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub seed: u64,
+    /// Assembly source, assembled at 0.
+    pub source: String,
+    pub bytes: Vec<u8>,
+    /// Every push is matched by a pop, so SP ends where it started.
+    pub stack_balanced: bool,
+    /// An upper bound on the instructions executed before HLT.
+    pub max_instructions: u64,
+}
+
+/// A 64-bit LCG; the high bits are used since the low ones are weak.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Rng {
+        return Rng { state: seed ^ 0x8080_8080_8080_8080 };
+    }
+
+    pub(crate) fn next(&mut self) -> u32 {
+        self.state = self.state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        return (self.state >> 33) as u32;
+    }
+
+    /// A value in `0..bound`.
+    pub(crate) fn below(&mut self, bound: u32) -> u32 {
+        return self.next() % bound;
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        return items[self.below(items.len() as u32) as usize];
+    }
+}
+
+struct Generator {
+    rng: Rng,
+    lines: Vec<String>,
+    labels: usize,
+    stack_balanced: bool,
+    max_instructions: u64,
+}
+
+impl Generator {
+    fn emit(&mut self, line: String) {
+        self.lines.push(format!("  {}", line));
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        return format!("l{}", self.labels);
+    }
+
+    /// One register or accumulator operation that never writes `avoid`.
+    fn operation(&mut self, avoid: &str) -> String {
+        let targets: Vec<&str> = REGISTERS.iter().copied().filter(|register| *register != avoid).collect();
+        let dst: &str = self.rng.pick(&targets);
+        let src: &str = self.rng.pick(&REGISTERS);
+        let byte: u32 = self.rng.below(256);
+        return match self.rng.below(10) {
+            0..=5 => match self.rng.pick(&OPERATIONS) {
+                "mov" => format!("mov {}, {}", dst, src),
+                "mvi" => format!("mvi {}, {}", dst, byte),
+                op @ ("inr" | "dcr") => format!("{} {}", op, dst),
+                op => format!("{} {}", op, src),
+            },
+            6..=7 => format!("{} {}", self.rng.pick(&IMMEDIATES), byte),
+            _ => self.rng.pick(&ACCUMULATOR_OPS).to_string(),
+        };
+    }
+
+    fn straight_line(&mut self) {
+        let count: u32 = 1 + self.rng.below(8);
+        for _ in 0..count {
+            let line = self.operation("");
+            self.emit(line);
+        }
+        self.max_instructions += count as u64;
+    }
+
+    /// `mvi c, n` then a body that leaves C alone, counted down to zero.
+    fn counted_loop(&mut self) {
+        let iterations: u32 = 1 + self.rng.below(16);
+        let body: u32 = 1 + self.rng.below(4);
+        let label = self.label();
+        self.emit(format!("mvi c, {}", iterations));
+        self.lines.push(format!("{}:", label));
+        for _ in 0..body {
+            let line = self.operation("c");
+            self.emit(line);
+        }
+        self.emit("dcr c".to_string());
+        self.emit(format!("jnz {}", label));
+        self.max_instructions += 1 + iterations as u64 * (body as u64 + 2);
+    }
+
+    /// Pushes some pairs and pops them back, possibly into other pairs.
+    /// Sometimes leaves one on the stack.
+    fn stack_ops(&mut self) {
+        let pairs: [&str; 3] = ["b", "d", "psw"];
+        let count: u32 = 1 + self.rng.below(4);
+        for _ in 0..count {
+            let pair = self.rng.pick(&pairs);
+            self.emit(format!("push {}", pair));
+        }
+        let leave: u32 = if self.rng.below(8) == 0 { 1 } else { 0 };
+        for _ in leave..count {
+            let pair = self.rng.pick(&pairs);
+            self.emit(format!("pop {}", pair));
+        }
+        self.stack_balanced &= leave == 0;
+        self.max_instructions += (2 * count - leave) as u64;
+    }
+
+    /// Fills or copies a block inside `SCRATCH`.
+    fn block_op(&mut self) {
+        let len: u32 = 1 + self.rng.below(32);
+        let dst: u32 = SCRATCH.start as u32 + self.rng.below(SCRATCH.len() as u32 - len);
+        let label = self.label();
+        self.emit(format!("lxi h, {}", dst));
+        self.emit(format!("mvi c, {}", len));
+        if self.rng.below(2) == 0 {
+            let value: u32 = self.rng.below(256);
+            self.emit(format!("mvi a, {}", value));
+            self.lines.push(format!("{}:", label));
+            self.emit("mov m, a".to_string());
+            self.max_instructions += 3;
+        } else {
+            let src: u32 = SCRATCH.start as u32 + self.rng.below(SCRATCH.len() as u32 - len);
+            self.emit(format!("lxi d, {}", src));
+            self.lines.push(format!("{}:", label));
+            self.emit("ldax d".to_string());
+            self.emit("mov m, a".to_string());
+            self.emit("inx d".to_string());
+            self.max_instructions += 3 + len as u64 * 2;
+        }
+        self.emit("inx h".to_string());
+        self.emit("dcr c".to_string());
+        self.emit(format!("jnz {}", label));
+        self.max_instructions += len as u64 * 4;
+    }
+
+    /// STA or LDA somewhere in `SCRATCH`.
+    fn direct_access(&mut self) {
+        let addr: u32 = SCRATCH.start as u32 + self.rng.below(SCRATCH.len() as u32);
+        let op = if self.rng.below(2) == 0 { "sta" } else { "lda" };
+        self.emit(format!("{} {}", op, addr));
+        self.max_instructions += 1;
+    }
+}
+
+/// Generates the program for `seed`. The same seed always gives the same
+/// program.
+pub fn generate(seed: u64) -> Program {
+    let mut generator = Generator {
+        rng: Rng::new(seed),
+        lines: Vec::new(),
+        labels: 0,
+        stack_balanced: true,
+        max_instructions: 2,
+    };
+    generator.emit(format!("lxi sp, {}", STACK.end));
+    let fragments: u32 = 1 + generator.rng.below(12);
+    for _ in 0..fragments {
+        match generator.rng.below(5) {
+            0 => generator.straight_line(),
+            1 => generator.counted_loop(),
+            2 => generator.stack_ops(),
+            3 => generator.block_op(),
+            _ => generator.direct_access(),
+        }
+    }
+    generator.emit("hlt".to_string());
+
+    let source: String = generator.lines.join("\n");
+    let bytes: Vec<u8> = match asm::assemble(&source) {
+        Ok(assembly) => assembly.bytes,
+        Err(err) => panic!("seed {} generated invalid source: {}\n{}", seed, err, source),
+    };
+    return Program {
+        seed,
+        source,
+        bytes,
+        stack_balanced: generator.stack_balanced,
+        max_instructions: generator.max_instructions,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm;
+    use crate::processor::{make_processor, RunOutcome, MEMORY_SIZE};
+
+    #[test]
+    fn test_generation_is_deterministic() {
+        assert_eq!(generate(42), generate(42));
+        assert_ne!(generate(42).bytes, generate(43).bytes);
+    }
+
+    #[test]
+    fn test_programs_disassemble_without_unknown_opcodes() {
+        for seed in 0..200 {
+            let program = generate(seed);
+            for line in disasm::disassemble(&program.bytes, 0) {
+                assert!(!line.text.starts_with("db "), "seed {}: {}", seed, line);
+            }
+            assert_eq!(program.bytes.last(), Some(&0x76), "seed {} does not end in hlt", seed);
+        }
+    }
+
+    #[test]
+    fn test_corpus_invariants() {
+        let mut unbalanced: usize = 0;
+        for seed in 0..300 {
+            let program = generate(seed);
+            let mut processor = make_processor();
+            processor.load_program(&program.bytes);
+            let before: Vec<u8> = (0..MEMORY_SIZE).map(|addr| processor.peek(addr as u16)).collect();
+
+            let outcome = processor.run_with_limit(program.max_instructions + 1);
+
+            let context = format!("seed {}\n{}", seed, program.source);
+            assert_eq!(outcome, RunOutcome::Halted, "{}", context);
+            assert!(processor.instruction_count() <= program.max_instructions, "{}", context);
+            if program.stack_balanced {
+                assert_eq!(processor.registers().sp, STACK.end, "{}", context);
+            } else {
+                unbalanced += 1;
+            }
+            for addr in (0..MEMORY_SIZE as u16).filter(|addr| !SCRATCH.contains(addr) && !STACK.contains(addr)) {
+                assert_eq!(processor.peek(addr), before[addr as usize], "{} wrote to {:#06x}", context, addr);
+            }
+        }
+        assert!(unbalanced > 0);
+    }
+}
@@ -0,0 +1,129 @@
+//! Just enough of CP/M to run programs that print through the BDOS.
+
+use crate::devices::Console;
+use crate::processor::{Processor, Registers, VectorAction, MEMORY_SIZE};
+
+/// Port a console is attached to alongside the shim.
+pub const CONSOLE_PORT: u8 = 0x01;
+
+/// Programs call here with the function number in C.
+pub const BDOS_ENTRY: u16 = 0x0005;
+
+/// Where CP/M programs are loaded and start.
+pub const TPA_START: u16 = 0x0100;
+
+/// Loaded at 0x0000 so that warm boot (a jump to 0) halts.
+const WARM_BOOT: [u8; 1] = [0x76];
+
+/// CP/M strings end here rather than at a NUL.
+const STRING_END: u8 = b'$';
+
+/// Services a BDOS call: function 2 prints the character in E and function
+/// 9 prints the `$`-terminated string at DE. Every other function returns
+/// without doing anything.
+fn bdos(processor: &mut Processor, console: &Console) -> VectorAction {
+    let registers: Registers = processor.registers();
+    match registers.c {
+        2 => console.print(&[registers.e]),
+        9 => {
+            let mut addr: u16 = registers.de();
+            let mut text: Vec<u8> = Vec::new();
+            while processor.peek(addr) != STRING_END && text.len() < MEMORY_SIZE {
+                text.push(processor.peek(addr));
+                addr = addr.wrapping_add(1);
+            }
+            console.print(&text);
+        },
+        _ => (),
+    }
+    return VectorAction::Ret;
+}
+
+/// Halts on warm boot and services the BDOS entry with a PC hook that
+/// prints to a console, returning a handle to read what the program prints.
+/// The console is also attached to `CONSOLE_PORT` for programs that write
+/// to it directly.
+pub fn install_bdos_shim(processor: &mut Processor) -> Console {
+    processor.load_at(0x0000, &WARM_BOOT);
+    let console = Console::new();
+    processor.attach_device(&[CONSOLE_PORT], Box::new(console.clone()));
+    let output = console.clone();
+    processor.install_pc_hook(BDOS_ENTRY, Box::new(move |processor| bdos(processor, &output)));
+    return console;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{EmuError, ProcessorBuilder, RunOutcome};
+
+    /// Prints "A" through BDOS function 2, then "hi" through function 9,
+    /// then warm boots.
+    const PROGRAM: [u8; 21] = [
+        0x31, 0x00, 0x30, // 0100 lxi sp, 3000h
+        0x0e, 0x02,       // 0103 mvi c, 2
+        0x1e, 0x41,       // 0105 mvi e, 'A'
+        0xcd, 0x05, 0x00, // 0107 call 5
+        0x0e, 0x09,       // 010a mvi c, 9
+        0x11, 0x15, 0x01, // 010c lxi d, msg
+        0xcd, 0x05, 0x00, // 010f call 5
+        0xc3, 0x00, 0x00, // 0112 jmp 0
+    ];
+
+    fn load(processor: &mut Processor) {
+        processor.load_at(TPA_START, &PROGRAM);
+        processor.load_at(0x0115, b"hi$"); // msg
+        processor.set_pc(TPA_START);
+    }
+
+    #[test]
+    fn test_strict_mode_catches_missing_bdos() {
+        let mut processor = ProcessorBuilder::new().strict(true).build();
+        load(&mut processor);
+
+        let outcome = processor.run_with_limit(100);
+
+        assert_eq!(outcome, RunOutcome::Fault(EmuError::UninitializedVector { addr: BDOS_ENTRY, caller: Some(0x0107) }));
+        assert_eq!(processor.pc(), BDOS_ENTRY);
+        assert_eq!(
+            EmuError::UninitializedVector { addr: BDOS_ENTRY, caller: Some(0x0107) }.to_string(),
+            "executed uninitialized vector at 0x0005, called from 0x0107");
+    }
+
+    #[test]
+    fn test_bdos_shim_satisfies_strict_mode() {
+        let mut processor = ProcessorBuilder::new().strict(true).build();
+        let console = install_bdos_shim(&mut processor);
+        load(&mut processor);
+
+        assert_eq!(processor.run_with_limit(1000), RunOutcome::Halted);
+        assert_eq!(console.text(), "Ahi");
+        assert!(processor.call_stack().is_empty());
+    }
+
+    #[test]
+    fn test_strict_mode_reports_rst_caller() {
+        let mut processor = ProcessorBuilder::new().strict(true).build();
+        processor.load_at(TPA_START, &[0x31, 0x00, 0x30, 0xdf]); // lxi sp, 3000h; rst 3
+        processor.set_pc(TPA_START);
+
+        let outcome = processor.run_with_limit(100);
+
+        assert_eq!(outcome, RunOutcome::Fault(EmuError::UninitializedVector { addr: 0x0018, caller: Some(0x0103) }));
+    }
+
+    #[test]
+    fn test_vector_written_by_guest_counts_as_initialized() {
+        let mut processor = ProcessorBuilder::new().strict(true).build();
+        processor.load_at(TPA_START, &[
+            0x3e, 0xc9,       // mvi a, ret
+            0x32, 0x18, 0x00, // sta 0018h
+            0x31, 0x00, 0x30, // lxi sp, 3000h
+            0xdf,             // rst 3
+            0x76,             // hlt
+        ]);
+        processor.set_pc(TPA_START);
+
+        assert_eq!(processor.run_with_limit(100), RunOutcome::Halted);
+    }
+}
@@ -0,0 +1,57 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::processor::{IoContext, IoDevice};
+
+/// Character output: every OUT appends a byte. IN reads 0.
+///
+/// Clones share the same output buffer, so keep one clone to read what the
+/// guest printed after attaching another to the processor.
+#[derive(Debug, Clone, Default)]
+pub struct Console {
+    output: Arc<Mutex<Vec<u8>>>,
+    echo: bool,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        return Console::default();
+    }
+
+    /// Also writes each byte to stdout as it arrives.
+    pub fn echo_to_stdout(mut self) -> Console {
+        self.echo = true;
+        return self;
+    }
+
+    /// Appends `bytes` as if the guest had written them, for host-side
+    /// code printing on the guest's behalf.
+    pub fn print(&self, bytes: &[u8]) {
+        self.output.lock().unwrap().extend_from_slice(bytes);
+        if self.echo {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(bytes);
+            let _ = stdout.flush();
+        }
+    }
+
+    /// Everything written so far.
+    pub fn bytes(&self) -> Vec<u8> {
+        return self.output.lock().unwrap().clone();
+    }
+
+    /// Everything written so far, with invalid UTF-8 replaced.
+    pub fn text(&self) -> String {
+        return String::from_utf8_lossy(&self.output.lock().unwrap()).into_owned();
+    }
+}
+
+impl IoDevice for Console {
+    fn read(&mut self, _port: u16, _ctx: &IoContext) -> u8 {
+        return 0;
+    }
+
+    fn write(&mut self, _port: u16, value: u8, _ctx: &IoContext) {
+        self.print(&[value]);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::processor::{BusRequest, IoContext, IoDevice};
+use crate::word::word;
+
+/// Register offsets from the base port.
+pub const DMA_SOURCE_LO: u8 = 0;
+pub const DMA_SOURCE_HI: u8 = 1;
+pub const DMA_DEST_LO: u8 = 2;
+pub const DMA_DEST_HI: u8 = 3;
+pub const DMA_LEN_LO: u8 = 4;
+pub const DMA_LEN_HI: u8 = 5;
+/// Any OUT starts the copy.
+pub const DMA_TRIGGER: u8 = 6;
+
+/// A block copy engine. The guest writes the source, destination and length
+/// a byte at a time, least significant first, then OUTs to `DMA_TRIGGER`.
+/// The processor copies the block through the memory bus and stalls for
+/// `cycles_per_byte` cycles per byte, then raises the completion interrupt
+/// if one is set. IN reads the registers back; the trigger port reads 0,
+/// as the copy is always finished by the next instruction.
+#[derive(Debug, Default)]
+pub struct DmaDevice {
+    base: u8,
+    cycles_per_byte: u64,
+    /// Interrupt source and RST opcode raised when a copy finishes.
+    interrupt: Option<(u8, u8)>,
+    /// Source, destination and length, in port order.
+    registers: [u8; 6],
+    triggered: bool,
+}
+
+impl DmaDevice {
+    pub fn new(base: u8, cycles_per_byte: u64) -> DmaDevice {
+        return DmaDevice { base, cycles_per_byte, ..Default::default() };
+    }
+
+    /// Raises `rst_opcode` as interrupt `source` after each copy.
+    pub fn completion_interrupt(mut self, source: u8, rst_opcode: u8) -> DmaDevice {
+        self.interrupt = Some((source, rst_opcode));
+        return self;
+    }
+
+    /// The ports this device should be attached to.
+    pub fn ports(&self) -> Vec<u8> {
+        return (0..=DMA_TRIGGER).map(|offset| self.base.wrapping_add(offset)).collect();
+    }
+
+    fn register(&self, offset: u8) -> u16 {
+        return word(self.registers[offset as usize + 1], self.registers[offset as usize]);
+    }
+}
+
+impl IoDevice for DmaDevice {
+    fn read(&mut self, port: u16, _ctx: &IoContext) -> u8 {
+        let offset = (port as u8).wrapping_sub(self.base);
+        return self.registers.get(offset as usize).copied().unwrap_or(0);
+    }
+
+    fn write(&mut self, port: u16, value: u8, _ctx: &IoContext) {
+        let offset = (port as u8).wrapping_sub(self.base);
+        match offset {
+            DMA_TRIGGER => self.triggered = true,
+            DMA_SOURCE_LO..=DMA_LEN_HI => self.registers[offset as usize] = value,
+            _ => {},
+        }
+    }
+
+    fn bus_request(&mut self) -> Option<BusRequest> {
+        if !std::mem::take(&mut self.triggered) {
+            return None;
+        }
+        let len: u16 = self.register(DMA_LEN_LO);
+        return Some(BusRequest {
+            source: self.register(DMA_SOURCE_LO),
+            dest: self.register(DMA_DEST_LO),
+            len,
+            stall_cycles: len as u64 * self.cycles_per_byte,
+            interrupt: self.interrupt,
+        });
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        return self.registers.to_vec();
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        if state.len() == self.registers.len() {
+            self.registers.copy_from_slice(state);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.registers = [0; 6];
+        self.triggered = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{Event, EventLog, ProcessorBuilder, RunOutcome};
+
+    const BASE: u8 = 0x40;
+
+    #[test]
+    fn test_guest_programmed_copy() {
+        let dma = DmaDevice::new(BASE, 4).completion_interrupt(2, 0xef); // RST 5
+        let mut processor = ProcessorBuilder::new().device(&dma.ports(), Box::new(dma)).build();
+        let log = EventLog::new(64);
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        processor.load_at(0x0000, &[
+            0x31, 0x00, 0x20, // 0000 LXI SP,2000h
+            0x3e, 0x00,       // 0003 MVI A,00h
+            0xd3, 0x40,       // 0005 OUT 40h    source 1000h
+            0x3e, 0x10,       // 0007 MVI A,10h
+            0xd3, 0x41,       // 0009 OUT 41h
+            0x3e, 0x00,       // 000b MVI A,00h
+            0xd3, 0x42,       // 000d OUT 42h    dest 1800h
+            0x3e, 0x18,       // 000f MVI A,18h
+            0xd3, 0x43,       // 0011 OUT 43h
+            0x3e, 0x00,       // 0013 MVI A,00h
+            0xd3, 0x44,       // 0015 OUT 44h    length 100h
+            0x3e, 0x01,       // 0017 MVI A,01h
+            0xd3, 0x45,       // 0019 OUT 45h
+            0xfb,             // 001b EI
+            0xd3, 0x46,       // 001c OUT 46h    go
+            0x76,             // 001e HLT
+        ]);
+        processor.load_at(0x0028, &[
+            0x3a, 0x00, 0x1f, // 0028 LDA 1f00h
+            0x3c,             // 002b INR A
+            0x32, 0x00, 0x1f, // 002c STA 1f00h
+            0xc9,             // 002f RET
+        ]);
+        let block: Vec<u8> = (0..=255u8).map(|i| i.wrapping_mul(7)).collect();
+        processor.load_at(0x1000, &block);
+
+        assert_eq!(processor.run_to(0x001c), RunOutcome::Stepped { pc: 0x001c });
+        let before: u64 = processor.cycle_count();
+        processor.step();
+        assert_eq!(processor.cycle_count() - before, 10 + 256 * 4);
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        assert_eq!((0x1800..0x1900).map(|addr| processor.peek(addr)).collect::<Vec<u8>>(), block);
+        assert_eq!(processor.peek(0x1f00), 1);
+        let delivered: Vec<Event> = log.events().into_iter().map(|event| event.event)
+            .filter(|event| matches!(event, Event::InterruptDelivered { .. })).collect();
+        assert_eq!(delivered, vec![Event::InterruptDelivered { source: 2, vector: 5 }]);
+    }
+
+    #[test]
+    fn test_registers_read_back() {
+        let mut dma = DmaDevice::new(BASE, 1);
+        let ctx = IoContext::default();
+        dma.write(BASE as u16 + DMA_DEST_HI as u16, 0x18, &ctx);
+
+        assert_eq!(dma.read(BASE as u16 + DMA_DEST_HI as u16, &ctx), 0x18);
+        assert_eq!(dma.read(BASE as u16 + DMA_TRIGGER as u16, &ctx), 0);
+        assert_eq!(dma.bus_request(), None);
+        dma.write(BASE as u16 + DMA_TRIGGER as u16, 0, &ctx);
+        assert_eq!(dma.bus_request().map(|request| (request.dest, request.len)), Some((0x1800, 0)));
+        assert_eq!(dma.bus_request(), None);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+
+use crate::processor::{Event, IoContext, IoDevice, TimedEvent};
+
+/// Where `--seed` attaches the entropy device.
+pub const ENTROPY_PORT: u8 = 0x50;
+
+/// Random bytes for the guest: each IN returns the next byte of a seeded
+/// xorshift64* stream, so a seed always gives the same run. Every draw is
+/// an IN, so the event log records it as `Event::PortRead`, and
+/// `replaying` feeds a recorded run's draws back instead of drawing again.
+/// OUT is ignored.
+#[derive(Debug, Clone)]
+pub struct Entropy {
+    /// The state the seed gave, which a reset goes back to.
+    seeded: u64,
+    state: u64,
+    /// Recorded draws still to be returned, ahead of the generator.
+    replay: VecDeque<u8>,
+}
+
+impl Entropy {
+    /// A stream seeded with `seed`. Seed 0 is as good as any other.
+    pub fn new(seed: u64) -> Entropy {
+        // xorshift never leaves 0, so mix the seed into a nonzero state.
+        let state: u64 = (seed ^ 0x9e37_79b9_7f4a_7c15).max(1);
+        return Entropy { seeded: state, state, replay: VecDeque::new() };
+    }
+
+    /// Returns the bytes `events` show were read from `port`, in order,
+    /// before drawing from `seed`'s stream. The recording needs every
+    /// draw: an `EventLog` that dropped events replays the wrong bytes.
+    pub fn replaying(seed: u64, port: u8, events: &[TimedEvent]) -> Entropy {
+        let mut entropy = Entropy::new(seed);
+        entropy.replay = events.iter().filter_map(|timed| match timed.event {
+            Event::PortRead { port: read, value } if read == port => Some(value),
+            _ => None,
+        }).collect();
+        return entropy;
+    }
+
+    /// The next byte of the stream.
+    fn draw(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8;
+    }
+}
+
+impl IoDevice for Entropy {
+    fn read(&mut self, _port: u16, _ctx: &IoContext) -> u8 {
+        return match self.replay.pop_front() {
+            Some(value) => value,
+            None => self.draw(),
+        };
+    }
+
+    fn write(&mut self, _port: u16, _value: u8, _ctx: &IoContext) {}
+
+    /// The generator state, so a checkpoint resumes the same stream.
+    /// Draws still to be replayed are not kept.
+    fn save_state(&self) -> Vec<u8> {
+        return self.state.to_le_bytes().to_vec();
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        if let Ok(bytes) = <[u8; 8]>::try_from(state) {
+            self.state = u64::from_le_bytes(bytes);
+        }
+    }
+
+    /// Starts the stream again from the seed.
+    fn reset(&mut self) {
+        self.state = self.seeded;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{EventLog, Processor, ProcessorBuilder, RunOutcome};
+
+    /// Stores 16 bytes from the entropy port at 2000h, then halts.
+    const DRAW_16: [u8; 13] = [
+        0x21, 0x00, 0x20,   // 0000 LXI H,2000h
+        0x06, 0x10,         // 0003 MVI B,16
+        0xdb, ENTROPY_PORT, // 0005 IN 50h
+        0x77,               // 0007 MOV M,A
+        0x23,               // 0008 INX H
+        0x05,               // 0009 DCR B
+        0xc2, 0x05, 0x00,   // 000a JNZ 0005h
+    ];
+
+    /// Runs `DRAW_16` on a fresh machine with `entropy`, returning the
+    /// bytes drawn and the events.
+    fn draw(entropy: Entropy) -> (Vec<u8>, EventLog) {
+        let log = EventLog::default();
+        let mut processor: Processor = ProcessorBuilder::new().device(&[ENTROPY_PORT], Box::new(entropy)).build();
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        processor.load_program(&DRAW_16);
+        processor.load_at(DRAW_16.len() as u16, &[0x76]); // HLT
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        return ((0x2000..0x2010).map(|addr| processor.peek(addr)).collect(), log);
+    }
+
+    #[test]
+    fn test_same_seed_same_draws() {
+        let (first, _) = draw(Entropy::new(494));
+        let (second, _) = draw(Entropy::new(494));
+        let (other, _) = draw(Entropy::new(495));
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        let mut distinct: Vec<u8> = first.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert!(distinct.len() > 12, "{:?}", first);
+    }
+
+    #[test]
+    fn test_replay_feeds_recorded_draws() {
+        let (recorded, log) = draw(Entropy::new(494));
+
+        let (replayed, _) = draw(Entropy::replaying(7, ENTROPY_PORT, &log.events()));
+
+        assert_eq!(replayed, recorded);
+        assert_ne!(draw(Entropy::new(7)).0, recorded);
+    }
+
+    #[test]
+    fn test_state_round_trips() {
+        let mut entropy = Entropy::new(1);
+        let ctx = IoContext::default();
+        entropy.read(0, &ctx);
+        let saved: Vec<u8> = entropy.save_state();
+        let next: Vec<u8> = (0..4).map(|_| entropy.read(0, &ctx)).collect();
+
+        let mut resumed = Entropy::new(2);
+        resumed.restore_state(&saved);
+
+        assert_eq!((0..4).map(|_| resumed.read(0, &ctx)).collect::<Vec<u8>>(), next);
+        entropy.reset();
+        assert_eq!(entropy.save_state(), Entropy::new(1).save_state());
+    }
+}
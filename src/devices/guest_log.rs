@@ -0,0 +1,134 @@
+use crate::processor::{IoContext, IoDevice, LogRequest};
+
+/// The first of the guest log device's two ports unless another is given.
+pub const GUEST_LOG_BASE_PORT: u8 = 0xe0;
+
+pub const LOG_DEBUG: u8 = 0;
+pub const LOG_INFO: u8 = 1;
+pub const LOG_WARN: u8 = 2;
+pub const LOG_ERROR: u8 = 3;
+
+/// Structured logging from the guest: each message becomes an
+/// `Event::GuestLog` with its severity, the PC of the OUT that sent it and
+/// the instruction count.
+///
+/// The calling convention uses two ports starting at `base`:
+///
+/// * OUT `base` sets the severity of the next message: `LOG_DEBUG` to
+///   `LOG_ERROR`, though any byte is passed on.
+/// * OUT `base + 1` twice gives the address of a NUL-terminated string, low
+///   byte first. The high byte sends the message.
+///
+/// The processor reads the string from guest memory once the second OUT
+/// has run, so the guest may reuse the buffer straight after.
+/// `tests/routines/guest_log.asm` is a helper taking the severity in A and
+/// the string in HL. IN from either port reads 0.
+#[derive(Debug, Default)]
+pub struct GuestLogDevice {
+    base: u8,
+    severity: u8,
+    /// The low byte of the address, once it has been written.
+    addr_lo: Option<u8>,
+    pending: Option<LogRequest>,
+}
+
+impl GuestLogDevice {
+    pub fn new(base: u8) -> GuestLogDevice {
+        return GuestLogDevice { base, ..Default::default() };
+    }
+
+    /// The ports this device should be attached to.
+    pub fn ports(&self) -> Vec<u8> {
+        return vec![self.base, self.base.wrapping_add(1)];
+    }
+}
+
+impl IoDevice for GuestLogDevice {
+    fn read(&mut self, _port: u16, _ctx: &IoContext) -> u8 {
+        return 0;
+    }
+
+    fn write(&mut self, port: u16, value: u8, _ctx: &IoContext) {
+        match (port as u8).wrapping_sub(self.base) {
+            0 => self.severity = value,
+            1 => match self.addr_lo.take() {
+                None => self.addr_lo = Some(value),
+                Some(lo) => self.pending = Some(LogRequest { severity: self.severity, addr: u16::from_le_bytes([lo, value]) }),
+            },
+            _ => {},
+        }
+    }
+
+    fn log_request(&mut self) -> Option<LogRequest> {
+        return self.pending.take();
+    }
+
+    fn reset(&mut self) {
+        *self = GuestLogDevice::new(self.base);
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        return match self.addr_lo {
+            Some(lo) => vec![self.severity, 1, lo],
+            None => vec![self.severity, 0, 0],
+        };
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        if let [severity, has_lo, lo] = *state {
+            self.severity = severity;
+            self.addr_lo = (has_lo != 0).then_some(lo);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "asm"))]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::asm;
+    use crate::processor::{make_processor, Event, EventLog, RunOutcome};
+
+    #[test]
+    fn test_messages_at_three_severities() {
+        let helper: String = fs::read_to_string("tests/routines/guest_log.asm").unwrap();
+        let source: String = format!("
+                lxi sp, 0f000h
+                mvi a, {}
+                lxi h, 20fch
+                call guest_log
+                mvi a, {}
+                lxi h, 2100h
+                call guest_log
+                mvi a, {}
+                lxi h, 2200h
+                call guest_log
+                hlt
+            {}", LOG_INFO, LOG_WARN, LOG_ERROR, helper);
+        let mut processor = make_processor();
+        processor.load_program(&asm::assemble(&source).unwrap().bytes);
+        // The first message crosses from page 20h into page 21h, where the
+        // second starts partway through it.
+        processor.load_at(0x20fc, b"boot ok\0");
+        processor.load_at(0x2200, b"\0");
+        let device = GuestLogDevice::new(GUEST_LOG_BASE_PORT);
+        processor.attach_device(&device.ports(), Box::new(device));
+        let log = EventLog::default();
+        processor.set_event_sink(Some(Box::new(log.clone())));
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        let messages: Vec<(u64, u16, u8, String)> = log.events().into_iter().filter_map(|event| match event.event {
+            Event::GuestLog { severity, message } => Some((event.instructions, event.pc, severity, message)),
+            _ => None,
+        }).collect();
+        assert_eq!(messages, vec![
+            (8, 0x0022, LOG_INFO, "boot ok".to_string()),
+            (17, 0x0022, LOG_WARN, " ok".to_string()),
+            (26, 0x0022, LOG_ERROR, String::new()),
+        ]);
+        assert_eq!(log.events().iter().find(|event| matches!(event.event, Event::GuestLog { .. })).unwrap().event.to_string(),
+                   "guest info: boot ok");
+    }
+}
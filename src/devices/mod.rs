@@ -0,0 +1,23 @@
+//! I/O devices that can be attached to a `Processor`.
+
+mod console;
+mod dma;
+mod entropy;
+mod guest_log;
+mod perf_counters;
+mod registry;
+mod rtc;
+mod timer;
+
+pub use console::Console;
+pub use dma::{DmaDevice, DMA_DEST_HI, DMA_DEST_LO, DMA_LEN_HI, DMA_LEN_LO, DMA_SOURCE_HI, DMA_SOURCE_LO, DMA_TRIGGER};
+pub use entropy::{Entropy, ENTROPY_PORT};
+pub use guest_log::{GuestLogDevice, GUEST_LOG_BASE_PORT, LOG_DEBUG, LOG_ERROR, LOG_INFO, LOG_WARN};
+pub use perf_counters::PerfCounters;
+pub use registry::{BuiltDevice, DeviceConfig, DevicePlugin, DeviceRegistry};
+pub use rtc::{to_bcd, DateTime, Rtc, RTC_BASE_PORT, RTC_CENTURY, RTC_DAY, RTC_HOURS, RTC_MINUTES, RTC_MONTH,
+              RTC_SECONDS, RTC_YEAR};
+pub use timer::Timer;
+
+/// What a plugin implements, so out-of-tree devices need only this module.
+pub use crate::processor::{IoContext, IoDevice};
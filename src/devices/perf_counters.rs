@@ -0,0 +1,156 @@
+use crate::processor::{IoContext, IoDevice};
+
+/// Read-only counters that let guest code time itself.
+///
+/// The device occupies five ports starting at `base`:
+///
+/// * `base` .. `base + 3` return the low 32 bits of the cycle counter, least
+///   significant byte first. Reading `base` latches the counter; the other
+///   three ports return bytes of that latched value, so reading the four
+///   ports in order gives one coherent snapshot even though cycles pass
+///   between the reads.
+/// * `base + 4`: any OUT latches the low 32 bits of the instruction count.
+///   Each following IN returns the next byte of the latch, least significant
+///   first, wrapping after the fourth.
+#[derive(Debug, Default)]
+pub struct PerfCounters {
+    base: u8,
+    cycle_latch: u32,
+    instruction_latch: u32,
+    instruction_byte: u8,
+}
+
+impl PerfCounters {
+    pub fn new(base: u8) -> PerfCounters {
+        return PerfCounters { base, ..Default::default() };
+    }
+
+    /// The ports this device should be attached to.
+    pub fn ports(&self) -> Vec<u8> {
+        return (0..5).map(|offset| self.base.wrapping_add(offset)).collect();
+    }
+}
+
+fn byte_of(value: u32, index: u8) -> u8 {
+    return (value >> (8 * index as u32)) as u8;
+}
+
+impl IoDevice for PerfCounters {
+    fn read(&mut self, port: u16, ctx: &IoContext) -> u8 {
+        let offset = (port as u8).wrapping_sub(self.base);
+        return match offset {
+            0 => {
+                self.cycle_latch = ctx.cycles as u32;
+                byte_of(self.cycle_latch, 0)
+            },
+            1..=3 => byte_of(self.cycle_latch, offset),
+            4 => {
+                let byte = byte_of(self.instruction_latch, self.instruction_byte);
+                self.instruction_byte = (self.instruction_byte + 1) % 4;
+                byte
+            },
+            _ => 0xff,
+        };
+    }
+
+    fn write(&mut self, port: u16, _value: u8, ctx: &IoContext) {
+        if (port as u8).wrapping_sub(self.base) == 4 {
+            self.instruction_latch = ctx.instructions as u32;
+            self.instruction_byte = 0;
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = PerfCounters::new(self.base);
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state: Vec<u8> = Vec::new();
+        state.extend_from_slice(&self.cycle_latch.to_le_bytes());
+        state.extend_from_slice(&self.instruction_latch.to_le_bytes());
+        state.push(self.instruction_byte);
+        return state;
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        if let [c0, c1, c2, c3, i0, i1, i2, i3, byte] = *state {
+            self.cycle_latch = u32::from_le_bytes([c0, c1, c2, c3]);
+            self.instruction_latch = u32::from_le_bytes([i0, i1, i2, i3]);
+            self.instruction_byte = byte;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::make_processor;
+
+    fn at(cycles: u64, instructions: u64) -> IoContext {
+        return IoContext { cycles, instructions, pc: 0 };
+    }
+
+    #[test]
+    fn test_cycle_latch_is_coherent_across_byte_rollover() {
+        let mut counters = PerfCounters::new(0x10);
+
+        let bytes = [
+            counters.read(0x10, &at(0x0001_23ff, 0)),
+            counters.read(0x11, &at(0x0001_2405, 0)),
+            counters.read(0x12, &at(0x0001_240f, 0)),
+            counters.read(0x13, &at(0x0001_2419, 0)),
+        ];
+
+        assert_eq!(u32::from_le_bytes(bytes), 0x0001_23ff);
+    }
+
+    #[test]
+    fn test_cycle_latch_truncates_and_survives_32_bit_rollover() {
+        let mut counters = PerfCounters::new(0x10);
+
+        assert_eq!(counters.read(0x10, &at(0x1_ffff_fffe, 0)), 0xfe);
+        assert_eq!(counters.read(0x13, &at(0x2_0000_0004, 0)), 0xff);
+        assert_eq!(counters.read(0x10, &at(0x2_0000_0004, 0)), 0x04);
+        assert_eq!(counters.read(0x13, &at(0x2_0000_0004, 0)), 0x00);
+    }
+
+    #[test]
+    fn test_instruction_latch_reads_bytes_in_order() {
+        let mut counters = PerfCounters::new(0x10);
+        counters.write(0x14, 0, &at(0, 0x0102_0304));
+
+        let bytes: Vec<u8> = (0..5).map(|_| counters.read(0x14, &at(0, 0x0999_9999))).collect();
+
+        assert_eq!(bytes, vec![0x04, 0x03, 0x02, 0x01, 0x04]);
+    }
+
+    #[test]
+    fn test_guest_measures_delay_loop() {
+        let mut processor = make_processor();
+        let counters = PerfCounters::new(0x10);
+        processor.attach_device(&counters.ports(), Box::new(counters));
+        processor.load_program(&[
+            0xdb, 0x10,       // IN 10h        ; latch start cycles
+            0x6f,             // MOV L,A
+            0xdb, 0x11,       // IN 11h
+            0x67,             // MOV H,A
+            0x22, 0x00, 0x20, // SHLD 2000h
+            0x06, 0x0a,       // MVI B,10
+            0x05,             // loop: DCR B
+            0xc2, 0x0b, 0x00, // JNZ loop
+            0xdb, 0x10,       // IN 10h        ; latch end cycles
+            0x6f,             // MOV L,A
+            0xdb, 0x11,       // IN 11h
+            0x67,             // MOV H,A
+            0x22, 0x02, 0x20, // SHLD 2002h
+            0x76,             // HLT
+        ]);
+
+        processor.run();
+
+        let start = u16::from_le_bytes([processor.peek(0x2000), processor.peek(0x2001)]);
+        let end = u16::from_le_bytes([processor.peek(0x2002), processor.peek(0x2003)]);
+        // MOV + IN + MOV + SHLD + MVI, ten DCR/JNZ passes, then the latching IN.
+        assert_eq!(end - start, 5 + 10 + 5 + 16 + 7 + 10 * (5 + 10) + 10);
+    }
+}
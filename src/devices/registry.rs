@@ -0,0 +1,295 @@
+//! Devices by name, so they can be attached from a string such as
+//! `timer:port=0x20,tick_ms=10`. The in-tree devices are registered in
+//! `DeviceRegistry::builtin`; a crate with devices of its own registers a
+//! `DevicePlugin` for each on the registry it hands to the emulator.
+//!
+//! `DevicePlugin`, `DeviceConfig` and the `IoDevice` trait they build are
+//! the plugin interface, and change only with a new major version.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::clock::SystemClock;
+use crate::devices::{Console, DateTime, DmaDevice, Entropy, GuestLogDevice, PerfCounters, Rtc, Timer, ENTROPY_PORT,
+                     GUEST_LOG_BASE_PORT, RTC_BASE_PORT};
+#[cfg(feature = "machines")]
+use crate::invaders::Cabinet;
+use crate::processor::{IoDevice, Processor};
+use crate::word::parse_number;
+
+/// A device and the ports to attach it to.
+pub type BuiltDevice = (Vec<u8>, Box<dyn IoDevice>);
+
+/// `name:key=value,key=value`, parsed. Values are kept as written for the
+/// device to interpret.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceConfig {
+    pub name: String,
+    pub settings: BTreeMap<String, String>,
+}
+
+impl DeviceConfig {
+    pub fn parse(text: &str) -> Result<DeviceConfig, String> {
+        let (name, rest) = text.split_once(':').unwrap_or((text, ""));
+        if name.is_empty() {
+            return Err(format!("'{}' does not start with a device name", text));
+        }
+        let mut settings: BTreeMap<String, String> = BTreeMap::new();
+        for pair in rest.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or(format!("expected key=value, got '{}'", pair))?;
+            if settings.insert(key.to_string(), value.to_string()).is_some() {
+                return Err(format!("{} is given twice", key));
+            }
+        }
+        return Ok(DeviceConfig { name: name.to_string(), settings });
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        return self.settings.get(key).map(String::as_str);
+    }
+
+    /// The number `key` is set to, e.g. `32`, `0x20` or `20h`, or `default`.
+    pub fn number(&self, key: &str, default: u16) -> Result<u16, String> {
+        return self.get(key).map_or(Ok(default), |value| parse_number(value).ok_or(format!("{}: '{}' is not a number", key, value)));
+    }
+
+    /// The `port` setting, or `default`.
+    pub fn port(&self, default: u8) -> Result<u8, String> {
+        let port: u16 = self.number("port", default as u16)?;
+        return u8::try_from(port).map_err(|_| format!("port: {:#x} is not an 8-bit port", port));
+    }
+}
+
+/// How to make one kind of device from a `DeviceConfig`.
+#[derive(Debug, Clone, Copy)]
+pub struct DevicePlugin {
+    pub name: &'static str,
+    /// The ports the device takes when `port` is not given.
+    pub default_ports: &'static [u8],
+    /// The settings the device reads; any other is an error.
+    pub keys: &'static [&'static str],
+    pub build: fn(&DeviceConfig) -> Result<BuiltDevice, String>,
+}
+
+/// The devices that can be attached by name.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceRegistry {
+    plugins: Vec<DevicePlugin>,
+}
+
+impl DeviceRegistry {
+    /// A registry with nothing in it.
+    pub fn new() -> DeviceRegistry {
+        return DeviceRegistry::default();
+    }
+
+    /// A registry with every in-tree device.
+    pub fn builtin() -> DeviceRegistry {
+        let mut registry = DeviceRegistry::new();
+        for plugin in BUILTIN {
+            registry.register(*plugin).expect("built-in device names are distinct");
+        }
+        return registry;
+    }
+
+    /// Adds `plugin`, unless a device of that name is already registered.
+    pub fn register(&mut self, plugin: DevicePlugin) -> Result<(), String> {
+        if self.find(plugin.name).is_some() {
+            return Err(format!("a device called '{}' is already registered", plugin.name));
+        }
+        self.plugins.push(plugin);
+        return Ok(());
+    }
+
+    pub fn find(&self, name: &str) -> Option<&DevicePlugin> {
+        return self.plugins.iter().find(|plugin| plugin.name == name);
+    }
+
+    /// Registered names, in registration order.
+    pub fn names(&self) -> Vec<&'static str> {
+        return self.plugins.iter().map(|plugin| plugin.name).collect();
+    }
+
+    /// The plugin for `config`, if it names one and gives only settings
+    /// the plugin reads.
+    fn plugin_for(&self, config: &DeviceConfig) -> Result<&DevicePlugin, String> {
+        let plugin = self.find(&config.name)
+            .ok_or(format!("unknown device '{}'; expected one of {}", config.name, self.names().join(", ")))?;
+        if let Some(key) = config.settings.keys().find(|key| !plugin.keys.contains(&key.as_str())) {
+            let known: String = if plugin.keys.is_empty() { "none".to_string() } else { plugin.keys.join(", ") };
+            return Err(format!("{}: unknown setting '{}'; it takes {}", plugin.name, key, known));
+        }
+        return Ok(plugin);
+    }
+
+    /// Checks that `spec` names a registered device and only settings it
+    /// reads, without making it. Bad values are only found by `build`.
+    pub fn check(&self, spec: &str) -> Result<(), String> {
+        return self.plugin_for(&DeviceConfig::parse(spec)?).map(|_| ());
+    }
+
+    /// Makes the device `spec` describes.
+    pub fn build(&self, spec: &str) -> Result<BuiltDevice, String> {
+        let config = DeviceConfig::parse(spec)?;
+        let plugin = self.plugin_for(&config)?;
+        return (plugin.build)(&config).map_err(|err| format!("{}: {}", plugin.name, err));
+    }
+
+    /// Makes the device `spec` describes and attaches it to `processor`.
+    pub fn attach(&self, processor: &mut Processor, spec: &str) -> Result<(), String> {
+        let (ports, device) = self.build(spec)?;
+        processor.attach_device(&ports, device);
+        return Ok(());
+    }
+}
+
+const BUILTIN: &[DevicePlugin] = &[
+    DevicePlugin { name: "console", default_ports: &[0x01], keys: &["port", "echo"], build: build_console },
+    DevicePlugin { name: "timer", default_ports: &[0x20], keys: &["port", "tick_ms"], build: build_timer },
+    DevicePlugin { name: "rtc", default_ports: &[RTC_BASE_PORT, RTC_BASE_PORT + 1], keys: &["port", "epoch"], build: build_rtc },
+    DevicePlugin { name: "guest_log", default_ports: &[GUEST_LOG_BASE_PORT, GUEST_LOG_BASE_PORT + 1], keys: &["port"],
+                   build: build_guest_log },
+    DevicePlugin { name: "perf_counters", default_ports: &[0xf0, 0xf1, 0xf2, 0xf3, 0xf4], keys: &["port"],
+                   build: build_perf_counters },
+    DevicePlugin { name: "dma", default_ports: &[0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36], keys: &["port", "cycles_per_byte"],
+                   build: build_dma },
+    DevicePlugin { name: "entropy", default_ports: &[ENTROPY_PORT], keys: &["port", "seed"], build: build_entropy },
+    #[cfg(feature = "machines")]
+    DevicePlugin { name: "invaders", default_ports: &[1, 2, 3, 4, 5, 6], keys: &[], build: build_invaders },
+];
+
+fn build_console(config: &DeviceConfig) -> Result<BuiltDevice, String> {
+    let console: Console = match config.get("echo").unwrap_or("on") {
+        "on" => Console::new().echo_to_stdout(),
+        "off" => Console::new(),
+        other => return Err(format!("echo: expected on or off, got '{}'", other)),
+    };
+    return Ok((vec![config.port(0x01)?], Box::new(console)));
+}
+
+fn build_timer(config: &DeviceConfig) -> Result<BuiltDevice, String> {
+    let tick = Duration::from_millis(config.number("tick_ms", 1)? as u64);
+    return Ok((vec![config.port(0x20)?], Box::new(Timer::new(Box::new(SystemClock::new()), tick))));
+}
+
+fn build_rtc(config: &DeviceConfig) -> Result<BuiltDevice, String> {
+    let epoch: DateTime = match config.get("epoch") {
+        Some(text) => DateTime::parse_iso8601(text).ok_or(format!("epoch: expected YYYY-MM-DDTHH:MM:SS, got '{}'", text))?,
+        None => DateTime::from_unix(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())),
+    };
+    let rtc = Rtc::new(config.port(RTC_BASE_PORT)?, Box::new(SystemClock::new()), epoch);
+    return Ok((rtc.ports(), Box::new(rtc)));
+}
+
+fn build_guest_log(config: &DeviceConfig) -> Result<BuiltDevice, String> {
+    let device = GuestLogDevice::new(config.port(GUEST_LOG_BASE_PORT)?);
+    return Ok((device.ports(), Box::new(device)));
+}
+
+fn build_perf_counters(config: &DeviceConfig) -> Result<BuiltDevice, String> {
+    let counters = PerfCounters::new(config.port(0xf0)?);
+    return Ok((counters.ports(), Box::new(counters)));
+}
+
+fn build_dma(config: &DeviceConfig) -> Result<BuiltDevice, String> {
+    let dma = DmaDevice::new(config.port(0x30)?, config.number("cycles_per_byte", 8)? as u64);
+    return Ok((dma.ports(), Box::new(dma)));
+}
+
+fn build_entropy(config: &DeviceConfig) -> Result<BuiltDevice, String> {
+    let seed: u64 = match config.get("seed") {
+        Some(text) => text.parse::<u64>().map_err(|_| format!("seed: '{}' is not a number", text))?,
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64),
+    };
+    return Ok((vec![config.port(ENTROPY_PORT)?], Box::new(Entropy::new(seed))));
+}
+
+#[cfg(feature = "machines")]
+fn build_invaders(_config: &DeviceConfig) -> Result<BuiltDevice, String> {
+    let cabinet = Cabinet::new();
+    return Ok((cabinet.ports(), Box::new(cabinet)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use crate::processor::{make_processor, IoContext};
+
+    /// Writes every `Probe` has seen, as (port, value, greeting). Plugins
+    /// are built from a plain function, so the test can only see them
+    /// through something global.
+    static TRAFFIC: Mutex<Vec<(u16, u8, String)>> = Mutex::new(Vec::new());
+
+    /// An out-of-tree device: it reads back its port and remembers writes.
+    #[derive(Debug)]
+    struct Probe {
+        greeting: String,
+    }
+
+    impl IoDevice for Probe {
+        fn read(&mut self, port: u16, _ctx: &IoContext) -> u8 {
+            return port as u8;
+        }
+
+        fn write(&mut self, port: u16, value: u8, _ctx: &IoContext) {
+            TRAFFIC.lock().unwrap().push((port, value, self.greeting.clone()));
+        }
+    }
+
+    fn build_probe(config: &DeviceConfig) -> Result<BuiltDevice, String> {
+        let probe = Probe { greeting: config.get("greeting").unwrap_or("hi").to_string() };
+        return Ok((vec![config.port(0x40)?], Box::new(probe)));
+    }
+
+    const PROBE: DevicePlugin = DevicePlugin { name: "probe", default_ports: &[0x40], keys: &["port", "greeting"], build: build_probe };
+
+    #[test]
+    fn test_plugin_attached_from_spec() {
+        let mut registry = DeviceRegistry::builtin();
+        registry.register(PROBE).unwrap();
+        let mut processor = make_processor();
+        registry.attach(&mut processor, "probe:port=0x20,greeting=hello").unwrap();
+        processor.load_program(&[
+            0xdb, 0x20, // IN 20h
+            0xd3, 0x20, // OUT 20h
+            0xd3, 0x40, // OUT 40h, nothing there
+            0x76,       // HLT
+        ]);
+
+        processor.run();
+
+        assert_eq!(processor.registers().a, 0x20);
+        assert_eq!(*TRAFFIC.lock().unwrap(), [(0x20, 0x20, "hello".to_string())]);
+        assert!(registry.register(PROBE).is_err());
+        assert_eq!(registry.names().last(), Some(&"probe"));
+    }
+
+    #[test]
+    fn test_bad_specs() {
+        let registry = DeviceRegistry::builtin();
+
+        assert!(registry.build("probe").unwrap_err().starts_with("unknown device 'probe'; expected one of console, timer, rtc"));
+        assert_eq!(registry.build("timer:tick=5").unwrap_err(), "timer: unknown setting 'tick'; it takes port, tick_ms");
+        assert_eq!(registry.build("timer:port=0x120").unwrap_err(), "timer: port: 0x120 is not an 8-bit port");
+        assert_eq!(registry.build("rtc:port").unwrap_err(), "expected key=value, got 'port'");
+        #[cfg(feature = "machines")]
+        assert_eq!(registry.build("invaders:port=8").unwrap_err(), "invaders: unknown setting 'port'; it takes none");
+        assert_eq!(registry.build("dma:port=0x80").unwrap().0, [0x80, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86]);
+        assert_eq!(registry.build("rtc:epoch=1983-06-01T09:00:00Z").unwrap().0, [RTC_BASE_PORT, RTC_BASE_PORT + 1]);
+        for plugin in BUILTIN {
+            assert_eq!(registry.build(plugin.name).unwrap().0, plugin.default_ports, "{}", plugin.name);
+        }
+    }
+
+    #[test]
+    fn test_config_parse() {
+        let config = DeviceConfig::parse("probe:port=20h,greeting=hi there").unwrap();
+
+        assert_eq!((config.name.as_str(), config.port(0), config.get("greeting")), ("probe", Ok(0x20), Some("hi there")));
+        assert_eq!(DeviceConfig::parse("console").unwrap().settings.len(), 0);
+        assert!(DeviceConfig::parse(":port=1").is_err());
+        assert!(DeviceConfig::parse("probe:port=1,port=2").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::processor::{IoContext, IoDevice};
+
+/// Where `--rtc-epoch` attaches the clock: the register select port, with
+/// the data port after it.
+pub const RTC_BASE_PORT: u8 = 0x70;
+
+pub const RTC_SECONDS: u8 = 0;
+pub const RTC_MINUTES: u8 = 1;
+pub const RTC_HOURS: u8 = 2;
+pub const RTC_DAY: u8 = 3;
+pub const RTC_MONTH: u8 = 4;
+/// The year within the century.
+pub const RTC_YEAR: u8 = 5;
+pub const RTC_CENTURY: u8 = 6;
+
+/// A calendar date and time of day, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+fn is_leap_year(year: u32) -> bool {
+    return year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    return match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+}
+
+impl DateTime {
+    /// The time `seconds` after 1970-01-01T00:00:00.
+    pub fn from_unix(seconds: u64) -> DateTime {
+        // Days to a civil date, counting from 0000-03-01 so that the leap
+        // day falls at the end of each year.
+        let days: u64 = seconds / 86_400 + 719_468;
+        let era: u64 = days / 146_097;
+        let day_of_era: u64 = days % 146_097;
+        let year_of_era: u64 = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year: u64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month: u64 = (5 * day_of_year + 2) / 153;
+        let day: u64 = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month: u64 = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year: u64 = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+        let time: u64 = seconds % 86_400;
+        return DateTime {
+            year: year as u32,
+            month: month as u32,
+            day: day as u32,
+            hour: (time / 3600) as u32,
+            minute: (time / 60 % 60) as u32,
+            second: (time % 60) as u32,
+        };
+    }
+
+    /// Seconds since 1970-01-01T00:00:00.
+    pub fn to_unix(&self) -> u64 {
+        let year: u64 = self.year as u64 - if self.month <= 2 { 1 } else { 0 };
+        let era: u64 = year / 400;
+        let year_of_era: u64 = year % 400;
+        let shifted_month: u64 = (self.month as u64 + 9) % 12;
+        let day_of_year: u64 = (153 * shifted_month + 2) / 5 + self.day as u64 - 1;
+        let day_of_era: u64 = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days: u64 = era * 146_097 + day_of_era - 719_468;
+        return days * 86_400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+    }
+
+    /// Parses `YYYY-MM-DDTHH:MM:SS`, optionally ending in `Z`, from 1970 on.
+    pub fn parse_iso8601(text: &str) -> Option<DateTime> {
+        let text = text.strip_suffix('Z').unwrap_or(text);
+        let (date, time) = text.split_once('T')?;
+        let date: Vec<&str> = date.split('-').collect();
+        let time: Vec<&str> = time.split(':').collect();
+        let field = |part: &str, digits: usize| {
+            let valid: bool = part.len() == digits && part.bytes().all(|byte| byte.is_ascii_digit());
+            return if valid { part.parse::<u32>().ok() } else { None };
+        };
+        let [year, month, day] = date[..] else {
+            return None;
+        };
+        let [hour, minute, second] = time[..] else {
+            return None;
+        };
+        let date_time = DateTime {
+            year: field(year, 4)?,
+            month: field(month, 2)?,
+            day: field(day, 2)?,
+            hour: field(hour, 2)?,
+            minute: field(minute, 2)?,
+            second: field(second, 2)?,
+        };
+        let valid: bool = date_time.year >= 1970
+            && (1..=12).contains(&date_time.month)
+            && (1..=days_in_month(date_time.year, date_time.month)).contains(&date_time.day)
+            && date_time.hour < 24 && date_time.minute < 60 && date_time.second < 60;
+        return if valid { Some(date_time) } else { None };
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                      self.year, self.month, self.day, self.hour, self.minute, self.second);
+    }
+}
+
+/// `value`, below 100, as two BCD digits.
+pub fn to_bcd(value: u32) -> u8 {
+    return (((value / 10 % 10) << 4) | (value % 10)) as u8;
+}
+
+/// A real-time clock the guest reads as BCD registers, starting at a
+/// settable epoch and advancing with a `Clock`.
+///
+/// The device occupies two ports starting at `base`:
+///
+/// * `base`: OUT selects one of the `RTC_*` registers. Selecting
+///   `RTC_SECONDS` latches the current time, and every register reads from
+///   the latch, so selecting and reading the registers in order gives one
+///   coherent time even if a second passes between the reads.
+/// * `base + 1`: IN returns the selected register. OUTs are ignored.
+#[derive(Debug)]
+pub struct Rtc {
+    base: u8,
+    clock: Box<dyn Clock>,
+    epoch: u64,
+    origin: Duration,
+    selected: u8,
+    latched: DateTime,
+}
+
+impl Rtc {
+    /// A clock reading `epoch` now.
+    pub fn new(base: u8, clock: Box<dyn Clock>, epoch: DateTime) -> Rtc {
+        let origin: Duration = clock.now();
+        return Rtc { base, clock, epoch: epoch.to_unix(), origin, selected: RTC_SECONDS, latched: epoch };
+    }
+
+    /// The ports this device should be attached to.
+    pub fn ports(&self) -> Vec<u8> {
+        return vec![self.base, self.base.wrapping_add(1)];
+    }
+
+    /// The current time, ignoring the latch.
+    pub fn now(&self) -> DateTime {
+        let elapsed: Duration = self.clock.now().saturating_sub(self.origin);
+        return DateTime::from_unix(self.epoch + elapsed.as_secs());
+    }
+
+    fn register(&self, index: u8) -> u8 {
+        let time = &self.latched;
+        return match index {
+            RTC_SECONDS => to_bcd(time.second),
+            RTC_MINUTES => to_bcd(time.minute),
+            RTC_HOURS => to_bcd(time.hour),
+            RTC_DAY => to_bcd(time.day),
+            RTC_MONTH => to_bcd(time.month),
+            RTC_YEAR => to_bcd(time.year % 100),
+            RTC_CENTURY => to_bcd(time.year / 100),
+            _ => 0xff,
+        };
+    }
+}
+
+impl IoDevice for Rtc {
+    fn read(&mut self, port: u16, _ctx: &IoContext) -> u8 {
+        if (port as u8).wrapping_sub(self.base) != 1 {
+            return 0xff;
+        }
+        return self.register(self.selected);
+    }
+
+    fn write(&mut self, port: u16, value: u8, _ctx: &IoContext) {
+        if (port as u8).wrapping_sub(self.base) != 0 {
+            return;
+        }
+        self.selected = value;
+        if value == RTC_SECONDS {
+            self.latched = self.now();
+        }
+    }
+
+    /// The time keeps running, as on a battery-backed clock; only the
+    /// selection and the latch start over.
+    fn reset(&mut self) {
+        self.selected = RTC_SECONDS;
+        self.latched = self.now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::processor::{ProcessorBuilder, RunOutcome};
+
+    fn date(text: &str) -> DateTime {
+        return DateTime::parse_iso8601(text).unwrap();
+    }
+
+    fn read_all(rtc: &mut Rtc) -> Vec<u8> {
+        let ctx = IoContext::default();
+        return (RTC_SECONDS..=RTC_CENTURY).map(|index| {
+            rtc.write(RTC_BASE_PORT as u16, index, &ctx);
+            return rtc.read(RTC_BASE_PORT as u16 + 1, &ctx);
+        }).collect();
+    }
+
+    #[test]
+    fn test_bcd() {
+        assert_eq!(to_bcd(0), 0x00);
+        assert_eq!(to_bcd(7), 0x07);
+        assert_eq!(to_bcd(59), 0x59);
+        assert_eq!(to_bcd(2024 % 100), 0x24);
+        assert_eq!(to_bcd(20), 0x20);
+    }
+
+    #[test]
+    fn test_parse_and_unix_round_trip() {
+        assert_eq!(date("1970-01-01T00:00:00Z").to_unix(), 0);
+        assert_eq!(date("2000-03-01T00:00:00").to_unix(), 951_868_800);
+        assert_eq!(date("2024-02-29T12:34:56Z").to_string(), "2024-02-29T12:34:56Z");
+        for seconds in [0, 68_169_599, 951_782_399, 1_709_251_199, 4_107_542_399] {
+            assert_eq!(DateTime::from_unix(seconds).to_unix(), seconds);
+        }
+
+        assert_eq!(DateTime::parse_iso8601("2023-02-29T00:00:00"), None);
+        assert_eq!(DateTime::parse_iso8601("2024-13-01T00:00:00"), None);
+        assert_eq!(DateTime::parse_iso8601("2024-01-01 00:00:00"), None);
+        assert_eq!(DateTime::parse_iso8601("2024-01-01T24:00:00"), None);
+        assert_eq!(DateTime::parse_iso8601("1969-12-31T23:59:59"), None);
+    }
+
+    #[test]
+    fn test_leap_days() {
+        for (before, after) in [
+            ("2024-02-28T23:59:59", "2024-02-29T00:00:00Z"),
+            ("2024-02-29T23:59:59", "2024-03-01T00:00:00Z"),
+            ("2023-02-28T23:59:59", "2023-03-01T00:00:00Z"),
+            ("2000-02-28T23:59:59", "2000-02-29T00:00:00Z"),
+            ("2100-02-28T23:59:59", "2100-03-01T00:00:00Z"),
+            ("1999-12-31T23:59:59", "2000-01-01T00:00:00Z"),
+        ] {
+            assert_eq!(DateTime::from_unix(date(before).to_unix() + 1).to_string(), after, "after {}", before);
+        }
+    }
+
+    #[test]
+    fn test_registers_tick_with_clock() {
+        let clock = ManualClock::new();
+        let mut rtc = Rtc::new(RTC_BASE_PORT, Box::new(clock.clone()), date("2099-12-31T23:59:58Z"));
+
+        assert_eq!(read_all(&mut rtc), vec![0x58, 0x59, 0x23, 0x31, 0x12, 0x99, 0x20]);
+        clock.advance(Duration::from_millis(2500));
+        assert_eq!(read_all(&mut rtc), vec![0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x21]);
+    }
+
+    #[test]
+    fn test_latch_holds_across_rollover() {
+        let clock = ManualClock::new();
+        let mut rtc = Rtc::new(RTC_BASE_PORT, Box::new(clock.clone()), date("2024-02-28T12:34:59Z"));
+        let ctx = IoContext::default();
+        let data = RTC_BASE_PORT as u16 + 1;
+
+        rtc.write(RTC_BASE_PORT as u16, RTC_SECONDS, &ctx);
+        assert_eq!(rtc.read(data, &ctx), 0x59);
+        clock.advance(Duration::from_secs(1));
+        rtc.write(RTC_BASE_PORT as u16, RTC_MINUTES, &ctx);
+        assert_eq!(rtc.read(data, &ctx), 0x34);
+        rtc.write(RTC_BASE_PORT as u16, RTC_HOURS, &ctx);
+        assert_eq!(rtc.read(data, &ctx), 0x12);
+
+        assert_eq!(read_all(&mut rtc), vec![0x00, 0x35, 0x12, 0x28, 0x02, 0x24, 0x20]);
+        rtc.write(RTC_BASE_PORT as u16, RTC_CENTURY + 1, &ctx);
+        assert_eq!(rtc.read(data, &ctx), 0xff);
+    }
+
+    #[test]
+    fn test_guest_reads_time() {
+        let clock = ManualClock::new();
+        let rtc = Rtc::new(RTC_BASE_PORT, Box::new(clock.clone()), date("1999-12-31T23:59:59Z"));
+        let ports = rtc.ports();
+        let mut processor = ProcessorBuilder::new()
+            .clock(Box::new(clock.clone()))
+            .device(&ports, Box::new(rtc))
+            .build();
+        processor.load_program(&[
+            0x21, 0x00, 0x20, // lxi h, 2000h
+            0x06, 0x00,       // mvi b, 0
+            0x78,             // loop: mov a, b
+            0xd3, 0x70,       // out 70h
+            0xdb, 0x71,       // in 71h
+            0x77,             // mov m, a
+            0x23,             // inx h
+            0x04,             // inr b
+            0x78,             // mov a, b
+            0xfe, 0x07,       // cpi 7
+            0xc2, 0x05, 0x00, // jnz loop
+            0x76,             // hlt
+        ]);
+        clock.advance(Duration::from_secs(1));
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        let registers: Vec<u8> = (0x2000..0x2007).map(|addr| processor.peek(addr)).collect();
+        assert_eq!(registers, vec![0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x20]);
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::processor::{IoContext, IoDevice};
+
+/// A free-running tick counter driven by a `Clock`, for guests that need to
+/// measure real time.
+///
+/// IN returns the number of whole ticks since the counter was last reset,
+/// wrapping at 256. Any OUT resets it.
+#[derive(Debug)]
+pub struct Timer {
+    clock: Box<dyn Clock>,
+    tick: Duration,
+    origin: Duration,
+    /// Interrupt source and RST opcode raised on each tick.
+    interrupt: Option<(u8, u8)>,
+    /// Ticks already interrupted for since the last reset.
+    interrupted: u128,
+}
+
+impl Timer {
+    pub fn new(clock: Box<dyn Clock>, tick: Duration) -> Timer {
+        let origin: Duration = clock.now();
+        return Timer { clock, tick: tick.max(Duration::from_nanos(1)), origin, interrupt: None, interrupted: 0 };
+    }
+
+    /// Raises `rst_opcode` as interrupt `source` on every tick. Ticks that
+    /// pass between two instructions raise it once.
+    pub fn tick_interrupt(mut self, source: u8, rst_opcode: u8) -> Timer {
+        self.interrupt = Some((source, rst_opcode));
+        return self;
+    }
+
+    fn ticks(&self) -> u128 {
+        return self.clock.now().saturating_sub(self.origin).as_nanos() / self.tick.as_nanos();
+    }
+}
+
+impl IoDevice for Timer {
+    fn read(&mut self, _port: u16, _ctx: &IoContext) -> u8 {
+        return self.ticks() as u8;
+    }
+
+    fn write(&mut self, _port: u16, _value: u8, _ctx: &IoContext) {
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.origin = self.clock.now();
+        self.interrupted = 0;
+    }
+
+    fn poll_interrupt(&mut self, _ctx: &IoContext) -> Option<(u8, u8)> {
+        self.interrupt?;
+        let ticks: u128 = self.ticks();
+        if ticks == self.interrupted {
+            return None;
+        }
+        self.interrupted = ticks;
+        return self.interrupt;
+    }
+
+    fn raises_interrupts(&self) -> bool {
+        return self.interrupt.is_some();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::processor::{ProcessorBuilder, RunOutcome};
+
+    #[test]
+    fn test_counts_ticks_and_resets() {
+        let clock = ManualClock::new();
+        let mut timer = Timer::new(Box::new(clock.clone()), Duration::from_millis(10));
+        let ctx = IoContext::default();
+
+        clock.advance(Duration::from_millis(35));
+        assert_eq!(timer.read(0, &ctx), 3);
+
+        timer.write(0, 0, &ctx);
+        clock.advance(Duration::from_millis(9));
+        assert_eq!(timer.read(0, &ctx), 0);
+        clock.advance(Duration::from_millis(2560));
+        assert_eq!(timer.read(0, &ctx), 0);
+    }
+
+    #[test]
+    fn test_guest_waits_for_ticks_under_throttle() {
+        let clock = ManualClock::new();
+        let timer = Timer::new(Box::new(clock.clone()), Duration::from_millis(1));
+        let mut processor = ProcessorBuilder::new()
+            .clock(Box::new(clock.clone()))
+            .throttle(1_000_000)
+            .deadline_check_interval(1)
+            .device(&[0x20], Box::new(timer))
+            .build();
+        processor.load_program(&[
+            0xdb, 0x20,       // loop: in 20h
+            0xfe, 0x03,       // cpi 3
+            0xda, 0x00, 0x00, // jc loop
+            0x76,             // hlt
+        ]);
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        assert!(clock.now() >= Duration::from_millis(3));
+        assert!(clock.now() < Duration::from_millis(4));
+        let cycles = processor.cycle_count();
+        assert!((3000..3100).contains(&cycles), "{} cycles", cycles);
+    }
+
+    #[test]
+    fn test_tick_interrupt_latency_and_handler_time() {
+        let clock = ManualClock::new();
+        let timer = Timer::new(Box::new(clock.clone()), Duration::from_millis(1)).tick_interrupt(4, 0xd7); // RST 2
+        let mut processor = ProcessorBuilder::new()
+            .clock(Box::new(clock.clone()))
+            .throttle(1_000_000)
+            .deadline_check_interval(1)
+            .interrupt_timing(true)
+            .device(&[0x20], Box::new(timer))
+            .build();
+        processor.load_at(0x0000, &[
+            0x31, 0x00, 0x20, // 0000 LXI SP,2000h
+            0xfb,             // 0003 EI
+            0xe3,             // 0004 XTHL         the longest instruction, 18 cycles
+            0xe3,             // 0005 XTHL
+            0x00,             // 0006 NOP
+            0xc3, 0x04, 0x00, // 0007 JMP 0004h
+        ]);
+        processor.load_at(0x0010, &[
+            0xf5,             // 0010 PUSH PSW     11
+            0xdb, 0x20,       // 0011 IN 20h       10
+            0xf1,             // 0013 POP PSW      10
+            0xfb,             // 0014 EI            4
+            0xc9,             // 0015 RET          10
+        ]);
+
+        processor.run_with_limit(50_000);
+
+        let timing = processor.interrupt_timing().unwrap().sources()[&4];
+        // About one tick per thousand cycles, each taken at the first
+        // boundary after it with the RST's 11 cycles on top.
+        let ticks: u64 = processor.cycle_count() / 1000;
+        assert!((ticks - 1..=ticks).contains(&timing.latency.count), "{} of {} ticks", timing.latency.count, ticks);
+        assert!(timing.latency.min >= 11);
+        assert!(timing.latency.max <= 18 + 11, "{:?}", timing.latency);
+        assert_eq!((timing.handler.min, timing.handler.max), (45, 45));
+        assert_eq!(timing.handler.count, timing.latency.count);
+    }
+}
@@ -0,0 +1,78 @@
+//! Named DIP switch and jumper settings. A machine declares its options,
+//! each a group of bits on one input port with the values it can take, so
+//! callers set `lives` to `5` rather than working out which bits that is.
+//! The declarations are plain data, to be listed by a UI as well as used.
+
+use std::fmt;
+
+/// One value an option can take and the bits it sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DipChoice {
+    pub value: &'static str,
+    pub bits: u8,
+}
+
+/// A setting made with one or more switches on an input port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DipOption {
+    pub name: &'static str,
+    pub port: u8,
+    /// The switches the option owns; its choices set no bits outside them.
+    pub mask: u8,
+    pub choices: &'static [DipChoice],
+    /// The value the machine is set to when it is made.
+    pub default: &'static str,
+}
+
+impl DipOption {
+    pub fn values(&self) -> Vec<&'static str> {
+        return self.choices.iter().map(|choice| choice.value).collect();
+    }
+
+    /// `port_byte` with this option's switches set for `value`.
+    pub fn apply(&self, port_byte: u8, value: &str) -> Result<u8, OptionError> {
+        let choice = self.choices.iter().find(|choice| choice.value == value).ok_or_else(|| OptionError::InvalidValue {
+            name: self.name,
+            value: value.to_string(),
+            choices: self.values(),
+        })?;
+        return Ok(port_byte & !self.mask | choice.bits);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionError {
+    UnknownOption { name: String, known: Vec<&'static str> },
+    InvalidValue { name: &'static str, value: String, choices: Vec<&'static str> },
+}
+
+impl fmt::Display for OptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            OptionError::UnknownOption { name, known } =>
+                write!(f, "unknown option '{}'; expected one of {}", name, known.join(", ")),
+            OptionError::InvalidValue { name, value, choices } =>
+                write!(f, "{}: invalid value '{}'; expected one of {}", name, value, choices.join(", ")),
+        };
+    }
+}
+
+/// The option called `name` among `options`.
+pub fn find<'a>(options: &'a [DipOption], name: &str) -> Result<&'a DipOption, OptionError> {
+    return options.iter().find(|option| option.name == name).ok_or_else(|| OptionError::UnknownOption {
+        name: name.to_string(),
+        known: options.iter().map(|option| option.name).collect(),
+    });
+}
+
+/// Splits `name=value`, as `--option` takes it.
+pub fn parse_setting(text: &str) -> Option<(String, String)> {
+    let (name, value) = text.split_once('=')?;
+    return Some((name.trim().to_string(), value.trim().to_string()));
+}
+
+/// The value each option in `options` starts at, applied to port `port`.
+pub fn defaults(options: &[DipOption], port: u8) -> u8 {
+    return options.iter().filter(|option| option.port == port)
+        .fold(0, |byte, option| option.apply(byte, option.default).expect("defaults are among the choices"));
+}
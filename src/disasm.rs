@@ -0,0 +1,435 @@
+//! Linear-sweep disassembler. Its output is valid input for `asm::assemble`,
+//! so any image can be disassembled and reassembled to the same bytes.
+//! `disassemble_reachable` instead follows control flow from given roots,
+//! leaving out whatever they never reach, and `to_labelled_source` turns
+//! that into source with labels and data directives that still assembles
+//! to the same bytes.
+
+#[cfg(feature = "disasm")]
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::processor::instruction_length;
+use crate::word::word;
+
+/// Instruction templates indexed by opcode. `{8}` and `{16}` stand for the
+/// immediate operand; undocumented opcodes are empty and come out as `db`.
+pub const MNEMONICS: [&str; 256] = [
+    "nop", "lxi b, {16}", "stax b", "inx b", // 00
+    "inr b", "dcr b", "mvi b, {8}", "rlc", // 04
+    "", "dad b", "ldax b", "dcx b", // 08
+    "inr c", "dcr c", "mvi c, {8}", "rrc", // 0c
+    "", "lxi d, {16}", "stax d", "inx d", // 10
+    "inr d", "dcr d", "mvi d, {8}", "ral", // 14
+    "", "dad d", "ldax d", "dcx d", // 18
+    "inr e", "dcr e", "mvi e, {8}", "rar", // 1c
+    "", "lxi h, {16}", "shld {16}", "inx h", // 20
+    "inr h", "dcr h", "mvi h, {8}", "daa", // 24
+    "", "dad h", "lhld {16}", "dcx h", // 28
+    "inr l", "dcr l", "mvi l, {8}", "cma", // 2c
+    "", "lxi sp, {16}", "sta {16}", "inx sp", // 30
+    "inr m", "dcr m", "mvi m, {8}", "stc", // 34
+    "", "dad sp", "lda {16}", "dcx sp", // 38
+    "inr a", "dcr a", "mvi a, {8}", "cmc", // 3c
+    "mov b, b", "mov b, c", "mov b, d", "mov b, e", // 40
+    "mov b, h", "mov b, l", "mov b, m", "mov b, a", // 44
+    "mov c, b", "mov c, c", "mov c, d", "mov c, e", // 48
+    "mov c, h", "mov c, l", "mov c, m", "mov c, a", // 4c
+    "mov d, b", "mov d, c", "mov d, d", "mov d, e", // 50
+    "mov d, h", "mov d, l", "mov d, m", "mov d, a", // 54
+    "mov e, b", "mov e, c", "mov e, d", "mov e, e", // 58
+    "mov e, h", "mov e, l", "mov e, m", "mov e, a", // 5c
+    "mov h, b", "mov h, c", "mov h, d", "mov h, e", // 60
+    "mov h, h", "mov h, l", "mov h, m", "mov h, a", // 64
+    "mov l, b", "mov l, c", "mov l, d", "mov l, e", // 68
+    "mov l, h", "mov l, l", "mov l, m", "mov l, a", // 6c
+    "mov m, b", "mov m, c", "mov m, d", "mov m, e", // 70
+    "mov m, h", "mov m, l", "hlt", "mov m, a", // 74
+    "mov a, b", "mov a, c", "mov a, d", "mov a, e", // 78
+    "mov a, h", "mov a, l", "mov a, m", "mov a, a", // 7c
+    "add b", "add c", "add d", "add e", // 80
+    "add h", "add l", "add m", "add a", // 84
+    "adc b", "adc c", "adc d", "adc e", // 88
+    "adc h", "adc l", "adc m", "adc a", // 8c
+    "sub b", "sub c", "sub d", "sub e", // 90
+    "sub h", "sub l", "sub m", "sub a", // 94
+    "sbb b", "sbb c", "sbb d", "sbb e", // 98
+    "sbb h", "sbb l", "sbb m", "sbb a", // 9c
+    "ana b", "ana c", "ana d", "ana e", // a0
+    "ana h", "ana l", "ana m", "ana a", // a4
+    "xra b", "xra c", "xra d", "xra e", // a8
+    "xra h", "xra l", "xra m", "xra a", // ac
+    "ora b", "ora c", "ora d", "ora e", // b0
+    "ora h", "ora l", "ora m", "ora a", // b4
+    "cmp b", "cmp c", "cmp d", "cmp e", // b8
+    "cmp h", "cmp l", "cmp m", "cmp a", // bc
+    "rnz", "pop b", "jnz {16}", "jmp {16}", // c0
+    "cnz {16}", "push b", "adi {8}", "rst 0", // c4
+    "rz", "ret", "jz {16}", "", // c8
+    "cz {16}", "call {16}", "aci {8}", "rst 1", // cc
+    "rnc", "pop d", "jnc {16}", "out {8}", // d0
+    "cnc {16}", "push d", "sui {8}", "rst 2", // d4
+    "rc", "", "jc {16}", "in {8}", // d8
+    "cc {16}", "", "sbi {8}", "rst 3", // dc
+    "rpo", "pop h", "jpo {16}", "xthl", // e0
+    "cpo {16}", "push h", "ani {8}", "rst 4", // e4
+    "rpe", "pchl", "jpe {16}", "xchg", // e8
+    "cpe {16}", "", "xri {8}", "rst 5", // ec
+    "rp", "pop psw", "jp {16}", "di", // f0
+    "cp {16}", "push psw", "ori {8}", "rst 6", // f4
+    "rm", "sphl", "jm {16}", "ei", // f8
+    "cm {16}", "", "cpi {8}", "rst 7", // fc
+];
+
+/// The documented instruction an undocumented opcode behaves as on a real
+/// 8080, as a template like those in `MNEMONICS`.
+pub fn alias_template(opcode: u8) -> Option<&'static str> {
+    return match opcode {
+        0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => Some("nop"),
+        0xcb => Some("jmp {16}"),
+        0xd9 => Some("ret"),
+        0xdd | 0xed | 0xfd => Some("call {16}"),
+        _ => None,
+    };
+}
+
+/// One decoded instruction, or a single data byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        return write!(f, "{:04x}  {:<8}  {}", self.addr, bytes.join(" "), self.text);
+    }
+}
+
+/// Formats a byte the way the assembler reads it back, e.g. `0feh`.
+pub fn hex8(value: u8) -> String {
+    return with_leading_digit(format!("{:02x}h", value));
+}
+
+/// Formats a word the way the assembler reads it back, e.g. `0c000h`.
+pub fn hex16(value: u16) -> String {
+    return with_leading_digit(format!("{:04x}h", value));
+}
+
+fn with_leading_digit(text: String) -> String {
+    if text.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return format!("0{}", text);
+    }
+    return text;
+}
+
+/// Decodes the instruction at the start of `bytes`. Undocumented opcodes and
+/// instructions cut off by the end of `bytes` decode as a one-byte `db`.
+pub fn decode(bytes: &[u8], addr: u16) -> Line {
+    let opcode: u8 = bytes[0];
+    let template: &str = MNEMONICS[opcode as usize];
+    let length: usize = instruction_length(opcode) as usize;
+    if template.is_empty() || bytes.len() < length {
+        return Line { addr, bytes: vec![opcode], text: format!("db {}", hex8(opcode)) };
+    }
+
+    let text: String = match length {
+        2 => template.replace("{8}", &hex8(bytes[1])),
+        3 => template.replace("{16}", &hex16(word(bytes[2], bytes[1]))),
+        _ => template.to_string(),
+    };
+    return Line { addr, bytes: bytes[..length].to_vec(), text };
+}
+
+/// The instruction at the start of `bytes` as diagnostics show it: what
+/// `decode` gives for a documented opcode, and for an undocumented one the
+/// instruction it aliases, e.g. `call 3456h alias`.
+pub fn describe(bytes: &[u8]) -> String {
+    let opcode: u8 = bytes[0];
+    return match alias_template(opcode) {
+        Some(template) if MNEMONICS[opcode as usize].is_empty() && bytes.len() >= instruction_length(opcode) as usize => {
+            let mut aliased: Vec<u8> = bytes.to_vec();
+            aliased[0] = MNEMONICS.iter().position(|known| *known == template).expect("aliases are documented") as u8;
+            format!("{} alias", decode(&aliased, 0).text)
+        },
+        _ => decode(bytes, 0).text,
+    };
+}
+
+/// Decodes every byte of `bytes` as code, starting at `origin`.
+pub fn disassemble(bytes: &[u8], origin: u16) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut offset: usize = 0;
+    while offset < bytes.len() {
+        let line: Line = decode(&bytes[offset..], origin.wrapping_add(offset as u16));
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+    return lines;
+}
+
+/// Where the instruction in `line` can go next, as far as its bytes say.
+/// PCHL and RET go somewhere only known at runtime, so they have none.
+#[cfg(feature = "disasm")]
+fn successors(line: &Line) -> Vec<u16> {
+    let next: u16 = line.addr.wrapping_add(line.bytes.len() as u16);
+    let opcode: u8 = line.bytes[0];
+    if MNEMONICS[opcode as usize].is_empty() || line.bytes.len() < instruction_length(opcode) as usize {
+        return Vec::new();
+    }
+    let target = || word(line.bytes[2], line.bytes[1]);
+    return match opcode {
+        0x76 | 0xc9 | 0xe9 => Vec::new(), // HLT, RET, PCHL
+        0xc3 => vec![target()],
+        _ if opcode & 0xc7 == 0xc2 || opcode & 0xc7 == 0xc4 || opcode == 0xcd => vec![target(), next],
+        _ if opcode & 0xc7 == 0xc7 => vec![(opcode & 0x38) as u16, next],
+        _ => vec![next],
+    };
+}
+
+/// Decodes the code in `bytes`, loaded at `origin`, that can be reached
+/// from `roots` by following jumps, calls and fall-through, in address
+/// order. Jump-table targets can't be found this way; pass those seen at
+/// runtime, e.g. `IndirectFlowReport::targets`, as extra roots.
+#[cfg(feature = "disasm")]
+pub fn disassemble_reachable(bytes: &[u8], origin: u16, roots: &[u16]) -> Vec<Line> {
+    let mut lines: BTreeMap<u16, Line> = BTreeMap::new();
+    let mut pending: Vec<u16> = roots.to_vec();
+    while let Some(addr) = pending.pop() {
+        let offset: usize = addr.wrapping_sub(origin) as usize;
+        if offset >= bytes.len() || lines.contains_key(&addr) {
+            continue;
+        }
+        let line: Line = decode(&bytes[offset..], addr);
+        pending.extend(successors(&line));
+        lines.insert(addr, line);
+    }
+    return lines.into_values().collect();
+}
+
+/// Renders `lines` as assembler source, starting with an `org`.
+#[cfg(feature = "disasm")]
+pub fn to_source(lines: &[Line], origin: u16) -> String {
+    let mut source: String = format!("  org {}\n", hex16(origin));
+    for line in lines {
+        source.push_str("  ");
+        source.push_str(&line.text);
+        source.push('\n');
+    }
+    return source;
+}
+
+/// How the `disasm` command prints an image.
+#[cfg(feature = "disasm")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// One instruction a line with its address and bytes, for reading.
+    #[default]
+    Listing,
+    /// `to_labelled_source`, for assembling again.
+    Asm,
+}
+
+#[cfg(feature = "disasm")]
+impl Format {
+    pub fn parse(text: &str) -> Option<Format> {
+        return match text {
+            "listing" => Some(Format::Listing),
+            "asm" => Some(Format::Asm),
+            _ => None,
+        };
+    }
+}
+
+/// Data bytes on one `db` line.
+#[cfg(feature = "disasm")]
+const DB_PER_LINE: usize = 8;
+
+/// The name `to_labelled_source` gives `addr`. Names come from addresses
+/// alone, so the same code gets the same labels whatever else changes.
+#[cfg(feature = "disasm")]
+pub fn label_name(addr: u16) -> String {
+    return format!("l_{:04x}", addr);
+}
+
+/// The address a jump, call or direct memory access in `line` names. Other
+/// 16-bit operands, like LXI's, may well be constants and stay numbers.
+#[cfg(feature = "disasm")]
+fn operand_target(line: &Line) -> Option<u16> {
+    let opcode: u8 = line.bytes[0];
+    let addressing: bool = opcode == 0xc3 || opcode == 0xcd || opcode & 0xc7 == 0xc2 || opcode & 0xc7 == 0xc4
+        || matches!(opcode, 0x22 | 0x2a | 0x32 | 0x3a);
+    return match line.bytes.len() {
+        3 if addressing && !MNEMONICS[opcode as usize].is_empty() => Some(word(line.bytes[2], line.bytes[1])),
+        _ => None,
+    };
+}
+
+/// Renders `bytes`, loaded at `origin`, as source `asm::assemble` turns
+/// back into the same bytes. Code reachable from `roots` comes out as
+/// instructions and everything else as `db`, even bytes that would decode
+/// as instructions, or as `dw` where a word holds the address of a code
+/// label, as in a jump table. The roots and the targets of jumps, calls
+/// and direct loads and stores get `label_name` labels where a line starts
+/// there; an instruction jumping into the middle of another keeps its
+/// number. Operands are in hex, as in `to_source`.
+#[cfg(feature = "disasm")]
+pub fn to_labelled_source(bytes: &[u8], origin: u16, roots: &[u16]) -> String {
+    let addr = |offset: usize| origin.wrapping_add(offset as u16);
+    let mut code: BTreeMap<usize, Line> = BTreeMap::new();
+    let mut covered: usize = 0;
+    for line in disassemble_reachable(bytes, origin, roots) {
+        let offset: usize = line.addr.wrapping_sub(origin) as usize;
+        if offset >= covered {
+            covered = offset + line.bytes.len();
+            code.insert(offset, line);
+        }
+    }
+    let is_code = |offset: usize| code.range(..=offset).next_back().is_some_and(|(start, line)| offset < start + line.bytes.len());
+
+    let wanted = roots.iter().copied().chain(code.values().filter_map(operand_target));
+    let labels: BTreeSet<u16> = wanted.filter(|target| {
+        let offset: usize = target.wrapping_sub(origin) as usize;
+        return offset < bytes.len() && (code.contains_key(&offset) || !is_code(offset));
+    }).collect();
+    let code_label = |value: u16| labels.contains(&value) && code.contains_key(&(value.wrapping_sub(origin) as usize));
+
+    let mut source: String = format!("  org {}\n", hex16(origin));
+    let mut offset: usize = 0;
+    while offset < bytes.len() {
+        if labels.contains(&addr(offset)) {
+            source.push_str(&format!("{}:\n", label_name(addr(offset))));
+        }
+        if let Some(line) = code.get(&offset) {
+            let text: String = match operand_target(line).filter(|target| labels.contains(target)) {
+                Some(target) => line.text.replace(&hex16(target), &label_name(target)),
+                None => line.text.clone(),
+            };
+            source.push_str(&format!("  {}\n", text));
+            offset += line.bytes.len();
+            continue;
+        }
+        // Data runs to the next instruction or label.
+        let end: usize = (offset + 1..bytes.len())
+            .find(|next| code.contains_key(next) || labels.contains(&addr(*next)))
+            .unwrap_or(bytes.len());
+        let mut pending: Vec<String> = Vec::new();
+        while offset < end {
+            let value: Option<u16> = (offset + 1 < end).then(|| word(bytes[offset + 1], bytes[offset]));
+            if let Some(value) = value.filter(|value| code_label(*value)) {
+                flush_db(&mut source, &mut pending);
+                source.push_str(&format!("  dw {}\n", label_name(value)));
+                offset += 2;
+                continue;
+            }
+            pending.push(hex8(bytes[offset]));
+            if pending.len() == DB_PER_LINE {
+                flush_db(&mut source, &mut pending);
+            }
+            offset += 1;
+        }
+        flush_db(&mut source, &mut pending);
+    }
+    return source;
+}
+
+#[cfg(feature = "disasm")]
+fn flush_db(source: &mut String, pending: &mut Vec<String>) {
+    if !pending.is_empty() {
+        source.push_str(&format!("  db {}\n", pending.join(", ")));
+        pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_operands() {
+        assert_eq!(decode(&[0x31, 0xff, 0x9f], 0).text, "lxi sp, 9fffh");
+        assert_eq!(decode(&[0xc3, 0x00, 0xc0], 0).text, "jmp 0c000h");
+        assert_eq!(decode(&[0xfe, 0x61], 0).text, "cpi 61h");
+        assert_eq!(decode(&[0x7e], 0).text, "mov a, m");
+        assert_eq!(decode(&[0xf5], 0).text, "push psw");
+        assert_eq!(decode(&[0xdf], 0).text, "rst 3");
+    }
+
+    #[test]
+    fn test_undocumented_and_truncated_become_data() {
+        let lines: Vec<Line> = disassemble(&[0x08, 0x00, 0xc3, 0x10], 0x100);
+
+        let texts: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, vec!["db 08h", "nop", "db 0c3h", "db 10h"]);
+        assert_eq!(lines[3].addr, 0x103);
+    }
+
+    #[test]
+    fn test_describe_names_aliases() {
+        assert_eq!(describe(&[0xdd, 0x56, 0x34]), "call 3456h alias");
+        assert_eq!(describe(&[0xcb, 0x00, 0xc0]), "jmp 0c000h alias");
+        assert_eq!(describe(&[0x08, 0x00, 0x00]), "nop alias");
+        assert_eq!(describe(&[0x32, 0x00, 0x20]), "sta 2000h");
+        assert_eq!(describe(&[0xed]), "db 0edh");
+    }
+
+    #[cfg(feature = "disasm")]
+    #[test]
+    fn test_runtime_targets_extend_reachable_code() {
+        use crate::processor::{ProcessorBuilder, RunOutcome};
+
+        // Calls each of three handlers through a table and PCHL.
+        let program: [u8; 43] = [
+            0x31, 0x00, 0x20, // 0000 lxi sp, 2000h
+            0x0e, 0x00,       // 0003 mvi c, 0
+            0x21, 0x1c, 0x00, // 0005 lxi h, table
+            0x79,             // 0008 mov a, c
+            0x87,             // 0009 add a
+            0x85,             // 000a add l
+            0x6f,             // 000b mov l, a
+            0x5e,             // 000c mov e, m
+            0x23,             // 000d inx h
+            0x56,             // 000e mov d, m
+            0xeb,             // 000f xchg
+            0xcd, 0x1b, 0x00, // 0010 call dispatch
+            0x0c,             // 0013 inr c
+            0x79,             // 0014 mov a, c
+            0xfe, 0x03,       // 0015 cpi 3
+            0xc2, 0x05, 0x00, // 0017 jnz 0005h
+            0x76,             // 001a hlt
+            0xe9,             // 001b dispatch: pchl
+            0x22, 0x00, 0x25, 0x00, 0x28, 0x00, // 001c table
+            0x06, 0x01, 0xc9, // 0022 mvi b, 1; ret
+            0x06, 0x02, 0xc9, // 0025 mvi b, 2; ret
+            0x06, 0x03, 0xc9, // 0028 mvi b, 3; ret
+        ];
+        let mut processor = ProcessorBuilder::new().indirect_flow_tracking(true).build();
+        processor.load_program(&program);
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        let report = processor.indirect_flow_report().unwrap();
+        let transfers: Vec<(u16, u16)> = report.transfers().keys().copied().collect();
+        assert_eq!(transfers, vec![(0x001b, 0x0022), (0x001b, 0x0025), (0x001b, 0x0028)]);
+
+        let addrs = |roots: &[u16]| -> Vec<u16> {
+            return disassemble_reachable(&program, 0, roots).iter().map(|line| line.addr).collect();
+        };
+        let static_only: Vec<u16> = addrs(&[0]);
+        assert_eq!(static_only.last(), Some(&0x001b));
+        let roots: Vec<u16> = [vec![0], report.targets()].concat();
+        let rerooted: Vec<u16> = addrs(&roots);
+        for handler in [0x0022, 0x0024, 0x0025, 0x0027, 0x0028, 0x002a] {
+            assert!(rerooted.contains(&handler), "{:04x}", handler);
+        }
+        assert!(!rerooted.contains(&0x001c));
+    }
+
+    #[test]
+    fn test_line_listing() {
+        let line: Line = decode(&[0xcd, 0x0c, 0x00], 0x0007);
+
+        assert_eq!(line.to_string(), "0007  cd 0c 00  call 000ch");
+    }
+}
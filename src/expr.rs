@@ -0,0 +1,276 @@
+//! Expressions over processor state, for watches and conditions typed at
+//! the command line or in the monitor, e.g. `hl - sp` or
+//! `flags.carry && a > 0x80`.
+//!
+//! Operands are numbers as the monitor reads them (`0x1f`, `1fh` or
+//! decimal), the registers `a` to `l`, `sp` and `pc`, the pairs `bc`, `de`
+//! and `hl`, `m` for the byte HL points at, `[<expr>]` for the byte at any
+//! address, and `flags.carry`, `flags.zero`, `flags.sign`, `flags.parity`
+//! and `flags.aux_carry`. Operators, loosest first: `||`, `&&`, `|`, `^`,
+//! `&`, `==` `!=`, `<` `<=` `>` `>=`, `+` `-`, `*`, and unary `!` and `-`.
+//! Values are 64-bit and arithmetic wraps; comparisons and logic give 0
+//! or 1.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::processor::Processor;
+use crate::word::parse_number;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.message);
+    }
+}
+
+impl Error for ExprError {}
+
+fn error<T>(message: String) -> Result<T, ExprError> {
+    return Err(ExprError { message });
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    A, B, C, D, E, H, L, Sp, Pc, Bc, De, Hl, M,
+    Carry, Zero, Sign, Parity, AuxCarry,
+}
+
+const OPERANDS: [(&str, Operand); 18] = [
+    ("a", Operand::A), ("b", Operand::B), ("c", Operand::C), ("d", Operand::D), ("e", Operand::E),
+    ("h", Operand::H), ("l", Operand::L), ("sp", Operand::Sp), ("pc", Operand::Pc),
+    ("bc", Operand::Bc), ("de", Operand::De), ("hl", Operand::Hl), ("m", Operand::M),
+    ("flags.carry", Operand::Carry), ("flags.zero", Operand::Zero), ("flags.sign", Operand::Sign),
+    ("flags.parity", Operand::Parity), ("flags.aux_carry", Operand::AuxCarry),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or, And, BitOr, BitXor, BitAnd, Eq, Ne, Lt, Le, Gt, Ge, Add, Sub, Mul,
+}
+
+/// Binary operators by how tightly they bind, loosest first.
+const LEVELS: [&[(&str, BinaryOp)]; 9] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[("<=", BinaryOp::Le), (">=", BinaryOp::Ge), ("<", BinaryOp::Lt), (">", BinaryOp::Gt)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul)],
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(u64),
+    Operand(Operand),
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+/// A parsed expression, ready to evaluate against a processor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    root: Node,
+}
+
+/// Splits `text` into numbers, names and operators.
+fn tokenize(text: &str) -> Result<Vec<String>, ExprError> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut rest: &str = text.trim_start();
+    while let Some(first) = rest.chars().next() {
+        let len: usize = if first.is_ascii_alphanumeric() || first == '_' {
+            rest.find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_' || ch == '.')).unwrap_or(rest.len())
+        } else if ["||", "&&", "==", "!=", "<=", ">="].iter().any(|op| rest.starts_with(op)) {
+            2
+        } else if "|^&<>+-*!()[]".contains(first) {
+            1
+        } else {
+            return error(format!("unexpected '{}'", first));
+        };
+        tokens.push(rest[..len].to_ascii_lowercase());
+        rest = rest[len..].trim_start();
+    }
+    return Ok(tokens);
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        return self.tokens.get(self.next).map(String::as_str);
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ExprError> {
+        if self.peek() != Some(token) {
+            return error(format!("expected '{}'", token));
+        }
+        self.next += 1;
+        return Ok(());
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Node, ExprError> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left: Node = self.binary(level + 1)?;
+        while let Some((_, op)) = LEVELS[level].iter().find(|(token, _)| self.peek() == Some(token)) {
+            self.next += 1;
+            let right: Node = self.binary(level + 1)?;
+            left = Node::Binary(*op, Box::new(left), Box::new(right));
+        }
+        return Ok(left);
+    }
+
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        let Some(token) = self.peek().map(str::to_string) else {
+            return error("expression ends early".to_string());
+        };
+        self.next += 1;
+        return match token.as_str() {
+            "!" => Ok(Node::Not(Box::new(self.unary()?))),
+            "-" => Ok(Node::Negate(Box::new(self.unary()?))),
+            "(" => {
+                let inner: Node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(inner)
+            },
+            "[" => {
+                let addr: Node = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Node::Memory(Box::new(addr)))
+            },
+            name => match OPERANDS.iter().find(|(known, _)| *known == name) {
+                Some((_, operand)) => Ok(Node::Operand(*operand)),
+                None => match parse_number(name) {
+                    Some(value) => Ok(Node::Number(value as u64)),
+                    None => error(format!("unknown name '{}'", name)),
+                },
+            },
+        };
+    }
+}
+
+fn operand(processor: &Processor, operand: Operand) -> u64 {
+    let registers = processor.registers();
+    let value: u16 = match operand {
+        Operand::A => registers.a as u16,
+        Operand::B => registers.b as u16,
+        Operand::C => registers.c as u16,
+        Operand::D => registers.d as u16,
+        Operand::E => registers.e as u16,
+        Operand::H => registers.h as u16,
+        Operand::L => registers.l as u16,
+        Operand::Sp => registers.sp,
+        Operand::Pc => registers.pc,
+        Operand::Bc => registers.bc(),
+        Operand::De => registers.de(),
+        Operand::Hl => registers.hl(),
+        Operand::M => processor.peek(registers.hl()) as u16,
+        Operand::Carry => registers.flags.carry as u16,
+        Operand::Zero => registers.flags.zero as u16,
+        Operand::Sign => registers.flags.sign as u16,
+        Operand::Parity => registers.flags.parity as u16,
+        Operand::AuxCarry => registers.flags.aux_carry as u16,
+    };
+    return value as u64;
+}
+
+fn eval(node: &Node, processor: &Processor) -> u64 {
+    return match node {
+        Node::Number(value) => *value,
+        Node::Operand(which) => operand(processor, *which),
+        Node::Memory(addr) => processor.peek(eval(addr, processor) as u16) as u64,
+        Node::Not(inner) => (eval(inner, processor) == 0) as u64,
+        Node::Negate(inner) => eval(inner, processor).wrapping_neg(),
+        Node::Binary(op, left, right) => {
+            let left: u64 = eval(left, processor);
+            match op {
+                BinaryOp::Or if left != 0 => 1,
+                BinaryOp::And if left == 0 => 0,
+                BinaryOp::Or | BinaryOp::And => (eval(right, processor) != 0) as u64,
+                _ => {
+                    let right: u64 = eval(right, processor);
+                    match op {
+                        BinaryOp::BitOr => left | right,
+                        BinaryOp::BitXor => left ^ right,
+                        BinaryOp::BitAnd => left & right,
+                        BinaryOp::Eq => (left == right) as u64,
+                        BinaryOp::Ne => (left != right) as u64,
+                        BinaryOp::Lt => (left < right) as u64,
+                        BinaryOp::Le => (left <= right) as u64,
+                        BinaryOp::Gt => (left > right) as u64,
+                        BinaryOp::Ge => (left >= right) as u64,
+                        BinaryOp::Add => left.wrapping_add(right),
+                        BinaryOp::Sub => left.wrapping_sub(right),
+                        BinaryOp::Mul => left.wrapping_mul(right),
+                        BinaryOp::Or | BinaryOp::And => unreachable!(),
+                    }
+                },
+            }
+        },
+    };
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, ExprError> {
+        let mut parser = Parser { tokens: tokenize(text)?, next: 0 };
+        let root: Node = parser.binary(0)?;
+        if let Some(token) = parser.peek() {
+            return error(format!("unexpected '{}'", token));
+        }
+        return Ok(Expr { root });
+    }
+
+    pub fn eval(&self, processor: &Processor) -> u64 {
+        return eval(&self.root, processor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::make_processor;
+
+    #[test]
+    fn test_precedence_and_operands() {
+        let mut processor = make_processor();
+        let mut registers = processor.registers();
+        registers.a = 0x90;
+        registers.set_bc(0x1234);
+        registers.set_hl(0x2000);
+        registers.sp = 0x1ff0;
+        registers.flags.carry = true;
+        processor.set_registers(registers);
+        processor.load_at(0x2000, &[0x7f]);
+
+        let value = |text: &str| Expr::parse(text).unwrap().eval(&processor);
+
+        assert_eq!(value("hl - sp"), 0x10);
+        assert_eq!(value("flags.carry && a > 0x80"), 1);
+        assert_eq!(value("1 + 2 * 3 == 7"), 1);
+        assert_eq!(value("(bc & 0ffh) | [hl] ^ 1"), 0x34 | 0x7e);
+        assert_eq!(value("m + !flags.zero"), 0x80);
+        assert_eq!(value("sp - hl"), 0u64.wrapping_sub(0x10));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Expr::parse("hl -").unwrap_err().to_string(), "expression ends early");
+        assert_eq!(Expr::parse("ix + 1").unwrap_err().to_string(), "unknown name 'ix'");
+        assert_eq!(Expr::parse("(a").unwrap_err().to_string(), "expected ')'");
+        assert_eq!(Expr::parse("a b").unwrap_err().to_string(), "unexpected 'b'");
+        assert_eq!(Expr::parse("a % 2").unwrap_err().to_string(), "unexpected '%'");
+    }
+}
@@ -0,0 +1,281 @@
+//! The machine-readable outputs: the state dump, trace lines, event records
+//! and the stats report, as versioned JSON documents. Each carries
+//! `format_version`; fields may be added within a version, but anything
+//! that would break a reader of an earlier document bumps it. The JSON
+//! Schema for each is kept by hand in `schemas/`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::disasm;
+use crate::processor::{AllocatorUsage, BranchSite, CycleStats, Event, LatencyTracker, Processor, RegisterPair, Registers, TimedEvent,
+                       TraceRecord, UnattachedRead};
+use crate::stats::{PageUsage, UsageMap};
+
+/// The version of the documents this build writes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Document names `schema` knows, as the `schema` subcommand takes them.
+pub const SCHEMAS: [&str; 4] = ["state", "trace", "event", "stats"];
+
+/// The JSON Schema for the document called `name`.
+pub fn schema(name: &str) -> Option<&'static str> {
+    return match name {
+        "state" => Some(include_str!("../schemas/state.schema.json")),
+        "trace" => Some(include_str!("../schemas/trace.schema.json")),
+        "event" => Some(include_str!("../schemas/event.schema.json")),
+        "stats" => Some(include_str!("../schemas/stats.schema.json")),
+        _ => None,
+    };
+}
+
+/// The processor as a run left it, without memory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDump {
+    pub format_version: u32,
+    pub registers: Registers,
+    pub halted: bool,
+    pub interrupts_enabled: bool,
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+impl StateDump {
+    pub fn of(processor: &Processor) -> StateDump {
+        return StateDump {
+            format_version: FORMAT_VERSION,
+            registers: processor.registers(),
+            halted: processor.is_halted(),
+            interrupts_enabled: processor.interrupts_enabled(),
+            instructions: processor.instruction_count(),
+            cycles: processor.cycle_count(),
+        };
+    }
+}
+
+/// One traced instruction, with the registers before it ran.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceLine {
+    pub format_version: u32,
+    pub instructions: u64,
+    pub cycles: u64,
+    pub pc: u16,
+    /// The instruction's own bytes.
+    pub bytes: Vec<u8>,
+    /// As the disassembler writes it, e.g. `call 0010h`.
+    pub text: String,
+    pub registers: Registers,
+}
+
+impl TraceLine {
+    pub fn of(record: &TraceRecord) -> TraceLine {
+        let line = disasm::decode(&record.bytes, record.pc);
+        return TraceLine {
+            format_version: FORMAT_VERSION,
+            instructions: record.instructions,
+            cycles: record.cycles,
+            pc: record.pc,
+            bytes: line.bytes,
+            text: line.text,
+            registers: record.registers,
+        };
+    }
+}
+
+/// `Event` as it is written out, tagged with its `kind`. Kept apart from
+/// `Event` so the processor's enum can change without changing the format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventKind {
+    PortRead { port: u8, value: u8 },
+    PortWrite { port: u8, value: u8 },
+    InterruptRequested { source: u8, vector: u8 },
+    InterruptDelivered { source: u8, vector: u8 },
+    RegionRead { region: String, addr: u16, value: u8 },
+    RegionWrite { region: String, addr: u16, value: u8 },
+    VectorWrite { addr: u16, vector: u8, value: u8 },
+    LoopSkipped { addr: u16, iterations: u64 },
+    SuspectFlagRead { flag: char, producer: Option<u16>, clobbered: Option<u16> },
+    Watchpoint { addr: u16 },
+    InterruptReentered { source: u8, first_pc: u16, second_pc: u16 },
+    UnmodeledAuxCarry { producer: Option<u16> },
+    ExprChanged { name: String, old: u64, new: u64 },
+    GuestLog { severity: u8, message: String },
+    AllocatorOverflow { pair: RegisterPair, pointer: u16, arena_end: u16 },
+    AllocatorCollision { pair: RegisterPair, pointer: u16, sp: u16 },
+    RomWrite { region: String, addr: u16, value: u8 },
+    UnimplementedOpcode { opcode: u8, operands: [u8; 2] },
+}
+
+impl From<&Event> for EventKind {
+    fn from(event: &Event) -> EventKind {
+        return match event.clone() {
+            Event::PortRead { port, value } => EventKind::PortRead { port, value },
+            Event::PortWrite { port, value } => EventKind::PortWrite { port, value },
+            Event::InterruptRequested { source, vector } => EventKind::InterruptRequested { source, vector },
+            Event::InterruptDelivered { source, vector } => EventKind::InterruptDelivered { source, vector },
+            Event::RegionRead { region, addr, value } => EventKind::RegionRead { region, addr, value },
+            Event::RegionWrite { region, addr, value } => EventKind::RegionWrite { region, addr, value },
+            Event::VectorWrite { addr, vector, value } => EventKind::VectorWrite { addr, vector, value },
+            Event::LoopSkipped { addr, iterations } => EventKind::LoopSkipped { addr, iterations },
+            Event::SuspectFlagRead { flag, producer, clobbered } => EventKind::SuspectFlagRead { flag, producer, clobbered },
+            Event::Watchpoint { addr } => EventKind::Watchpoint { addr },
+            Event::InterruptReentered { source, first_pc, second_pc } =>
+                EventKind::InterruptReentered { source, first_pc, second_pc },
+            Event::UnmodeledAuxCarry { producer } => EventKind::UnmodeledAuxCarry { producer },
+            Event::ExprChanged { name, old, new } => EventKind::ExprChanged { name, old, new },
+            Event::GuestLog { severity, message } => EventKind::GuestLog { severity, message },
+            Event::AllocatorOverflow { pair, pointer, arena_end } => EventKind::AllocatorOverflow { pair, pointer, arena_end },
+            Event::AllocatorCollision { pair, pointer, sp } => EventKind::AllocatorCollision { pair, pointer, sp },
+            Event::RomWrite { region, addr, value } => EventKind::RomWrite { region, addr, value },
+            Event::UnimplementedOpcode { opcode, operands } => EventKind::UnimplementedOpcode { opcode, operands },
+        };
+    }
+}
+
+/// One event with when and where it happened.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecord {
+    pub format_version: u32,
+    pub instructions: u64,
+    pub cycles: u64,
+    pub pc: u16,
+    #[serde(flatten)]
+    pub event: EventKind,
+}
+
+impl EventRecord {
+    pub fn of(event: &TimedEvent) -> EventRecord {
+        return EventRecord {
+            format_version: FORMAT_VERSION,
+            instructions: event.instructions,
+            cycles: event.cycles,
+            pc: event.pc,
+            event: EventKind::from(&event.event),
+        };
+    }
+}
+
+/// Interrupt service times for one source, in cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterruptSource {
+    pub source: u8,
+    pub latency: CycleStats,
+    pub handler: CycleStats,
+}
+
+/// What `--stats` reports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatsReport {
+    pub format_version: u32,
+    pub instructions: u64,
+    pub cycles: u64,
+    /// Each 256-byte page, from address 0.
+    pub pages: Vec<PageUsage>,
+    pub interrupts: Vec<InterruptSource>,
+    /// Each allocator the run tracked; missing from earlier documents.
+    #[serde(default)]
+    pub allocators: Vec<AllocatorUsage>,
+    /// Each port read with no device attached, if the run checked; missing
+    /// from earlier documents.
+    #[serde(default)]
+    pub unattached_ports: Vec<UnattachedRead>,
+    /// Each conditional branch that ran, most executed first; missing from
+    /// earlier documents.
+    #[serde(default)]
+    pub branches: Vec<BranchSite>,
+}
+
+impl StatsReport {
+    pub fn of(processor: &Processor, usage: &UsageMap, timing: Option<&LatencyTracker>) -> StatsReport {
+        let interrupts: Vec<InterruptSource> = timing.map_or(Vec::new(), |timing| timing.sources().iter()
+            .map(|(source, timing)| InterruptSource { source: *source, latency: timing.latency, handler: timing.handler })
+            .collect());
+        return StatsReport {
+            format_version: FORMAT_VERSION,
+            instructions: processor.instruction_count(),
+            cycles: processor.cycle_count(),
+            pages: usage.pages.clone(),
+            interrupts,
+            allocators: processor.allocator_usage(),
+            unattached_ports: processor.unattached_reads(),
+            branches: processor.branch_stats(),
+        };
+    }
+}
+
+/// `document` as a single line of JSON.
+pub fn to_json_line<T: Serialize>(document: &T) -> String {
+    return serde_json::to_string(document).expect("output documents always serialize");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::fs;
+
+    use serde::de::DeserializeOwned;
+    use serde_json::Value;
+
+    use crate::processor::{make_processor, AccessMap};
+
+    /// Reads the sample written by format `version` of `name`.
+    fn sample<T: DeserializeOwned>(version: u32, name: &str) -> T {
+        let path: String = format!("tests/formats/v{}/{}.json", version, name);
+        let text: String = fs::read_to_string(&path).unwrap();
+        return serde_json::from_str(&text).unwrap_or_else(|err| panic!("{}: {}", path, err));
+    }
+
+    /// The top-level properties the schema for `name` lists.
+    fn schema_properties(name: &str) -> BTreeSet<String> {
+        let schema: Value = serde_json::from_str(schema(name).unwrap()).unwrap();
+        return schema["properties"].as_object().unwrap().keys().cloned().collect();
+    }
+
+    fn keys(document: &impl Serialize) -> BTreeSet<String> {
+        return serde_json::to_value(document).unwrap().as_object().unwrap().keys().cloned().collect();
+    }
+
+    #[test]
+    fn test_version_1_samples_still_read() {
+        let state: StateDump = sample(1, "state");
+        assert_eq!((state.format_version, state.registers.pc, state.halted), (1, 0x0007, true));
+
+        let trace: TraceLine = sample(1, "trace");
+        assert_eq!((trace.bytes.clone(), trace.text.as_str()), (vec![0xcd, 0x10, 0x00], "call 0010h"));
+
+        let event: EventRecord = sample(1, "event");
+        assert_eq!(event.event, EventKind::PortWrite { port: 1, value: 0x41 });
+
+        let stats: StatsReport = sample(1, "stats");
+        assert_eq!((stats.pages.len(), stats.interrupts[0].handler.max), (256, 45));
+    }
+
+    #[test]
+    fn test_schemas_list_every_field() {
+        let mut processor = make_processor();
+        processor.load_program(&[0xd3, 0x01, 0x76]); // OUT 1; HLT
+        processor.run();
+        let record = TraceRecord { instructions: 0, cycles: 0, pc: 0, bytes: [0xd3, 0x01, 0x76], registers: processor.registers() };
+        let event = TimedEvent { instructions: 0, cycles: 10, pc: 0, event: Event::PortWrite { port: 1, value: 0 } };
+
+        assert_eq!(keys(&StateDump::of(&processor)), schema_properties("state"));
+        assert_eq!(keys(&TraceLine::of(&record)), schema_properties("trace"));
+        assert!(schema_properties("event").is_superset(&keys(&EventRecord::of(&event))));
+        let usage = UsageMap::classify(&AccessMap::new());
+        assert_eq!(keys(&StatsReport::of(&processor, &usage, None)), schema_properties("stats"));
+        for name in SCHEMAS {
+            assert!(schema(name).unwrap().contains(&format!("\"const\": {}", FORMAT_VERSION)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_event_kinds_are_tagged() {
+        let event = TimedEvent { instructions: 12, cycles: 87, pc: 0x0105, event: Event::InterruptDelivered { source: 2, vector: 5 } };
+
+        let line: String = to_json_line(&EventRecord::of(&event));
+
+        assert_eq!(line, r#"{"format_version":1,"instructions":12,"cycles":87,"pc":261,"kind":"interrupt_delivered","source":2,"vector":5}"#);
+        assert_eq!(serde_json::from_str::<EventRecord>(&line).unwrap(), EventRecord::of(&event));
+    }
+}
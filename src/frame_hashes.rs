@@ -0,0 +1,165 @@
+//! Regression tests by picture, without screenshots: `record` runs a Space
+//! Invaders-style machine a frame at a time and hashes each picture with
+//! `video::frame_hash`, and `check_golden` compares the hashes with a list
+//! checked into the repository, one per line. Running the tests with
+//! `BLESS=1` in the environment writes the lists afresh instead.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::clock::ManualClock;
+use crate::invaders::{self, CPU_HZ};
+use crate::processor::{Processor, Throttle};
+use crate::video;
+
+/// Set to anything to have `check_golden` write the golden list rather
+/// than check against it.
+pub const BLESS_VAR: &str = "BLESS";
+
+/// Why hashes did not match their golden list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GoldenError {
+    /// The list could not be read or written.
+    Io { path: String, message: String },
+    /// Line `line`, counting from 1, is not a hash.
+    Parse { line: usize, text: String },
+    /// The first frame whose hash differs, counting from 0. A side that ran
+    /// out of frames has None.
+    Mismatch { frame: usize, expected: Option<u64>, actual: Option<u64> },
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hash = |hash: &Option<u64>| hash.map_or("no frame".to_string(), |hash| format!("{:016x}", hash));
+        return match self {
+            GoldenError::Io { path, message } => write!(f, "{}: {}", path, message),
+            GoldenError::Parse { line, text } => write!(f, "line {}: not a frame hash: {}", line, text),
+            GoldenError::Mismatch { frame, expected, actual } => write!(f,
+                "frame {}: expected {}, got {} (rerun with {}=1 to accept)", frame, hash(expected), hash(actual), BLESS_VAR),
+        };
+    }
+}
+
+/// Runs `frames` frames with `invaders::run_frame`, raising the screen
+/// interrupts, and hashes the picture after each. Time comes from a
+/// `ManualClock`, so pacing costs nothing and the run is the same every
+/// time.
+pub fn record(processor: &mut Processor, frames: u64) -> Vec<u64> {
+    let clock = ManualClock::new();
+    let mut throttle = Throttle::new(CPU_HZ);
+    return (0..frames).map(|frame| {
+        invaders::run_frame(processor, frame, &mut throttle, &clock);
+        return video::frame_hash(&video::capture(|addr| processor.peek(addr)));
+    }).collect();
+}
+
+/// `hashes` as a golden list: one 16-digit hex hash per line.
+pub fn to_golden(hashes: &[u64]) -> String {
+    return hashes.iter().map(|hash| format!("{:016x}\n", hash)).collect();
+}
+
+/// The hashes in a golden list. Blank lines and lines starting with `#`
+/// are skipped.
+pub fn parse_golden(text: &str) -> Result<Vec<u64>, GoldenError> {
+    return text.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(index, line)| u64::from_str_radix(line.trim(), 16)
+            .map_err(|_| GoldenError::Parse { line: index + 1, text: line.to_string() }))
+        .collect();
+}
+
+/// The first frame where `actual` differs from `expected`, if any.
+pub fn compare(expected: &[u64], actual: &[u64]) -> Result<(), GoldenError> {
+    let frames: usize = expected.len().max(actual.len());
+    return match (0..frames).find(|frame| expected.get(*frame) != actual.get(*frame)) {
+        Some(frame) => Err(GoldenError::Mismatch {
+            frame,
+            expected: expected.get(frame).copied(),
+            actual: actual.get(frame).copied(),
+        }),
+        None => Ok(()),
+    };
+}
+
+/// Checks `hashes` against the golden list at `path`, or with `BLESS_VAR`
+/// set, writes them there.
+pub fn check_golden(path: &Path, hashes: &[u64]) -> Result<(), GoldenError> {
+    let io_error = |err: std::io::Error| GoldenError::Io { path: path.display().to_string(), message: err.to_string() };
+    if env::var_os(BLESS_VAR).is_some() {
+        return fs::write(path, to_golden(hashes)).map_err(io_error);
+    }
+    let text: String = fs::read_to_string(path).map_err(io_error)?;
+    return compare(&parse_golden(&text)?, hashes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::ProcessorBuilder;
+
+    /// Draws a short bar into video memory at every vertical blank,
+    /// continuing from where the last one stopped, and halts in between.
+    fn vram_writer() -> Processor {
+        let mut processor: Processor = ProcessorBuilder::new().build();
+        processor.load_at(0x0000, &[0xc3, 0x40, 0x00]); // JMP 0040h
+        processor.load_at(0x0008, &[0xfb, 0xc9]); // RST 1: EI; RET
+        processor.load_at(0x0010, &[
+            0x36, 0xff,       // 0010 MVI M,0FFh
+            0x23,             // 0012 INX H
+            0x36, 0x81,       // 0013 MVI M,81h
+            0x23,             // 0015 INX H
+            0xfb,             // 0016 EI
+            0xc9,             // 0017 RET
+        ]);
+        processor.load_at(0x0040, &[
+            0x31, 0x00, 0x24, // 0040 LXI SP,2400h
+            0x21, 0x00, 0x24, // 0043 LXI H,2400h
+            0xfb,             // 0046 EI
+            0x76,             // 0047 HLT
+            0xc3, 0x46, 0x00, // 0048 JMP 0046h
+        ]);
+        return processor;
+    }
+
+    #[test]
+    fn test_vram_writer_matches_golden() {
+        let hashes: Vec<u64> = record(&mut vram_writer(), 8);
+
+        assert_eq!(hashes.len(), 8);
+        if let Err(err) = check_golden(Path::new("tests/golden/vram_writer.hashes"), &hashes) {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn test_recording_is_repeatable() {
+        let first: Vec<u64> = record(&mut vram_writer(), 4);
+
+        assert_eq!(record(&mut vram_writer(), 4), first);
+        let mut distinct: Vec<u64> = first.clone();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 4);
+    }
+
+    #[test]
+    fn test_golden_lists_round_trip() {
+        let hashes: Vec<u64> = vec![0, 0x0123_4567_89ab_cdef, u64::MAX];
+
+        let text: String = to_golden(&hashes);
+
+        assert_eq!(text, "0000000000000000\n0123456789abcdef\nffffffffffffffff\n");
+        assert_eq!(parse_golden(&format!("# three frames\n\n{}", text)), Ok(hashes));
+        assert_eq!(parse_golden("00\nframe 2\n"), Err(GoldenError::Parse { line: 2, text: "frame 2".to_string() }));
+    }
+
+    #[test]
+    fn test_compare_reports_the_first_difference() {
+        assert_eq!(compare(&[1, 2, 3], &[1, 2, 3]), Ok(()));
+        assert_eq!(compare(&[1, 2, 3], &[1, 5, 6]), Err(GoldenError::Mismatch { frame: 1, expected: Some(2), actual: Some(5) }));
+        assert_eq!(compare(&[1, 2], &[1, 2, 3]), Err(GoldenError::Mismatch { frame: 2, expected: None, actual: Some(3) }));
+        assert_eq!(compare(&[1, 2, 3], &[1]).unwrap_err().to_string(),
+                   "frame 1: expected 0000000000000002, got no frame (rerun with BLESS=1 to accept)");
+    }
+}
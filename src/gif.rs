@@ -0,0 +1,277 @@
+//! A small animated GIF encoder for 1-bit images: black and white, every
+//! frame the full size of the screen.
+
+use std::collections::HashMap;
+
+/// LZW minimum code size. Two colors need only one bit, but GIF requires at
+/// least two.
+const MIN_CODE_SIZE: u8 = 2;
+
+const CLEAR_CODE: u16 = 1 << MIN_CODE_SIZE;
+const END_CODE: u16 = CLEAR_CODE + 1;
+
+/// GIF codes are at most 12 bits.
+const MAX_CODES: u16 = 4096;
+
+/// Black for unlit pixels, white for lit ones.
+const PALETTE: [u8; 6] = [0x00, 0x00, 0x00, 0xff, 0xff, 0xff];
+
+/// Builds an animated GIF frame by frame. Pixels are row-major, `true` for
+/// lit.
+#[derive(Debug, Clone)]
+pub struct GifEncoder {
+    width: u16,
+    height: u16,
+    bytes: Vec<u8>,
+    frames: usize,
+}
+
+impl GifEncoder {
+    /// An animation that loops forever.
+    pub fn new(width: u16, height: u16) -> GifEncoder {
+        let mut bytes: Vec<u8> = b"GIF89a".to_vec();
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        // Global color table of 2 entries, 1 bit per primary color.
+        bytes.extend([0x80, 0x00, 0x00]);
+        bytes.extend(PALETTE);
+        // NETSCAPE2.0 application extension: loop forever.
+        bytes.extend([0x21, 0xff, 0x0b]);
+        bytes.extend(b"NETSCAPE2.0");
+        bytes.extend([0x03, 0x01, 0x00, 0x00, 0x00]);
+        return GifEncoder { width, height, bytes, frames: 0 };
+    }
+
+    /// Adds a frame shown for `delay` hundredths of a second.
+    pub fn add_frame(&mut self, pixels: &[bool], delay: u16) {
+        assert_eq!(pixels.len(), self.width as usize * self.height as usize, "frame size does not match the screen");
+        self.bytes.extend([0x21, 0xf9, 0x04, 0x00]);
+        self.bytes.extend(delay.to_le_bytes());
+        self.bytes.extend([0x00, 0x00]);
+
+        self.bytes.extend([0x2c, 0x00, 0x00, 0x00, 0x00]);
+        self.bytes.extend(self.width.to_le_bytes());
+        self.bytes.extend(self.height.to_le_bytes());
+        self.bytes.push(0x00);
+
+        self.bytes.push(MIN_CODE_SIZE);
+        let data: Vec<u8> = compress(pixels);
+        for block in data.chunks(255) {
+            self.bytes.push(block.len() as u8);
+            self.bytes.extend(block);
+        }
+        self.bytes.push(0x00);
+        self.frames += 1;
+    }
+
+    pub fn frame_count(&self) -> usize {
+        return self.frames;
+    }
+
+    /// The finished file.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(0x3b);
+        return self.bytes;
+    }
+}
+
+/// Packs variable-width codes least significant bit first.
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        return self.bytes;
+    }
+}
+
+/// LZW-compresses one frame's color indices.
+fn compress(pixels: &[bool]) -> Vec<u8> {
+    let mut out = BitWriter::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size: u8 = MIN_CODE_SIZE + 1;
+    let mut next: u16 = END_CODE + 1;
+    out.write(CLEAR_CODE, size);
+
+    let mut indices = pixels.iter().map(|lit| *lit as u8);
+    let Some(first) = indices.next() else {
+        out.write(END_CODE, size);
+        return out.finish();
+    };
+    let mut prefix: u16 = first as u16;
+    for index in indices {
+        if let Some(code) = table.get(&(prefix, index)) {
+            prefix = *code;
+            continue;
+        }
+        out.write(prefix, size);
+        if next < MAX_CODES {
+            // The decoder adds this entry one code later, so it widens its
+            // codes when the table fills up just as this does.
+            if next == 1 << size {
+                size += 1;
+            }
+            table.insert((prefix, index), next);
+            next += 1;
+        } else {
+            out.write(CLEAR_CODE, size);
+            table.clear();
+            size = MIN_CODE_SIZE + 1;
+            next = END_CODE + 1;
+        }
+        prefix = index as u16;
+    }
+    out.write(prefix, size);
+    if next < MAX_CODES && next == 1 << size {
+        size += 1;
+    }
+    out.write(END_CODE, size);
+    return out.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A plain LZW decoder, written from the GIF spec independently of the
+    /// encoder.
+    fn decompress(data: &[u8], pixels: usize) -> Vec<u8> {
+        let mut bits: u32 = 0;
+        let mut buffer: u32 = 0;
+        let mut input = data.iter();
+        let mut size: u32 = MIN_CODE_SIZE as u32 + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..CLEAR_CODE + 2).map(|code| vec![code as u8]).collect();
+        };
+        reset(&mut table);
+        let mut previous: Option<Vec<u8>> = None;
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            while bits < size {
+                buffer |= (*input.next().expect("ran out of data before the end code") as u32) << bits;
+                bits += 8;
+            }
+            let code = (buffer & ((1 << size) - 1)) as u16;
+            buffer >>= size;
+            bits -= size;
+            if code == CLEAR_CODE {
+                reset(&mut table);
+                size = MIN_CODE_SIZE as u32 + 1;
+                previous = None;
+                continue;
+            }
+            if code == END_CODE {
+                break;
+            }
+            let entry: Vec<u8> = match (&previous, table.get(code as usize)) {
+                (_, Some(entry)) => entry.clone(),
+                (Some(previous), None) => [previous.clone(), vec![previous[0]]].concat(),
+                (None, None) => panic!("code {} before any entry", code),
+            };
+            if let Some(previous) = previous {
+                table.push([previous, vec![entry[0]]].concat());
+                if table.len() == 1 << size && size < 12 {
+                    size += 1;
+                }
+            }
+            out.extend(&entry);
+            previous = Some(entry);
+        }
+        assert_eq!(out.len(), pixels);
+        return out;
+    }
+
+    /// FNV-1a, so the golden value does not depend on any decoder.
+    fn fnv1a(bytes: &[u8]) -> u64 {
+        return bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3));
+    }
+
+    fn checkerboard(width: usize, height: usize, phase: usize) -> Vec<bool> {
+        return (0..width * height).map(|i| (i % width / 4 + i / width / 4 + phase) % 2 == 1).collect();
+    }
+
+    #[test]
+    fn test_header_and_screen_descriptor() {
+        let gif = GifEncoder::new(224, 256).finish();
+
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(&gif[6..10], &[224, 0, 0, 1]);
+        assert_eq!(&gif[10..13], &[0x80, 0x00, 0x00]);
+        assert_eq!(&gif[13..19], &PALETTE);
+        assert_eq!(&gif[19..22], &[0x21, 0xff, 0x0b]);
+        assert_eq!(gif.last(), Some(&0x3b));
+    }
+
+    #[test]
+    fn test_frame_delays_and_image_data() {
+        let mut encoder = GifEncoder::new(16, 8);
+        encoder.add_frame(&checkerboard(16, 8, 0), 5);
+        encoder.add_frame(&checkerboard(16, 8, 1), 300);
+        let gif = encoder.finish();
+
+        let controls: Vec<usize> = (0..gif.len() - 3).filter(|i| gif[*i..*i + 3] == [0x21, 0xf9, 0x04]).collect();
+        assert_eq!(controls.len(), 2);
+        assert_eq!(&gif[controls[0] + 4..controls[0] + 6], &[5, 0]);
+        assert_eq!(&gif[controls[1] + 4..controls[1] + 6], &[0x2c, 0x01]);
+
+        for (control, phase) in controls.iter().zip(0..) {
+            let descriptor = control + 8;
+            assert_eq!(&gif[descriptor..descriptor + 10], &[0x2c, 0, 0, 0, 0, 16, 0, 8, 0, 0]);
+            assert_eq!(gif[descriptor + 10], MIN_CODE_SIZE);
+            let mut data: Vec<u8> = Vec::new();
+            let mut block = descriptor + 11;
+            while gif[block] != 0 {
+                data.extend(&gif[block + 1..block + 1 + gif[block] as usize]);
+                block += 1 + gif[block] as usize;
+            }
+            let expected: Vec<u8> = checkerboard(16, 8, phase).iter().map(|lit| *lit as u8).collect();
+            assert_eq!(decompress(&data, 128), expected);
+        }
+    }
+
+    #[test]
+    fn test_large_frames_round_trip() {
+        // Noise fills the code table, forcing clear codes mid-frame.
+        let mut state: u32 = 1;
+        let noise: Vec<bool> = (0..224 * 256).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            return state & 1 == 1;
+        }).collect();
+        for pixels in [noise, vec![false; 224 * 256], checkerboard(224, 256, 0)] {
+            let data = compress(&pixels);
+            let expected: Vec<u8> = pixels.iter().map(|lit| *lit as u8).collect();
+            assert_eq!(decompress(&data, pixels.len()), expected);
+        }
+    }
+
+    #[test]
+    fn test_golden_animation() {
+        let mut encoder = GifEncoder::new(16, 8);
+        encoder.add_frame(&checkerboard(16, 8, 0), 10);
+        encoder.add_frame(&checkerboard(16, 8, 1), 10);
+        assert_eq!(encoder.frame_count(), 2);
+        let gif = encoder.finish();
+
+        assert_eq!(fnv1a(&gif), 0x5d75f9c190e4e417);
+    }
+}
@@ -0,0 +1,340 @@
+//! 8080 instructions as values, for programs generated in Rust rather than
+//! assembled from text. `Instruction::decode` and `Instruction::encode` are
+//! inverses over every documented opcode.
+
+use crate::word::word;
+
+/// An 8-bit register, or `M`, the byte HL points at. Declared in the order
+/// of their 3-bit codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reg {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    M,
+    A,
+}
+
+/// A register pair as LXI, INX, DCX, DAD, LDAX and STAX name it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegPair {
+    B,
+    D,
+    H,
+    Sp,
+}
+
+/// A register pair as PUSH and POP name it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StackPair {
+    B,
+    D,
+    H,
+    Psw,
+}
+
+/// What a conditional jump, call or return tests, in condition code order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Condition {
+    Nz,
+    Z,
+    Nc,
+    C,
+    Po,
+    Pe,
+    P,
+    M,
+}
+
+/// One documented 8080 instruction with its operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    Nop,
+    Lxi(RegPair, u16),
+    /// Only `RegPair::B` and `RegPair::D` can be encoded.
+    Stax(RegPair),
+    Inx(RegPair),
+    Inr(Reg),
+    Dcr(Reg),
+    Mvi(Reg, u8),
+    Rlc,
+    Rrc,
+    Ral,
+    Rar,
+    Dad(RegPair),
+    /// Only `RegPair::B` and `RegPair::D` can be encoded.
+    Ldax(RegPair),
+    Dcx(RegPair),
+    Shld(u16),
+    Lhld(u16),
+    Daa,
+    Cma,
+    Sta(u16),
+    Lda(u16),
+    Stc,
+    Cmc,
+    /// Destination first. `Mov(M, M)` is not an instruction: its opcode is
+    /// HLT.
+    Mov(Reg, Reg),
+    Hlt,
+    Add(Reg),
+    Adc(Reg),
+    Sub(Reg),
+    Sbb(Reg),
+    Ana(Reg),
+    Xra(Reg),
+    Ora(Reg),
+    Cmp(Reg),
+    Adi(u8),
+    Aci(u8),
+    Sui(u8),
+    Sbi(u8),
+    Ani(u8),
+    Xri(u8),
+    Ori(u8),
+    Cpi(u8),
+    Ret,
+    Rcond(Condition),
+    Jmp(u16),
+    Jcond(Condition, u16),
+    Call(u16),
+    Ccond(Condition, u16),
+    Push(StackPair),
+    Pop(StackPair),
+    /// The vector, 0 to 7.
+    Rst(u8),
+    Out(u8),
+    In(u8),
+    Xthl,
+    Pchl,
+    Xchg,
+    Sphl,
+    Di,
+    Ei,
+}
+
+const REGS: [Reg; 8] = [Reg::B, Reg::C, Reg::D, Reg::E, Reg::H, Reg::L, Reg::M, Reg::A];
+const REG_PAIRS: [RegPair; 4] = [RegPair::B, RegPair::D, RegPair::H, RegPair::Sp];
+const STACK_PAIRS: [StackPair; 4] = [StackPair::B, StackPair::D, StackPair::H, StackPair::Psw];
+const CONDITIONS: [Condition; 8] =
+    [Condition::Nz, Condition::Z, Condition::Nc, Condition::C, Condition::Po, Condition::Pe, Condition::P, Condition::M];
+
+/// ALU operations on a register and on an immediate, in opcode order.
+const ALU_REG: [fn(Reg) -> Instruction; 8] = [
+    Instruction::Add, Instruction::Adc, Instruction::Sub, Instruction::Sbb,
+    Instruction::Ana, Instruction::Xra, Instruction::Ora, Instruction::Cmp,
+];
+const ALU_IMM: [fn(u8) -> Instruction; 8] = [
+    Instruction::Adi, Instruction::Aci, Instruction::Sui, Instruction::Sbi,
+    Instruction::Ani, Instruction::Xri, Instruction::Ori, Instruction::Cpi,
+];
+
+/// Instructions with no operands, by opcode.
+const IMPLIED: [(u8, Instruction); 17] = [
+    (0x00, Instruction::Nop), (0x07, Instruction::Rlc), (0x0f, Instruction::Rrc), (0x17, Instruction::Ral),
+    (0x1f, Instruction::Rar), (0x27, Instruction::Daa), (0x2f, Instruction::Cma), (0x37, Instruction::Stc),
+    (0x3f, Instruction::Cmc), (0x76, Instruction::Hlt), (0xc9, Instruction::Ret), (0xe3, Instruction::Xthl),
+    (0xe9, Instruction::Pchl), (0xeb, Instruction::Xchg), (0xf9, Instruction::Sphl), (0xf3, Instruction::Di),
+    (0xfb, Instruction::Ei),
+];
+
+impl Reg {
+    fn code(self) -> u8 {
+        return self as u8;
+    }
+}
+
+impl RegPair {
+    fn code(self) -> u8 {
+        return self as u8;
+    }
+}
+
+impl StackPair {
+    fn code(self) -> u8 {
+        return self as u8;
+    }
+}
+
+impl Condition {
+    fn code(self) -> u8 {
+        return self as u8;
+    }
+}
+
+fn with_word(opcode: u8, value: u16) -> Vec<u8> {
+    return vec![opcode, value as u8, (value >> 8) as u8];
+}
+
+impl Instruction {
+    /// Decodes the instruction at the start of `bytes`, or `None` for an
+    /// undocumented opcode or one cut off by the end of `bytes`.
+    pub fn decode(bytes: &[u8]) -> Option<Instruction> {
+        let opcode: u8 = *bytes.first()?;
+        let reg = |shift: u8| REGS[(opcode >> shift & 0b111) as usize];
+        let pair: RegPair = REG_PAIRS[(opcode >> 4 & 0b11) as usize];
+        let condition: Condition = CONDITIONS[(opcode >> 3 & 0b111) as usize];
+        let byte = || bytes.get(1).copied();
+        let addr = || Some(word(*bytes.get(2)?, *bytes.get(1)?));
+
+        if let Some((_, instruction)) = IMPLIED.iter().find(|(code, _)| *code == opcode) {
+            return Some(*instruction);
+        }
+        return Some(match opcode {
+            0x40..=0x7f => Instruction::Mov(reg(3), reg(0)),
+            0x80..=0xbf => ALU_REG[(opcode >> 3 & 0b111) as usize](reg(0)),
+            0x22 => Instruction::Shld(addr()?),
+            0x2a => Instruction::Lhld(addr()?),
+            0x32 => Instruction::Sta(addr()?),
+            0x3a => Instruction::Lda(addr()?),
+            0xc3 => Instruction::Jmp(addr()?),
+            0xcd => Instruction::Call(addr()?),
+            0xd3 => Instruction::Out(byte()?),
+            0xdb => Instruction::In(byte()?),
+            0x02 | 0x12 => Instruction::Stax(pair),
+            0x0a | 0x1a => Instruction::Ldax(pair),
+            _ if opcode & 0xcf == 0x01 => Instruction::Lxi(pair, addr()?),
+            _ if opcode & 0xcf == 0x03 => Instruction::Inx(pair),
+            _ if opcode & 0xcf == 0x09 => Instruction::Dad(pair),
+            _ if opcode & 0xcf == 0x0b => Instruction::Dcx(pair),
+            _ if opcode & 0xc7 == 0x04 => Instruction::Inr(reg(3)),
+            _ if opcode & 0xc7 == 0x05 => Instruction::Dcr(reg(3)),
+            _ if opcode & 0xc7 == 0x06 => Instruction::Mvi(reg(3), byte()?),
+            _ if opcode & 0xc7 == 0xc0 => Instruction::Rcond(condition),
+            _ if opcode & 0xc7 == 0xc2 => Instruction::Jcond(condition, addr()?),
+            _ if opcode & 0xc7 == 0xc4 => Instruction::Ccond(condition, addr()?),
+            _ if opcode & 0xc7 == 0xc6 => ALU_IMM[(opcode >> 3 & 0b111) as usize](byte()?),
+            _ if opcode & 0xc7 == 0xc7 => Instruction::Rst(opcode >> 3 & 0b111),
+            _ if opcode & 0xcf == 0xc1 => Instruction::Pop(STACK_PAIRS[(opcode >> 4 & 0b11) as usize]),
+            _ if opcode & 0xcf == 0xc5 => Instruction::Push(STACK_PAIRS[(opcode >> 4 & 0b11) as usize]),
+            _ => return None,
+        });
+    }
+
+    /// The instruction's bytes, or what is wrong with an operand that has
+    /// no encoding.
+    pub fn encode(&self) -> Result<Vec<u8>, &'static str> {
+        let alu = |index: usize, reg: Reg| vec![0x80 | (index as u8) << 3 | reg.code()];
+        let alu_imm = |index: usize, value: u8| vec![0xc6 | (index as u8) << 3, value];
+        return Ok(match *self {
+            Instruction::Lxi(pair, value) => with_word(0x01 | pair.code() << 4, value),
+            Instruction::Stax(pair @ (RegPair::B | RegPair::D)) => vec![0x02 | pair.code() << 4],
+            Instruction::Ldax(pair @ (RegPair::B | RegPair::D)) => vec![0x0a | pair.code() << 4],
+            Instruction::Stax(_) | Instruction::Ldax(_) => return Err("ldax and stax only take b or d"),
+            Instruction::Inx(pair) => vec![0x03 | pair.code() << 4],
+            Instruction::Dad(pair) => vec![0x09 | pair.code() << 4],
+            Instruction::Dcx(pair) => vec![0x0b | pair.code() << 4],
+            Instruction::Inr(reg) => vec![0x04 | reg.code() << 3],
+            Instruction::Dcr(reg) => vec![0x05 | reg.code() << 3],
+            Instruction::Mvi(reg, value) => vec![0x06 | reg.code() << 3, value],
+            Instruction::Shld(addr) => with_word(0x22, addr),
+            Instruction::Lhld(addr) => with_word(0x2a, addr),
+            Instruction::Sta(addr) => with_word(0x32, addr),
+            Instruction::Lda(addr) => with_word(0x3a, addr),
+            Instruction::Mov(Reg::M, Reg::M) => return Err("mov m, m has no encoding"),
+            Instruction::Mov(dest, source) => vec![0x40 | dest.code() << 3 | source.code()],
+            Instruction::Add(reg) => alu(0, reg),
+            Instruction::Adc(reg) => alu(1, reg),
+            Instruction::Sub(reg) => alu(2, reg),
+            Instruction::Sbb(reg) => alu(3, reg),
+            Instruction::Ana(reg) => alu(4, reg),
+            Instruction::Xra(reg) => alu(5, reg),
+            Instruction::Ora(reg) => alu(6, reg),
+            Instruction::Cmp(reg) => alu(7, reg),
+            Instruction::Adi(value) => alu_imm(0, value),
+            Instruction::Aci(value) => alu_imm(1, value),
+            Instruction::Sui(value) => alu_imm(2, value),
+            Instruction::Sbi(value) => alu_imm(3, value),
+            Instruction::Ani(value) => alu_imm(4, value),
+            Instruction::Xri(value) => alu_imm(5, value),
+            Instruction::Ori(value) => alu_imm(6, value),
+            Instruction::Cpi(value) => alu_imm(7, value),
+            Instruction::Rcond(condition) => vec![0xc0 | condition.code() << 3],
+            Instruction::Jmp(addr) => with_word(0xc3, addr),
+            Instruction::Jcond(condition, addr) => with_word(0xc2 | condition.code() << 3, addr),
+            Instruction::Call(addr) => with_word(0xcd, addr),
+            Instruction::Ccond(condition, addr) => with_word(0xc4 | condition.code() << 3, addr),
+            Instruction::Push(pair) => vec![0xc5 | pair.code() << 4],
+            Instruction::Pop(pair) => vec![0xc1 | pair.code() << 4],
+            Instruction::Rst(vector @ 0..=7) => vec![0xc7 | vector << 3],
+            Instruction::Rst(_) => return Err("rst vector must be 0 to 7"),
+            Instruction::Out(port) => vec![0xd3, port],
+            Instruction::In(port) => vec![0xdb, port],
+            implied => match IMPLIED.iter().find(|(_, instruction)| *instruction == implied) {
+                Some((opcode, _)) => vec![*opcode],
+                None => unreachable!("{:?} is missing from IMPLIED", implied),
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::MNEMONICS;
+
+    #[test]
+    fn test_round_trip_every_opcode() {
+        let mut documented: usize = 0;
+        for opcode in 0..=255u8 {
+            let bytes: [u8; 3] = [opcode, 0x34, 0x12];
+            let Some(instruction) = Instruction::decode(&bytes) else {
+                assert!(MNEMONICS[opcode as usize].is_empty(), "{:02x} did not decode", opcode);
+                continue;
+            };
+            documented += 1;
+            let encoded: Vec<u8> = instruction.encode().unwrap();
+            assert_eq!(encoded, bytes[..encoded.len()], "{:?}", instruction);
+            assert_eq!(Instruction::decode(&encoded), Some(instruction));
+        }
+        assert_eq!(documented, 244);
+    }
+
+    #[test]
+    fn test_round_trip_every_operand_combination() {
+        let mut instructions: Vec<Instruction> = Vec::new();
+        for reg in REGS {
+            instructions.extend([Instruction::Inr(reg), Instruction::Dcr(reg), Instruction::Mvi(reg, 0xff)]);
+            instructions.extend(ALU_REG.iter().map(|op| op(reg)));
+            instructions.extend(REGS.iter().filter(|source| (reg, **source) != (Reg::M, Reg::M))
+                .map(|source| Instruction::Mov(reg, *source)));
+        }
+        for pair in REG_PAIRS {
+            instructions.extend([Instruction::Lxi(pair, 0xbeef), Instruction::Inx(pair), Instruction::Dcx(pair),
+                                 Instruction::Dad(pair)]);
+        }
+        for pair in STACK_PAIRS {
+            instructions.extend([Instruction::Push(pair), Instruction::Pop(pair)]);
+        }
+        for condition in CONDITIONS {
+            instructions.extend([Instruction::Rcond(condition), Instruction::Jcond(condition, 0x0100),
+                                 Instruction::Ccond(condition, 0xfffe)]);
+        }
+        instructions.extend((0..8).map(Instruction::Rst));
+        instructions.extend(ALU_IMM.iter().map(|op| op(0x80)));
+        instructions.extend(IMPLIED.iter().map(|(_, instruction)| *instruction));
+
+        for instruction in instructions {
+            assert_eq!(Instruction::decode(&instruction.encode().unwrap()), Some(instruction));
+        }
+    }
+
+    #[test]
+    fn test_operands_without_an_encoding() {
+        assert!(Instruction::Rst(8).encode().is_err());
+        assert!(Instruction::Mov(Reg::M, Reg::M).encode().is_err());
+        assert!(Instruction::Ldax(RegPair::H).encode().is_err());
+        assert!(Instruction::Stax(RegPair::Sp).encode().is_err());
+        assert_eq!(Instruction::Stax(RegPair::D).encode(), Ok(vec![0x12]));
+    }
+
+    #[test]
+    fn test_truncated_and_undocumented() {
+        assert_eq!(Instruction::decode(&[0xc3, 0x00]), None);
+        assert_eq!(Instruction::decode(&[0x08]), None);
+        assert_eq!(Instruction::decode(&[]), None);
+    }
+}
@@ -0,0 +1,509 @@
+//! The Space Invaders cabinet around the 8080: the input ports, the shift
+//! register the game uses to draw sprites at any bit offset, the sound
+//! latches, and the two screen interrupts. Nothing here needs a window, so
+//! frontends only have to draw `video::capture` and move keys and sounds
+//! in and out.
+
+use std::sync::{Arc, Mutex};
+
+use crate::clock::Clock;
+use crate::dip::{self, DipChoice, DipOption, OptionError};
+use crate::processor::{BlockResult, IoContext, IoDevice, Processor, Throttle};
+use crate::video::CYCLES_PER_FRAME;
+
+pub const CPU_HZ: u64 = 2_000_000;
+
+/// Ports the cabinet answers. Reads: 1 and 2 are the controls, 3 the shift
+/// result. Writes: 2 is the shift amount, 3 and 5 the sound latches, 4 the
+/// shift data and 6 the watchdog, which is ignored.
+pub const INPUT_PORT_1: u8 = 1;
+pub const INPUT_PORT_2: u8 = 2;
+pub const SHIFT_AMOUNT_PORT: u8 = 2;
+pub const SHIFT_RESULT_PORT: u8 = 3;
+pub const SOUND_PORT_1: u8 = 3;
+pub const SHIFT_DATA_PORT: u8 = 4;
+pub const SOUND_PORT_2: u8 = 5;
+pub const WATCHDOG_PORT: u8 = 6;
+
+/// Interrupt source the screen raises its RSTs as.
+pub const SCREEN_INTERRUPT_SOURCE: u8 = 0;
+/// RST 1, raised when the beam reaches the middle of the screen.
+pub const MID_SCREEN_RST: u8 = 0xcf;
+/// RST 2, raised at the start of vertical blank.
+pub const VBLANK_RST: u8 = 0xd7;
+
+pub const CYCLES_PER_HALF_FRAME: u64 = CYCLES_PER_FRAME / 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Control {
+    Coin,
+    P1Start,
+    P2Start,
+    P1Left,
+    P1Right,
+    P1Fire,
+    P2Left,
+    P2Right,
+    P2Fire,
+    Tilt,
+}
+
+/// Keys by the names SDL gives them, and the controls they work.
+pub const KEY_MAP: &[(&str, Control)] = &[
+    ("C", Control::Coin),
+    ("1", Control::P1Start),
+    ("2", Control::P2Start),
+    ("Left", Control::P1Left),
+    ("Right", Control::P1Right),
+    ("Space", Control::P1Fire),
+    ("A", Control::P2Left),
+    ("D", Control::P2Right),
+    ("W", Control::P2Fire),
+    ("T", Control::Tilt),
+];
+
+/// The control the key named `name` works, if any.
+pub fn control_for_key(name: &str) -> Option<Control> {
+    return KEY_MAP.iter().find(|(key, _)| *key == name).map(|(_, control)| *control);
+}
+
+/// The cabinet's DIP switches, all on port 2.
+pub const OPTIONS: &[DipOption] = &[
+    DipOption {
+        name: "lives",
+        port: INPUT_PORT_2,
+        mask: 0b0000_0011,
+        choices: &[
+            DipChoice { value: "3", bits: 0b00 },
+            DipChoice { value: "4", bits: 0b01 },
+            DipChoice { value: "5", bits: 0b10 },
+            DipChoice { value: "6", bits: 0b11 },
+        ],
+        default: "3",
+    },
+    DipOption {
+        name: "bonus_at",
+        port: INPUT_PORT_2,
+        mask: 0b0000_1000,
+        choices: &[DipChoice { value: "1000", bits: 0 }, DipChoice { value: "1500", bits: 0b0000_1000 }],
+        default: "1500",
+    },
+    DipOption {
+        name: "coin_info",
+        port: INPUT_PORT_2,
+        mask: 0b1000_0000,
+        choices: &[DipChoice { value: "on", bits: 0 }, DipChoice { value: "off", bits: 0b1000_0000 }],
+        default: "on",
+    },
+];
+
+/// Which controls are held, and the DIP switches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inputs {
+    pub coin: bool,
+    pub p1_start: bool,
+    pub p2_start: bool,
+    pub p1_left: bool,
+    pub p1_right: bool,
+    pub p1_fire: bool,
+    pub p2_left: bool,
+    pub p2_right: bool,
+    pub p2_fire: bool,
+    pub tilt: bool,
+    /// Port 2's DIP switch bits, as `OPTIONS` describes them.
+    pub switches: u8,
+}
+
+impl Default for Inputs {
+    fn default() -> Inputs {
+        return Inputs {
+            coin: false,
+            p1_start: false,
+            p2_start: false,
+            p1_left: false,
+            p1_right: false,
+            p1_fire: false,
+            p2_left: false,
+            p2_right: false,
+            p2_fire: false,
+            tilt: false,
+            switches: dip::defaults(OPTIONS, INPUT_PORT_2),
+        };
+    }
+}
+
+impl Inputs {
+    pub fn set(&mut self, control: Control, pressed: bool) {
+        let held: &mut bool = match control {
+            Control::Coin => &mut self.coin,
+            Control::P1Start => &mut self.p1_start,
+            Control::P2Start => &mut self.p2_start,
+            Control::P1Left => &mut self.p1_left,
+            Control::P1Right => &mut self.p1_right,
+            Control::P1Fire => &mut self.p1_fire,
+            Control::P2Left => &mut self.p2_left,
+            Control::P2Right => &mut self.p2_right,
+            Control::P2Fire => &mut self.p2_fire,
+            Control::Tilt => &mut self.tilt,
+        };
+        *held = pressed;
+    }
+
+    /// Bit 3 is always set.
+    pub fn port_1(&self) -> u8 {
+        return self.coin as u8 | (self.p2_start as u8) << 1 | (self.p1_start as u8) << 2 | 0b1000
+            | (self.p1_fire as u8) << 4 | (self.p1_left as u8) << 5 | (self.p1_right as u8) << 6;
+    }
+
+    pub fn port_2(&self) -> u8 {
+        return self.switches | (self.tilt as u8) << 2
+            | (self.p2_fire as u8) << 4 | (self.p2_left as u8) << 5 | (self.p2_right as u8) << 6;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sound {
+    Ufo,
+    Shot,
+    PlayerDies,
+    InvaderDies,
+    ExtraLife,
+    Fleet1,
+    Fleet2,
+    Fleet3,
+    Fleet4,
+    UfoHit,
+}
+
+/// The latch and bit that plays each sound.
+const SOUND_BITS: [(u8, u8, Sound); 10] = [
+    (SOUND_PORT_1, 0, Sound::Ufo),
+    (SOUND_PORT_1, 1, Sound::Shot),
+    (SOUND_PORT_1, 2, Sound::PlayerDies),
+    (SOUND_PORT_1, 3, Sound::InvaderDies),
+    (SOUND_PORT_1, 4, Sound::ExtraLife),
+    (SOUND_PORT_2, 0, Sound::Fleet1),
+    (SOUND_PORT_2, 1, Sound::Fleet2),
+    (SOUND_PORT_2, 2, Sound::Fleet3),
+    (SOUND_PORT_2, 3, Sound::Fleet4),
+    (SOUND_PORT_2, 4, Sound::UfoHit),
+];
+
+/// Sounds started by writing `value` to sound latch `port` when it held
+/// `previous`: those whose bit went from 0 to 1. The UFO sound loops while
+/// its bit stays set on the real board; here it plays once per start.
+pub fn sounds_started(port: u8, previous: u8, value: u8) -> Vec<Sound> {
+    let rising: u8 = value & !previous;
+    return SOUND_BITS.iter()
+        .filter(|(latch, bit, _)| *latch == port && rising & (1 << bit) != 0)
+        .map(|(_, _, sound)| *sound)
+        .collect();
+}
+
+impl Sound {
+    /// The file holding this sound in the usual numbered sample set.
+    pub fn sample_file(self) -> &'static str {
+        return match self {
+            Sound::Ufo => "0.wav",
+            Sound::Shot => "1.wav",
+            Sound::PlayerDies => "2.wav",
+            Sound::InvaderDies => "3.wav",
+            Sound::Fleet1 => "4.wav",
+            Sound::Fleet2 => "5.wav",
+            Sound::Fleet3 => "6.wav",
+            Sound::Fleet4 => "7.wav",
+            Sound::UfoHit => "8.wav",
+            Sound::ExtraLife => "9.wav",
+        };
+    }
+
+    /// Frequency in hertz and length in milliseconds of the stand-in tone.
+    fn tone_shape(self) -> (u32, u32) {
+        return match self {
+            Sound::Ufo => (880, 200),
+            Sound::Shot => (1600, 80),
+            Sound::PlayerDies => (110, 600),
+            Sound::InvaderDies => (440, 120),
+            Sound::ExtraLife => (1320, 400),
+            Sound::Fleet1 => (98, 60),
+            Sound::Fleet2 => (87, 60),
+            Sound::Fleet3 => (78, 60),
+            Sound::Fleet4 => (73, 60),
+            Sound::UfoHit => (220, 300),
+        };
+    }
+
+    /// A square wave to play when there is no sample for the sound, as
+    /// signed 16-bit mono at `sample_rate`, at a quarter of full scale.
+    pub fn tone(self, sample_rate: u32) -> Vec<i16> {
+        let (hz, millis): (u32, u32) = self.tone_shape();
+        let len: usize = (sample_rate as u64 * millis as u64 / 1000) as usize;
+        let half_period: usize = (sample_rate / hz / 2).max(1) as usize;
+        return (0..len).map(|i| if (i / half_period).is_multiple_of(2) { i16::MAX / 4 } else { i16::MIN / 4 }).collect();
+    }
+}
+
+/// The cabinet's I/O board, attached at `ports()`.
+///
+/// Clones share the inputs and the sounds waiting to be played, so keep one
+/// clone to press keys and collect sounds after attaching another.
+#[derive(Debug, Clone, Default)]
+pub struct Cabinet {
+    inputs: Arc<Mutex<Inputs>>,
+    sounds: Arc<Mutex<Vec<Sound>>>,
+    /// The last two bytes written to the shift data port, the newest high.
+    shift: u16,
+    shift_amount: u8,
+    latches: [u8; 2],
+}
+
+impl Cabinet {
+    pub fn new() -> Cabinet {
+        return Cabinet::default();
+    }
+
+    pub fn ports(&self) -> Vec<u8> {
+        return (INPUT_PORT_1..=WATCHDOG_PORT).collect();
+    }
+
+    pub fn set(&self, control: Control, pressed: bool) {
+        self.inputs.lock().unwrap().set(control, pressed);
+    }
+
+    pub fn inputs(&self) -> Inputs {
+        return *self.inputs.lock().unwrap();
+    }
+
+    pub fn set_inputs(&self, inputs: Inputs) {
+        *self.inputs.lock().unwrap() = inputs;
+    }
+
+    /// The options `set_option` takes.
+    pub fn options(&self) -> &'static [DipOption] {
+        return OPTIONS;
+    }
+
+    /// Sets the switches for option `name` to `value`, e.g. `lives` to `5`.
+    pub fn set_option(&self, name: &str, value: &str) -> Result<(), OptionError> {
+        let option = dip::find(OPTIONS, name)?;
+        let mut inputs = self.inputs.lock().unwrap();
+        inputs.switches = option.apply(inputs.switches, value)?;
+        return Ok(());
+    }
+
+    /// Sounds started since the last call, oldest first.
+    pub fn take_sounds(&self) -> Vec<Sound> {
+        return std::mem::take(&mut *self.sounds.lock().unwrap());
+    }
+}
+
+impl IoDevice for Cabinet {
+    fn read(&mut self, port: u16, _ctx: &IoContext) -> u8 {
+        return match port as u8 {
+            INPUT_PORT_1 => self.inputs().port_1(),
+            INPUT_PORT_2 => self.inputs().port_2(),
+            SHIFT_RESULT_PORT => (self.shift >> (8 - self.shift_amount)) as u8,
+            _ => 0,
+        };
+    }
+
+    fn write(&mut self, port: u16, value: u8, _ctx: &IoContext) {
+        match port as u8 {
+            SHIFT_AMOUNT_PORT => self.shift_amount = value & 0b111,
+            SHIFT_DATA_PORT => self.shift = (value as u16) << 8 | self.shift >> 8,
+            latch @ (SOUND_PORT_1 | SOUND_PORT_2) => {
+                let index: usize = (latch == SOUND_PORT_2) as usize;
+                self.sounds.lock().unwrap().extend(sounds_started(latch, self.latches[index], value));
+                self.latches[index] = value;
+            },
+            _ => {},
+        }
+    }
+
+    fn peek_input(&self, port: u16) -> Option<u8> {
+        return match port as u8 {
+            INPUT_PORT_1 => Some(self.inputs().port_1()),
+            INPUT_PORT_2 => Some(self.inputs().port_2()),
+            _ => None,
+        };
+    }
+
+    /// Clears the shift register and sound latches. The inputs are the
+    /// cabinet's switches and stay as they are.
+    fn reset(&mut self) {
+        self.shift = 0;
+        self.shift_amount = 0;
+        self.latches = [0; 2];
+    }
+}
+
+/// The screen interrupt at the end of half frame `half_frame`, counting
+/// from 0: mid-screen after the first half of each frame, vertical blank
+/// after the second.
+pub fn screen_interrupt(half_frame: u64) -> u8 {
+    return if half_frame.is_multiple_of(2) { MID_SCREEN_RST } else { VBLANK_RST };
+}
+
+/// Runs frame `frame`, counting from 0 at cycle 0, raising each screen
+/// interrupt as its half ends and pacing through `throttle` after each
+/// half so the frame takes a 60th of a second of `clock` time. Waits for
+/// the interrupt or a key spent idle are skipped rather than run, as the
+/// inputs only change between frames. Stops early if the guest halts with
+/// interrupts disabled or faults.
+pub fn run_frame(processor: &mut Processor, frame: u64, throttle: &mut Throttle, clock: &dyn Clock) {
+    throttle.pace(processor.cycle_count(), clock);
+    for half_frame in [frame * 2, frame * 2 + 1] {
+        let end: u64 = (half_frame + 1) * CYCLES_PER_HALF_FRAME;
+        while processor.cycle_count() < end {
+            match processor.run_block(end - processor.cycle_count()) {
+                BlockResult::Done => {},
+                BlockResult::Idle { .. } => {
+                    processor.skip_idle(end);
+                },
+                BlockResult::Stopped(_) => return,
+            }
+        }
+        processor.raise_interrupt(SCREEN_INTERRUPT_SOURCE, screen_interrupt(half_frame));
+        throttle.pace(processor.cycle_count(), clock);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::clock::ManualClock;
+    use crate::processor::ProcessorBuilder;
+
+    #[test]
+    fn test_key_map() {
+        assert_eq!(control_for_key("Space"), Some(Control::P1Fire));
+        assert_eq!(control_for_key("C"), Some(Control::Coin));
+        assert_eq!(control_for_key("Escape"), None);
+
+        let mut inputs = Inputs::default();
+        for (key, _) in KEY_MAP {
+            inputs.set(control_for_key(key).unwrap(), true);
+        }
+        assert_eq!(inputs.port_1(), 0b0111_1111);
+        assert_eq!(inputs.port_2(), 0b0111_1100);
+        inputs.set(Control::P1Start, false);
+        assert_eq!(inputs.port_1(), 0b0111_1011);
+        assert_eq!(Inputs::default().port_1(), 0b0000_1000);
+    }
+
+    #[test]
+    fn test_dip_options() {
+        let cabinet = Cabinet::new();
+        let ctx = IoContext::default();
+        let port_2 = |name: &str, value: &str| -> u8 {
+            cabinet.set_option(name, value).unwrap();
+            return cabinet.clone().read(INPUT_PORT_2 as u16, &ctx);
+        };
+
+        assert_eq!(cabinet.clone().read(INPUT_PORT_2 as u16, &ctx), 0b0000_1000);
+        assert_eq!(port_2("lives", "5"), 0b0000_1010);
+        assert_eq!(port_2("lives", "6"), 0b0000_1011);
+        assert_eq!(port_2("lives", "4"), 0b0000_1001);
+        assert_eq!(port_2("bonus_at", "1000"), 0b0000_0001);
+        assert_eq!(port_2("coin_info", "off"), 0b1000_0001);
+        assert_eq!(port_2("lives", "3"), 0b1000_0000);
+        assert_eq!(port_2("bonus_at", "1500"), 0b1000_1000);
+        assert_eq!(port_2("coin_info", "on"), 0b0000_1000);
+        assert_eq!(cabinet.options().iter().map(|option| option.name).collect::<Vec<&str>>(), ["lives", "bonus_at", "coin_info"]);
+    }
+
+    #[test]
+    fn test_dip_option_errors() {
+        let cabinet = Cabinet::new();
+
+        assert_eq!(cabinet.set_option("lives", "7").unwrap_err().to_string(), "lives: invalid value '7'; expected one of 3, 4, 5, 6");
+        assert_eq!(cabinet.set_option("difficulty", "hard").unwrap_err(), OptionError::UnknownOption {
+            name: "difficulty".to_string(),
+            known: vec!["lives", "bonus_at", "coin_info"],
+        });
+        assert_eq!(cabinet.set_option("bonus_at", "2000").unwrap_err().to_string(),
+                   "bonus_at: invalid value '2000'; expected one of 1000, 1500");
+        assert_eq!(cabinet.inputs().switches, 0b0000_1000);
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut cabinet = Cabinet::new();
+        let ctx = IoContext::default();
+        cabinet.write(SHIFT_DATA_PORT as u16, 0xab, &ctx);
+        cabinet.write(SHIFT_DATA_PORT as u16, 0xcd, &ctx);
+
+        assert_eq!(cabinet.read(SHIFT_RESULT_PORT as u16, &ctx), 0xcd);
+        cabinet.write(SHIFT_AMOUNT_PORT as u16, 4, &ctx);
+        assert_eq!(cabinet.read(SHIFT_RESULT_PORT as u16, &ctx), 0xda);
+        cabinet.write(SHIFT_AMOUNT_PORT as u16, 0x0f, &ctx);
+        assert_eq!(cabinet.read(SHIFT_RESULT_PORT as u16, &ctx), 0xd5);
+    }
+
+    #[test]
+    fn test_sounds_start_on_rising_bits() {
+        let mut cabinet = Cabinet::new();
+        let host = cabinet.clone();
+        let ctx = IoContext::default();
+        cabinet.write(SOUND_PORT_1 as u16, 0b0000_0010, &ctx);
+        cabinet.write(SOUND_PORT_1 as u16, 0b0000_1010, &ctx);
+        cabinet.write(SOUND_PORT_2 as u16, 0b0001_0001, &ctx);
+
+        assert_eq!(host.take_sounds(), vec![Sound::Shot, Sound::InvaderDies, Sound::Fleet1, Sound::UfoHit]);
+        assert_eq!(host.take_sounds(), vec![]);
+        assert_eq!(Sound::Fleet1.sample_file(), "4.wav");
+        let tone: Vec<i16> = Sound::Shot.tone(44_100);
+        assert_eq!(tone.len(), 44_100 * 80 / 1000);
+        assert!(tone.iter().any(|sample| *sample < 0) && tone.iter().any(|sample| *sample > 0));
+    }
+
+    #[test]
+    fn test_frames_are_paced_and_interrupted() {
+        let mut processor = ProcessorBuilder::new().build();
+        processor.load_at(0x0000, &[
+            0x31, 0x00, 0x24, // 0000 LXI SP,2400h
+            0xfb,             // 0003 EI
+            0xc3, 0x04, 0x00, // 0004 JMP 0004h
+        ]);
+        // Each handler counts its interrupts and returns with EI.
+        for (vector, counter) in [(0x08u16, 0x20u8), (0x10, 0x21)] {
+            processor.load_at(vector, &[0x21, 0x00, counter, 0x34, 0xfb, 0xc9]); // LXI H; INR M; EI; RET
+        }
+        let clock = ManualClock::new();
+        let mut throttle = Throttle::new(CPU_HZ);
+
+        for frame in 0..3 {
+            run_frame(&mut processor, frame, &mut throttle, &clock);
+        }
+
+        assert_eq!((processor.peek(0x2000), processor.peek(0x2100)), (3, 2));
+        // Three frames at 60 a second.
+        assert!(clock.now() > Duration::from_micros(49_900) && clock.now() < Duration::from_micros(50_100));
+    }
+
+    #[test]
+    fn test_frames_wait_out_a_halted_guest() {
+        let mut processor = ProcessorBuilder::new().build();
+        processor.load_at(0x0000, &[
+            0x31, 0x00, 0x24, // 0000 LXI SP,2400h
+            0xfb,             // 0003 EI
+            0x76,             // 0004 HLT
+            0xc3, 0x04, 0x00, // 0005 JMP 0004h
+        ]);
+        for (vector, counter) in [(0x08u16, 0x20u8), (0x10, 0x21)] {
+            processor.load_at(vector, &[0x21, 0x00, counter, 0x34, 0xfb, 0xc9]); // LXI H; INR M; EI; RET
+        }
+        let clock = ManualClock::new();
+        let mut throttle = Throttle::new(CPU_HZ);
+
+        for frame in 0..3 {
+            run_frame(&mut processor, frame, &mut throttle, &clock);
+        }
+
+        assert_eq!((processor.peek(0x2000), processor.peek(0x2100)), (3, 2));
+        assert!(processor.instruction_count() < 100);
+        assert_eq!(processor.cycle_count(), 6 * CYCLES_PER_HALF_FRAME);
+    }
+}
@@ -0,0 +1,75 @@
+#![allow(clippy::needless_return)]
+
+//! The processor, instruction decoder and clock are always built; the
+//! rest sits behind the cargo features named on each module. Features only
+//! ever lean on the core or on features they enable themselves, so any
+//! combination compiles.
+
+#[cfg(all(feature = "serde-state", feature = "debugger", feature = "devices"))]
+pub mod artifacts;
+#[cfg(feature = "async")]
+pub mod async_runner;
+#[cfg(feature = "asm")]
+pub mod asm;
+#[doc(hidden)]
+pub mod asm_macro;
+#[cfg(feature = "serde-state")]
+pub mod checkpoint;
+pub mod clock;
+#[cfg(feature = "debugger")]
+pub mod compact_trace;
+#[cfg(feature = "asm")]
+pub mod corpus;
+#[cfg(feature = "cpm")]
+pub mod cpm;
+#[cfg(feature = "devices")]
+pub mod devices;
+#[cfg(feature = "machines")]
+pub mod dip;
+pub mod disasm;
+#[cfg(feature = "debugger")]
+pub mod expr;
+#[cfg(all(feature = "serde-state", feature = "debugger"))]
+pub mod formats;
+#[cfg(feature = "machines")]
+pub mod frame_hashes;
+#[cfg(feature = "machines")]
+pub mod gif;
+pub mod instruction;
+#[cfg(feature = "machines")]
+pub mod invaders;
+#[cfg(feature = "machines")]
+pub mod machine;
+#[cfg(feature = "debugger")]
+pub mod mem_view;
+#[cfg(feature = "debugger")]
+pub mod memedit;
+#[cfg(feature = "debugger")]
+pub mod monitor;
+#[cfg(all(feature = "machines", feature = "serde-state"))]
+pub mod package;
+pub mod patchsets;
+pub mod prelude;
+#[cfg(feature = "disasm")]
+pub mod prescan;
+pub mod processor;
+#[cfg(feature = "serde-state")]
+pub mod profiles;
+pub mod progress;
+pub mod reloc;
+#[cfg(feature = "serde-state")]
+pub mod repro;
+#[cfg(all(feature = "disasm", feature = "asm"))]
+pub mod roundtrip;
+#[cfg(all(test, feature = "asm"))]
+mod routines;
+pub mod selftest;
+#[cfg(feature = "debugger")]
+pub mod stats;
+#[cfg(feature = "debugger")]
+pub mod style;
+#[cfg(feature = "disasm")]
+pub mod variant;
+#[cfg(feature = "machines")]
+pub mod video;
+pub mod word;
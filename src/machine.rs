@@ -0,0 +1,94 @@
+//! A processor together with the ROMs it was started from, so it can be
+//! reset the way a cabinet's reset button or power switch would reset it.
+
+use crate::processor::Processor;
+
+/// How thoroughly `Machine::reset` starts over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetKind {
+    /// The reset button: the CPU and devices start over, RAM keeps what
+    /// the guest left in it.
+    Soft,
+    /// Power off and on: a soft reset, with RAM cleared and the ROMs
+    /// loaded again.
+    Hard,
+}
+
+#[derive(Debug, Default)]
+pub struct Machine {
+    pub processor: Processor,
+    /// Load address and bytes of each ROM, patched, in load order.
+    roms: Vec<(u16, Vec<u8>)>,
+}
+
+impl Machine {
+    pub fn new(processor: Processor) -> Machine {
+        return Machine { processor, roms: Vec::new() };
+    }
+
+    /// Loads `image` at `load_addr` and keeps it to load again after a
+    /// hard reset. Patch the image first; the patched bytes are what gets
+    /// loaded each time.
+    pub fn rom(mut self, load_addr: u16, image: Vec<u8>) -> Machine {
+        self.processor.load_at(load_addr, &image);
+        self.roms.push((load_addr, image));
+        return self;
+    }
+
+    /// Resets the processor and every attached device, and for a hard
+    /// reset clears memory and loads the ROMs again.
+    pub fn reset(&mut self, kind: ResetKind) {
+        self.processor.reset();
+        self.processor.reset_devices();
+        if kind == ResetKind::Hard {
+            self.processor.clear_memory();
+            for (load_addr, image) in &self.roms {
+                self.processor.load_at(*load_addr, image);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invaders::{Cabinet, SHIFT_RESULT_PORT};
+    use crate::processor::RunOutcome;
+
+    #[test]
+    fn test_soft_reset_keeps_ram_and_hard_reset_reloads_rom() {
+        let rom: Vec<u8> = vec![
+            0x3e, 0xa5,       // 0000 MVI A,0A5h
+            0xd3, 0x04,       // 0002 OUT 4, shift data
+            0xd3, 0x04,       // 0004 OUT 4
+            0x32, 0x00, 0x20, // 0006 STA 2000h
+            0xfb,             // 0009 EI
+            0x76,             // 000a HLT
+        ];
+        let cabinet = Cabinet::new();
+        let mut processor = Processor::default();
+        processor.attach_device(&cabinet.ports(), Box::new(cabinet));
+        let mut machine: Machine = Machine::new(processor).rom(0, rom.clone());
+
+        assert_eq!(machine.processor.run(), RunOutcome::Stuck { pc: 0x000b });
+        machine.processor.request_interrupt(1);
+        assert_eq!(machine.processor.read_port(SHIFT_RESULT_PORT), 0xa5);
+        assert_eq!(machine.processor.peek(0x2000), 0xa5);
+
+        machine.reset(ResetKind::Soft);
+
+        assert_eq!(machine.processor.read_port(SHIFT_RESULT_PORT), 0);
+        assert_eq!(machine.processor.peek(0x2000), 0xa5);
+        assert_eq!(machine.processor.pc(), 0);
+        assert!(!machine.processor.is_halted() && !machine.processor.interrupts_enabled());
+        assert!(!machine.processor.interrupt_controller().is_pending());
+
+        machine.processor.load_at(0x0000, &[0x76]); // a guest scribbling over its ROM
+        machine.reset(ResetKind::Hard);
+
+        assert_eq!(machine.processor.peek(0x2000), 0);
+        assert_eq!((0..rom.len() as u16).map(|addr| machine.processor.peek(addr)).collect::<Vec<u8>>(), rom);
+        assert_eq!(machine.processor.run(), RunOutcome::Stuck { pc: 0x000b });
+        assert_eq!(machine.processor.peek(0x2000), 0xa5);
+    }
+}
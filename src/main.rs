@@ -1,14 +1,641 @@
+#![allow(clippy::needless_return)]
+
+use std::collections::BTreeMap;
 use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use intel_8080_emu::artifacts::{ArtifactCollector, ArtifactPolicy, TraceTail, DEFAULT_TRACE_TAIL};
+use intel_8080_emu::checkpoint::{self, Checkpointer};
+use intel_8080_emu::devices::DeviceRegistry;
+use intel_8080_emu::disasm;
+use intel_8080_emu::expr::Expr;
+use intel_8080_emu::gif::GifEncoder;
+use intel_8080_emu::machine::Machine;
+use intel_8080_emu::monitor::{Action, History, Monitor};
+use intel_8080_emu::package;
+use intel_8080_emu::patchsets;
+use intel_8080_emu::prescan;
+use intel_8080_emu::formats::{self, EventRecord, StateDump, StatsReport, TraceLine};
+use intel_8080_emu::processor::{self, EmuError, EventLog, ProcessorBuilder, QuirkProfile, Registers, RunOutcome, Throttle, TraceRecord,
+                                Tracer};
+use intel_8080_emu::profiles;
+use intel_8080_emu::progress::{Progress, ProgressReporter, ProgressSink};
+use intel_8080_emu::reloc::{self, RelocOptions};
+use intel_8080_emu::repro::{self, Expectation, ReproCase, Window};
+use intel_8080_emu::roundtrip;
+use intel_8080_emu::selftest;
+use intel_8080_emu::stats::{BranchReport, InterruptReport, UsageMap};
+use intel_8080_emu::style::{ColorChoice, Style};
+use intel_8080_emu::variant;
+use intel_8080_emu::video;
+
+mod cli;
+mod config;
+#[cfg(feature = "sdl")]
+mod sdl;
+
+/// Keeps a single progress line updated on stderr.
+struct StderrProgress;
+
+impl ProgressSink for StderrProgress {
+    fn report(&mut self, progress: &Progress) {
+        eprint!("\r{}\x1b[K", progress);
+        let _ = io::stderr().flush();
+    }
+
+    fn finish(&mut self, progress: &Progress) {
+        eprintln!("\r{}\x1b[K", progress);
+    }
+}
+
+/// Prints each traced instruction on stderr as its `TraceRecord` line, with
+/// the flags it changed if `flags`, or as a `TraceLine` with `json`.
+#[derive(Debug)]
+struct StderrTracer {
+    json: bool,
+    flags: bool,
+    style: Style,
+}
+
+impl Tracer for StderrTracer {
+    fn trace(&mut self, record: &TraceRecord, after: &Registers) {
+        if self.json {
+            eprintln!("{}", formats::to_json_line(&TraceLine::of(record)));
+            return;
+        }
+        let line: String = match self.flags {
+            true => record.with_flag_delta(after),
+            false => record.to_string(),
+        };
+        eprintln!("{}", self.style.trace(record, &line));
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    return env::var_os("HOME").map(|home| PathBuf::from(home).join(".intel_8080_emu_history"));
+}
+
+/// The style for output to stdout or stderr, as `--color` and `NO_COLOR`
+/// ask for it.
+fn style_for(choice: ColorChoice, terminal: bool) -> Style {
+    let no_color: bool = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    return Style::choose(choice, terminal, no_color);
+}
+
+fn run_monitor(processor: &mut processor::Processor, script: Option<&str>, style: Style) -> io::Result<()> {
+    let history_path = history_path();
+    let history = match &history_path {
+        Some(path) => History::load(path)?,
+        None => History::new(),
+    };
+    let mut monitor = Monitor::with_history(history);
+    monitor.set_style(style);
+    let mut stdout = io::stdout();
+
+    let mut action = Action::Continue;
+    if let Some(script) = script {
+        action = monitor.run_script(processor, script, &mut stdout)?;
+    }
+    if action == Action::Continue {
+        monitor.run_interactive(processor, &mut io::stdin().lock(), &mut stdout)?;
+    }
+
+    if let Some(path) = &history_path {
+        monitor.history().save(path)?;
+    }
+    return Ok(());
+}
+
+fn read_or_exit(path: &str) -> Vec<u8> {
+    return fs::read(path).unwrap_or_else(|err| {
+        eprintln!("could not read {}: {}", path, err);
+        process::exit(1);
+    });
+}
+
+/// The roots `--format asm` follows code from: those given, or the origin.
+fn asm_roots(args: &cli::DisasmArgs) -> Vec<u16> {
+    return match args.roots.is_empty() {
+        true => vec![args.origin],
+        false => args.roots.clone(),
+    };
+}
+
+fn run_roundtrip(args: &cli::DisasmArgs) {
+    let program = read_or_exit(&args.path);
+    let result = match args.format {
+        disasm::Format::Listing => roundtrip::roundtrip(&program, args.origin),
+        disasm::Format::Asm => roundtrip::roundtrip_labelled(&program, args.origin, &asm_roots(args)),
+    };
+    match result {
+        Ok(()) => println!("{}: {} bytes round-trip", args.path, program.len()),
+        Err(err) => {
+            eprintln!("{}: {}", args.path, err);
+            process::exit(1);
+        },
+    }
+}
+
+fn run_disasm(args: &cli::DisasmArgs) {
+    let program = read_or_exit(&args.path);
+    match args.format {
+        disasm::Format::Listing => {
+            let lines = match args.roots.is_empty() {
+                true => disasm::disassemble(&program, args.origin),
+                false => disasm::disassemble_reachable(&program, args.origin, &args.roots),
+            };
+            for line in lines {
+                println!("{}", line);
+            }
+        },
+        disasm::Format::Asm => print!("{}", disasm::to_labelled_source(&program, args.origin, &asm_roots(args))),
+    }
+}
+
+/// Loads the program as the options say, returning where it went and the
+/// bytes loaded there.
+fn load(processor: &mut processor::Processor, options: &cli::Options) -> (u16, Vec<u8>) {
+    let mut program = read_or_exit(&options.program);
+    let origin: u16 = options.relocate.map_or(options.origin.unwrap_or(0), |(from, _)| from);
+    for name in &options.patchsets {
+        let patchset = patchsets::find(name).expect("parse_args checks patch set names");
+        if let Err(err) = patchset.apply(&mut program, origin) {
+            eprintln!("--patchset {}: {}", name, err);
+            process::exit(1);
+        }
+    }
+    if options.prescan == Some(true) {
+        eprint!("{}", prescan::prescan(&program, origin));
+    }
+    match options.relocate {
+        Some((from, to)) => {
+            let reloc_options = RelocOptions { data_ranges: options.reloc_data.clone() };
+            let result = reloc::relocate(&program, from, to, &reloc_options);
+            eprint!("{}", result);
+            processor.load_at(to, &result.bytes);
+            processor.set_pc(to);
+            return (to, result.bytes);
+        },
+        None => {
+            processor.load_at(origin, &program);
+            processor.set_pc(origin);
+            return (origin, program);
+        },
+    }
+}
+
+/// Sets SP and stores the bytes `--sp` and `--poke` ask for.
+fn apply_start(processor: &mut processor::Processor, options: &cli::Options) {
+    if let Some(sp) = options.sp {
+        let mut registers: Registers = processor.registers();
+        registers.sp = sp;
+        processor.set_registers(registers);
+    }
+    for (addr, value) in &options.pokes {
+        processor.poke(*addr, *value);
+    }
+}
+
+fn load_checkpoint_or_exit(path: &str) -> processor::Snapshot {
+    return checkpoint::load(Path::new(path)).unwrap_or_else(|err| {
+        eprintln!("could not load checkpoint {}: {}", path, err);
+        process::exit(2);
+    });
+}
+
+fn restore_or_exit(processor: &mut processor::Processor, snapshot: &processor::Snapshot, path: &str) {
+    if let Err(err) = processor.restore(snapshot) {
+        eprintln!("could not resume {}: {}", path, err);
+        process::exit(1);
+    }
+}
+
+/// Prints how checkpoint `b` differs from `a`, exiting 1 if it does.
+fn run_snapshot_diff(a: &str, b: &str) {
+    let diff = load_checkpoint_or_exit(a).diff(&load_checkpoint_or_exit(b));
+    print!("{}", style_for(ColorChoice::Auto, io::stdout().is_terminal()).snapshot_diff(&diff));
+    if !diff.is_empty() {
+        process::exit(1);
+    }
+}
+
+/// Runs the built-in test programs, printing PASS or FAIL for each, and
+/// exits 1 if any failed.
+fn run_selftest() {
+    let mut failed: usize = 0;
+    for test in selftest::tests() {
+        match test.run() {
+            Ok(()) => println!("PASS {}", test.name),
+            Err(err) => {
+                println!("FAIL {}: {}", test.name, err);
+                failed += 1;
+            },
+        }
+    }
+    if failed > 0 {
+        println!("{} of {} self tests failed", failed, selftest::tests().len());
+        process::exit(1);
+    }
+}
+
+/// Runs the program under every quirk profile and prints which agree.
+fn run_compare_profiles(path: &str, max_instructions: Option<u64>) {
+    let program = read_or_exit(path);
+    let max_instructions: u64 = max_instructions.unwrap_or(profiles::DEFAULT_MAX_INSTRUCTIONS);
+    print!("{}", profiles::compare_with_limit(&program, &QuirkProfile::ALL, max_instructions));
+}
+
+/// Lists where the program would behave or time differently on the 8085,
+/// by scanning it or by running it from the origin.
+fn run_variant_check(args: &cli::VariantCheckArgs) {
+    let program = read_or_exit(&args.path);
+    let report = match (args.run, args.roots.is_empty()) {
+        (true, _) => {
+            let mut processor = ProcessorBuilder::new().build();
+            processor.load_at(args.origin, &program);
+            processor.set_pc(args.origin);
+            variant::check_run(&mut processor, args.max_instructions.unwrap_or(variant::DEFAULT_MAX_INSTRUCTIONS))
+        },
+        (false, true) => variant::diff_report(&program, args.origin),
+        (false, false) => variant::diff_report_reachable(&program, args.origin, &args.roots),
+    };
+    print!("{}", report);
+}
+
+/// Resumes a checkpoint in strict mode and writes the fault it runs into
+/// as a test case. The run has no devices, so a checkpoint taken with
+/// some is refused rather than replayed with different inputs.
+fn run_repro(args: &cli::ReproArgs) {
+    let snapshot = load_checkpoint_or_exit(&args.checkpoint);
+    if !snapshot.devices.is_empty() {
+        eprintln!("repro: {} was taken with {} devices attached; repro replays without devices", args.checkpoint, snapshot.devices.len());
+        process::exit(1);
+    }
+    let mut processor: processor::Processor = ProcessorBuilder::new().strict(true).build();
+    restore_or_exit(&mut processor, &snapshot, &args.checkpoint);
+    let outcome = match args.max_instructions {
+        Some(max) => processor.run_with_limit(max),
+        None => processor.run(),
+    };
+    let RunOutcome::Fault(err) = outcome else {
+        eprintln!("repro: the run did not fault: {:?}", outcome);
+        process::exit(1);
+    };
+    let failure: u64 = processor.instruction_count() + 1;
+    let window = Window { failure, before: args.window.unwrap_or(repro::DEFAULT_WINDOW), expected: Expectation::NoFault };
+    let case: ReproCase = repro::extract(&snapshot, &window).unwrap_or_else(|err| {
+        eprintln!("repro: {}", err);
+        process::exit(1);
+    });
+    let path: PathBuf = match &args.out {
+        Some(out) => PathBuf::from(out),
+        None => Path::new(repro::REPRO_DIR).join(format!("fault-{}.json", failure)),
+    };
+    if let Err(err) = repro::save(&case, &path) {
+        eprintln!("could not write {}: {}", path.display(), err);
+        process::exit(1);
+    }
+    println!("{}: {} instructions, {} bytes of memory: {}", path.display(), case.instructions,
+             case.memory.iter().map(|segment| segment.bytes.len()).sum::<usize>(), err);
+}
+
+fn run_with_checkpoints(processor: &mut processor::Processor, dir: &str, options: &cli::Options) -> RunOutcome {
+    let keep = options.checkpoint_keep.unwrap_or(checkpoint::DEFAULT_CHECKPOINT_KEEP);
+    let interval = options.checkpoint_interval.unwrap_or(checkpoint::DEFAULT_CHECKPOINT_INTERVAL);
+    let mut checkpointer = Checkpointer::new(dir, keep).unwrap_or_else(|err| {
+        eprintln!("could not use checkpoint directory {}: {}", dir, err);
+        process::exit(1);
+    });
+    let outcome = checkpoint::run_with_checkpoints(processor, &mut checkpointer, interval, options.max_instructions)
+        .unwrap_or_else(|err| {
+            eprintln!("could not write checkpoint: {}", err);
+            process::exit(1);
+        });
+    if let (RunOutcome::Fault(_), Some(latest)) = (&outcome, checkpointer.latest()) {
+        eprintln!("latest checkpoint: {}", latest.display());
+    }
+    return outcome;
+}
+
+fn write_events(path: &str, log: &EventLog, json: bool) {
+    let mut text = String::new();
+    if log.dropped() > 0 && !json {
+        text.push_str(&format!("({} earlier events dropped)\n", log.dropped()));
+    }
+    for event in log.events() {
+        match json {
+            true => text.push_str(&formats::to_json_line(&EventRecord::of(&event))),
+            false => text.push_str(&event.to_string()),
+        }
+        text.push('\n');
+    }
+    if let Err(err) = fs::write(path, text) {
+        eprintln!("could not write {}: {}", path, err);
+        process::exit(1);
+    }
+}
+
+/// Captures every `every`th frame of video memory into `encoder`.
+fn record_gif(processor: &mut processor::Processor, encoder: Arc<Mutex<GifEncoder>>, every: u64) {
+    // Hundredths of a second between captured frames at 60 frames a second.
+    let delay: u16 = ((every * 100 + 30) / 60).min(u16::MAX as u64) as u16;
+    processor.set_frame_callback(video::CYCLES_PER_FRAME, Some(Box::new(move |frame, processor| {
+        if frame.is_multiple_of(every) {
+            encoder.lock().unwrap().add_frame(&video::capture(|addr| processor.peek(addr)), delay);
+        }
+    })));
+}
+
+fn write_gif(path: &str, encoder: Arc<Mutex<GifEncoder>>) {
+    let encoder: GifEncoder = encoder.lock().unwrap().clone();
+    if let Err(err) = fs::write(path, encoder.finish()) {
+        eprintln!("could not write {}: {}", path, err);
+        process::exit(1);
+    }
+}
+
+fn report_usage(processor: &processor::Processor, options: &cli::Options) {
+    let Some(access) = processor.access_map() else {
+        return;
+    };
+    let usage = UsageMap::classify(access);
+    if options.stats == Some(true) && options.json == Some(true) {
+        println!("{}", formats::to_json_line(&StatsReport::of(processor, &usage, processor.interrupt_timing())));
+    } else if options.stats == Some(true) {
+        println!("instructions: {}", processor.instruction_count());
+        println!("cycles: {}", processor.cycle_count());
+        println!("{}", usage);
+        if let Some(timing) = processor.interrupt_timing().filter(|timing| !timing.sources().is_empty()) {
+            println!("{}", InterruptReport::collect(timing));
+        }
+        for allocator in processor.allocator_usage() {
+            println!("{}", allocator);
+        }
+        for read in processor.unattached_reads() {
+            println!("{}", read);
+        }
+        if !processor.branch_stats().is_empty() {
+            println!("{}", BranchReport::collect(processor, &BTreeMap::new()));
+        }
+    }
+    if let Some(path) = &options.usage_json {
+        if let Err(err) = fs::write(path, usage.to_json()) {
+            eprintln!("could not write {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn report_history(processor: &processor::Processor, addrs: &[u16]) {
+    for addr in addrs {
+        println!("writes to {:#06x}:", addr);
+        for record in processor.write_history(*addr) {
+            println!("{}", record);
+        }
+    }
+    if let Some(dropped) = processor.write_journal().map(|journal| journal.dropped()).filter(|dropped| *dropped > 0) {
+        println!("(+{} writes not journaled)", dropped);
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn run_invaders(machine: &mut Machine, options: &cli::Options) {
+    if let Err(err) = sdl::run(machine, options.scale.unwrap_or(2), options.samples.as_deref(), &options.machine_options) {
+        eprintln!("sdl: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_invaders(_machine: &mut Machine, _options: &cli::Options) {
+    eprintln!("--machine invaders needs a build with --features sdl");
+    process::exit(2);
+}
+
+/// Sets `processor` up from the package `options.program`, taking the
+/// machine and its options from the manifest unless given on the command
+/// line. Returns the ROMs loaded.
+fn load_package(processor: &mut processor::Processor, options: &mut cli::Options) -> Vec<(u16, Vec<u8>)> {
+    let package = package::read(Path::new(&options.program)).unwrap_or_else(|err| {
+        eprintln!("could not load package {}: {}", options.program, err);
+        process::exit(1);
+    });
+    if let Err(err) = package.apply_to(processor) {
+        eprintln!("{}: {}", options.program, err);
+        process::exit(1);
+    }
+    if let Some(name) = &package.manifest.machine {
+        options.machine = options.machine.or(cli::Machine::parse(name));
+    }
+    let settings: Vec<(String, String)> = package.manifest.options.iter().map(|(name, value)| (name.clone(), value.clone())).collect();
+    options.machine_options.splice(0..0, settings);
+    return package.images().expect("apply_to loaded the images");
+}
+
+/// Attaches the `--device` specs, then the devices `--rtc-epoch`,
+/// `--guest-log` and `--seed` stand for.
+fn attach_devices(processor: &mut processor::Processor, options: &cli::Options) {
+    let mut devices: Vec<String> = options.devices.clone();
+    if let Some(epoch) = options.rtc_epoch {
+        devices.push(format!("rtc:epoch={}", epoch));
+    }
+    if options.guest_log == Some(true) {
+        devices.push("guest_log".to_string());
+    }
+    if let Some(seed) = options.seed {
+        devices.push(format!("entropy:seed={}", seed));
+    }
+    let registry = DeviceRegistry::builtin();
+    for spec in &devices {
+        if let Err(err) = registry.attach(processor, spec) {
+            eprintln!("--device: {}", err);
+            process::exit(2);
+        }
+    }
+}
 
-mod processor;
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = cli::parse_command(&args).unwrap_or_else(|message| {
+        eprintln!("{}\n{}", message, cli::USAGE);
+        process::exit(2);
+    });
+    let mut options = match command {
+        cli::Command::Run(options) => *options,
+        cli::Command::Roundtrip(args) => return run_roundtrip(&args),
+        cli::Command::Disasm(args) => return run_disasm(&args),
+        cli::Command::SnapshotDiff { a, b } => return run_snapshot_diff(&a, &b),
+        cli::Command::Repro(args) => return run_repro(&args),
+        cli::Command::VariantCheck(args) => return run_variant_check(&args),
+        cli::Command::SelfTest => return run_selftest(),
+        cli::Command::CompareProfiles { program, max_instructions } => return run_compare_profiles(&program, max_instructions),
+        cli::Command::Schema { name } => return print!("{}", formats::schema(&name).expect("parse_command checks the name")),
+    };
+
+    let mut processor: processor::Processor = ProcessorBuilder::new().strict(options.strict == Some(true)).build();
+    // Before any snapshot is restored, which pairs saved device state with
+    // the devices by attach order.
+    attach_devices(&mut processor, &options);
+    let roms: Vec<(u16, Vec<u8>)> = if options.resume {
+        let snapshot = checkpoint::load(Path::new(&options.program)).unwrap_or_else(|err| {
+            eprintln!("could not load checkpoint {}: {}", options.program, err);
+            process::exit(1);
+        });
+        restore_or_exit(&mut processor, &snapshot, &options.program);
+        Vec::new()
+    } else if options.package {
+        load_package(&mut processor, &mut options)
+    } else {
+        vec![load(&mut processor, &options)]
+    };
+    apply_start(&mut processor, &options);
+    if options.machine == Some(cli::Machine::Invaders) {
+        let mut machine = roms.into_iter().fold(Machine::new(processor), |machine, (load_addr, image)| machine.rom(load_addr, image));
+        return run_invaders(&mut machine, &options);
+    }
+    if let Some(mhz) = options.mhz {
+        processor.set_throttle(Some(Throttle::new((mhz * 1_000_000.0) as u64)));
+    }
+    processor.set_fast_forward_loops(options.fast_forward_loops == Some(true));
+    if options.progress == Some(true) {
+        let reporter = ProgressReporter::new(Box::new(StderrProgress), Duration::from_millis(250));
+        processor.set_progress_reporter(Some(reporter));
+    }
+
+    let stderr_tracer = options.trace.as_ref().map(|filter| {
+        processor.set_trace_filter(filter.clone());
+        return Box::new(StderrTracer {
+            json: options.json == Some(true),
+            flags: options.trace_flags == Some(true),
+            style: style_for(options.color.unwrap_or_default(), io::stderr().is_terminal()),
+        }) as Box<dyn Tracer>;
+    });
+    let trace_tail = options.artifacts_dir.as_ref().map(|_| TraceTail::new(DEFAULT_TRACE_TAIL));
+    match &trace_tail {
+        Some(tail) => processor.set_tracer(Some(tail.tracer(stderr_tracer))),
+        None => processor.set_tracer(stderr_tracer),
+    }
+
+    let event_log = (options.events.is_some() || options.artifacts_dir.is_some()).then(EventLog::default);
+    if let Some(log) = &event_log {
+        processor.set_event_sink(Some(Box::new(log.clone())));
+    }
+    for text in &options.watch_exprs {
+        let expr = Expr::parse(text).expect("parse_args checks watch expressions");
+        processor.watch_expr(text, move |processor| expr.eval(processor));
+    }
+
+    let gif = options.gif.as_ref().map(|_| {
+        let encoder = Arc::new(Mutex::new(GifEncoder::new(video::SCREEN_WIDTH as u16, video::SCREEN_HEIGHT as u16)));
+        record_gif(&mut processor, encoder.clone(), options.gif_frames.unwrap_or(1));
+        return encoder;
+    });
 
-    let file_path = &args[1];
+    if !options.history.is_empty() {
+        processor.set_write_journal(true);
+    }
+    if options.stats == Some(true) || options.usage_json.is_some() {
+        processor.set_access_tracking(true);
+    }
+    processor.set_interrupt_timing(options.stats == Some(true));
+    processor.set_branch_stats(options.stats == Some(true));
+    for (pair, arena) in &options.allocators {
+        processor.track_allocator(*pair, arena.clone());
+    }
+    processor.set_unattached_port_check(options.unattached_ports == Some(true));
 
-    let mut processor: processor::Processor = processor::make_processor();
+    if options.debug == Some(true) {
+        let script = options.debug_script.as_ref().map(|path| fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!("could not read {}: {}", path, err);
+            process::exit(1);
+        }));
+        let result = run_monitor(&mut processor, script.as_deref(), style_for(options.color.unwrap_or_default(), io::stdout().is_terminal()));
+        if let (Some(path), Some(log)) = (&options.events, &event_log) {
+            write_events(path, log, options.json == Some(true));
+        }
+        if let (Some(path), Some(encoder)) = (&options.gif, gif) {
+            write_gif(path, encoder);
+        }
+        if let Err(err) = result {
+            eprintln!("monitor: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
 
-    let result = processor.run_program(&file_path);
+    let outcome = match &options.checkpoint_dir {
+        Some(dir) => run_with_checkpoints(&mut processor, dir, &options),
+        None => match options.max_instructions {
+            Some(max) => processor.run_with_limit(max),
+            None => processor.run(),
+        },
+    };
+    match (&outcome, processor.fault_report()) {
+        (RunOutcome::Halted, _) => {},
+        (RunOutcome::Fault(_), Some(report)) => eprint!("{}", report),
+        _ => eprintln!("stopped: {:?}", outcome),
+    }
+    let verdict = match options.asserts.is_empty() {
+        true => Ok(()),
+        false => processor.apply_verdict(&outcome, |processor, outcome| check_asserts(processor, outcome, &options.asserts)),
+    };
+    if let Err(EmuError::PostconditionFailed { report: Some(report), .. }) = &verdict {
+        eprint!("{}", report);
+    }
+    if let (Some(path), Some(log)) = (&options.events, &event_log) {
+        write_events(path, log, options.json == Some(true));
+    }
+    if let (Some(path), Some(encoder)) = (&options.gif, gif) {
+        write_gif(path, encoder);
+    }
+    if let Some(dir) = &options.artifacts_dir {
+        collect_artifacts(&processor, &outcome, &verdict, dir, trace_tail, event_log);
+    }
+
+    match options.json == Some(true) {
+        true => println!("{}", formats::to_json_line(&StateDump::of(&processor))),
+        false => println!("{}", processor.state_report()),
+    }
+    report_usage(&processor, &options);
+    report_history(&processor, &options.history);
+    if verdict.is_err() {
+        process::exit(1);
+    }
+}
+
+/// Saves what `--artifacts-dir` asks for if the run failed.
+fn collect_artifacts(processor: &processor::Processor, outcome: &RunOutcome, verdict: &Result<(), EmuError>, dir: &str,
+                     trace_tail: Option<TraceTail>, event_log: Option<EventLog>) {
+    let mut collector = ArtifactCollector::new(dir, ArtifactPolicy::default());
+    if let Some(tail) = trace_tail {
+        collector = collector.trace_tail(tail);
+    }
+    if let Some(log) = event_log {
+        collector = collector.events(log);
+    }
+    match collector.collect(processor, outcome, verdict) {
+        Some(Ok(path)) => eprintln!("artifacts: {}", path.display()),
+        Some(Err(err)) => eprintln!("--artifacts-dir: could not create a directory in {}: {}", dir, err),
+        None => {},
+    }
+}
 
-    println!("{}", result);
+/// The verdict `--assert` gives: the run halted and every expression in
+/// `asserts` is nonzero.
+fn check_asserts(processor: &processor::Processor, outcome: &RunOutcome, asserts: &[String]) -> Result<(), String> {
+    if *outcome != RunOutcome::Halted {
+        return Err(format!("run did not halt: {:?}", outcome));
+    }
+    for text in asserts {
+        let expr = Expr::parse(text).expect("parse_args checks assertions");
+        if expr.eval(processor) == 0 {
+            return Err(format!("assertion failed: {}", text));
+        }
+    }
+    return Ok(());
 }
@@ -0,0 +1,137 @@
+use std::fmt;
+
+use crate::word::read_word;
+
+/// How to interpret the bytes at an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewType {
+    U8,
+    /// A little-endian word.
+    U16,
+    /// Text ending in NUL or `$` (the CP/M convention), read for at most
+    /// `max_len` bytes.
+    Str { max_len: u16 },
+}
+
+/// A decoded view of guest memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    U8(u8),
+    U16(u16),
+    /// `terminated` is false when the string ran into `max_len`.
+    Str { text: String, terminated: bool },
+}
+
+impl ViewType {
+    /// Number of bytes this view covers, for watching writes.
+    pub fn footprint(&self) -> u16 {
+        return match self {
+            ViewType::U8 => 1,
+            ViewType::U16 => 2,
+            ViewType::Str { max_len } => (*max_len).max(1),
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ViewType::U8 => "u8",
+            ViewType::U16 => "u16",
+            ViewType::Str { .. } => "str",
+        };
+    }
+}
+
+/// Reads up to `max_len` bytes, stopping at NUL or `$`. Returns the text
+/// (non-printable bytes escaped as `\xNN`) and whether a terminator was found.
+pub fn read_string(mut read: impl FnMut(u16) -> u8, addr: u16, max_len: u16) -> (String, bool) {
+    let mut text = String::new();
+    for offset in 0..max_len {
+        let byte = read(addr.wrapping_add(offset));
+        if byte == 0 || byte == b'$' {
+            return (text, true);
+        }
+        if byte.is_ascii_graphic() || byte == b' ' {
+            text.push(byte as char);
+        } else {
+            text.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    return (text, false);
+}
+
+pub fn decode(mut read: impl FnMut(u16) -> u8, addr: u16, view: ViewType) -> Value {
+    return match view {
+        ViewType::U8 => Value::U8(read(addr)),
+        ViewType::U16 => Value::U16(read_word(read, addr)),
+        ViewType::Str { max_len } => {
+            let (text, terminated) = read_string(read, addr, max_len);
+            Value::Str { text, terminated }
+        },
+    };
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Value::U8(value) => write!(f, "{:#04x} ({})", value, value),
+            Value::U16(value) => write!(f, "{:#06x} ({})", value, value),
+            Value::Str { text, terminated: true } => write!(f, "\"{}\"", text),
+            Value::Str { text, terminated: false } => write!(f, "\"{}\" (unterminated)", text),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_with(addr: u16, bytes: &[u8]) -> Vec<u8> {
+        let mut memory = vec![0xee; 0x10000];
+        for (offset, byte) in bytes.iter().enumerate() {
+            memory[addr.wrapping_add(offset as u16) as usize] = *byte;
+        }
+        return memory;
+    }
+
+    #[test]
+    fn test_u16_little_endian() {
+        let memory = memory_with(0x2050, &[0x34, 0x12]);
+
+        assert_eq!(decode(|addr| memory[addr as usize], 0x2050, ViewType::U16), Value::U16(0x1234));
+        assert_eq!(Value::U16(0x1234).to_string(), "0x1234 (4660)");
+    }
+
+    #[test]
+    fn test_u16_wraps_at_top_of_memory() {
+        let memory = memory_with(0xffff, &[0xcd, 0xab]);
+
+        assert_eq!(read_word(|addr| memory[addr as usize], 0xffff), 0xabcd);
+    }
+
+    #[test]
+    fn test_string_terminators() {
+        let memory = memory_with(0x2100, b"hi\0there$");
+        let read = |addr: u16| memory[addr as usize];
+
+        assert_eq!(read_string(read, 0x2100, 32), ("hi".to_string(), true));
+        assert_eq!(read_string(read, 0x2103, 32), ("there".to_string(), true));
+        assert_eq!(read_string(read, 0x2100, 0), (String::new(), false));
+    }
+
+    #[test]
+    fn test_unterminated_string_hits_max() {
+        let memory = memory_with(0x2100, b"abcdefgh");
+        let value = decode(|addr| memory[addr as usize], 0x2100, ViewType::Str { max_len: 4 });
+
+        assert_eq!(value, Value::Str { text: "abcd".to_string(), terminated: false });
+        assert_eq!(value.to_string(), "\"abcd\" (unterminated)");
+    }
+
+    #[test]
+    fn test_string_escapes_control_bytes() {
+        let memory = memory_with(0x2100, b"a\r\n\0");
+        let value = decode(|addr| memory[addr as usize], 0x2100, ViewType::Str { max_len: 8 });
+
+        assert_eq!(value.to_string(), "\"a\\x0d\\x0a\"");
+    }
+}
@@ -0,0 +1,258 @@
+//! Editing guest memory from the monitor: byte entry, fill and copy, each of
+//! which `undo` can take back. Edits are written with `Processor::poke`, so
+//! the write journal and watchpoints see them like guest stores, and regions
+//! declared `RegionAccess::ReadOnly` refuse them unless protection is
+//! overridden.
+
+use std::fmt;
+
+use crate::processor::Processor;
+
+/// Why an edit was refused. Nothing is written by a refused fill or copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditError {
+    /// `addr` is in the read-only region `region`.
+    Protected { addr: u16, region: String },
+    /// `enter` was called outside byte entry.
+    NotEntering,
+    NothingToUndo,
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            EditError::Protected { addr, region } => {
+                write!(f, "{:#06x} is in read-only region {} (set override on to write it)", addr, region)
+            },
+            EditError::NotEntering => write!(f, "not entering bytes"),
+            EditError::NothingToUndo => write!(f, "nothing to undo"),
+        };
+    }
+}
+
+/// What an edit wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edited {
+    pub len: usize,
+    /// Addresses written that hit a watchpoint, in write order.
+    pub watched: Vec<u16>,
+}
+
+/// One undoable operation: what it was and the bytes it overwrote, in the
+/// order it wrote them.
+#[derive(Debug, Clone)]
+struct Edit {
+    what: String,
+    original: Vec<(u16, u8)>,
+}
+
+/// Memory edits and the history to undo them.
+#[derive(Debug, Default)]
+pub struct MemoryEditor {
+    undo: Vec<Edit>,
+    /// The next address byte entry writes, while it is on.
+    entry: Option<u16>,
+    override_protection: bool,
+}
+
+impl MemoryEditor {
+    pub fn new() -> MemoryEditor {
+        return MemoryEditor::default();
+    }
+
+    /// Lets edits write read-only regions.
+    pub fn set_override(&mut self, enabled: bool) {
+        self.override_protection = enabled;
+    }
+
+    /// The address the next entered byte goes to, if byte entry is on.
+    pub fn entering(&self) -> Option<u16> {
+        return self.entry;
+    }
+
+    /// Starts byte entry at `addr`. Everything entered until `finish_entry`
+    /// is undone as one edit.
+    pub fn begin_entry(&mut self, addr: u16) {
+        self.finish_entry();
+        self.entry = Some(addr);
+        self.undo.push(Edit { what: format!("edit at {:#06x}", addr), original: Vec::new() });
+    }
+
+    /// Writes `bytes` at the entry address and moves it past them. A
+    /// protected byte stops entry there, keeping the bytes before it.
+    pub fn enter(&mut self, processor: &mut Processor, bytes: &[u8]) -> Result<Edited, EditError> {
+        let Some(start) = self.entry else {
+            return Err(EditError::NotEntering);
+        };
+        let mut edited = Edited { len: 0, watched: Vec::new() };
+        for (offset, byte) in bytes.iter().enumerate() {
+            let addr: u16 = start.wrapping_add(offset as u16);
+            self.check(processor, addr)?;
+            self.write(processor, addr, *byte, &mut edited);
+            self.entry = Some(addr.wrapping_add(1));
+        }
+        return Ok(edited);
+    }
+
+    /// Ends byte entry. An entry that wrote nothing leaves nothing to undo.
+    pub fn finish_entry(&mut self) {
+        if self.entry.take().is_some() && self.undo.last().is_some_and(|edit| edit.original.is_empty()) {
+            self.undo.pop();
+        }
+    }
+
+    /// Writes `value` to every address from `start` to `end` inclusive,
+    /// wrapping past 0xffff if `end` is below `start`.
+    pub fn fill(&mut self, processor: &mut Processor, start: u16, end: u16, value: u8) -> Result<Edited, EditError> {
+        let len: usize = end.wrapping_sub(start) as usize + 1;
+        let bytes: Vec<u8> = vec![value; len];
+        return self.write_all(processor, start, &bytes, format!("fill {:#06x}-{:#06x}", start, end));
+    }
+
+    /// Copies `len` bytes from `src` to `dst`. Overlapping ranges copy as if
+    /// through a buffer, so the destination ends up with what the source held.
+    pub fn copy(&mut self, processor: &mut Processor, src: u16, dst: u16, len: u16) -> Result<Edited, EditError> {
+        let bytes: Vec<u8> = (0..len).map(|offset| processor.peek(src.wrapping_add(offset))).collect();
+        return self.write_all(processor, dst, &bytes, format!("copy {:#06x} to {:#06x}", src, dst));
+    }
+
+    /// Puts back the bytes the last edit overwrote, returning what it was.
+    /// Undo restores read-only regions too, since it only puts back what
+    /// was there.
+    pub fn undo(&mut self, processor: &mut Processor) -> Result<String, EditError> {
+        self.finish_entry();
+        let edit: Edit = self.undo.pop().ok_or(EditError::NothingToUndo)?;
+        for (addr, old) in edit.original.iter().rev() {
+            processor.poke(*addr, *old);
+        }
+        return Ok(edit.what);
+    }
+
+    fn write_all(&mut self, processor: &mut Processor, start: u16, bytes: &[u8], what: String) -> Result<Edited, EditError> {
+        self.finish_entry();
+        for offset in 0..bytes.len() {
+            self.check(processor, start.wrapping_add(offset as u16))?;
+        }
+        self.undo.push(Edit { what, original: Vec::new() });
+        let mut edited = Edited { len: 0, watched: Vec::new() };
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.write(processor, start.wrapping_add(offset as u16), *byte, &mut edited);
+        }
+        return Ok(edited);
+    }
+
+    fn check(&self, processor: &Processor, addr: u16) -> Result<(), EditError> {
+        return match processor.read_only_region(addr) {
+            Some(region) if !self.override_protection => Err(EditError::Protected { addr, region: region.to_string() }),
+            _ => Ok(()),
+        };
+    }
+
+    /// Writes one byte of the edit on top of the undo stack.
+    fn write(&mut self, processor: &mut Processor, addr: u16, value: u8, edited: &mut Edited) {
+        let old: u8 = processor.peek(addr);
+        if let Some(edit) = self.undo.last_mut() {
+            edit.original.push((addr, old));
+        }
+        if processor.poke(addr, value) {
+            edited.watched.push(addr);
+        }
+        edited.len += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{make_processor, RegionAccess};
+
+    fn bytes(processor: &Processor, start: u16, len: u16) -> Vec<u8> {
+        return (0..len).map(|offset| processor.peek(start.wrapping_add(offset))).collect();
+    }
+
+    #[test]
+    fn test_entry_writes_sequentially_and_undoes_as_one_edit() {
+        let mut processor: Processor = make_processor();
+        processor.load_at(0x2000, &[0x11, 0x22, 0x33, 0x44]);
+        let mut editor = MemoryEditor::new();
+
+        editor.begin_entry(0x2001);
+        assert_eq!(editor.enter(&mut processor, &[0xaa, 0xbb]).unwrap().len, 2);
+        editor.enter(&mut processor, &[0xcc]).unwrap();
+        assert_eq!(editor.entering(), Some(0x2004));
+        editor.finish_entry();
+        assert_eq!(bytes(&processor, 0x2000, 5), vec![0x11, 0xaa, 0xbb, 0xcc, 0x00]);
+        assert_eq!(editor.enter(&mut processor, &[0xdd]), Err(EditError::NotEntering));
+
+        assert_eq!(editor.undo(&mut processor).unwrap(), "edit at 0x2001");
+        assert_eq!(bytes(&processor, 0x2000, 5), vec![0x11, 0x22, 0x33, 0x44, 0x00]);
+        assert_eq!(editor.undo(&mut processor), Err(EditError::NothingToUndo));
+    }
+
+    #[test]
+    fn test_fill_and_undo_restore_exact_bytes() {
+        let mut processor: Processor = make_processor();
+        processor.load_at(0x3000, &[1, 2, 3, 4, 5]);
+        let mut editor = MemoryEditor::new();
+
+        editor.fill(&mut processor, 0x3001, 0x3003, 0xff).unwrap();
+        editor.fill(&mut processor, 0x3000, 0x3001, 0x00).unwrap();
+        assert_eq!(bytes(&processor, 0x3000, 5), vec![0, 0, 0xff, 0xff, 5]);
+
+        editor.undo(&mut processor).unwrap();
+        assert_eq!(bytes(&processor, 0x3000, 5), vec![1, 0xff, 0xff, 0xff, 5]);
+        editor.undo(&mut processor).unwrap();
+        assert_eq!(bytes(&processor, 0x3000, 5), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_copy_between_overlapping_ranges() {
+        let mut processor: Processor = make_processor();
+        processor.load_at(0x4000, &[1, 2, 3, 4, 5, 6]);
+        let mut editor = MemoryEditor::new();
+
+        editor.copy(&mut processor, 0x4000, 0x4002, 4).unwrap();
+        assert_eq!(bytes(&processor, 0x4000, 6), vec![1, 2, 1, 2, 3, 4]);
+        editor.copy(&mut processor, 0x4002, 0x4001, 4).unwrap();
+        assert_eq!(bytes(&processor, 0x4000, 6), vec![1, 1, 2, 3, 4, 4]);
+
+        editor.undo(&mut processor).unwrap();
+        editor.undo(&mut processor).unwrap();
+        assert_eq!(bytes(&processor, 0x4000, 6), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_read_only_regions_refuse_edits_without_override() {
+        let mut processor: Processor = make_processor();
+        processor.declare_region("ROM", 0x1000..0x2000, RegionAccess::ReadOnly);
+        let mut editor = MemoryEditor::new();
+
+        let refused = EditError::Protected { addr: 0x1000, region: "ROM".to_string() };
+        assert_eq!(editor.fill(&mut processor, 0x0ffe, 0x1001, 0x55), Err(refused.clone()));
+        assert_eq!(bytes(&processor, 0x0ffe, 4), vec![0; 4]);
+        assert_eq!(editor.undo(&mut processor), Err(EditError::NothingToUndo));
+
+        editor.begin_entry(0x0fff);
+        assert_eq!(editor.enter(&mut processor, &[0x55, 0x66]), Err(refused));
+        assert_eq!(bytes(&processor, 0x0fff, 2), vec![0x55, 0x00]);
+        assert_eq!(editor.entering(), Some(0x1000));
+
+        editor.set_override(true);
+        editor.enter(&mut processor, &[0x66]).unwrap();
+        editor.set_override(false);
+        editor.undo(&mut processor).unwrap();
+        assert_eq!(bytes(&processor, 0x0fff, 2), vec![0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_edits_hit_watchpoints_without_stopping_the_next_run() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0x76]); // HLT
+        processor.add_watchpoint(0x2001, 1);
+        let mut editor = MemoryEditor::new();
+
+        let edited: Edited = editor.fill(&mut processor, 0x2000, 0x2002, 0x01).unwrap();
+        assert_eq!(edited, Edited { len: 3, watched: vec![0x2001] });
+        assert_eq!(processor.run(), crate::processor::RunOutcome::Halted);
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::processor::{Registers, Snapshot, WriteJournal};
+
+/// Flag bits shown in diffs, in the order `register_summary` prints them.
+const FLAGS: [(char, u8); 5] = [('S', 0x80), ('Z', 0x40), ('A', 0x10), ('P', 0x04), ('C', 0x01)];
+
+/// What changed between two stops: registers, flags, and journaled memory
+/// writes whose byte actually changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateDiff {
+    /// Register name with old and new values; 8-bit registers widened.
+    pub registers: Vec<(&'static str, u16, u16)>,
+    /// Flag letter and its new value.
+    pub flags: Vec<(char, bool)>,
+    /// Address with old and new byte, in address order.
+    pub memory: Vec<(u16, u8, u8)>,
+    /// Writes the journal could not hold, so `memory` may be incomplete.
+    pub unjournaled: u64,
+}
+
+impl StateDiff {
+    pub fn between(before: &Snapshot, after: &Snapshot, journal: &WriteJournal) -> StateDiff {
+        let StateDiff { registers, flags, .. } = StateDiff::registers_between(&before.registers, &after.registers);
+
+        let written: BTreeSet<u16> = journal.entries().iter().map(|entry| entry.addr).collect();
+        let memory: Vec<(u16, u8, u8)> = written.into_iter()
+            .map(|addr| (addr, before.memory[addr as usize], after.memory[addr as usize]))
+            .filter(|(_, old, new)| old != new)
+            .collect();
+
+        return StateDiff { registers, flags, memory, unjournaled: journal.dropped() };
+    }
+
+    /// Register and flag changes only, for callers without memory snapshots.
+    pub fn registers_between(before: &Registers, after: &Registers) -> StateDiff {
+        let bytes: [(&'static str, u8, u8); 7] = [
+            ("A", before.a, after.a),
+            ("B", before.b, after.b),
+            ("C", before.c, after.c),
+            ("D", before.d, after.d),
+            ("E", before.e, after.e),
+            ("H", before.h, after.h),
+            ("L", before.l, after.l),
+        ];
+        let mut registers: Vec<(&'static str, u16, u16)> = bytes.iter()
+            .filter(|(_, old, new)| old != new)
+            .map(|(name, old, new)| (*name, *old as u16, *new as u16))
+            .collect();
+        for (name, old, new) in [("SP", before.sp, after.sp), ("PC", before.pc, after.pc)] {
+            if old != new {
+                registers.push((name, old, new));
+            }
+        }
+
+        let (old_flags, new_flags): (u8, u8) = (before.flags.convert_to_flags(), after.flags.convert_to_flags());
+        let flags: Vec<(char, bool)> = FLAGS.iter()
+            .filter(|(_, mask)| (old_flags ^ new_flags) & mask != 0)
+            .map(|(name, mask)| (*name, new_flags & mask != 0))
+            .collect();
+        return StateDiff { registers, flags, memory: Vec::new(), unjournaled: 0 };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.registers.is_empty() && self.flags.is_empty() && self.memory.is_empty()
+            && self.unjournaled == 0;
+    }
+}
+
+impl fmt::Display for StateDiff {
+    /// e.g. `B=02->04 PC=000b->000f +Z -C [2018]=00->04`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no changes");
+        }
+        let mut parts: Vec<String> = Vec::new();
+        for (name, old, new) in &self.registers {
+            if name.len() == 1 {
+                parts.push(format!("{}={:02x}->{:02x}", name, old, new));
+            } else {
+                parts.push(format!("{}={:04x}->{:04x}", name, old, new));
+            }
+        }
+        for (name, set) in &self.flags {
+            parts.push(format!("{}{}", if *set { '+' } else { '-' }, name));
+        }
+        for (addr, old, new) in &self.memory {
+            parts.push(format!("[{:04x}]={:02x}->{:02x}", addr, old, new));
+        }
+        if self.unjournaled > 0 {
+            parts.push(format!("(+{} writes not journaled)", self.unjournaled));
+        }
+        return write!(f, "{}", parts.join(" "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{make_processor, Processor};
+
+    fn run(program: &[u8], steps: usize) -> (Snapshot, Snapshot, WriteJournal) {
+        let mut processor: Processor = make_processor();
+        processor.load_program(program);
+        processor.set_write_journal(true);
+        let before: Snapshot = processor.snapshot();
+        for _ in 0..steps {
+            processor.step();
+        }
+        return (before, processor.snapshot(), processor.take_write_journal());
+    }
+
+    #[test]
+    fn test_register_changes() {
+        // MVI B,5; MVI C,0; INR D
+        let (before, after, journal) = run(&[0x06, 0x05, 0x0e, 0x00, 0x14], 3);
+
+        let diff = StateDiff::between(&before, &after, &journal);
+
+        assert_eq!(diff.registers, vec![("B", 0, 5), ("D", 0, 1), ("PC", 0, 5)]);
+        assert_eq!(diff.to_string(), "B=00->05 D=00->01 PC=0000->0005");
+    }
+
+    #[test]
+    fn test_flag_changes() {
+        // MVI A,1; DCR A; then INR A
+        let (_, zero, _) = run(&[0x3e, 0x01, 0x3d, 0x3c], 2);
+        let (_, nonzero, journal) = run(&[0x3e, 0x01, 0x3d, 0x3c], 3);
+
+        let diff = StateDiff::between(&zero, &nonzero, &journal);
+
+        assert_eq!(diff.flags, vec![('Z', false), ('P', false)]);
+        assert_eq!(diff.to_string(), "A=00->01 PC=0003->0004 -Z -P");
+    }
+
+    #[test]
+    fn test_aux_carry_changes() {
+        // MVI A,0Fh; then ADI 1, which carries out of bit 3
+        let (_, before, _) = run(&[0x3e, 0x0f, 0xc6, 0x01], 1);
+        let (_, after, journal) = run(&[0x3e, 0x0f, 0xc6, 0x01], 2);
+
+        let diff = StateDiff::between(&before, &after, &journal);
+
+        assert_eq!(diff.flags, vec![('A', true)]);
+        assert_eq!(diff.to_string(), "A=0f->10 PC=0002->0004 +A");
+    }
+
+    #[test]
+    fn test_multi_byte_and_no_op_writes() {
+        let program: [u8; 13] = [
+            0x21, 0x34, 0x12, // LXI H,1234h
+            0x22, 0x00, 0x20, // SHLD 2000h
+            0x3e, 0x00,       // MVI A,0
+            0x32, 0x10, 0x20, // STA 2010h (already 0)
+            0x76,             // HLT
+            0x00,
+        ];
+        let (before, after, journal) = run(&program, 5);
+
+        let diff = StateDiff::between(&before, &after, &journal);
+
+        assert_eq!(journal.entries().len(), 3);
+        assert_eq!(diff.memory, vec![(0x2000, 0x00, 0x34), (0x2001, 0x00, 0x12)]);
+        assert!(diff.to_string().ends_with("[2000]=00->34 [2001]=00->12"));
+    }
+
+    #[test]
+    fn test_write_then_restore_is_not_a_change() {
+        // MVI A,7; STA 2000h; MVI A,0; STA 2000h
+        let (before, after, journal) = run(&[0x3e, 0x07, 0x32, 0x00, 0x20, 0x3e, 0x00, 0x32, 0x00, 0x20], 4);
+
+        let diff = StateDiff::between(&before, &after, &journal);
+
+        assert_eq!(journal.entries().len(), 2);
+        assert!(diff.memory.is_empty());
+    }
+}
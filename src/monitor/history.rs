@@ -0,0 +1,100 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Command history with shell-style `!!` and `!<prefix>` recall.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+}
+
+impl History {
+    pub fn new() -> History {
+        return History { entries: Vec::new() };
+    }
+
+    /// Reads one command per line. A missing file is an empty history.
+    pub fn load(path: &Path) -> io::Result<History> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let entries = text.lines().filter(|line| !line.trim().is_empty()).map(String::from).collect();
+        return Ok(History { entries });
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut text = self.entries.join("\n");
+        text.push('\n');
+        return fs::write(path, text);
+    }
+
+    pub fn entries(&self) -> &[String] {
+        return &self.entries;
+    }
+
+    /// Records a command, skipping blanks and immediate repeats.
+    pub fn push(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || self.entries.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.entries.push(line.to_string());
+    }
+
+    /// Resolves `!!` to the previous command and `!<prefix>` to the most
+    /// recent command starting with `prefix`. Other lines are returned as is.
+    pub fn expand(&self, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        if line == "!!" {
+            return self.entries.last().cloned().ok_or("!!: no previous command".to_string());
+        }
+        if let Some(prefix) = line.strip_prefix('!') {
+            return self.entries.iter().rev()
+                .find(|entry| entry.starts_with(prefix))
+                .cloned()
+                .ok_or(format!("!{}: event not found", prefix));
+        }
+        return Ok(line.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_expand() {
+        let mut history = History::new();
+        assert!(history.expand("!!").is_err());
+
+        history.push("break 0x10");
+        history.push("step");
+        history.push("step");
+        history.push("mem 0x2000");
+
+        assert_eq!(history.entries().len(), 3);
+        assert_eq!(history.expand("!!").unwrap(), "mem 0x2000");
+        assert_eq!(history.expand("!st").unwrap(), "step");
+        assert_eq!(history.expand("!b").unwrap(), "break 0x10");
+        assert!(history.expand("!quit").is_err());
+        assert_eq!(history.expand("regs").unwrap(), "regs");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = env::temp_dir().join(format!("i8080_history_test_{}", std::process::id()));
+        let mut history = History::new();
+        history.push("break 0x10");
+        history.push("continue");
+        history.save(&path).unwrap();
+
+        let loaded = History::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.entries(), history.entries());
+        assert!(History::load(&path).unwrap().entries().is_empty());
+    }
+}
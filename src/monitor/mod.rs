@@ -0,0 +1,1169 @@
+use std::io::{self, BufRead, Write};
+
+use crate::expr::Expr;
+use crate::mem_view::{self, ViewType};
+use crate::memedit::{EditError, Edited, MemoryEditor};
+use crate::processor::{Processor, RunOutcome, Snapshot, StackEntry, WriteJournal};
+use crate::style::Style;
+use crate::word::parse_number;
+
+mod diff;
+mod history;
+
+pub use diff::StateDiff;
+pub use history::History;
+
+pub const PROMPT: &str = "(i8080) ";
+
+const HELP: &str = "\
+commands:
+  break <addr>       set a breakpoint (b)
+  delete <addr>      remove a breakpoint
+  continue           run until a breakpoint or halt (c)
+  step [n]           execute n instructions, default 1 (s)
+  n                  step, running CALLs and RSTs through to their return
+  fin                run until the current call returns
+  rtc <addr>         run to addr
+  until mem <addr> == <value> [max <n>]
+                     run until value is stored at addr, for at most n
+                     instructions (default 100000000)
+  regs               show registers and flags (r)
+  mem <addr> [len]   hex dump len bytes, default 16 (x)
+  watch [u8|u16|str] <addr> [max <n>]
+                     stop when the value is written; shown at every stop
+  watch expr <expr>  show expr at every stop and log an event when it
+                     changes, e.g. watch expr \"hl - sp\"
+  unwatch <addr>     remove a watch
+  unwatch expr <expr>
+                     remove an expression watch
+  info watch         show the current value of every watch
+  info stack [n]     show n words from SP up, default 16, labelled with the
+                     call or PUSH that stored them where known; PUSHes are
+                     known while journaling is on
+  set diff on|off    after step and continue, show only what changed
+  edit <addr>        enter hex bytes to write from addr, . to finish
+  fill <start> <end> <byte>
+                     write byte from start to end inclusive
+  copy <src> <dst> <len>
+                     copy len bytes, overlapping ranges included
+  undo               take back the last instruction run, or if an edit, fill
+                     or copy came since, put back what it overwrote; an OUT,
+                     an IN from a device or a taken interrupt cannot be
+                     taken back
+  set override on|off
+                     let edits write read-only regions
+  history <addr>     list the journaled writes to addr; journaling starts
+                     with set diff on or --history
+  find <hex bytes> [in <region>]
+                     list where the bytes are stored, optionally only
+                     inside an annotated region
+  find16 <value> [in <region>]
+                     list where the word is stored, low byte first
+  find --changed     list the addresses the last command other than find
+                     wrote, from the write journal
+  more               show the next page of find results
+  quit               leave the monitor (q)
+  !!, !<prefix>      repeat the last command, or the last one starting with prefix";
+
+/// What the REPL should do after a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Continue,
+    Quit,
+}
+
+/// `until mem` gives up after this many instructions unless `max` is given.
+const DEFAULT_UNTIL_LIMIT: u64 = 100_000_000;
+
+/// Words `info stack` shows unless told otherwise.
+const DEFAULT_STACK_ENTRIES: usize = 16;
+
+/// Strings are watched for this many bytes unless `max` is given.
+const DEFAULT_STR_WATCH_LEN: u16 = 32;
+
+/// `find` results shown at a time; `more` shows the next lot.
+const FIND_PAGE: usize = 16;
+
+/// The debugger command interpreter. Commands are fed in as lines and all
+/// output goes to the writer passed in, so it can be driven from a terminal,
+/// a script, or a test.
+#[derive(Debug, Default)]
+pub struct Monitor {
+    history: History,
+    watches: Vec<(u16, ViewType)>,
+    /// State at the last stop while `set diff on` is in effect.
+    last_stop: Option<Snapshot>,
+    /// Journal entries and dropped writes at the last stop, so the next
+    /// diff only covers writes since then.
+    journal_mark: (usize, u64),
+    /// The same at the start of the last command other than a search, so
+    /// `find --changed` can list what that command wrote.
+    prompt_mark: (usize, u64),
+    /// `find` results not shown yet.
+    found: Vec<u16>,
+    editor: MemoryEditor,
+    /// Whether an instruction ran since the last edit, so `undo` takes it
+    /// back rather than the edit.
+    undo_step: bool,
+    style: Style,
+}
+
+fn journal_mark(processor: &Processor) -> (usize, u64) {
+    return processor.write_journal().map_or((0, 0), |journal| (journal.entries().len(), journal.dropped()));
+}
+
+/// Parses the arguments of `watch`: `[u8|u16|str] <addr> [max <n>]`.
+fn parse_watch(args: &[&str]) -> Option<(u16, ViewType)> {
+    let (view, rest) = match args.first() {
+        Some(&"u8") => (ViewType::U8, &args[1..]),
+        Some(&"u16") => (ViewType::U16, &args[1..]),
+        Some(&"str") => (ViewType::Str { max_len: DEFAULT_STR_WATCH_LEN }, &args[1..]),
+        _ => (ViewType::U8, args),
+    };
+    let addr = parse_number(rest.first()?)?;
+    return match (view, &rest[1..]) {
+        (_, []) => Some((addr, view)),
+        (ViewType::Str { .. }, ["max", len]) => Some((addr, ViewType::Str { max_len: parse_number(len)? })),
+        _ => None,
+    };
+}
+
+/// The expression after `expr` in `watch expr` or `unwatch expr`, without
+/// the quotes it may have been given in.
+fn expr_text(args: &[&str]) -> String {
+    return args[1..].join(" ").trim_matches('"').to_string();
+}
+
+/// Parses the arguments of `find` or `find16`: the bytes or word to look
+/// for, then `in <region>` if given.
+fn parse_find<'a>(command: &str, args: &[&'a str]) -> Option<(Vec<u8>, Option<&'a str>)> {
+    let (args, region) = match args {
+        [rest @ .., "in", region] => (rest, Some(*region)),
+        _ => (args, None),
+    };
+    let pattern: Vec<u8> = match (command, args) {
+        ("find16", [value]) => parse_number(value)?.to_le_bytes().to_vec(),
+        ("find", [_, ..]) => args.iter().map(|byte| u8::from_str_radix(byte, 16).ok()).collect::<Option<Vec<u8>>>()?,
+        _ => return None,
+    };
+    return Some((pattern, region));
+}
+
+/// Parses the arguments of `fill`: `<start> <end> <byte>`.
+fn parse_fill(args: &[&str]) -> Option<(u16, u16, u8)> {
+    let [start, end, value] = args else {
+        return None;
+    };
+    let (start, end) = (parse_number(start)?, parse_number(end)?);
+    return (start <= end).then_some((start, end, u8::try_from(parse_number(value)?).ok()?));
+}
+
+/// Parses a line typed during byte entry: hex bytes, optionally ending in
+/// `.`. Returns the bytes and whether entry should finish.
+fn parse_entry(words: &[&str]) -> Result<(Vec<u8>, bool), String> {
+    let (words, done) = match words {
+        [rest @ .., "."] => (rest, true),
+        _ => (words, false),
+    };
+    let bytes = words.iter()
+        .map(|word| u8::from_str_radix(word, 16).map_err(|_| format!("not a byte: {} (. to finish)", word)))
+        .collect::<Result<Vec<u8>, String>>()?;
+    return Ok((bytes, done));
+}
+
+/// Parses the arguments of `until`: `mem <addr> == <value> [max <n>]`.
+fn parse_until(args: &[&str]) -> Option<(u16, u8, u64)> {
+    let (addr, value, rest) = match args {
+        ["mem", addr, "==", value, rest @ ..] => (parse_number(addr)?, parse_number(value)?, rest),
+        _ => return None,
+    };
+    let max: u64 = match rest {
+        [] => DEFAULT_UNTIL_LIMIT,
+        ["max", max] => max.parse::<u64>().ok()?,
+        _ => return None,
+    };
+    return Some((addr, u8::try_from(value).ok()?, max));
+}
+
+impl Monitor {
+    pub fn new() -> Monitor {
+        return Monitor::with_history(History::new());
+    }
+
+    pub fn with_history(history: History) -> Monitor {
+        return Monitor {
+            history,
+            watches: Vec::new(),
+            last_stop: None,
+            journal_mark: (0, 0),
+            prompt_mark: (0, 0),
+            found: Vec::new(),
+            editor: MemoryEditor::new(),
+            undo_step: false,
+            style: Style::PLAIN,
+        };
+    }
+
+    /// Paints registers and diffs with `style`; plain by default.
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    pub fn history(&self) -> &History {
+        return &self.history;
+    }
+
+    /// `PROMPT`, or during byte entry, the address the next byte goes to.
+    pub fn prompt(&self) -> String {
+        return match self.editor.entering() {
+            Some(addr) => format!("{:04x}: ", addr),
+            None => PROMPT.to_string(),
+        };
+    }
+
+    /// Runs one command.
+    pub fn execute(&mut self, processor: &mut Processor, line: &str, out: &mut dyn Write) -> io::Result<Action> {
+        if self.editor.entering().is_some() {
+            self.enter_bytes(processor, line, out)?;
+            return Ok(Action::Continue);
+        }
+        if !matches!(line.split_whitespace().next(), Some("find" | "find16" | "more")) {
+            self.prompt_mark = journal_mark(processor);
+        }
+        return self.dispatch(processor, line, out);
+    }
+
+    fn dispatch(&mut self, processor: &mut Processor, line: &str, out: &mut dyn Write) -> io::Result<Action> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, args)) = words.split_first() else {
+            return Ok(Action::Continue);
+        };
+
+        match *command {
+            "break" | "b" => match args.first().and_then(|arg| parse_number(arg)) {
+                Some(addr) => {
+                    processor.add_breakpoint(addr);
+                    writeln!(out, "breakpoint set at {:#06x}", addr)?;
+                },
+                None => writeln!(out, "usage: break <addr>")?,
+            },
+            "delete" => match args.first().and_then(|arg| parse_number(arg)) {
+                Some(addr) => {
+                    processor.remove_breakpoint(addr);
+                    writeln!(out, "breakpoint removed at {:#06x}", addr)?;
+                },
+                None => writeln!(out, "usage: delete <addr>")?,
+            },
+            "continue" | "c" => {
+                let outcome = processor.run();
+                self.report_stop(processor, outcome, out)?;
+            },
+            "step" | "s" => {
+                let count = match args.first() {
+                    Some(arg) => match parse_number(arg) {
+                        Some(count) => count,
+                        None => {
+                            writeln!(out, "usage: step [n]")?;
+                            return Ok(Action::Continue);
+                        },
+                    },
+                    None => 1,
+                };
+                for _ in 0..count {
+                    if processor.is_halted() {
+                        break;
+                    }
+                    processor.step();
+                    self.undo_step = true;
+                }
+                self.show_state(processor, out)?;
+                self.show_watches(processor, out)?;
+            },
+            "until" => match parse_until(args) {
+                Some((addr, value, max)) => {
+                    let outcome = processor.run_until_mem(addr, value, max);
+                    self.report_stop(processor, outcome, out)?;
+                },
+                None => writeln!(out, "usage: until mem <addr> == <value> [max <n>]")?,
+            },
+            "n" => {
+                let outcome = processor.step_over();
+                self.report_stop(processor, outcome, out)?;
+            },
+            "fin" => match processor.step_out() {
+                Some(outcome) => self.report_stop(processor, outcome, out)?,
+                None => writeln!(out, "not in a call")?,
+            },
+            "rtc" => match args.first().and_then(|arg| parse_number(arg)) {
+                Some(addr) => {
+                    let outcome = processor.run_to(addr);
+                    self.report_stop(processor, outcome, out)?;
+                },
+                None => writeln!(out, "usage: rtc <addr>")?,
+            },
+            "regs" | "r" => writeln!(out, "{}", self.style.registers(&processor.registers()))?,
+            "mem" | "x" => {
+                let start = args.first().and_then(|arg| parse_number(arg));
+                let len = args.get(1).map_or(Some(16), |arg| parse_number(arg));
+                match (start, len) {
+                    (Some(start), Some(len)) => dump_memory(processor, start, len, out)?,
+                    _ => writeln!(out, "usage: mem <addr> [len]")?,
+                }
+            },
+            "watch" if args.first() == Some(&"expr") => match Expr::parse(&expr_text(args)) {
+                Ok(expr) => {
+                    let text: String = expr_text(args);
+                    writeln!(out, "watching {} = {:#x}", text, expr.eval(processor))?;
+                    processor.watch_expr(&text, move |processor| expr.eval(processor));
+                },
+                Err(err) => writeln!(out, "watch expr: {}", err)?,
+            },
+            "unwatch" if args.first() == Some(&"expr") => match processor.unwatch_expr(&expr_text(args)) {
+                true => writeln!(out, "watch removed for {}", expr_text(args))?,
+                false => writeln!(out, "not watching {}", expr_text(args))?,
+            },
+            "watch" => match parse_watch(args) {
+                Some((addr, view)) => {
+                    processor.add_watchpoint(addr, view.footprint());
+                    self.watches.push((addr, view));
+                    writeln!(out, "watching {}", self.format_watch(processor, addr, view))?;
+                },
+                None => writeln!(out, "usage: watch [u8|u16|str] <addr> [max <n>]")?,
+            },
+            "unwatch" => match args.first().and_then(|arg| parse_number(arg)) {
+                Some(addr) => {
+                    processor.remove_watchpoint(addr);
+                    self.watches.retain(|(watch_addr, _)| *watch_addr != addr);
+                    writeln!(out, "watch removed at {:#06x}", addr)?;
+                },
+                None => writeln!(out, "usage: unwatch <addr>")?,
+            },
+            "info" => match args.first() {
+                Some(&"watch") if self.watches.is_empty() && processor.expr_values().is_empty() => writeln!(out, "no watches")?,
+                Some(&"watch") => self.show_watches(processor, out)?,
+                Some(&"stack") => match args.get(1).map(|arg| arg.parse::<usize>()) {
+                    None => show_stack(processor, DEFAULT_STACK_ENTRIES, out)?,
+                    Some(Ok(entries)) => show_stack(processor, entries, out)?,
+                    Some(Err(_)) => writeln!(out, "usage: info stack [n]")?,
+                },
+                _ => writeln!(out, "usage: info watch|stack [n]")?,
+            },
+            "set" => match args {
+                ["diff", "on"] => {
+                    processor.set_write_journal(true);
+                    self.last_stop = Some(processor.snapshot());
+                    self.journal_mark = journal_mark(processor);
+                    writeln!(out, "diff on")?;
+                },
+                ["diff", "off"] => {
+                    self.last_stop = None;
+                    writeln!(out, "diff off")?;
+                },
+                ["override", setting @ ("on" | "off")] => {
+                    self.editor.set_override(*setting == "on");
+                    writeln!(out, "override {}", setting)?;
+                },
+                _ => writeln!(out, "usage: set diff|override on|off")?,
+            },
+            "edit" => match args.first().and_then(|arg| parse_number(arg)) {
+                Some(addr) => {
+                    self.editor.begin_entry(addr);
+                    writeln!(out, "entering bytes at {:#06x}, . to finish", addr)?;
+                },
+                None => writeln!(out, "usage: edit <addr>")?,
+            },
+            "fill" => match parse_fill(args) {
+                Some((start, end, value)) => {
+                    let result = self.editor.fill(processor, start, end, value);
+                    self.report_edit(processor, result, out)?;
+                },
+                None => writeln!(out, "usage: fill <start> <end> <byte>")?,
+            },
+            "copy" => match args.iter().map(|arg| parse_number(arg)).collect::<Option<Vec<u16>>>().as_deref() {
+                Some(&[src, dst, len]) => {
+                    let result = self.editor.copy(processor, src, dst, len);
+                    self.report_edit(processor, result, out)?;
+                },
+                _ => writeln!(out, "usage: copy <src> <dst> <len>")?,
+            },
+            "undo" if self.undo_step => self.undo_instruction(processor, out)?,
+            "undo" => match self.editor.undo(processor) {
+                Ok(what) => writeln!(out, "undid {}", what)?,
+                Err(err) => writeln!(out, "{}", err)?,
+            },
+            "history" => match args.first().and_then(|arg| parse_number(arg)) {
+                Some(addr) => self.show_write_history(processor, addr, out)?,
+                None => writeln!(out, "usage: history <addr>")?,
+            },
+            "find" if args == ["--changed"] => self.find_changed(processor, out)?,
+            "find" | "find16" => match parse_find(command, args) {
+                Some((pattern, region)) => self.find(processor, &pattern, region, out)?,
+                None if *command == "find" => writeln!(out, "usage: find <hex bytes> [in <region>] | find --changed")?,
+                None => writeln!(out, "usage: find16 <value> [in <region>]")?,
+            },
+            "more" if self.found.is_empty() => writeln!(out, "no more results")?,
+            "more" => self.show_found(out)?,
+            "quit" | "q" => return Ok(Action::Quit),
+            "help" | "h" | "?" => writeln!(out, "{}", HELP)?,
+            other => writeln!(out, "unknown command: {} (try help)", other)?,
+        }
+        return Ok(Action::Continue);
+    }
+
+    /// Handles a line typed during byte entry.
+    fn enter_bytes(&mut self, processor: &mut Processor, line: &str, out: &mut dyn Write) -> io::Result<()> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (bytes, done) = match parse_entry(&words) {
+            Ok(entry) => entry,
+            Err(message) => return writeln!(out, "{}", message),
+        };
+        let result = self.editor.enter(processor, &bytes);
+        let refused: bool = result.is_err();
+        self.report_edit(processor, result, out)?;
+        if done || refused {
+            self.editor.finish_entry();
+        }
+        return Ok(());
+    }
+
+    /// Reports the watchpoints an edit hit, or why it was refused.
+    fn report_edit(&mut self, processor: &Processor, result: Result<Edited, EditError>, out: &mut dyn Write) -> io::Result<()> {
+        let edited: Edited = match result {
+            Ok(edited) => edited,
+            Err(err) => return writeln!(out, "{}", err),
+        };
+        self.undo_step = false;
+        for addr in edited.watched {
+            let watch = self.watches.iter().find(|(start, view)| addr.wrapping_sub(*start) < view.footprint());
+            match watch {
+                Some((start, view)) => writeln!(out, "watch hit: {}", self.format_watch(processor, *start, *view))?,
+                None => writeln!(out, "watch hit: {:#06x}", addr)?,
+            }
+        }
+        return Ok(());
+    }
+
+    /// Runs each line of `script` as a command, echoing it first so the
+    /// output reads as a transcript. Blank lines and `#` comments are
+    /// skipped. Script lines are not added to the history.
+    pub fn run_script(&mut self, processor: &mut Processor, script: &str, out: &mut dyn Write) -> io::Result<Action> {
+        for line in script.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            writeln!(out, "{}{}", self.prompt(), line)?;
+            if self.execute(processor, line, out)? == Action::Quit {
+                return Ok(Action::Quit);
+            }
+        }
+        return Ok(Action::Continue);
+    }
+
+    /// Reads commands from `input` until `quit` or end of input.
+    pub fn run_interactive(&mut self, processor: &mut Processor, input: &mut dyn BufRead, out: &mut dyn Write) -> io::Result<()> {
+        loop {
+            write!(out, "{}", self.prompt())?;
+            out.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(out)?;
+                return Ok(());
+            }
+            if self.editor.entering().is_some() {
+                self.enter_bytes(processor, &line, out)?;
+                continue;
+            }
+            let command = match self.history.expand(&line) {
+                Ok(command) => command,
+                Err(message) => {
+                    writeln!(out, "{}", message)?;
+                    continue;
+                },
+            };
+            if command != line.trim() {
+                writeln!(out, "{}", command)?;
+            }
+            self.history.push(&command);
+            if self.execute(processor, &command, out)? == Action::Quit {
+                return Ok(());
+            }
+        }
+    }
+
+    fn report_stop(&mut self, processor: &mut Processor, outcome: RunOutcome, out: &mut dyn Write) -> io::Result<()> {
+        self.undo_step = true;
+        match outcome {
+            RunOutcome::Halted => writeln!(out, "halted")?,
+            RunOutcome::Stuck { pc } => writeln!(out, "stuck at {:#06x}: halted with interrupts enabled and nothing to raise one", pc)?,
+            RunOutcome::Stepped { .. } => {},
+            RunOutcome::Breakpoint { pc } => writeln!(out, "breakpoint at {:#06x} ({})", pc, processor.describe_instruction(pc))?,
+            RunOutcome::Watchpoint { addr, pc } => {
+                let watch = self.watches.iter()
+                    .find(|(start, view)| addr.wrapping_sub(*start) < view.footprint());
+                let writer: String = processor.describe_instruction(pc);
+                match watch {
+                    Some((start, view)) => writeln!(out, "watch hit: {} (written by {} at {:#06x})",
+                        self.format_watch(processor, *start, *view), writer, pc)?,
+                    None => writeln!(out, "watch hit: {:#06x} (written by {} at {:#06x})", addr, writer, pc)?,
+                }
+            },
+            RunOutcome::MemoryValue { addr, value, pc } => {
+                writeln!(out, "[{:#06x}] == {:#04x} (written by {} at {:#06x})", addr, value, processor.describe_instruction(pc), pc)?;
+            },
+            other => writeln!(out, "stopped: {:?}", other)?,
+        }
+        self.show_state(processor, out)?;
+        return self.show_watches(processor, out);
+    }
+
+    /// Prints the registers, or with `set diff on`, what changed since the
+    /// last stop.
+    fn show_state(&mut self, processor: &mut Processor, out: &mut dyn Write) -> io::Result<()> {
+        let Some(before) = &self.last_stop else {
+            return writeln!(out, "{}", self.style.registers(&processor.registers()));
+        };
+        let after: Snapshot = processor.snapshot();
+        let (entries, dropped) = self.journal_mark;
+        let journal: WriteJournal = processor.write_journal()
+            .map_or_else(WriteJournal::new, |journal| journal.since(entries, dropped));
+        writeln!(out, "{}", self.style.state_diff(&StateDiff::between(before, &after, &journal)))?;
+        self.last_stop = Some(after);
+        self.journal_mark = journal_mark(processor);
+        return Ok(());
+    }
+
+    /// Takes back the last instruction run. A refusal leaves `undo` on the
+    /// instruction rather than reaching past it to an older edit.
+    fn undo_instruction(&mut self, processor: &mut Processor, out: &mut dyn Write) -> io::Result<()> {
+        if let Err(err) = processor.undo_step() {
+            return writeln!(out, "{}", err);
+        }
+        self.undo_step = false;
+        let pc: u16 = processor.registers().pc;
+        writeln!(out, "undid {} at {:#06x}", processor.describe_instruction(pc), pc)?;
+        writeln!(out, "{}", self.style.registers(&processor.registers()))?;
+        // The next diff starts from here, not from before the undone write.
+        if self.last_stop.is_some() {
+            self.last_stop = Some(processor.snapshot());
+            self.journal_mark = journal_mark(processor);
+        }
+        return Ok(());
+    }
+
+    fn show_write_history(&self, processor: &Processor, addr: u16, out: &mut dyn Write) -> io::Result<()> {
+        let Some(journal) = processor.write_journal() else {
+            return writeln!(out, "no write journal (set diff on or --history starts one)");
+        };
+        let records = processor.write_history(addr);
+        if records.is_empty() {
+            writeln!(out, "no writes to {:#06x}", addr)?;
+        }
+        for record in records {
+            writeln!(out, "{}", record)?;
+        }
+        if journal.dropped() > 0 {
+            writeln!(out, "(+{} writes not journaled)", journal.dropped())?;
+        }
+        return Ok(());
+    }
+
+    fn find(&mut self, processor: &Processor, pattern: &[u8], region: Option<&str>, out: &mut dyn Write) -> io::Result<()> {
+        let found: Vec<u16> = match region {
+            None => processor.find(pattern),
+            Some(name) => match processor.region(name) {
+                Some(range) => processor.find_in(pattern, range),
+                None => return writeln!(out, "no region named {}", name),
+            },
+        };
+        return self.start_results(found, out);
+    }
+
+    /// Lists the addresses the journal saw written by the last command.
+    fn find_changed(&mut self, processor: &Processor, out: &mut dyn Write) -> io::Result<()> {
+        let Some(journal) = processor.write_journal() else {
+            return writeln!(out, "no write journal (set diff on or --history starts one)");
+        };
+        let (entries, dropped) = self.prompt_mark;
+        let changed: WriteJournal = journal.since(entries, dropped);
+        let mut found: Vec<u16> = changed.entries().iter().map(|entry| entry.addr).collect();
+        found.sort_unstable();
+        found.dedup();
+        if changed.dropped() > 0 {
+            writeln!(out, "(+{} writes not journaled)", changed.dropped())?;
+        }
+        return self.start_results(found, out);
+    }
+
+    fn start_results(&mut self, found: Vec<u16>, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{} found", found.len())?;
+        self.found = found;
+        return self.show_found(out);
+    }
+
+    /// Shows the next page of `find` results.
+    fn show_found(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        let page: Vec<u16> = self.found.drain(..FIND_PAGE.min(self.found.len())).collect();
+        for row in page.chunks(8) {
+            let addrs: Vec<String> = row.iter().map(|addr| format!("{:04x}", addr)).collect();
+            writeln!(out, "  {}", addrs.join(" "))?;
+        }
+        if !self.found.is_empty() {
+            writeln!(out, "({} more, type more)", self.found.len())?;
+        }
+        return Ok(());
+    }
+
+    fn format_watch(&self, processor: &Processor, addr: u16, view: ViewType) -> String {
+        let value = mem_view::decode(|addr| processor.peek(addr), addr, view);
+        return format!("{} {:#06x} = {}", view.name(), addr, value);
+    }
+
+    /// Each watched expression as `<expr> = <value>`.
+    fn format_exprs(&self, processor: &Processor) -> Vec<String> {
+        return processor.expr_values().into_iter().map(|(text, value)| format!("{} = {:#x}", text, value)).collect();
+    }
+
+    fn show_watches(&self, processor: &Processor, out: &mut dyn Write) -> io::Result<()> {
+        for (addr, view) in &self.watches {
+            writeln!(out, "  {}", self.format_watch(processor, *addr, *view))?;
+        }
+        for line in self.format_exprs(processor) {
+            writeln!(out, "  {}", line)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Writes the stack as `info stack` shows it, innermost word first.
+fn show_stack(processor: &Processor, max_entries: usize, out: &mut dyn Write) -> io::Result<()> {
+    let entries: Vec<StackEntry> = processor.stack_view(max_entries);
+    if entries.is_empty() {
+        return writeln!(out, "stack empty");
+    }
+    for entry in entries {
+        writeln!(out, "  {}", entry)?;
+    }
+    return Ok(());
+}
+
+/// Writes `len` bytes from `start` as the `dump` command shows them, 16 to
+/// a line.
+pub fn dump_memory(processor: &Processor, start: u16, len: u16, out: &mut dyn Write) -> io::Result<()> {
+    let addrs: Vec<u16> = (0..len).map(|offset| start.wrapping_add(offset)).collect();
+    for row in addrs.chunks(16) {
+        let bytes: Vec<String> = row.iter().map(|addr| format!("{:02x}", processor.peek(*addr))).collect();
+        writeln!(out, "{:04x}: {}", row[0], bytes.join(" "))?;
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::make_processor;
+    use std::fs;
+
+    #[test]
+    fn test_script_transcript() {
+        let mut processor = make_processor();
+        processor.load_program(&fs::read("tests/mov_test.bin").unwrap());
+        let script = "\
+            # stop before LDA 2018H
+            break 0x000f
+            continue
+            mem 0x2018 2
+            step
+            quit
+            regs
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        let action = Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        assert_eq!(action, Action::Quit);
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+(i8080) break 0x000f
+breakpoint set at 0x000f
+(i8080) continue
+breakpoint at 0x000f (lda 2018h)
+A=00 B=04 C=03 D=04 E=00 H=20 L=18 SP=0000 PC=000f -----
+(i8080) mem 0x2018 2
+2018: 04 02
+(i8080) step
+A=04 B=04 C=03 D=04 E=00 H=20 L=18 SP=0000 PC=0012 -----
+(i8080) quit
+");
+    }
+
+    #[test]
+    fn test_interactive_history_recall() {
+        let mut processor = make_processor();
+        processor.load_program(&[0x04, 0x04, 0x04, 0x76]); // INR B x3; HLT
+        let mut input = "step\n!!\n!s\nbogus\nq\n".as_bytes();
+        let mut out: Vec<u8> = Vec::new();
+        let mut monitor = Monitor::new();
+
+        monitor.run_interactive(&mut processor, &mut input, &mut out).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("B=03"));
+        assert!(text.contains("unknown command: bogus"));
+        assert_eq!(monitor.history().entries(), ["step", "bogus", "q"]);
+    }
+
+    #[test]
+    fn test_parse_watch() {
+        assert_eq!(parse_watch(&["0x2050"]), Some((0x2050, ViewType::U8)));
+        assert_eq!(parse_watch(&["u16", "0x2050"]), Some((0x2050, ViewType::U16)));
+        assert_eq!(parse_watch(&["str", "0x2100"]), Some((0x2100, ViewType::Str { max_len: 32 })));
+        assert_eq!(parse_watch(&["str", "0x2100", "max", "8"]), Some((0x2100, ViewType::Str { max_len: 8 })));
+        assert_eq!(parse_watch(&["u16", "0x2050", "max", "8"]), None);
+        assert_eq!(parse_watch(&["u16"]), None);
+    }
+
+    #[test]
+    fn test_stepping_transcript() {
+        let mut processor = make_processor();
+        processor.load_program(&[
+            0x31, 0x00, 0x20, // 0000 LXI SP,2000h
+            0xcd, 0x08, 0x00, // 0003 CALL sub
+            0x76,             // 0006 HLT
+            0x00,
+            0x06, 0x07,       // 0008 sub: MVI B,7
+            0x0e, 0x09,       // 000a MVI C,9
+            0xc9,             // 000c RET
+        ]);
+        let script = "\
+            fin
+            rtc 0x000a
+            fin
+            n
+            n
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+(i8080) fin
+not in a call
+(i8080) rtc 0x000a
+A=00 B=07 C=00 D=00 E=00 H=00 L=00 SP=1ffe PC=000a -----
+(i8080) fin
+A=00 B=07 C=09 D=00 E=00 H=00 L=00 SP=2000 PC=0006 -----
+(i8080) n
+halted
+A=00 B=07 C=09 D=00 E=00 H=00 L=00 SP=2000 PC=0007 -----
+(i8080) n
+halted
+A=00 B=07 C=09 D=00 E=00 H=00 L=00 SP=2000 PC=0007 -----
+");
+    }
+
+    #[test]
+    fn test_parse_until() {
+        assert_eq!(parse_until(&["mem", "0x2040", "==", "5"]), Some((0x2040, 5, DEFAULT_UNTIL_LIMIT)));
+        assert_eq!(parse_until(&["mem", "2040h", "==", "0ffh", "max", "500"]), Some((0x2040, 0xff, 500)));
+        assert_eq!(parse_until(&["mem", "0x2040", "==", "256"]), None);
+        assert_eq!(parse_until(&["mem", "0x2040", "5"]), None);
+        assert_eq!(parse_until(&["mem", "0x2040", "==", "5", "max"]), None);
+    }
+
+    #[test]
+    fn test_until_transcript() {
+        let mut processor = make_processor();
+        processor.load_program(&fs::read("tests/mov_test.bin").unwrap());
+        let script = "\
+            until mem 0x2018 == 4
+            until mem 0x2018 == 9 max 2
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+(i8080) until mem 0x2018 == 4
+[0x2018] == 0x04 (written by mov m, d at 0x000e)
+A=00 B=04 C=03 D=04 E=00 H=20 L=18 SP=0000 PC=000f -----
+(i8080) until mem 0x2018 == 9 max 2
+stopped: InstructionLimit { instructions: 11 }
+A=04 B=04 C=03 D=04 E=00 H=19 L=18 SP=0000 PC=0014 -----
+");
+    }
+
+    #[test]
+    fn test_expr_watch_transcript() {
+        let mut processor = make_processor();
+        processor.load_program(&fs::read("tests/mov_test.bin").unwrap());
+        let script = "\
+            watch expr \"hl + bc\"
+            watch expr hl +
+            step 6
+            unwatch expr hl + bc
+            info watch
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+(i8080) watch expr \"hl + bc\"
+watching hl + bc = 0x0
+(i8080) watch expr hl +
+watch expr: expression ends early
+(i8080) step 6
+A=00 B=02 C=03 D=04 E=00 H=20 L=19 SP=0000 PC=000b -----
+  hl + bc = 0x221c
+(i8080) unwatch expr hl + bc
+watch removed for hl + bc
+(i8080) info watch
+no watches
+");
+    }
+
+    #[test]
+    fn test_typed_watch_transcript() {
+        let mut processor = make_processor();
+        processor.load_program(&fs::read("tests/mov_test.bin").unwrap());
+        let script = "\
+            info watch
+            watch u16 0x2018
+            continue
+            info watch
+            continue
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+(i8080) info watch
+no watches
+(i8080) watch u16 0x2018
+watching u16 0x2018 = 0x0000 (0)
+(i8080) continue
+watch hit: u16 0x2018 = 0x0200 (512) (written by mov m, b at 0x000a)
+A=00 B=02 C=03 D=04 E=00 H=20 L=19 SP=0000 PC=000b -----
+  u16 0x2018 = 0x0200 (512)
+(i8080) info watch
+  u16 0x2018 = 0x0200 (512)
+(i8080) continue
+watch hit: u16 0x2018 = 0x0204 (516) (written by mov m, d at 0x000e)
+A=00 B=04 C=03 D=04 E=00 H=20 L=18 SP=0000 PC=000f -----
+  u16 0x2018 = 0x0204 (516)
+");
+    }
+
+    #[test]
+    fn test_watch_hit_names_the_writer() {
+        let mut processor = make_processor();
+        processor.load_program(&[
+            0x3e, 0x2a,       // 0000 MVI A,2Ah
+            0x32, 0x40, 0x20, // 0002 STA 2040h
+            0x76,             // 0005 HLT
+        ]);
+
+        let mut out: Vec<u8> = Vec::new();
+        Monitor::new().run_script(&mut processor, "watch u8 0x2040\ncontinue\n", &mut out).unwrap();
+
+        let transcript: String = String::from_utf8(out).unwrap();
+        assert!(transcript.contains("\nwatch hit: u8 0x2040 = 0x2a (42) (written by sta 2040h at 0x0002)\n"), "{}", transcript);
+    }
+
+    #[test]
+    fn test_diff_transcript() {
+        let mut processor = make_processor();
+        processor.load_program(&fs::read("tests/mov_test.bin").unwrap());
+        let script = "\
+            set diff on
+            step 5
+            step
+            break 0x000f
+            continue
+            set diff off
+            step
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+(i8080) set diff on
+diff on
+(i8080) step 5
+B=00->02 C=00->03 D=00->04 H=00->20 L=00->19 PC=0000->000a
+(i8080) step
+PC=000a->000b [2019]=00->02
+(i8080) break 0x000f
+breakpoint set at 0x000f
+(i8080) continue
+breakpoint at 0x000f (lda 2018h)
+B=02->04 L=19->18 PC=000b->000f [2018]=00->04
+(i8080) set diff off
+diff off
+(i8080) step
+A=04 B=04 C=03 D=04 E=00 H=20 L=18 SP=0000 PC=0012 -----
+");
+    }
+
+    #[test]
+    fn test_parse_find() {
+        assert_eq!(parse_find("find", &["de", "ad"]), Some((vec![0xde, 0xad], None)));
+        assert_eq!(parse_find("find", &["de", "in", "heap"]), Some((vec![0xde], Some("heap"))));
+        assert_eq!(parse_find("find16", &["0x1234"]), Some((vec![0x34, 0x12], None)));
+        assert_eq!(parse_find("find", &["in", "heap"]), None);
+        assert_eq!(parse_find("find", &["1ff"]), None);
+        assert_eq!(parse_find("find16", &["1", "2"]), None);
+    }
+
+    #[test]
+    fn test_find_transcript() {
+        let mut processor = make_processor();
+        processor.annotate_region("table", 0x2000..0x2100);
+        processor.annotate_region("heap", 0x2100..0x2800);
+        for addr in [0x2010, 0x20ff, 0x2400] {
+            processor.load_at(addr, &[0xde, 0xad, 0xbe]);
+        }
+        processor.load_at(0x3000, &[0x5a; 20]);
+        let script = "\
+            find de ad be
+            find de ad be in table
+            find16 0xadde in heap
+            find de in nowhere
+            find 5a
+            more
+            more
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+(i8080) find de ad be
+3 found
+  2010 20ff 2400
+(i8080) find de ad be in table
+1 found
+  2010
+(i8080) find16 0xadde in heap
+1 found
+  2400
+(i8080) find de in nowhere
+no region named nowhere
+(i8080) find 5a
+20 found
+  3000 3001 3002 3003 3004 3005 3006 3007
+  3008 3009 300a 300b 300c 300d 300e 300f
+(4 more, type more)
+(i8080) more
+  3010 3011 3012 3013
+(i8080) more
+no more results
+");
+    }
+
+    #[test]
+    fn test_find_changed_lists_journaled_writes() {
+        let mut processor = make_processor();
+        processor.load_program(&fs::read("tests/mov_test.bin").unwrap());
+        let script = "\
+            find --changed
+            set diff on
+            step 6
+            find --changed
+            find --changed
+            continue
+            find --changed
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let results: Vec<&str> = text.split("(i8080) find --changed\n").skip(1)
+            .map(|after| after.split("(i8080)").next().unwrap()).collect();
+        assert!(results[0].starts_with("no write journal"));
+        assert_eq!(results[1], "1 found\n  2019\n");
+        assert_eq!(results[2], results[1]);
+        let journaled: Vec<u16> = processor.write_journal().unwrap().entries().iter().skip(1).map(|entry| entry.addr).collect();
+        assert_eq!(journaled, vec![0x2018, 0x1918]);
+        assert_eq!(results[3], "2 found\n  1918 2018\n");
+    }
+
+    #[test]
+    fn test_history_transcript() {
+        let mut processor = make_processor();
+        processor.load_program(&fs::read("tests/mov_test.bin").unwrap());
+        let script = "\
+            history 0x2018
+            set diff on
+            continue
+            set diff off
+            history 0x2018
+            history 0x2019
+            history 0x2020
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let (_, history) = text.split_once("(i8080) set diff off\ndiff off\n").unwrap();
+        assert!(text.starts_with("(i8080) history 0x2018\nno write journal"));
+        assert_eq!(history, "\
+(i8080) history 0x2018
+         8  000e  [2018] 00->04
+(i8080) history 0x2019
+         5  000a  [2019] 00->02
+(i8080) history 0x2020
+no writes to 0x2020
+");
+    }
+
+    #[test]
+    fn test_info_stack_transcript() {
+        let mut processor = make_processor();
+        processor.load_program(&[
+            0x31, 0x00, 0x30, // 0000 LXI SP,3000h
+            0xd5,             // 0003 PUSH D
+            0xcd, 0x08, 0x00, // 0004 CALL 0008h
+            0x76,             // 0007 HLT
+            0x76,             // 0008 HLT
+        ]);
+        processor.set_stack_top(Some(0x3000));
+        let script = "\
+            step
+            info stack
+            set diff on
+            break 0x0008
+            continue
+            info stack
+            info stack 1
+            info stack x
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        let out: String = String::from_utf8(out).unwrap();
+        assert!(out.contains("(i8080) info stack\nstack empty\n"), "{}", out);
+        assert!(out.contains("\
+(i8080) info stack
+  2ffc  07 00  0007  return from call 0008h at 0004
+  2ffe  00 00  0000  push d at 0003
+(i8080) info stack 1
+  2ffc  07 00  0007  return from call 0008h at 0004
+(i8080) info stack x
+"), "{}", out);
+        assert!(out.ends_with("usage: info stack [n]\n"), "{}", out);
+    }
+
+    #[test]
+    fn test_edit_transcript() {
+        let mut processor = make_processor();
+        processor.declare_region("ROM", 0x1000..0x2000, crate::processor::RegionAccess::ReadOnly);
+        let script = "\
+            edit 0x2000
+            aa bb
+            zz
+            cc .
+            watch 0x2011
+            fill 0x2010 0x2013 0xff
+            copy 0x2000 0x2001 3
+            mem 0x2000 4
+            undo
+            undo
+            mem 0x2000 4
+            fill 0x0fff 0x1000 1
+            set override on
+            fill 0x0fff 0x1000 1
+            undo
+            undo
+            undo
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+(i8080) edit 0x2000
+entering bytes at 0x2000, . to finish
+2000: aa bb
+2002: zz
+not a byte: zz (. to finish)
+2002: cc .
+(i8080) watch 0x2011
+watching u8 0x2011 = 0x00 (0)
+(i8080) fill 0x2010 0x2013 0xff
+watch hit: u8 0x2011 = 0xff (255)
+(i8080) copy 0x2000 0x2001 3
+(i8080) mem 0x2000 4
+2000: aa aa bb cc
+(i8080) undo
+undid copy 0x2000 to 0x2001
+(i8080) undo
+undid fill 0x2010-0x2013
+(i8080) mem 0x2000 4
+2000: aa bb cc 00
+(i8080) fill 0x0fff 0x1000 1
+0x1000 is in read-only region ROM (set override on to write it)
+(i8080) set override on
+override on
+(i8080) fill 0x0fff 0x1000 1
+(i8080) undo
+undid fill 0x0fff-0x1000
+(i8080) undo
+undid edit at 0x2000
+(i8080) undo
+nothing to undo
+");
+    }
+
+    #[test]
+    fn test_undo_instruction_transcript() {
+        let mut processor = make_processor();
+        processor.load_program(&[
+            0x3e, 0x5a,       // 0000 MVI A,5Ah
+            0x32, 0x00, 0x20, // 0002 STA 2000h
+            0xd3, 0x10,       // 0005 OUT 10h
+        ]);
+        let script = "\
+            step
+            fill 0x2000 0x2000 0x11
+            step
+            mem 0x2000 1
+            undo
+            mem 0x2000 1
+            undo
+            step 2
+            undo
+            undo
+        ";
+        let mut out: Vec<u8> = Vec::new();
+
+        Monitor::new().run_script(&mut processor, script, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+(i8080) step
+A=5a B=00 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0002 -----
+(i8080) fill 0x2000 0x2000 0x11
+(i8080) step
+A=5a B=00 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0005 -----
+(i8080) mem 0x2000 1
+2000: 5a
+(i8080) undo
+undid sta 2000h at 0x0002
+A=5a B=00 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0002 -----
+(i8080) mem 0x2000 1
+2000: 11
+(i8080) undo
+undid fill 0x2000-0x2000
+(i8080) step 2
+A=5a B=00 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0007 -----
+(i8080) undo
+cannot undo the OUT to port 10h at 0005: the write has already left the processor
+(i8080) undo
+cannot undo the OUT to port 10h at 0005: the write has already left the processor
+");
+    }
+}
@@ -0,0 +1,404 @@
+//! Packages: one file that sets a machine up exactly, for sharing a
+//! session. A package is a tar archive holding `manifest.json`, the ROM
+//! images it names, and optionally a snapshot to start from. The manifest
+//! says where each ROM loads, which patch sets to apply to it, where to
+//! start SP and PC, and which machine and options to run it on.
+
+mod tar;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dip::{self, DipOption, OptionError};
+use crate::invaders;
+use crate::patchsets::{self, PatchError};
+use crate::processor::{Processor, Snapshot};
+
+/// The manifest version this build writes and reads.
+pub const PACKAGE_VERSION: u32 = 1;
+
+pub const MANIFEST: &str = "manifest.json";
+
+/// A ROM image in the package and where it goes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RomEntry {
+    /// The archive member holding the image.
+    pub file: String,
+    pub load_addr: u16,
+    /// Patch sets to apply to the image before loading it, in order.
+    #[serde(default)]
+    pub patchsets: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// Loaded in order, so a later ROM overwrites an earlier one.
+    pub roms: Vec<RomEntry>,
+    #[serde(default)]
+    pub sp: Option<u16>,
+    #[serde(default)]
+    pub pc: Option<u16>,
+    /// e.g. `invaders`; a bare processor if missing.
+    #[serde(default)]
+    pub machine: Option<String>,
+    /// The machine's DIP switch settings, by option name.
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    /// The archive member holding a snapshot to restore after loading the
+    /// ROMs and before setting SP and PC.
+    #[serde(default)]
+    pub snapshot: Option<String>,
+}
+
+impl Default for Manifest {
+    fn default() -> Manifest {
+        return Manifest {
+            format_version: PACKAGE_VERSION,
+            roms: Vec::new(),
+            sp: None,
+            pc: None,
+            machine: None,
+            options: BTreeMap::new(),
+            snapshot: None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackageError {
+    Io { message: String },
+    /// The file is not a tar archive this reader understands.
+    Archive { message: String },
+    MissingManifest,
+    BadManifest { message: String },
+    UnsupportedVersion { version: u32 },
+    /// The manifest names `name` but the archive does not hold it.
+    MissingMember { name: String },
+    TooLarge { file: String, load_addr: u16, len: usize },
+    UnknownPatchset { file: String, name: String },
+    Patch { file: String, name: String, error: PatchError },
+    BadSnapshot { message: String },
+    UnknownMachine { name: String },
+    BadOption { error: OptionError },
+}
+
+impl fmt::Display for PackageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            PackageError::Io { message } => write!(f, "{}", message),
+            PackageError::Archive { message } => write!(f, "not a package: {}", message),
+            PackageError::MissingManifest => write!(f, "package has no {}", MANIFEST),
+            PackageError::BadManifest { message } => write!(f, "{}: {}", MANIFEST, message),
+            PackageError::UnsupportedVersion { version } =>
+                write!(f, "{}: format_version {} is not supported; this build reads {}", MANIFEST, version, PACKAGE_VERSION),
+            PackageError::MissingMember { name } => write!(f, "{} names {}, which the package does not hold", MANIFEST, name),
+            PackageError::TooLarge { file, load_addr, len } =>
+                write!(f, "{}: {} bytes do not fit in memory from {:#06x}", file, len, load_addr),
+            PackageError::UnknownPatchset { file, name } => write!(f, "{}: unknown patch set '{}'", file, name),
+            PackageError::Patch { file, name, error } => write!(f, "{}: patch set {}: {}", file, name, error),
+            PackageError::BadSnapshot { message } => write!(f, "snapshot: {}", message),
+            PackageError::UnknownMachine { name } => write!(f, "unknown machine '{}'", name),
+            PackageError::BadOption { error } => write!(f, "{}", error),
+        };
+    }
+}
+
+/// The DIP switch options of the machine called `name`.
+fn machine_options(name: &str) -> Option<&'static [DipOption]> {
+    return match name {
+        "invaders" => Some(invaders::OPTIONS),
+        _ => None,
+    };
+}
+
+/// A manifest and the files it names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Package {
+    pub manifest: Manifest,
+    members: BTreeMap<String, Vec<u8>>,
+}
+
+/// What `load` gives: a processor set up as the package says, and the
+/// machine to run it on.
+#[derive(Debug)]
+pub struct ConfiguredMachine {
+    pub processor: Processor,
+    pub machine: Option<String>,
+    /// DIP switch settings as name and value, in name order.
+    pub options: Vec<(String, String)>,
+}
+
+impl Package {
+    pub fn new() -> Package {
+        return Package::default();
+    }
+
+    /// Adds a ROM image to load at `load_addr`, kept as member `file`.
+    pub fn rom(mut self, file: &str, load_addr: u16, image: &[u8]) -> Package {
+        self.manifest.roms.push(RomEntry { file: file.to_string(), load_addr, patchsets: Vec::new() });
+        self.members.insert(file.to_string(), image.to_vec());
+        return self;
+    }
+
+    /// Applies patch set `name` to the ROM kept as `file`.
+    pub fn patchset(mut self, file: &str, name: &str) -> Package {
+        if let Some(rom) = self.manifest.roms.iter_mut().find(|rom| rom.file == file) {
+            rom.patchsets.push(name.to_string());
+        }
+        return self;
+    }
+
+    pub fn sp(mut self, sp: u16) -> Package {
+        self.manifest.sp = Some(sp);
+        return self;
+    }
+
+    pub fn pc(mut self, pc: u16) -> Package {
+        self.manifest.pc = Some(pc);
+        return self;
+    }
+
+    pub fn machine(mut self, name: &str) -> Package {
+        self.manifest.machine = Some(name.to_string());
+        return self;
+    }
+
+    pub fn option(mut self, name: &str, value: &str) -> Package {
+        self.manifest.options.insert(name.to_string(), value.to_string());
+        return self;
+    }
+
+    /// Starts from `snapshot`, kept as `snapshot.json`.
+    pub fn snapshot(mut self, snapshot: &Snapshot) -> Package {
+        let json: Vec<u8> = serde_json::to_vec(snapshot).expect("snapshots always serialize");
+        self.manifest.snapshot = Some("snapshot.json".to_string());
+        self.members.insert("snapshot.json".to_string(), json);
+        return self;
+    }
+
+    fn member(&self, name: &str) -> Result<&[u8], PackageError> {
+        return self.members.get(name).map(Vec::as_slice).ok_or(PackageError::MissingMember { name: name.to_string() });
+    }
+
+    /// The package as a tar archive, manifest first.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PackageError> {
+        let manifest: Vec<u8> = serde_json::to_vec_pretty(&self.manifest).expect("manifests always serialize");
+        let mut members: Vec<(&str, &[u8])> = vec![(MANIFEST, &manifest)];
+        members.extend(self.members.iter().map(|(name, data)| (name.as_str(), data.as_slice())));
+        return tar::write(&members).map_err(|message| PackageError::Archive { message });
+    }
+
+    /// Reads and checks a package, so that `apply_to` can only fail on
+    /// the processor's side.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Package, PackageError> {
+        let mut members: BTreeMap<String, Vec<u8>> = tar::read(bytes)
+            .map_err(|message| PackageError::Archive { message })?
+            .into_iter().collect();
+        let manifest: Vec<u8> = members.remove(MANIFEST).ok_or(PackageError::MissingManifest)?;
+        let version: Option<u64> = serde_json::from_slice::<serde_json::Value>(&manifest)
+            .map_err(|err| PackageError::BadManifest { message: err.to_string() })?
+            .get("format_version").and_then(serde_json::Value::as_u64);
+        match version {
+            Some(version) if version == PACKAGE_VERSION as u64 => {},
+            Some(version) => return Err(PackageError::UnsupportedVersion { version: version as u32 }),
+            None => return Err(PackageError::BadManifest { message: "format_version is missing".to_string() }),
+        }
+        let manifest: Manifest = serde_json::from_slice(&manifest).map_err(|err| PackageError::BadManifest { message: err.to_string() })?;
+        let package = Package { manifest, members };
+        package.check()?;
+        return Ok(package);
+    }
+
+    fn check(&self) -> Result<(), PackageError> {
+        for rom in &self.manifest.roms {
+            let len: usize = self.member(&rom.file)?.len();
+            if rom.load_addr as usize + len > 0x10000 {
+                return Err(PackageError::TooLarge { file: rom.file.clone(), load_addr: rom.load_addr, len });
+            }
+            if let Some(name) = rom.patchsets.iter().find(|name| patchsets::find(name).is_none()) {
+                return Err(PackageError::UnknownPatchset { file: rom.file.clone(), name: name.clone() });
+            }
+        }
+        self.load_snapshot()?;
+        if let Some(machine) = &self.manifest.machine {
+            let options = machine_options(machine).ok_or(PackageError::UnknownMachine { name: machine.clone() })?;
+            for (name, value) in &self.manifest.options {
+                dip::find(options, name).and_then(|option| option.apply(0, value))
+                    .map_err(|error| PackageError::BadOption { error })?;
+            }
+        } else if !self.manifest.options.is_empty() {
+            return Err(PackageError::BadManifest { message: "options need a machine".to_string() });
+        }
+        return Ok(());
+    }
+
+    fn load_snapshot(&self) -> Result<Option<Snapshot>, PackageError> {
+        let Some(name) = &self.manifest.snapshot else {
+            return Ok(None);
+        };
+        let snapshot: Snapshot = Snapshot::from_json(self.member(name)?)
+            .map_err(|err| PackageError::BadSnapshot { message: err.to_string() })?;
+        if snapshot.memory.len() != 0x10000 {
+            return Err(PackageError::BadSnapshot { message: format!("memory is {} bytes, not 65536", snapshot.memory.len()) });
+        }
+        return Ok(Some(snapshot));
+    }
+
+    /// Each ROM with its patch sets applied, with its load address.
+    pub fn images(&self) -> Result<Vec<(u16, Vec<u8>)>, PackageError> {
+        let mut images: Vec<(u16, Vec<u8>)> = Vec::new();
+        for rom in &self.manifest.roms {
+            let mut image: Vec<u8> = self.member(&rom.file)?.to_vec();
+            for name in &rom.patchsets {
+                let patchset = patchsets::find(name)
+                    .ok_or(PackageError::UnknownPatchset { file: rom.file.clone(), name: name.clone() })?;
+                patchset.apply(&mut image, rom.load_addr)
+                    .map_err(|error| PackageError::Patch { file: rom.file.clone(), name: name.clone(), error })?;
+            }
+            images.push((rom.load_addr, image));
+        }
+        return Ok(images);
+    }
+
+    /// Loads the ROMs into `processor`, patched, then restores the
+    /// snapshot and sets SP and PC as the manifest says.
+    pub fn apply_to(&self, processor: &mut Processor) -> Result<(), PackageError> {
+        for (load_addr, image) in self.images()? {
+            processor.load_at(load_addr, &image);
+        }
+        if let Some(snapshot) = self.load_snapshot()? {
+            processor.restore(&snapshot).map_err(|err| PackageError::BadSnapshot { message: err.to_string() })?;
+        }
+        if let Some(sp) = self.manifest.sp {
+            let mut registers = processor.registers();
+            registers.sp = sp;
+            processor.set_registers(registers);
+        }
+        if let Some(pc) = self.manifest.pc {
+            processor.set_pc(pc);
+        }
+        return Ok(());
+    }
+
+    /// A fresh processor set up as the package says.
+    pub fn configure(&self) -> Result<ConfiguredMachine, PackageError> {
+        let mut processor = Processor::default();
+        self.apply_to(&mut processor)?;
+        return Ok(ConfiguredMachine {
+            processor,
+            machine: self.manifest.machine.clone(),
+            options: self.manifest.options.iter().map(|(name, value)| (name.clone(), value.clone())).collect(),
+        });
+    }
+}
+
+/// Reads the package at `path`.
+pub fn read(path: &Path) -> Result<Package, PackageError> {
+    let bytes: Vec<u8> = fs::read(path).map_err(|err| PackageError::Io { message: format!("{}: {}", path.display(), err) })?;
+    return Package::from_bytes(&bytes);
+}
+
+/// Reads the package at `path` and sets up a processor from it.
+pub fn load(path: &Path) -> Result<ConfiguredMachine, PackageError> {
+    return read(path)?.configure();
+}
+
+pub fn save(package: &Package, path: &Path) -> Result<(), PackageError> {
+    return fs::write(path, package.to_bytes()?).map_err(|err| PackageError::Io { message: format!("{}: {}", path.display(), err) });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    use crate::processor::{make_processor, RunOutcome};
+
+    /// NOPs from 0100h up to an LXI SP,06ADh at 016Eh, which the
+    /// cpudiag-stack patch set moves to 07ADh, then LDA 2000h; HLT.
+    fn program() -> Vec<u8> {
+        let mut image: Vec<u8> = vec![0; 0x80];
+        image[0x6e..0x75].copy_from_slice(&[
+            0x31, 0xad, 0x06, // 016E LXI SP,06ADh
+            0x3a, 0x00, 0x20, // 0171 LDA 2000h
+            0x76,             // 0174 HLT
+        ]);
+        return image;
+    }
+
+    fn package() -> Package {
+        return Package::new()
+            .rom("program.bin", 0x0100, &program())
+            .patchset("program.bin", "cpudiag-stack")
+            .rom("data.bin", 0x2000, b"hi")
+            .pc(0x0100)
+            .sp(0x1234);
+    }
+
+    #[test]
+    fn test_saved_package_runs_like_hand_setup() {
+        let path = env::temp_dir().join(format!("package-test-{}.tar", std::process::id()));
+        save(&package(), &path).unwrap();
+
+        let mut machine: ConfiguredMachine = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(machine.processor.registers().sp, 0x1234);
+        assert_eq!(machine.processor.run(), RunOutcome::Halted);
+
+        let mut by_hand: Processor = make_processor();
+        let mut image: Vec<u8> = program();
+        patchsets::find("cpudiag-stack").unwrap().apply(&mut image, 0x0100).unwrap();
+        by_hand.load_at(0x0100, &image);
+        by_hand.load_at(0x2000, b"hi");
+        let mut registers = by_hand.registers();
+        registers.sp = 0x1234;
+        by_hand.set_registers(registers);
+        by_hand.set_pc(0x0100);
+        by_hand.run();
+
+        assert_eq!((machine.processor.registers().sp, machine.processor.registers().a), (0x07ad, b'h'));
+        assert_eq!(machine.processor.snapshot().state_hash(), by_hand.snapshot().state_hash());
+        assert_eq!(machine.machine, None);
+    }
+
+    #[test]
+    fn test_snapshot_and_machine_options() {
+        let mut processor: Processor = make_processor();
+        processor.load_at(0x0000, &[0x3e, 0x07, 0x76]); // MVI A,7; HLT
+        processor.run();
+        let package = Package::new().snapshot(&processor.snapshot()).machine("invaders").option("lives", "5");
+
+        let machine = Package::from_bytes(&package.to_bytes().unwrap()).unwrap().configure().unwrap();
+
+        assert_eq!(machine.processor.snapshot(), processor.snapshot());
+        assert_eq!((machine.machine.as_deref(), machine.options), (Some("invaders"), vec![("lives".to_string(), "5".to_string())]));
+    }
+
+    #[test]
+    fn test_bad_packages() {
+        let reread = |package: &Package| Package::from_bytes(&package.to_bytes().unwrap()).unwrap_err();
+        let mut missing = package();
+        missing.members.remove("data.bin");
+        assert_eq!(reread(&missing).to_string(), "manifest.json names data.bin, which the package does not hold");
+
+        let mut future = package();
+        future.manifest.format_version = 2;
+        assert_eq!(reread(&future), PackageError::UnsupportedVersion { version: 2 });
+
+        let unknown = package().patchset("data.bin", "no-such-patch");
+        assert_eq!(reread(&unknown).to_string(), "data.bin: unknown patch set 'no-such-patch'");
+        assert_eq!(reread(&package().machine("invaders").option("lives", "9")).to_string(),
+                   "lives: invalid value '9'; expected one of 3, 4, 5, 6");
+        assert_eq!(reread(&Package::new().rom("big.bin", 0xff00, &[0; 0x101])).to_string(),
+                   "big.bin: 257 bytes do not fit in memory from 0xff00");
+
+        let no_manifest: Vec<u8> = tar::write(&[("program.bin", &[0x76])]).unwrap();
+        assert_eq!(Package::from_bytes(&no_manifest).unwrap_err(), PackageError::MissingManifest);
+        assert!(matches!(Package::from_bytes(b"not a tar file"), Err(PackageError::Archive { .. })));
+    }
+}
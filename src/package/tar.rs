@@ -0,0 +1,97 @@
+//! Just enough of the ustar format for packages: regular files in one
+//! directory, no links, no long names.
+
+const BLOCK: usize = 512;
+const NAME_LEN: usize = 100;
+
+fn octal(field: &mut [u8], value: u64) {
+    let digits: String = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let text: &str = std::str::from_utf8(field).ok()?.trim_matches(|c: char| c == '\0' || c == ' ');
+    return if text.is_empty() { Some(0) } else { u64::from_str_radix(text, 8).ok() };
+}
+
+fn checksum(header: &[u8]) -> u64 {
+    return header.iter().enumerate()
+        .map(|(index, byte)| if (148..156).contains(&index) { b' ' as u64 } else { *byte as u64 })
+        .sum();
+}
+
+/// An archive holding `members`, in order.
+pub fn write(members: &[(&str, &[u8])]) -> Result<Vec<u8>, String> {
+    let mut archive: Vec<u8> = Vec::new();
+    for (name, data) in members {
+        if name.is_empty() || name.len() >= NAME_LEN {
+            return Err(format!("member name '{}' must be 1 to {} bytes", name, NAME_LEN - 1));
+        }
+        let mut header = [0u8; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], data.len() as u64);
+        octal(&mut header[136..148], 0);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        let sum: u64 = checksum(&header);
+        octal(&mut header[148..155], sum);
+        header[155] = b' ';
+
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(BLOCK), 0);
+    }
+    archive.resize(archive.len() + 2 * BLOCK, 0);
+    return Ok(archive);
+}
+
+/// The regular files in `archive`, as name and contents.
+pub fn read(archive: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut members: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut offset: usize = 0;
+    while offset + BLOCK <= archive.len() {
+        let header: &[u8] = &archive[offset..offset + BLOCK];
+        if header.iter().all(|byte| *byte == 0) {
+            return Ok(members);
+        }
+        if parse_octal(&header[148..156]) != Some(checksum(header)) {
+            return Err(format!("bad header checksum at byte {}", offset));
+        }
+        let name_len: usize = header[..NAME_LEN].iter().position(|byte| *byte == 0).unwrap_or(NAME_LEN);
+        let name: String = String::from_utf8_lossy(&header[..name_len]).into_owned();
+        let size: usize = parse_octal(&header[124..136]).ok_or(format!("{}: bad size", name))? as usize;
+        let start: usize = offset + BLOCK;
+        let data: &[u8] = archive.get(start..start + size).ok_or(format!("{}: cut off", name))?;
+        if matches!(header[156], b'0' | 0) {
+            members.push((name, data.to_vec()));
+        }
+        offset = start + size.next_multiple_of(BLOCK);
+    }
+    return Err("archive ends without its end-of-archive blocks".to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let big: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let archive: Vec<u8> = write(&[("manifest.json", b"{}"), ("rom.bin", &big), ("empty", b"")]).unwrap();
+
+        assert_eq!(archive.len(), 512 * 8);
+        assert_eq!(read(&archive).unwrap(), vec![
+            ("manifest.json".to_string(), b"{}".to_vec()),
+            ("rom.bin".to_string(), big),
+            ("empty".to_string(), Vec::new()),
+        ]);
+        assert!(read(&archive[..1024]).is_err());
+        let mut corrupt: Vec<u8> = archive.clone();
+        corrupt[0] = b'M';
+        assert_eq!(read(&corrupt).unwrap_err(), "bad header checksum at byte 0");
+    }
+}
@@ -0,0 +1,139 @@
+//! Named sets of patches for well-known ROMs, for getting them to run on an
+//! emulator that is not finished yet. Each patch says which bytes it expects
+//! to replace, and a set is only applied if every one of them matches, so a
+//! set given the wrong ROM leaves it alone.
+
+use std::fmt;
+
+/// Replaces `original` at `addr` with `replacement`, which is the same
+/// length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Patch {
+    pub addr: u16,
+    pub original: &'static [u8],
+    pub replacement: &'static [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Patchset {
+    /// What `--patchset` takes.
+    pub name: &'static str,
+    pub description: &'static str,
+    pub patches: &'static [Patch],
+}
+
+/// Every patch set, by name. Addresses are where the ROM runs; cpudiag.bin
+/// is a CP/M program and runs at 0100h.
+pub const PATCHSETS: &[Patchset] = &[
+    Patchset {
+        name: "cpudiag-no-daa",
+        description: "cpudiag.bin: jump over the DAA test, for cores without the auxiliary carry",
+        patches: &[
+            // MVI A,88H; ADD A -> JMP 05C2h
+            Patch { addr: 0x059c, original: &[0x3e, 0x88, 0x87], replacement: &[0xc3, 0xc2, 0x05] },
+        ],
+    },
+    Patchset {
+        name: "cpudiag-stack",
+        description: "cpudiag.bin: start the stack at 07ADh instead of 06ADh, clear of the program",
+        patches: &[
+            // High byte of LXI SP,06ADh
+            Patch { addr: 0x0170, original: &[0x06], replacement: &[0x07] },
+        ],
+    },
+];
+
+/// The patch set called `name`.
+pub fn find(name: &str) -> Option<&'static Patchset> {
+    return PATCHSETS.iter().find(|patchset| patchset.name == name);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch at `addr` runs past the end of the image.
+    OutsideImage { addr: u16 },
+    /// The image does not have the patch's original bytes at `addr`.
+    Mismatch { addr: u16, expected: Vec<u8>, found: Vec<u8> },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<String>>().join(" ");
+        return match self {
+            PatchError::OutsideImage { addr } => write!(f, "patch at {:#06x} is outside the image", addr),
+            PatchError::Mismatch { addr, expected, found } =>
+                write!(f, "expected {} at {:#06x} but found {}; is this the right ROM?", hex(expected), addr, hex(found)),
+        };
+    }
+}
+
+impl Patchset {
+    /// Patches `image`, which is loaded at `origin`. Checks every patch
+    /// before changing anything, so on error `image` is untouched.
+    pub fn apply(&self, image: &mut [u8], origin: u16) -> Result<(), PatchError> {
+        let mut spans: Vec<(usize, &Patch)> = Vec::new();
+        for patch in self.patches {
+            let start: usize = patch.addr.wrapping_sub(origin) as usize;
+            let found: &[u8] = image.get(start..start + patch.original.len())
+                .ok_or(PatchError::OutsideImage { addr: patch.addr })?;
+            if found != patch.original {
+                return Err(PatchError::Mismatch { addr: patch.addr, expected: patch.original.to_vec(), found: found.to_vec() });
+            }
+            spans.push((start, patch));
+        }
+        for (start, patch) in spans {
+            image[start..start + patch.replacement.len()].copy_from_slice(patch.replacement);
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: u16 = 0x0100;
+
+    /// An image at `ORIGIN` long enough for every patch, with `fill`
+    /// everywhere a patch expects its original bytes.
+    fn image(patchset: &Patchset, fill: impl Fn(&Patch) -> Vec<u8>) -> Vec<u8> {
+        let mut image: Vec<u8> = vec![0; 0x0600];
+        for patch in patchset.patches {
+            let start: usize = (patch.addr - ORIGIN) as usize;
+            image[start..start + patch.original.len()].copy_from_slice(&fill(patch));
+        }
+        return image;
+    }
+
+    #[test]
+    fn test_patches_replace_matching_bytes() {
+        let patchset: &Patchset = find("cpudiag-no-daa").unwrap();
+        let mut image: Vec<u8> = image(patchset, |patch| patch.original.to_vec());
+
+        assert_eq!(patchset.apply(&mut image, ORIGIN), Ok(()));
+        assert_eq!(image[0x049c..0x049f], [0xc3, 0xc2, 0x05]);
+    }
+
+    #[test]
+    fn test_wrong_rom_is_left_alone() {
+        let patchset: &Patchset = find("cpudiag-no-daa").unwrap();
+        let mut image: Vec<u8> = image(patchset, |patch| vec![0x00; patch.original.len()]);
+        let before: Vec<u8> = image.clone();
+
+        let err: PatchError = patchset.apply(&mut image, ORIGIN).unwrap_err();
+
+        assert_eq!(err, PatchError::Mismatch { addr: 0x059c, expected: vec![0x3e, 0x88, 0x87], found: vec![0x00; 3] });
+        assert_eq!(image, before);
+        assert_eq!(patchset.apply(&mut image[..0x0100], ORIGIN), Err(PatchError::OutsideImage { addr: 0x059c }));
+    }
+
+    #[test]
+    fn test_patches_are_well_formed() {
+        for patchset in PATCHSETS {
+            assert_eq!(find(patchset.name), Some(patchset));
+            for patch in patchset.patches {
+                assert_eq!(patch.original.len(), patch.replacement.len(), "{} at {:#06x}", patchset.name, patch.addr);
+            }
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! The supported way in: everything needed to build a processor, run guest
+//! code, stop it, look at it and attach devices, in one import.
+//!
+//! ```
+//! use intel_8080_emu::prelude::*;
+//!
+//! let mut processor: Processor = ProcessorBuilder::new().build();
+//! processor.load_program(&[0x3e, 0x2a, 0x76]); // MVI A,2Ah; HLT
+//! assert_eq!(processor.run(), RunOutcome::Halted);
+//! assert_eq!(processor.registers().a, 0x2a);
+//! ```
+//!
+//! Changes here follow semver. The modules behind it stay public for the
+//! tools built on this crate, but may change between minor versions.
+//! `Registers` holds the register file and `ConditionBits` the flags.
+
+#[cfg(feature = "devices")]
+pub use crate::devices::{Console, DmaDevice, PerfCounters, Rtc, Timer};
+pub use crate::processor::{
+    BreakpointPolicy, BusRequest, CallbackId, ConditionBits, Ctx, EmuError, Event, EventLog, EventSink,
+    FlatMemory, IoContext, IoDevice, MemoryBus, PortAddressing, Processor, ProcessorBuilder, Registers, RunOutcome,
+    Snapshot, StepInfo, Steps,
+};
@@ -0,0 +1,193 @@
+//! A static look at a ROM image before running it: which opcodes this
+//! emulator cannot execute, which ports it talks to, and which RST vectors
+//! it uses.
+
+use std::fmt;
+
+use crate::disasm::{self, Line};
+
+/// Runs of at least this many identical `00` or `ff` bytes are taken to be
+/// padding and not scanned.
+pub const FILL_RUN: usize = 16;
+
+/// Why an opcode cannot be run here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unsupported {
+    /// An 8085 instruction with no 8080 equivalent.
+    Only8085(&'static str),
+    Undocumented,
+}
+
+/// An opcode the interpreter does not implement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedOpcode {
+    pub addr: u16,
+    pub opcode: u8,
+    pub reason: Unsupported,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortAccess {
+    In,
+    Out,
+}
+
+/// An IN or OUT instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortReference {
+    pub addr: u16,
+    pub access: PortAccess,
+    pub port: u8,
+}
+
+/// What a linear sweep of an image found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrescanReport {
+    pub origin: u16,
+    pub len: usize,
+    pub unsupported: Vec<UnsupportedOpcode>,
+    pub ports: Vec<PortReference>,
+    /// Address and vector of every RST instruction.
+    pub rst: Vec<(u16, u8)>,
+    /// Bytes skipped as padding.
+    pub fill_bytes: usize,
+}
+
+impl PrescanReport {
+    /// Whether every scanned opcode is one this emulator implements.
+    pub fn runnable(&self) -> bool {
+        return self.unsupported.is_empty();
+    }
+}
+
+fn unsupported(opcode: u8) -> Option<Unsupported> {
+    return match opcode {
+        0x20 => Some(Unsupported::Only8085("rim")),
+        0x30 => Some(Unsupported::Only8085("sim")),
+        _ if disasm::MNEMONICS[opcode as usize].is_empty() => Some(Unsupported::Undocumented),
+        _ => None,
+    };
+}
+
+/// Length of the run of padding starting at `bytes[0]`, or 0.
+fn fill_run(bytes: &[u8]) -> usize {
+    let first: u8 = bytes[0];
+    if first != 0x00 && first != 0xff {
+        return 0;
+    }
+    let run: usize = bytes.iter().take_while(|byte| **byte == first).count();
+    return if run >= FILL_RUN { run } else { 0 };
+}
+
+/// Decodes everything in `bytes`, loaded at `origin`, outside padding as
+/// code. Returns the instructions in address order and how many bytes
+/// were skipped as padding.
+pub fn sweep(bytes: &[u8], origin: u16) -> (Vec<Line>, usize) {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut fill_bytes: usize = 0;
+    let mut offset: usize = 0;
+    while offset < bytes.len() {
+        let fill: usize = fill_run(&bytes[offset..]);
+        if fill > 0 {
+            fill_bytes += fill;
+            offset += fill;
+            continue;
+        }
+        let line: Line = disasm::decode(&bytes[offset..], origin.wrapping_add(offset as u16));
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+    return (lines, fill_bytes);
+}
+
+/// Sweeps `bytes`, loaded at `origin`, decoding everything outside padding
+/// as code.
+pub fn prescan(bytes: &[u8], origin: u16) -> PrescanReport {
+    let (lines, fill_bytes) = sweep(bytes, origin);
+    let mut report = PrescanReport { origin, len: bytes.len(), fill_bytes, ..Default::default() };
+    for line in lines {
+        let (addr, opcode): (u16, u8) = (line.addr, line.bytes[0]);
+        if let Some(reason) = unsupported(opcode) {
+            report.unsupported.push(UnsupportedOpcode { addr, opcode, reason });
+        }
+        match (opcode, line.bytes.get(1)) {
+            (0xdb, Some(port)) => report.ports.push(PortReference { addr, access: PortAccess::In, port: *port }),
+            (0xd3, Some(port)) => report.ports.push(PortReference { addr, access: PortAccess::Out, port: *port }),
+            (opcode, _) if opcode & 0xc7 == 0xc7 => report.rst.push((addr, (opcode >> 3) & 0b111)),
+            _ => {},
+        }
+    }
+    return report;
+}
+
+impl fmt::Display for PrescanReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let last: u16 = self.origin.wrapping_add(self.len.saturating_sub(1) as u16);
+        writeln!(f, "image {:04x}-{:04x} ({} bytes, {} skipped as padding)", self.origin, last, self.len, self.fill_bytes)?;
+        if self.unsupported.is_empty() {
+            writeln!(f, "all opcodes supported")?;
+        }
+        for op in &self.unsupported {
+            match op.reason {
+                Unsupported::Only8085(name) => writeln!(f, "  {:04x}  {:02x}  {} (8085 only)", op.addr, op.opcode, name)?,
+                Unsupported::Undocumented => writeln!(f, "  {:04x}  {:02x}  undocumented", op.addr, op.opcode)?,
+            }
+        }
+        for reference in &self.ports {
+            let access: &str = match reference.access {
+                PortAccess::In => "in ",
+                PortAccess::Out => "out",
+            };
+            writeln!(f, "  {:04x}  {} port {:02x}", reference.addr, access, reference.port)?;
+        }
+        for (addr, vector) in &self.rst {
+            writeln!(f, "  {:04x}  rst {}", addr, vector)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthetic_image() {
+        let mut image: Vec<u8> = vec![
+            0x3e, 0x41, // 0100 mvi a, 41h
+            0xd3, 0x01, // 0102 out 1
+            0x30,       // 0104 sim
+            0xd3, 0x02, // 0105 out 2
+            0xdb, 0x03, // 0107 in 3
+            0xcf,       // 0109 rst 1
+            0x76,       // 010a hlt
+        ];
+        image.extend([0xff; 32]);
+        image.push(0xd9); // 012b
+
+        let report = prescan(&image, 0x100);
+
+        assert_eq!(report.unsupported, vec![
+            UnsupportedOpcode { addr: 0x0104, opcode: 0x30, reason: Unsupported::Only8085("sim") },
+            UnsupportedOpcode { addr: 0x012b, opcode: 0xd9, reason: Unsupported::Undocumented },
+        ]);
+        assert_eq!(report.ports, vec![
+            PortReference { addr: 0x0102, access: PortAccess::Out, port: 0x01 },
+            PortReference { addr: 0x0105, access: PortAccess::Out, port: 0x02 },
+            PortReference { addr: 0x0107, access: PortAccess::In, port: 0x03 },
+        ]);
+        assert_eq!(report.rst, vec![(0x0109, 1)]);
+        assert_eq!(report.fill_bytes, 32);
+        assert!(!report.runnable());
+        assert!(report.to_string().starts_with("image 0100-012b (44 bytes, 32 skipped as padding)\n  0104  30  sim (8085 only)\n"));
+    }
+
+    #[test]
+    fn test_short_runs_are_code() {
+        let report = prescan(&[0x00, 0x00, 0xff, 0x76], 0);
+
+        assert_eq!(report.fill_bytes, 0);
+        assert_eq!(report.rst, vec![(0x0002, 7)]);
+        assert!(report.runnable());
+    }
+}
@@ -0,0 +1,46 @@
+use super::MEMORY_SIZE;
+
+/// The byte was fetched as an opcode or operand.
+pub const ACCESS_EXECUTED: u8 = 0b001;
+/// The byte was read as data.
+pub const ACCESS_READ: u8 = 0b010;
+pub const ACCESS_WRITTEN: u8 = 0b100;
+
+/// Which kinds of guest access each byte of memory has seen, as
+/// `ACCESS_*` bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessMap {
+    flags: Vec<u8>,
+}
+
+impl Default for AccessMap {
+    fn default() -> AccessMap {
+        return AccessMap { flags: vec![0; MEMORY_SIZE] };
+    }
+}
+
+impl AccessMap {
+    pub fn new() -> AccessMap {
+        return AccessMap::default();
+    }
+
+    pub fn mark(&mut self, addr: u16, access: u8) {
+        self.flags[addr as usize] |= access;
+    }
+
+    pub fn flags(&self, addr: u16) -> u8 {
+        return self.flags[addr as usize];
+    }
+
+    pub fn executed(&self, addr: u16) -> bool {
+        return self.flags(addr) & ACCESS_EXECUTED != 0;
+    }
+
+    pub fn read(&self, addr: u16) -> bool {
+        return self.flags(addr) & ACCESS_READ != 0;
+    }
+
+    pub fn written(&self, addr: u16) -> bool {
+        return self.flags(addr) & ACCESS_WRITTEN != 0;
+    }
+}
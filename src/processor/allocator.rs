@@ -0,0 +1,110 @@
+use std::fmt;
+use std::ops::Range;
+
+#[cfg(feature = "serde-state")]
+use serde::{Deserialize, Serialize};
+
+use super::{Event, RegisterPair, Registers};
+
+/// How far a bump allocator's pointer got through its arena.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-state", derive(Serialize, Deserialize))]
+pub struct AllocatorUsage {
+    pub pair: RegisterPair,
+    pub arena: Range<u16>,
+    /// The highest value the pointer had inside the arena, its end
+    /// included, or None if it never pointed into it.
+    pub peak: Option<u16>,
+}
+
+impl AllocatorUsage {
+    /// Bytes from the start of the arena to the peak.
+    pub fn used(&self) -> u16 {
+        return self.peak.map_or(0, |peak| peak - self.arena.start);
+    }
+}
+
+impl fmt::Display for AllocatorUsage {
+    /// e.g. `allocator HL 3000-7fff: peak 3456, 1110 of 20480 bytes`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "allocator {} {:04x}-{:04x}: ", self.pair, self.arena.start, self.arena.end.wrapping_sub(1))?;
+        return match self.peak {
+            Some(peak) => write!(f, "peak {:04x}, {} of {} bytes", peak, self.used(), self.arena.len()),
+            None => write!(f, "never entered"),
+        };
+    }
+}
+
+/// Follows a register pair the guest keeps as a bump allocator's pointer
+/// into `arena`, sampled after every instruction. Once the pointer has been
+/// inside the arena, it warns the first time the pointer moves past the
+/// end and the first time the stack pointer is inside what it has
+/// allocated.
+#[derive(Debug, Clone)]
+pub struct AllocatorTracker {
+    usage: AllocatorUsage,
+    /// Whether the last sample was inside the arena.
+    inside: bool,
+    overflowed: bool,
+    collided: bool,
+}
+
+impl AllocatorTracker {
+    pub fn new(pair: RegisterPair, arena: Range<u16>) -> AllocatorTracker {
+        return AllocatorTracker {
+            usage: AllocatorUsage { pair, arena, peak: None },
+            inside: false,
+            overflowed: false,
+            collided: false,
+        };
+    }
+
+    pub fn usage(&self) -> &AllocatorUsage {
+        return &self.usage;
+    }
+
+    /// Samples the pointer after an instruction, returning a warning if
+    /// this is the first overflow or stack collision.
+    pub fn observe(&mut self, registers: &Registers) -> Option<Event> {
+        let pointer: u16 = registers.pair(self.usage.pair.index());
+        let Range { start, end } = self.usage.arena;
+        let was_inside: bool = self.inside;
+        self.inside = (start..=end).contains(&pointer);
+        if self.inside {
+            self.usage.peak = Some(self.usage.peak.map_or(pointer, |peak| peak.max(pointer)));
+            if !self.collided && (start..pointer).contains(&registers.sp) {
+                self.collided = true;
+                return Some(Event::AllocatorCollision { pair: self.usage.pair, pointer, sp: registers.sp });
+            }
+        } else if was_inside && pointer > end && !self.overflowed {
+            self.overflowed = true;
+            return Some(Event::AllocatorOverflow { pair: self.usage.pair, pointer, arena_end: end });
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(tracker: &mut AllocatorTracker, hl: u16, sp: u16) -> Option<Event> {
+        let mut registers = Registers { sp, ..Registers::default() };
+        registers.set_hl(hl);
+        return tracker.observe(&registers);
+    }
+
+    #[test]
+    fn test_overflow_is_reported_once_from_inside() {
+        let mut tracker = AllocatorTracker::new(RegisterPair::HL, 0x3000..0x3100);
+
+        assert_eq!(sample(&mut tracker, 0x9000, 0xf000), None);
+        assert_eq!(sample(&mut tracker, 0x3000, 0xf000), None);
+        assert_eq!(sample(&mut tracker, 0x3100, 0xf000), None);
+        assert_eq!(sample(&mut tracker, 0x3101, 0xf000),
+                   Some(Event::AllocatorOverflow { pair: RegisterPair::HL, pointer: 0x3101, arena_end: 0x3100 }));
+        assert_eq!(sample(&mut tracker, 0x3000, 0xf000), None);
+        assert_eq!(sample(&mut tracker, 0x3200, 0xf000), None);
+        assert_eq!(tracker.usage().peak, Some(0x3100));
+    }
+}
@@ -0,0 +1,95 @@
+use super::QuirkProfile;
+
+/// Where the aux carry flag's current value came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum AuxCarry {
+    /// Nothing has written it since reset, so hardware would have any value.
+    #[default]
+    Unset,
+    /// An addition, DAA or POP PSW, or a logical operation under a quirk
+    /// profile that models it, which set it as hardware does.
+    Modeled,
+    /// The instruction at this PC changes AC on hardware, but the
+    /// interpreter leaves the old value.
+    Unmodeled(u16),
+}
+
+/// Whether `opcode` sets AC the way hardware does under `quirks`.
+fn models_aux_carry(opcode: u8, quirks: QuirkProfile) -> bool {
+    let logical: bool = matches!(opcode, 0xa0..=0xb7 | 0xe6 | 0xee | 0xf6); // ANA, XRA, ORA, ANI, XRI, ORI
+    return matches!(opcode, 0x80..=0x8f | 0xc6 | 0xce | 0x27 | 0xf1) // ADD, ADC, ADI, ACI, DAA, POP PSW
+        || (logical && quirks != QuirkProfile::Classic);
+}
+
+/// Whether `opcode` changes AC on hardware without the interpreter
+/// following: subtractions, compares, logical operations, INR and DCR.
+/// `models_aux_carry` is checked first, for the logical operations.
+fn leaves_aux_carry_stale(opcode: u8) -> bool {
+    return matches!(opcode, 0x90..=0xbf | 0xd6 | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe) || opcode & 0xc6 == 0x04;
+}
+
+/// Follows whether AC holds a value the interpreter actually computed, to
+/// catch a DAA whose result depends on one it did not.
+#[derive(Debug, Clone, Default)]
+pub struct AuxCarryAudit {
+    state: AuxCarry,
+    warned: bool,
+}
+
+impl AuxCarryAudit {
+    pub fn new() -> AuxCarryAudit {
+        return AuxCarryAudit::default();
+    }
+
+    /// Called before `opcode` at `pc` executes under `quirks`. The first
+    /// DAA to read an untrusted AC gets back the instruction that left it,
+    /// or `None` if nothing did; later ones are let through.
+    pub fn observe(&mut self, pc: u16, opcode: u8, quirks: QuirkProfile) -> Option<Option<u16>> {
+        let untrusted: Option<Option<u16>> = match self.state {
+            _ if opcode != 0x27 || self.warned => None,
+            AuxCarry::Modeled => None,
+            AuxCarry::Unset => Some(None),
+            AuxCarry::Unmodeled(producer) => Some(Some(producer)),
+        };
+        if models_aux_carry(opcode, quirks) {
+            self.state = AuxCarry::Modeled;
+        } else if leaves_aux_carry_stale(opcode) {
+            self.state = AuxCarry::Unmodeled(pc);
+        }
+        self.warned |= untrusted.is_some();
+        return untrusted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warns_once_for_daa_after_unmodeled_write() {
+        let mut audit = AuxCarryAudit::new();
+
+        assert_eq!(audit.observe(0x0100, 0x87, QuirkProfile::Classic), None); // ADD A
+        assert_eq!(audit.observe(0x0101, 0x27, QuirkProfile::Classic), None); // DAA
+        assert_eq!(audit.observe(0x0102, 0x3c, QuirkProfile::Classic), None); // INR A
+        assert_eq!(audit.observe(0x0103, 0x27, QuirkProfile::Classic), Some(Some(0x0102)));
+        assert_eq!(audit.observe(0x0104, 0xd6, QuirkProfile::Classic), None); // SUI
+        assert_eq!(audit.observe(0x0106, 0x27, QuirkProfile::Classic), None);
+        assert_eq!(AuxCarryAudit::new().observe(0x0000, 0x27, QuirkProfile::Classic), Some(None));
+    }
+
+    #[test]
+    fn test_logical_operations_are_modeled_outside_classic() {
+        let mut audit = AuxCarryAudit::new();
+        assert_eq!(audit.observe(0x0100, 0xa0, QuirkProfile::Classic), None); // ANA B
+        assert_eq!(audit.observe(0x0101, 0x27, QuirkProfile::Classic), Some(Some(0x0100)));
+
+        for quirks in [QuirkProfile::Intel8080, QuirkProfile::Intel8085] {
+            let mut audit = AuxCarryAudit::new();
+            assert_eq!(audit.observe(0x0100, 0xe6, quirks), None); // ANI
+            assert_eq!(audit.observe(0x0102, 0x27, quirks), None, "{}", quirks);
+            assert_eq!(audit.observe(0x0103, 0xb8, quirks), None); // CMP B
+            assert_eq!(audit.observe(0x0104, 0x27, quirks), Some(Some(0x0103)), "{}", quirks);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+#[cfg(feature = "serde-state")]
+use serde::{Deserialize, Serialize};
+
+use super::Processor;
+
+/// How often one conditional JMP, CALL or RET ran and how often its
+/// condition held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-state", derive(Serialize, Deserialize))]
+pub struct BranchSite {
+    pub pc: u16,
+    pub executed: u64,
+    pub taken: u64,
+}
+
+impl BranchSite {
+    /// The fraction of executions that branched, or 0 if it never ran.
+    pub fn taken_ratio(&self) -> f64 {
+        return if self.executed == 0 { 0.0 } else { self.taken as f64 / self.executed as f64 };
+    }
+}
+
+impl Processor {
+    /// Starts or stops counting, for each conditional JMP, CALL and RET,
+    /// how often it ran and how often it branched. Stopping discards
+    /// anything recorded.
+    pub fn set_branch_stats(&mut self, enabled: bool) {
+        if enabled != self.branch_stats.is_some() {
+            self.branch_stats = enabled.then(HashMap::new);
+        }
+    }
+
+    /// Every conditional branch that has run, most executed first and by
+    /// address among equals. Empty if counting is off.
+    pub fn branch_stats(&self) -> Vec<BranchSite> {
+        let mut sites: Vec<BranchSite> = self.branch_stats.as_ref()
+            .map_or_else(Vec::new, |sites| sites.values().copied().collect());
+        sites.sort_by_key(|site| (std::cmp::Reverse(site.executed), site.pc));
+        return sites;
+    }
+
+    /// Whether the condition of the conditional JMP, CALL or RET `opcode`
+    /// holds, counting it against the current instruction.
+    pub(super) fn branch_condition(&mut self, opcode: u8) -> bool {
+        let taken: bool = self.match_conds(opcode);
+        let pc: u16 = self.instruction_pc;
+        if let Some(sites) = &mut self.branch_stats {
+            let site: &mut BranchSite = sites.entry(pc).or_insert(BranchSite { pc, executed: 0, taken: 0 });
+            site.executed += 1;
+            site.taken += taken as u64;
+        }
+        return taken;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{make_processor, RunOutcome};
+
+    /// Loops 8 times over an always-taken JNC at 0004h, a never-taken JC at
+    /// 0007h and a JZ at 000ch that branches on every other pass, on bit 0
+    /// of the counter.
+    const SITES: [u8; 19] = [
+        0x06, 0x08,       // 0000 MVI B,8
+        0x78,             // 0002 MOV A,B
+        0xb7,             // 0003 ORA A: clears carry
+        0xd2, 0x07, 0x00, // 0004 JNC 0007h
+        0xda, 0x00, 0x00, // 0007 JC 0000h
+        0xe6, 0x01,       // 000a ANI 01h
+        0xca, 0x0f, 0x00, // 000c JZ 000fh
+        0x05,             // 000f DCR B
+        0xc2, 0x02, 0x00, // 0010 JNZ 0002h
+    ];
+
+    fn run_sites(enabled: bool) -> Processor {
+        let mut processor: Processor = make_processor();
+        processor.set_branch_stats(enabled);
+        processor.load_program(&SITES);
+        processor.load_at(SITES.len() as u16, &[0x76]); // HLT
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        return processor;
+    }
+
+    #[test]
+    fn test_counts_each_site() {
+        let processor: Processor = run_sites(true);
+
+        assert_eq!(processor.branch_stats(), vec![
+            BranchSite { pc: 0x0004, executed: 8, taken: 8 },
+            BranchSite { pc: 0x0007, executed: 8, taken: 0 },
+            BranchSite { pc: 0x000c, executed: 8, taken: 4 },
+            BranchSite { pc: 0x0010, executed: 8, taken: 7 },
+        ]);
+        let ratios: Vec<f64> = processor.branch_stats().iter().map(BranchSite::taken_ratio).collect();
+        assert_eq!(ratios, [1.0, 0.0, 0.5, 0.875]);
+    }
+
+    #[test]
+    fn test_off_by_default() {
+        assert!(run_sites(false).branch_stats().is_empty());
+    }
+
+    #[test]
+    fn test_taken_calls_and_returns_cost_more() {
+        let cycles = |zero: bool, program: &[u8]| {
+            let mut processor: Processor = make_processor();
+            processor.load_program(program);
+            let mut registers = processor.registers();
+            registers.flags.zero = zero;
+            registers.sp = 0x2000;
+            processor.set_registers(registers);
+            processor.step();
+            return processor.cycle_count();
+        };
+
+        assert_eq!((cycles(true, &[0xcc, 0x00, 0x10]), cycles(false, &[0xcc, 0x00, 0x10])), (17, 11)); // CZ 1000h
+        assert_eq!((cycles(true, &[0xc8]), cycles(false, &[0xc8])), (11, 5)); // RZ
+        assert_eq!((cycles(true, &[0xca, 0x00, 0x10]), cycles(false, &[0xca, 0x00, 0x10])), (10, 10)); // JZ 1000h
+    }
+}
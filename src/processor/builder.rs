@@ -0,0 +1,273 @@
+use std::ops::Range;
+
+use crate::clock::Clock;
+
+use super::{make_processor, IoDevice, MemoryBus, PortAddressing, Processor, QuirkProfile, RegisterPair, RomWritePolicy, StrictMode, Throttle,
+            TraceFilter, Tracer, DEFAULT_VECTOR_PAGE};
+
+/// A band of memory below the stack that the guest should never write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackCanary {
+    pub guard: Range<u16>,
+    /// Every byte of the band is filled with this value.
+    pub value: u8,
+    /// How many instructions pass between full scans of the band, which catch
+    /// changes that did not go through the processor's own writes.
+    pub check_interval: u64,
+}
+
+/// Configures optional processor features before it is created.
+#[derive(Debug, Default)]
+pub struct ProcessorBuilder {
+    bus: Option<Box<dyn MemoryBus>>,
+    deadline_check_interval: Option<u64>,
+    canary: Option<StackCanary>,
+    devices: Vec<(Vec<u8>, Box<dyn IoDevice>)>,
+    strict: bool,
+    vector_page: Option<Range<u16>>,
+    clock: Option<Box<dyn Clock>>,
+    throttle_hz: Option<u64>,
+    port_addressing: PortAddressing,
+    quirks: QuirkProfile,
+    vector_write_check: bool,
+    operand_check: bool,
+    rom_write_policy: RomWritePolicy,
+    unattached_port_check: bool,
+    unattached_read_value: u8,
+    tracer: Option<Box<dyn Tracer>>,
+    trace_filter: Option<TraceFilter>,
+    fast_forward_loops: bool,
+    flag_audit: bool,
+    aux_carry_audit: bool,
+    interrupt_timing: bool,
+    branch_stats: bool,
+    reentry_check: bool,
+    max_interrupt_nesting: Option<usize>,
+    indirect_flow_tracking: bool,
+    allocators: Vec<(RegisterPair, Range<u16>)>,
+}
+
+impl ProcessorBuilder {
+    pub fn new() -> ProcessorBuilder {
+        return ProcessorBuilder::default();
+    }
+
+    pub fn memory_bus(mut self, bus: Box<dyn MemoryBus>) -> ProcessorBuilder {
+        self.bus = Some(bus);
+        return self;
+    }
+
+    pub fn deadline_check_interval(mut self, interval: u64) -> ProcessorBuilder {
+        self.deadline_check_interval = Some(interval);
+        return self;
+    }
+
+    /// Fills `guard` with `value` and stops the run with
+    /// `EmuError::StackCanary` as soon as the guest writes into it. The band
+    /// is also rescanned every `DEFAULT_CANARY_CHECK_INTERVAL`
+    /// instructions unless `canary_check_interval` says otherwise.
+    pub fn stack_canary(mut self, guard: Range<u16>, value: u8) -> ProcessorBuilder {
+        self.canary = Some(StackCanary { guard, value, check_interval: super::DEFAULT_CANARY_CHECK_INTERVAL });
+        return self;
+    }
+
+    pub fn canary_check_interval(mut self, interval: u64) -> ProcessorBuilder {
+        if let Some(canary) = &mut self.canary {
+            canary.check_interval = interval.max(1);
+        }
+        return self;
+    }
+
+    /// Turns on strict mode, which stops the run with a fault when the guest
+    /// does something that is almost certainly a bug, such as executing a
+    /// vector page entry that was never set up.
+    pub fn strict(mut self, enabled: bool) -> ProcessorBuilder {
+        self.strict = enabled;
+        return self;
+    }
+
+    /// The range strict mode treats as the vector page. Defaults to
+    /// `DEFAULT_VECTOR_PAGE`.
+    pub fn vector_page(mut self, page: Range<u16>) -> ProcessorBuilder {
+        self.vector_page = Some(page);
+        return self;
+    }
+
+    /// See `Processor::set_tracer`.
+    pub fn tracer(mut self, tracer: Box<dyn Tracer>) -> ProcessorBuilder {
+        self.tracer = Some(tracer);
+        return self;
+    }
+
+    pub fn trace_filter(mut self, filter: TraceFilter) -> ProcessorBuilder {
+        self.trace_filter = Some(filter);
+        return self;
+    }
+
+    /// See `Processor::set_fast_forward_loops`.
+    pub fn fast_forward_loops(mut self, enabled: bool) -> ProcessorBuilder {
+        self.fast_forward_loops = enabled;
+        return self;
+    }
+
+    /// See `Processor::set_flag_audit`.
+    pub fn flag_audit(mut self, enabled: bool) -> ProcessorBuilder {
+        self.flag_audit = enabled;
+        return self;
+    }
+
+    /// See `Processor::set_aux_carry_audit`.
+    pub fn aux_carry_audit(mut self, enabled: bool) -> ProcessorBuilder {
+        self.aux_carry_audit = enabled;
+        return self;
+    }
+
+    /// See `Processor::set_interrupt_timing`.
+    pub fn interrupt_timing(mut self, enabled: bool) -> ProcessorBuilder {
+        self.interrupt_timing = enabled;
+        return self;
+    }
+
+    /// See `Processor::set_branch_stats`.
+    pub fn branch_stats(mut self, enabled: bool) -> ProcessorBuilder {
+        self.branch_stats = enabled;
+        return self;
+    }
+
+    /// See `Processor::set_indirect_flow_tracking`.
+    pub fn indirect_flow_tracking(mut self, enabled: bool) -> ProcessorBuilder {
+        self.indirect_flow_tracking = enabled;
+        return self;
+    }
+
+    /// See `Processor::set_reentry_check`.
+    pub fn reentry_check(mut self, enabled: bool) -> ProcessorBuilder {
+        self.reentry_check = enabled;
+        return self;
+    }
+
+    /// See `Processor::set_max_interrupt_nesting`.
+    pub fn max_interrupt_nesting(mut self, max_depth: usize) -> ProcessorBuilder {
+        self.max_interrupt_nesting = Some(max_depth);
+        return self;
+    }
+
+    /// See `Processor::track_allocator`. May be given more than once.
+    pub fn track_allocator(mut self, pair: RegisterPair, arena: Range<u16>) -> ProcessorBuilder {
+        self.allocators.push((pair, arena));
+        return self;
+    }
+
+    /// See `Processor::set_vector_write_check`.
+    pub fn vector_write_check(mut self, enabled: bool) -> ProcessorBuilder {
+        self.vector_write_check = enabled;
+        return self;
+    }
+
+    /// See `Processor::set_operand_check`.
+    pub fn operand_check(mut self, enabled: bool) -> ProcessorBuilder {
+        self.operand_check = enabled;
+        return self;
+    }
+
+    /// See `Processor::set_rom_write_policy`.
+    pub fn rom_write_policy(mut self, policy: RomWritePolicy) -> ProcessorBuilder {
+        self.rom_write_policy = policy;
+        return self;
+    }
+
+    /// See `Processor::set_unattached_port_check`.
+    pub fn unattached_port_check(mut self, enabled: bool) -> ProcessorBuilder {
+        self.unattached_port_check = enabled;
+        return self;
+    }
+
+    /// See `Processor::set_unattached_read_value`.
+    pub fn unattached_read_value(mut self, value: u8) -> ProcessorBuilder {
+        self.unattached_read_value = value;
+        return self;
+    }
+
+    /// Attaches `device` to `ports`; see `Processor::attach_device`.
+    pub fn device(mut self, ports: &[u8], device: Box<dyn IoDevice>) -> ProcessorBuilder {
+        self.devices.push((ports.to_vec(), device));
+        return self;
+    }
+
+    /// The clock for deadlines, progress, and throttling. Defaults to
+    /// `SystemClock`.
+    pub fn clock(mut self, clock: Box<dyn Clock>) -> ProcessorBuilder {
+        self.clock = Some(clock);
+        return self;
+    }
+
+    /// Holds runs to `hz` emulated clock cycles per second.
+    pub fn throttle(mut self, hz: u64) -> ProcessorBuilder {
+        self.throttle_hz = Some(hz);
+        return self;
+    }
+
+    /// The 16-bit port value devices see; `PortAddressing::Standard` unless
+    /// set.
+    pub fn port_addressing(mut self, addressing: PortAddressing) -> ProcessorBuilder {
+        self.port_addressing = addressing;
+        return self;
+    }
+
+    /// See `Processor::set_quirks`; `QuirkProfile::Classic` unless set.
+    pub fn quirks(mut self, profile: QuirkProfile) -> ProcessorBuilder {
+        self.quirks = profile;
+        return self;
+    }
+
+    pub fn build(self) -> Processor {
+        let mut processor = make_processor();
+        if let Some(bus) = self.bus {
+            processor.memory = bus;
+        }
+        processor.set_port_addressing(self.port_addressing);
+        processor.set_quirks(self.quirks);
+        processor.set_vector_write_check(self.vector_write_check);
+        processor.set_operand_check(self.operand_check);
+        processor.set_rom_write_policy(self.rom_write_policy);
+        processor.set_unattached_port_check(self.unattached_port_check);
+        processor.set_unattached_read_value(self.unattached_read_value);
+        processor.set_fast_forward_loops(self.fast_forward_loops);
+        processor.set_flag_audit(self.flag_audit);
+        processor.set_aux_carry_audit(self.aux_carry_audit);
+        processor.set_interrupt_timing(self.interrupt_timing);
+        processor.set_branch_stats(self.branch_stats);
+        processor.set_reentry_check(self.reentry_check);
+        processor.set_indirect_flow_tracking(self.indirect_flow_tracking);
+        processor.set_max_interrupt_nesting(self.max_interrupt_nesting);
+        for (pair, arena) in self.allocators {
+            processor.track_allocator(pair, arena);
+        }
+        processor.set_tracer(self.tracer);
+        if let Some(filter) = self.trace_filter {
+            processor.set_trace_filter(filter);
+        }
+        if let Some(interval) = self.deadline_check_interval {
+            processor.set_deadline_check_interval(interval);
+        }
+        if let Some(clock) = self.clock {
+            processor.set_clock(clock);
+        }
+        if let Some(hz) = self.throttle_hz {
+            processor.set_throttle(Some(Throttle::new(hz)));
+        }
+        if self.strict {
+            processor.strict = Some(StrictMode::new(self.vector_page.unwrap_or(DEFAULT_VECTOR_PAGE)));
+        }
+        if let Some(canary) = self.canary {
+            for addr in canary.guard.clone() {
+                processor.memory.load(addr, &[canary.value]);
+            }
+            processor.canary = Some(canary);
+        }
+        for (ports, device) in self.devices {
+            processor.attach_device(&ports, device);
+        }
+        return processor;
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt::{self, Debug};
+use std::ops::Range;
+
+/// Size of the 8080 address space.
+pub const MEMORY_SIZE: usize = 0x10000;
+
+/// Everything the processor reads or writes in its 64K address space goes
+/// through a `MemoryBus`, including instruction fetches.
+pub trait MemoryBus: Debug + Send {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, value: u8);
+
+    /// Reads a byte without any side effects, for debuggers and state dumps.
+    fn peek(&self, addr: u16) -> u8;
+
+    /// Copies `bytes` into memory starting at `origin`. This is how programs
+    /// are loaded, so it should not count as a guest access.
+    fn load(&mut self, origin: u16, bytes: &[u8]) {
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.write(origin.wrapping_add(offset as u16), *byte);
+        }
+    }
+
+    /// `range` borrowed in place, if the bus keeps it as plain contiguous
+    /// RAM. Buses with memory-mapped or lock-guarded regions keep the
+    /// default, so callers fall back to `peek`.
+    fn slice(&self, _range: Range<u16>) -> Option<&[u8]> {
+        return None;
+    }
+}
+
+/// `Processor::memory_view` was asked for memory the bus cannot lend out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryViewError {
+    pub range: Range<u16>,
+}
+
+impl fmt::Display for MemoryViewError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{:04x}-{:04x} is not plain RAM; read it with peek",
+                      self.range.start, self.range.end.wrapping_sub(1));
+    }
+}
+
+/// Plain 64K of RAM with no side effects.
+#[derive(Debug)]
+pub struct FlatMemory {
+    bytes: Vec<u8>,
+}
+
+impl FlatMemory {
+    pub fn new() -> FlatMemory {
+        return FlatMemory { bytes: vec![0; MEMORY_SIZE] };
+    }
+}
+
+impl Default for FlatMemory {
+    fn default() -> FlatMemory {
+        return FlatMemory::new();
+    }
+}
+
+impl MemoryBus for FlatMemory {
+    fn read(&mut self, addr: u16) -> u8 {
+        return self.bytes[addr as usize];
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.bytes[addr as usize] = value;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        return self.bytes[addr as usize];
+    }
+
+    fn load(&mut self, origin: u16, bytes: &[u8]) {
+        let start = origin as usize;
+        let len = bytes.len().min(MEMORY_SIZE - start);
+        self.bytes[start..start + len].copy_from_slice(&bytes[..len]);
+    }
+
+    fn slice(&self, range: Range<u16>) -> Option<&[u8]> {
+        return self.bytes.get(range.start as usize..range.end as usize);
+    }
+}
+
+impl Default for Box<dyn MemoryBus> {
+    fn default() -> Box<dyn MemoryBus> {
+        return Box::new(FlatMemory::new());
+    }
+}
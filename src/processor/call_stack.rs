@@ -0,0 +1,95 @@
+/// Frames deeper than this are dropped from the bottom, so guest code that
+/// calls without ever returning cannot grow the shadow stack without bound.
+const MAX_FRAMES: usize = 4096;
+
+/// One CALL or RST that has not returned yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    /// Address of the CALL or RST instruction.
+    pub call_site: u16,
+    /// Where it jumped to.
+    pub target: u16,
+    /// The return address it pushed.
+    pub return_addr: u16,
+    /// SP after the return address was pushed.
+    pub sp: u16,
+}
+
+/// Mirrors the guest's calls and returns so diagnostics can say how
+/// execution got somewhere. Frames are matched to returns by SP, so guest
+/// code that unwinds the stack by hand does not leave stale frames behind.
+#[derive(Debug, Default)]
+pub struct ShadowStack {
+    frames: Vec<CallFrame>,
+}
+
+impl ShadowStack {
+    pub fn push(&mut self, frame: CallFrame) {
+        if self.frames.len() == MAX_FRAMES {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    /// Called before a RET pops its address with the SP at that point. Drops
+    /// the frame being returned from and any deeper ones that were abandoned.
+    pub fn on_return(&mut self, sp: u16) {
+        while self.frames.last().is_some_and(|frame| frame.sp <= sp) {
+            self.frames.pop();
+        }
+    }
+
+    /// Keeps only the outermost `len` frames.
+    #[cfg(feature = "debugger")]
+    pub fn truncate(&mut self, len: usize) {
+        self.frames.truncate(len);
+    }
+
+    /// Innermost frame last.
+    pub fn frames(&self) -> &[CallFrame] {
+        return &self.frames;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(call_site: u16, sp: u16) -> CallFrame {
+        return CallFrame { call_site, target: 0, return_addr: call_site + 3, sp };
+    }
+
+    #[test]
+    fn test_return_pops_matching_frame() {
+        let mut stack = ShadowStack::default();
+        stack.push(frame(0x100, 0x2ffe));
+        stack.push(frame(0x200, 0x2ffc));
+
+        stack.on_return(0x2ffc);
+
+        assert_eq!(stack.frames(), &[frame(0x100, 0x2ffe)]);
+    }
+
+    #[test]
+    fn test_return_discards_abandoned_frames() {
+        let mut stack = ShadowStack::default();
+        stack.push(frame(0x100, 0x2ffe));
+        stack.push(frame(0x200, 0x2ffc));
+        stack.push(frame(0x300, 0x2ffa));
+
+        // The guest dropped two frames' worth of stack before returning.
+        stack.on_return(0x2ffe);
+
+        assert!(stack.frames().is_empty());
+    }
+
+    #[test]
+    fn test_unmatched_return_keeps_frames() {
+        let mut stack = ShadowStack::default();
+        stack.push(frame(0x100, 0x2ffe));
+
+        stack.on_return(0x2000);
+
+        assert_eq!(stack.frames().len(), 1);
+    }
+}
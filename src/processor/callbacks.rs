@@ -0,0 +1,207 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::{Processor, Registers};
+
+/// Names a callback registered with `Processor::at`, for
+/// `Processor::remove_callback`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CallbackId(u64);
+
+/// What an address callback can reach while it runs: the registers, memory,
+/// the I/O ports, and a slot for data of its own that lasts between calls.
+pub struct Ctx<'a> {
+    processor: &'a mut Processor,
+    data: &'a mut Option<Box<dyn Any + Send>>,
+}
+
+impl Ctx<'_> {
+    /// Address of the instruction about to run.
+    pub fn pc(&self) -> u16 {
+        return self.processor.pc();
+    }
+
+    pub fn registers(&self) -> Registers {
+        return self.processor.registers();
+    }
+
+    /// Replaces the registers, including the PC, before the instruction
+    /// runs. Moving the PC does not run callbacks at the new address.
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.processor.set_registers(registers);
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        return self.processor.peek(addr);
+    }
+
+    /// Writes memory as the host, like `Processor::load_at`.
+    pub fn write(&mut self, addr: u16, value: u8) {
+        self.processor.load_at(addr, &[value]);
+    }
+
+    /// Sends `value` to the device on `port` as an OUT from the guest would.
+    pub fn out(&mut self, port: u8, value: u8) {
+        self.processor.write_port(port, value);
+    }
+
+    /// The callback's own data, if it has stored some of type `T`.
+    pub fn data<T: Any + Send>(&mut self) -> Option<&mut T> {
+        return self.data.as_mut().and_then(|data| data.downcast_mut::<T>());
+    }
+
+    pub fn set_data<T: Any + Send>(&mut self, value: T) {
+        *self.data = Some(Box::new(value));
+    }
+}
+
+/// Host code run each time execution reaches an address, before the
+/// instruction there. Unlike a breakpoint, the run carries on afterwards.
+pub type AddrCallback = Box<dyn FnMut(&mut Ctx) + Send>;
+
+struct Entry {
+    id: CallbackId,
+    callback: AddrCallback,
+    data: Option<Box<dyn Any + Send>>,
+}
+
+/// The registered callbacks, in registration order at each address.
+#[derive(Default)]
+pub struct Callbacks {
+    by_addr: BTreeMap<u16, Vec<Entry>>,
+    next_id: u64,
+}
+
+impl Callbacks {
+    pub fn insert(&mut self, addr: u16, callback: AddrCallback) -> CallbackId {
+        let id = CallbackId(self.next_id);
+        self.next_id += 1;
+        self.by_addr.entry(addr).or_default().push(Entry { id, callback, data: None });
+        return id;
+    }
+
+    pub fn remove(&mut self, id: CallbackId) -> bool {
+        for entries in self.by_addr.values_mut() {
+            if let Some(index) = entries.iter().position(|entry| entry.id == id) {
+                entries.remove(index);
+                self.by_addr.retain(|_, entries| !entries.is_empty());
+                return true;
+            }
+        }
+        return false;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.by_addr.is_empty();
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        return self.by_addr.contains_key(&addr);
+    }
+}
+
+impl fmt::Debug for Callbacks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f.debug_map()
+            .entries(self.by_addr.iter().map(|(addr, entries)| (format!("{:04x}", addr), entries.len())))
+            .finish();
+    }
+}
+
+impl Processor {
+    /// Calls `callback` every time execution reaches `addr`, before the
+    /// instruction there runs, after any callbacks registered there
+    /// earlier.
+    pub fn at(&mut self, addr: u16, callback: impl FnMut(&mut Ctx) + Send + 'static) -> CallbackId {
+        return self.callbacks.insert(addr, Box::new(callback));
+    }
+
+    /// Unregisters a callback. False if it was already gone.
+    pub fn remove_callback(&mut self, id: CallbackId) -> bool {
+        return self.callbacks.remove(id);
+    }
+
+    /// Runs the callbacks for the PC. They are taken out while they run so
+    /// each can be given the processor.
+    pub(super) fn run_callbacks(&mut self) {
+        let pc: u16 = self.registers.pc;
+        let Some(mut entries) = self.callbacks.by_addr.remove(&pc) else {
+            return;
+        };
+        for entry in &mut entries {
+            let mut ctx = Ctx { processor: self, data: &mut entry.data };
+            (entry.callback)(&mut ctx);
+        }
+        self.callbacks.by_addr.insert(pc, entries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::processor::{make_processor, RunOutcome};
+
+    /// Counts C down from 5 at 0002h.
+    const COUNTDOWN: [u8; 7] = [
+        0x0e, 0x05,       // 0000 MVI C,5
+        0x0d,             // 0002 DCR C
+        0xc2, 0x02, 0x00, // 0003 JNZ 0002h
+        0x76,             // 0006 HLT
+    ];
+
+    #[test]
+    fn test_callbacks_at_one_address_run_in_order() {
+        let mut processor = make_processor();
+        processor.load_program(&COUNTDOWN);
+        let calls: Arc<Mutex<Vec<(u8, u8)>>> = Arc::new(Mutex::new(Vec::new()));
+        for tag in [1, 2] {
+            let calls = calls.clone();
+            processor.at(0x0002, move |ctx| calls.lock().unwrap().push((tag, ctx.registers().c)));
+        }
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 10);
+        assert_eq!(calls[..4], [(1, 5), (2, 5), (1, 4), (2, 4)]);
+    }
+
+    #[test]
+    fn test_guest_sees_register_set_by_callback() {
+        let mut processor = make_processor();
+        processor.load_program(&[
+            0x06, 0x00,       // 0000 MVI B,0
+            0x78,             // 0002 MOV A,B
+            0x32, 0x00, 0x01, // 0003 STA 0100h
+            0x76,             // 0006 HLT
+        ]);
+        processor.at(0x0002, |ctx| {
+            let mut registers = ctx.registers();
+            registers.b = 0x42;
+            ctx.set_registers(registers);
+        });
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert_eq!(processor.peek(0x0100), 0x42);
+    }
+
+    #[test]
+    fn test_removed_callback_stops_mid_run() {
+        let mut processor = make_processor();
+        processor.load_program(&COUNTDOWN);
+        let id = processor.at(0x0002, |ctx| {
+            let count: u8 = ctx.data::<u8>().map_or(1, |count| *count + 1);
+            ctx.set_data(count);
+            ctx.write(0x0100, count);
+        });
+
+        assert!(matches!(processor.run_with_limit(4), RunOutcome::InstructionLimit { .. }));
+        assert!(processor.remove_callback(id));
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        assert_eq!(processor.peek(0x0100), 2);
+        assert!(!processor.remove_callback(id));
+    }
+}
@@ -0,0 +1,86 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::disasm;
+
+use super::FaultReport;
+
+/// A problem with the guest program detected by one of the emulator's
+/// diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmuError {
+    /// A byte in the stack guard band no longer holds the canary value.
+    /// `pc` is the instruction that wrote it when the write was caught as it
+    /// happened, or `None` when a periodic check found it.
+    StackCanary { addr: u16, pc: Option<u16> },
+    /// Strict mode: an instruction was fetched from the vector page at
+    /// `addr`, but nothing was ever loaded or written there. `caller` is the
+    /// most recent CALL or RST still on the shadow call stack.
+    UninitializedVector { addr: u16, caller: Option<u16> },
+    /// Strict mode with the vector write check on: the instruction at `pc`
+    /// wrote to `addr`, part of RST `vector`, after interrupts were first
+    /// enabled.
+    VectorWrite { addr: u16, vector: u8, pc: u16 },
+    /// Strict mode: the instruction at `pc` is an opcode the interpreter
+    /// does not implement. `operands` are the two bytes after it.
+    InvalidOpcode { opcode: u8, operands: [u8; 2], pc: u16 },
+    /// Strict mode with the operand check on: the LDA, STA, LHLD, SHLD,
+    /// LDAX or STAX at `pc` would read or `write` `addr` in `region`, which
+    /// does not allow it.
+    OperandAccess { pc: u16, addr: u16, region: String, write: bool },
+    /// `RomWritePolicy::Fault`: the instruction at `pc` wrote `addr` in
+    /// the read-only `region`. The byte was left alone.
+    RomWrite { addr: u16, region: String, pc: u16 },
+    /// Strict mode with a nesting limit: taking the interrupt pending at
+    /// `pc` would leave `depth` handlers running inside one another.
+    InterruptNesting { depth: usize, pc: u16 },
+    /// Strict mode with the unattached port check on: the IN at `pc` read
+    /// `port`, which has no device attached.
+    UnattachedPort { port: u8, pc: u16 },
+    /// `Processor::load_instructions`: instruction `index` has an operand
+    /// with no encoding.
+    InvalidOperand { index: usize, reason: &'static str },
+    /// `Processor::load_instructions`: `len` bytes do not fit between
+    /// `origin` and the top of memory.
+    ProgramTooLarge { origin: u16, len: usize },
+    /// `Processor::apply_verdict`: the host's check on the finished run
+    /// failed with `message`. `report` shows where the run stopped; it is
+    /// `None` only in the copy of this error inside the report itself.
+    PostconditionFailed { message: String, report: Option<Box<FaultReport>> },
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            EmuError::StackCanary { addr, pc: Some(pc) } =>
+                write!(f, "stack guard band at {:#06x} overwritten by instruction at {:#06x}", addr, pc),
+            EmuError::StackCanary { addr, pc: None } =>
+                write!(f, "stack guard band corrupted at {:#06x}", addr),
+            EmuError::UninitializedVector { addr, caller: Some(caller) } =>
+                write!(f, "executed uninitialized vector at {:#06x}, called from {:#06x}", addr, caller),
+            EmuError::UninitializedVector { addr, caller: None } =>
+                write!(f, "executed uninitialized vector at {:#06x}", addr),
+            EmuError::VectorWrite { addr, vector, pc } =>
+                write!(f, "instruction at {:#06x} wrote {:#06x} in rst {} after interrupts were enabled", pc, addr, vector),
+            EmuError::InvalidOpcode { opcode, operands, pc } =>
+                write!(f, "invalid opcode {:#04x} at {:#06x} ({})", opcode, pc, disasm::describe(&[*opcode, operands[0], operands[1]])),
+            EmuError::OperandAccess { pc, addr, region, write: true } =>
+                write!(f, "instruction at {:#06x} would write {:#06x} in read-only region {}", pc, addr, region),
+            EmuError::OperandAccess { pc, addr, region, write: false } =>
+                write!(f, "instruction at {:#06x} would read {:#06x} in write-only region {}", pc, addr, region),
+            EmuError::RomWrite { addr, region, pc } =>
+                write!(f, "instruction at {:#06x} wrote {:#06x} in read-only region {}", pc, addr, region),
+            EmuError::InterruptNesting { depth, pc } =>
+                write!(f, "interrupt at {:#06x} would nest handlers {} deep", pc, depth),
+            EmuError::UnattachedPort { port, pc } =>
+                write!(f, "instruction at {:#06x} read port {:#04x}, which has no device attached", pc, port),
+            EmuError::InvalidOperand { index, reason } =>
+                write!(f, "instruction {}: {}", index, reason),
+            EmuError::ProgramTooLarge { origin, len } =>
+                write!(f, "{} bytes do not fit in memory from {:#06x}", len, origin),
+            EmuError::PostconditionFailed { message, .. } => write!(f, "postcondition failed: {}", message),
+        };
+    }
+}
+
+impl Error for EmuError {}
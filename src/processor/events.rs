@@ -0,0 +1,196 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex};
+
+use crate::disasm;
+
+use super::RegisterPair;
+
+/// Events an `EventLog` keeps unless configured otherwise.
+pub const DEFAULT_EVENT_LOG_CAPACITY: usize = 4096;
+
+/// Something worth seeing in a hardware bring-up log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// IN from `port` read `value`.
+    PortRead { port: u8, value: u8 },
+    /// OUT wrote `value` to `port`.
+    PortWrite { port: u8, value: u8 },
+    /// `source` requested an interrupt that executes RST `vector`.
+    InterruptRequested { source: u8, vector: u8 },
+    /// The guest took `source`'s interrupt: RST `vector` was executed.
+    InterruptDelivered { source: u8, vector: u8 },
+    /// A read from an annotated region, including instruction fetches.
+    RegionRead { region: String, addr: u16, value: u8 },
+    RegionWrite { region: String, addr: u16, value: u8 },
+    /// A write to RST `vector` after interrupts were first enabled.
+    VectorWrite { addr: u16, vector: u8, value: u8 },
+    /// Fast-forwarding skipped `iterations` trips round the countdown loop,
+    /// or the idle polling loop, at `addr`.
+    LoopSkipped { addr: u16, iterations: u64 },
+    /// The flag audit found a conditional testing `flag` as set by
+    /// `producer`, which overwrote the unread result of `clobbered`, or
+    /// with `producer` `None`, a flag nothing has set.
+    SuspectFlagRead { flag: char, producer: Option<u16>, clobbered: Option<u16> },
+    /// A write hit a watchpoint.
+    Watchpoint { addr: u16 },
+    /// `source`'s interrupt was taken at `second_pc` while its handler for
+    /// the one taken at `first_pc` was still running.
+    InterruptReentered { source: u8, first_pc: u16, second_pc: u16 },
+    /// A DAA read an aux carry flag the interpreter did not compute:
+    /// `producer` changes AC on hardware but not here, or with `None`,
+    /// nothing has set it since reset.
+    UnmodeledAuxCarry { producer: Option<u16> },
+    /// The watched expression `name` went from `old` to `new`.
+    ExprChanged { name: String, old: u64, new: u64 },
+    /// The guest logged `message` at `severity`, 0 for debug to 3 for
+    /// error, through a device such as `GuestLogDevice`.
+    GuestLog { severity: u8, message: String },
+    /// The allocator pointer in `pair` moved from inside its arena to
+    /// `pointer`, past the arena's end.
+    AllocatorOverflow { pair: RegisterPair, pointer: u16, arena_end: u16 },
+    /// The stack pointer is inside the part of the arena the allocator
+    /// pointer in `pair` has handed out.
+    AllocatorCollision { pair: RegisterPair, pointer: u16, sp: u16 },
+    /// A guest write of `value` to `addr` in the read-only `region` that
+    /// the ROM write policy kept out of memory.
+    RomWrite { region: String, addr: u16, value: u8 },
+    /// Outside strict mode, the interpreter skipped `opcode`, which it does
+    /// not implement. `operands` are the two bytes after it.
+    UnimplementedOpcode { opcode: u8, operands: [u8; 2] },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Event::PortRead { port, value } => write!(f, "in  port {:02x} -> {:02x}", port, value),
+            Event::PortWrite { port, value } => write!(f, "out port {:02x} <- {:02x}", port, value),
+            Event::InterruptRequested { source, vector } => write!(f, "interrupt requested rst {} by {}", vector, source),
+            Event::InterruptDelivered { source, vector } => write!(f, "interrupt delivered rst {} from {}", vector, source),
+            Event::RegionRead { region, addr, value } => write!(f, "read  {} [{:04x}] -> {:02x}", region, addr, value),
+            Event::RegionWrite { region, addr, value } => write!(f, "write {} [{:04x}] <- {:02x}", region, addr, value),
+            Event::VectorWrite { addr, vector, value } => write!(f, "vector write rst {} [{:04x}] <- {:02x}", vector, addr, value),
+            Event::LoopSkipped { addr, iterations } => write!(f, "skipped {} trips of loop at {:04x}", iterations, addr),
+            Event::SuspectFlagRead { flag, producer: Some(producer), clobbered: Some(clobbered) } =>
+                write!(f, "flag {} read as set at {:04x}, which overwrote the result from {:04x}", flag, producer, clobbered),
+            Event::SuspectFlagRead { flag, .. } => write!(f, "flag {} read but never set", flag),
+            Event::Watchpoint { addr } => write!(f, "watchpoint [{:04x}]", addr),
+            Event::UnmodeledAuxCarry { producer: Some(producer) } =>
+                write!(f, "daa read aux carry left stale by the instruction at {:04x}", producer),
+            Event::UnmodeledAuxCarry { producer: None } => write!(f, "daa read aux carry that nothing set"),
+            Event::AllocatorOverflow { pair, pointer, arena_end } =>
+                write!(f, "allocator {} at {:04x} ran past its arena ending {:04x}", pair, pointer, arena_end),
+            Event::AllocatorCollision { pair, pointer, sp } =>
+                write!(f, "allocator {} at {:04x} has reached the stack at {:04x}", pair, pointer, sp),
+            Event::RomWrite { region, addr, value } => write!(f, "rom write {} [{:04x}] <- {:02x} suppressed", region, addr, value),
+            Event::UnimplementedOpcode { opcode, operands } =>
+                write!(f, "unimplemented instruction {:02x} ({})", opcode, disasm::describe(&[*opcode, operands[0], operands[1]])),
+            Event::GuestLog { severity, message } => match severity {
+                0 => write!(f, "guest debug: {}", message),
+                1 => write!(f, "guest info: {}", message),
+                2 => write!(f, "guest warn: {}", message),
+                3 => write!(f, "guest error: {}", message),
+                _ => write!(f, "guest level {}: {}", severity, message),
+            },
+            Event::ExprChanged { name, old, new } => write!(f, "{} changed {:#x} -> {:#x}", name, old, new),
+            Event::InterruptReentered { source, first_pc, second_pc } =>
+                write!(f, "interrupt from {} taken at {:04x} while the handler for the one taken at {:04x} is still running", source, second_pc, first_pc),
+        };
+    }
+}
+
+/// An event with when and where it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedEvent {
+    /// Instructions completed before the one that caused the event.
+    pub instructions: u64,
+    /// Cycles executed so far, including the instruction causing the event.
+    pub cycles: u64,
+    /// Address of the instruction that caused the event.
+    pub pc: u16,
+    pub event: Event,
+}
+
+impl fmt::Display for TimedEvent {
+    /// e.g. `        12          87  0105  out port 01 <- 41`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{:>10}  {:>10}  {:04x}  {}", self.instructions, self.cycles, self.pc, self.event);
+    }
+}
+
+/// Receives events from the processor as they happen.
+pub trait EventSink: Debug + Send {
+    fn record(&mut self, event: TimedEvent);
+}
+
+#[derive(Debug, Default)]
+struct Ring {
+    events: VecDeque<TimedEvent>,
+    capacity: usize,
+    dropped: u64,
+}
+
+/// Keeps the most recent events, dropping the oldest beyond its capacity.
+///
+/// Clones share the same buffer, so keep one clone to read the log after
+/// giving another to the processor.
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    ring: Arc<Mutex<Ring>>,
+}
+
+impl Default for EventLog {
+    fn default() -> EventLog {
+        return EventLog::new(DEFAULT_EVENT_LOG_CAPACITY);
+    }
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> EventLog {
+        let ring = Ring { capacity: capacity.max(1), ..Default::default() };
+        return EventLog { ring: Arc::new(Mutex::new(ring)) };
+    }
+
+    /// The events held, oldest first.
+    pub fn events(&self) -> Vec<TimedEvent> {
+        return self.ring.lock().unwrap().events.iter().cloned().collect();
+    }
+
+    /// Events pushed out by newer ones.
+    pub fn dropped(&self) -> u64 {
+        return self.ring.lock().unwrap().dropped;
+    }
+}
+
+impl EventSink for EventLog {
+    fn record(&mut self, event: TimedEvent) {
+        let mut ring = self.ring.lock().unwrap();
+        if ring.events.len() == ring.capacity {
+            ring.events.pop_front();
+            ring.dropped += 1;
+        }
+        ring.events.push_back(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(instructions: u64) -> TimedEvent {
+        return TimedEvent { instructions, cycles: instructions * 4, pc: 0, event: Event::Watchpoint { addr: 0 } };
+    }
+
+    #[test]
+    fn test_ring_drops_oldest() {
+        let log = EventLog::new(2);
+        let mut sink = log.clone();
+
+        for instructions in 0..5 {
+            sink.record(event(instructions));
+        }
+
+        assert_eq!(log.events(), vec![event(3), event(4)]);
+        assert_eq!(log.dropped(), 3);
+    }
+}
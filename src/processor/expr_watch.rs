@@ -0,0 +1,105 @@
+use std::fmt;
+
+use super::{Event, Processor};
+
+/// Host code computing a value from the processor's state, for
+/// `Processor::watch_expr`.
+pub type ExprFn = Box<dyn Fn(&Processor) -> u64 + Send>;
+
+struct Watch {
+    name: String,
+    eval: ExprFn,
+    value: u64,
+}
+
+/// Watched expressions with the value each had after the last instruction.
+#[derive(Default)]
+pub struct ExprWatches {
+    watches: Vec<Watch>,
+}
+
+impl ExprWatches {
+    pub fn is_empty(&self) -> bool {
+        return self.watches.is_empty();
+    }
+}
+
+impl fmt::Debug for ExprWatches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f.debug_map().entries(self.watches.iter().map(|watch| (&watch.name, watch.value))).finish();
+    }
+}
+
+impl Processor {
+    /// Evaluates `eval` after every instruction and emits an
+    /// `Event::ExprChanged` named `name` whenever its value differs from the
+    /// one before. Replaces any watch already called `name`. Nothing is
+    /// evaluated while no expressions are watched.
+    pub fn watch_expr(&mut self, name: &str, eval: impl Fn(&Processor) -> u64 + Send + 'static) {
+        self.unwatch_expr(name);
+        let value: u64 = eval(self);
+        self.expr_watches.watches.push(Watch { name: name.to_string(), eval: Box::new(eval), value });
+    }
+
+    /// Stops watching `name`. False if it was not watched.
+    pub fn unwatch_expr(&mut self, name: &str) -> bool {
+        let before: usize = self.expr_watches.watches.len();
+        self.expr_watches.watches.retain(|watch| watch.name != name);
+        return self.expr_watches.watches.len() != before;
+    }
+
+    /// Each watched expression's name and current value, oldest first.
+    pub fn expr_values(&self) -> Vec<(&str, u64)> {
+        return self.expr_watches.watches.iter().map(|watch| (watch.name.as_str(), watch.value)).collect();
+    }
+
+    pub(super) fn check_expr_watches(&mut self) {
+        let mut watches: Vec<Watch> = std::mem::take(&mut self.expr_watches.watches);
+        for watch in &mut watches {
+            let new: u64 = (watch.eval)(self);
+            if new != watch.value {
+                let old: u64 = std::mem::replace(&mut watch.value, new);
+                self.emit_after(Event::ExprChanged { name: watch.name.clone(), old, new });
+            }
+        }
+        self.expr_watches.watches = watches;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::{make_processor, Event, EventLog};
+
+    #[test]
+    fn test_changes_follow_the_loop() {
+        let mut processor = make_processor();
+        processor.load_program(&[
+            0x01, 0x03, 0x00, // 0000 LXI B,3
+            0x11, 0x10, 0x00, // 0003 LXI D,10h
+            0x13,             // 0006 INX D
+            0x0d,             // 0007 DCR C
+            0xc2, 0x06, 0x00, // 0008 JNZ 0006h
+            0x76,             // 000b HLT
+        ]);
+        let log = EventLog::default();
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        processor.watch_expr("bc + de", |processor| {
+            let registers = processor.registers();
+            return registers.bc() as u64 + registers.de() as u64;
+        });
+
+        processor.run();
+
+        let changes: Vec<(u16, u64, u64)> = log.events().into_iter().filter_map(|event| match event.event {
+            Event::ExprChanged { old, new, .. } => Some((event.pc, old, new)),
+            _ => None,
+        }).collect();
+        let mut expected: Vec<(u16, u64, u64)> = vec![(0x0000, 0, 3), (0x0003, 3, 0x13)];
+        for _ in 0..3 {
+            expected.extend([(0x0006, 0x13, 0x14), (0x0007, 0x14, 0x13)]);
+        }
+        assert_eq!(changes, expected);
+        assert!(processor.unwatch_expr("bc + de"));
+        assert!(processor.expr_values().is_empty());
+    }
+}
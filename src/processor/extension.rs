@@ -0,0 +1,142 @@
+use std::fmt;
+
+use super::timing::CYCLES;
+use super::Processor;
+
+/// What an opcode extension did with the opcode it was given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionResult {
+    /// The extension carried the instruction out, taking `cycles` clock
+    /// cycles in all.
+    Handled { cycles: u64 },
+    /// Not an opcode the extension knows; the processor treats it as
+    /// unimplemented, as if there were no extension.
+    Declined,
+}
+
+/// Host code carrying out opcodes the core does not implement. It runs
+/// with the PC just past the opcode, so `Processor::fetch_operand` reads
+/// the instruction's operand bytes in order.
+pub type OpcodeExtension = Box<dyn FnMut(&mut Processor, u8) -> ExtensionResult + Send>;
+
+/// The installed opcode extension, if any.
+#[derive(Default)]
+pub struct ExtensionSlot {
+    extension: Option<OpcodeExtension>,
+}
+
+impl fmt::Debug for ExtensionSlot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f.debug_struct("ExtensionSlot").field("installed", &self.extension.is_some()).finish();
+    }
+}
+
+impl Processor {
+    /// Hands opcodes the core does not implement to `extension` before
+    /// treating them as unimplemented, replacing any extension already
+    /// installed. None removes it.
+    pub fn set_opcode_extension(&mut self, extension: Option<OpcodeExtension>) {
+        self.extension.extension = extension;
+    }
+
+    /// The next byte of the instruction being executed, for an opcode
+    /// extension. Reads it as the guest does and moves the PC past it.
+    pub fn fetch_operand(&mut self) -> u8 {
+        return self.get_byte();
+    }
+
+    /// Runs the opcode extension on `opcode`, whose base cycles have been
+    /// counted already. False if there is no extension or it declined, in
+    /// which case the PC is put back just past the opcode.
+    pub(super) fn run_opcode_extension(&mut self, opcode: u8) -> bool {
+        let Some(mut extension) = self.extension.extension.take() else {
+            return false;
+        };
+        let result: ExtensionResult = extension(self, opcode);
+        self.extension.extension.get_or_insert(extension);
+        return match result {
+            ExtensionResult::Handled { cycles } => {
+                self.cycles = self.cycles - CYCLES[opcode as usize] as u64 + cycles;
+                true
+            },
+            ExtensionResult::Declined => {
+                self.registers.pc = self.instruction_pc.wrapping_add(1);
+                false
+            },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{make_processor, EmuError, IoContext, IoDevice, ProcessorBuilder, RunOutcome};
+
+    #[derive(Debug)]
+    struct Switches;
+
+    impl IoDevice for Switches {
+        fn read(&mut self, port: u16, _ctx: &IoContext) -> u8 {
+            return (port as u8).wrapping_mul(3);
+        }
+
+        fn write(&mut self, _port: u16, _value: u8, _ctx: &IoContext) {}
+    }
+
+    /// 08h swaps A and B; 10h nn loads A from port nn.
+    fn homebrew() -> OpcodeExtension {
+        return Box::new(|processor: &mut Processor, opcode: u8| match opcode {
+            0x08 => {
+                let mut registers = processor.registers();
+                (registers.a, registers.b) = (registers.b, registers.a);
+                processor.set_registers(registers);
+                ExtensionResult::Handled { cycles: 5 }
+            },
+            0x10 => {
+                let port: u8 = processor.fetch_operand();
+                let value: u8 = processor.read_port(port);
+                let mut registers = processor.registers();
+                registers.a = value;
+                processor.set_registers(registers);
+                ExtensionResult::Handled { cycles: 10 }
+            },
+            _ => ExtensionResult::Declined,
+        });
+    }
+
+    #[test]
+    fn test_extension_runs_custom_opcodes() {
+        let mut processor: Processor = make_processor();
+        processor.attach_device(&[0x14], Box::new(Switches));
+        processor.set_opcode_extension(Some(homebrew()));
+        processor.load_program(&[
+            0x3e, 0x11, // 0000 MVI A,11h
+            0x06, 0x22, // 0002 MVI B,22h
+            0x08,       // 0004 swap A and B
+            0x10, 0x14, // 0005 A = IN 14h
+            0x76,       // 0007 HLT
+        ]);
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        let registers = processor.registers();
+        assert_eq!((registers.a, registers.b, registers.pc), (0x3c, 0x11, 0x0008));
+        assert_eq!(processor.cycle_count(), 7 + 7 + 5 + 10 + 7);
+    }
+
+    #[test]
+    fn test_declined_opcode_still_faults_in_strict_mode() {
+        let mut processor: Processor = ProcessorBuilder::new().strict(true).build();
+        processor.set_opcode_extension(Some(homebrew()));
+        processor.load_program(&[
+            0x08,             // 0000 swap A and B
+            0xdd, 0x34, 0x12, // 0001 CALL 1234h alias, declined
+            0x76,             // 0004 HLT
+        ]);
+
+        let outcome: RunOutcome = processor.run();
+
+        assert_eq!(outcome, RunOutcome::Fault(EmuError::InvalidOpcode { opcode: 0xdd, operands: [0x34, 0x12], pc: 0x0001 }));
+        assert_eq!(processor.pc(), 0x0002);
+    }
+}
@@ -0,0 +1,193 @@
+use super::timing::CYCLES;
+
+/// Register code of the M operand.
+const M: u8 = 0b110;
+
+const JNZ: u8 = 0xc2;
+const JZ: u8 = 0xca;
+const JNC: u8 = 0xd2;
+const JC: u8 = 0xda;
+const IN: u8 = 0xdb;
+const ANI: u8 = 0xe6;
+const CPI: u8 = 0xfe;
+const ORA_A: u8 = 0xb7;
+
+/// A countdown delay loop starting at `start`: `DCR r; JNZ start`,
+/// optionally with one register-to-register MOV before or after the DCR
+/// that touches neither the counter nor memory. Nothing in it reads or
+/// writes memory or ports, so running it only changes registers, flags and
+/// the counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CountdownLoop {
+    pub start: u16,
+    /// Address just past the JNZ, where the loop exits.
+    pub end: u16,
+    /// Register code of the counter.
+    pub counter: u8,
+    /// Destination and source register codes of the MOV, if there is one.
+    pub mov: Option<(u8, u8)>,
+    pub instructions: u64,
+    /// Cycles one trip round the loop takes, with the JNZ taken.
+    pub cycles: u64,
+}
+
+impl CountdownLoop {
+    /// Trips the loop makes when entered with `count` in the counter: DCR
+    /// of 0 wraps to 255, so 0 means 256.
+    pub fn iterations(count: u8) -> u64 {
+        return if count == 0 { 256 } else { count as u64 };
+    }
+}
+
+/// An input polling loop starting at `start`: `IN port`, then ANI, CPI or
+/// ORA A to test what it read, then JZ, JNZ, JC or JNC back to `start`.
+/// While the port reads a value that takes the jump, every trip leaves the
+/// registers and flags as the last one did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollLoop {
+    pub start: u16,
+    /// Address just past the jump, where the loop exits.
+    pub end: u16,
+    pub port: u8,
+    /// Opcode and immediate byte of the test; ORA A's byte is unused.
+    test: (u8, u8),
+    jump: u8,
+    pub instructions: u64,
+    /// Cycles one trip round the loop takes.
+    pub cycles: u64,
+}
+
+impl PollLoop {
+    /// Whether the loop goes round again when the port reads `value`.
+    pub fn repeats(&self, value: u8) -> bool {
+        let (zero, carry): (bool, bool) = match self.test {
+            (ANI, mask) => (value & mask == 0, false),
+            (CPI, operand) => (value == operand, value < operand),
+            _ => (value == 0, false),
+        };
+        return match self.jump {
+            JZ => zero,
+            JNZ => !zero,
+            JC => carry,
+            _ => !carry,
+        };
+    }
+}
+
+/// The input polling loop starting at `pc`, if the code there is one.
+pub fn match_poll(peek: impl Fn(u16) -> u8, pc: u16) -> Option<PollLoop> {
+    if pc > 0xfff8 || peek(pc) != IN {
+        return None;
+    }
+    let test: (u8, u8) = match peek(pc + 2) {
+        ORA_A => (ORA_A, 0),
+        opcode @ (ANI | CPI) => (opcode, peek(pc + 3)),
+        _ => return None,
+    };
+    let jump: u16 = if test.0 == ORA_A { pc + 3 } else { pc + 4 };
+    if ![JZ, JNZ, JC, JNC].contains(&peek(jump)) || peek(jump + 1) != pc as u8 || peek(jump + 2) != (pc >> 8) as u8 {
+        return None;
+    }
+    return Some(PollLoop {
+        start: pc,
+        end: jump + 3,
+        port: peek(pc + 1),
+        test,
+        jump: peek(jump),
+        instructions: 3,
+        cycles: [IN, test.0, peek(jump)].iter().map(|opcode| CYCLES[*opcode as usize] as u64).sum(),
+    });
+}
+
+fn dcr_register(opcode: u8) -> Option<u8> {
+    let reg: u8 = (opcode >> 3) & 0b111;
+    return (opcode & 0xc7 == 0x05 && reg != M).then_some(reg);
+}
+
+fn register_mov(opcode: u8) -> Option<(u8, u8)> {
+    let (dest, source): (u8, u8) = ((opcode >> 3) & 0b111, opcode & 0b111);
+    return (opcode & 0xc0 == 0x40 && dest != M && source != M).then_some((dest, source));
+}
+
+/// The countdown loop starting at `pc`, if the code there is one.
+pub fn match_countdown(peek: impl Fn(u16) -> u8, pc: u16) -> Option<CountdownLoop> {
+    if pc > 0xfffa {
+        return None;
+    }
+    let jnz_to_start = |addr: u16| peek(addr) == JNZ && peek(addr + 1) == pc as u8 && peek(addr + 2) == (pc >> 8) as u8;
+    let (counter, mov, jnz): (u8, Option<(u8, u8)>, u16) = match (dcr_register(peek(pc)), dcr_register(peek(pc + 1))) {
+        (Some(counter), _) if jnz_to_start(pc + 1) => (counter, None, pc + 1),
+        (Some(counter), _) => (counter, Some(register_mov(peek(pc + 1))?), pc + 2),
+        (None, Some(counter)) => (counter, Some(register_mov(peek(pc))?), pc + 2),
+        (None, None) => return None,
+    };
+    if !jnz_to_start(jnz) {
+        return None;
+    }
+    if mov.is_some_and(|(dest, source)| dest == counter || source == counter) {
+        return None;
+    }
+    let cycles: u64 = [peek(pc), peek(pc + 1), JNZ][..(jnz - pc) as usize + 1].iter()
+        .map(|opcode| CYCLES[*opcode as usize] as u64).sum();
+    return Some(CountdownLoop {
+        start: pc,
+        end: jnz + 3,
+        counter,
+        mov,
+        instructions: (jnz - pc) as u64 + 1,
+        cycles,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matched(code: &[u8]) -> Option<CountdownLoop> {
+        return match_countdown(|addr| code.get(addr as usize - 0x100).copied().unwrap_or(0), 0x100);
+    }
+
+    #[test]
+    fn test_countdown_patterns() {
+        assert_eq!(matched(&[0x05, 0xc2, 0x00, 0x01]), Some(CountdownLoop {
+            start: 0x100, end: 0x104, counter: 0, mov: None, instructions: 2, cycles: 15,
+        }));
+        assert_eq!(matched(&[0x0d, 0x7a, 0xc2, 0x00, 0x01]).map(|found| (found.counter, found.mov, found.cycles)),
+                   Some((1, Some((7, 2)), 20)));
+        assert_eq!(matched(&[0x7a, 0x0d, 0xc2, 0x00, 0x01]).map(|found| found.end), Some(0x105));
+
+        // Memory, I/O, the counter in the MOV, or a jump elsewhere.
+        assert_eq!(matched(&[0x35, 0xc2, 0x00, 0x01]), None);
+        assert_eq!(matched(&[0x05, 0x77, 0xc2, 0x00, 0x01]), None);
+        assert_eq!(matched(&[0x05, 0xd3, 0x01, 0xc2, 0x00, 0x01]), None);
+        assert_eq!(matched(&[0x05, 0x78, 0xc2, 0x00, 0x01]), None);
+        assert_eq!(matched(&[0x05, 0xc2, 0x01, 0x01]), None);
+        assert_eq!(matched(&[0x05, 0x05, 0xc2, 0x00, 0x01]), None);
+    }
+
+    fn polled(code: &[u8]) -> Option<PollLoop> {
+        return match_poll(|addr| code.get(addr as usize - 0x100).copied().unwrap_or(0), 0x100);
+    }
+
+    #[test]
+    fn test_poll_patterns() {
+        // IN 1; ANI 01h; JZ 0100h: waits for bit 0.
+        let found: PollLoop = polled(&[0xdb, 0x01, 0xe6, 0x01, 0xca, 0x00, 0x01]).unwrap();
+        assert_eq!((found.port, found.end, found.cycles), (1, 0x107, 27));
+        assert!(found.repeats(0xfe) && !found.repeats(0x01));
+
+        // IN 2; ORA A; JNZ 0100h: waits for 0.
+        let found: PollLoop = polled(&[0xdb, 0x02, 0xb7, 0xc2, 0x00, 0x01]).unwrap();
+        assert_eq!((found.end, found.cycles), (0x106, 24));
+        assert!(found.repeats(0x80) && !found.repeats(0));
+
+        // IN 3; CPI 10h; JC 0100h: waits for 10h or more.
+        let found: PollLoop = polled(&[0xdb, 0x03, 0xfe, 0x10, 0xda, 0x00, 0x01]).unwrap();
+        assert!(found.repeats(0x0f) && !found.repeats(0x10));
+
+        // Some other test, a jump elsewhere, or no IN.
+        assert_eq!(polled(&[0xdb, 0x01, 0xee, 0x01, 0xca, 0x00, 0x01]), None);
+        assert_eq!(polled(&[0xdb, 0x01, 0xe6, 0x01, 0xca, 0x02, 0x01]), None);
+        assert_eq!(polled(&[0x3a, 0x01, 0xe6, 0x01, 0xca, 0x00, 0x01]), None);
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt;
+
+use crate::disasm::{self, Line};
+
+use super::{CallFrame, EmuError, Registers};
+
+/// Instructions a `FaultReport` shows leading up to the fault.
+pub const FAULT_HISTORY: usize = 8;
+
+/// Stack bytes a `FaultReport` shows from SP up.
+pub const FAULT_STACK_BYTES: usize = 8;
+
+/// Addresses of the most recently executed instructions, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct RecentPcs {
+    pcs: [u16; FAULT_HISTORY],
+    len: usize,
+    next: usize,
+}
+
+impl RecentPcs {
+    pub fn push(&mut self, pc: u16) {
+        self.pcs[self.next] = pc;
+        self.next = (self.next + 1) % FAULT_HISTORY;
+        self.len = (self.len + 1).min(FAULT_HISTORY);
+    }
+
+    pub fn pcs(&self) -> Vec<u16> {
+        let start: usize = (self.next + FAULT_HISTORY - self.len) % FAULT_HISTORY;
+        return (0..self.len).map(|i| self.pcs[(start + i) % FAULT_HISTORY]).collect();
+    }
+}
+
+/// Everything needed to debug a fault without running the guest again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaultReport {
+    pub error: EmuError,
+    /// The instruction that faulted, or for a fault found between
+    /// instructions, the next one.
+    pub instruction: Line,
+    /// The instructions executed before it, oldest first, disassembled from
+    /// memory as it is now.
+    pub history: Vec<Line>,
+    pub registers: Registers,
+    /// Bytes from SP up.
+    pub stack: Vec<u8>,
+    /// The shadow call stack, innermost call last.
+    pub backtrace: Vec<CallFrame>,
+}
+
+impl FaultReport {
+    /// Decodes the instruction at `pc` using `peek` to read memory.
+    pub fn line_at(peek: impl Fn(u16) -> u8, pc: u16) -> Line {
+        let bytes: [u8; 3] = [0, 1, 2].map(|offset| peek(pc.wrapping_add(offset)));
+        return disasm::decode(&bytes, pc);
+    }
+}
+
+impl fmt::Display for FaultReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "fault: {}", self.error)?;
+        for line in &self.history {
+            writeln!(f, "    {}", line)?;
+        }
+        writeln!(f, "  > {}", self.instruction)?;
+        writeln!(f, "registers: {}", self.registers)?;
+        let stack: Vec<String> = self.stack.iter().map(|byte| format!("{:02x}", byte)).collect();
+        writeln!(f, "stack at {:04x}: {}", self.registers.sp, stack.join(" "))?;
+        if self.backtrace.is_empty() {
+            return writeln!(f, "backtrace: not in a call");
+        }
+        writeln!(f, "backtrace:")?;
+        for (depth, frame) in self.backtrace.iter().rev().enumerate() {
+            writeln!(f, "  #{} {:04x} called from {:04x}", depth, frame.target, frame.call_site)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_pcs_keep_the_last_few() {
+        let mut recent = RecentPcs::default();
+        recent.push(1);
+        recent.push(2);
+        assert_eq!(recent.pcs(), vec![1, 2]);
+
+        for pc in 3..=12 {
+            recent.push(pc);
+        }
+        assert_eq!(recent.pcs(), (5..=12).collect::<Vec<u16>>());
+    }
+}
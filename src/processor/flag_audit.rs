@@ -0,0 +1,210 @@
+/// Flags conditional instructions can test, in condition code order.
+const TESTED: [char; 4] = ['Z', 'C', 'P', 'S'];
+
+/// What a flag-writing instruction is for. An ALU operation is taken to
+/// set flags on purpose; INR, DCR, DAD and the rotates mostly set them as
+/// a side effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Producer {
+    Deliberate,
+    Incidental,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FlagState {
+    /// PC of the last instruction to write the flag and what it was for.
+    producer: Option<(u16, Producer)>,
+    /// Whether anything has read the flag since then.
+    read: bool,
+    /// A deliberate result nobody read before an incidental write replaced
+    /// it.
+    clobbered: Option<u16>,
+}
+
+/// A conditional instruction testing a flag that looks wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuspectRead {
+    pub flag: char,
+    /// The instruction that last set the flag, or `None` if nothing has.
+    pub producer: Option<u16>,
+    /// The compare or other ALU result the producer overwrote unread.
+    pub clobbered: Option<u16>,
+}
+
+/// Follows which instruction last set each flag that conditionals test,
+/// to catch a branch reading a flag that nothing set, or that an INR or
+/// similar overwrote between a compare and the branch meant to test it.
+#[derive(Debug, Clone, Default)]
+pub struct FlagAudit {
+    flags: [FlagState; 4],
+}
+
+/// Flags in `TESTED` order written by `opcode`, as a bitmask, and whether
+/// the write is deliberate.
+fn writes(opcode: u8) -> (u8, Producer) {
+    const ALL: u8 = 0b1111;
+    const CARRY: u8 = 0b0010;
+    return match opcode {
+        0x80..=0xbf | 0xc6 | 0xce | 0xd6 | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe | 0x27 | 0xf1 => (ALL, Producer::Deliberate),
+        0x37 | 0x3f => (CARRY, Producer::Deliberate), // STC, CMC
+        _ if opcode & 0xc6 == 0x04 => (ALL & !CARRY, Producer::Incidental), // INR, DCR
+        0x07 | 0x0f | 0x17 | 0x1f | 0x09 | 0x19 | 0x29 | 0x39 => (CARRY, Producer::Incidental),
+        _ => (0, Producer::Incidental),
+    };
+}
+
+/// Flags `opcode` reads other than through a condition code.
+fn plain_reads(opcode: u8) -> u8 {
+    return match opcode {
+        0x88..=0x8f | 0x98..=0x9f | 0xce | 0xde | 0x17 | 0x1f | 0x3f => 0b0010, // ADC, SBB, RAL, RAR, CMC
+        0xf5 | 0x27 => 0b1111, // PUSH PSW, DAA
+        _ => 0,
+    };
+}
+
+/// The flag a conditional JMP, CALL or RET tests, as an index into
+/// `TESTED`.
+fn tested(opcode: u8) -> Option<usize> {
+    let conditional: bool = opcode & 0xc0 == 0xc0 && matches!(opcode & 0b111, 0b000 | 0b010 | 0b100);
+    return conditional.then_some(match (opcode >> 4) & 0b11 {
+        0 => 0,
+        1 => 1,
+        2 => 2,
+        _ => 3,
+    });
+}
+
+impl FlagAudit {
+    pub fn new() -> FlagAudit {
+        return FlagAudit::default();
+    }
+
+    /// Takes note of the instruction `opcode` at `pc` before it executes,
+    /// returning what is wrong with the flag it tests, if anything.
+    pub fn observe(&mut self, pc: u16, opcode: u8) -> Option<SuspectRead> {
+        let mut suspect: Option<SuspectRead> = None;
+        if let Some(index) = tested(opcode) {
+            let state: &mut FlagState = &mut self.flags[index];
+            suspect = match (state.producer, state.clobbered) {
+                (None, _) => Some(SuspectRead { flag: TESTED[index], producer: None, clobbered: None }),
+                (Some((producer, Producer::Incidental)), Some(clobbered)) =>
+                    Some(SuspectRead { flag: TESTED[index], producer: Some(producer), clobbered: Some(clobbered) }),
+                _ => None,
+            };
+            state.read = true;
+            state.clobbered = None;
+        }
+        let read: u8 = plain_reads(opcode);
+        let (written, kind): (u8, Producer) = writes(opcode);
+        for (index, state) in self.flags.iter_mut().enumerate() {
+            if read & (1 << index) != 0 {
+                state.read = true;
+                state.clobbered = None;
+            }
+            if written & (1 << index) == 0 {
+                continue;
+            }
+            state.clobbered = match (state.producer, state.read, kind) {
+                (Some((earlier, Producer::Deliberate)), false, Producer::Incidental) => Some(earlier),
+                (_, _, Producer::Incidental) => state.clobbered,
+                _ => None,
+            };
+            state.producer = Some((pc, kind));
+            state.read = false;
+        }
+        return suspect;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "asm")]
+    use crate::corpus::Rng;
+    #[cfg(feature = "asm")]
+    use crate::instruction::Instruction;
+    #[cfg(feature = "asm")]
+    use crate::processor::{make_processor, ConditionBits, Processor, Registers};
+
+    fn audit(program: &[(u16, u8)]) -> Vec<(u16, SuspectRead)> {
+        let mut audit = FlagAudit::new();
+        return program.iter().filter_map(|(pc, opcode)| audit.observe(*pc, *opcode).map(|suspect| (*pc, suspect))).collect();
+    }
+
+    #[test]
+    fn test_flag_classes() {
+        assert_eq!(writes(0x04), (0b1101, Producer::Incidental)); // INR B
+        assert_eq!(writes(0x3d), (0b1101, Producer::Incidental)); // DCR A
+        assert_eq!(writes(0x06).0, 0); // MVI B
+        assert_eq!(writes(0xfe), (0b1111, Producer::Deliberate)); // CPI
+        assert_eq!(tested(0xca), Some(0)); // JZ
+        assert_eq!(tested(0xd4), Some(1)); // CNC
+        assert_eq!(tested(0xe8), Some(2)); // RPE
+        assert_eq!(tested(0xfa), Some(3)); // JM
+        assert_eq!(tested(0xc3), None); // JMP
+    }
+
+    #[test]
+    fn test_audit_rules() {
+        // CPI; JZ
+        assert_eq!(audit(&[(0, 0xfe), (2, 0xca)]), vec![]);
+        // CPI; INR; JZ
+        assert_eq!(audit(&[(0, 0xfe), (2, 0x04), (3, 0xca)]), vec![
+            (3, SuspectRead { flag: 'Z', producer: Some(2), clobbered: Some(0) }),
+        ]);
+        // CPI; INR; JC: INR leaves carry alone.
+        assert_eq!(audit(&[(0, 0xfe), (2, 0x04), (3, 0xda)]), vec![]);
+        // CPI; JZ; INR; JZ: the compare was read before INR replaced it.
+        assert_eq!(audit(&[(0, 0xfe), (2, 0xca), (5, 0x04), (6, 0xca)]), vec![]);
+        // JNZ with nothing setting Z first.
+        assert_eq!(audit(&[(0, 0x06), (2, 0xc2)]), vec![
+            (2, SuspectRead { flag: 'Z', producer: None, clobbered: None }),
+        ]);
+    }
+
+    /// Each flag in `TESTED` order, then the auxiliary carry.
+    #[cfg(feature = "asm")]
+    fn flag_bits(flags: ConditionBits) -> [bool; 5] {
+        return [flags.zero, flags.carry, flags.parity, flags.sign, flags.aux_carry];
+    }
+
+    /// Runs every documented opcode from random registers and flags and
+    /// checks the interpreter against `writes`: flags it says an opcode
+    /// leaves alone stay as they were, and an opcode it says writes flags
+    /// changes some in one of the states. POP PSW loads the flags from the
+    /// stack, so `writes` counts it as writing all of them.
+    #[cfg(feature = "asm")]
+    #[test]
+    fn test_interpreter_writes_only_the_flags_the_table_says() {
+        let mut rng = Rng::new(488);
+        for opcode in (0..=0xffu8).filter(|opcode| Instruction::decode(&[*opcode, 0, 0]).is_some()) {
+            let (written, _) = writes(opcode);
+            let mut changed: bool = false;
+            for _ in 0..8 {
+                let mut processor: Processor = make_processor();
+                let mut registers: Registers = processor.registers();
+                for pair in 0..3 {
+                    registers.set_pair_wrapping(pair, rng.next() as u16);
+                }
+                registers.a = rng.next() as u8;
+                registers.sp = 0x8000;
+                registers.flags.set_flags(rng.next() as u8);
+                registers.pc = 0x1000;
+                processor.set_registers(registers);
+                processor.load_at(0x1000, &[opcode, rng.next() as u8, rng.next() as u8]);
+
+                processor.step();
+
+                let (before, after) = (flag_bits(registers.flags), flag_bits(processor.registers().flags));
+                for (index, flag) in TESTED.iter().enumerate().filter(|(index, _)| written & (1 << index) == 0) {
+                    assert_eq!(before[index], after[index], "{:02x} changed {}", opcode, flag);
+                }
+                if written == 0 {
+                    assert_eq!(before[4], after[4], "{:02x} changed the auxiliary carry", opcode);
+                }
+                changed |= before != after;
+            }
+            assert_eq!(changed, written != 0, "{:02x} is listed as writing flags {:04b}", opcode, written);
+        }
+    }
+}
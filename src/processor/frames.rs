@@ -0,0 +1,40 @@
+use std::fmt;
+
+use super::Processor;
+
+/// Host code run at the end of every video frame, with the frame number
+/// counting from 1.
+pub type FrameCallback = Box<dyn FnMut(u64, &Processor) + Send>;
+
+/// Divides emulated time into frames of a fixed number of cycles.
+#[derive(Default)]
+pub struct FrameClock {
+    pub cycles_per_frame: u64,
+    /// Cycle count at which the current frame ends.
+    pub next_frame_at: u64,
+    pub frames: u64,
+    pub callback: Option<FrameCallback>,
+}
+
+impl FrameClock {
+    /// Whether a frame has ended by `cycles`. Frames that ended during one
+    /// long instruction are counted once each.
+    pub fn frame_ended(&mut self, cycles: u64) -> bool {
+        if self.callback.is_none() || cycles < self.next_frame_at {
+            return false;
+        }
+        self.frames += 1;
+        self.next_frame_at += self.cycles_per_frame;
+        return true;
+    }
+}
+
+impl fmt::Debug for FrameClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f.debug_struct("FrameClock")
+            .field("cycles_per_frame", &self.cycles_per_frame)
+            .field("next_frame_at", &self.next_frame_at)
+            .field("frames", &self.frames)
+            .finish();
+    }
+}
@@ -0,0 +1,200 @@
+use super::Processor;
+
+/// Cycles a halted processor waits between looks at its interrupt sources.
+pub const HALT_WAIT_CYCLES: u64 = 4;
+
+/// Cycles the slowest instruction, XTHL, takes.
+pub(super) const MAX_INSTRUCTION_CYCLES: u64 = 18;
+
+/// What a run does with the processor at an instruction boundary, as far
+/// as HLT goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltState {
+    /// Not halted, or halted with an interrupt about to wake it.
+    Running,
+    /// Halted with interrupts enabled and something attached that can
+    /// raise one, so time passes until it does.
+    Waiting,
+    /// Halted with interrupts disabled: the program has finished.
+    Halted,
+    /// Halted with interrupts enabled and nothing that could ever raise
+    /// one: the program waits forever.
+    Stuck,
+}
+
+/// Classifies the halt state from whether the processor is `halted`,
+/// whether `interrupts_enabled`, whether any devices or a schedule that
+/// can raise interrupts are `sources_attached`, and whether an interrupt
+/// the processor would take now is `pending`.
+pub fn classify_halt(halted: bool, interrupts_enabled: bool, sources_attached: bool, pending: bool) -> HaltState {
+    if !halted {
+        return HaltState::Running;
+    }
+    if !interrupts_enabled {
+        return HaltState::Halted;
+    }
+    if pending {
+        return HaltState::Running;
+    }
+    return if sources_attached { HaltState::Waiting } else { HaltState::Stuck };
+}
+
+impl Processor {
+    /// Where the processor stands with respect to HLT; see `classify_halt`.
+    pub fn halt_state(&self) -> HaltState {
+        let sources: bool = self.schedule.is_some() || self.devices.iter().any(|device| device.raises_interrupts());
+        return classify_halt(self.halt, self.interrupt_enabled, sources, self.interrupt_pending());
+    }
+
+    /// Lets time pass on a halted processor, polling devices and the
+    /// schedule as it goes by: `HALT_WAIT_CYCLES` while a device could
+    /// raise an interrupt, or else straight to the scheduled one or the
+    /// next frame end, whichever comes first.
+    pub(super) fn wait_halted(&mut self) {
+        let until: u64 = match self.devices.iter().any(|device| device.raises_interrupts()) {
+            true => self.cycles + HALT_WAIT_CYCLES,
+            false if self.frames.callback.is_some() => self.frames.next_frame_at,
+            false => u64::MAX,
+        };
+        self.skip_halted(until);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{make_processor, IoContext, IoDevice, ProcessorBuilder, RunOutcome};
+
+    /// Raises RST 2 as source 0 on its `after`th poll.
+    #[derive(Debug)]
+    struct Doorbell {
+        after: u64,
+        polls: u64,
+    }
+
+    impl IoDevice for Doorbell {
+        fn read(&mut self, _port: u16, _ctx: &IoContext) -> u8 {
+            return 0;
+        }
+
+        fn write(&mut self, _port: u16, _value: u8, _ctx: &IoContext) {}
+
+        fn poll_interrupt(&mut self, _ctx: &IoContext) -> Option<(u8, u8)> {
+            self.polls += 1;
+            return (self.polls == self.after).then_some((0, 0xd7));
+        }
+
+        fn raises_interrupts(&self) -> bool {
+            return true;
+        }
+    }
+
+    #[test]
+    fn test_classification_matrix() {
+        let cases: [((bool, bool, bool, bool), HaltState); 9] = [
+            ((false, false, false, false), HaltState::Running),
+            ((false, true, true, true), HaltState::Running),
+            ((true, false, false, false), HaltState::Halted),
+            ((true, false, true, false), HaltState::Halted),
+            ((true, false, true, true), HaltState::Halted),
+            ((true, true, false, false), HaltState::Stuck),
+            ((true, true, true, false), HaltState::Waiting),
+            ((true, true, false, true), HaltState::Running),
+            ((true, true, true, true), HaltState::Running),
+        ];
+        for ((halted, enabled, sources, pending), expected) in cases {
+            assert_eq!(classify_halt(halted, enabled, sources, pending), expected, "{:?}", (halted, enabled, sources, pending));
+        }
+    }
+
+    #[test]
+    fn test_hlt_with_interrupts_disabled_is_clean() {
+        let mut processor: Processor = make_processor();
+        processor.schedule_interrupt_in(100, 0xcf); // RST 1
+        processor.load_program(&[0xf3, 0x76]); // DI; HLT
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert_eq!(processor.halt_state(), HaltState::Halted);
+    }
+
+    #[test]
+    fn test_hlt_with_interrupts_enabled_and_no_source_is_stuck() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0xfb, 0x76]); // EI; HLT
+
+        assert_eq!(processor.run(), RunOutcome::Stuck { pc: 0x0002 });
+        assert_eq!(processor.halt_state(), HaltState::Stuck);
+        processor.request_interrupt(1);
+        processor.load_at(0x0008, &[0x76]); // HLT, with interrupts now off
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert_eq!(processor.pc(), 0x0009);
+    }
+
+    #[test]
+    fn test_hlt_waits_for_the_schedule() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0xfb, 0x76]); // EI; HLT
+        processor.load_at(0x0008, &[0x76]); // RST 1: HLT, with interrupts now off
+        processor.schedule_interrupt_in(1000, 0xcf);
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert_eq!(processor.pc(), 0x0009);
+        assert!(processor.cycle_count() >= 1000);
+    }
+
+    #[test]
+    fn test_hlt_waits_for_a_device_that_interrupts() {
+        let doorbell = Doorbell { after: 50, polls: 0 };
+        let mut processor: Processor = ProcessorBuilder::new().device(&[0x10], Box::new(doorbell)).build();
+        processor.load_program(&[0xfb, 0x76]); // EI; HLT
+        processor.load_at(0x0010, &[0x76]); // RST 2: HLT, with interrupts now off
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert_eq!(processor.pc(), 0x0011);
+        assert_eq!(processor.cycle_count(), 4 + 7 + 48 * HALT_WAIT_CYCLES + 11 + 7);
+    }
+
+    #[test]
+    fn test_di_hlt_in_a_handler_is_clean() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0xfb, 0x00, 0xc3, 0x01, 0x00]); // EI; NOP; JMP 0001h
+        processor.load_at(0x0008, &[0xf3, 0x76]); // RST 1: DI; HLT
+        processor.request_interrupt(1);
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert_eq!(processor.pc(), 0x000a);
+        assert_eq!(processor.call_stack().len(), 1);
+    }
+
+    #[test]
+    fn test_spin_loops_stop_at_the_limit() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0xfb, 0xc3, 0x01, 0x00]); // EI; JMP 0001h
+
+        assert_eq!(processor.run_with_limit(1000), RunOutcome::InstructionLimit { instructions: 1000 });
+        assert_eq!(processor.halt_state(), HaltState::Running);
+    }
+
+    #[test]
+    fn test_waiting_does_not_count_against_the_limit() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0xfb, 0x76]); // EI; HLT
+        processor.load_at(0x0008, &[0xfb, 0x76]); // RST 1: EI; HLT
+        processor.schedule_interrupt_in(1_000_000, 0xcf);
+
+        assert_eq!(processor.run_with_limit(100), RunOutcome::Stuck { pc: 0x000a });
+        assert_eq!(processor.instruction_count(), 5);
+        assert!(processor.cycle_count() >= 1_000_000);
+    }
+
+    #[test]
+    fn test_waiting_on_a_device_is_bounded_by_the_limit() {
+        let doorbell = Doorbell { after: u64::MAX, polls: 0 };
+        let mut processor: Processor = ProcessorBuilder::new().device(&[0x10], Box::new(doorbell)).build();
+        processor.load_program(&[0xfb, 0x76]); // EI; HLT
+
+        assert_eq!(processor.run_with_limit(100), RunOutcome::InstructionLimit { instructions: 2 });
+        assert_eq!(processor.halt_state(), HaltState::Waiting);
+        assert!(processor.cycle_count() >= 100 * MAX_INSTRUCTION_CYCLES);
+    }
+}
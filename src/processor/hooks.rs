@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::Processor;
+
+/// What the processor does after a PC hook returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorAction {
+    /// Acts as if the guest executed RET at the hooked address: pops the
+    /// return address and jumps to it. The guest code there never runs.
+    Ret,
+    /// Executes the guest instruction at the PC as usual.
+    FallThrough,
+}
+
+/// Host code run when execution reaches an address, before the guest
+/// instruction there is fetched.
+pub type PcHook = Box<dyn FnMut(&mut Processor) -> VectorAction + Send>;
+
+/// The installed PC hooks, one per address.
+#[derive(Default)]
+pub struct PcHooks {
+    hooks: HashMap<u16, PcHook>,
+}
+
+impl PcHooks {
+    pub fn insert(&mut self, addr: u16, hook: PcHook) {
+        self.hooks.insert(addr, hook);
+    }
+
+    pub fn remove(&mut self, addr: u16) -> Option<PcHook> {
+        return self.hooks.remove(&addr);
+    }
+
+    /// Puts back a hook taken out to run it, unless the hook installed a
+    /// replacement for its own address while it ran.
+    pub fn restore(&mut self, addr: u16, hook: PcHook) {
+        self.hooks.entry(addr).or_insert(hook);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.hooks.is_empty();
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        return self.hooks.contains_key(&addr);
+    }
+}
+
+impl fmt::Debug for PcHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut addrs: Vec<&u16> = self.hooks.keys().collect();
+        addrs.sort();
+        return f.debug_set().entries(addrs.iter().map(|addr| format!("{:04x}", addr))).finish();
+    }
+}
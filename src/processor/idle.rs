@@ -0,0 +1,269 @@
+use super::fast_forward::{self, PollLoop};
+use super::{Event, Processor, RunOutcome};
+
+/// Why `Processor::run_block` returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockResult {
+    /// The block's cycles have passed.
+    Done,
+    /// The guest is waiting, and nothing it does changes anything until
+    /// `until`. `Processor::skip_idle` lets the wait pass without running
+    /// it.
+    Idle { until: IdleWake },
+    /// The guest stopped as `run` would have: HLT with interrupts
+    /// disabled, or a fault.
+    Stopped(RunOutcome),
+}
+
+/// What ends an idle stretch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleWake {
+    /// Halted until the interrupt scheduled for cycle `at`.
+    Scheduled { at: u64 },
+    /// Halted with nothing scheduled, until a device or the host raises an
+    /// interrupt.
+    Interrupt,
+    /// Polling `port` until what it reads changes or, with interrupts
+    /// enabled, until the interrupt scheduled for cycle `scheduled`, if any.
+    Input { port: u8, scheduled: Option<u64> },
+}
+
+impl IdleWake {
+    /// The cycle count the guest wakes at without help from the host, if
+    /// it does.
+    pub fn cycle(&self) -> Option<u64> {
+        return match self {
+            IdleWake::Scheduled { at } => Some(*at),
+            IdleWake::Interrupt => None,
+            IdleWake::Input { scheduled, .. } => *scheduled,
+        };
+    }
+}
+
+impl Processor {
+    /// Runs until `cycles` more cycles have passed, finishing the
+    /// instruction that crosses the end, but returns as soon as the guest
+    /// goes idle so a frontend can sleep through the wait rather than
+    /// emulate it. Breakpoints are not checked; a throttle paces the block
+    /// as it does `run`. A fault `skip_idle` ran into stops the next block
+    /// before it starts.
+    pub fn run_block(&mut self, cycles: u64) -> BlockResult {
+        if let Some(fault) = self.fault.take() {
+            return BlockResult::Stopped(self.fault_outcome(fault, self.instruction_pc));
+        }
+        let end: u64 = self.cycles + cycles;
+        let interval: u64 = self.deadline_check_interval.max(1);
+        let mut executed: u64 = 0;
+        while self.cycles < end {
+            if let Some(until) = self.idle_wake() {
+                return BlockResult::Idle { until };
+            }
+            if self.halt && !self.interrupt_pending() {
+                return BlockResult::Stopped(RunOutcome::Halted);
+            }
+            if executed.is_multiple_of(interval) {
+                if let Some(throttle) = &mut self.throttle {
+                    throttle.pace(self.cycles, &*self.clock);
+                }
+            }
+            self.step();
+            executed += 1;
+            if let Some(fault) = self.fault.take() {
+                return BlockResult::Stopped(self.fault_outcome(fault, self.instruction_pc));
+            }
+        }
+        return BlockResult::Done;
+    }
+
+    /// What the guest is waiting for, if it is idle: halted with interrupts
+    /// enabled, or going round an input polling loop on a port that keeps
+    /// reading the same until something changes it. Ports read that way
+    /// are those with no device, which read 0, and those whose device
+    /// answers `IoDevice::peek_input`.
+    pub fn idle_wake(&self) -> Option<IdleWake> {
+        if self.interrupt_pending() {
+            return None;
+        }
+        let scheduled: Option<u64> = self.schedule.map(|(at, _)| at).filter(|_| self.interrupt_enabled);
+        if self.halt {
+            return self.interrupt_enabled.then(|| scheduled.map_or(IdleWake::Interrupt, |at| IdleWake::Scheduled { at }));
+        }
+        let found: PollLoop = self.idle_poll()?;
+        return Some(IdleWake::Input { port: found.port, scheduled });
+    }
+
+    /// Lets emulated time pass up to cycle `until` on an idle guest without
+    /// running the wait an instruction at a time, leaving the processor as
+    /// running it would have. A halted processor's clock runs on to `until`
+    /// or the scheduled interrupt, whichever comes first, as the chip's
+    /// does. A polling loop goes round as many whole trips as end by then,
+    /// the last of them run for real. Frame callbacks, device polls and the
+    /// scheduled interrupt come due on the way, and a throttle sleeps
+    /// through the time skipped. Returns the cycles that passed.
+    pub fn skip_idle(&mut self, until: u64) -> u64 {
+        let start: u64 = self.cycles;
+        if self.halt && !self.interrupt_pending() {
+            self.skip_halted(until);
+        } else if let Some(found) = self.idle_wake().and_then(|_| self.idle_poll()) {
+            self.skip_trips(found, until);
+        }
+        if let Some(throttle) = &mut self.throttle {
+            throttle.pace(self.cycles, &*self.clock);
+        }
+        return self.cycles - start;
+    }
+
+    /// The polling loop at the PC, if what its port reads keeps it going
+    /// round and nothing would see trips being skipped. Ports with no
+    /// device are left alone while their reads are being counted.
+    fn idle_poll(&self) -> Option<PollLoop> {
+        let found: PollLoop = fast_forward::match_poll(|addr| self.memory.peek(addr), self.registers.pc)?;
+        let value: u8 = match self.port_map.get(&found.port) {
+            Some(index) => self.devices[*index].peek_input(self.port_addressing.bus_port(found.port))?,
+            None if self.unattached.is_some() => return None,
+            None => 0,
+        };
+        if !found.repeats(value) || self.observed(found.start..found.end) {
+            return None;
+        }
+        return Some(found);
+    }
+
+    pub(super) fn skip_halted(&mut self, until: u64) {
+        let until: u64 = self.schedule.map_or(until, |(at, _)| until.min(at));
+        while self.cycles < until {
+            self.cycles = match self.frames.callback {
+                Some(_) => until.min(self.frames.next_frame_at),
+                None => until,
+            };
+            while self.frames.frame_ended(self.cycles) {
+                self.run_frame_callback();
+            }
+        }
+        self.poll_devices();
+        self.fire_schedule();
+        self.metrics.set_progress(self.instructions, self.cycles);
+    }
+
+    /// Every trip leaves the registers as the last did, so all but the last
+    /// only add to the counters. The trips skipped all end before the
+    /// scheduled interrupt or frame end that would have come due after one.
+    fn skip_trips(&mut self, found: PollLoop, until: u64) {
+        let mut due: u64 = self.schedule.map_or(u64::MAX, |(at, _)| at);
+        if self.frames.callback.is_some() {
+            due = due.min(self.frames.next_frame_at);
+        }
+        let fit: u64 = until.saturating_sub(self.cycles) / found.cycles;
+        let before_due: u64 = due.saturating_sub(self.cycles + 1) / found.cycles + 1;
+        let skipped: u64 = fit.min(before_due).max(1) - 1;
+        if skipped > 0 {
+            self.instructions += skipped * found.instructions;
+            self.cycles += skipped * found.cycles;
+            self.emit(Event::LoopSkipped { addr: found.start, iterations: skipped });
+        }
+        for _ in 0..found.instructions {
+            self.step();
+            if self.fault.is_some() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{make_processor, Snapshot};
+
+    /// Cycles from one vertical blank to the next.
+    const FRAME: u64 = 33_333;
+
+    /// Waits for vertical blank in HLT, and counts the blanks at 2000h.
+    /// The RST 2 handler schedules the next one.
+    fn vblank_waiter() -> Processor {
+        let mut processor: Processor = make_processor();
+        processor.load_at(0x0000, &[
+            0x31, 0x00, 0x30, // 0000 LXI SP,3000h
+            0xfb,             // 0003 EI
+            0x76,             // 0004 HLT
+            0xc3, 0x04, 0x00, // 0005 JMP 0004h
+        ]);
+        processor.load_at(0x0010, &[0x21, 0x00, 0x20, 0x34, 0xfb, 0xc9]); // LXI H,2000h; INR M; EI; RET
+        processor.schedule_interrupt_in(FRAME, 0xd7);
+        return processor;
+    }
+
+    /// Steps until the interrupt has been taken and the guest is back at
+    /// its wait.
+    fn take_interrupt(processor: &mut Processor) -> Snapshot {
+        let count: u8 = processor.peek(0x2000);
+        while processor.peek(0x2000) == count || processor.pc() != 0x0005 {
+            processor.step();
+        }
+        return processor.snapshot();
+    }
+
+    #[test]
+    fn test_halted_guest_reports_the_scheduled_wake() {
+        let mut processor: Processor = vblank_waiter();
+
+        assert_eq!(processor.run_block(100_000), BlockResult::Idle { until: IdleWake::Scheduled { at: FRAME } });
+        assert_eq!(processor.cycle_count(), 21);
+        assert_eq!(processor.idle_wake().and_then(|wake| wake.cycle()), Some(FRAME));
+
+        assert_eq!(processor.skip_idle(100_000), FRAME - 21);
+        assert_eq!(processor.idle_wake(), None);
+        take_interrupt(&mut processor);
+        assert_eq!(processor.peek(0x2000), 1);
+        assert_eq!(processor.run_block(100_000), BlockResult::Idle { until: IdleWake::Interrupt });
+    }
+
+    #[test]
+    fn test_skipping_a_halt_matches_waiting_it_out() {
+        let mut skipped: Processor = vblank_waiter();
+        let mut waited: Processor = vblank_waiter();
+        skipped.run_block(100_000);
+        waited.run_block(100_000);
+
+        skipped.skip_idle(u64::MAX);
+        while waited.idle_wake().is_some() {
+            waited.skip_idle(waited.cycle_count() + 1);
+        }
+
+        let after: Snapshot = take_interrupt(&mut skipped);
+        assert_eq!(after, take_interrupt(&mut waited));
+        // RST 2 at the scheduled cycle, then the handler.
+        assert_eq!(after.cycles, FRAME + 11 + 10 + 10 + 4 + 10);
+    }
+
+    #[test]
+    fn test_skipping_a_polling_loop_matches_running_it() {
+        let build = || -> Processor {
+            let mut processor: Processor = make_processor();
+            processor.load_at(0x0100, &[
+                0x31, 0x00, 0x30, // 0100 LXI SP,3000h
+                0xfb,             // 0103 EI
+                0xdb, 0x01,       // 0104 IN 1, which has no device
+                0xe6, 0x01,       // 0106 ANI 01h
+                0xca, 0x04, 0x01, // 0108 JZ 0104h
+            ]);
+            processor.set_pc(0x0100);
+            processor.load_at(0x0008, &[0xfb, 0xc9]); // RST 1: EI; RET
+            processor.schedule_interrupt_in(10_000, 0xcf);
+            return processor;
+        };
+        let mut skipped: Processor = build();
+        let mut stepped: Processor = build();
+
+        assert_eq!(skipped.run_block(100_000), BlockResult::Idle { until: IdleWake::Input { port: 1, scheduled: Some(10_000) } });
+        assert!(skipped.skip_idle(u64::MAX) > 9_900);
+        while skipped.pc() != 0x0008 {
+            skipped.step();
+        }
+        while stepped.pc() != 0x0008 {
+            stepped.step();
+        }
+
+        assert_eq!(skipped.snapshot(), stepped.snapshot());
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Control transfers static disassembly cannot follow, as (source, target)
+/// pairs with how often each was taken: every PCHL, and every RET that did
+/// not go back to the instruction after the CALL or RST whose frame it
+/// popped. Its targets make good extra roots for `disasm::disassemble_reachable`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndirectFlowReport {
+    transfers: BTreeMap<(u16, u16), u64>,
+}
+
+impl IndirectFlowReport {
+    pub fn new() -> IndirectFlowReport {
+        return IndirectFlowReport::default();
+    }
+
+    /// The instruction at `source` went to `target`.
+    pub fn record(&mut self, source: u16, target: u16) {
+        *self.transfers.entry((source, target)).or_insert(0) += 1;
+    }
+
+    /// Times each (source, target) pair was taken, by source.
+    pub fn transfers(&self) -> &BTreeMap<(u16, u16), u64> {
+        return &self.transfers;
+    }
+
+    /// Every address reached indirectly, in order.
+    pub fn targets(&self) -> Vec<u16> {
+        let targets: BTreeSet<u16> = self.transfers.keys().map(|(_, target)| *target).collect();
+        return targets.into_iter().collect();
+    }
+}
+
+impl fmt::Display for IndirectFlowReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "indirect transfers:")?;
+        for ((source, target), count) in &self.transfers {
+            writeln!(f, "  {:04x} -> {:04x}  {:>8}", source, target, count)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets_and_listing() {
+        let mut report = IndirectFlowReport::new();
+        report.record(0x011b, 0x0128);
+        report.record(0x011b, 0x0122);
+        report.record(0x011b, 0x0128);
+        report.record(0x0140, 0x0122);
+
+        assert_eq!(report.targets(), vec![0x0122, 0x0128]);
+        assert_eq!(report.to_string(), "indirect transfers:\n  011b -> 0122         1\n  011b -> 0128         2\n  0140 -> 0122         1\n");
+    }
+}
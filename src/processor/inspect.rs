@@ -0,0 +1,141 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use super::{MemoryBus, Registers};
+
+/// Instructions between published views unless configured otherwise.
+pub const DEFAULT_INSPECTION_INTERVAL: u64 = 10_000;
+
+/// The machine state at one instruction boundary: the registers and copies
+/// of the memory ranges the handle asked for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InspectionView {
+    /// Instructions completed when the view was taken.
+    pub instructions: u64,
+    pub registers: Registers,
+    ranges: Vec<(Range<u16>, Vec<u8>)>,
+}
+
+impl InspectionView {
+    /// The bytes of `range`, if it lies inside one of the copied ranges.
+    pub fn read_range(&self, range: Range<u16>) -> Option<Vec<u8>> {
+        let (copied, bytes) = self.ranges.iter()
+            .find(|(copied, _)| copied.start <= range.start && range.end <= copied.end)?;
+        let start = (range.start - copied.start) as usize;
+        return Some(bytes[start..start + range.len()].to_vec());
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    view: Mutex<Arc<InspectionView>>,
+    ranges: Mutex<Vec<Range<u16>>>,
+}
+
+/// A read-only view of a processor that may be running on another thread.
+///
+/// The view is slightly stale: the run loop publishes a fresh one every
+/// inspection interval. Each view is taken between two instructions, so
+/// its registers and memory always agree with each other.
+#[derive(Debug, Clone, Default)]
+pub struct InspectionHandle {
+    shared: Arc<Shared>,
+}
+
+impl InspectionHandle {
+    /// Copies `range` into every view published from now on.
+    pub fn add_range(&self, range: Range<u16>) {
+        self.shared.ranges.lock().unwrap().push(range);
+    }
+
+    /// The latest view. Hold on to it to read several values from the same
+    /// instruction boundary.
+    pub fn view(&self) -> Arc<InspectionView> {
+        return self.shared.view.lock().unwrap().clone();
+    }
+
+    pub fn registers(&self) -> Registers {
+        return self.view().registers;
+    }
+
+    /// See `InspectionView::read_range`.
+    pub fn read_range(&self, range: Range<u16>) -> Option<Vec<u8>> {
+        return self.view().read_range(range);
+    }
+
+    pub fn instructions(&self) -> u64 {
+        return self.view().instructions;
+    }
+}
+
+/// The processor's side of an `InspectionHandle`. Views are built in a
+/// spare buffer and swapped in, so readers only ever wait for the swap.
+#[derive(Debug)]
+pub struct Publisher {
+    handle: InspectionHandle,
+    pub interval: u64,
+    spare: Option<Arc<InspectionView>>,
+}
+
+impl Publisher {
+    pub fn new(interval: u64) -> Publisher {
+        return Publisher { handle: InspectionHandle::default(), interval: interval.max(1), spare: None };
+    }
+
+    pub fn handle(&self) -> InspectionHandle {
+        return self.handle.clone();
+    }
+
+    pub fn publish(&mut self, instructions: u64, registers: Registers, memory: &dyn MemoryBus) {
+        let ranges: Vec<Range<u16>> = self.handle.shared.ranges.lock().unwrap().clone();
+        let mut view: Arc<InspectionView> = self.spare.take().unwrap_or_default();
+        // Readers still holding the spare keep it; build a new one instead.
+        let buffer: &mut InspectionView = Arc::make_mut(&mut view);
+        buffer.instructions = instructions;
+        buffer.registers = registers;
+        buffer.ranges.resize_with(ranges.len(), Default::default);
+        for ((copied, bytes), range) in buffer.ranges.iter_mut().zip(ranges) {
+            bytes.clear();
+            bytes.extend(range.clone().map(|addr| memory.peek(addr)));
+            *copied = range;
+        }
+        let old: Arc<InspectionView> = std::mem::replace(&mut *self.handle.shared.view.lock().unwrap(), view);
+        self.spare = Some(old);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::FlatMemory;
+
+    #[test]
+    fn test_read_range_within_copied_ranges() {
+        let mut memory = FlatMemory::new();
+        memory.load(0x2000, &[1, 2, 3, 4]);
+        let mut publisher = Publisher::new(1);
+        let handle = publisher.handle();
+        handle.add_range(0x2000..0x2004);
+
+        publisher.publish(7, Registers::default(), &memory);
+
+        assert_eq!(handle.instructions(), 7);
+        assert_eq!(handle.read_range(0x2001..0x2003), Some(vec![2, 3]));
+        assert_eq!(handle.read_range(0x2002..0x2005), None);
+    }
+
+    #[test]
+    fn test_held_view_is_not_overwritten() {
+        let memory = FlatMemory::new();
+        let mut publisher = Publisher::new(1);
+        let handle = publisher.handle();
+        publisher.publish(1, Registers::default(), &memory);
+        let held = handle.view();
+
+        publisher.publish(2, Registers::default(), &memory);
+        publisher.publish(3, Registers::default(), &memory);
+
+        assert_eq!(held.instructions, 1);
+        assert_eq!(handle.instructions(), 3);
+    }
+}
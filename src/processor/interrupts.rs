@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde-state")]
+use serde::{Deserialize, Serialize};
+
+/// The source `Processor::request_interrupt` raises. It has the lowest
+/// priority.
+pub const HOST_INTERRUPT_SOURCE: u8 = u8::MAX;
+
+/// The source `Processor::schedule_interrupt_in` raises, just above
+/// `HOST_INTERRUPT_SOURCE`.
+pub const SCHEDULED_INTERRUPT_SOURCE: u8 = u8::MAX - 1;
+
+/// Arbitrates between interrupt sources. Each source has at most one
+/// request pending; lower source ids have higher priority. The highest
+/// pending request is what the processor sees on its interrupt line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-state", derive(Serialize, Deserialize))]
+pub struct InterruptController {
+    /// RST opcode requested by each source.
+    pending: BTreeMap<u8, u8>,
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        return InterruptController::default();
+    }
+
+    /// Requests RST `rst_opcode` for `source`, replacing any request from
+    /// the same source not yet taken. Only the vector bits of the opcode
+    /// are used.
+    pub fn raise(&mut self, source: u8, rst_opcode: u8) {
+        self.pending.insert(source, 0xc7 | (rst_opcode & 0b0011_1000));
+    }
+
+    /// Withdraws the request from `source`, if it has not been taken yet.
+    pub fn clear(&mut self, source: u8) {
+        self.pending.remove(&source);
+    }
+
+    /// The source and RST opcode that would be delivered next.
+    pub fn highest(&self) -> Option<(u8, u8)> {
+        return self.pending.iter().next().map(|(source, opcode)| (*source, *opcode));
+    }
+
+    /// Removes and returns the highest-priority request.
+    pub fn acknowledge(&mut self) -> Option<(u8, u8)> {
+        return self.pending.pop_first();
+    }
+
+    pub fn is_pending(&self) -> bool {
+        return !self.pending.is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_order() {
+        let mut controller = InterruptController::new();
+        controller.raise(5, 0xcf);
+        controller.raise(2, 0xd7);
+        controller.raise(9, 0xff);
+        controller.clear(9);
+        controller.raise(5, 0xdf);
+
+        assert_eq!(controller.acknowledge(), Some((2, 0xd7)));
+        assert_eq!(controller.highest(), Some((5, 0xdf)));
+        assert_eq!(controller.acknowledge(), Some((5, 0xdf)));
+        assert!(!controller.is_pending());
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt::Debug;
+
+use crate::word::word;
+
+/// What a device can see of the processor when the guest accesses it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoContext {
+    /// Cycles executed so far, including the IN or OUT doing the access.
+    pub cycles: u64,
+    /// Instructions completed before the IN or OUT doing the access.
+    pub instructions: u64,
+    /// Address of the IN or OUT instruction.
+    pub pc: u16,
+}
+
+/// How the port number appears on the 16-bit address bus during IN and OUT.
+/// Devices are always selected by the low byte.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PortAddressing {
+    /// The port with a zero high byte.
+    #[default]
+    Standard,
+    /// The port on both halves of the bus, as the 8080 drives it, for
+    /// hardware that decodes the high byte.
+    Duplicated,
+}
+
+impl PortAddressing {
+    /// The 16-bit port value devices see for `port`.
+    pub fn bus_port(self, port: u8) -> u16 {
+        return match self {
+            PortAddressing::Standard => port as u16,
+            PortAddressing::Duplicated => word(port, port),
+        };
+    }
+}
+
+/// Work a device asks the processor to do on its behalf after an OUT: a
+/// block copy through the memory bus, with the CPU stalled while it runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BusRequest {
+    pub source: u16,
+    pub dest: u16,
+    /// Bytes to copy, in increasing address order.
+    pub len: u16,
+    /// Cycles the CPU is held off the bus for.
+    pub stall_cycles: u64,
+    /// Interrupt source and RST opcode to raise once the copy is done.
+    pub interrupt: Option<(u8, u8)>,
+}
+
+/// A message a device asks the processor to log after an OUT: the
+/// NUL-terminated string at `addr`, at the guest's `severity`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogRequest {
+    pub severity: u8,
+    pub addr: u16,
+}
+
+/// Something attached to one or more I/O ports. `port` is the value on the
+/// address bus as `PortAddressing` composes it; devices that only decode
+/// eight bits use the low byte.
+pub trait IoDevice: Debug + Send {
+    /// Called for IN. The result is loaded into the accumulator.
+    fn read(&mut self, port: u16, ctx: &IoContext) -> u8;
+
+    /// Called for OUT with the accumulator's value.
+    fn write(&mut self, port: u16, value: u8, ctx: &IoContext);
+
+    /// Called after every OUT to the device. Devices that master the bus
+    /// return the transfer the OUT started; the rest keep the default.
+    fn bus_request(&mut self) -> Option<BusRequest> {
+        return None;
+    }
+
+    /// Called after every OUT to the device. Devices that carry guest log
+    /// messages return the one the OUT completed; the rest keep the default.
+    fn log_request(&mut self) -> Option<LogRequest> {
+        return None;
+    }
+
+    /// Called between instructions. Devices that interrupt on their own,
+    /// rather than in answer to an OUT, return the source and RST opcode
+    /// to raise; the rest keep the default.
+    fn poll_interrupt(&mut self, _ctx: &IoContext) -> Option<(u8, u8)> {
+        return None;
+    }
+
+    /// Whether `poll_interrupt` may ever raise anything, so a guest halted
+    /// with interrupts enabled waits for this device rather than counting
+    /// as stuck. Devices that override `poll_interrupt` say so here.
+    fn raises_interrupts(&self) -> bool {
+        return false;
+    }
+
+    /// What IN from `port` would read, for ports whose reads have no side
+    /// effects and only change when the host changes the device's inputs,
+    /// such as a cabinet's switches. A guest polling such a port counts as
+    /// idle; the rest keep the default.
+    fn peek_input(&self, _port: u16) -> Option<u8> {
+        return None;
+    }
+
+    /// Internal state to keep in snapshots, so a resumed run sees the device
+    /// exactly as it was. Stateless devices keep the default.
+    fn save_state(&self) -> Vec<u8> {
+        return Vec::new();
+    }
+
+    /// Puts back what `save_state` returned.
+    fn restore_state(&mut self, _state: &[u8]) {}
+
+    /// Called when the machine is reset. Devices go back to the state they
+    /// power on in, keeping host-side settings such as their ports and
+    /// interrupt vectors; stateless devices keep the default.
+    fn reset(&mut self) {}
+}
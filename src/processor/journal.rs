@@ -0,0 +1,128 @@
+use std::fmt;
+
+/// Entries kept before further writes are only counted.
+pub const DEFAULT_JOURNAL_LIMIT: usize = 1 << 16;
+
+/// One guest memory write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+    /// Address of the instruction that wrote.
+    pub pc: u16,
+    /// Instructions completed before the one that wrote.
+    pub instruction: u64,
+}
+
+/// One write to a single address, as `Processor::write_history` lists it.
+pub type WriteRecord = JournalEntry;
+
+impl fmt::Display for JournalEntry {
+    /// e.g. `        12  0107  [2f3a] 00->05`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{:>10}  {:04x}  [{:04x}] {:02x}->{:02x}", self.instruction, self.pc, self.addr, self.old, self.new);
+    }
+}
+
+/// Log of guest memory writes, oldest first. Once `limit` entries are held,
+/// further writes are counted in `dropped` instead of stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteJournal {
+    entries: Vec<JournalEntry>,
+    limit: usize,
+    dropped: u64,
+}
+
+impl Default for WriteJournal {
+    fn default() -> WriteJournal {
+        return WriteJournal::with_limit(DEFAULT_JOURNAL_LIMIT);
+    }
+}
+
+impl WriteJournal {
+    pub fn new() -> WriteJournal {
+        return WriteJournal::default();
+    }
+
+    pub fn with_limit(limit: usize) -> WriteJournal {
+        return WriteJournal { entries: Vec::new(), limit, dropped: 0 };
+    }
+
+    pub fn record(&mut self, entry: JournalEntry) {
+        if self.entries.len() < self.limit {
+            self.entries.push(entry);
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        return &self.entries;
+    }
+
+    /// Writes that happened after the journal filled up.
+    pub fn dropped(&self) -> u64 {
+        return self.dropped;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty() && self.dropped == 0;
+    }
+
+    /// The entries and dropped count recorded after the journal held
+    /// `entries` entries and had dropped `dropped` writes, as a journal of
+    /// their own.
+    pub fn since(&self, entries: usize, dropped: u64) -> WriteJournal {
+        return WriteJournal {
+            entries: self.entries.get(entries..).unwrap_or_default().to_vec(),
+            limit: self.limit,
+            dropped: self.dropped.saturating_sub(dropped),
+        };
+    }
+
+    /// Every entry for `addr`, oldest first.
+    pub fn writes_to(&self, addr: u16) -> Vec<WriteRecord> {
+        return self.entries.iter().filter(|entry| entry.addr == addr).copied().collect();
+    }
+
+    /// Empties the journal, keeping its limit.
+    pub fn take(&mut self) -> WriteJournal {
+        let limit: usize = self.limit;
+        return std::mem::replace(self, WriteJournal::with_limit(limit));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_counts_dropped_writes() {
+        let mut journal = WriteJournal::with_limit(2);
+        for addr in 0..5 {
+            journal.record(JournalEntry { addr, old: 0, new: 1, pc: 0, instruction: addr as u64 });
+        }
+
+        assert_eq!(journal.entries().len(), 2);
+        assert_eq!(journal.dropped(), 3);
+
+        let taken = journal.take();
+        assert_eq!(taken.dropped(), 3);
+        assert!(journal.is_empty());
+    }
+
+    #[test]
+    fn test_since_and_writes_to() {
+        let mut journal = WriteJournal::with_limit(4);
+        for (instruction, addr) in [0x10, 0x20, 0x10, 0x30, 0x10, 0x10].into_iter().enumerate() {
+            journal.record(JournalEntry { addr, old: 0, new: instruction as u8, pc: 0, instruction: instruction as u64 });
+        }
+
+        let recent = journal.since(2, 0);
+        assert_eq!(recent.entries().iter().map(|entry| entry.addr).collect::<Vec<u16>>(), vec![0x10, 0x30]);
+        assert_eq!(recent.dropped(), 2);
+        assert_eq!(journal.writes_to(0x10).iter().map(|entry| entry.instruction).collect::<Vec<u64>>(), vec![0, 2]);
+        assert!(journal.since(9, 2).is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde-state")]
+use serde::{Deserialize, Serialize};
+
+/// The fewest, most and total cycles over a number of measurements.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-state", derive(Serialize, Deserialize))]
+pub struct CycleStats {
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub total: u64,
+}
+
+impl CycleStats {
+    pub fn record(&mut self, cycles: u64) {
+        self.min = if self.count == 0 { cycles } else { self.min.min(cycles) };
+        self.max = self.max.max(cycles);
+        self.total += cycles;
+        self.count += 1;
+    }
+
+    /// Mean cycles per measurement, or 0 with none.
+    pub fn avg(&self) -> f64 {
+        return if self.count == 0 { 0.0 } else { self.total as f64 / self.count as f64 };
+    }
+}
+
+/// How long one interrupt source waited to be serviced and how long its
+/// handler ran.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-state", derive(Serialize))]
+pub struct InterruptTiming {
+    /// Cycles from the request being raised to the first instruction of
+    /// the handler, including the RST.
+    pub latency: CycleStats,
+    /// Cycles from the first instruction of the handler to the end of the
+    /// RET that leaves it, including any interrupts nested inside.
+    pub handler: CycleStats,
+}
+
+/// A handler that has not returned yet.
+#[derive(Debug, Clone, Copy)]
+struct Handler {
+    source: u8,
+    /// Shadow call stack depth with the handler's frame on it.
+    depth: usize,
+    entered: u64,
+}
+
+/// Times interrupt service per source from the interrupt controller's
+/// requests and the shadow call stack: a handler is entered when its RST
+/// frame is pushed and left when that frame is popped.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyTracker {
+    /// Cycle count at which each request not yet taken was raised.
+    raised: BTreeMap<u8, u64>,
+    handlers: Vec<Handler>,
+    sources: BTreeMap<u8, InterruptTiming>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> LatencyTracker {
+        return LatencyTracker::default();
+    }
+
+    /// `source` raised a request at `cycles`. A request replacing one not
+    /// yet taken keeps the earlier time, as the source has been waiting
+    /// since then.
+    pub fn raised(&mut self, source: u8, cycles: u64) {
+        self.raised.entry(source).or_insert(cycles);
+    }
+
+    pub fn cleared(&mut self, source: u8) {
+        self.raised.remove(&source);
+    }
+
+    /// `source`'s handler starts at `cycles`, with its frame making the
+    /// shadow call stack `depth` deep.
+    pub fn entered(&mut self, source: u8, cycles: u64, depth: usize) {
+        if let Some(raised) = self.raised.remove(&source) {
+            self.sources.entry(source).or_default().latency.record(cycles - raised);
+        }
+        self.handlers.push(Handler { source, depth, entered: cycles });
+    }
+
+    /// Called after each instruction with the shadow call stack's depth;
+    /// handlers whose frame has gone returned at `cycles`.
+    pub fn returned(&mut self, cycles: u64, depth: usize) {
+        while let Some(handler) = self.handlers.last().filter(|handler| handler.depth > depth).copied() {
+            self.handlers.pop();
+            self.sources.entry(handler.source).or_default().handler.record(cycles - handler.entered);
+        }
+    }
+
+    /// Forgets requests and handlers in flight, for when the machine is
+    /// put back to an earlier state. Finished measurements are kept.
+    pub fn abandon(&mut self) {
+        self.raised.clear();
+        self.handlers.clear();
+    }
+
+    /// Timings for each source that has been serviced, by source.
+    pub fn sources(&self) -> &BTreeMap<u8, InterruptTiming> {
+        return &self.sources;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_handlers() {
+        let mut tracker = LatencyTracker::new();
+        tracker.raised(1, 100);
+        tracker.raised(1, 150);
+        tracker.entered(1, 130, 1);
+        tracker.raised(0, 140);
+        tracker.entered(0, 160, 2);
+        tracker.returned(170, 2);
+        tracker.returned(200, 1);
+        tracker.returned(220, 0);
+
+        let sources = tracker.sources();
+        assert_eq!(sources[&1].latency, CycleStats { count: 1, min: 30, max: 30, total: 30 });
+        assert_eq!(sources[&0].handler, CycleStats { count: 1, min: 40, max: 40, total: 40 });
+        assert_eq!(sources[&1].handler.max, 90);
+        assert_eq!(sources[&0].latency.avg(), 20.0);
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "serde-state")]
+use serde::Serialize;
+
+/// Counters describing what a processor has done so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-state", derive(Serialize))]
+pub struct Metrics {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub interrupts_delivered: u64,
+    /// Runs stopped by a breakpoint.
+    pub breakpoint_hits: u64,
+    /// Bytes the guest wrote with OUT, to any port.
+    pub output_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    instructions: AtomicU64,
+    cycles: AtomicU64,
+    interrupts_delivered: AtomicU64,
+    breakpoint_hits: AtomicU64,
+    output_bytes: AtomicU64,
+}
+
+/// Lets a host poll a processor's `Metrics`, including from another thread
+/// while it runs. Clones share the same counters.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsHandle {
+    counters: Arc<Counters>,
+}
+
+impl MetricsHandle {
+    /// The counters as of the last instruction boundary. A snapshot never
+    /// has more instructions than the cycles it reports account for.
+    pub fn snapshot(&self) -> Metrics {
+        let counters = &self.counters;
+        let instructions: u64 = counters.instructions.load(Ordering::Acquire);
+        return Metrics {
+            instructions,
+            cycles: counters.cycles.load(Ordering::Relaxed),
+            interrupts_delivered: counters.interrupts_delivered.load(Ordering::Relaxed),
+            breakpoint_hits: counters.breakpoint_hits.load(Ordering::Relaxed),
+            output_bytes: counters.output_bytes.load(Ordering::Relaxed),
+        };
+    }
+
+    /// Publishes the processor's own instruction and cycle counts.
+    pub(super) fn set_progress(&self, instructions: u64, cycles: u64) {
+        self.counters.cycles.store(cycles, Ordering::Relaxed);
+        self.counters.instructions.store(instructions, Ordering::Release);
+    }
+
+    pub(super) fn count_interrupt(&self) {
+        self.counters.interrupts_delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn count_breakpoint(&self) {
+        self.counters.breakpoint_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn count_output(&self) {
+        self.counters.output_bytes.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,148 @@
+//! Reading snapshots written by earlier versions. Each snapshot carries
+//! `format_version`. Fields added with a serde default keep the version,
+//! since older files still read; a layout change that would stop them
+//! reading bumps it and adds a migration from the version before, so a
+//! file of any earlier version is upgraded one step at a time.
+//!
+//! Snapshots taken before stack words were kept low byte first hold return
+//! addresses and pushed pairs byte-swapped. Nothing tells those bytes from
+//! data, so no migration can put them right.
+
+use std::error::Error;
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use super::{ConditionBits, Snapshot, SNAPSHOT_VERSION};
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` upgrades version `n + 1` to `n + 2`.
+const MIGRATIONS: [Migration; SNAPSHOT_VERSION as usize - 1] = [registers_and_flags];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Not JSON, or not a snapshot once migrated.
+    Invalid { message: String },
+    /// Written by a newer build than this one.
+    FutureVersion { version: u64 },
+    /// A version no build wrote, so nothing can upgrade it.
+    UnknownVersion { version: u64 },
+    Migration { from: u32, message: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            SnapshotError::Invalid { message } => write!(f, "not a snapshot: {}", message),
+            SnapshotError::FutureVersion { version } =>
+                write!(f, "snapshot format_version {} is newer than this build reads ({})", version, SNAPSHOT_VERSION),
+            SnapshotError::UnknownVersion { version } => write!(f, "no migration from snapshot format_version {}", version),
+            SnapshotError::Migration { from, message } =>
+                write!(f, "could not upgrade snapshot from format_version {}: {}", from, message),
+        };
+    }
+}
+
+impl Error for SnapshotError {}
+
+/// Version 1 kept the registers at the top level with the flags as a PSW
+/// byte; version 2 nests them as `Registers`.
+fn registers_and_flags(snapshot: &mut Map<String, Value>) -> Result<(), String> {
+    let mut registers: Map<String, Value> = Map::new();
+    for name in ["a", "b", "c", "d", "e", "h", "l", "sp", "pc"] {
+        let value: Value = snapshot.remove(name).ok_or(format!("missing register {}", name))?;
+        registers.insert(name.to_string(), value);
+    }
+    let flags: u8 = snapshot.remove("flags").as_ref().and_then(Value::as_u64)
+        .and_then(|byte| u8::try_from(byte).ok())
+        .ok_or("missing flags byte")?;
+    let flags: Value = serde_json::to_value(ConditionBits::from_psw_byte(flags)).map_err(|err| err.to_string())?;
+    registers.insert("flags".to_string(), flags);
+    snapshot.insert("registers".to_string(), Value::Object(registers));
+    return Ok(());
+}
+
+/// Upgrades a snapshot document to `SNAPSHOT_VERSION`. One without
+/// `format_version` is version 1, which did not write it.
+pub fn migrate(document: Value) -> Result<Value, SnapshotError> {
+    let Value::Object(mut snapshot) = document else {
+        return Err(SnapshotError::Invalid { message: "expected an object".to_string() });
+    };
+    let version: u64 = match snapshot.get("format_version") {
+        None => 1,
+        Some(version) => version.as_u64().ok_or(SnapshotError::Invalid { message: "format_version is not a number".to_string() })?,
+    };
+    if version > SNAPSHOT_VERSION as u64 {
+        return Err(SnapshotError::FutureVersion { version });
+    }
+    if version == 0 {
+        return Err(SnapshotError::UnknownVersion { version });
+    }
+    for from in version as u32..SNAPSHOT_VERSION {
+        MIGRATIONS[from as usize - 1](&mut snapshot).map_err(|message| SnapshotError::Migration { from, message })?;
+        snapshot.insert("format_version".to_string(), Value::from(from + 1));
+    }
+    return Ok(Value::Object(snapshot));
+}
+
+impl Snapshot {
+    /// Reads a snapshot saved as JSON by this or any earlier version.
+    pub fn from_json(json: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let document: Value = serde_json::from_slice(json).map_err(|err| SnapshotError::Invalid { message: err.to_string() })?;
+        return serde_json::from_value(migrate(document)?).map_err(|err| SnapshotError::Invalid { message: err.to_string() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::processor::{make_processor, Processor, RunOutcome};
+
+    /// Written by the first version with checkpoints, 80 instructions into
+    /// tests/capitalize.bin. That version kept stack words high byte first.
+    const CAPITALIZE_V1: &str = "tests/snapshots/v1/capitalize-80.json";
+
+    #[test]
+    fn test_version_1_checkpoint_resumes_like_a_fresh_run() {
+        let mut snapshot: Snapshot = Snapshot::from_json(&fs::read(CAPITALIZE_V1).unwrap()).unwrap();
+        assert_eq!((snapshot.format_version, snapshot.instructions, snapshot.registers.pc), (SNAPSHOT_VERSION, 80, 0x000f));
+        // The return address of the CALL at 0008h, the only word on the stack.
+        let sp: usize = snapshot.registers.sp as usize;
+        assert_eq!(&snapshot.memory[sp..sp + 2], &[0x00, 0x0b]);
+        snapshot.memory.swap(sp, sp + 1);
+
+        let mut fresh: Processor = make_processor();
+        fresh.load_program(&fs::read("tests/capitalize.bin").unwrap());
+        fresh.run_with_limit(80);
+        assert_eq!(fresh.snapshot(), snapshot);
+
+        let mut resumed: Processor = make_processor();
+        resumed.restore(&snapshot).unwrap();
+        assert_eq!((resumed.run(), fresh.run()), (RunOutcome::Halted, RunOutcome::Halted));
+        assert_eq!(resumed.snapshot(), fresh.snapshot());
+    }
+
+    #[test]
+    fn test_current_snapshots_read_back_unchanged() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0x3e, 0x42, 0x37, 0x76]); // MVI A,42h; STC; HLT
+        processor.run();
+        let snapshot: Snapshot = processor.snapshot();
+
+        assert_eq!(Snapshot::from_json(&serde_json::to_vec(&snapshot).unwrap()).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_versions_without_a_migration_are_refused() {
+        let future: String = format!("{{\"format_version\": {}}}", SNAPSHOT_VERSION + 1);
+        assert_eq!(Snapshot::from_json(future.as_bytes()).unwrap_err().to_string(),
+                   "snapshot format_version 3 is newer than this build reads (2)");
+        assert_eq!(Snapshot::from_json(b"{\"format_version\": 0}").unwrap_err(), SnapshotError::UnknownVersion { version: 0 });
+        assert_eq!(Snapshot::from_json(b"{\"a\": 1}").unwrap_err().to_string(),
+                   "could not upgrade snapshot from format_version 1: missing register b");
+        assert!(matches!(Snapshot::from_json(b"[1, 2]"), Err(SnapshotError::Invalid { .. })));
+    }
+}
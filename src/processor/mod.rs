@@ -1,55 +1,237 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-
-#[derive(Debug)]
-#[derive(Default)]
-struct ConditionBits {
-    carry: bool, // set if value is carried out of the highest order bit
-    aux_carry: bool, // NOT IMPLEMENTED: Not used for this project
-    sign: bool, // set to 1 when bit 7 is set
-    zero: bool, // set when result is equal to 0
-    parity: bool // set when result is even
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+use crate::disasm;
+use crate::instruction::Instruction;
+use crate::progress::{Progress, ProgressReporter};
+use crate::word::{self, hi, lo};
+
+mod access;
+mod allocator;
+mod aux_carry;
+mod branches;
+mod builder;
+mod bus;
+mod call_stack;
+mod callbacks;
+mod error;
+mod events;
+mod expr_watch;
+mod extension;
+mod fast_forward;
+mod fault;
+mod flag_audit;
+mod frames;
+mod halting;
+mod hooks;
+mod idle;
+mod indirect;
+mod inspect;
+mod interrupts;
+mod io;
+mod journal;
+mod latency;
+mod metrics;
+#[cfg(feature = "serde-state")]
+mod migrations;
+mod multi;
+mod nesting;
+mod opcodes;
+mod outcome;
+mod quirks;
+mod registers;
+mod rom_writes;
+mod schedule;
+mod search;
+mod snapshot;
+mod stack_view;
+#[cfg(feature = "debugger")]
+mod step_undo;
+mod steps;
+mod strict;
+mod throttle;
+mod timing;
+mod trace;
+mod transfer;
+mod unattached;
+#[cfg(test)]
+mod wrap;
+
+pub use access::{AccessMap, ACCESS_EXECUTED, ACCESS_READ, ACCESS_WRITTEN};
+pub use allocator::{AllocatorTracker, AllocatorUsage};
+pub use branches::BranchSite;
+pub use builder::{ProcessorBuilder, StackCanary};
+pub use bus::{FlatMemory, MemoryBus, MemoryViewError, MEMORY_SIZE};
+pub use call_stack::CallFrame;
+pub use callbacks::{AddrCallback, CallbackId, Ctx};
+pub use error::EmuError;
+pub use events::{Event, EventLog, EventSink, TimedEvent, DEFAULT_EVENT_LOG_CAPACITY};
+pub use expr_watch::ExprFn;
+pub use extension::{ExtensionResult, OpcodeExtension};
+pub use fault::{FaultReport, FAULT_HISTORY, FAULT_STACK_BYTES};
+use aux_carry::AuxCarryAudit;
+use flag_audit::FlagAudit;
+use nesting::HandlerNesting;
+pub use frames::FrameCallback;
+pub use halting::{classify_halt, HaltState, HALT_WAIT_CYCLES};
+use halting::MAX_INSTRUCTION_CYCLES;
+pub use hooks::{PcHook, VectorAction};
+pub use idle::{BlockResult, IdleWake};
+pub use indirect::IndirectFlowReport;
+pub use inspect::{InspectionHandle, InspectionView, DEFAULT_INSPECTION_INTERVAL};
+pub use interrupts::{InterruptController, HOST_INTERRUPT_SOURCE, SCHEDULED_INTERRUPT_SOURCE};
+pub use io::{BusRequest, IoContext, IoDevice, LogRequest, PortAddressing};
+pub use journal::{JournalEntry, WriteJournal, WriteRecord, DEFAULT_JOURNAL_LIMIT};
+pub use latency::{CycleStats, InterruptTiming, LatencyTracker};
+pub use metrics::{Metrics, MetricsHandle};
+#[cfg(feature = "serde-state")]
+pub use migrations::{migrate, SnapshotError};
+pub use multi::{MultiCpu, MultiOutcome, SharedBus, SharedMemory, DEFAULT_QUANTUM};
+pub(crate) use opcodes::instruction_length;
+pub use outcome::RunOutcome;
+pub use quirks::QuirkProfile;
+pub use registers::{ConditionBits, RegisterPair, Registers, PSW_ALWAYS_CLEAR, PSW_ALWAYS_SET};
+pub use rom_writes::RomWritePolicy;
+pub use snapshot::{RegisterChange, RestoreError, Snapshot, SnapshotDiff, SNAPSHOT_VERSION};
+pub use stack_view::{StackEntry, StackSlot};
+#[cfg(feature = "debugger")]
+pub use step_undo::UndoError;
+#[cfg(feature = "serde-state")]
+pub(crate) use snapshot::hex_bytes;
+pub use steps::{BreakpointPolicy, StepInfo, Steps};
+pub use strict::{RegionAccess, StrictMode, DEFAULT_VECTOR_PAGE, RST_VECTORS};
+pub use throttle::Throttle;
+pub use transfer::{StateSelection, TransferError};
+pub use trace::{opcode_class, parse_class, FlagDelta, TraceFilter, TraceRecord, Tracer, TRACE_ALL, TRACE_DATA, TRACE_FLOW, TRACE_IO, TRACE_STACK};
+pub use unattached::UnattachedRead;
+use call_stack::ShadowStack;
+use callbacks::Callbacks;
+use expr_watch::ExprWatches;
+use extension::ExtensionSlot;
+use fast_forward::CountdownLoop;
+use fault::RecentPcs;
+use frames::FrameClock;
+use hooks::PcHooks;
+use inspect::Publisher;
+use timing::{mov_penalty, CONDITIONAL_TAKEN_PENALTY, CYCLES};
+
+/// How many instructions `run_with_deadline` executes between clock reads
+/// unless configured otherwise.
+pub const DEFAULT_DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+/// How many instructions pass between scans of the stack guard band unless
+/// configured otherwise.
+pub const DEFAULT_CANARY_CHECK_INTERVAL: u64 = 1024;
+
+/// The longest string a guest log message is read as.
+pub const GUEST_STRING_MAX: usize = 1024;
+
+/// A call `step_over` or `step_out` is waiting to return from.
+#[derive(Debug, Clone, Copy)]
+struct ReturnTarget {
+    /// Shadow stack depth once the call has returned.
+    depth: usize,
+    return_addr: u16,
+    /// SP once the return address has been popped.
+    sp: u16,
 }
 
 #[derive(Debug)]
 #[derive(Default)]
 pub struct Processor {
-    a: u8,
-    b: u8,
-    c: u8,
-    d: u8,
-    e: u8,
-    h: u8,
-    l: u8,
-    sp: u16,
-    pc: u16,
-    conditions: ConditionBits,
+    registers: Registers,
     halt: bool,
     interrupt_enabled: bool,
-    memory: Vec<u8>,
-}
-
-pub fn make_processor() -> Processor {
-    return Processor { ..Default::default()};
+    memory: Box<dyn MemoryBus>,
+    instructions: u64,
+    cycles: u64,
+    breakpoints: HashSet<u16>,
+    watchpoints: Vec<(u16, u16)>,
+    watch_hit: Option<u16>,
+    /// The address and value `run_until_mem` is waiting for.
+    until_mem: Option<(u16, u8)>,
+    /// PC of the instruction that stored the `until_mem` value.
+    until_hit: Option<u16>,
+    /// Where `run_to` stops.
+    run_target: Option<u16>,
+    /// The frame `step_over` or `step_out` runs until it returns.
+    return_target: Option<ReturnTarget>,
+    instruction_pc: u16,
+    deadline_check_interval: u64,
+    progress: Option<ProgressReporter>,
+    canary: Option<StackCanary>,
+    fault: Option<EmuError>,
+    devices: Vec<Box<dyn IoDevice>>,
+    port_map: HashMap<u8, usize>,
+    call_stack: ShadowStack,
+    strict: Option<StrictMode>,
+    journal: Option<WriteJournal>,
+    clock: Box<dyn Clock>,
+    throttle: Option<Throttle>,
+    hooks: PcHooks,
+    callbacks: Callbacks,
+    expr_watches: ExprWatches,
+    interrupts: InterruptController,
+    /// Set by EI: interrupts stay blocked until the next instruction has
+    /// run, so `EI; RET` returns before another interrupt is taken.
+    ei_delay: bool,
+    event_sink: Option<Box<dyn EventSink>>,
+    regions: Vec<(Range<u16>, String, RegionAccess)>,
+    access: Option<AccessMap>,
+    inspection: Option<Publisher>,
+    port_addressing: PortAddressing,
+    metrics: MetricsHandle,
+    frames: FrameClock,
+    vector_write_check: bool,
+    operand_check: bool,
+    rom_write_policy: RomWritePolicy,
+    /// Whether the guest has executed EI since the processor was created.
+    /// Vector writes before then count as setup.
+    ei_seen: bool,
+    tracer: Option<Box<dyn Tracer>>,
+    trace_filter: TraceFilter,
+    fast_forward_loops: bool,
+    /// Instructions the current run may still execute, so a skipped loop
+    /// does not carry it past its limit.
+    skip_budget: Option<u64>,
+    recent_pcs: RecentPcs,
+    fault_report: Option<FaultReport>,
+    flag_audit: Option<FlagAudit>,
+    aux_carry_audit: Option<AuxCarryAudit>,
+    interrupt_timing: Option<LatencyTracker>,
+    reentry_check: bool,
+    max_interrupt_nesting: Option<usize>,
+    nesting: HandlerNesting,
+    allocators: Vec<AllocatorTracker>,
+    indirect_flow: Option<IndirectFlowReport>,
+    extension: ExtensionSlot,
+    /// Cycle count and RST opcode of the interrupt `schedule_interrupt_in`
+    /// set up.
+    schedule: Option<(u64, u8)>,
+    /// Reads from ports with no device, by port, while the check is on.
+    unattached: Option<BTreeMap<u8, UnattachedRead>>,
+    /// The last value OUT wrote to each port with no device.
+    unattached_writes: BTreeMap<u8, u8>,
+    /// What IN from a port with no device reads.
+    unattached_read_value: u8,
+    quirks: QuirkProfile,
+    /// Conditional branches by address, while branch stats are on.
+    branch_stats: Option<HashMap<u16, BranchSite>>,
+    /// Where `stack_view` stops, if declared.
+    stack_top: Option<u16>,
+    /// How to take back the instruction the last `step` ran.
+    #[cfg(feature = "debugger")]
+    step_undo: Option<step_undo::StepUndo>,
 }
 
-impl ConditionBits {
-    pub fn set_flags(&mut self, byte: u8) {
-        self.carry = (byte & 0b1) != 0;
-        self.parity = (byte & 0b100) != 0;
-        self.aux_carry = (byte & 0b10000) != 0;
-        self.zero = (byte & 0b1000000) != 0;
-        self.sign = (byte & 0b10000000) != 0;
-    }
-
-    pub fn convert_to_flags(&mut self) -> u8 {
-        let mut ret: u8 = 0b0;
-        if self.carry { ret = ret | 0b1};
-        if self.parity { ret = ret | 0b100 };
-        if self.aux_carry { ret = ret | 0b10000 };
-        if self.zero { ret = ret | 0b1000000 };
-        if self.sign { ret = ret | 0b10000000};
-        return ret;
-    }
+pub(crate) fn make_processor() -> Processor {
+    return Processor {
+        deadline_check_interval: DEFAULT_DEADLINE_CHECK_INTERVAL,
+        ..Default::default()
+    };
 }
 
 impl Processor {
@@ -58,544 +240,1816 @@ impl Processor {
 
         self.initialize_memory(path);
 
-        while !self.halt {
-            self.run_one_command();
-        }
+        self.run();
 
-        return format!("Final Processor State:\n{:#?}", self);
+        return self.state_report();
     }
 
     fn initialize_memory(&mut self, path: &str) {
-        self.memory.extend_from_slice(&fs::read(path)
+        self.load_program(&fs::read(path)
         .expect("Should have been able to read the file"));
-        self.memory.resize_with(0xffff, || {0});
     }
 
-    fn parity(&mut self, mut num: u16, size: usize) -> bool {
-        let mut hamming_weight: u16 = 0;
-        for _i in 0..size {
-            hamming_weight += num & 0x1;
-            num = num >> 1;
-        }
-        return (hamming_weight % 2) == 0;
+    /// Reads memory without side effects.
+    pub fn peek(&self, addr: u16) -> u8 {
+        return self.memory.peek(addr);
     }
 
-    fn set_add_flags(&mut self, answer: u16) {
-        self.conditions.sign = (answer & 0x80) != 0;
-        self.conditions.zero = (answer & 0xff) == 0;
-        self.conditions.parity = self.parity(answer & 0xff, 8);
-        self.conditions.carry = answer > 0xff;
+    /// Writes memory from the host the way a guest store would, so the write
+    /// journal, region events and watchpoints all see it, but past the ROM
+    /// write policy. Returns true if the write hit a watchpoint, which does
+    /// not stop the next run.
+    pub fn poke(&mut self, addr: u16, value: u8) -> bool {
+        self.store_byte(addr, value);
+        return self.watch_hit.take().is_some();
     }
 
-    fn subtract_acc(&mut self, minuend: u16, subtrahend: u16) -> u8 {
-        let min = minuend + 0x100;
-        let difference: u16 = min - subtrahend;
-        let ret_diff = (difference & 0xff) as u8;
-        self.conditions.carry = subtrahend > minuend;
-        self.conditions.sign = (ret_diff & 0x80) != 0;
-        self.conditions.zero = ret_diff == 0;
-        self.conditions.parity = self.parity(ret_diff as u16, 8);
-        return ret_diff
+    /// `range` of memory borrowed without copying, like `peek` over each
+    /// address. Fails for memory the bus does not keep as plain RAM, such
+    /// as a `SharedMemory` block.
+    pub fn memory_view(&self, range: Range<u16>) -> Result<&[u8], MemoryViewError> {
+        return self.memory.slice(range.clone()).ok_or(MemoryViewError { range });
     }
 
-    fn logical_op(&mut self, left: u8, right: u8, f: fn(u8, u8) -> u8  ){
-        self.a = f(left, right);
-        self.conditions.carry = false;
-        self.conditions.sign = (self.a & 0x80) != 0;
-        self.conditions.zero = self.a == 0;
-        self.conditions.parity = self.parity(self.a as u16, 8);
+    /// The instruction at `pc` as diagnostics show it, e.g. `sta 2000h` or
+    /// `call 3456h alias` for an undocumented opcode.
+    pub fn describe_instruction(&self, pc: u16) -> String {
+        return disasm::describe(&self.instruction_bytes(pc));
     }
 
-    fn get_mem_addr(&mut self) -> u16 {
-        let high_bits: u16 = (self.h as u16) << 8;
-        let low_bits: u16 = self.l as u16;
-        return high_bits | low_bits;
+    fn instruction_bytes(&self, pc: u16) -> [u8; 3] {
+        return [0, 1, 2].map(|offset| self.memory.peek(pc.wrapping_add(offset)));
     }
 
-    fn split_bytes(&mut self, val: u16) -> (u8, u8) {
-        let high_byte: u8 = (val >> 8) as u8;
-        let low_byte: u8 = (val & 0xff) as u8;
+    pub fn pc(&self) -> u16 {
+        return self.registers.pc;
+    }
 
-        return (high_byte, low_byte);
+    pub fn set_pc(&mut self, pc: u16) {
+        self.registers.pc = pc;
     }
 
-    fn merge_bytes(&mut self, high_byte: u8, low_byte: u8) -> u16 {
-        return ((high_byte as u16) << 8)  | low_byte as u16;
+    pub fn is_halted(&self) -> bool {
+        return self.halt;
     }
 
-    fn push_to_stack(&mut self, byte: u8) {
-        self.sp -= 1;
-        let sp: usize = self.sp as usize;
-        self.memory[sp] = byte;
+    pub fn interrupts_enabled(&self) -> bool {
+        return self.interrupt_enabled;
     }
 
-    fn push_addr_to_stack(&mut self, addr: u16) {
-        let bytes = self.split_bytes(addr);
-        self.push_to_stack(bytes.1);
-        self.push_to_stack(bytes.0);
+    /// One-line summary of the registers and flags, e.g. for a debugger prompt.
+    pub fn register_summary(&self) -> String {
+        return self.registers.to_string();
     }
 
-    fn pop_from_stack(&mut self) -> u8 {
-        let sp = self.sp;
-        self.sp += 1;
-        return self.memory[sp as usize];
+    /// A copy of the registers and flags, without memory.
+    pub fn registers(&self) -> Registers {
+        return self.registers;
     }
 
-    fn pop_addr_from_stack(&mut self) -> u16 {
-        let high_byte = self.pop_from_stack();
-        let low_byte = self.pop_from_stack();
-        return self.merge_bytes(high_byte, low_byte);
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.registers = registers;
     }
 
-    fn get_register(&mut self, reg: u8) -> &mut u8 {
-        let mem_addr = self.get_mem_addr();
-        
-        return match reg {
-            0 => &mut self.b,
-            1 => &mut self.c,
-            2 => &mut self.d,
-            3 => &mut self.e,
-            4 => &mut self.h,
-            5 => &mut self.l,
-            6 => &mut self.memory[mem_addr as usize],
-            _ => &mut self.a,
-        }
-    }
-
-    fn get_register_pair_value(&mut self, reg_pair: u8) -> u16{
-        let mut high_byte: u16 = 0;
-        let mut low_byte: u16 = 0;
-        let mut sp_addr: u16 = 0;
-        
-        match reg_pair {
-            0 => (|| {
-                    high_byte = self.b as u16;
-                    low_byte = self.c as u16;
-                })(),
-            1 => (|| {
-                    high_byte = self.d as u16;
-                    low_byte = self.e as u16;
-                })(),
-            2 => (|| {
-                    high_byte = self.h as u16;
-                    low_byte = self.l as u16;
-                })(),
-            3 => (|| {
-                    sp_addr = self.sp;
-                })(),
-            _ => (),
-        }
-
-        return if reg_pair == 3 {
-            sp_addr
-        } else {
-            (high_byte << 8) | low_byte
+    /// Copies the registers, flags, counters, and memory.
+    pub fn snapshot(&self) -> Snapshot {
+        return Snapshot {
+            format_version: SNAPSHOT_VERSION,
+            registers: self.registers,
+            halted: self.halt,
+            interrupt_enabled: self.interrupt_enabled,
+            interrupts: self.interrupts.clone(),
+            schedule: self.schedule,
+            ei_delay: self.ei_delay,
+            ei_seen: self.ei_seen,
+            instructions: self.instructions,
+            cycles: self.cycles,
+            memory: (0..MEMORY_SIZE).map(|addr| self.memory.peek(addr as u16)).collect(),
+            devices: self.devices.iter().map(|device| device.save_state()).collect(),
+            strict_initialized: self.strict.as_ref().map(|strict| strict.initialized().to_vec()),
         };
     }
 
-
-    fn set_register(&mut self, reg: u8, value: u8) {
-        *self.get_register(reg) = value;
+    /// Puts the machine back in the state `snapshot` recorded. Devices are
+    /// matched by attach order, so attach the same devices first; a
+    /// different number of them is refused and nothing is restored. Breakpoints,
+    /// watchpoints, and other host-side settings are left alone; the shadow
+    /// call stack starts empty. A snapshot taken outside strict mode counts
+    /// all of its memory as initialized.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), RestoreError> {
+        if self.devices.len() != snapshot.devices.len() {
+            return Err(RestoreError::DeviceCount { attached: self.devices.len(), saved: snapshot.devices.len() });
+        }
+        self.registers = snapshot.registers;
+        self.halt = snapshot.halted;
+        self.interrupt_enabled = snapshot.interrupt_enabled;
+        self.interrupts = snapshot.interrupts.clone();
+        self.schedule = snapshot.schedule;
+        self.ei_delay = snapshot.ei_delay;
+        self.ei_seen = snapshot.ei_seen;
+        self.instructions = snapshot.instructions;
+        self.cycles = snapshot.cycles;
+        self.metrics.set_progress(self.instructions, self.cycles);
+        self.memory.load(0, &snapshot.memory);
+        for (device, state) in self.devices.iter_mut().zip(&snapshot.devices) {
+            device.restore_state(state);
+        }
+        if let Some(strict) = &mut self.strict {
+            match &snapshot.strict_initialized {
+                Some(initialized) => strict.restore_initialized(initialized),
+                None => strict.mark_initialized(0, MEMORY_SIZE),
+            }
+        }
+        self.call_stack = ShadowStack::default();
+        #[cfg(feature = "debugger")]
+        {
+            self.step_undo = None;
+        }
+        if let Some(timing) = &mut self.interrupt_timing {
+            timing.abandon();
+        }
+        self.nesting.clear();
+        self.fault = None;
+        self.watch_hit = None;
+        self.until_hit = None;
+        return Ok(());
+    }
+
+    /// Starts or stops recording guest memory writes. Stopping discards
+    /// anything recorded.
+    pub fn set_write_journal(&mut self, enabled: bool) {
+        if enabled != self.journal.is_some() {
+            self.journal = if enabled { Some(WriteJournal::new()) } else { None };
+        }
     }
 
-    fn get_byte(&mut self) -> u8 {
-        self.pc += 1;
-        return self.memory[(self.pc - 1) as usize];
-    }
-
-    fn set_register_pair(&mut self, reg_pair: u8, val: u16) {
-
-        let high_byte: u8 = (val >> 8) as u8;
-        let low_byte: u8 = (val & 0xff) as u8;
-
-        match reg_pair {
-            0 => (|| {
-                    self.b = high_byte;
-                    self.c = low_byte;
-                })(),
-            1 => (|| {
-                    self.d = high_byte;
-                    self.e = low_byte
-                })(),
-            2 => (|| {
-                    self.h = high_byte;
-                    self.l = low_byte;
-                })(),
-            3 => (|| {
-                    let mut sp_addr : u16 = high_byte as u16;
-                    sp_addr = sp_addr << 8;
-                    sp_addr = sp_addr | low_byte as u16;
-                    self.sp = sp_addr
-                })(),
-            _ => (),
+    /// Starts or stops recording which bytes the guest executes, reads, and
+    /// writes. Stopping discards anything recorded.
+    pub fn set_access_tracking(&mut self, enabled: bool) {
+        if enabled != self.access.is_some() {
+            self.access = if enabled { Some(AccessMap::new()) } else { None };
         }
     }
 
-    fn unimplemented_instruction(&mut self) {
-        println!("Error: Unimplemented Instruction: {}\n", self.memory[self.pc as usize]);
+    /// What has been recorded since tracking was turned on.
+    pub fn access_map(&self) -> Option<&AccessMap> {
+        return self.access.as_ref();
     }
 
-    fn nop(&mut self) {
-        println!("NOP");
+    /// A handle other threads can use to look at the registers and chosen
+    /// memory ranges while this processor runs. Runs publish a fresh view
+    /// every `DEFAULT_INSPECTION_INTERVAL` instructions unless
+    /// `set_inspection_interval` says otherwise, and when they stop.
+    pub fn inspection_handle(&mut self) -> InspectionHandle {
+        let publisher = self.inspection.get_or_insert_with(|| Publisher::new(DEFAULT_INSPECTION_INTERVAL));
+        publisher.publish(self.instructions, self.registers, &*self.memory);
+        return publisher.handle();
     }
 
-    fn lxi(&mut self, opcode: u8) {
-        let reg_pair = opcode >> 4;
+    pub fn set_inspection_interval(&mut self, interval: u64) {
+        if let Some(publisher) = &mut self.inspection {
+            publisher.interval = interval.max(1);
+        }
+    }
 
-        let val: u16 = self.get_two_bytes();
-        self.set_register_pair(
-            reg_pair, 
-            val 
-        );
+    fn publish_inspection(&mut self, force: bool) {
+        if let Some(publisher) = &mut self.inspection {
+            if force || self.instructions.is_multiple_of(publisher.interval) {
+                publisher.publish(self.instructions, self.registers, &*self.memory);
+            }
+        }
     }
 
-    fn get_two_bytes(&mut self) -> u16 {
-        let low_byte = self.get_byte();
-        let high_byte = self.get_byte();
-        return self.merge_bytes(high_byte, low_byte);
+    /// The counters as of the last instruction boundary.
+    pub fn metrics_snapshot(&self) -> Metrics {
+        return self.metrics.snapshot();
     }
 
-    fn lhld(&mut self) {
-        let addr: usize = self.get_two_bytes() as usize;
-        self.l = self.memory[addr];
-        self.h = self.memory[addr + 1];
+    /// A handle for polling `metrics_snapshot` from another thread while
+    /// this processor runs.
+    pub fn metrics_handle(&self) -> MetricsHandle {
+        return self.metrics.clone();
     }
 
-    fn shld(&mut self) {
+    /// Reports guest writes to `RST_VECTORS` after the first EI as
+    /// `Event::VectorWrite`, and in strict mode also stops the run with
+    /// `EmuError::VectorWrite`. Writes before the first EI are taken to be
+    /// the program installing its handlers.
+    pub fn set_vector_write_check(&mut self, enabled: bool) {
+        self.vector_write_check = enabled;
+    }
 
-        let addr: usize = self.get_two_bytes() as usize;
-        self.memory[addr] = self.l;
-        self.memory[addr + 1] = self.h;
+    /// In strict mode, stops the run with `EmuError::OperandAccess` before
+    /// an LDA, STA, LHLD, SHLD, LDAX or STAX that would write a region
+    /// declared `RegionAccess::ReadOnly` or read one declared
+    /// `RegionAccess::WriteOnly`. LHLD and SHLD stop if either byte would.
+    /// Unlike a bus that ignores writes to ROM, this is meant to catch the
+    /// instruction responsible.
+    pub fn set_operand_check(&mut self, enabled: bool) {
+        self.operand_check = enabled;
     }
 
-    fn sta(&mut self) {
+    /// The region the instruction about to run would access against its
+    /// `RegionAccess`, as a fault.
+    fn check_operand(&self) -> Option<EmuError> {
+        if self.strict.is_none() || !self.operand_check {
+            return None;
+        }
+        let pc: u16 = self.registers.pc;
+        let opcode: u8 = self.memory.peek(pc);
+        let operand = || word::read_word(|addr| self.memory.peek(addr), pc.wrapping_add(1));
+        let (addr, len, write): (u16, u16, bool) = match opcode {
+            0x02 | 0x12 => (self.registers.pair(opcode >> 4), 1, true), // STAX
+            0x0a | 0x1a => (self.registers.pair(opcode >> 4), 1, false), // LDAX
+            0x22 => (operand(), 2, true), // SHLD
+            0x2a => (operand(), 2, false), // LHLD
+            0x32 => (operand(), 1, true), // STA
+            0x3a => (operand(), 1, false), // LDA
+            _ => return None,
+        };
+        for addr in (0..len).map(|offset| addr.wrapping_add(offset)) {
+            let denied = self.regions.iter().find(|(range, _, access)| range.contains(&addr) && !access.allows(write));
+            if let Some((_, region, _)) = denied {
+                return Some(EmuError::OperandAccess { pc, addr, region: region.clone(), write });
+            }
+        }
+        return None;
+    }
 
-        let addr: usize = self.get_two_bytes() as usize;
-        self.memory[addr] = self.a;
+    fn check_vector_write(&mut self, addr: u16, value: u8) {
+        if !RST_VECTORS.contains(&addr) || !self.ei_seen {
+            return;
+        }
+        let vector: u8 = (addr / 8) as u8;
+        self.emit(Event::VectorWrite { addr, vector, value });
+        if self.strict.is_some() && self.fault.is_none() {
+            self.fault = Some(EmuError::VectorWrite { addr, vector, pc: self.instruction_pc });
+        }
     }
 
-    fn lda(&mut self) {
-        let addr: usize = self.get_two_bytes() as usize;
-        self.a = self.memory[addr];
+    /// The writes recorded so far, if journaling is on.
+    pub fn write_journal(&self) -> Option<&WriteJournal> {
+        return self.journal.as_ref();
     }
 
-    fn stax(&mut self, opcode: u8) {
-        let reg_pair = opcode >> 4;
-        let addr: usize = self.get_register_pair_value(reg_pair) as usize;
-        self.memory[addr] = self.a;
+    /// Every journaled write to `addr`, oldest first. Empty if journaling
+    /// is off; writes after the journal filled up are not included.
+    pub fn write_history(&self, addr: u16) -> Vec<WriteRecord> {
+        return self.journal.as_ref().map_or_else(Vec::new, |journal| journal.writes_to(addr));
     }
 
-    fn ldax(&mut self, opcode: u8){
-        let reg_pair = opcode >> 4;
-        let addr: usize = self.get_register_pair_value(reg_pair) as usize;
-        self.a = self.memory[addr];
+    /// Returns the writes recorded since the last call and starts a fresh
+    /// journal. Empty if journaling is off.
+    pub fn take_write_journal(&mut self) -> WriteJournal {
+        return match &mut self.journal {
+            Some(journal) => journal.take(),
+            None => WriteJournal::new(),
+        };
     }
 
-    fn mvi(&mut self, opcode: u8) {
-        let reg = opcode >> 3;
-        let byte = self.get_byte();
-        self.set_register(reg, byte);
+    /// Formats the processor state the way `run_program` reports it.
+    pub fn state_report(&self) -> String {
+        return format!("Final Processor State:\n{:#?}", self);
     }
 
-    fn mov(&mut self, opcode: u8) {
-        let reg_1: u8 = (opcode << 2) >> 5;
-        let reg_2: u8 = opcode & 0b00000111;
-        let val = *self.get_register(reg_2);
-        self.set_register(reg_1, val);
+    /// Copies `program` into memory starting at address 0.
+    pub fn load_program(&mut self, program: &[u8]) {
+        self.load_at(0, program);
     }
 
-    fn halt(&mut self) {
-        println!("halt");
-        self.halt = true;
+    /// Copies `program` into memory starting at `origin`. The PC is left
+    /// alone; use `set_pc` to start somewhere other than 0.
+    pub fn load_at(&mut self, origin: u16, program: &[u8]) {
+        self.memory.load(origin, program);
+        self.mark_initialized(origin, program.len());
     }
 
-    fn inr(&mut self, opcode: u8) {
-        let reg_code: u8 = opcode >> 3;
+    /// Encodes `instructions` and loads them from `origin`, returning the
+    /// address after the last byte, which wraps to 0 for a program that
+    /// ends at the top of memory. Nothing is loaded if any instruction has
+    /// no encoding or the program does not fit.
+    pub fn load_instructions(&mut self, instructions: &[Instruction], origin: u16) -> Result<u16, EmuError> {
+        let mut bytes: Vec<u8> = Vec::new();
+        for (index, instruction) in instructions.iter().enumerate() {
+            bytes.extend(instruction.encode().map_err(|reason| EmuError::InvalidOperand { index, reason })?);
+        }
+        if origin as usize + bytes.len() > MEMORY_SIZE {
+            return Err(EmuError::ProgramTooLarge { origin, len: bytes.len() });
+        }
+        self.load_at(origin, &bytes);
+        return Ok(origin.wrapping_add(bytes.len() as u16));
+    }
 
-        let register = self.get_register(reg_code);
-        let cur_val: u16 = (*register as u16) + 1;
-        *register = (cur_val & 0x00ff) as u8;
-        self.conditions.sign = (cur_val >> 7) != 0;
-        self.conditions.zero = cur_val == 0;
-        self.conditions.parity = self.parity(cur_val, 8);
+    /// Tells strict mode that `len` bytes from `start` hold deliberate
+    /// contents, for memory set up some way other than `load_at`.
+    pub fn mark_initialized(&mut self, start: u16, len: usize) {
+        if let Some(strict) = &mut self.strict {
+            strict.mark_initialized(start, len);
+        }
     }
 
-    fn inx(&mut self, opcode: u8) {
-        let reg_pair = opcode >> 4;
-        let pair_val = self.get_register_pair_value(reg_pair) + 1;
-        self.set_register_pair(reg_pair, pair_val);
-        self.conditions.sign = (pair_val >> 15) != 0;
-        self.conditions.zero = pair_val == 0;
-        self.conditions.parity = self.parity(pair_val, 16);
+    /// Resets the CPU as its RESET input does: the PC goes to 0, interrupts
+    /// are disabled and a HLT is left. Requests waiting on the interrupt
+    /// controller and any scheduled interrupt are dropped, as the devices
+    /// raising them are reset too. Registers, memory and the instruction
+    /// and cycle counters are left alone.
+    pub fn reset(&mut self) {
+        self.registers.pc = 0;
+        self.halt = false;
+        self.interrupt_enabled = false;
+        self.ei_delay = false;
+        self.interrupts = InterruptController::new();
+        self.schedule = None;
+        self.fault = None;
+        self.call_stack = ShadowStack::default();
+        #[cfg(feature = "debugger")]
+        {
+            self.step_undo = None;
+        }
+        self.nesting = HandlerNesting::default();
+        if let Some(timing) = &mut self.interrupt_timing {
+            timing.abandon();
+        }
     }
 
-    fn dcr(&mut self, opcode: u8) {
-        let reg_code: u8 = opcode >> 3;
+    /// Resets every attached device; see `IoDevice::reset`.
+    pub fn reset_devices(&mut self) {
+        for device in &mut self.devices {
+            device.reset();
+        }
+    }
 
-        let register = self.get_register(reg_code);
-        let cur_val: u16 = if *register > 0 {
-            (*register as u16) - 1
+    /// Zeroes all of memory, as after power-up, and forgets which vector
+    /// bytes strict mode saw written.
+    pub fn clear_memory(&mut self) {
+        self.memory.load(0, &vec![0; MEMORY_SIZE]);
+        if let Some(strict) = &mut self.strict {
+            let len: usize = strict.initialized().len();
+            strict.restore_initialized(&vec![false; len]);
         }
-        else {
-            0xff as u16
-        };
-        *register = (cur_val & 0x00ff) as u8;
-        self.conditions.sign = (cur_val >> 7) != 0;
-        self.conditions.zero = cur_val == 0;
-        self.conditions.parity = self.parity(cur_val, 8);
     }
 
-    fn dcx(&mut self, opcode: u8) {
-        let reg_pair = (opcode >> 4) & 0b1100;
-        let mut pair_val = self.get_register_pair_value(reg_pair);
-        pair_val -= 1;
-        self.set_register_pair(reg_pair, pair_val);
-        self.conditions.sign = (pair_val >> 15) != 0;
-        self.conditions.zero = pair_val == 0;
-        self.conditions.parity = self.parity(pair_val, 16);
+    /// The CALLs and RSTs that have not returned yet, innermost last.
+    pub fn call_stack(&self) -> &[CallFrame] {
+        return self.call_stack.frames();
     }
 
-    fn add(&mut self, opcode: u8) {
-        let reg_num: u8 = opcode & 0b111;
-        let answer: u16 = (self.a as u16) + (*self.get_register(reg_num) as u16);
-        self.set_add_flags(answer);
-        self.a = (answer << 8 >> 8) as u8;
+    /// Executes exactly one instruction. In strict mode a fetch the checks
+    /// reject leaves the instruction unexecuted and the fault pending for
+    /// the run loop.
+    pub fn step(&mut self) {
+        #[cfg(feature = "debugger")]
+        self.begin_step_undo();
+        self.execute_step();
+        if !self.expr_watches.is_empty() {
+            self.check_expr_watches();
+        }
+        if !self.allocators.is_empty() {
+            self.sample_allocators();
+        }
+        if let Some(timing) = &mut self.interrupt_timing {
+            timing.returned(self.cycles, self.call_stack.frames().len());
+        }
+        if self.nesting_tracked() {
+            self.nesting.returned(self.call_stack.frames().len());
+        }
+        self.poll_devices();
+        self.fire_schedule();
+        self.metrics.set_progress(self.instructions, self.cycles);
+        while self.frames.frame_ended(self.cycles) {
+            self.run_frame_callback();
+        }
+        #[cfg(feature = "debugger")]
+        self.end_step_undo();
     }
 
-    fn adi(&mut self) {
-        let immediate = self.get_byte();
-        let answer: u16 = (self.a as u16) + (immediate as u16);
-        self.set_add_flags(answer);
-        self.a = (answer << 8 >> 8) as u8;
+    /// Calls `callback` at the end of every `cycles_per_frame` cycles from
+    /// now, with the frame number and the processor as it is between
+    /// instructions. None removes it.
+    pub fn set_frame_callback(&mut self, cycles_per_frame: u64, callback: Option<FrameCallback>) {
+        let cycles_per_frame: u64 = cycles_per_frame.max(1);
+        self.frames = FrameClock { cycles_per_frame, next_frame_at: self.cycles + cycles_per_frame, frames: 0, callback };
+    }
 
+    fn run_frame_callback(&mut self) {
+        let Some(mut callback) = self.frames.callback.take() else {
+            return;
+        };
+        callback(self.frames.frames, self);
+        self.frames.callback = Some(callback);
+    }
+
+    fn execute_step(&mut self) {
+        self.watch_hit = None;
+        self.until_hit = None;
+        self.fault = None;
+        self.instruction_pc = self.registers.pc;
+        if self.interrupt_pending() {
+            if let Some(fault) = self.check_nesting() {
+                self.fault = Some(fault);
+                return;
+            }
+            self.deliver_interrupt();
+            self.instructions += 1;
+            return;
+        }
+        // EI only blocks the instruction boundary right after it.
+        self.ei_delay = false;
+        if self.fast_forward_loops && self.skip_countdown() {
+            return;
+        }
+        if !self.callbacks.is_empty() {
+            self.run_callbacks();
+        }
+        if self.run_hook() == VectorAction::Ret {
+            self.cycles += CYCLES[0xc9] as u64;
+            self.ret();
+            self.instructions += 1;
+            return;
+        }
+        if let Some(fault) = self.check_fetch().or_else(|| self.check_operand()) {
+            self.fault = Some(fault);
+            return;
+        }
+        let traced: Option<TraceRecord> = if self.tracer.is_some() { self.trace_record() } else { None };
+        if self.flag_audit.is_some() {
+            self.audit_flags();
+        }
+        if self.aux_carry_audit.is_some() {
+            self.audit_aux_carry();
+        }
+        self.run_one_command();
+        if let (Some(record), Some(tracer)) = (traced, &mut self.tracer) {
+            tracer.trace(&record, &self.registers);
+        }
+        // The report shows a faulting instruction on its own.
+        if self.fault.is_none() {
+            self.recent_pcs.push(self.instruction_pc);
+        }
+        self.instructions += 1;
+    }
+
+    /// Fast-forwards a countdown loop starting at the PC to the start of its
+    /// last trip, which then runs as usual, or as far as the run's
+    /// instruction limit and the next frame end allow. Does nothing if there
+    /// is no such loop or skipping it could be seen: a breakpoint, hook or
+    /// `run_to` target inside it, an expression watch or allocator tracker
+    /// sampling every instruction, or an interrupt waiting to be taken.
+    fn skip_countdown(&mut self) -> bool {
+        if !self.expr_watches.is_empty() || !self.allocators.is_empty() {
+            return false;
+        }
+        let pc: u16 = self.registers.pc;
+        let Some(found) = fast_forward::match_countdown(|addr| self.memory.peek(addr), pc) else {
+            return false;
+        };
+        let iterations: u64 = CountdownLoop::iterations(self.get_register(found.counter));
+        let affordable: u64 = self.skip_budget.map_or(u64::MAX, |budget| budget / found.instructions);
+        let before_frame: u64 = match self.frames.callback {
+            Some(_) => self.frames.next_frame_at.saturating_sub(self.cycles + 1) / found.cycles,
+            None => u64::MAX,
+        };
+        let skipped: u64 = (iterations - 1).min(affordable).min(before_frame);
+        if skipped == 0 || (self.interrupt_enabled && self.interrupts.is_pending()) {
+            return false;
+        }
+        if self.schedule.is_some_and(|(at, _)| at <= self.cycles + skipped * found.cycles) {
+            return false;
+        }
+        if self.observed(found.start..found.end) {
+            return false;
+        }
+        let counter: u8 = (iterations - skipped) as u8;
+        self.set_register(found.counter, counter);
+        self.set_inr_dcr_flags(counter);
+        if let Some((dest, source)) = found.mov {
+            let value: u8 = self.get_register(source);
+            self.set_register(dest, value);
+        }
+        self.instructions += skipped * found.instructions;
+        self.cycles += skipped * found.cycles;
+        self.emit(Event::LoopSkipped { addr: pc, iterations: skipped });
+        return true;
+    }
+
+    /// Whether a breakpoint, hook or `run_to` target in `range` would see
+    /// a loop there being skipped.
+    fn observed(&self, range: Range<u16>) -> bool {
+        return range.into_iter().any(|addr| self.breakpoints.contains(&addr) || self.hooks.contains(addr) || self.callbacks.contains(addr) || self.run_target == Some(addr));
+    }
+
+    /// Stops with `error`, keeping a `FaultReport` on the instruction at
+    /// `pc` for `fault_report`.
+    fn fault_outcome(&mut self, error: EmuError, pc: u16) -> RunOutcome {
+        self.fault_report = Some(self.report_fault(error.clone(), pc));
+        return RunOutcome::Fault(error);
+    }
+
+    fn report_fault(&self, error: EmuError, pc: u16) -> FaultReport {
+        let peek = |addr: u16| self.memory.peek(addr);
+        let sp: u16 = self.registers.sp;
+        return FaultReport {
+            error,
+            instruction: FaultReport::line_at(peek, pc),
+            history: self.recent_pcs.pcs().into_iter().map(|pc| FaultReport::line_at(peek, pc)).collect(),
+            registers: self.registers,
+            stack: (0..FAULT_STACK_BYTES as u16).map(|offset| peek(sp.wrapping_add(offset))).collect(),
+            backtrace: self.call_stack.frames().to_vec(),
+        };
     }
 
-    fn adc(&mut self, opcode: u8) {
-        let reg_num: u8 = opcode & 0b111;
-        let answer: u16 = (self.a as u16) + (*self.get_register(reg_num) as u16) + (self.conditions.carry as u16);
+    /// Judges a finished run: a fault fails it as it is, otherwise
+    /// `verdict` decides from the final state. A verdict's message becomes
+    /// `EmuError::PostconditionFailed` with a `FaultReport` on where the run
+    /// stopped, also kept for `fault_report`.
+    pub fn apply_verdict(&mut self, outcome: &RunOutcome,
+                         verdict: impl FnOnce(&Processor, &RunOutcome) -> Result<(), String>) -> Result<(), EmuError> {
+        if let RunOutcome::Fault(error) = outcome {
+            return Err(error.clone());
+        }
+        let message: String = match verdict(self, outcome) {
+            Ok(()) => return Ok(()),
+            Err(message) => message,
+        };
+        let report: FaultReport = self.report_fault(EmuError::PostconditionFailed { message: message.clone(), report: None },
+                                                    self.registers.pc);
+        self.fault_report = Some(report.clone());
+        return Err(EmuError::PostconditionFailed { message, report: Some(Box::new(report)) });
+    }
 
-        self.set_add_flags(answer);
-        self.a = (answer & 0xff) as u8;
+    /// Runs as `run` does, then judges the outcome with `apply_verdict`.
+    pub fn run_with_verdict(&mut self, verdict: impl FnOnce(&Processor, &RunOutcome) -> Result<(), String>) -> Result<(), EmuError> {
+        let outcome: RunOutcome = self.run();
+        return self.apply_verdict(&outcome, verdict);
     }
 
-    fn aci(&mut self) {
-        let imm = self.get_byte();
-        let answer: u16 = (self.a as u16) + (imm as u16) + (self.conditions.carry as u16);
-        self.set_add_flags(answer);
-        self.a = (answer << 8 >> 8) as u8;
+    /// The report on the most recent run that stopped with a fault.
+    pub fn fault_report(&self) -> Option<&FaultReport> {
+        return self.fault_report.as_ref();
+    }
 
+    fn audit_flags(&mut self) {
+        let pc: u16 = self.registers.pc;
+        let opcode: u8 = self.memory.peek(pc);
+        let Some(suspect) = self.flag_audit.as_mut().and_then(|audit| audit.observe(pc, opcode)) else {
+            return;
+        };
+        self.emit(Event::SuspectFlagRead { flag: suspect.flag, producer: suspect.producer, clobbered: suspect.clobbered });
     }
 
-    fn sub(&mut self, opcode: u8) {
-        let reg_num: u8 = opcode & 0b111;
-        let minuend: u16 = self.a as u16;
-        let subtrahend: u16 = *self.get_register(reg_num) as u16;
-        self.a = self.subtract_acc(minuend, subtrahend);
+    fn audit_aux_carry(&mut self) {
+        let pc: u16 = self.registers.pc;
+        let opcode: u8 = self.memory.peek(pc);
+        let quirks: QuirkProfile = self.quirks;
+        if let Some(producer) = self.aux_carry_audit.as_mut().and_then(|audit| audit.observe(pc, opcode, quirks)) {
+            let event: Event = Event::UnmodeledAuxCarry { producer };
+            if let Some(tracer) = &mut self.tracer {
+                tracer.warn(&TimedEvent { instructions: self.instructions, cycles: self.cycles, pc: self.instruction_pc, event: event.clone() });
+            }
+            self.emit(event);
+        }
     }
 
-    fn sbb(&mut self, opcode: u8) {
-        let reg_num: u8 = opcode & 0b111;
-        let minuend: u16 = self.a as u16;
-        let subtrahend = (*self.get_register(reg_num) as u16) + (self.conditions.carry as u16);
-        self.a = self.subtract_acc(minuend, subtrahend);
+    /// The record for the instruction about to run, if the filter lets it
+    /// through.
+    fn trace_record(&mut self) -> Option<TraceRecord> {
+        let pc: u16 = self.registers.pc;
+        if !self.trace_filter.admit(pc, self.memory.peek(pc)) {
+            return None;
+        }
+        return Some(TraceRecord {
+            instructions: self.instructions,
+            cycles: self.cycles,
+            pc,
+            bytes: [0, 1, 2].map(|offset| self.memory.peek(pc.wrapping_add(offset))),
+            registers: self.registers,
+        });
     }
 
-    fn sui(&mut self) {
-        let minuend: u16 = self.a as u16;
-        let subtrahend: u16 = self.get_byte() as u16;
-        self.a =self.subtract_acc(minuend, subtrahend);
+    fn interrupt_pending(&self) -> bool {
+        return self.interrupt_enabled && !self.ei_delay && self.interrupts.is_pending();
     }
 
-    fn sbi(&mut self) {
-        let minuend: u16 = self.a as u16;
-        let subtrahend = (self.get_byte() as u16) + (self.conditions.carry as u16);
-        self.a = self.subtract_acc(minuend, subtrahend);
+    /// Executes the highest-priority pending interrupt's RST in place of
+    /// the next instruction, waking the processor if it was halted. Lower
+    /// ones stay pending.
+    fn deliver_interrupt(&mut self) {
+        let Some((source, opcode)) = self.interrupts.acknowledge() else {
+            return;
+        };
+        let vector: u8 = (opcode >> 3) & 0b111;
+        #[cfg(feature = "debugger")]
+        self.block_undo(UndoError::Interrupt { vector });
+        self.halt = false;
+        self.interrupt_enabled = false;
+        self.cycles += CYCLES[opcode as usize] as u64;
+        self.emit(Event::InterruptDelivered { source, vector });
+        self.metrics.count_interrupt();
+        let ret: u16 = self.registers.pc;
+        self.push_addr_to_stack(ret);
+        self.registers.pc = vector as u16 * 8;
+        self.record_call(ret);
+        if let Some(timing) = &mut self.interrupt_timing {
+            timing.entered(source, self.cycles, self.call_stack.frames().len());
+        }
+        if self.nesting_tracked() {
+            if let Some(first_pc) = self.nesting.running(source).filter(|_| self.reentry_check) {
+                self.emit(Event::InterruptReentered { source, first_pc, second_pc: ret });
+            }
+            self.nesting.entered(source, ret, self.call_stack.frames().len());
+        }
     }
 
-    fn cpi(&mut self){
-        let minuend: u16 = self.a as u16;
-        let subtrahend: u16 = self.get_byte() as u16;
-        self.subtract_acc(minuend, subtrahend);
+    fn nesting_tracked(&self) -> bool {
+        return self.reentry_check || self.max_interrupt_nesting.is_some();
     }
 
-    fn cmp(&mut self, opcode: u8) {
-        let reg_num: u8 = opcode & 0b111;
-        let minuend: u16 = self.a as u16;
-        let subtrahend: u16 = *self.get_register(reg_num) as u16;
-        self.subtract_acc(minuend, subtrahend);
+    /// In strict mode, the fault for taking an interrupt now if it would
+    /// nest handlers deeper than allowed.
+    fn check_nesting(&self) -> Option<EmuError> {
+        let max: usize = self.max_interrupt_nesting.filter(|_| self.strict.is_some())?;
+        let depth: usize = self.nesting.depth() + 1;
+        return (depth > max).then_some(EmuError::InterruptNesting { depth, pc: self.registers.pc });
     }
 
-    fn dad(&mut self, opcode: u8) {
-        let reg_pair: u32 = self.get_register_pair_value(opcode >> 4) as u32;
-        let hl_val: u32 = self.get_register_pair_value(2) as u32;
-        let sum: u32 = reg_pair + hl_val;
-        self.conditions.carry = sum & 0xffff0000 > 0;
-        let sum_cast: u16 = (sum & 0x0000ffff) as u16;
-        self.set_register_pair(2, sum_cast);
+    /// Raises the interrupts devices ask for on their own.
+    fn poll_devices(&mut self) {
+        let ctx = IoContext { cycles: self.cycles, instructions: self.instructions, pc: self.registers.pc };
+        for index in 0..self.devices.len() {
+            if let Some((source, rst_opcode)) = self.devices[index].poll_interrupt(&ctx) {
+                self.raise_interrupt(source, rst_opcode);
+            }
+        }
     }
-    
-    fn ana(&mut self, opcode: u8) {
-        let f = |left: u8, right: u8| -> u8 {
-            return left & right;
+
+    fn run_hook(&mut self) -> VectorAction {
+        if self.hooks.is_empty() {
+            return VectorAction::FallThrough;
+        }
+        let pc: u16 = self.registers.pc;
+        let Some(mut hook) = self.hooks.remove(pc) else {
+            return VectorAction::FallThrough;
         };
-        let right = *self.get_register(opcode & 0b111);
-        self.logical_op(self.a, right, f)
+        let action: VectorAction = hook(self);
+        self.hooks.restore(pc, hook);
+        return action;
     }
 
-    fn xra(&mut self, opcode: u8) {
-        let f = |left: u8, right: u8| -> u8 {
-            return left ^ right;
-        };
-        let right = *self.get_register(opcode & 0b111);
-        self.logical_op(self.a, right, f)
+    fn check_fetch(&self) -> Option<EmuError> {
+        let strict = self.strict.as_ref()?;
+        if strict.is_uninitialized_vector(self.registers.pc) {
+            let caller = self.call_stack.frames().last().map(|frame| frame.call_site);
+            return Some(EmuError::UninitializedVector { addr: self.registers.pc, caller });
+        }
+        return None;
     }
 
-    fn ora(&mut self, opcode: u8) {
-        let f = |left: u8, right: u8| -> u8 {
-            return left | right;
-        };
-        let right = *self.get_register(opcode & 0b111);
-        self.logical_op(self.a, right, f)
+    /// Total number of instructions executed since the processor was created.
+    pub fn instruction_count(&self) -> u64 {
+        return self.instructions;
     }
 
-    fn ani(&mut self) {
-        let f = |left: u8, right: u8| -> u8 {
-            return left & right;
-        };
-        let right = self.get_byte();
-        self.logical_op(self.a, right, f)
+    /// Total number of clock cycles executed since the processor was created.
+    pub fn cycle_count(&self) -> u64 {
+        return self.cycles;
     }
 
-    fn ori(&mut self){
-        let f = |left: u8, right: u8| -> u8 {
-            return left | right;
-        };
-        let right = self.get_byte();
-        self.logical_op(self.a, right, f)
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
     }
 
-    fn xchg(&mut self) {
-        let de = self.get_register_pair_value(1);
-        let hl = self.get_register_pair_value(2);
-        self.set_register_pair(1, hl);
-        self.set_register_pair(2, de);
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
     }
-    fn xthl(&mut self) {
-        let hl: u16 = self.get_register_pair_value(2);
-        let mem: u16 = self.pop_addr_from_stack();
-        self.set_register_pair(2, mem);
-        self.push_addr_to_stack(hl);
+
+    /// Routes IN and OUT on each of `ports` to `device`, replacing whatever
+    /// was attached to those ports before. IN from a port with no device
+    /// reads `unattached_read_value`, noted if `set_unattached_port_check`
+    /// is on, and OUT to one is kept for `unattached_writes`.
+    pub fn attach_device(&mut self, ports: &[u8], device: Box<dyn IoDevice>) {
+        self.devices.push(device);
+        for port in ports {
+            self.port_map.insert(*port, self.devices.len() - 1);
+        }
     }
 
-    fn xri(&mut self){
-        let f = |left: u8, right: u8| -> u8 {
-            return left ^ right;
-        };
-        let right = self.get_byte();
-        self.logical_op(self.a, right, f)
+    /// Runs `hook` whenever execution reaches `addr`, before the instruction
+    /// there is fetched, replacing any hook already at `addr`. A hook that
+    /// returns `VectorAction::Ret` counts as one RET instruction.
+    pub fn install_pc_hook(&mut self, addr: u16, hook: PcHook) {
+        self.hooks.insert(addr, hook);
     }
 
-    fn pchl(&mut self) { // Set program counter to address in HL registers
-        let high_bits: u16 = (self.h as u16)<< 8;
-        let low_bits: u16 = self.l as u16;
-        self.pc = high_bits | low_bits;
+    pub fn remove_pc_hook(&mut self, addr: u16) {
+        self.hooks.remove(addr);
     }
 
-    fn jmp(&mut self) {
-        let pc = self.pc as usize;
-        let low_byte: u16 = self.memory[pc] as u16;
-        let high_byte: u16 = (self.memory[pc + 1] as u16) << 8 ;
-        let addr = high_byte | low_byte;
+    /// Installs `handler` as a PC hook at the target of RST `vector`
+    /// (`vector * 8`), so the host can service the restart in place of
+    /// guest code.
+    pub fn install_vector_hook(&mut self, vector: u8, handler: PcHook) {
+        self.install_pc_hook((vector as u16 & 0b111) * 8, handler);
+    }
 
-        self.pc = addr;
+    /// Requests an interrupt that executes RST `vector` from
+    /// `HOST_INTERRUPT_SOURCE`; see `raise_interrupt`.
+    pub fn request_interrupt(&mut self, vector: u8) {
+        self.raise_interrupt(HOST_INTERRUPT_SOURCE, 0xc7 | ((vector & 0b111) << 3));
     }
 
-    fn rotate_acc(&mut self, opcode: u8) {
-        let high_bit: u8 = self.a >> 7;
-        let low_bit: u8 = self.a & 0xfe;
-        let instr: u8 = opcode >> 3;
-        let acc: u8 = self.a;
-        self.a = match instr {
-            0 => { || -> u8 {
-                self.conditions.carry = high_bit == 1;
-                return (acc << 1) + high_bit
-            }()},
-            1 => {
-                || -> u8 {
-                    self.conditions.carry = low_bit == 1;
-                    return (acc >> 1) + (low_bit << 7)
-                }()
-            },
-            2 => {|| -> u8 {
-                    let res = (acc << 1) + (self.conditions.carry as u8);
-                    self.conditions.carry = high_bit == 1;
-                    return res;
-                }()
-            },
-            _ => {|| -> u8 {
-                    let res = (acc >> 1) + ((self.conditions.carry as u8) << 7);
-                    self.conditions.carry = low_bit == 1;
-                    return res;
-                }()
-                
-            }
+    /// Raises `source`'s request for `rst_opcode` on the interrupt
+    /// controller. The highest-priority request is taken at the first
+    /// instruction boundary with interrupts enabled.
+    pub fn raise_interrupt(&mut self, source: u8, rst_opcode: u8) {
+        self.interrupts.raise(source, rst_opcode);
+        if let Some(timing) = &mut self.interrupt_timing {
+            timing.raised(source, self.cycles);
         }
+        self.emit(Event::InterruptRequested { source, vector: (rst_opcode >> 3) & 0b111 });
     }
 
-    fn match_conds(&mut self, opcode: u8) -> bool {
-        let condition = (opcode >> 3) & 0b00111;
-        return match condition {
-            0 => { !self.conditions.zero }, // JNZ
-            1 => { self.conditions.zero }, // JZ
-            2 => { !self.conditions.carry }, // JNC
-            3 => { self.conditions.carry }, // JC
-            4 => { !self.conditions.parity }, // JPO
-            5 => { self.conditions.parity }, // JPE
-            6 => { !self.conditions.sign }, // JP
-            7 => { self.conditions.sign }, // JM
-            _ => { false }
-        };
+    pub fn clear_interrupt(&mut self, source: u8) {
+        self.interrupts.clear(source);
+        if let Some(timing) = &mut self.interrupt_timing {
+            timing.cleared(source);
+        }
     }
 
-    fn call(&mut self) {
-        let ret: u16 = self.pc + 2;
-        self.push_addr_to_stack(ret);
-        self.jmp();
+    pub fn interrupt_controller(&self) -> &InterruptController {
+        return &self.interrupts;
     }
 
-    fn ret(&mut self) {
-        self.pc = self.pop_addr_from_stack();
+    /// Sends port accesses, interrupts, watchpoint hits, and accesses to
+    /// annotated regions to `sink`, or stops when `None`.
+    pub fn set_event_sink(&mut self, sink: Option<Box<dyn EventSink>>) {
+        self.event_sink = sink;
     }
 
-    fn pop(&mut self, opcode: u8) {
-        let reg_pair: u8 = opcode >> 4; 
-        let low_byte: u8 = self.pop_from_stack();
-        let high_byte: u8 = self.pop_from_stack();
-        if reg_pair < 3 {
-            let val = self.merge_bytes(high_byte, low_byte);
-            self.set_register_pair(reg_pair, val);
-            return;
-        }
+    /// Shows `tracer` each instruction the trace filter lets through, just
+    /// before it executes, or stops tracing when `None`.
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) {
+        self.tracer = tracer;
+    }
 
-        self.a = high_byte;
-        self.conditions.set_flags(low_byte);
+    /// Recognizes countdown delay loops (`DCR r; JNZ` back to the DCR,
+    /// optionally with one register-to-register MOV) and skips all but their
+    /// last trip in one step, leaving registers, flags and counters exactly
+    /// as running them would. Each skip is sent to the event sink as
+    /// `Event::LoopSkipped`, and counts as a single step towards
+    /// `run_with_limit`.
+    pub fn set_fast_forward_loops(&mut self, enabled: bool) {
+        self.fast_forward_loops = enabled;
     }
 
-    fn push(&mut self, opcode: u8) {
-        let reg_pair: u8 = (opcode >> 4) & 0b11; 
-        if reg_pair < 3 {
-            let val = self.get_register_pair_value(reg_pair);
-            self.push_addr_to_stack(val);
-            return;
+    /// Starts or stops the flag audit, which sends `Event::SuspectFlagRead`
+    /// to the event sink when a conditional tests a flag nothing set, or
+    /// one an INR, DCR, DAD or rotate overwrote after a compare or other
+    /// ALU operation and before anything read it. It is a heuristic:
+    /// deliberate uses of those flags get reported too.
+    pub fn set_flag_audit(&mut self, enabled: bool) {
+        if enabled != self.flag_audit.is_some() {
+            self.flag_audit = if enabled { Some(FlagAudit::new()) } else { None };
         }
+    }
 
-        self.push_to_stack(self.a);
-        let flags: u8 = self.conditions.convert_to_flags();
+    /// Reports the first DAA to read an aux carry flag the interpreter did
+    /// not compute as `Event::UnmodeledAuxCarry`. Additions, DAA and POP
+    /// PSW set AC, and so do logical operations outside
+    /// `QuirkProfile::Classic`; after a subtraction, compare, INR or DCR,
+    /// or a logical operation under `Classic`, it still holds an older
+    /// value, and a DAA that reads it can give a different result than
+    /// hardware. Turning it on again allows another report.
+    pub fn set_aux_carry_audit(&mut self, enabled: bool) {
+        self.aux_carry_audit = if enabled { Some(AuxCarryAudit::new()) } else { None };
+    }
+
+    /// Starts or stops timing interrupt service: for each source, the
+    /// cycles from a request being raised to its handler starting, and
+    /// from then to the RET that leaves the handler. Stopping discards
+    /// anything recorded.
+    pub fn set_interrupt_timing(&mut self, enabled: bool) {
+        if enabled != self.interrupt_timing.is_some() {
+            self.interrupt_timing = if enabled { Some(LatencyTracker::new()) } else { None };
+        }
+    }
+
+    /// What has been measured since timing was turned on.
+    pub fn interrupt_timing(&self) -> Option<&LatencyTracker> {
+        return self.interrupt_timing.as_ref();
+    }
+
+    /// Starts or stops recording where PCHL and RETs that do not return to
+    /// their caller go. Stopping discards anything recorded.
+    pub fn set_indirect_flow_tracking(&mut self, enabled: bool) {
+        if enabled != self.indirect_flow.is_some() {
+            self.indirect_flow = if enabled { Some(IndirectFlowReport::new()) } else { None };
+        }
+    }
+
+    /// The indirect transfers taken since tracking was turned on.
+    pub fn indirect_flow_report(&self) -> Option<&IndirectFlowReport> {
+        return self.indirect_flow.as_ref();
+    }
+
+    /// Reports an interrupt taken while the handler for an earlier one from
+    /// the same source has not returned as `Event::InterruptReentered`.
+    /// Handlers are followed through the shadow call stack, so one that
+    /// leaves by adjusting SP and jumping still counts as running.
+    pub fn set_reentry_check(&mut self, enabled: bool) {
+        self.reentry_check = enabled;
+        self.nesting.clear();
+    }
+
+    /// In strict mode, stops the run with `EmuError::InterruptNesting`
+    /// instead of taking an interrupt that would leave more than
+    /// `max_depth` handlers running inside one another. None allows any
+    /// depth, as hardware does.
+    pub fn set_max_interrupt_nesting(&mut self, max_depth: Option<usize>) {
+        self.max_interrupt_nesting = max_depth;
+        self.nesting.clear();
+    }
+
+    /// Which instructions the tracer sees. Everything unless set.
+    pub fn set_trace_filter(&mut self, filter: TraceFilter) {
+        self.trace_filter = filter;
+    }
+
+    /// Names `range` so that reads and writes to it are sent to the event
+    /// sink.
+    pub fn annotate_region(&mut self, name: &str, range: Range<u16>) {
+        self.declare_region(name, range, RegionAccess::ReadWrite);
+    }
+
+    /// Annotates `range` as `annotate_region` does, with what guest
+    /// instructions may do there for `set_operand_check`.
+    pub fn declare_region(&mut self, name: &str, range: Range<u16>, access: RegionAccess) {
+        self.regions.push((range, name.to_string(), access));
+    }
+
+    /// The range of the region annotated as `name`.
+    pub fn region(&self, name: &str) -> Option<Range<u16>> {
+        return self.regions.iter().find(|(_, region, _)| region == name).map(|(range, _, _)| range.clone());
+    }
+
+    /// The name of the region declared `RegionAccess::ReadOnly` that holds
+    /// `addr`, if any.
+    pub fn read_only_region(&self, addr: u16) -> Option<&str> {
+        return self.regions.iter()
+            .find(|(range, _, access)| range.contains(&addr) && !access.allows(true))
+            .map(|(_, region, _)| region.as_str());
+    }
+
+    fn emit(&mut self, event: Event) {
+        if let Some(sink) = &mut self.event_sink {
+            sink.record(TimedEvent { instructions: self.instructions, cycles: self.cycles, pc: self.instruction_pc, event });
+        }
+    }
+
+    /// Emits `event` for the instruction that has just completed, from
+    /// checks that run between instructions.
+    fn emit_after(&mut self, event: Event) {
+        if let Some(sink) = &mut self.event_sink {
+            let instructions: u64 = self.instructions.saturating_sub(1);
+            sink.record(TimedEvent { instructions, cycles: self.cycles, pc: self.instruction_pc, event });
+        }
+    }
+
+    /// The name of the annotated region holding `addr`, if events are on.
+    fn region_of(&self, addr: u16) -> Option<String> {
+        if self.event_sink.is_none() || self.regions.is_empty() {
+            return None;
+        }
+        return self.regions.iter().find(|(range, _, _)| range.contains(&addr)).map(|(_, name, _)| name.clone());
+    }
+
+    /// Follows `pair` as a bump allocator's pointer into `arena`, emitting
+    /// `Event::AllocatorOverflow` the first time it moves past the end and
+    /// `Event::AllocatorCollision` the first time the stack pointer is inside
+    /// what it has allocated. The peak is in `allocator_usage`.
+    pub fn track_allocator(&mut self, pair: RegisterPair, arena: Range<u16>) {
+        self.allocators.push(AllocatorTracker::new(pair, arena));
+    }
+
+    /// How far each tracked allocator got, in the order they were added.
+    pub fn allocator_usage(&self) -> Vec<AllocatorUsage> {
+        return self.allocators.iter().map(|tracker| tracker.usage().clone()).collect();
+    }
+
+    fn sample_allocators(&mut self) {
+        let registers: Registers = self.registers;
+        let events: Vec<Event> = self.allocators.iter_mut().filter_map(|tracker| tracker.observe(&registers)).collect();
+        for event in events {
+            self.emit_after(event);
+        }
+    }
+
+    /// Sets the 16-bit port value devices see for IN and OUT.
+    pub fn set_port_addressing(&mut self, addressing: PortAddressing) {
+        self.port_addressing = addressing;
+    }
+
+    /// Stops the run after any instruction that writes to one of the `len`
+    /// bytes starting at `start`.
+    pub fn add_watchpoint(&mut self, start: u16, len: u16) {
+        self.watchpoints.push((start, len.max(1)));
+    }
+
+    /// Removes every watchpoint starting at `start`.
+    pub fn remove_watchpoint(&mut self, start: u16) {
+        self.watchpoints.retain(|(watch_start, _)| *watch_start != start);
+    }
+
+    /// Sets how many instructions the run loop executes between reads of the
+    /// clock for deadlines, progress, and throttling. Values below 1 are
+    /// treated as 1.
+    pub fn set_deadline_check_interval(&mut self, interval: u64) {
+        self.deadline_check_interval = interval.max(1);
+    }
+
+    /// Replaces the clock used for deadlines, progress, and throttling.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Holds runs to the throttle's speed, or runs flat out when `None`.
+    pub fn set_throttle(&mut self, throttle: Option<Throttle>) {
+        self.throttle = throttle;
+    }
+
+    /// Sends progress updates to `reporter` while running, or stops
+    /// reporting when `None`. The clock is read alongside the deadline
+    /// checks, so a disabled reporter costs nothing.
+    pub fn set_progress_reporter(&mut self, reporter: Option<ProgressReporter>) {
+        self.progress = reporter;
+    }
+
+    /// Runs until the program halts or hits a breakpoint.
+    pub fn run(&mut self) -> RunOutcome {
+        return self.run_loop(None, None);
+    }
+
+    /// Runs until the program halts, hits a breakpoint, or has executed
+    /// `max_instructions` instructions in this call.
+    pub fn run_with_limit(&mut self, max_instructions: u64) -> RunOutcome {
+        return self.run_loop(None, Some(max_instructions));
+    }
+
+    /// Runs until an instruction stores `value` at `addr`, or otherwise
+    /// stops as `run_with_limit` would. Only writes are checked, so a value
+    /// already there when the run starts does not count until it is
+    /// written again.
+    pub fn run_until_mem(&mut self, addr: u16, value: u8, max_instructions: u64) -> RunOutcome {
+        self.until_mem = Some((addr, value));
+        let outcome = self.run_loop(None, Some(max_instructions));
+        self.until_mem = None;
+        return outcome;
+    }
+
+    /// Executes one instruction, or if it is a CALL or RST that is taken,
+    /// runs until that call returns. Recursive calls are told apart by SP,
+    /// and a callee that returns past the return address it was given still
+    /// counts as returning once its RET pops the frame.
+    pub fn step_over(&mut self) -> RunOutcome {
+        if self.halt && !self.interrupt_pending() {
+            return RunOutcome::Halted;
+        }
+        let depth: usize = self.call_stack.frames().len();
+        self.step();
+        if let Some(fault) = self.fault.take() {
+            return self.fault_outcome(fault, self.instruction_pc);
+        }
+        let frame: Option<CallFrame> = self.call_stack.frames().last().copied()
+            .filter(|_| self.call_stack.frames().len() > depth);
+        return match frame {
+            Some(frame) => self.run_until_return(depth, frame),
+            None if self.halt => RunOutcome::Halted,
+            None => RunOutcome::Stepped { pc: self.registers.pc },
+        };
+    }
+
+    /// Runs until the innermost call on the shadow call stack returns. None
+    /// if there is no call to return from.
+    pub fn step_out(&mut self) -> Option<RunOutcome> {
+        let frame: CallFrame = *self.call_stack.frames().last()?;
+        let depth: usize = self.call_stack.frames().len() - 1;
+        return Some(self.run_until_return(depth, frame));
+    }
+
+    fn run_until_return(&mut self, depth: usize, frame: CallFrame) -> RunOutcome {
+        self.return_target = Some(ReturnTarget { depth, return_addr: frame.return_addr, sp: frame.sp.wrapping_add(2) });
+        let outcome = self.run_loop(None, None);
+        self.return_target = None;
+        return outcome;
+    }
+
+    /// Whether the call `return_target` waits for has returned, either by a
+    /// RET popping its frame or by some other jump back to its return address
+    /// with the stack as it was before the call.
+    fn returned(&self) -> bool {
+        let Some(target) = self.return_target else {
+            return false;
+        };
+        return self.call_stack.frames().len() <= target.depth
+            || (self.registers.pc == target.return_addr && self.registers.sp == target.sp);
+    }
+
+    /// Runs until execution reaches `addr`, as if it had a breakpoint that
+    /// is removed afterwards. The instruction at the current PC always runs
+    /// first, so `run_to` the current address goes round a loop once.
+    pub fn run_to(&mut self, addr: u16) -> RunOutcome {
+        self.run_target = Some(addr);
+        let outcome = self.run_loop(None, None);
+        self.run_target = None;
+        return outcome;
+    }
+
+    /// Runs until the program halts, hits a breakpoint, or `deadline` passes.
+    ///
+    /// The clock is only read every `deadline_check_interval` instructions, so
+    /// the run may overshoot the deadline by up to that many instructions.
+    pub fn run_with_deadline(&mut self, deadline: Instant) -> RunOutcome {
+        return self.run_for(deadline.saturating_duration_since(Instant::now()));
+    }
+
+    /// Like `run_with_deadline`, with the deadline `budget` from now on the
+    /// processor's clock.
+    pub fn run_for(&mut self, budget: Duration) -> RunOutcome {
+        let deadline: Duration = self.clock.now() + budget;
+        return self.run_loop(Some(deadline), None);
+    }
+
+    /// The shared run loop. A breakpoint at the current PC is ignored for the
+    /// first instruction so that running again resumes from a breakpoint stop.
+    fn run_loop(&mut self, deadline: Option<Duration>, max_instructions: Option<u64>) -> RunOutcome {
+        let interval = self.deadline_check_interval.max(1);
+        let mut executed: u64 = 0;
+        let mut steps: u64 = 0;
+        let started = self.clock.now();
+        let start_cycles = self.cycles;
+        let progress_at = |cycles: u64, executed: u64, now: Duration| Progress {
+            instructions: executed,
+            cycles: cycles - start_cycles,
+            elapsed: now.saturating_sub(started),
+            max_instructions,
+        };
+        if let Some(throttle) = &mut self.throttle {
+            throttle.reset();
+        }
+
+        let outcome = loop {
+            let waiting: bool = match self.halt_state() {
+                HaltState::Halted => break RunOutcome::Halted,
+                HaltState::Stuck => break RunOutcome::Stuck { pc: self.registers.pc },
+                HaltState::Waiting => true,
+                HaltState::Running => false,
+            };
+            if !waiting && executed > 0 && self.run_target == Some(self.registers.pc) {
+                break RunOutcome::Stepped { pc: self.registers.pc };
+            }
+            if !waiting && executed > 0 && self.breakpoints.contains(&self.registers.pc) {
+                self.metrics.count_breakpoint();
+                break RunOutcome::Breakpoint { pc: self.registers.pc };
+            }
+            if max_instructions.is_some_and(|max| executed >= max) {
+                break RunOutcome::InstructionLimit { instructions: self.instructions };
+            }
+            // Waiting runs no instructions, so with only devices to end the
+            // wait it is bounded by the cycles the limit's worth of the
+            // slowest instruction would take instead.
+            if waiting && self.schedule.is_none()
+                && max_instructions.is_some_and(|max| self.cycles - start_cycles >= max.saturating_mul(MAX_INSTRUCTION_CYCLES)) {
+                break RunOutcome::InstructionLimit { instructions: self.instructions };
+            }
+            if let Some(canary) = &self.canary {
+                if self.instructions.is_multiple_of(canary.check_interval) {
+                    if let Some(fault) = self.check_canary() {
+                        break self.fault_outcome(fault, self.registers.pc);
+                    }
+                }
+            }
+            if steps.is_multiple_of(interval) {
+                if let Some(throttle) = &mut self.throttle {
+                    throttle.pace(self.cycles, &*self.clock);
+                }
+                if deadline.is_some_and(|deadline| self.clock.now() >= deadline) {
+                    break RunOutcome::DeadlineExceeded { instructions: self.instructions };
+                }
+                if let Some(reporter) = &mut self.progress {
+                    reporter.poll(&progress_at(self.cycles, executed, self.clock.now()));
+                }
+            }
+            if waiting {
+                self.wait_halted();
+                steps += 1;
+                continue;
+            }
+            // A skipped loop counts every instruction it stands for.
+            let before: u64 = self.instructions;
+            self.skip_budget = max_instructions.map(|max| max - executed);
+            self.step();
+            self.skip_budget = None;
+            executed += (self.instructions - before).max(1);
+            steps += 1;
+            self.publish_inspection(false);
+            if let Some(fault) = self.fault.take() {
+                break self.fault_outcome(fault, self.instruction_pc);
+            }
+            if self.returned() {
+                break RunOutcome::Stepped { pc: self.registers.pc };
+            }
+            if let (Some(pc), Some((addr, value))) = (self.until_hit.take(), self.until_mem) {
+                break RunOutcome::MemoryValue { addr, value, pc };
+            }
+            if let Some(addr) = self.watch_hit.take() {
+                break RunOutcome::Watchpoint { addr, pc: self.instruction_pc };
+            }
+        };
+
+        self.publish_inspection(true);
+        if let Some(reporter) = &mut self.progress {
+            reporter.finish(&progress_at(self.cycles, executed, self.clock.now()));
+        }
+        return outcome;
+    }
+
+    fn parity(&mut self, mut num: u16, size: usize) -> bool {
+        let mut hamming_weight: u16 = 0;
+        for _i in 0..size {
+            hamming_weight += num & 0x1;
+            num >>= 1;
+        }
+        return hamming_weight.is_multiple_of(2);
+    }
+
+    fn set_add_flags(&mut self, answer: u16) {
+        self.registers.flags.sign = (answer & 0x80) != 0;
+        self.registers.flags.zero = (answer & 0xff) == 0;
+        self.registers.flags.parity = self.parity(answer & 0xff, 8);
+        self.registers.flags.carry = answer > 0xff;
+    }
+
+    /// Sets aux carry if `left + right + carry_in` carries out of bit 3.
+    fn set_aux_carry(&mut self, left: u8, right: u8, carry_in: bool) {
+        self.registers.flags.aux_carry = (left & 0x0f) + (right & 0x0f) + carry_in as u8 > 0x0f;
+    }
+
+    fn subtract_acc(&mut self, minuend: u16, subtrahend: u16) -> u8 {
+        let min = minuend + 0x100;
+        let difference: u16 = min - subtrahend;
+        let ret_diff = (difference & 0xff) as u8;
+        self.registers.flags.carry = subtrahend > minuend;
+        self.registers.flags.sign = (ret_diff & 0x80) != 0;
+        self.registers.flags.zero = ret_diff == 0;
+        self.registers.flags.parity = self.parity(ret_diff as u16, 8);
+        return ret_diff
+    }
+
+    fn logical_op(&mut self, left: u8, right: u8, f: fn(u8, u8) -> u8  ){
+        self.registers.a = f(left, right);
+        self.registers.flags.carry = false;
+        self.registers.flags.sign = (self.registers.a & 0x80) != 0;
+        self.registers.flags.zero = self.registers.a == 0;
+        self.registers.flags.parity = self.parity(self.registers.a as u16, 8);
+    }
+
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        return self.read_access(addr, ACCESS_READ);
+    }
+
+    /// Reads through the bus, recording the access as `access`.
+    fn read_access(&mut self, addr: u16, access: u8) -> u8 {
+        let value: u8 = self.memory.read(addr);
+        if let Some(map) = &mut self.access {
+            map.mark(addr, access);
+        }
+        if let Some(region) = self.region_of(addr) {
+            self.emit(Event::RegionRead { region, addr, value });
+        }
+        return value;
+    }
+
+    /// Scans the stack guard band for bytes that no longer hold the canary.
+    fn check_canary(&self) -> Option<EmuError> {
+        let canary = self.canary.as_ref()?;
+        let corrupted = canary.guard.clone().find(|addr| self.memory.peek(*addr) != canary.value)?;
+        return Some(EmuError::StackCanary { addr: corrupted, pc: None });
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        if self.admit_write(addr, value) {
+            self.store_byte(addr, value);
+        }
+    }
+
+    /// Stores a byte the ROM write policy has let through, or one the host
+    /// pokes.
+    fn store_byte(&mut self, addr: u16, value: u8) {
+        if let Some(journal) = &mut self.journal {
+            let old: u8 = self.memory.peek(addr);
+            journal.record(JournalEntry { addr, old, new: value, pc: self.instruction_pc, instruction: self.instructions });
+        }
+        #[cfg(feature = "debugger")]
+        self.note_undo_write(addr, self.memory.peek(addr));
+        self.memory.write(addr, value);
+        if let Some(map) = &mut self.access {
+            map.mark(addr, ACCESS_WRITTEN);
+        }
+        if let Some(region) = self.region_of(addr) {
+            self.emit(Event::RegionWrite { region, addr, value });
+        }
+        if let Some(strict) = &mut self.strict {
+            strict.mark_initialized(addr, 1);
+        }
+        if let Some(canary) = &self.canary {
+            if canary.guard.contains(&addr) && self.fault.is_none() {
+                self.fault = Some(EmuError::StackCanary { addr, pc: Some(self.instruction_pc) });
+            }
+        }
+        if self.vector_write_check {
+            self.check_vector_write(addr, value);
+        }
+        if self.until_mem == Some((addr, value)) && self.until_hit.is_none() {
+            self.until_hit = Some(self.instruction_pc);
+        }
+        if !self.watchpoints.is_empty() && self.watch_hit.is_none() {
+            let watched = self.watchpoints.iter()
+                .any(|(start, len)| addr.wrapping_sub(*start) < *len);
+            if watched {
+                self.watch_hit = Some(addr);
+                self.emit(Event::Watchpoint { addr });
+            }
+        }
+    }
+
+    fn read_word(&mut self, addr: u16) -> u16 {
+        return word::read_word(|addr| self.read_byte(addr), addr);
+    }
+
+    fn write_word(&mut self, addr: u16, value: u16) {
+        word::write_word(|addr, value| self.write_byte(addr, value), addr, value);
+    }
+
+    fn push_to_stack(&mut self, byte: u8) {
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write_byte(self.registers.sp, byte);
+    }
+
+    /// Pushes `addr` so it sits at SP low byte first, as every other word
+    /// in memory does.
+    fn push_addr_to_stack(&mut self, addr: u16) {
+        self.push_to_stack(hi(addr));
+        self.push_to_stack(lo(addr));
+    }
+
+    fn pop_from_stack(&mut self) -> u8 {
+        let sp = self.registers.sp;
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        return self.read_byte(sp);
+    }
+
+    fn pop_addr_from_stack(&mut self) -> u16 {
+        let low_byte = self.pop_from_stack();
+        let high_byte = self.pop_from_stack();
+        return word::word(high_byte, low_byte);
+    }
+
+    fn get_register(&mut self, reg: u8) -> u8 {
+        return match reg {
+            0 => self.registers.b,
+            1 => self.registers.c,
+            2 => self.registers.d,
+            3 => self.registers.e,
+            4 => self.registers.h,
+            5 => self.registers.l,
+            6 => {
+                let mem_addr = self.registers.hl();
+                self.read_byte(mem_addr)
+            },
+            _ => self.registers.a,
+        }
+    }
+
+
+    fn set_register(&mut self, reg: u8, value: u8) {
+        match reg {
+            0 => self.registers.b = value,
+            1 => self.registers.c = value,
+            2 => self.registers.d = value,
+            3 => self.registers.e = value,
+            4 => self.registers.h = value,
+            5 => self.registers.l = value,
+            6 => {
+                let mem_addr = self.registers.hl();
+                self.write_byte(mem_addr, value);
+            },
+            _ => self.registers.a = value,
+        }
+    }
+
+    /// Fetches the byte at PC and moves PC past it, wrapping from 0xffff to
+    /// 0x0000 as the chip's program counter does.
+    fn get_byte(&mut self) -> u8 {
+        let addr = self.registers.pc;
+        self.registers.pc = addr.wrapping_add(1);
+        return self.read_access(addr, ACCESS_EXECUTED);
+    }
+
+    fn io_context(&self) -> IoContext {
+        return IoContext {
+            cycles: self.cycles,
+            instructions: self.instructions,
+            pc: self.instruction_pc,
+        };
+    }
+
+    fn input(&mut self) {
+        let port = self.get_byte();
+        self.registers.a = self.read_port(port);
+    }
+
+    /// Reads the device on `port` as IN does, for an opcode extension.
+    pub fn read_port(&mut self, port: u8) -> u8 {
+        let ctx = self.io_context();
+        let value: u8 = match self.port_map.get(&port).copied() {
+            Some(index) => {
+                #[cfg(feature = "debugger")]
+                self.block_undo(UndoError::Input { pc: self.instruction_pc, port });
+                self.devices[index].read(self.port_addressing.bus_port(port), &ctx)
+            },
+            None => {
+                self.note_unattached_read(port);
+                self.unattached_read_value
+            },
+        };
+        self.emit(Event::PortRead { port, value });
+        return value;
+    }
+
+    fn output(&mut self) {
+        let port = self.get_byte();
+        self.write_port(port, self.registers.a);
+    }
+
+    /// Writes `value` to the device on `port` as OUT does.
+    fn write_port(&mut self, port: u8, value: u8) {
+        let ctx = self.io_context();
+        let mut request: Option<BusRequest> = None;
+        let mut log: Option<LogRequest> = None;
+        #[cfg(feature = "debugger")]
+        self.block_undo(UndoError::Output { pc: self.instruction_pc, port });
+        match self.port_map.get(&port) {
+            Some(index) => {
+                self.devices[*index].write(self.port_addressing.bus_port(port), value, &ctx);
+                request = self.devices[*index].bus_request();
+                log = self.devices[*index].log_request();
+            },
+            None => {
+                self.unattached_writes.insert(port, value);
+            },
+        }
+        self.emit(Event::PortWrite { port, value });
+        self.metrics.count_output();
+        if let Some(log) = log {
+            let message: String = self.guest_string(log.addr);
+            self.emit(Event::GuestLog { severity: log.severity, message });
+        }
+        if let Some(request) = request {
+            self.run_bus_request(request);
+        }
+    }
+
+    /// The NUL-terminated string at `addr`, read without side effects and
+    /// cut off at `GUEST_STRING_MAX` bytes. Bytes that are not UTF-8 are
+    /// replaced.
+    fn guest_string(&self, addr: u16) -> String {
+        let bytes: Vec<u8> = (0..GUEST_STRING_MAX as u16)
+            .map(|offset| self.memory.peek(addr.wrapping_add(offset)))
+            .take_while(|byte| *byte != 0)
+            .collect();
+        return String::from_utf8_lossy(&bytes).into_owned();
+    }
+
+    /// Carries out a device's block copy with the guest's own reads and
+    /// writes, so watchpoints, the journal and the other diagnostics see it.
+    fn run_bus_request(&mut self, request: BusRequest) {
+        for offset in 0..request.len {
+            let value: u8 = self.read_byte(request.source.wrapping_add(offset));
+            self.write_byte(request.dest.wrapping_add(offset), value);
+        }
+        self.cycles += request.stall_cycles;
+        if let Some((source, rst_opcode)) = request.interrupt {
+            self.raise_interrupt(source, rst_opcode);
+        }
+    }
+
+    fn unimplemented_instruction(&mut self) {
+        let [opcode, operands @ ..] = self.instruction_bytes(self.instruction_pc);
+        if self.run_opcode_extension(opcode) {
+            return;
+        }
+        if self.strict.is_some() {
+            self.fault = Some(EmuError::InvalidOpcode { opcode, operands, pc: self.instruction_pc });
+            return;
+        }
+        self.emit(Event::UnimplementedOpcode { opcode, operands });
+    }
+
+    fn nop(&mut self) {}
+
+    fn lxi(&mut self, opcode: u8) {
+        let reg_pair = opcode >> 4;
+
+        let val: u16 = self.get_two_bytes();
+        self.registers.set_pair_wrapping(
+            reg_pair, 
+            val 
+        );
+    }
+
+    fn get_two_bytes(&mut self) -> u16 {
+        let low_byte = self.get_byte();
+        let high_byte = self.get_byte();
+        return word::word(high_byte, low_byte);
+    }
+
+    fn lhld(&mut self) {
+        let addr: u16 = self.get_two_bytes();
+        let val: u16 = self.read_word(addr);
+        self.registers.set_hl(val);
+    }
+
+    fn shld(&mut self) {
+        let addr: u16 = self.get_two_bytes();
+        self.write_word(addr, self.registers.hl());
+    }
+
+    fn sta(&mut self) {
+
+        let addr: u16 = self.get_two_bytes();
+        self.write_byte(addr, self.registers.a);
+    }
+
+    fn lda(&mut self) {
+        let addr: u16 = self.get_two_bytes();
+        self.registers.a = self.read_byte(addr);
+    }
+
+    fn stax(&mut self, opcode: u8) {
+        let reg_pair = opcode >> 4;
+        let addr: u16 = self.registers.pair(reg_pair);
+        self.write_byte(addr, self.registers.a);
+    }
+
+    fn ldax(&mut self, opcode: u8){
+        let reg_pair = opcode >> 4;
+        let addr: u16 = self.registers.pair(reg_pair);
+        self.registers.a = self.read_byte(addr);
+    }
+
+    fn mvi(&mut self, opcode: u8) {
+        let reg = opcode >> 3;
+        let byte = self.get_byte();
+        self.set_register(reg, byte);
+    }
+
+    fn mov(&mut self, opcode: u8) {
+        let reg_1: u8 = (opcode << 2) >> 5;
+        let reg_2: u8 = opcode & 0b00000111;
+        self.cycles += mov_penalty(opcode) as u64;
+        let val = self.get_register(reg_2);
+        self.set_register(reg_1, val);
+    }
+
+    fn halt(&mut self) {
+        self.halt = true;
+    }
+
+    fn set_inr_dcr_flags(&mut self, result: u8) {
+        self.registers.flags.sign = (result & 0x80) != 0;
+        self.registers.flags.zero = result == 0;
+        self.registers.flags.parity = self.parity(result as u16, 8);
+    }
+
+    fn inr(&mut self, opcode: u8) {
+        let reg_code: u8 = opcode >> 3;
+
+        // Flags come from the result, whether or not INR M gets to store it.
+        let result: u8 = self.get_register(reg_code).wrapping_add(1);
+        self.set_inr_dcr_flags(result);
+        self.set_register(reg_code, result);
+    }
+
+    fn inx(&mut self, opcode: u8) {
+        let reg_pair = opcode >> 4;
+        let pair_val = self.registers.pair(reg_pair).wrapping_add(1);
+        self.registers.set_pair_wrapping(reg_pair, pair_val);
+    }
+
+    fn dcr(&mut self, opcode: u8) {
+        let reg_code: u8 = opcode >> 3;
+
+        let result: u8 = self.get_register(reg_code).wrapping_sub(1);
+        self.set_inr_dcr_flags(result);
+        self.set_register(reg_code, result);
+    }
+
+    fn dcx(&mut self, opcode: u8) {
+        let reg_pair = (opcode >> 4) & 0b11;
+        let pair_val = self.registers.pair(reg_pair).wrapping_sub(1);
+        self.registers.set_pair_wrapping(reg_pair, pair_val);
+    }
+
+    fn add(&mut self, opcode: u8) {
+        let reg_num: u8 = opcode & 0b111;
+        let right: u8 = self.get_register(reg_num);
+        let answer: u16 = (self.registers.a as u16) + (right as u16);
+        self.set_aux_carry(self.registers.a, right, false);
+        self.set_add_flags(answer);
+        self.registers.a = (answer << 8 >> 8) as u8;
+    }
+
+    fn adi(&mut self) {
+        let immediate = self.get_byte();
+        let answer: u16 = (self.registers.a as u16) + (immediate as u16);
+        self.set_aux_carry(self.registers.a, immediate, false);
+        self.set_add_flags(answer);
+        self.registers.a = (answer << 8 >> 8) as u8;
+
+    }
+
+    fn adc(&mut self, opcode: u8) {
+        let reg_num: u8 = opcode & 0b111;
+        let right: u8 = self.get_register(reg_num);
+        let answer: u16 = (self.registers.a as u16) + (right as u16) + (self.registers.flags.carry as u16);
+        self.set_aux_carry(self.registers.a, right, self.registers.flags.carry);
+        self.set_add_flags(answer);
+        self.registers.a = (answer & 0xff) as u8;
+    }
+
+    fn aci(&mut self) {
+        let imm = self.get_byte();
+        let answer: u16 = (self.registers.a as u16) + (imm as u16) + (self.registers.flags.carry as u16);
+        self.set_aux_carry(self.registers.a, imm, self.registers.flags.carry);
+        self.set_add_flags(answer);
+        self.registers.a = (answer << 8 >> 8) as u8;
+
+    }
+
+    fn sub(&mut self, opcode: u8) {
+        let reg_num: u8 = opcode & 0b111;
+        let minuend: u16 = self.registers.a as u16;
+        let subtrahend: u16 = self.get_register(reg_num) as u16;
+        self.registers.a = self.subtract_acc(minuend, subtrahend);
+    }
+
+    fn sbb(&mut self, opcode: u8) {
+        let reg_num: u8 = opcode & 0b111;
+        let minuend: u16 = self.registers.a as u16;
+        let subtrahend = (self.get_register(reg_num) as u16) + (self.registers.flags.carry as u16);
+        self.registers.a = self.subtract_acc(minuend, subtrahend);
+    }
+
+    fn sui(&mut self) {
+        let minuend: u16 = self.registers.a as u16;
+        let subtrahend: u16 = self.get_byte() as u16;
+        self.registers.a =self.subtract_acc(minuend, subtrahend);
+    }
+
+    fn sbi(&mut self) {
+        let minuend: u16 = self.registers.a as u16;
+        let subtrahend = (self.get_byte() as u16) + (self.registers.flags.carry as u16);
+        self.registers.a = self.subtract_acc(minuend, subtrahend);
+    }
+
+    fn cpi(&mut self){
+        let minuend: u16 = self.registers.a as u16;
+        let subtrahend: u16 = self.get_byte() as u16;
+        self.subtract_acc(minuend, subtrahend);
+    }
+
+    fn cmp(&mut self, opcode: u8) {
+        let reg_num: u8 = opcode & 0b111;
+        let minuend: u16 = self.registers.a as u16;
+        let subtrahend: u16 = self.get_register(reg_num) as u16;
+        self.subtract_acc(minuend, subtrahend);
+    }
+
+    fn dad(&mut self, opcode: u8) {
+        let reg_pair: u16 = self.registers.pair(opcode >> 4);
+        let (sum, carry) = self.registers.hl().overflowing_add(reg_pair);
+        self.registers.flags.carry = carry;
+        self.registers.set_pair_wrapping(2, sum);
+    }
+    
+    fn ana(&mut self, opcode: u8) {
+        let f = |left: u8, right: u8| -> u8 {
+            return left & right;
+        };
+        let right = self.get_register(opcode & 0b111);
+        self.set_logical_aux_carry(true, self.registers.a, right);
+        self.logical_op(self.registers.a, right, f)
+    }
+
+    fn xra(&mut self, opcode: u8) {
+        let f = |left: u8, right: u8| -> u8 {
+            return left ^ right;
+        };
+        let right = self.get_register(opcode & 0b111);
+        self.set_logical_aux_carry(false, self.registers.a, right);
+        self.logical_op(self.registers.a, right, f)
+    }
+
+    fn ora(&mut self, opcode: u8) {
+        let f = |left: u8, right: u8| -> u8 {
+            return left | right;
+        };
+        let right = self.get_register(opcode & 0b111);
+        self.set_logical_aux_carry(false, self.registers.a, right);
+        self.logical_op(self.registers.a, right, f)
+    }
+
+    fn ani(&mut self) {
+        let f = |left: u8, right: u8| -> u8 {
+            return left & right;
+        };
+        let right = self.get_byte();
+        self.set_logical_aux_carry(true, self.registers.a, right);
+        self.logical_op(self.registers.a, right, f)
+    }
+
+    fn ori(&mut self){
+        let f = |left: u8, right: u8| -> u8 {
+            return left | right;
+        };
+        let right = self.get_byte();
+        self.set_logical_aux_carry(false, self.registers.a, right);
+        self.logical_op(self.registers.a, right, f)
+    }
+
+    fn xchg(&mut self) {
+        let de = self.registers.de();
+        let hl = self.registers.hl();
+        self.registers.set_de(hl);
+        self.registers.set_hl(de);
+    }
+    fn xthl(&mut self) {
+        let hl: u16 = self.registers.hl();
+        let mem: u16 = self.pop_addr_from_stack();
+        self.registers.set_hl(mem);
+        self.push_addr_to_stack(hl);
+    }
+
+    fn xri(&mut self){
+        let f = |left: u8, right: u8| -> u8 {
+            return left ^ right;
+        };
+        let right = self.get_byte();
+        self.set_logical_aux_carry(false, self.registers.a, right);
+        self.logical_op(self.registers.a, right, f)
+    }
+
+    fn pchl(&mut self) { // Set program counter to address in HL registers
+        self.registers.pc = self.registers.hl();
+        if let Some(flow) = &mut self.indirect_flow {
+            flow.record(self.instruction_pc, self.registers.pc);
+        }
+    }
+
+    fn jmp(&mut self) {
+        self.registers.pc = self.read_word(self.registers.pc);
+    }
+
+    /// Decimal adjust: corrects A after adding two BCD numbers. The low
+    /// nibble is adjusted first, and its carry into the high nibble can
+    /// trigger the high adjustment. Carry is only ever set, never cleared.
+    fn daa(&mut self) {
+        let mut result: u16 = self.registers.a as u16;
+        let mut aux_carry: bool = false;
+        if (result & 0x0f) > 9 || self.registers.flags.aux_carry {
+            aux_carry = (result & 0x0f) + 6 > 0x0f;
+            result += 6;
+        }
+        if (result >> 4) > 9 || self.registers.flags.carry {
+            result += 0x60;
+        }
+        self.registers.a = result as u8;
+        self.registers.flags.carry |= result > 0xff;
+        self.registers.flags.aux_carry = aux_carry;
+        self.set_inr_dcr_flags(result as u8);
+    }
+
+    fn rotate_acc(&mut self, opcode: u8) {
+        let high_bit: u8 = self.registers.a >> 7;
+        let low_bit: u8 = self.registers.a & 0x01;
+        let instr: u8 = opcode >> 3;
+        let acc: u8 = self.registers.a;
+        self.registers.a = match instr {
+            0 => { || -> u8 {
+                self.registers.flags.carry = high_bit == 1;
+                return (acc << 1) + high_bit
+            }()},
+            1 => {
+                || -> u8 {
+                    self.registers.flags.carry = low_bit == 1;
+                    return (acc >> 1) + (low_bit << 7)
+                }()
+            },
+            2 => {|| -> u8 {
+                    let res = (acc << 1) + (self.registers.flags.carry as u8);
+                    self.registers.flags.carry = high_bit == 1;
+                    return res;
+                }()
+            },
+            _ => {|| -> u8 {
+                    let res = (acc >> 1) + ((self.registers.flags.carry as u8) << 7);
+                    self.registers.flags.carry = low_bit == 1;
+                    return res;
+                }()
+                
+            }
+        }
+    }
+
+    fn match_conds(&mut self, opcode: u8) -> bool {
+        let condition = (opcode >> 3) & 0b00111;
+        return match condition {
+            0 => { !self.registers.flags.zero }, // JNZ
+            1 => { self.registers.flags.zero }, // JZ
+            2 => { !self.registers.flags.carry }, // JNC
+            3 => { self.registers.flags.carry }, // JC
+            4 => { !self.registers.flags.parity }, // JPO
+            5 => { self.registers.flags.parity }, // JPE
+            6 => { !self.registers.flags.sign }, // JP
+            7 => { self.registers.flags.sign }, // JM
+            _ => { false }
+        };
+    }
+
+    fn call(&mut self) {
+        let ret: u16 = self.registers.pc.wrapping_add(2);
+        self.push_addr_to_stack(ret);
+        self.jmp();
+        self.record_call(ret);
+    }
+
+    fn rst(&mut self, opcode: u8) {
+        let ret: u16 = self.registers.pc;
+        self.push_addr_to_stack(ret);
+        self.registers.pc = (opcode & 0b00111000) as u16;
+        self.record_call(ret);
+    }
+
+    fn record_call(&mut self, return_addr: u16) {
+        self.call_stack.push(CallFrame {
+            call_site: self.instruction_pc,
+            target: self.registers.pc,
+            return_addr,
+            sp: self.registers.sp,
+        });
+    }
+
+    fn ret(&mut self) {
+        let sp: u16 = self.registers.sp;
+        let expected: Option<u16> = self.call_stack.frames().iter().rev()
+            .find(|frame| frame.sp == sp)
+            .map(|frame| frame.return_addr);
+        self.call_stack.on_return(sp);
+        self.registers.pc = self.pop_addr_from_stack();
+        if let Some(flow) = self.indirect_flow.as_mut().filter(|_| expected != Some(self.registers.pc)) {
+            flow.record(self.instruction_pc, self.registers.pc);
+        }
+    }
+
+    fn pop(&mut self, opcode: u8) {
+        let reg_pair: u8 = (opcode >> 4) & 0b11;
+        if reg_pair < 3 {
+            let val = self.pop_addr_from_stack();
+            self.registers.set_pair_wrapping(reg_pair, val);
+            return;
+        }
+
+        let low_byte: u8 = self.pop_from_stack();
+        let high_byte: u8 = self.pop_from_stack();
+        self.registers.a = high_byte;
+        self.registers.flags.set_flags(low_byte);
+    }
+
+    fn push(&mut self, opcode: u8) {
+        let reg_pair: u8 = (opcode >> 4) & 0b11; 
+        if reg_pair < 3 {
+            let val = self.registers.pair(reg_pair);
+            self.push_addr_to_stack(val);
+            return;
+        }
+
+        self.push_to_stack(self.registers.a);
+        let flags: u8 = self.registers.flags.to_psw_byte();
         self.push_to_stack(flags);
     }
 
     fn run_one_command(&mut self) {
         let opcode: u8 = self.get_byte();
+        self.cycles += CYCLES[opcode as usize] as u64;
         return match opcode {
             0x00 => self.nop(),
             0x01 | 0x11 | 0x21 | 0x31 => self.lxi(opcode),
@@ -609,13 +2063,13 @@ impl Processor {
             0x0a | 0x1a => self.ldax(opcode),
             0x0b | 0x1b | 0x2b | 0x3b => self.dcx(opcode),
             0x22 => self.shld(),
-            0x27 => self.nop(), // DAA
+            0x27 => self.daa(),
             0x2a => self.lhld(),
-            0x2f => self.a = !self.a, // CMA
+            0x2f => self.registers.a = !self.registers.a, // CMA
             0x32 => self.sta(),
-            0x37 => self.conditions.carry = true,
+            0x37 => self.registers.flags.carry = true,
             0x3a => self.lda(),
-            0x3f => self.conditions.carry = !self.conditions.carry,
+            0x3f => self.registers.flags.carry = !self.registers.flags.carry,
             0x40..=0x75 |0x77..=0x7f => self.mov(opcode),
             0x76 => self.halt(),
             0x80..=0x87 => self.add(opcode), // ADD
@@ -626,28 +2080,32 @@ impl Processor {
             0xa8..=0xaf => self.xra(opcode), // XRA
             0xb0..=0xb7 => self.ora(opcode), // ORA
             0xb8..=0xbf => self.cmp(opcode), // CMP
-            0xc2 | 0xca | 0xd2 | 0xda | 0xe2 | 0xea | 0xf2 | 0xfa => if self.match_conds(opcode) {
+            0xc2 | 0xca | 0xd2 | 0xda | 0xe2 | 0xea | 0xf2 | 0xfa => if self.branch_condition(opcode) {
                 self.jmp()
             } else {
-                self.pc += 2;
+                self.registers.pc = self.registers.pc.wrapping_add(2);
             },
             0xc3 => self.jmp(),
-            0xc4 | 0xcc | 0xd4 | 0xdc | 0xe4 | 0xec | 0xf4 | 0xfc => if self.match_conds(opcode) { 
+            0xc4 | 0xcc | 0xd4 | 0xdc | 0xe4 | 0xec | 0xf4 | 0xfc => if self.branch_condition(opcode) { 
+                self.cycles += CONDITIONAL_TAKEN_PENALTY as u64;
                 self.call()
             } else {
-                self.pc += 2;
+                self.registers.pc = self.registers.pc.wrapping_add(2);
+            },
+            0xc0 | 0xc8 | 0xd0 | 0xd8 | 0xe0 | 0xe8 | 0xf0 | 0xf8 => if self.branch_condition(opcode) {
+                self.cycles += CONDITIONAL_TAKEN_PENALTY as u64;
+                self.ret()
             },
-            0xc0 | 0xc8 | 0xd0 | 0xd8 | 0xe0 | 0xe8 | 0xf0 | 0xf8 => if self.match_conds(opcode) { self.ret() },
             0xc1 | 0xd1 | 0xe1 | 0xf1 => self.pop(opcode),
             0xc5 | 0xd5 | 0xe5 | 0xf5=> self.push(opcode),
             0xc6 => self.adi(),
-            0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => self.unimplemented_instruction(), // TODO: RST
+            0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => self.rst(opcode),
             0xc9 => self.ret(),
             0xcd => self.call(),
             0xce => self.aci(),
-            0xd3 => self.unimplemented_instruction(), // TODO: OUT
+            0xd3 => self.output(),
             0xd6 => self.sui(),
-            0xdb => self.unimplemented_instruction(), // TODO: IN
+            0xdb => self.input(),
             0xde => self.sbi(),
             0xe3 => self.xthl(),
             0xe6 => self.ani(),
@@ -656,110 +2114,1798 @@ impl Processor {
             0xee => self.xri(),
             0xf3 => self.interrupt_enabled = false,
             0xf6 => self.ori(),
-            0xf9 => self.sp = self.get_register_pair_value(2), // SPHL
-            0xfb => self.interrupt_enabled = true,
+            0xf9 => self.registers.set_pair_wrapping(3, self.registers.hl()), // SPHL
+            0xfb => (self.interrupt_enabled, self.ei_delay, self.ei_seen) = (true, true, true), // EI
             0xfe => self.cpi(),
             _ => self.unimplemented_instruction(),
         }
     }
-}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::clock::ManualClock;
+    use crate::progress::ProgressSink;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Access {
+        Read(u16),
+        Write(u16, u8),
+    }
+
+    #[derive(Debug)]
+    struct RecordingBus {
+        memory: FlatMemory,
+        log: Arc<Mutex<Vec<Access>>>,
+    }
+
+    impl MemoryBus for RecordingBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.log.lock().unwrap().push(Access::Read(addr));
+            return self.memory.read(addr);
+        }
+
+        fn write(&mut self, addr: u16, value: u8) {
+            self.log.lock().unwrap().push(Access::Write(addr, value));
+            self.memory.write(addr, value);
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            return self.memory.peek(addr);
+        }
+    }
+
+    /// Assembles `source`, checks it still matches the legacy fixture
+    /// `tests/<fixture>.bin` it replaces, then runs it to the end.
+    #[cfg(feature = "asm")]
+    fn run_source(source: &str, fixture: &str) -> Processor {
+        let bytes: Vec<u8> = crate::asm::assemble(source).unwrap_or_else(|err| panic!("{}: {}", fixture, err)).bytes;
+        assert_eq!(bytes, fs::read(format!("tests/{}.bin", fixture)).unwrap(), "{} no longer matches its fixture", fixture);
+        let mut processor: Processor = make_processor();
+        processor.load_program(&bytes);
+        processor.run();
+        return processor;
+    }
+
+    /// Runs a single memory-form instruction with HL = 0x2040 holding
+    /// `initial`, returning the processor and the accesses it made.
+    fn run_m_instruction(opcode: u8, initial: u8, carry: bool) -> (Processor, Vec<Access>) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut memory = FlatMemory::new();
+        memory.load(0, &[opcode]);
+        memory.load(0x2040, &[initial]);
+        let mut processor = ProcessorBuilder::new().memory_bus(Box::new(RecordingBus { memory, log: log.clone() })).build();
+        processor.registers.h = 0x20;
+        processor.registers.l = 0x40;
+        processor.registers.flags.carry = carry;
+
+        processor.step();
+
+        let accesses = log.lock().unwrap().clone();
+        return (processor, accesses);
+    }
+
+    #[test]
+    fn test_inr() {
+        let mut processor: Processor = make_processor();
+        processor.run_program("tests/inr_test.bin");
+
+        assert_eq!(processor.registers.b, 2);
+        assert_eq!(processor.registers.c, 3);
+        assert_eq!(processor.registers.d, 4);
+        assert_eq!(processor.registers.e, 5);
+        assert_eq!(processor.registers.h, 0x21);
+        assert_eq!(processor.registers.l, 0x21);
+        assert_eq!(processor.memory.peek(0x2121), 1);
+    }
+
+    #[test]
+    fn test_mem() {
+        let mut processor: Processor = make_processor();
+        processor.run_program("tests/mem_test.bin");
+
+        assert_eq!(processor.registers.b, 1);
+        assert_eq!(processor.registers.c, 1);
+        assert_eq!(processor.memory.peek(0x2020), 1);
+    }
+
+    #[test]
+    fn test_add() {
+        let mut processor: Processor = make_processor();
+        processor.run_program("tests/add_test.bin");
+
+        assert_eq!(processor.registers.a, 0xfb);
+        assert!(processor.registers.flags.sign);
+        assert!(processor.registers.flags.carry);
+    }
+
+    #[test]
+    fn test_call(){
+        let mut processor: Processor = make_processor();
+        processor.run_program("tests/call_test.bin");
+
+        assert_eq!(processor.registers.sp, 0x53);
+        assert_eq!(processor.registers.pc, 0xc);
+    }
+
+    #[test]
+    fn test_mov(){
+        let mut processor: Processor = make_processor();
+        processor.run_program("tests/mov_test.bin");
+
+        assert_eq!(processor.registers.b, 0x4);
+        assert_eq!(processor.memory.peek(0x2019), 0x2);
+        assert_eq!(processor.memory.peek(0x1918), 0x4);
+    }
+
+    /// The state tests/jump.bin halts in.
+    fn check_jump(processor: &Processor) {
+        assert_eq!(processor.registers.a, 0x0);
+        assert_eq!(processor.registers.c, 0x14);
+        assert_eq!(processor.registers.pc, 0xc);
+        assert!(processor.registers.flags.zero);
+        assert!(processor.registers.flags.parity);
+    }
+
+    #[test]
+    fn test_jump() {
+        let mut processor: Processor = make_processor();
+        processor.run_program("tests/jump.bin");
+        check_jump(&processor);
+    }
+
+    #[cfg(feature = "asm")]
+    #[test]
+    fn test_jump_source() {
+        let processor: Processor = run_source("
+              mvi a, 1h
+              dcr a
+              jz YesZero
+              jnz NoZero
+
+            YesZero:
+              mvi c, 20
+              hlt
+
+            NoZero:
+              mvi c, 50
+              hlt
+        ", "jump");
+        check_jump(&processor);
+    }
+
+    /// The state tests/memcpy.bin halts in.
+    fn check_mem_cpy(processor: &Processor) {
+        assert_eq!(processor.registers.e, 0x16);
+        assert_eq!(processor.registers.pc, 0x11);
+        assert_eq!(processor.registers.l, 0x1b);
+        assert_eq!(processor.registers.sp, 0x9fff);
+        assert!(processor.registers.flags.zero);
+        assert!(processor.registers.flags.parity);
+        assert!(!processor.registers.flags.carry);
+        assert!(!processor.registers.flags.sign);
+        assert_eq!(processor.memory.peek(0x17), 0x22);
+    }
+
+    #[test]
+    fn test_mem_cpy() {
+        let mut processor: Processor = make_processor();
+        processor.run_program("tests/memcpy.bin");
+        check_mem_cpy(&processor);
+    }
+
+    #[cfg(feature = "asm")]
+    #[test]
+    fn test_mem_cpy_source() {
+        let processor: Processor = run_source("
+              lxi de, SourceArray
+              lxi hl, TargetArray
+              lxi sp, 9fffh
+              mvi b, 0
+              mvi c, 5
+              call memcpy
+              hlt
+
+            SourceArray:
+              db 11h, 22h, 33h, 44h, 55h
+
+            TargetArray:
+              db 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+
+              ; bc: number of bytes to copy
+              ; de: source block
+              ; hl: target block
+            memcpy:
+              mov a, b
+              ora c
+              rz
+            loop:
+              ldax de
+              mov m, a
+              inx de
+              inx hl
+              dcx bc
+              mov a, b
+              ora c
+              jnz loop
+              ret
+        ", "memcpy");
+        check_mem_cpy(&processor);
+    }
+
+    /// The state tests/capitalize.bin halts in.
+    fn check_capitalize(processor: &Processor) {
+        assert_eq!(processor.registers.b, 0x0);
+        assert_eq!(processor.registers.pc, 0xc);
+        assert_eq!(processor.registers.l, 0x34);
+        assert_eq!(processor.memory.peek(0x32), 0x44);
+        assert!(processor.registers.flags.zero);
+        assert!(processor.registers.flags.parity);
+        assert!(!processor.registers.flags.carry);
+        assert!(!processor.registers.flags.sign);
+    }
+
+    #[test]
+    fn test_capitalize() {
+        let mut processor: Processor = make_processor();
+        processor.run_program("tests/capitalize.bin");
+        check_capitalize(&processor);
+    }
+
+    #[cfg(feature = "asm")]
+    #[test]
+    fn test_capitalize_source() {
+        let processor: Processor = run_source("
+              lxi sp, 9fffh
+              lxi hl, str
+              mvi c, 14
+              call Capitalize
+              hlt
+
+            Capitalize:
+              mov a, c
+              cpi 0
+              jz AllDone
+
+              mov a, m
+              cpi 61h
+              jc SkipIt
+
+              cpi 7bh
+              jnc SkipIt
+
+              sui 20h
+              mov m, a
+
+            SkipIt:
+              inx hl
+              dcr c
+              jmp Capitalize
+
+            AllDone:
+              ret
+
+            str:
+              db 'hello, friends'
+        ", "capitalize");
+        check_capitalize(&processor);
+    }
+
+    #[test]
+    fn test_deadline_stops_infinite_loop() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0xc3, 0x00, 0x00]); // JMP 0
+        processor.set_deadline_check_interval(100);
+
+        let start = Instant::now();
+        let outcome = processor.run_with_deadline(start + Duration::from_millis(50));
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        match outcome {
+            RunOutcome::DeadlineExceeded { instructions } => {
+                assert!(instructions > 0);
+                assert_eq!(instructions, processor.instruction_count());
+                assert_eq!(instructions % 100, 0);
+            },
+            other => panic!("expected deadline, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_deadline_fast_program_halts() {
+        let mut processor: Processor = make_processor();
+        processor.initialize_memory("tests/add_test.bin");
+
+        let outcome = processor.run_with_deadline(Instant::now() + Duration::from_secs(5));
+
+        assert_eq!(outcome, RunOutcome::Halted);
+        assert_eq!(processor.registers.a, 0xfb);
+        assert_eq!(processor.instruction_count(), 5);
+    }
+
+    #[test]
+    fn test_deadline_breakpoint_and_resume() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0x3e, 0x01, 0xc3, 0x02, 0x00]); // MVI A,1; JMP 2
+        processor.add_breakpoint(0x2);
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        assert_eq!(processor.run_with_deadline(deadline), RunOutcome::Breakpoint { pc: 0x2 });
+        assert_eq!(processor.instruction_count(), 1);
+        assert_eq!(processor.run_with_deadline(deadline), RunOutcome::Breakpoint { pc: 0x2 });
+        assert_eq!(processor.instruction_count(), 2);
+
+        processor.remove_breakpoint(0x2);
+        let outcome = processor.run_with_deadline(Instant::now());
+        assert_eq!(outcome, RunOutcome::DeadlineExceeded { instructions: 2 });
+    }
+
+    #[test]
+    fn test_inr_m_access_order_and_flags() {
+        let (processor, accesses) = run_m_instruction(0x34, 0xff, true);
+
+        assert_eq!(accesses, vec![Access::Read(0x0), Access::Read(0x2040), Access::Write(0x2040, 0x00)]);
+        assert_eq!(processor.memory.peek(0x2040), 0x00);
+        assert!(processor.registers.flags.zero);
+        assert!(processor.registers.flags.parity);
+        assert!(!processor.registers.flags.sign);
+        assert!(processor.registers.flags.carry);
+        assert_eq!(processor.cycle_count(), 10);
+    }
+
+    #[test]
+    fn test_dcr_m_access_order_and_flags() {
+        let (processor, accesses) = run_m_instruction(0x35, 0x00, false);
+
+        assert_eq!(accesses, vec![Access::Read(0x0), Access::Read(0x2040), Access::Write(0x2040, 0xff)]);
+        assert_eq!(processor.memory.peek(0x2040), 0xff);
+        assert!(!processor.registers.flags.zero);
+        assert!(processor.registers.flags.parity);
+        assert!(processor.registers.flags.sign);
+        assert!(!processor.registers.flags.carry);
+        assert_eq!(processor.cycle_count(), 10);
+    }
+
+    #[test]
+    fn test_inr_dcr_register_cycles() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0x04, 0x05, 0x76]); // INR B; DCR B; HLT
+        processor.run_with_deadline(Instant::now() + Duration::from_secs(5));
+
+        assert_eq!(processor.cycle_count(), 5 + 5 + 7);
+    }
+
+    #[test]
+    fn test_mov_memory_cycles() {
+        let cycles_for = |opcode: u8| -> u64 {
+            let mut processor: Processor = make_processor();
+            let mut program: Vec<u8> = vec![opcode; 100];
+            program.push(0x76); // HLT
+            processor.load_program(&program);
+            assert_eq!(processor.run(), RunOutcome::Halted);
+            return processor.cycle_count();
+        };
+
+        let register: u64 = cycles_for(0x41); // MOV B,C
+        let memory: u64 = cycles_for(0x46); // MOV B,M
+        assert_eq!(register, 100 * 5 + 7);
+        assert_eq!(memory, 100 * 7 + 7);
+        assert_eq!(memory - register, 200);
+        assert_eq!(cycles_for(0x70), memory); // MOV M,B
+    }
+
+    #[test]
+    fn test_stack_words_are_low_byte_first() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[
+            0x31, 0x00, 0x30, // 0000 LXI SP,3000h
+            0x01, 0x34, 0x12, // 0003 LXI B,1234h
+            0xc5,             // 0006 PUSH B
+            0xcd, 0x0b, 0x00, // 0007 CALL 000bh
+            0x76,             // 000a HLT
+            0x76,             // 000b HLT
+        ]);
+        processor.run_with_limit(3);
+        assert_eq!((processor.registers.sp, processor.peek(0x2ffe), processor.peek(0x2fff)), (0x2ffe, 0x34, 0x12));
+        processor.run_with_limit(1);
+        assert_eq!((processor.registers.sp, processor.peek(0x2ffc), processor.peek(0x2ffd)), (0x2ffc, 0x0a, 0x00));
+
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[
+            0x31, 0x00, 0x30, // 0000 LXI SP,3000h
+            0x3e, 0x5a,       // 0003 MVI A,5Ah
+            0x37,             // 0005 STC
+            0xf5,             // 0006 PUSH PSW
+            0xc1,             // 0007 POP B
+            0x21, 0xef, 0xbe, // 0008 LXI H,0BEEFh
+            0x22, 0x00, 0x20, // 000b SHLD 2000h
+            0x31, 0x00, 0x20, // 000e LXI SP,2000h
+            0x21, 0x00, 0x00, // 0011 LXI H,0
+            0xe1,             // 0014 POP H
+            0x76,             // 0015 HLT
+        ]);
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert_eq!((processor.registers.b, processor.registers.c), (0x5a, 0x03));
+        assert_eq!(processor.registers.hl(), 0xbeef);
+    }
+
+    #[test]
+    fn test_stack_exchange_cycles() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[
+            0x31, 0x00, 0x30, // 0000 LXI SP,3000h
+            0x21, 0x34, 0x12, // 0003 LXI H,1234h
+            0x01, 0xe8, 0x03, // 0006 LXI B,1000
+            0xc5,             // 0009 loop: PUSH B
+            0xe3,             // 000a XTHL
+            0xd1,             // 000b POP D
+            0xeb,             // 000c XCHG
+            0x0b,             // 000d DCX B
+            0x78,             // 000e MOV A,B
+            0xb1,             // 000f ORA C
+            0xc2, 0x09, 0x00, // 0010 JNZ loop
+            0x76,             // 0013 HLT
+        ]);
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        // PUSH 11, XTHL 18, POP 10 and XCHG 4, then DCX 5, MOV 5, ORA 4 and JNZ 10.
+        let iteration: u64 = 11 + 18 + 10 + 4 + 5 + 5 + 4 + 10;
+        assert_eq!(processor.cycle_count(), 3 * 10 + 1000 * iteration + 7);
+        assert_eq!(processor.instruction_count(), 3 + 1000 * 8 + 1);
+        assert_eq!((processor.registers.hl(), processor.registers.de(), processor.registers.sp), (0x1234, 0x0001, 0x3000));
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct RecordingTracer {
+        records: Arc<Mutex<Vec<TraceRecord>>>,
+        warnings: Arc<Mutex<Vec<TimedEvent>>>,
+    }
+
+    impl Tracer for RecordingTracer {
+        fn trace(&mut self, record: &TraceRecord, _after: &Registers) {
+            self.records.lock().unwrap().push(*record);
+        }
+
+        fn warn(&mut self, warning: &TimedEvent) {
+            self.warnings.lock().unwrap().push(warning.clone());
+        }
+    }
+
+    fn trace_records(filter: TraceFilter) -> Vec<TraceRecord> {
+        let tracer = RecordingTracer::default();
+        let mut processor: Processor = ProcessorBuilder::new()
+            .tracer(Box::new(tracer.clone()))
+            .trace_filter(filter)
+            .build();
+        processor.load_at(0x0000, &[
+            0x31, 0x00, 0x20, // 0000 LXI SP,2000h
+            0x06, 0x03,       // 0003 MVI B,3
+            0xcd, 0x10, 0x00, // 0005 CALL 0010h
+            0x05,             // 0008 DCR B
+            0xc2, 0x05, 0x00, // 0009 JNZ 0005h
+            0x76,             // 000c HLT
+        ]);
+        processor.load_at(0x0010, &[
+            0x78,             // 0010 MOV A,B
+            0x87,             // 0011 ADD A
+            0xc9,             // 0012 RET
+        ]);
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        return tracer.records.lock().unwrap().clone();
+    }
+
+    #[test]
+    fn test_trace_range_filter() {
+        let records = trace_records(TraceFilter::new().range(0x0010..=0x0012));
+
+        let pcs: Vec<u16> = records.iter().map(|record| record.pc).collect();
+        assert_eq!(pcs, [0x0010, 0x0011, 0x0012].repeat(3));
+        assert_eq!(records[0].bytes, [0x78, 0x87, 0xc9]);
+        assert_eq!(records[0].registers.b, 3);
+        assert_eq!((records[3].instructions, records[3].registers.b), (9, 2));
+    }
+
+    #[test]
+    fn test_trace_flow_filter() {
+        let records = trace_records(TraceFilter::new().classes(TRACE_FLOW));
+
+        let pcs: Vec<u16> = records.iter().map(|record| record.pc).collect();
+        assert_eq!(pcs, [0x0005, 0x0012, 0x0009].repeat(3));
+        assert!(records.iter().all(|record| matches!(record.bytes[0], 0xcd | 0xc9 | 0xc2)));
+        assert_eq!(trace_records(TraceFilter::new()).len(), 2 + 6 * 3 + 1);
+    }
+
+    fn run_delay_loops(program: &[u8], fast_forward: bool) -> (Processor, Vec<Event>) {
+        let mut processor: Processor = ProcessorBuilder::new().fast_forward_loops(fast_forward).build();
+        let log = EventLog::new(64);
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        processor.load_program(program);
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        return (processor, log.events().into_iter().map(|event| event.event).collect());
+    }
+
+    #[test]
+    fn test_fast_forward_matches_running_the_loop() {
+        let program: Vec<u8> = vec![
+            0x0e, 0x03,       // 0000 MVI C,3
+            0x06, 0x00,       // 0002 MVI B,0
+            0x05,             // 0004 DCR B
+            0x51,             // 0005 MOV D,C
+            0xc2, 0x04, 0x00, // 0006 JNZ 0004h
+            0x1e, 0x07,       // 0009 MVI E,7
+            0x1d,             // 000b DCR E
+            0xc2, 0x0b, 0x00, // 000c JNZ 000bh
+            0x0d,             // 000f DCR C
+            0xc2, 0x02, 0x00, // 0010 JNZ 0002h
+            0x76,             // 0013 HLT
+        ];
+
+        let (slow, slow_events) = run_delay_loops(&program, false);
+        let (fast, fast_events) = run_delay_loops(&program, true);
+
+        assert_eq!(fast.snapshot(), slow.snapshot());
+        assert_eq!(fast.cycle_count(), 7 + 3 * (7 + 256 * 20 + 7 + 7 * 15 + 15) + 7);
+        assert!(slow_events.is_empty());
+        let skips: Vec<Event> = vec![
+            Event::LoopSkipped { addr: 0x0004, iterations: 255 },
+            Event::LoopSkipped { addr: 0x000b, iterations: 6 },
+        ];
+        assert_eq!(fast_events, skips.iter().cycle().take(6).cloned().collect::<Vec<Event>>());
+    }
+
+    #[test]
+    fn test_fast_forward_stops_at_the_instruction_limit() {
+        let program: [u8; 9] = [
+            0x06, 0x00,       // 0000 MVI B,0
+            0x05,             // 0002 DCR B
+            0xc2, 0x02, 0x00, // 0003 JNZ 0002h
+            0xc3, 0x00, 0x00, // 0006 JMP 0000h
+        ];
+        for limit in [1, 10, 11, 600, 1000] {
+            let mut slow: Processor = make_processor();
+            let mut fast: Processor = ProcessorBuilder::new().fast_forward_loops(true).build();
+            for processor in [&mut slow, &mut fast] {
+                processor.load_program(&program);
+            }
+
+            assert_eq!(fast.run_with_limit(limit), RunOutcome::InstructionLimit { instructions: limit });
+            assert_eq!(slow.run_with_limit(limit), RunOutcome::InstructionLimit { instructions: limit });
+            assert_eq!(fast.snapshot(), slow.snapshot(), "after {} instructions", limit);
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_fast_forward_stops_at_frame_ends() {
+        let program: [u8; 7] = [
+            0x06, 0x00,       // 0000 MVI B,0
+            0x05,             // 0002 DCR B
+            0xc2, 0x02, 0x00, // 0003 JNZ 0002h
+            0x76,             // 0006 HLT
+        ];
+        let frames = |fast_forward: bool| -> (Vec<(u64, u8, u64)>, Vec<Event>) {
+            let seen: Arc<Mutex<Vec<(u64, u8, u64)>>> = Arc::default();
+            let mut processor: Processor = ProcessorBuilder::new().fast_forward_loops(fast_forward).build();
+            let log = EventLog::new(64);
+            processor.set_event_sink(Some(Box::new(log.clone())));
+            let record = seen.clone();
+            processor.set_frame_callback(1000, Some(Box::new(move |frame, processor| {
+                record.lock().unwrap().push((frame, processor.registers().b, processor.cycle_count()));
+            })));
+            processor.load_program(&program);
+            assert_eq!(processor.run(), RunOutcome::Halted);
+            let events: Vec<Event> = log.events().into_iter().map(|timed| timed.event).collect();
+            return (seen.lock().unwrap().clone(), events);
+        };
+
+        let (slow, _) = frames(false);
+        let (fast, skips) = frames(true);
+
+        assert_eq!(fast, slow);
+        assert_eq!(slow.len(), 3);
+        assert!(skips.len() > 1, "{:?}", skips);
+
+        let mut watched: Processor = ProcessorBuilder::new().fast_forward_loops(true).build();
+        let log = EventLog::new(512);
+        watched.set_event_sink(Some(Box::new(log.clone())));
+        watched.watch_expr("b", |processor| processor.registers().b as u64);
+        watched.load_program(&program);
+        assert_eq!(watched.run(), RunOutcome::Halted);
+        assert!(!log.events().iter().any(|timed| matches!(timed.event, Event::LoopSkipped { .. })));
+    }
 
     #[test]
-    fn test_inr() {
+    fn test_fast_forward_leaves_memory_loops_alone() {
+        let program: Vec<u8> = vec![
+            0x21, 0x00, 0x20, // 0000 LXI H,2000h
+            0x06, 0x10,       // 0003 MVI B,10h
+            0x05,             // 0005 DCR B
+            0x70,             // 0006 MOV M,B
+            0xc2, 0x05, 0x00, // 0007 JNZ 0005h
+            0x76,             // 000a HLT
+        ];
+
+        let (slow, _) = run_delay_loops(&program, false);
+        let (fast, events) = run_delay_loops(&program, true);
+
+        assert!(events.is_empty());
+        assert_eq!(fast.snapshot(), slow.snapshot());
+
+        let mut processor: Processor = ProcessorBuilder::new().fast_forward_loops(true).build();
+        processor.load_program(&[0x06, 0x10, 0x05, 0xc2, 0x02, 0x00, 0x76]); // MVI B,10h; DCR B; JNZ; HLT
+        processor.add_breakpoint(0x0003);
+        assert_eq!(processor.run(), RunOutcome::Breakpoint { pc: 0x0003 });
+        assert_eq!(processor.registers().b, 0x0f);
+    }
+
+    #[test]
+    fn test_invalid_opcode_fault_report() {
+        let mut processor: Processor = ProcessorBuilder::new().strict(true).build();
+        processor.load_at(0x0000, &[
+            0x31, 0x00, 0x20, // 0000 LXI SP,2000h
+            0x3e, 0x01,       // 0003 MVI A,1
+            0xcd, 0x10, 0x00, // 0005 CALL 0010h
+            0x76,             // 0008 HLT
+        ]);
+        processor.load_at(0x0010, &[
+            0x3c,             // 0010 INR A
+            0x47,             // 0011 MOV B,A
+            0xed,             // 0012 (invalid)
+        ]);
+
+        assert_eq!(processor.run(), RunOutcome::Fault(EmuError::InvalidOpcode { opcode: 0xed, operands: [0, 0], pc: 0x0012 }));
+
+        let report = processor.fault_report().expect("a fault leaves a report");
+        let history: Vec<String> = report.history.iter().map(|line| line.text.clone()).collect();
+        assert_eq!(history, vec!["lxi sp, 2000h", "mvi a, 01h", "call 0010h", "inr a", "mov b, a"]);
+        assert_eq!(report.instruction.addr, 0x0012);
+        assert_eq!(report.backtrace, vec![CallFrame { call_site: 0x0005, target: 0x0010, return_addr: 0x0008, sp: 0x1ffe }]);
+        assert_eq!(report.stack, (0x1ffe..0x2006).map(|addr| processor.peek(addr)).collect::<Vec<u8>>());
+        assert_eq!(report.registers.b, 2);
+        let text = report.to_string();
+        assert!(text.starts_with("fault: invalid opcode 0xed at 0x0012 (call 0000h alias)\n    0000  31 00 20  lxi sp, 2000h\n"));
+        assert!(text.contains("  > 0012  ed        db 0edh\n"));
+        assert!(text.ends_with("backtrace:\n  #0 0010 called from 0005\n"));
+    }
+
+    #[test]
+    fn test_unimplemented_opcode_messages() {
+        let program: [u8; 3] = [0xdd, 0x56, 0x34]; // CALL 3456h alias
         let mut processor: Processor = make_processor();
-        processor.run_program("tests/inr_test.bin");
+        let log = EventLog::default();
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        processor.load_at(0x0142, &program);
+        processor.set_pc(0x0142);
+
+        processor.step();
+
+        let events: Vec<TimedEvent> = log.events();
+        assert_eq!(events.iter().map(|timed| (timed.pc, timed.event.clone())).collect::<Vec<_>>(),
+                   vec![(0x0142, Event::UnimplementedOpcode { opcode: 0xdd, operands: [0x56, 0x34] })]);
+        assert_eq!(events[0].event.to_string(), "unimplemented instruction dd (call 3456h alias)");
 
-        assert_eq!(processor.b, 2);
-        assert_eq!(processor.c, 3);
-        assert_eq!(processor.d, 4);
-        assert_eq!(processor.e, 5);
-        assert_eq!(processor.h, 0x21);
-        assert_eq!(processor.l, 0x21);
-        assert_eq!(processor.memory[0x2121], 1);
+        let mut processor: Processor = ProcessorBuilder::new().strict(true).build();
+        processor.load_at(0x0142, &program);
+        processor.set_pc(0x0142);
+
+        let outcome: RunOutcome = processor.run();
+
+        assert_eq!(outcome, RunOutcome::Fault(EmuError::InvalidOpcode { opcode: 0xdd, operands: [0x56, 0x34], pc: 0x0142 }));
+        let RunOutcome::Fault(error) = outcome else { unreachable!() };
+        assert_eq!(error.to_string(), "invalid opcode 0xdd at 0x0142 (call 3456h alias)");
+    }
+
+    /// A verdict that the program halted having copied "ok" to 2000h,
+    /// with the last byte copied still in A.
+    fn copied_ok(processor: &Processor, outcome: &RunOutcome) -> Result<(), String> {
+        if *outcome != RunOutcome::Halted {
+            return Err(format!("stopped with {:?}", outcome));
+        }
+        let copied: Vec<u8> = (0x2000..0x2002).map(|addr| processor.peek(addr)).collect();
+        if copied != b"ok" {
+            return Err(format!("2000h-2001h hold {:02x?}", copied));
+        }
+        return if processor.registers().a == b'k' { Ok(()) } else { Err(format!("A is {:02x}", processor.registers().a)) };
+    }
+
+    /// Copies `count` bytes of "ok" to 2000h.
+    fn copy_ok_program(count: u8) -> Processor {
+        let mut processor: Processor = make_processor();
+        processor.load_at(0x0000, &[
+            0x21, 0x11, 0x00, // 0000 LXI H,0011h
+            0x11, 0x00, 0x20, // 0003 LXI D,2000h
+            0x06, count,      // 0006 MVI B,count
+            0x7e,             // 0008 loop: MOV A,M
+            0x12,             // 0009 STAX D
+            0x23,             // 000a INX H
+            0x13,             // 000b INX D
+            0x05,             // 000c DCR B
+            0xc2, 0x08, 0x00, // 000d JNZ loop
+            0x76,             // 0010 HLT
+            b'o', b'k',       // 0011
+        ]);
+        return processor;
     }
 
     #[test]
-    fn test_mem() {
+    fn test_verdict_passes() {
+        let mut processor = copy_ok_program(2);
+
+        assert_eq!(processor.run_with_verdict(copied_ok), Ok(()));
+        assert!(processor.fault_report().is_none());
+    }
+
+    #[test]
+    fn test_verdict_failure_reports_final_state() {
+        let mut processor = copy_ok_program(1);
+
+        let error: EmuError = processor.run_with_verdict(copied_ok).unwrap_err();
+
+        let EmuError::PostconditionFailed { message, report: Some(report) } = &error else { panic!("{:?}", error) };
+        assert_eq!(message, "2000h-2001h hold [6f, 00]");
+        assert_eq!((report.registers.a, report.registers.b, report.registers.pc), (b'o', 0, 0x0011));
+        assert_eq!(processor.fault_report(), Some(report.as_ref()));
+        let text = report.to_string();
+        assert!(text.starts_with("fault: postcondition failed: 2000h-2001h hold [6f, 00]\n"), "{}", text);
+        assert!(text.contains("registers: A=6f"), "{}", text);
+        assert_eq!(copy_ok_program(2).apply_verdict(&RunOutcome::Fault(EmuError::InvalidOpcode { opcode: 0xed, operands: [0, 0], pc: 0 }), copied_ok),
+                   Err(EmuError::InvalidOpcode { opcode: 0xed, operands: [0, 0], pc: 0 }));
+    }
+
+    #[test]
+    fn test_instruction_limit() {
         let mut processor: Processor = make_processor();
-        processor.run_program("tests/mem_test.bin");
+        processor.load_program(&[0xc3, 0x00, 0x00]); // JMP 0
+
+        assert_eq!(processor.run_with_limit(10), RunOutcome::InstructionLimit { instructions: 10 });
+        assert_eq!(processor.run_with_limit(5), RunOutcome::InstructionLimit { instructions: 15 });
+        assert_eq!(processor.cycle_count(), 150);
+    }
+
+    struct CountingSink {
+        finished: Arc<Mutex<Option<Progress>>>,
+    }
 
-        assert_eq!(processor.b, 1);
-        assert_eq!(processor.c, 1);
-        assert_eq!(processor.memory[0x2020], 1);
+    impl ProgressSink for CountingSink {
+        fn report(&mut self, _progress: &Progress) {}
+
+        fn finish(&mut self, progress: &Progress) {
+            *self.finished.lock().unwrap() = Some(*progress);
+        }
     }
 
     #[test]
-    fn test_add() {
+    fn test_progress_finish_reports_run_totals() {
+        let finished = Arc::new(Mutex::new(None));
         let mut processor: Processor = make_processor();
-        processor.run_program("tests/add_test.bin");
+        processor.load_program(&[0xc3, 0x00, 0x00]); // JMP 0
+        let sink = CountingSink { finished: finished.clone() };
+        processor.set_progress_reporter(Some(ProgressReporter::new(Box::new(sink), Duration::ZERO)));
 
-        assert_eq!(processor.a, 0xfb);
-        assert!(processor.conditions.sign);
-        assert!(processor.conditions.carry);
+        processor.run_with_limit(2000);
+
+        let progress = finished.lock().unwrap().unwrap();
+        assert_eq!(progress.instructions, 2000);
+        assert_eq!(progress.cycles, 20000);
+        assert_eq!(progress.max_instructions, Some(2000));
+        assert_eq!(progress.percent_complete(), Some(100.0));
     }
 
     #[test]
-    fn test_call(){
+    fn test_throttled_run_for_uses_processor_clock() {
+        let clock = ManualClock::new();
+        let finished = Arc::new(Mutex::new(None));
+        let mut processor: Processor = ProcessorBuilder::new()
+            .clock(Box::new(clock.clone()))
+            .throttle(1_000_000)
+            .deadline_check_interval(100)
+            .build();
+        processor.load_program(&[0xc3, 0x00, 0x00]); // JMP 0, 10 cycles
+        let sink = CountingSink { finished: finished.clone() };
+        processor.set_progress_reporter(Some(ProgressReporter::new(Box::new(sink), Duration::ZERO)));
+
+        let started = Instant::now();
+        let outcome = processor.run_for(Duration::from_millis(10));
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(outcome, RunOutcome::DeadlineExceeded { instructions: 1000 });
+        assert_eq!(clock.now(), Duration::from_millis(10));
+        let progress = finished.lock().unwrap().unwrap();
+        assert_eq!(progress.elapsed, Duration::from_millis(10));
+        assert_eq!(progress.emulated_mhz(), 1.0);
+    }
+
+    #[test]
+    fn test_watchpoint_stops_after_write() {
         let mut processor: Processor = make_processor();
-        processor.run_program("tests/call_test.bin");
+        processor.initialize_memory("tests/mov_test.bin");
+        processor.add_watchpoint(0x1918, 1);
 
-        assert_eq!(processor.sp, 0x53);
-        assert_eq!(processor.pc, 0xc);
+        assert_eq!(processor.run(), RunOutcome::Watchpoint { addr: 0x1918, pc: 0x14 });
+        assert_eq!(processor.registers.pc, 0x15);
+        assert_eq!(processor.memory.peek(0x1918), 0x4);
+
+        processor.remove_watchpoint(0x1918);
+        assert_eq!(processor.run(), RunOutcome::Halted);
     }
 
     #[test]
-    fn test_mov(){
+    fn test_watchpoint_covers_range() {
         let mut processor: Processor = make_processor();
-        processor.run_program("tests/mov_test.bin");
+        processor.initialize_memory("tests/mov_test.bin");
+        processor.add_watchpoint(0x2017, 2);
 
-        assert_eq!(processor.b, 0x4);
-        assert_eq!(processor.memory[0x2019], 0x2);
-        assert_eq!(processor.memory[0x1918], 0x4);
+        assert_eq!(processor.run(), RunOutcome::Watchpoint { addr: 0x2018, pc: 0x0e });
     }
+
     #[test]
-    fn test_jump() {
+    fn test_run_until_mem() {
         let mut processor: Processor = make_processor();
-        processor.run_program("tests/jump.bin");
-        assert_eq!(processor.a, 0x0);
-        assert_eq!(processor.c, 0x14);
-        assert_eq!(processor.pc, 0xc);
-        assert!(processor.conditions.zero);
-        assert!(processor.conditions.parity);
+        processor.load_program(&[
+            0x3e, 0x00,       // 0000 MVI A,0
+            0x3c,             // 0002 loop: INR A
+            0x32, 0x40, 0x20, // 0003 STA 2040h
+            0xc3, 0x02, 0x00, // 0006 JMP loop
+        ]);
+
+        assert_eq!(processor.run_until_mem(0x2040, 5, 1000), RunOutcome::MemoryValue { addr: 0x2040, value: 5, pc: 0x0003 });
+        assert_eq!(processor.instruction_count(), 1 + 4 * 3 + 2);
+        assert_eq!(processor.pc(), 0x0006);
+
+        assert_eq!(processor.run_until_mem(0x2041, 6, 100), RunOutcome::InstructionLimit { instructions: 115 });
+        assert_eq!(processor.peek(0x2040), 38);
+    }
+
+    /// Counts B down through a recursive call, one frame per count.
+    fn recursive_processor() -> Processor {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[
+            0x31, 0x00, 0x20, // 0000 LXI SP,2000h
+            0x06, 0x03,       // 0003 MVI B,3
+            0xcd, 0x0c, 0x00, // 0005 CALL rec
+            0x76,             // 0008 HLT
+            0x00, 0x00, 0x00,
+            0x05,             // 000c rec: DCR B
+            0xc4, 0x0c, 0x00, // 000d CNZ rec
+            0xc9,             // 0010 RET
+        ]);
+        return processor;
     }
 
     #[test]
-    fn test_mem_cpy() {
+    fn test_step_over_recursive_call() {
+        let mut processor = recursive_processor();
+        assert_eq!(processor.run_to(0x000d), RunOutcome::Stepped { pc: 0x000d });
+        assert_eq!((processor.registers.b, processor.registers.sp), (2, 0x1ffe));
+
+        // The deeper frames reach 0010 first, with a lower SP.
+        assert_eq!(processor.step_over(), RunOutcome::Stepped { pc: 0x0010 });
+        assert_eq!((processor.registers.b, processor.registers.sp), (0, 0x1ffe));
+        assert_eq!(processor.call_stack().len(), 1);
+
+        assert_eq!(processor.step_over(), RunOutcome::Stepped { pc: 0x0008 });
+        assert_eq!(processor.step_over(), RunOutcome::Halted);
+    }
+
+    #[test]
+    fn test_step_over_plain_and_untaken_calls() {
+        let mut processor = recursive_processor();
+        assert_eq!(processor.step_over(), RunOutcome::Stepped { pc: 0x0003 });
+        processor.registers.b = 1;
+        processor.set_pc(0x000c);
+        processor.step();
+
+        assert_eq!(processor.step_over(), RunOutcome::Stepped { pc: 0x0010 });
+        assert_eq!(processor.instruction_count(), 3);
+    }
+
+    #[test]
+    fn test_step_out_two_frames() {
+        let mut processor = recursive_processor();
+        processor.add_breakpoint(0x0010);
+        assert_eq!(processor.run(), RunOutcome::Breakpoint { pc: 0x0010 });
+        assert_eq!(processor.call_stack().len(), 3);
+        processor.remove_breakpoint(0x0010);
+
+        assert_eq!(processor.step_out(), Some(RunOutcome::Stepped { pc: 0x0010 }));
+        assert_eq!((processor.call_stack().len(), processor.registers.sp), (2, 0x1ffc));
+        assert_eq!(processor.step_out(), Some(RunOutcome::Stepped { pc: 0x0010 }));
+        assert_eq!((processor.call_stack().len(), processor.registers.sp), (1, 0x1ffe));
+        assert_eq!(processor.step_out(), Some(RunOutcome::Stepped { pc: 0x0008 }));
+        assert_eq!(processor.step_out(), None);
+    }
+
+    #[test]
+    fn test_step_over_call_that_skips_inline_data() {
         let mut processor: Processor = make_processor();
-        processor.run_program("tests/memcpy.bin");
+        processor.load_program(&[
+            0x31, 0x00, 0x20, // 0000 LXI SP,2000h
+            0xcd, 0x0a, 0x00, // 0003 CALL skip
+            0x2a,             // 0006 DB 2ah, skipped by the callee
+            0x76,             // 0007 HLT
+            0x00, 0x00,
+            0xe3,             // 000a skip: XTHL
+            0x23,             // 000b INX H
+            0xe3,             // 000c XTHL
+            0xc9,             // 000d RET
+        ]);
+        processor.step();
+
+        assert_eq!(processor.step_over(), RunOutcome::Stepped { pc: 0x0007 });
+        assert_eq!(processor.registers.sp, 0x2000);
+    }
+
+    fn audited_events(program: &[u8]) -> Vec<Event> {
+        let mut processor: Processor = ProcessorBuilder::new().flag_audit(true).build();
+        let log = EventLog::new(16);
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        processor.load_program(program);
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        return log.events().into_iter().map(|event| event.event).collect();
+    }
 
-        assert_eq!(processor.e, 0x16);
-        assert_eq!(processor.pc, 0x11);
-        assert_eq!(processor.l, 0x1b);
-        assert_eq!(processor.sp, 0x9fff);
-        assert!(processor.conditions.zero);
-        assert!(processor.conditions.parity);
-        assert!(!processor.conditions.carry);
-        assert!(!processor.conditions.sign);
-        assert_eq!(processor.memory[0x17], 0x22);
+    #[test]
+    fn test_flag_audit_reports_clobbered_compare() {
+        let compare_then_branch: Vec<u8> = vec![
+            0x3e, 0x05,       // 0000 MVI A,5
+            0xfe, 0x05,       // 0002 CPI 5
+            0xca, 0x08, 0x00, // 0004 JZ 0008h
+            0x00,             // 0007 NOP
+            0x76,             // 0008 HLT
+        ];
+        assert_eq!(audited_events(&compare_then_branch), vec![]);
+
+        let increment_in_between: Vec<u8> = vec![
+            0x3e, 0x05,       // 0000 MVI A,5
+            0xfe, 0x05,       // 0002 CPI 5
+            0x0c,             // 0004 INR C
+            0xca, 0x09, 0x00, // 0005 JZ 0009h
+            0x00,             // 0008 NOP
+            0x76,             // 0009 HLT
+        ];
+        assert_eq!(audited_events(&increment_in_between), vec![
+            Event::SuspectFlagRead { flag: 'Z', producer: Some(0x0004), clobbered: Some(0x0002) },
+        ]);
     }
 
     #[test]
-    fn test_capitalize() {
+    fn test_run_to_inside_loop() {
         let mut processor: Processor = make_processor();
-        processor.run_program("tests/capitalize.bin");
+        processor.load_program(&[
+            0x0e, 0x03,       // 0000 MVI C,3
+            0x0d,             // 0002 loop: DCR C
+            0xc2, 0x02, 0x00, // 0003 JNZ loop
+            0x76,             // 0006 HLT
+        ]);
+
+        assert_eq!(processor.run_to(0x0003), RunOutcome::Stepped { pc: 0x0003 });
+        assert_eq!(processor.registers.c, 2);
+        assert_eq!(processor.run_to(0x0003), RunOutcome::Stepped { pc: 0x0003 });
+        assert_eq!(processor.registers.c, 1);
+        assert_eq!(processor.run_to(0x0003), RunOutcome::Stepped { pc: 0x0003 });
+        assert_eq!(processor.run_to(0x0003), RunOutcome::Halted);
+        assert!(processor.breakpoints.is_empty());
+    }
+
+    fn canary_processor(program: &[u8]) -> Processor {
+        let mut processor = ProcessorBuilder::new()
+            .stack_canary(0x2f00..0x2f10, 0xa5)
+            .canary_check_interval(10)
+            .build();
+        processor.load_program(program);
+        return processor;
+    }
 
-        assert_eq!(processor.b, 0x0);
-        assert_eq!(processor.pc, 0xc);
-        assert_eq!(processor.l, 0x34);
-        assert_eq!(processor.memory[0x32], 0x44);
-        assert!(processor.conditions.zero);
-        assert!(processor.conditions.parity);
-        assert!(!processor.conditions.carry);
-        assert!(!processor.conditions.sign);
+    #[test]
+    fn test_canary_catches_mov_m_overrun() {
+        let mut processor = canary_processor(&[
+            0x21, 0xf8, 0x2e, // LXI H,2EF8h
+            0x3e, 0xaa,       // MVI A,0AAh
+            0x0e, 0x20,       // MVI C,20h
+            0x77,             // loop: MOV M,A
+            0x23,             // INX H
+            0x0d,             // DCR C
+            0xc2, 0x07, 0x00, // JNZ loop
+            0x76,             // HLT
+        ]);
+
+        let outcome = processor.run();
+
+        assert_eq!(outcome, RunOutcome::Fault(EmuError::StackCanary { addr: 0x2f00, pc: Some(0x0007) }));
+        assert_eq!(processor.memory.peek(0x2eff), 0xaa);
+        assert_eq!(processor.registers.c, 0x18);
+        assert_eq!(
+            EmuError::StackCanary { addr: 0x2f00, pc: Some(0x0007) }.to_string(),
+            "stack guard band at 0x2f00 overwritten by instruction at 0x0007");
+    }
+
+    #[test]
+    fn test_canary_catches_stax_overrun() {
+        let mut processor = canary_processor(&[
+            0x11, 0xfc, 0x2e, // LXI D,2EFCh
+            0x3e, 0xaa,       // MVI A,0AAh
+            0x0e, 0x20,       // MVI C,20h
+            0x12,             // loop: STAX D
+            0x13,             // INX D
+            0x0d,             // DCR C
+            0xc2, 0x07, 0x00, // JNZ loop
+            0x76,             // HLT
+        ]);
+
+        assert_eq!(processor.run(), RunOutcome::Fault(EmuError::StackCanary { addr: 0x2f00, pc: Some(0x0007) }));
+    }
+
+    #[test]
+    fn test_canary_periodic_scan() {
+        let mut processor = canary_processor(&[0xc3, 0x00, 0x00]); // JMP 0
+        processor.run_with_limit(25);
+        processor.load_at(0x2f03, &[0x00]);
+
+        let outcome = processor.run_with_limit(100);
+
+        assert_eq!(outcome, RunOutcome::Fault(EmuError::StackCanary { addr: 0x2f03, pc: None }));
+        assert_eq!(processor.instruction_count(), 30);
+    }
+
+    #[test]
+    fn test_canary_untouched_program_halts() {
+        let mut processor = canary_processor(&fs::read("tests/memcpy.bin").unwrap());
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert_eq!(processor.memory.peek(0x2f0f), 0xa5);
+    }
+
+    /// Calls RST 3 five times. The guest handler at 0x0018 would set A.
+    const RST_PROGRAM: [u8; 12] = [
+        0x31, 0x00, 0x30, // 0100 LXI SP,3000h
+        0x06, 0x05,       // 0103 MVI B,5
+        0xdf,             // 0105 loop: RST 3
+        0x05,             // 0106 DCR B
+        0xc2, 0x05, 0x01, // 0107 JNZ loop
+        0x76,             // 010a HLT
+        0x00,
+    ];
+
+    fn rst_processor() -> Processor {
+        let mut processor = make_processor();
+        processor.load_at(0x0018, &[0x3e, 0xff, 0xc9]); // MVI A,0FFh; RET
+        processor.load_at(0x0100, &RST_PROGRAM);
+        processor.set_pc(0x0100);
+        return processor;
+    }
+
+    #[test]
+    fn test_vector_hook_emulates_ret() {
+        let calls = Arc::new(Mutex::new(0));
+        let mut processor = rst_processor();
+        let counter = calls.clone();
+        processor.install_vector_hook(3, Box::new(move |_| {
+            *counter.lock().unwrap() += 1;
+            return VectorAction::Ret;
+        }));
+
+        assert_eq!(processor.run_with_limit(100), RunOutcome::Halted);
+
+        assert_eq!(*calls.lock().unwrap(), 5);
+        assert_eq!(processor.registers.a, 0);
+        assert_eq!(processor.registers.sp, 0x3000);
+        assert!(processor.call_stack().is_empty());
+        assert_eq!(processor.instruction_count(), 2 + 5 * 4 + 1);
+    }
+
+    #[test]
+    fn test_vector_hook_falls_through_to_guest() {
+        let calls = Arc::new(Mutex::new(0));
+        let mut processor = rst_processor();
+        let counter = calls.clone();
+        processor.install_vector_hook(3, Box::new(move |processor| {
+            *counter.lock().unwrap() += 1;
+            assert_eq!(processor.pc(), 0x0018);
+            return VectorAction::FallThrough;
+        }));
+
+        assert_eq!(processor.run_with_limit(100), RunOutcome::Halted);
+
+        assert_eq!(*calls.lock().unwrap(), 5);
+        assert_eq!(processor.registers.a, 0xff);
+        assert_eq!(processor.instruction_count(), 2 + 5 * 5 + 1);
+    }
+
+    #[test]
+    fn test_event_log_sequence() {
+        let mut processor = make_processor();
+        processor.load_at(0x0000, &[0xc3, 0x00, 0x01]); // JMP 0100h
+        processor.load_at(0x0008, &[
+            0x32, 0x00, 0x24, // STA 2400h
+            0xfb,             // EI
+            0xc9,             // RET
+        ]);
+        processor.load_at(0x0100, &[
+            0x31, 0x00, 0x20, // 0100 LXI SP,2000h
+            0xfb,             // 0103 EI
+            0x3e, 0x41,       // 0104 MVI A,41h
+            0xd3, 0x01,       // 0106 OUT 1
+            0xd3, 0x02,       // 0108 OUT 2
+            0x76,             // 010a HLT
+            0x76,             // 010b HLT
+        ]);
+        let log = EventLog::new(16);
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        processor.annotate_region("vram", 0x2400..0x4000);
+
+        assert_eq!(processor.run(), RunOutcome::Stuck { pc: 0x010b });
+        processor.request_interrupt(1);
+        assert_eq!(processor.run(), RunOutcome::Stuck { pc: 0x010c });
+
+        let events = log.events();
+        assert_eq!(events.iter().map(|event| event.event.clone()).collect::<Vec<Event>>(), vec![
+            Event::PortWrite { port: 1, value: 0x41 },
+            Event::PortWrite { port: 2, value: 0x41 },
+            Event::InterruptRequested { source: HOST_INTERRUPT_SOURCE, vector: 1 },
+            Event::InterruptDelivered { source: HOST_INTERRUPT_SOURCE, vector: 1 },
+            Event::RegionWrite { region: "vram".to_string(), addr: 0x2400, value: 0x41 },
+        ]);
+        assert_eq!((events[0].pc, events[1].pc, events[4].pc), (0x0106, 0x0108, 0x0008));
+        assert!(events.windows(2).all(|pair| pair[0].instructions <= pair[1].instructions
+            && pair[0].cycles <= pair[1].cycles));
+        assert!(events[3].cycles < events[4].cycles);
+        assert_eq!(processor.pc(), 0x010c);
+        assert_eq!(log.dropped(), 0);
     }
-}
 
+    #[test]
+    fn test_interrupt_waits_for_ei() {
+        let mut processor = make_processor();
+        processor.load_at(0x0000, &[0x31, 0x00, 0x30, 0x00, 0xfb, 0x00, 0x76]); // LXI SP; NOP; EI; NOP; HLT
+        processor.load_at(0x0038, &[0x76]); // HLT
+        processor.request_interrupt(7);
+
+        processor.step();
+        processor.step();
+        assert_eq!(processor.pc(), 0x0004);
+        processor.step();
+        processor.step();
+        assert_eq!(processor.pc(), 0x0006);
+        processor.step();
+
+        assert_eq!(processor.pc(), 0x0038);
+        assert_eq!(processor.call_stack()[0].return_addr, 0x0006);
+        assert!(!processor.interrupt_enabled);
+    }
+
+    /// Installs RST 1 before EI, then patches it afterwards.
+    fn vector_patching_processor(strict: bool) -> Processor {
+        let mut processor: Processor = ProcessorBuilder::new()
+            .strict(strict)
+            .vector_write_check(true)
+            .build();
+        processor.load_at(0x0100, &[
+            0x3e, 0xc9,       // 0100 MVI A,C9h
+            0x32, 0x08, 0x00, // 0102 STA 0008h
+            0xfb,             // 0105 EI
+            0x3e, 0x76,       // 0106 MVI A,76h
+            0x32, 0x09, 0x00, // 0108 STA 0009h
+            0x76,             // 010b HLT
+        ]);
+        processor.set_pc(0x0100);
+        return processor;
+    }
+
+    #[test]
+    fn test_vector_write_after_ei() {
+        let mut processor = vector_patching_processor(false);
+        let log = EventLog::new(16);
+        processor.set_event_sink(Some(Box::new(log.clone())));
+
+        for _ in 0..3 {
+            processor.step();
+        }
+        assert!(log.events().is_empty());
+        assert_eq!(processor.run(), RunOutcome::Stuck { pc: 0x010c });
+
+        let events = log.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, Event::VectorWrite { addr: 0x0009, vector: 1, value: 0x76 });
+        assert_eq!(events[0].pc, 0x0108);
+        assert_eq!(processor.peek(0x0009), 0x76);
+    }
+
+    #[test]
+    fn test_vector_write_faults_in_strict_mode() {
+        let mut processor = vector_patching_processor(true);
+
+        assert_eq!(processor.run(), RunOutcome::Fault(EmuError::VectorWrite { addr: 0x0009, vector: 1, pc: 0x0108 }));
+    }
+
+    /// A handler for RST 2 that re-enables interrupts before its work is
+    /// done, with the same source raised again once it has.
+    fn reentered_processor(builder: ProcessorBuilder) -> Processor {
+        let mut processor: Processor = builder.build();
+        processor.load_at(0x0000, &[
+            0x31, 0x00, 0x20, // 0000 LXI SP,2000h
+            0xfb,             // 0003 EI
+            0x00,             // 0004 NOP
+            0x76,             // 0005 HLT
+        ]);
+        processor.load_at(0x0010, &[
+            0xfb,             // 0010 EI
+            0x00,             // 0011 NOP
+            0xc9,             // 0012 RET
+        ]);
+        processor.raise_interrupt(3, 0xd7); // RST 2
+        for _ in 0..4 {
+            processor.step();
+        }
+        assert_eq!(processor.pc(), 0x0010);
+        processor.raise_interrupt(3, 0xd7);
+        return processor;
+    }
+
+    #[test]
+    fn test_reentered_handler_is_reported() {
+        let mut processor = reentered_processor(ProcessorBuilder::new().reentry_check(true));
+        let log = EventLog::new(16);
+        processor.set_event_sink(Some(Box::new(log.clone())));
+
+        assert_eq!(processor.run(), RunOutcome::Stuck { pc: 0x0006 });
+
+        let reentered: Vec<Event> = log.events().into_iter()
+            .map(|event| event.event)
+            .filter(|event| matches!(event, Event::InterruptReentered { .. }))
+            .collect();
+        assert_eq!(reentered, vec![Event::InterruptReentered { source: 3, first_pc: 0x0005, second_pc: 0x0012 }]);
+        assert!(processor.call_stack().is_empty());
+    }
+
+    #[test]
+    fn test_nesting_limit_faults_in_strict_mode() {
+        let mut processor = reentered_processor(ProcessorBuilder::new().strict(true).max_interrupt_nesting(1));
+
+        assert_eq!(processor.run(), RunOutcome::Fault(EmuError::InterruptNesting { depth: 2, pc: 0x0012 }));
+
+        let mut processor = reentered_processor(ProcessorBuilder::new().max_interrupt_nesting(1));
+        assert_eq!(processor.run(), RunOutcome::Stuck { pc: 0x0006 });
+    }
+
+    /// Bumps HL through the arena at 3000h in 10h steps, `steps` times,
+    /// with the stack at `stack`.
+    #[cfg(feature = "asm")]
+    fn bump_allocator(stack: u16, steps: u8) -> (Processor, EventLog) {
+        let mut processor: Processor = ProcessorBuilder::new().track_allocator(RegisterPair::HL, 0x3000..0x3100).build();
+        let source: String = format!("
+                lxi sp, {}
+                lxi h, 3000h
+                lxi d, 10h
+                mvi b, {}
+        bump:   dad d
+                dcr b
+                jnz bump
+                hlt", stack, steps);
+        processor.load_program(&crate::asm::assemble(&source).unwrap().bytes);
+        let log = EventLog::default();
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        return (processor, log);
+    }
+
+    #[cfg(feature = "asm")]
+    #[test]
+    fn test_allocator_peak() {
+        let (processor, log) = bump_allocator(0xf000, 10);
+
+        assert_eq!(processor.allocator_usage(),
+                   vec![AllocatorUsage { pair: RegisterPair::HL, arena: 0x3000..0x3100, peak: Some(0x30a0) }]);
+        assert!(log.events().is_empty());
+
+        let (processor, log) = bump_allocator(0xf000, 17);
+        assert_eq!(processor.allocator_usage()[0].peak, Some(0x3100));
+        let warnings: Vec<(u16, Event)> = log.events().into_iter().map(|event| (event.pc, event.event)).collect();
+        assert_eq!(warnings, vec![(0x000b, Event::AllocatorOverflow { pair: RegisterPair::HL, pointer: 0x3110, arena_end: 0x3100 })]);
+    }
+
+    #[cfg(feature = "asm")]
+    #[test]
+    fn test_allocator_stack_collision() {
+        let (_, log) = bump_allocator(0x3050, 10);
+
+        let warnings: Vec<(u64, u16, Event)> = log.events().into_iter().map(|event| (event.instructions, event.pc, event.event)).collect();
+        assert_eq!(warnings, vec![(19, 0x000b, Event::AllocatorCollision { pair: RegisterPair::HL, pointer: 0x3060, sp: 0x3050 })]);
+    }
+
+    #[test]
+    fn test_ret_to_pushed_address_is_indirect() {
+        let mut processor: Processor = ProcessorBuilder::new().indirect_flow_tracking(true).build();
+        processor.load_program(&[
+            0x31, 0x00, 0x20, // 0000 LXI SP,2000h
+            0xcd, 0x0a, 0x00, // 0003 CALL 000Ah
+            0x21, 0x0e, 0x00, // 0006 LXI H,000Eh
+            0xe5,             // 0009 PUSH H
+            0xc9,             // 000a RET
+            0x00, 0x00, 0x00, // 000b NOP; NOP; NOP
+            0x76,             // 000e HLT
+        ]);
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        let report: &IndirectFlowReport = processor.indirect_flow_report().unwrap();
+        assert_eq!(report.transfers().iter().collect::<Vec<_>>(), vec![(&(0x000a, 0x000e), &1)]);
+    }
+
+    /// ROM at 1000h-1fffh and a write-only device register at 2000h, with
+    /// `program` in RAM at 0.
+    fn operand_checked_processor(program: &[u8]) -> Processor {
+        let mut processor: Processor = ProcessorBuilder::new().strict(true).operand_check(true).build();
+        processor.declare_region("ROM", 0x1000..0x2000, RegionAccess::ReadOnly);
+        processor.declare_region("shift", 0x2000..0x2001, RegionAccess::WriteOnly);
+        processor.load_program(program);
+        return processor;
+    }
+
+    #[test]
+    fn test_store_into_rom_faults() {
+        let mut processor = operand_checked_processor(&[
+            0x3e, 0x41,       // 0000 MVI A,41h
+            0x32, 0x00, 0x18, // 0002 STA 1800h
+            0x76,             // 0005 HLT
+        ]);
+
+        let error = EmuError::OperandAccess { pc: 0x0002, addr: 0x1800, region: "ROM".to_string(), write: true };
+        assert_eq!(processor.run(), RunOutcome::Fault(error.clone()));
+        let report: &FaultReport = processor.fault_report().unwrap();
+        assert_eq!(report.instruction.text, "sta 1800h");
+        assert_eq!(error.to_string(), "instruction at 0x0002 would write 0x1800 in read-only region ROM");
+        assert_eq!(processor.peek(0x1800), 0x00);
+        assert_eq!(processor.pc(), 0x0002);
+    }
+
+    #[test]
+    fn test_word_access_faults_if_either_byte_is_denied() {
+        // Reading ROM is fine, even across into RAM.
+        let mut processor = operand_checked_processor(&[0x2a, 0xff, 0x0f, 0x76]); // LHLD 0FFFh; HLT
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        // Only the high byte lands in ROM.
+        let mut processor = operand_checked_processor(&[0x22, 0xff, 0x0f, 0x76]); // SHLD 0FFFh; HLT
+        assert_eq!(processor.run(), RunOutcome::Fault(
+            EmuError::OperandAccess { pc: 0x0000, addr: 0x1000, region: "ROM".to_string(), write: true }));
+        assert_eq!(processor.peek(0x0fff), 0x00);
+
+        // Only the high byte is the write-only register.
+        let mut processor = operand_checked_processor(&[0x2a, 0xff, 0x1f, 0x76]); // LHLD 1FFFh; HLT
+        assert_eq!(processor.run(), RunOutcome::Fault(
+            EmuError::OperandAccess { pc: 0x0000, addr: 0x2000, region: "shift".to_string(), write: false }));
+    }
+
+    #[test]
+    fn test_word_access_reaches_the_bus_a_byte_at_a_time() {
+        /// Drops writes to 1000h onwards, as ROM would.
+        #[derive(Debug)]
+        struct RomAbove1000 {
+            recording: RecordingBus,
+        }
+
+        impl MemoryBus for RomAbove1000 {
+            fn read(&mut self, addr: u16) -> u8 {
+                return self.recording.read(addr);
+            }
+
+            fn write(&mut self, addr: u16, value: u8) {
+                self.recording.log.lock().unwrap().push(Access::Write(addr, value));
+                if addr < 0x1000 {
+                    self.recording.memory.write(addr, value);
+                }
+            }
+
+            fn peek(&self, addr: u16) -> u8 {
+                return self.recording.peek(addr);
+            }
+        }
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut memory = FlatMemory::new();
+        memory.load(0, &[0x22, 0xff, 0x0f]); // SHLD 0FFFh
+        memory.load(0x1000, &[0x99]);
+        let recording = RecordingBus { memory, log: log.clone() };
+        let mut processor = ProcessorBuilder::new().memory_bus(Box::new(RomAbove1000 { recording })).build();
+        processor.registers.set_hl(0xbeef);
+
+        processor.step();
+
+        let writes: Vec<Access> = log.lock().unwrap().iter().copied().filter(|access| matches!(access, Access::Write(..))).collect();
+        assert_eq!(writes, vec![Access::Write(0x0fff, 0xef), Access::Write(0x1000, 0xbe)]);
+        assert_eq!((processor.peek(0x0fff), processor.peek(0x1000)), (0xef, 0x99));
+    }
+
+    /// Adds the BCD numbers 19 and 28 with the low digits' carry coming
+    /// from `carry_opcode` under `quirks`, then stores A at 0100h.
+    fn bcd_processor(quirks: QuirkProfile, carry_opcode: u8) -> (Processor, EventLog) {
+        let mut processor: Processor = ProcessorBuilder::new().aux_carry_audit(true).build();
+        processor.set_quirks(quirks);
+        processor.load_program(&[
+            0x3e, 0x19,       // 0000 MVI A,19h
+            0x06, 0x28,       // 0002 MVI B,28h
+            carry_opcode,     // 0004 ADD B or ORA B
+            0x27,             // 0005 DAA
+            0x32, 0x00, 0x01, // 0006 STA 0100h
+            0x27,             // 0009 DAA
+            0x76,             // 000a HLT
+        ]);
+        let log = EventLog::new(16);
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        return (processor, log);
+    }
+
+    #[test]
+    fn test_bcd_addition_trusts_aux_carry() {
+        let (processor, log) = bcd_processor(QuirkProfile::Classic, 0x80); // ADD B
+
+        assert_eq!(processor.peek(0x0100), 0x47);
+        assert!(log.events().is_empty());
+    }
+
+    #[test]
+    fn test_daa_after_unmodeled_aux_carry_is_reported_once() {
+        let (_, log) = bcd_processor(QuirkProfile::Classic, 0xb0); // ORA B
+
+        let events = log.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, Event::UnmodeledAuxCarry { producer: Some(0x0004) });
+        assert_eq!(events[0].pc, 0x0005);
+        assert_eq!(events[0].event.to_string(), "daa read aux carry left stale by the instruction at 0004");
+    }
+
+    #[test]
+    fn test_unmodeled_aux_carry_warning_reaches_the_tracer() {
+        let tracer = RecordingTracer::default();
+        let mut processor: Processor = ProcessorBuilder::new().aux_carry_audit(true).tracer(Box::new(tracer.clone())).build();
+        processor.load_program(&[
+            0x3e, 0x19, // 0000 MVI A,19h
+            0x06, 0x28, // 0002 MVI B,28h
+            0xb0,       // 0004 ORA B
+            0x27,       // 0005 DAA
+            0x27,       // 0006 DAA
+            0x76,       // 0007 HLT
+        ]);
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        let warnings = tracer.warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].pc, &warnings[0].event), (0x0005, &Event::UnmodeledAuxCarry { producer: Some(0x0004) }));
+    }
+
+    #[test]
+    fn test_daa_after_modeled_logical_aux_carry_is_not_reported() {
+        let (_, log) = bcd_processor(QuirkProfile::Intel8080, 0xa0); // ANA B
+
+        assert!(log.events().is_empty());
+    }
+
+    #[test]
+    fn test_interrupt_priority() {
+        let mut processor = make_processor();
+        processor.load_at(0x0000, &[
+            0x31, 0x00, 0x20, // LXI SP,2000h
+            0x21, 0x00, 0x21, // LXI H,2100h
+            0xfb,             // EI
+            0x7f,             // MOV A,A
+            0x76,             // HLT
+        ]);
+        for (vector, mark) in [(2u8, 0x02u8), (3, 0x03)] {
+            // MVI A,mark; MOV M,A; INX H; EI; RET
+            processor.load_at(vector as u16 * 8, &[0x3e, mark, 0x77, 0x23, 0xfb, 0xc9]);
+        }
+        let log = EventLog::new(16);
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        processor.raise_interrupt(3, 0xd7); // RST 2
+        processor.raise_interrupt(0, 0xdf); // RST 3
+
+        for _ in 0..5 {
+            processor.step();
+        }
+        assert_eq!(processor.pc(), 0x0018);
+        assert_eq!(processor.interrupt_controller().highest(), Some((3, 0xd7)));
+        for _ in 0..5 {
+            processor.step();
+        }
+        // Back at HLT: the EI before RET held the second interrupt off.
+        assert_eq!(processor.pc(), 0x0008);
+        assert!(processor.call_stack().is_empty());
+        processor.step();
+        assert_eq!(processor.pc(), 0x0010);
+        assert_eq!(processor.call_stack()[0].return_addr, 0x0008);
+        assert_eq!(processor.run(), RunOutcome::Stuck { pc: 0x0009 });
+
+        assert_eq!((processor.peek(0x2100), processor.peek(0x2101)), (0x03, 0x02));
+        assert!(!processor.interrupt_controller().is_pending());
+        let delivered: Vec<Event> = log.events().into_iter().map(|event| event.event)
+            .filter(|event| matches!(event, Event::InterruptDelivered { .. })).collect();
+        assert_eq!(delivered, vec![
+            Event::InterruptDelivered { source: 0, vector: 3 },
+            Event::InterruptDelivered { source: 3, vector: 2 },
+        ]);
+    }
+
+    #[test]
+    fn test_frame_callback() {
+        let mut processor = make_processor();
+        processor.load_program(&[0xc3, 0x00, 0x00]); // JMP 0, 10 cycles
+        let ends: Arc<Mutex<Vec<(u64, u64)>>> = Arc::new(Mutex::new(Vec::new()));
+        let record = ends.clone();
+        processor.set_frame_callback(100, Some(Box::new(move |frame, processor| {
+            record.lock().unwrap().push((frame, processor.cycle_count()));
+        })));
+
+        processor.run_with_limit(95);
+
+        let ends = ends.lock().unwrap();
+        assert_eq!(ends.len(), 9);
+        assert!(ends.iter().zip(1..).all(|((frame, cycles), n)| *frame == n && *cycles == n * 100));
+    }
+
+    #[test]
+    fn test_metrics_counts() {
+        let mut processor = make_processor();
+        processor.load_at(0x0000, &[
+            0x31, 0x00, 0x20, // 0000 LXI SP,2000h
+            0x3e, 0x41,       // 0003 MVI A,41h
+            0xd3, 0x01,       // 0005 OUT 1
+            0xd3, 0x01,       // 0007 OUT 1
+            0xfb,             // 0009 EI
+            0x7f,             // 000a MOV A,A
+            0x76,             // 000b HLT, after the interrupt returns
+        ]);
+        processor.load_at(0x0010, &[0xd3, 0x02, 0xfb, 0xc9]); // OUT 2; EI; RET
+        processor.add_breakpoint(0x0007);
+
+        assert_eq!(processor.run(), RunOutcome::Breakpoint { pc: 0x0007 });
+        processor.request_interrupt(2);
+        assert_eq!(processor.run(), RunOutcome::Stuck { pc: 0x000c });
+
+        assert_eq!(processor.metrics_snapshot(), Metrics {
+            instructions: 11,
+            cycles: 10 + 7 + 10 + 10 + 4 + 5 + 11 + 10 + 4 + 10 + 7,
+            interrupts_delivered: 1,
+            breakpoint_hits: 1,
+            output_bytes: 3,
+        });
+    }
+
+    #[test]
+    fn test_inspection_handle_never_sees_torn_counter() {
+        let mut processor = make_processor();
+        processor.load_program(&[
+            0x21, 0x00, 0x00, // LXI H,0
+            0x23,             // loop: INX H
+            0x22, 0x00, 0x20, // SHLD 2000h
+            0xc3, 0x03, 0x00, // JMP loop
+        ]);
+        let handle = processor.inspection_handle();
+        processor.set_inspection_interval(7);
+        handle.add_range(0x2000..0x2002);
+        let metrics = processor.metrics_handle();
+
+        let runner = std::thread::spawn(move || processor.run_with_limit(150_000));
+        let mut seen: Vec<u64> = Vec::new();
+        let mut polled: Vec<Metrics> = Vec::new();
+        while seen.len() < 20 && !runner.is_finished() {
+            let snapshot = metrics.snapshot();
+            // Every instruction in this loop takes at least 5 cycles.
+            assert!(snapshot.cycles >= 5 * snapshot.instructions, "{:?}", snapshot);
+            polled.push(snapshot);
+            let view = handle.view();
+            if seen.last().is_none_or(|last| view.instructions > *last) {
+                let Some(bytes) = view.read_range(0x2000..0x2002) else {
+                    continue;
+                };
+                let counter = word::word(bytes[1], bytes[0]);
+                let hl = view.registers.hl();
+                assert!(counter == hl || counter.wrapping_add(1) == hl, "counter {:04x} with HL {:04x}", counter, hl);
+                seen.push(view.instructions);
+            }
+        }
+        let outcome = runner.join().unwrap();
+
+        assert_eq!(outcome, RunOutcome::InstructionLimit { instructions: 150_000 });
+        assert_eq!(handle.instructions(), 150_000);
+        assert!(seen.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(polled.windows(2).all(|pair| pair[0].instructions <= pair[1].instructions
+            && pair[0].cycles <= pair[1].cycles));
+        assert_eq!(metrics.snapshot().instructions, 150_000);
+    }
+
+    /// DAA straight from the Intel manual's description: add 06h if the low
+    /// nibble is above 9 or AC is set, and 60h if A is above 99h or CY is
+    /// set. Returns A, CY, and AC.
+    fn reference_daa(a: u8, carry: bool, aux_carry: bool) -> (u8, bool, bool) {
+        let low: u8 = if (a & 0x0f) > 9 || aux_carry { 0x06 } else { 0 };
+        let high: u8 = if a > 0x99 || carry { 0x60 } else { 0 };
+        let result: u8 = a.wrapping_add(low | high);
+        return (result, carry || a > 0x99, (a & 0x0f) + low > 0x0f);
+    }
+
+    #[test]
+    fn test_daa_exhaustive() {
+        let mut processor = make_processor();
+        processor.load_program(&[0x27]); // DAA
+        for a in 0..=255u8 {
+            for (carry, aux_carry) in [(false, false), (false, true), (true, false), (true, true)] {
+                let mut registers = Registers { a, ..Registers::default() };
+                registers.flags.carry = carry;
+                registers.flags.aux_carry = aux_carry;
+                processor.set_registers(registers);
+
+                processor.step();
+
+                let (result, expected_carry, expected_aux) = reference_daa(a, carry, aux_carry);
+                let flags = processor.registers.flags;
+                let input = format!("A={:02x} CY={} AC={}", a, carry as u8, aux_carry as u8);
+                assert_eq!(processor.registers.a, result, "A after DAA with {}", input);
+                assert_eq!(flags.carry, expected_carry, "CY after DAA with {}", input);
+                assert_eq!(flags.aux_carry, expected_aux, "AC after DAA with {}", input);
+                assert_eq!(flags.zero, result == 0, "Z after DAA with {}", input);
+                assert_eq!(flags.sign, result & 0x80 != 0, "S after DAA with {}", input);
+                assert_eq!(flags.parity, result.count_ones() % 2 == 0, "P after DAA with {}", input);
+            }
+        }
+    }
+
+    /// RLC, RRC, RAL and RAR straight from the Intel manual: rotate A one
+    /// bit, through CY for RAL and RAR. Returns A and CY.
+    fn reference_rotate(opcode: u8, a: u8, carry: bool) -> (u8, bool) {
+        return match opcode {
+            0x07 => (a.rotate_left(1), a & 0x80 != 0),
+            0x0f => (a.rotate_right(1), a & 0x01 != 0),
+            0x17 => ((a << 1) | carry as u8, a & 0x80 != 0),
+            _ => ((a >> 1) | ((carry as u8) << 7), a & 0x01 != 0),
+        };
+    }
+
+    #[test]
+    fn test_rotates_exhaustive() {
+        let mut processor = make_processor();
+        for (opcode, name) in [(0x07, "RLC"), (0x0f, "RRC"), (0x17, "RAL"), (0x1f, "RAR")] {
+            processor.load_program(&[opcode]);
+            for a in 0..=255u8 {
+                for carry in [false, true] {
+                    let mut registers = Registers { a, ..Registers::default() };
+                    registers.flags.carry = carry;
+                    processor.set_registers(registers);
+
+                    processor.step();
+
+                    let (result, expected_carry) = reference_rotate(opcode, a, carry);
+                    let input = format!("A={:02x} CY={}", a, carry as u8);
+                    assert_eq!(processor.registers.a, result, "A after {} with {}", name, input);
+                    assert_eq!(processor.registers.flags.carry, expected_carry, "CY after {} with {}", name, input);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_additions_set_aux_carry() {
+        let mut processor = make_processor();
+        processor.load_program(&[
+            0x3e, 0x09, // MVI A,09h
+            0xc6, 0x09, // ADI 09h: 9 + 9 carries out of bit 3
+            0x27,       // DAA
+            0xc6, 0x01, // ADI 01h
+        ]);
+        processor.step();
+        processor.step();
+        assert!(processor.registers().flags.aux_carry);
+        processor.step();
+        assert_eq!(processor.registers().a, 0x18);
+        processor.step();
+        assert!(!processor.registers().flags.aux_carry);
+    }
+
+    #[test]
+    fn test_write_history_across_routines() {
+        let mut processor = make_processor();
+        processor.load_at(0x0000, &[
+            0x31, 0x00, 0x30, // 0000 LXI SP,3000h
+            0xcd, 0x10, 0x00, // 0003 CALL 0010h
+            0xcd, 0x20, 0x00, // 0006 CALL 0020h
+            0xcd, 0x30, 0x00, // 0009 CALL 0030h
+            0x76,             // 000c HLT
+        ]);
+        processor.load_at(0x0010, &[0x3e, 0x01, 0x32, 0x3a, 0x2f, 0xc9]);       // MVI A,1; STA 2F3Ah; RET
+        processor.load_at(0x0020, &[0x21, 0x3a, 0x2f, 0x36, 0x02, 0xc9]);       // LXI H,2F3Ah; MVI M,2; RET
+        processor.load_at(0x0030, &[0x3e, 0x03, 0x11, 0x3a, 0x2f, 0x12, 0xc9]); // MVI A,3; LXI D,2F3Ah; STAX D; RET
+        processor.set_write_journal(true);
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        assert_eq!(processor.write_history(0x2f3a), vec![
+            WriteRecord { addr: 0x2f3a, old: 0, new: 1, pc: 0x0012, instruction: 3 },
+            WriteRecord { addr: 0x2f3a, old: 1, new: 2, pc: 0x0023, instruction: 7 },
+            WriteRecord { addr: 0x2f3a, old: 2, new: 3, pc: 0x0035, instruction: 12 },
+        ]);
+        assert!(processor.write_history(0x2f3b).is_empty());
+    }
+
+    /// Records the full port value of every IN and OUT.
+    #[derive(Debug, Clone, Default)]
+    struct PortRecorder {
+        accesses: Arc<Mutex<Vec<(&'static str, u16)>>>,
+    }
+
+    impl IoDevice for PortRecorder {
+        fn read(&mut self, port: u16, _ctx: &IoContext) -> u8 {
+            self.accesses.lock().unwrap().push(("in", port));
+            return 0x5a;
+        }
+
+        fn write(&mut self, port: u16, _value: u8, _ctx: &IoContext) {
+            self.accesses.lock().unwrap().push(("out", port));
+        }
+    }
+
+    fn port_accesses(addressing: PortAddressing) -> (Vec<(&'static str, u16)>, u8) {
+        let recorder = PortRecorder::default();
+        let mut processor = ProcessorBuilder::new()
+            .port_addressing(addressing)
+            .device(&[0x12], Box::new(recorder.clone()))
+            .build();
+        processor.load_program(&[0xdb, 0x12, 0xd3, 0x12, 0x76]); // IN 12h; OUT 12h; HLT
+
+        processor.run();
+
+        let accesses = recorder.accesses.lock().unwrap().clone();
+        return (accesses, processor.registers.a);
+    }
+
+    #[test]
+    fn test_pop_push_psw_normalizes_flag_byte() {
+        for (popped, pushed) in [(0x00, 0x02), (0xff, 0xd7)] {
+            let mut processor = make_processor();
+            processor.load_program(&[
+                0x31, 0x00, 0x10, // LXI SP,1000h
+                0xf1,             // POP PSW
+                0xf5,             // PUSH PSW
+                0x76,             // HLT
+            ]);
+            processor.load_at(0x1000, &[popped, 0x5a]);
+
+            processor.run();
+
+            assert_eq!((processor.peek(0x1000), processor.peek(0x1001)), (pushed, 0x5a), "popped {:02x}", popped);
+        }
+    }
+
+    #[test]
+    fn test_load_instructions() {
+        use crate::instruction::{Reg, RegPair};
+        let mut processor = make_processor();
+        let program: Vec<Instruction> = vec![
+            Instruction::Lxi(RegPair::H, 0x0200),
+            Instruction::Mvi(Reg::M, 0x11),
+            Instruction::Mov(Reg::A, Reg::M),
+            Instruction::Adi(0x22),
+            Instruction::Hlt,
+        ];
+
+        assert_eq!(processor.load_instructions(&program, 0x0100), Ok(0x0109));
+        processor.set_pc(0x0100);
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert_eq!(processor.registers.a, 0x33);
+
+        assert_eq!(processor.load_instructions(&[Instruction::Nop, Instruction::Rst(9)], 0),
+                   Err(EmuError::InvalidOperand { index: 1, reason: "rst vector must be 0 to 7" }));
+        assert_eq!(processor.load_instructions(&[Instruction::Jmp(0)], 0xfffe),
+                   Err(EmuError::ProgramTooLarge { origin: 0xfffe, len: 3 }));
+        assert_eq!(processor.load_instructions(&[Instruction::Jmp(0)], 0xfffd), Ok(0x0000));
+    }
+
+    #[test]
+    fn test_port_addressing_modes() {
+        assert_eq!(port_accesses(PortAddressing::Standard), (vec![("in", 0x0012), ("out", 0x0012)], 0x5a));
+        assert_eq!(port_accesses(PortAddressing::Duplicated), (vec![("in", 0x1212), ("out", 0x1212)], 0x5a));
+    }
+}
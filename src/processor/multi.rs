@@ -0,0 +1,306 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use super::{EmuError, FlatMemory, MemoryBus, Processor};
+
+/// Cycles each processor runs per turn unless `MultiCpu::set_quantum` says
+/// otherwise.
+pub const DEFAULT_QUANTUM: u64 = 1000;
+
+/// A block of memory several processors see at the same addresses. Each
+/// `bus` made from it has private memory everywhere else.
+#[derive(Debug, Clone)]
+pub struct SharedMemory {
+    range: Range<u16>,
+    bytes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SharedMemory {
+    pub fn new(range: Range<u16>) -> SharedMemory {
+        let len: usize = range.len();
+        return SharedMemory { range, bytes: Arc::new(Mutex::new(vec![0; len])) };
+    }
+
+    /// A bus for one more processor, for `ProcessorBuilder::memory_bus`.
+    pub fn bus(&self) -> SharedBus {
+        return SharedBus { private: FlatMemory::new(), shared: self.clone() };
+    }
+
+    pub fn range(&self) -> Range<u16> {
+        return self.range.clone();
+    }
+
+    /// The shared byte at `addr`, or None outside the range.
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        if !self.range.contains(&addr) {
+            return None;
+        }
+        return Some(self.bytes.lock().unwrap()[(addr - self.range.start) as usize]);
+    }
+
+    fn write(&self, addr: u16, value: u8) {
+        self.bytes.lock().unwrap()[(addr - self.range.start) as usize] = value;
+    }
+}
+
+/// One processor's view of a `SharedMemory`.
+#[derive(Debug)]
+pub struct SharedBus {
+    private: FlatMemory,
+    shared: SharedMemory,
+}
+
+impl MemoryBus for SharedBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        return self.peek(addr);
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match self.shared.range.contains(&addr) {
+            true => self.shared.write(addr, value),
+            false => self.private.write(addr, value),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        return self.shared.peek(addr).unwrap_or_else(|| self.private.peek(addr));
+    }
+
+    /// Private memory only; the shared block is behind a lock.
+    fn slice(&self, range: Range<u16>) -> Option<&[u8]> {
+        if range.start < self.shared.range.end && self.shared.range.start < range.end {
+            return None;
+        }
+        return self.private.slice(range);
+    }
+}
+
+/// Why `MultiCpu::run` returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultiOutcome {
+    /// Every processor is halted with no interrupt pending.
+    AllHalted,
+    /// The `run_until` condition held.
+    Done,
+    /// `cycles` more cycles passed without anything else stopping the run.
+    CycleLimit { cycles: u64 },
+    /// The processor at `cpu` faulted. Its `fault_report` has the details.
+    Fault { cpu: usize, error: EmuError },
+}
+
+/// Runs several processors in turn, `quantum` cycles at a time, so that no
+/// two of their clocks are ever more than a quantum apart. They share
+/// nothing but what their buses share, usually a `SharedMemory`.
+#[derive(Debug)]
+pub struct MultiCpu {
+    cpus: Vec<Processor>,
+    /// Each processor's cycle count when it was added.
+    started: Vec<u64>,
+    quantum: u64,
+    cycles: u64,
+}
+
+impl Default for MultiCpu {
+    fn default() -> MultiCpu {
+        return MultiCpu::new();
+    }
+}
+
+impl MultiCpu {
+    pub fn new() -> MultiCpu {
+        return MultiCpu { cpus: Vec::new(), started: Vec::new(), quantum: DEFAULT_QUANTUM, cycles: 0 };
+    }
+
+    /// Smaller quanta keep the processors closer in step at the cost of
+    /// switching between them more often. At least 1.
+    pub fn set_quantum(&mut self, cycles: u64) {
+        self.quantum = cycles.max(1);
+    }
+
+    /// Adds `processor`, which joins from the scheduler's current time,
+    /// and returns its index.
+    pub fn add(&mut self, processor: Processor) -> usize {
+        self.started.push(processor.cycle_count().wrapping_sub(self.cycles));
+        self.cpus.push(processor);
+        return self.cpus.len() - 1;
+    }
+
+    pub fn cpus(&self) -> &[Processor] {
+        return &self.cpus;
+    }
+
+    pub fn cpu(&self, index: usize) -> &Processor {
+        return &self.cpus[index];
+    }
+
+    pub fn cpu_mut(&mut self, index: usize) -> &mut Processor {
+        return &mut self.cpus[index];
+    }
+
+    /// Cycles the scheduler has run since it was created.
+    pub fn cycles(&self) -> u64 {
+        return self.cycles;
+    }
+
+    /// Runs until every processor halts, one faults, or `max_cycles` pass.
+    pub fn run(&mut self, max_cycles: u64) -> MultiOutcome {
+        return self.run_until(max_cycles, |_| false);
+    }
+
+    /// Runs as `run` does, also stopping once `done` holds. It is checked
+    /// between quanta, so the processors may run up to a quantum past the
+    /// point where it first became true.
+    pub fn run_until(&mut self, max_cycles: u64, mut done: impl FnMut(&[Processor]) -> bool) -> MultiOutcome {
+        let limit: u64 = self.cycles + max_cycles;
+        loop {
+            if done(&self.cpus) {
+                return MultiOutcome::Done;
+            }
+            if self.cpus.iter().all(|cpu| cpu.halt && !cpu.interrupt_pending()) {
+                return MultiOutcome::AllHalted;
+            }
+            if self.cycles >= limit {
+                return MultiOutcome::CycleLimit { cycles: max_cycles };
+            }
+            self.cycles = (self.cycles + self.quantum).min(limit);
+            for (index, cpu) in self.cpus.iter_mut().enumerate() {
+                if let Some(error) = cpu.run_to_cycle(self.started[index] + self.cycles) {
+                    return MultiOutcome::Fault { cpu: index, error };
+                }
+            }
+        }
+    }
+}
+
+impl Processor {
+    /// Steps until the cycle count reaches `target`, finishing whatever
+    /// instruction crosses it. A halted processor's clock keeps going while
+    /// it waits for an interrupt, as the chip's does.
+    fn run_to_cycle(&mut self, target: u64) -> Option<EmuError> {
+        while self.cycles < target {
+            if self.halt && !self.interrupt_pending() {
+                self.skip_idle(target);
+                continue;
+            }
+            self.step();
+            if let Some(fault) = self.fault.take() {
+                self.fault_outcome(fault.clone(), self.instruction_pc);
+                return Some(fault);
+            }
+        }
+        return None;
+    }
+}
+
+#[cfg(all(test, feature = "asm"))]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::processor::ProcessorBuilder;
+
+    const PRODUCED: u8 = 100;
+
+    /// Writes 1 to 100 into a 16-byte ring at 8010h. The head index is at
+    /// 8000h and the tail at 8001h.
+    const PRODUCER: &str = "
+            mvi b, 1
+    next:   lda 8000h
+            mov c, a
+            inr a
+            ani 0fh
+            mov d, a
+    full:   lda 8001h
+            cmp d
+            jz full
+            mvi a, 10h
+            add c
+            mov l, a
+            mvi h, 80h
+            mov m, b
+            mov a, d
+            sta 8000h
+            inr b
+            mov a, b
+            cpi 101
+            jnz next
+            hlt";
+
+    /// Copies 100 bytes out of the ring to 3000h.
+    const CONSUMER: &str = "
+            lxi d, 3000h
+            mvi b, 100
+    next:   lda 8001h
+            mov c, a
+    empty:  lda 8000h
+            cmp c
+            jz empty
+            mvi a, 10h
+            add c
+            mov l, a
+            mvi h, 80h
+            mov a, m
+            stax d
+            inx d
+            mov a, c
+            inr a
+            ani 0fh
+            sta 8001h
+            dcr b
+            jnz next
+            hlt";
+
+    fn cpu(shared: &SharedMemory, source: &str) -> Processor {
+        let mut processor = ProcessorBuilder::new().memory_bus(Box::new(shared.bus())).build();
+        processor.load_program(&asm::assemble(source).unwrap().bytes);
+        return processor;
+    }
+
+    #[test]
+    fn test_consumer_sees_every_byte_once() {
+        for quantum in [1, 7, 50, 1000] {
+            let shared = SharedMemory::new(0x8000..0x8100);
+            let mut system = MultiCpu::new();
+            system.set_quantum(quantum);
+            system.add(cpu(&shared, PRODUCER));
+            let consumer: usize = system.add(cpu(&shared, CONSUMER));
+
+            assert_eq!(system.run(1_000_000), MultiOutcome::AllHalted, "quantum {}", quantum);
+
+            let received: Vec<u8> = (0x3000..0x3000 + PRODUCED as u16 + 1).map(|addr| system.cpu(consumer).peek(addr)).collect();
+            assert_eq!(received[..PRODUCED as usize], (1..=PRODUCED).collect::<Vec<u8>>()[..], "quantum {}", quantum);
+            assert_eq!(received[PRODUCED as usize], 0, "quantum {}", quantum);
+        }
+    }
+
+    #[test]
+    fn test_clocks_stay_within_a_quantum() {
+        let shared = SharedMemory::new(0x8000..0x8001);
+        let mut system = MultiCpu::new();
+        system.set_quantum(100);
+        system.add(cpu(&shared, "spin: jmp spin"));
+        system.add(cpu(&shared, "hlt"));
+
+        let mut spread: u64 = 0;
+        let outcome = system.run_until(10_000, |cpus| {
+            spread = spread.max(cpus[0].cycle_count().abs_diff(cpus[1].cycle_count()));
+            return false;
+        });
+
+        assert_eq!(outcome, MultiOutcome::CycleLimit { cycles: 10_000 });
+        assert_eq!((system.cpu(0).cycle_count(), system.cpu(1).cycle_count()), (10_000, 10_000));
+        assert!(spread <= 100, "{}", spread);
+    }
+
+    #[test]
+    fn test_shared_block_has_no_memory_view() {
+        let shared = SharedMemory::new(0x8000..0x8100);
+        let mut processor = cpu(&shared, "mvi a, 5\nsta 7fffh\nsta 8000h\nhlt");
+        processor.run();
+
+        assert_eq!(processor.memory_view(0x7ff0..0x8000).unwrap()[0x0f], 5);
+        let error = processor.memory_view(0x7ff0..0x8001).unwrap_err();
+        assert_eq!(error.to_string(), "7ff0-8000 is not plain RAM; read it with peek");
+        assert!(processor.memory_view(0x80ff..0x8100).is_err());
+        assert!(processor.memory_view(0x8100..0x8200).is_ok());
+    }
+}
@@ -0,0 +1,65 @@
+/// A handler that has been entered and not returned from.
+#[derive(Debug, Clone, Copy)]
+struct Running {
+    source: u8,
+    /// The PC the interrupt was taken at, which the handler returns to.
+    taken_at: u16,
+    /// Shadow call stack depth with the handler's frame on it.
+    depth: usize,
+}
+
+/// Follows which interrupt handlers are running, innermost last, by their
+/// frames on the shadow call stack.
+#[derive(Debug, Clone, Default)]
+pub struct HandlerNesting {
+    running: Vec<Running>,
+}
+
+impl HandlerNesting {
+    /// Where the interrupt was taken whose handler for `source` is still
+    /// running, if one is.
+    pub fn running(&self, source: u8) -> Option<u16> {
+        return self.running.iter().rev().find(|running| running.source == source).map(|running| running.taken_at);
+    }
+
+    /// Handlers running inside one another.
+    pub fn depth(&self) -> usize {
+        return self.running.len();
+    }
+
+    /// `source`'s interrupt was taken at `taken_at`, pushing a frame that
+    /// made the shadow call stack `depth` deep.
+    pub fn entered(&mut self, source: u8, taken_at: u16, depth: usize) {
+        self.running.push(Running { source, taken_at, depth });
+    }
+
+    /// Called after each instruction with the shadow call stack's depth.
+    pub fn returned(&mut self, depth: usize) {
+        while self.running.last().is_some_and(|running| running.depth > depth) {
+            self.running.pop();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.running.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handlers_leave_with_their_frames() {
+        let mut nesting = HandlerNesting::default();
+        nesting.entered(3, 0x0104, 1);
+        nesting.entered(0, 0x0011, 2);
+        nesting.entered(3, 0x0021, 3);
+
+        assert_eq!((nesting.depth(), nesting.running(3)), (3, Some(0x0021)));
+        nesting.returned(2);
+        assert_eq!(nesting.running(3), Some(0x0104));
+        nesting.returned(0);
+        assert_eq!((nesting.depth(), nesting.running(3)), (0, None));
+    }
+}
@@ -0,0 +1,35 @@
+use super::EmuError;
+
+/// Why a call into one of the `run_*` methods returned control to the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The program executed HLT with interrupts disabled.
+    Halted,
+    /// The program executed HLT with interrupts enabled and nothing that
+    /// could raise one; see `HaltState::Stuck`. `pc` is the address after
+    /// the HLT.
+    Stuck { pc: u16 },
+    /// Execution reached an address with a breakpoint set. The instruction
+    /// at `pc` has not been executed yet.
+    Breakpoint { pc: u16 },
+    /// The instruction at `pc` wrote to the watched address `addr`. The
+    /// instruction has completed.
+    Watchpoint { addr: u16, pc: u16 },
+    /// The instruction at `pc` stored `value` at `addr`, which is what
+    /// `run_until_mem` was waiting for. The instruction has completed.
+    MemoryValue { addr: u16, value: u8, pc: u16 },
+    /// A `step_over`, `step_out` or `run_to` got where it was going. The
+    /// instruction at `pc` has not been executed yet.
+    Stepped { pc: u16 },
+    /// The wall-clock deadline passed before the program halted.
+    /// `instructions` is the processor's total instruction count when the
+    /// deadline was noticed.
+    DeadlineExceeded { instructions: u64 },
+    /// The run executed its maximum number of instructions without halting,
+    /// or sat at a HLT waiting on devices for as many cycles as that many
+    /// instructions could take. `instructions` is the processor's total
+    /// instruction count.
+    InstructionLimit { instructions: u64 },
+    /// A diagnostic caught a guest bug and stopped the run.
+    Fault(EmuError),
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+
+use super::Processor;
+
+/// How the interpreter behaves where 8080-family chips, and the emulators
+/// programs were tested on, disagree. Running a program under each is a way
+/// to find out which one it expects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum QuirkProfile {
+    /// What this interpreter has always done: logical operations leave the
+    /// aux carry flag as it was.
+    #[default]
+    Classic,
+    /// The 8080: ANA and ANI set AC to bit 3 of the two operands ORed
+    /// together, and XRA, XRI, ORA and ORI clear it.
+    Intel8080,
+    /// The 8085: ANA and ANI set AC, and XRA, XRI, ORA and ORI clear it.
+    Intel8085,
+}
+
+impl QuirkProfile {
+    pub const ALL: [QuirkProfile; 3] = [QuirkProfile::Classic, QuirkProfile::Intel8080, QuirkProfile::Intel8085];
+
+    pub fn name(self) -> &'static str {
+        return match self {
+            QuirkProfile::Classic => "classic",
+            QuirkProfile::Intel8080 => "i8080",
+            QuirkProfile::Intel8085 => "i8085",
+        };
+    }
+
+    pub fn parse(name: &str) -> Option<QuirkProfile> {
+        return QuirkProfile::ALL.into_iter().find(|profile| profile.name() == name);
+    }
+
+    /// The aux carry flag after an AND (`and`) or an OR or XOR of `left`
+    /// and `right`, or `None` to leave it alone.
+    fn logical_aux_carry(self, and: bool, left: u8, right: u8) -> Option<bool> {
+        return match self {
+            QuirkProfile::Classic => None,
+            QuirkProfile::Intel8080 => Some(and && (left | right) & 0x08 != 0),
+            QuirkProfile::Intel8085 => Some(and),
+        };
+    }
+}
+
+impl fmt::Display for QuirkProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f.pad(self.name());
+    }
+}
+
+impl Processor {
+    /// Switches to `profile`, which takes effect from the next instruction.
+    pub fn set_quirks(&mut self, profile: QuirkProfile) {
+        self.quirks = profile;
+    }
+
+    pub fn quirks(&self) -> QuirkProfile {
+        return self.quirks;
+    }
+
+    /// Sets the aux carry flag after a logical operation as the quirk
+    /// profile says.
+    pub(super) fn set_logical_aux_carry(&mut self, and: bool, left: u8, right: u8) {
+        if let Some(aux_carry) = self.quirks.logical_aux_carry(and, left, right) {
+            self.registers.flags.aux_carry = aux_carry;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::make_processor;
+
+    /// AC after running `program` from A = `a` with AC set.
+    fn aux_carry_after(profile: QuirkProfile, a: u8, program: &[u8]) -> bool {
+        let mut processor: Processor = make_processor();
+        processor.set_quirks(profile);
+        processor.load_program(program);
+        let mut registers = processor.registers();
+        registers.a = a;
+        registers.flags.aux_carry = true;
+        processor.set_registers(registers);
+        processor.step();
+        return processor.registers().flags.aux_carry;
+    }
+
+    #[test]
+    fn test_logical_aux_carry_by_profile() {
+        let cases: [(u8, &[u8]); 4] = [
+            (0x0c, &[0xe6, 0x03]), // ANI 03h: 0ch | 03h has bit 3 set
+            (0x00, &[0xe6, 0x03]), // 00h | 03h does not
+            (0x0c, &[0xb7]),       // ORA A
+            (0x0c, &[0xee, 0x01]), // XRI 01h
+        ];
+        let expected: [(QuirkProfile, [bool; 4]); 3] = [
+            (QuirkProfile::Classic, [true, true, true, true]),
+            (QuirkProfile::Intel8080, [true, false, false, false]),
+            (QuirkProfile::Intel8085, [true, true, false, false]),
+        ];
+        for (profile, flags) in expected {
+            assert_eq!(cases.map(|(a, program)| aux_carry_after(profile, a, program)), flags, "{}", profile);
+        }
+    }
+
+    #[test]
+    fn test_names_round_trip() {
+        for profile in QuirkProfile::ALL {
+            assert_eq!(QuirkProfile::parse(&profile.to_string()), Some(profile));
+        }
+        assert_eq!(QuirkProfile::parse("z80"), None);
+    }
+}
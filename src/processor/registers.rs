@@ -0,0 +1,236 @@
+use std::fmt;
+
+#[cfg(feature = "serde-state")]
+use serde::{Deserialize, Serialize};
+
+use crate::word::{hi, lo, word};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde-state", derive(Serialize, Deserialize))]
+pub struct ConditionBits {
+    pub carry: bool, // set if value is carried out of the highest order bit
+    pub aux_carry: bool, // carry out of bit 3 from additions and DAA; ANA, XRA and ORA set it as the quirk profile says
+    pub sign: bool, // set to 1 when bit 7 is set
+    pub zero: bool, // set when result is equal to 0
+    pub parity: bool // set when result is even
+}
+
+/// The PSW flag byte bit that reads 1 on every 8080.
+pub const PSW_ALWAYS_SET: u8 = 0b0000_0010;
+/// The PSW flag byte bits that read 0 on every 8080.
+pub const PSW_ALWAYS_CLEAR: u8 = 0b0010_1000;
+
+impl ConditionBits {
+    /// The flags a POP PSW of `byte` leaves. Bits 1, 3 and 5 are not
+    /// flags, so whatever the guest put there is dropped.
+    pub fn from_psw_byte(byte: u8) -> ConditionBits {
+        return ConditionBits {
+            carry: (byte & 0b1) != 0,
+            parity: (byte & 0b100) != 0,
+            aux_carry: (byte & 0b10000) != 0,
+            zero: (byte & 0b1000000) != 0,
+            sign: (byte & 0b10000000) != 0,
+        };
+    }
+
+    /// The flag byte PUSH PSW stores, with bit 1 set and bits 3 and 5
+    /// clear as on hardware.
+    pub fn to_psw_byte(&self) -> u8 {
+        return self.convert_to_flags() | PSW_ALWAYS_SET;
+    }
+
+    pub fn set_flags(&mut self, byte: u8) {
+        *self = ConditionBits::from_psw_byte(byte);
+    }
+
+    /// The flags in their PSW positions with the constant bits all clear;
+    /// see `to_psw_byte` for the byte the guest sees.
+    pub fn convert_to_flags(&self) -> u8 {
+        let mut ret: u8 = 0b0;
+        if self.carry { ret |= 0b1};
+        if self.parity { ret |= 0b100 };
+        if self.aux_carry { ret |= 0b10000 };
+        if self.zero { ret |= 0b1000000 };
+        if self.sign { ret |= 0b10000000};
+        return ret;
+    }
+}
+
+/// A register pair a guest may use by convention, such as an allocator's
+/// pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde-state", derive(Serialize, Deserialize))]
+pub enum RegisterPair {
+    BC,
+    DE,
+    HL,
+}
+
+impl RegisterPair {
+    /// Parses `bc`, `de` or `hl` in either case.
+    pub fn parse(name: &str) -> Option<RegisterPair> {
+        return match name.to_ascii_lowercase().as_str() {
+            "bc" => Some(RegisterPair::BC),
+            "de" => Some(RegisterPair::DE),
+            "hl" => Some(RegisterPair::HL),
+            _ => None,
+        };
+    }
+
+    /// Its opcode encoding, as `Registers::pair` takes it.
+    pub(crate) fn index(self) -> u8 {
+        return match self {
+            RegisterPair::BC => 0,
+            RegisterPair::DE => 1,
+            RegisterPair::HL => 2,
+        };
+    }
+}
+
+impl fmt::Display for RegisterPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{:?}", self);
+    }
+}
+
+/// The architectural registers: everything but memory and the counters.
+/// Cheap to copy, so features that only need register state can take one
+/// of these instead of a full `Snapshot`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde-state", derive(Serialize, Deserialize))]
+pub struct Registers {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub flags: ConditionBits,
+}
+
+impl Registers {
+    pub fn bc(&self) -> u16 {
+        return word(self.b, self.c);
+    }
+
+    pub fn de(&self) -> u16 {
+        return word(self.d, self.e);
+    }
+
+    pub fn hl(&self) -> u16 {
+        return word(self.h, self.l);
+    }
+
+    pub fn set_bc(&mut self, value: u16) {
+        (self.b, self.c) = (hi(value), lo(value));
+    }
+
+    pub fn set_de(&mut self, value: u16) {
+        (self.d, self.e) = (hi(value), lo(value));
+    }
+
+    pub fn set_hl(&mut self, value: u16) {
+        (self.h, self.l) = (hi(value), lo(value));
+    }
+
+    pub fn set_sp(&mut self, value: u16) {
+        self.sp = value;
+    }
+
+    /// A register pair by its opcode encoding: 0 = BC, 1 = DE, 2 = HL,
+    /// 3 = SP.
+    pub(crate) fn pair(&self, index: u8) -> u16 {
+        return match index {
+            0 => self.bc(),
+            1 => self.de(),
+            2 => self.hl(),
+            3 => self.sp,
+            _ => 0,
+        };
+    }
+
+    /// Sets a register pair by its opcode encoding, for instruction
+    /// semantics. Arithmetic on pairs wraps at 16 bits on the 8080, so
+    /// callers compute `value` with `wrapping_add` or `wrapping_sub`.
+    pub(crate) fn set_pair_wrapping(&mut self, index: u8, value: u16) {
+        match index {
+            0 => self.set_bc(value),
+            1 => self.set_de(value),
+            2 => self.set_hl(value),
+            3 => self.set_sp(value),
+            _ => (),
+        }
+    }
+}
+
+impl fmt::Display for Registers {
+    /// e.g. `A=00 B=04 C=03 D=04 E=00 H=20 L=18 SP=0000 PC=000f -Z---`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |set: bool, name: char| if set { name } else { '-' };
+        return write!(f, "A={:02x} B={:02x} C={:02x} D={:02x} E={:02x} H={:02x} L={:02x} SP={:04x} PC={:04x} {}{}{}{}{}",
+            self.a, self.b, self.c, self.d, self.e, self.h, self.l, self.sp, self.pc,
+            flag(self.flags.sign, 'S'), flag(self.flags.zero, 'Z'), flag(self.flags.aux_carry, 'A'),
+            flag(self.flags.parity, 'P'), flag(self.flags.carry, 'C'));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_pairs() {
+        let mut registers = Registers::default();
+
+        registers.set_pair_wrapping(0, 0x1234);
+        registers.set_de(0xabcd);
+        registers.set_pair_wrapping(2, 0x2040);
+        registers.set_sp(0x9fff);
+
+        assert_eq!((registers.b, registers.c), (0x12, 0x34));
+        assert_eq!((registers.d, registers.e), (0xab, 0xcd));
+        assert_eq!(registers.hl(), 0x2040);
+        assert_eq!(registers.pair(1), 0xabcd);
+        assert_eq!(registers.pair(3), 0x9fff);
+    }
+
+    #[test]
+    fn test_equality_and_hash() {
+        let mut first = Registers::default();
+        first.set_hl(0x2040);
+        let mut second = first;
+        assert_eq!(first, second);
+
+        second.flags.zero = true;
+        assert_ne!(first, second);
+
+        let mut seen: HashSet<Registers> = HashSet::new();
+        seen.insert(first);
+        assert!(seen.contains(&first));
+        assert!(!seen.contains(&second));
+        seen.insert(second);
+        seen.insert(first);
+        assert_eq!(seen.len(), 2);
+    }
+
+    #[test]
+    fn test_flags_round_trip_through_psw() {
+        let mut flags = ConditionBits::default();
+
+        flags.set_flags(0b1101_0101);
+
+        assert!(flags.sign && flags.zero && flags.aux_carry && flags.parity && flags.carry);
+        assert_eq!(flags.convert_to_flags(), 0b1101_0101);
+    }
+
+    #[test]
+    fn test_psw_byte_is_normalized() {
+        assert_eq!(ConditionBits::from_psw_byte(0x00).to_psw_byte(), 0x02);
+        assert_eq!(ConditionBits::from_psw_byte(0xff).to_psw_byte(), 0xd7);
+        assert_eq!(ConditionBits::from_psw_byte(PSW_ALWAYS_CLEAR), ConditionBits::default());
+    }
+}
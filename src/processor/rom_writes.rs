@@ -0,0 +1,141 @@
+use super::{EmuError, Event, Processor};
+
+/// What a guest write to a region declared `RegionAccess::ReadOnly` does.
+/// Whatever happens to the byte, INR M and DCR M still read the old value
+/// and set flags from the result they computed, as an 8080 wired to a ROM
+/// chip would.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RomWritePolicy {
+    /// The write goes through, as if the region were RAM.
+    #[default]
+    Allow,
+    /// The byte is left alone and `Event::RomWrite` records the attempt.
+    Ignore,
+    /// As `Ignore`, and the run stops with `EmuError::RomWrite`.
+    Fault,
+}
+
+impl Processor {
+    /// Sets what guest writes to read-only regions do; see
+    /// `RomWritePolicy`. Loading and `poke` always write, without an
+    /// event or a fault.
+    pub fn set_rom_write_policy(&mut self, policy: RomWritePolicy) {
+        self.rom_write_policy = policy;
+    }
+
+    pub fn rom_write_policy(&self) -> RomWritePolicy {
+        return self.rom_write_policy;
+    }
+
+    /// Whether the guest's write of `value` to `addr` should reach memory,
+    /// recording it and raising the fault the policy asks for if not.
+    pub(super) fn admit_write(&mut self, addr: u16, value: u8) -> bool {
+        if self.rom_write_policy == RomWritePolicy::Allow {
+            return true;
+        }
+        let Some(region) = self.read_only_region(addr).map(str::to_string) else {
+            return true;
+        };
+        self.emit(Event::RomWrite { region: region.clone(), addr, value });
+        if self.rom_write_policy == RomWritePolicy::Fault && self.fault.is_none() {
+            self.fault = Some(EmuError::RomWrite { addr, region, pc: self.instruction_pc });
+        }
+        return false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{ConditionBits, EventLog, ProcessorBuilder, RegionAccess, RunOutcome};
+
+    /// Points HL at 1000h in a ROM holding `old` and runs `instruction`
+    /// there under `policy`, logging events.
+    fn run_at_rom(policy: RomWritePolicy, instruction: &[u8], old: u8) -> (Processor, RunOutcome, EventLog) {
+        let log = EventLog::default();
+        let mut processor: Processor = ProcessorBuilder::new().build();
+        processor.set_event_sink(Some(Box::new(log.clone())));
+        processor.declare_region("ROM", 0x1000..0x2000, RegionAccess::ReadOnly);
+        processor.set_rom_write_policy(policy);
+        processor.load_at(0x1000, &[old]);
+        processor.load_program(&[0x21, 0x00, 0x10]); // LXI H,1000h
+        processor.load_at(0x0003, instruction);
+        processor.load_at(0x0003 + instruction.len() as u16, &[0x76]); // HLT
+        let outcome: RunOutcome = processor.run();
+        return (processor, outcome, log);
+    }
+
+    fn rom_writes(log: &EventLog) -> Vec<Event> {
+        return log.events().into_iter().map(|timed| timed.event).filter(|event| matches!(event, Event::RomWrite { .. })).collect();
+    }
+
+    fn suppressed(value: u8) -> Event {
+        return Event::RomWrite { region: "ROM".to_string(), addr: 0x1000, value };
+    }
+
+    #[test]
+    fn test_inr_m_sets_flags_from_the_suppressed_result() {
+        let (processor, outcome, log) = run_at_rom(RomWritePolicy::Ignore, &[0x34], 0xff); // INR M
+
+        assert_eq!(outcome, RunOutcome::Halted);
+        assert_eq!(processor.peek(0x1000), 0xff);
+        let flags: ConditionBits = processor.registers().flags;
+        assert!(flags.zero && flags.parity && !flags.sign);
+        assert_eq!(rom_writes(&log), [suppressed(0x00)]);
+    }
+
+    #[test]
+    fn test_dcr_m_sets_flags_from_the_suppressed_result() {
+        let (processor, outcome, log) = run_at_rom(RomWritePolicy::Ignore, &[0x35], 0x00); // DCR M
+
+        assert_eq!(outcome, RunOutcome::Halted);
+        assert_eq!(processor.peek(0x1000), 0x00);
+        let flags: ConditionBits = processor.registers().flags;
+        assert!(!flags.zero && flags.parity && flags.sign);
+        assert_eq!(rom_writes(&log), [suppressed(0xff)]);
+    }
+
+    #[test]
+    fn test_mvi_m_leaves_rom_and_flags_alone() {
+        let (processor, outcome, log) = run_at_rom(RomWritePolicy::Ignore, &[0x36, 0x5a], 0xc3); // MVI M,5Ah
+
+        assert_eq!(outcome, RunOutcome::Halted);
+        assert_eq!(processor.peek(0x1000), 0xc3);
+        assert_eq!(processor.registers().flags, ConditionBits::default());
+        assert_eq!(rom_writes(&log), [suppressed(0x5a)]);
+    }
+
+    #[test]
+    fn test_fault_policy_stops_after_setting_flags() {
+        let (processor, outcome, log) = run_at_rom(RomWritePolicy::Fault, &[0x34], 0x7f); // INR M
+
+        assert_eq!(outcome, RunOutcome::Fault(EmuError::RomWrite { addr: 0x1000, region: "ROM".to_string(), pc: 0x0003 }));
+        assert_eq!(processor.peek(0x1000), 0x7f);
+        let flags: ConditionBits = processor.registers().flags;
+        assert!(flags.sign && !flags.zero && !flags.parity);
+        assert_eq!(rom_writes(&log), [suppressed(0x80)]);
+    }
+
+    #[test]
+    fn test_poke_writes_rom_under_every_policy() {
+        for policy in [RomWritePolicy::Allow, RomWritePolicy::Ignore, RomWritePolicy::Fault] {
+            let (mut processor, _, log) = run_at_rom(policy, &[], 0x00);
+            processor.poke(0x1000, 0x42);
+            processor.set_pc(0x0003);
+
+            assert_eq!(processor.peek(0x1000), 0x42, "{:?}", policy);
+            assert!(rom_writes(&log).is_empty(), "{:?}", policy);
+            assert_eq!(processor.run(), RunOutcome::Halted, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_allow_writes_rom_as_ram() {
+        let (processor, outcome, log) = run_at_rom(RomWritePolicy::Allow, &[0x35], 0x01); // DCR M
+
+        assert_eq!(outcome, RunOutcome::Halted);
+        assert_eq!(processor.peek(0x1000), 0x00);
+        assert!(processor.registers().flags.zero);
+        assert!(rom_writes(&log).is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+use super::{Processor, SCHEDULED_INTERRUPT_SOURCE};
+
+impl Processor {
+    /// Raises RST `rst_opcode` from `SCHEDULED_INTERRUPT_SOURCE` at the
+    /// first instruction boundary at or after `cycles` cycles from now,
+    /// replacing any interrupt already scheduled. Schedule the next one
+    /// from the handler for a periodic tick.
+    pub fn schedule_interrupt_in(&mut self, cycles: u64, rst_opcode: u8) {
+        self.schedule = Some((self.cycles + cycles, 0xc7 | (rst_opcode & 0b0011_1000)));
+    }
+
+    /// The cycle count and RST opcode of the scheduled interrupt not yet
+    /// raised, if any.
+    pub fn pending_schedule(&self) -> Option<(u64, u8)> {
+        return self.schedule;
+    }
+
+    pub fn cancel_schedule(&mut self) {
+        self.schedule = None;
+    }
+
+    /// Raises the scheduled interrupt if its cycle has come.
+    pub(super) fn fire_schedule(&mut self) {
+        if let Some((_, rst_opcode)) = self.schedule.filter(|(at, _)| self.cycles >= *at) {
+            self.schedule = None;
+            self.raise_interrupt(SCHEDULED_INTERRUPT_SOURCE, rst_opcode);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use crate::processor::{make_processor, VectorAction};
+
+    /// EI, then MVI A,0 over and over: 7 cycles a boundary from cycle 4.
+    fn mvi_sled() -> Processor {
+        let mut processor: Processor = make_processor();
+        processor.load_at(0x0100, &[0x31, 0x00, 0x30, 0xfb]); // LXI SP,3000h; EI
+        processor.load_at(0x0104, &[0x3e, 0x00].repeat(0x800)); // MVI A,0 ...
+        processor.load_at(0x0008, &[0xfb, 0xc9]); // RST 1: EI; RET
+        processor.set_pc(0x0100);
+        processor.step();
+        processor.step();
+        return processor;
+    }
+
+    /// The cycle count at each boundary where RST 1 was taken, up to
+    /// `until` cycles.
+    fn deliveries(processor: &mut Processor, until: u64) -> Vec<u64> {
+        let mut taken: Vec<u64> = Vec::new();
+        while processor.cycle_count() < until {
+            let before: u64 = processor.cycle_count();
+            processor.step();
+            if processor.pc() == 0x0008 {
+                taken.push(before);
+            }
+        }
+        return taken;
+    }
+
+    #[test]
+    fn test_delivers_at_the_first_boundary_past_the_target() {
+        let mut processor: Processor = mvi_sled();
+        assert_eq!(processor.cycle_count(), 14);
+        processor.schedule_interrupt_in(987, 0xcf);
+        assert_eq!(processor.pending_schedule(), Some((1001, 0xcf)));
+
+        // Boundaries fall at 14 + 7n: 994, then 1001.
+        assert_eq!(deliveries(&mut processor, 3000), vec![1001]);
+        assert_eq!(processor.pending_schedule(), None);
+
+        processor.schedule_interrupt_in(10, 0xcf);
+        processor.cancel_schedule();
+        assert!(deliveries(&mut processor, 4000).is_empty());
+    }
+
+    #[test]
+    fn test_rescheduling_from_the_handler_ticks_periodically() {
+        let mut processor: Processor = mvi_sled();
+        let ticks = Arc::new(Mutex::new(0));
+        let counted = ticks.clone();
+        processor.install_pc_hook(0x0008, Box::new(move |processor: &mut Processor| {
+            *counted.lock().unwrap() += 1;
+            processor.schedule_interrupt_in(1000, 0xcf);
+            return VectorAction::FallThrough;
+        }));
+        processor.schedule_interrupt_in(1000, 0xcf);
+
+        let taken: Vec<u64> = deliveries(&mut processor, 5000);
+
+        assert_eq!(*ticks.lock().unwrap(), 4);
+        assert_eq!(taken.len(), 4);
+        // Each tick is scheduled 1000 cycles after its handler started, so
+        // the next lands within one MVI of that.
+        for pair in taken.windows(2) {
+            assert!((1000 + 11..1000 + 11 + 7).contains(&(pair[1] - pair[0])), "{:?}", taken);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::ops::Range;
+
+use crate::word::{hi, lo};
+
+use super::Processor;
+
+impl Processor {
+    /// Addresses where `pattern` appears in memory, lowest first. Matches
+    /// may overlap, but do not wrap round past ffffh.
+    pub fn find(&self, pattern: &[u8]) -> Vec<u16> {
+        return self.search(pattern, 0, 0x10000);
+    }
+
+    /// Addresses where `pattern` appears wholly inside `range`.
+    pub fn find_in(&self, pattern: &[u8], range: Range<u16>) -> Vec<u16> {
+        return self.search(pattern, range.start as u32, range.end as u32);
+    }
+
+    /// Addresses where `value` is stored least significant byte first.
+    pub fn find_u16_le(&self, value: u16) -> Vec<u16> {
+        return self.find(&[lo(value), hi(value)]);
+    }
+
+    fn search(&self, pattern: &[u8], start: u32, end: u32) -> Vec<u16> {
+        let len: u32 = pattern.len() as u32;
+        if len == 0 || start + len > end {
+            return Vec::new();
+        }
+        return (start..=end - len)
+            .filter(|addr| pattern.iter().enumerate().all(|(offset, byte)| self.peek((addr + offset as u32) as u16) == *byte))
+            .map(|addr| addr as u16)
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::make_processor;
+
+    #[test]
+    fn test_find_across_a_region_boundary() {
+        let mut processor = make_processor();
+        processor.annotate_region("table", 0x2000..0x2100);
+        processor.annotate_region("heap", 0x2100..0x2800);
+        for addr in [0x2010, 0x20ff, 0x2400] {
+            processor.load_at(addr, &[0xde, 0xad, 0xbe]);
+        }
+
+        assert_eq!(processor.find(&[0xde, 0xad, 0xbe]), vec![0x2010, 0x20ff, 0x2400]);
+        assert_eq!(processor.find_in(&[0xde, 0xad, 0xbe], processor.region("table").unwrap()), vec![0x2010]);
+        assert_eq!(processor.find_in(&[0xde, 0xad, 0xbe], processor.region("heap").unwrap()), vec![0x2400]);
+        assert_eq!(processor.find_u16_le(0xadde), vec![0x2010, 0x20ff, 0x2400]);
+        assert_eq!(processor.find(&[]), Vec::<u16>::new());
+    }
+
+    #[test]
+    fn test_find_at_the_top_of_memory() {
+        let mut processor = make_processor();
+        processor.load_at(0xfffe, &[0x34, 0x12]);
+
+        assert_eq!(processor.find_u16_le(0x1234), vec![0xfffe]);
+        assert_eq!(processor.find(&[0x12, 0x00]), Vec::<u16>::new());
+    }
+}
@@ -0,0 +1,283 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[cfg(feature = "serde-state")]
+use serde::{Deserialize, Serialize};
+
+use super::{InterruptController, Registers};
+
+/// The snapshot layout this build writes.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// A copy of the complete machine state at one moment: registers, flags,
+/// counters, memory, and enough device and strict-mode state that a run
+/// resumed from it behaves exactly like the original. Read saved ones with
+/// `Snapshot::from_json`, which upgrades those of earlier versions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-state", derive(Serialize, Deserialize))]
+pub struct Snapshot {
+    /// The layout version, `SNAPSHOT_VERSION` when written by this build.
+    pub format_version: u32,
+    pub registers: Registers,
+    pub halted: bool,
+    pub interrupt_enabled: bool,
+    /// Interrupt requests not yet taken.
+    #[cfg_attr(feature = "serde-state", serde(default))]
+    pub interrupts: InterruptController,
+    /// The interrupt `schedule_interrupt_in` set up, not yet raised.
+    #[cfg_attr(feature = "serde-state", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub schedule: Option<(u64, u8)>,
+    /// An EI whose following instruction has not run yet.
+    #[cfg_attr(feature = "serde-state", serde(default))]
+    pub ei_delay: bool,
+    /// Whether EI has ever run, for the vector write check.
+    #[cfg_attr(feature = "serde-state", serde(default))]
+    pub ei_seen: bool,
+    pub instructions: u64,
+    pub cycles: u64,
+    #[cfg_attr(feature = "serde-state", serde(with = "hex_bytes"))]
+    pub memory: Vec<u8>,
+    /// `IoDevice::save_state` of each attached device, in attach order.
+    #[cfg_attr(feature = "serde-state", serde(default))]
+    pub devices: Vec<Vec<u8>>,
+    /// Strict mode's initialized flags for the vector page, if enabled.
+    #[cfg_attr(feature = "serde-state", serde(default))]
+    pub strict_initialized: Option<Vec<bool>>,
+}
+
+impl Snapshot {
+    /// Stable 64-bit FNV-1a hash of the whole state, for comparing runs.
+    #[cfg(feature = "serde-state")]
+    pub fn state_hash(&self) -> u64 {
+        let bytes: Vec<u8> = serde_json::to_vec(self).expect("snapshots always serialize");
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in bytes {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        return hash;
+    }
+
+    /// How `other` differs from this snapshot.
+    pub fn diff(&self, other: &Snapshot) -> SnapshotDiff {
+        if self == other {
+            return SnapshotDiff::default();
+        }
+        let (a, b) = (&self.registers, &other.registers);
+        let registers: Vec<RegisterChange> = [
+            ("A", a.a as u16, b.a as u16, false),
+            ("B", a.b as u16, b.b as u16, false),
+            ("C", a.c as u16, b.c as u16, false),
+            ("D", a.d as u16, b.d as u16, false),
+            ("E", a.e as u16, b.e as u16, false),
+            ("H", a.h as u16, b.h as u16, false),
+            ("L", a.l as u16, b.l as u16, false),
+            ("F", a.flags.to_psw_byte() as u16, b.flags.to_psw_byte() as u16, false),
+            ("SP", a.sp, b.sp, true),
+            ("PC", a.pc, b.pc, true),
+        ].into_iter()
+            .filter(|(_, before, after, _)| before != after)
+            .map(|(name, before, after, wide)| RegisterChange { name, before, after, wide })
+            .collect();
+
+        let mut memory: Vec<RangeInclusive<u16>> = Vec::new();
+        let differs = (0..self.memory.len().max(other.memory.len()))
+            .filter(|addr| self.memory.get(*addr) != other.memory.get(*addr))
+            .map(|addr| addr as u16);
+        for addr in differs {
+            match memory.last_mut() {
+                Some(range) if *range.end() == addr.wrapping_sub(1) => *range = *range.start()..=addr,
+                _ => memory.push(addr..=addr),
+            }
+        }
+
+        let other_state: Vec<&'static str> = [
+            ("halted", self.halted != other.halted),
+            ("interrupts enabled", self.interrupt_enabled != other.interrupt_enabled),
+            ("pending interrupts", self.interrupts != other.interrupts),
+            ("ei delay", self.ei_delay != other.ei_delay),
+            ("ei seen", self.ei_seen != other.ei_seen),
+            ("instructions", self.instructions != other.instructions),
+            ("cycles", self.cycles != other.cycles),
+            ("devices", self.devices != other.devices),
+            ("strict mode", self.strict_initialized != other.strict_initialized),
+        ].into_iter().filter(|(_, differs)| *differs).map(|(name, _)| name).collect();
+
+        return SnapshotDiff { registers, memory, other: other_state };
+    }
+}
+
+/// Why `Processor::restore` refused a snapshot. Nothing was restored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreError {
+    /// The snapshot holds the state of `saved` devices but `attached` are
+    /// attached, so they cannot be paired up by attach order.
+    DeviceCount { attached: usize, saved: usize },
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            RestoreError::DeviceCount { attached, saved } =>
+                write!(f, "snapshot holds the state of {} devices, but {} are attached", saved, attached),
+        };
+    }
+}
+
+impl Error for RestoreError {}
+
+/// A register whose value differs between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    /// `A` to `L`, `F` for the flag byte as PUSH PSW stores it, `SP` or `PC`.
+    pub name: &'static str,
+    pub before: u16,
+    pub after: u16,
+    /// Whether it is a 16-bit register.
+    pub wide: bool,
+}
+
+/// What `Snapshot::diff` found. Empty when the snapshots are equal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    pub registers: Vec<RegisterChange>,
+    /// Differing memory, adjacent addresses merged into ranges.
+    pub memory: Vec<RangeInclusive<u16>>,
+    /// Names of anything else that differs, such as the counters.
+    pub other: Vec<&'static str>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        return self.registers.is_empty() && self.memory.is_empty() && self.other.is_empty();
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    /// e.g.
+    /// ```text
+    /// B    00 -> 42
+    /// PC   0100 -> 0105
+    /// 2000-2002  3 bytes
+    /// also differs: cycles
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "snapshots are identical");
+        }
+        for change in &self.registers {
+            match change.wide {
+                true => writeln!(f, "{:<4} {:04x} -> {:04x}", change.name, change.before, change.after)?,
+                false => writeln!(f, "{:<4} {:02x} -> {:02x}", change.name, change.before, change.after)?,
+            }
+        }
+        for range in &self.memory {
+            let len: usize = (range.end() - range.start()) as usize + 1;
+            match len {
+                1 => writeln!(f, "{:04x}       1 byte", range.start())?,
+                _ => writeln!(f, "{:04x}-{:04x}  {} bytes", range.start(), range.end(), len)?,
+            }
+        }
+        if !self.other.is_empty() {
+            writeln!(f, "also differs: {}", self.other.join(", "))?;
+        }
+        return Ok(());
+    }
+}
+
+/// Memory as one hex string rather than 64K JSON numbers.
+#[cfg(feature = "serde-state")]
+pub(crate) mod hex_bytes {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let text: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        return serializer.serialize_str(&text);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text: String = String::deserialize(deserializer)?;
+        if !text.is_ascii() || !text.len().is_multiple_of(2) {
+            return Err(D::Error::custom("expected an even number of hex digits"));
+        }
+        return (0..text.len()).step_by(2)
+            .map(|index| u8::from_str_radix(&text[index..index + 2], 16).map_err(D::Error::custom))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RestoreError;
+    use crate::processor::{make_processor, IoContext, IoDevice, ProcessorBuilder, RunOutcome};
+
+    #[derive(Debug)]
+    struct Latch(u8);
+
+    impl IoDevice for Latch {
+        fn read(&mut self, _port: u16, _ctx: &IoContext) -> u8 {
+            return self.0;
+        }
+
+        fn write(&mut self, _port: u16, value: u8, _ctx: &IoContext) {
+            self.0 = value;
+        }
+    }
+
+    #[test]
+    fn test_diff_coalesces_memory_ranges() {
+        let mut processor = make_processor();
+        let before = processor.snapshot();
+        let mut registers = processor.registers();
+        registers.b = 0x42;
+        registers.pc = 0x0105;
+        processor.set_registers(registers);
+        processor.load_at(0x2000, &[1, 2, 3]);
+        processor.load_at(0x2010, &[4]);
+
+        let diff = before.diff(&processor.snapshot());
+
+        assert_eq!(diff.memory, vec![0x2000..=0x2002, 0x2010..=0x2010]);
+        assert_eq!(diff.to_string(), "B    00 -> 42\nPC   0000 -> 0105\n2000-2002  3 bytes\n2010       1 byte\n");
+    }
+
+    #[test]
+    fn test_equal_snapshots_have_empty_diff() {
+        let snapshot = make_processor().snapshot();
+
+        let diff = snapshot.diff(&snapshot.clone());
+
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "snapshots are identical\n");
+    }
+
+    #[test]
+    fn test_strict_resume_of_a_lax_snapshot_trusts_its_memory() {
+        let mut lax = make_processor();
+        lax.load_program(&[0x00, 0x00, 0x76]); // NOP; NOP; HLT
+        let snapshot = lax.snapshot();
+        assert_eq!(snapshot.strict_initialized, None);
+
+        let mut strict = ProcessorBuilder::new().strict(true).build();
+        strict.restore(&snapshot).unwrap();
+
+        assert_eq!(strict.run(), RunOutcome::Halted);
+    }
+
+    #[test]
+    fn test_restore_refuses_a_different_number_of_devices() {
+        let mut bare = make_processor();
+        bare.load_program(&[0x76]); // HLT
+        let snapshot = bare.snapshot();
+
+        let mut processor = ProcessorBuilder::new().device(&[0x10], Box::new(Latch(0))).build();
+        processor.load_program(&[0x3e, 0x01]); // MVI A,1
+        let before = processor.snapshot();
+
+        assert_eq!(processor.restore(&snapshot), Err(RestoreError::DeviceCount { attached: 1, saved: 0 }));
+        assert_eq!(processor.snapshot(), before);
+        assert_eq!(bare.restore(&before).unwrap_err().to_string(), "snapshot holds the state of 1 devices, but 0 are attached");
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+
+use super::Processor;
+use crate::word;
+
+/// What put a word on the stack, as far as the processor can tell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackSlot {
+    /// The return address of a CALL or RST on the shadow call stack.
+    Return { call_site: u16, target: u16 },
+    /// A register pair saved by the PUSH at `pc`, e.g. `push d`.
+    Pushed { pc: u16, instruction: String },
+    /// A word nothing accounts for: stored some other way, or pushed
+    /// while the write journal was off.
+    Raw,
+}
+
+/// One word of the stack, from `Processor::stack_view`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackEntry {
+    pub addr: u16,
+    /// The bytes at `addr` and `addr + 1`.
+    pub bytes: [u8; 2],
+    /// The word as POP or RET would take it.
+    pub value: u16,
+    pub slot: StackSlot,
+}
+
+impl fmt::Display for StackEntry {
+    /// e.g. `2ffa  07 01  0107  return from call 0200h at 0104`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}  {:02x} {:02x}  {:04x}", self.addr, self.bytes[0], self.bytes[1], self.value)?;
+        return match &self.slot {
+            StackSlot::Return { call_site, target } => write!(f, "  return from call {:04x}h at {:04x}", target, call_site),
+            StackSlot::Pushed { pc, instruction } => write!(f, "  {} at {:04x}", instruction, pc),
+            StackSlot::Raw => Ok(()),
+        };
+    }
+}
+
+impl Processor {
+    /// Declares where the stack starts, so `stack_view` stops there. None
+    /// forgets it.
+    pub fn set_stack_top(&mut self, top: Option<u16>) {
+        self.stack_top = top;
+    }
+
+    pub fn stack_top(&self) -> Option<u16> {
+        return self.stack_top;
+    }
+
+    /// Up to `max_entries` words from SP towards the stack top, innermost
+    /// first, each labelled from the shadow call stack or, if the write
+    /// journal is on, the PUSH that stored it.
+    pub fn stack_view(&self, max_entries: usize) -> Vec<StackEntry> {
+        let sp: u16 = self.registers.sp;
+        let end: u32 = self.stack_top.map_or(0x10000, |top| top.max(sp) as u32);
+        return (sp as u32..end).step_by(2).take(max_entries).map(|addr| {
+            let addr: u16 = addr as u16;
+            let bytes: [u8; 2] = [self.memory.peek(addr), self.memory.peek(addr.wrapping_add(1))];
+            let value: u16 = word::read_word(|addr| self.memory.peek(addr), addr);
+            return StackEntry { addr, bytes, value, slot: self.stack_slot(addr, bytes, value) };
+        }).collect();
+    }
+
+    fn stack_slot(&self, addr: u16, bytes: [u8; 2], value: u16) -> StackSlot {
+        if let Some(frame) = self.call_stack.frames().iter().find(|frame| frame.sp == addr && frame.return_addr == value) {
+            return StackSlot::Return { call_site: frame.call_site, target: frame.target };
+        }
+        let last = |addr: u16| self.write_history(addr).last().copied();
+        let (Some(low), Some(high)) = (last(addr), last(addr.wrapping_add(1))) else {
+            return StackSlot::Raw;
+        };
+        let pushed: bool = matches!(self.memory.peek(low.pc), 0xc5 | 0xd5 | 0xe5 | 0xf5);
+        if !pushed || low.pc != high.pc || low.instruction != high.instruction || [low.new, high.new] != bytes {
+            return StackSlot::Raw;
+        }
+        return StackSlot::Pushed { pc: low.pc, instruction: self.describe_instruction(low.pc) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{make_processor, RunOutcome};
+
+    /// Calls 0100h, which pushes DE, calls 0200h and stops at a breakpoint
+    /// there with a word stored below the frames by SHLD.
+    fn nested() -> Processor {
+        let mut processor: Processor = make_processor();
+        processor.set_write_journal(true);
+        processor.load_program(&[
+            0x31, 0x00, 0x30, // 0000 LXI SP,3000h
+            0xcd, 0x00, 0x01, // 0003 CALL 0100h
+        ]);
+        processor.load_at(0x0100, &[
+            0x11, 0x34, 0x12, // 0100 LXI D,1234h
+            0xd5,             // 0103 PUSH D
+            0xcd, 0x00, 0x02, // 0104 CALL 0200h
+        ]);
+        processor.load_at(0x0200, &[
+            0x21, 0x78, 0x56, // 0200 LXI H,5678h
+            0x22, 0xf8, 0x2f, // 0203 SHLD 2ff8h
+            0x3b,             // 0206 DCX SP
+            0x3b,             // 0207 DCX SP
+            0x76,             // 0208 HLT
+        ]);
+        processor.add_breakpoint(0x0208);
+        assert_eq!(processor.run(), RunOutcome::Breakpoint { pc: 0x0208 });
+        return processor;
+    }
+
+    #[test]
+    fn test_labels_returns_and_pushes() {
+        let mut processor: Processor = nested();
+        processor.set_stack_top(Some(0x3000));
+
+        let view: Vec<StackEntry> = processor.stack_view(16);
+
+        assert_eq!(view, vec![
+            StackEntry { addr: 0x2ff8, bytes: [0x78, 0x56], value: 0x5678, slot: StackSlot::Raw },
+            StackEntry { addr: 0x2ffa, bytes: [0x07, 0x01], value: 0x0107,
+                         slot: StackSlot::Return { call_site: 0x0104, target: 0x0200 } },
+            StackEntry { addr: 0x2ffc, bytes: [0x34, 0x12], value: 0x1234,
+                         slot: StackSlot::Pushed { pc: 0x0103, instruction: "push d".to_string() } },
+            StackEntry { addr: 0x2ffe, bytes: [0x06, 0x00], value: 0x0006,
+                         slot: StackSlot::Return { call_site: 0x0003, target: 0x0100 } },
+        ]);
+        let lines: Vec<String> = view.iter().map(StackEntry::to_string).collect();
+        assert_eq!(lines, [
+            "2ff8  78 56  5678",
+            "2ffa  07 01  0107  return from call 0200h at 0104",
+            "2ffc  34 12  1234  push d at 0103",
+            "2ffe  06 00  0006  return from call 0100h at 0003",
+        ]);
+    }
+
+    #[test]
+    fn test_without_the_journal_pushes_are_raw() {
+        let mut processor: Processor = nested();
+        processor.set_write_journal(false);
+
+        let view: Vec<StackEntry> = processor.stack_view(3);
+
+        let slots: Vec<&StackSlot> = view.iter().map(|entry| &entry.slot).collect();
+        assert_eq!(slots, [&StackSlot::Raw, &StackSlot::Return { call_site: 0x0104, target: 0x0200 }, &StackSlot::Raw]);
+    }
+
+    #[test]
+    fn test_a_word_overwritten_after_its_push_is_raw() {
+        let mut processor: Processor = nested();
+        processor.poke(0x2ffd, 0x99);
+
+        let view: Vec<StackEntry> = processor.stack_view(4);
+
+        assert_eq!(view[2].slot, StackSlot::Raw);
+        assert_eq!(view[2].value, 0x9934);
+    }
+}
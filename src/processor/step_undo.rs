@@ -0,0 +1,222 @@
+use std::fmt;
+
+use super::{CallFrame, Processor, Registers};
+
+/// What `step` keeps to take its instruction back: the state before it and
+/// the bytes it overwrote. An 8080 instruction writes at most two, so they
+/// fit in place and capturing costs a copy of the registers.
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct StepUndo {
+    registers: Registers,
+    halt: bool,
+    interrupt_enabled: bool,
+    ei_delay: bool,
+    instructions: u64,
+    cycles: u64,
+    call_depth: usize,
+    /// The innermost call frame, for a RET to put back.
+    top_frame: Option<CallFrame>,
+    /// Address and old value of each byte written, in order.
+    writes: [(u16, u8); 2],
+    write_count: usize,
+    /// Whether the step is still running, so writes belong to it.
+    recording: bool,
+    blocked: Option<UndoError>,
+}
+
+/// Why `Processor::undo_step` could not take the last instruction back.
+/// Nothing was changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoError {
+    /// No instruction has run since the processor was made or the last
+    /// undo.
+    NothingToUndo,
+    /// The instruction at `pc` sent a byte to `port`; the device, if any,
+    /// has already acted on it.
+    Output { pc: u16, port: u8 },
+    /// The instruction at `pc` read the device on `port`, which may have
+    /// changed state by being read.
+    Input { pc: u16, port: u8 },
+    /// The step took an interrupt instead of running an instruction.
+    Interrupt { vector: u8 },
+    /// Host code run by the step at `pc` wrote more than the two bytes kept.
+    TooManyWrites { pc: u16 },
+}
+
+impl fmt::Display for UndoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            UndoError::NothingToUndo => write!(f, "no instruction to undo"),
+            UndoError::Output { pc, port } =>
+                write!(f, "cannot undo the OUT to port {:02x}h at {:04x}: the write has already left the processor", port, pc),
+            UndoError::Input { pc, port } =>
+                write!(f, "cannot undo the IN from port {:02x}h at {:04x}: reading may have changed the device", port, pc),
+            UndoError::Interrupt { vector } => write!(f, "cannot undo taking interrupt RST {}", vector),
+            UndoError::TooManyWrites { pc } => write!(f, "cannot undo the step at {:04x}: it wrote more than two bytes", pc),
+        };
+    }
+}
+
+impl Processor {
+    /// Takes back the instruction the last `step` ran: registers, flags,
+    /// the bytes it wrote, the interrupt enable, the halt, the counters and
+    /// the shadow call stack. Only the last instruction is kept, whether or
+    /// not the write journal is on. Port I/O with devices and interrupts
+    /// cannot be taken back, so undo refuses after them. Events, traces
+    /// and statistics keep what the instruction did.
+    pub fn undo_step(&mut self) -> Result<(), UndoError> {
+        let Some(undo) = self.step_undo else {
+            return Err(UndoError::NothingToUndo);
+        };
+        if let Some(err) = undo.blocked {
+            return Err(err);
+        }
+        for (addr, old) in undo.writes[..undo.write_count].iter().rev() {
+            self.memory.write(*addr, *old);
+        }
+        self.registers = undo.registers;
+        self.halt = undo.halt;
+        self.interrupt_enabled = undo.interrupt_enabled;
+        self.ei_delay = undo.ei_delay;
+        self.instructions = undo.instructions;
+        self.cycles = undo.cycles;
+        // A RET that abandoned deeper frames only gets the innermost back.
+        self.call_stack.truncate(undo.call_depth);
+        if let Some(frame) = undo.top_frame.filter(|_| self.call_stack.frames().len() < undo.call_depth) {
+            self.call_stack.push(frame);
+        }
+        self.step_undo = None;
+        return Ok(());
+    }
+
+    /// Keeps the state before the instruction `step` is about to run.
+    pub(super) fn begin_step_undo(&mut self) {
+        self.step_undo = Some(StepUndo {
+            registers: self.registers,
+            halt: self.halt,
+            interrupt_enabled: self.interrupt_enabled,
+            ei_delay: self.ei_delay,
+            instructions: self.instructions,
+            cycles: self.cycles,
+            call_depth: self.call_stack.frames().len(),
+            top_frame: self.call_stack.frames().last().copied(),
+            recording: true,
+            ..Default::default()
+        });
+    }
+
+    pub(super) fn end_step_undo(&mut self) {
+        if let Some(undo) = &mut self.step_undo {
+            undo.recording = false;
+        }
+    }
+
+    /// Notes that the running step overwrote `old` at `addr`.
+    pub(super) fn note_undo_write(&mut self, addr: u16, old: u8) {
+        let pc: u16 = self.instruction_pc;
+        let Some(undo) = self.step_undo.as_mut().filter(|undo| undo.recording) else {
+            return;
+        };
+        match undo.writes.get_mut(undo.write_count) {
+            Some(slot) => {
+                *slot = (addr, old);
+                undo.write_count += 1;
+            },
+            None => undo.block(UndoError::TooManyWrites { pc }),
+        }
+    }
+
+    /// Marks the running step as one undo must refuse.
+    pub(super) fn block_undo(&mut self, err: UndoError) {
+        if let Some(undo) = self.step_undo.as_mut().filter(|undo| undo.recording) {
+            undo.block(err);
+        }
+    }
+}
+
+impl StepUndo {
+    /// Keeps the first reason, which is the one the user saw happen first.
+    fn block(&mut self, err: UndoError) {
+        self.blocked.get_or_insert(err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::make_processor;
+
+    #[test]
+    fn test_undo_puts_back_a_store() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[
+            0x3e, 0x5a,       // 0000 MVI A,5Ah
+            0x32, 0x00, 0x20, // 0002 STA 2000h
+        ]);
+        processor.load_at(0x2000, &[0x11]);
+        processor.step();
+        let before: Registers = processor.registers();
+        let (instructions, cycles): (u64, u64) = (processor.instruction_count(), processor.cycle_count());
+
+        processor.step();
+        assert_eq!(processor.peek(0x2000), 0x5a);
+        assert_eq!(processor.undo_step(), Ok(()));
+
+        assert_eq!(processor.peek(0x2000), 0x11);
+        assert_eq!(processor.registers(), before);
+        assert_eq!((processor.instruction_count(), processor.cycle_count()), (instructions, cycles));
+        assert_eq!(processor.undo_step(), Err(UndoError::NothingToUndo));
+    }
+
+    #[test]
+    fn test_undo_puts_back_a_call() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[
+            0x31, 0x00, 0x30, // 0000 LXI SP,3000h
+            0xcd, 0x00, 0x01, // 0003 CALL 0100h
+        ]);
+        processor.load_at(0x0100, &[0xc9]); // RET
+        processor.step();
+        processor.step();
+
+        assert_eq!(processor.undo_step(), Ok(()));
+        assert_eq!((processor.registers().pc, processor.registers().sp), (0x0003, 0x3000));
+        assert_eq!((processor.peek(0x2ffe), processor.peek(0x2fff)), (0x00, 0x00));
+        assert!(processor.call_stack().is_empty());
+
+        processor.step();
+        processor.step();
+        assert!(processor.call_stack().is_empty());
+        assert_eq!(processor.undo_step(), Ok(()));
+        assert_eq!((processor.registers().pc, processor.registers().sp), (0x0100, 0x2ffe));
+        assert_eq!(processor.call_stack().len(), 1);
+    }
+
+    #[test]
+    fn test_undo_refuses_after_out() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[
+            0x3e, 0x07, // 0000 MVI A,07h
+            0xd3, 0x10, // 0002 OUT 10h
+        ]);
+        processor.step();
+        processor.step();
+
+        let err: UndoError = processor.undo_step().unwrap_err();
+
+        assert_eq!(err, UndoError::Output { pc: 0x0002, port: 0x10 });
+        assert_eq!(err.to_string(), "cannot undo the OUT to port 10h at 0002: the write has already left the processor");
+        assert_eq!(processor.registers().pc, 0x0004);
+    }
+
+    #[test]
+    fn test_pokes_between_steps_are_not_undone() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0x00]); // NOP
+        processor.step();
+        processor.poke(0x2000, 0x42);
+
+        assert_eq!(processor.undo_step(), Ok(()));
+        assert_eq!((processor.registers().pc, processor.peek(0x2000)), (0x0000, 0x42));
+    }
+}
@@ -0,0 +1,208 @@
+use std::iter::FusedIterator;
+
+use super::{Processor, Registers, RunOutcome};
+
+/// What `Steps` does on reaching an address with a breakpoint set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BreakpointPolicy {
+    /// End the iteration before the instruction runs, as `run` would stop.
+    #[default]
+    Stop,
+    /// Run the instruction and yield it with `StepInfo::breakpoint` set.
+    Yield,
+}
+
+/// One instruction, or one interrupt taken in place of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepInfo {
+    /// Address the step started at.
+    pub pc: u16,
+    /// The byte at `pc` when the step started. For an interrupt this is the
+    /// interrupted instruction, not the RST.
+    pub opcode: u8,
+    pub before: Registers,
+    pub after: Registers,
+    /// Cycles the step took.
+    pub cycles: u64,
+    /// Whether `pc` had a breakpoint. Under `BreakpointPolicy::Stop` only
+    /// the first step can have one.
+    pub breakpoint: bool,
+}
+
+/// Executes a processor one step per item, until it halts, faults or
+/// reaches a breakpoint. Created by `Processor::steps`.
+///
+/// The processor is always between instructions when `next` returns, so
+/// dropping the iterator part way leaves it ready to step or run on.
+///
+/// ```
+/// use intel_8080_emu::processor::ProcessorBuilder;
+///
+/// let mut processor = ProcessorBuilder::new().build();
+/// processor.load_at(0x0000, &[
+///     0x3e, 0xfe, // 0000 MVI A,FEh
+///     0xc6, 0x01, // 0002 ADI 1
+///     0xc6, 0x01, // 0004 ADI 1
+///     0xc6, 0x01, // 0006 ADI 1
+///     0x76,       // 0008 HLT
+/// ]);
+///
+/// // Every instruction that set the carry flag.
+/// let carries: Vec<u16> = processor.steps()
+///     .filter(|step| !step.before.flags.carry && step.after.flags.carry)
+///     .map(|step| step.pc)
+///     .collect();
+/// assert_eq!(carries, vec![0x0004]);
+/// ```
+#[derive(Debug)]
+pub struct Steps<'a> {
+    processor: &'a mut Processor,
+    policy: BreakpointPolicy,
+    started: bool,
+    outcome: Option<RunOutcome>,
+}
+
+impl<'a> Steps<'a> {
+    pub fn on_breakpoint(mut self, policy: BreakpointPolicy) -> Steps<'a> {
+        self.policy = policy;
+        return self;
+    }
+
+    /// Why the iteration ended: `Halted`, `Breakpoint` or `Fault`. None
+    /// while it can still go on.
+    pub fn outcome(&self) -> Option<&RunOutcome> {
+        return self.outcome.as_ref();
+    }
+
+    /// Why the next step cannot run, if it cannot. A breakpoint at the
+    /// starting PC is ignored, as it is for `run`.
+    fn stop_reason(&self) -> Option<RunOutcome> {
+        let processor = &*self.processor;
+        if processor.halt && !processor.interrupt_pending() {
+            return Some(RunOutcome::Halted);
+        }
+        if self.started && self.policy == BreakpointPolicy::Stop && processor.breakpoints.contains(&processor.registers.pc) {
+            return Some(RunOutcome::Breakpoint { pc: processor.registers.pc });
+        }
+        return None;
+    }
+}
+
+impl Iterator for Steps<'_> {
+    type Item = StepInfo;
+
+    fn next(&mut self) -> Option<StepInfo> {
+        if self.outcome.is_some() {
+            return None;
+        }
+        if let Some(outcome) = self.stop_reason() {
+            self.outcome = Some(outcome);
+            return None;
+        }
+        self.started = true;
+        let before: Registers = self.processor.registers;
+        let cycles: u64 = self.processor.cycles;
+        let step = StepInfo {
+            pc: before.pc,
+            opcode: self.processor.peek(before.pc),
+            before,
+            after: before,
+            cycles: 0,
+            breakpoint: self.processor.breakpoints.contains(&before.pc),
+        };
+        self.processor.step();
+        if let Some(fault) = self.processor.fault.take() {
+            self.outcome = Some(self.processor.fault_outcome(fault, before.pc));
+            return None;
+        }
+        return Some(StepInfo { after: self.processor.registers, cycles: self.processor.cycles - cycles, ..step });
+    }
+}
+
+impl FusedIterator for Steps<'_> {}
+
+impl Processor {
+    /// Iterates over execution one step at a time. See `Steps`.
+    pub fn steps(&mut self) -> Steps<'_> {
+        return Steps { processor: self, policy: BreakpointPolicy::Stop, started: false, outcome: None };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::ProcessorBuilder;
+
+    fn counting_processor() -> Processor {
+        let mut processor: Processor = ProcessorBuilder::new().build();
+        processor.load_at(0x0000, &[
+            0x06, 0x05,       // 0000 MVI B,5
+            0x05,             // 0002 DCR B
+            0xc2, 0x02, 0x00, // 0003 JNZ 0002h
+            0x76,             // 0006 HLT
+            0x00,             // 0007 NOP
+        ]);
+        return processor;
+    }
+
+    #[test]
+    fn test_steps_end_at_hlt() {
+        let mut processor = counting_processor();
+
+        let mut steps = processor.steps();
+        let pcs: Vec<u16> = steps.by_ref().map(|step| step.pc).collect();
+        assert_eq!(steps.next(), None);
+        assert_eq!(steps.outcome(), Some(&RunOutcome::Halted));
+
+        assert_eq!(pcs.len(), 12);
+        assert_eq!(pcs.last(), Some(&0x0006));
+        assert_eq!(processor.pc(), 0x0007);
+        assert_eq!(processor.instruction_count(), 12);
+    }
+
+    #[test]
+    fn test_steps_report_registers_and_cycles() {
+        let mut processor = counting_processor();
+
+        let first: Vec<StepInfo> = processor.steps().take(2).collect();
+
+        assert_eq!((first[0].opcode, first[0].before.b, first[0].after.b), (0x06, 0, 5));
+        assert_eq!((first[1].opcode, first[1].after.b, first[1].after.pc), (0x05, 4, 0x0003));
+        assert_eq!(first[0].cycles + first[1].cycles, processor.cycle_count());
+    }
+
+    #[test]
+    fn test_dropping_steps_leaves_processor_resumable() {
+        let mut processor = counting_processor();
+        let mut reference = counting_processor();
+
+        assert_eq!(processor.steps().take(5).count(), 5);
+        assert_eq!(processor.instruction_count(), 5);
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert_eq!(reference.run(), RunOutcome::Halted);
+
+        assert_eq!(processor.registers(), reference.registers());
+        assert_eq!((processor.instruction_count(), processor.cycle_count()), (reference.instruction_count(), reference.cycle_count()));
+    }
+
+    #[test]
+    fn test_steps_breakpoint_policies() {
+        let mut processor = counting_processor();
+        processor.add_breakpoint(0x0003);
+
+        let mut steps = processor.steps();
+        assert_eq!(steps.by_ref().count(), 2);
+        assert_eq!(steps.outcome(), Some(&RunOutcome::Breakpoint { pc: 0x0003 }));
+        // Resuming from the breakpoint runs the instruction at it.
+        assert_eq!(processor.steps().next().map(|step| step.pc), Some(0x0003));
+
+        let mut processor = counting_processor();
+        processor.add_breakpoint(0x0003);
+        let flagged: Vec<u16> = processor.steps().on_breakpoint(BreakpointPolicy::Yield)
+            .filter(|step| step.breakpoint)
+            .map(|step| step.pc)
+            .collect();
+        assert_eq!(flagged, vec![0x0003; 5]);
+        assert!(processor.is_halted());
+    }
+}
@@ -0,0 +1,74 @@
+use std::ops::Range;
+
+/// The vector page checked by strict mode unless configured otherwise: the
+/// RST vectors and, under CP/M, the BDOS entry at 0x0005.
+pub const DEFAULT_VECTOR_PAGE: Range<u16> = 0x0000..0x0100;
+
+/// The eight RST vectors, checked by `Processor::set_vector_write_check`.
+pub const RST_VECTORS: Range<u16> = 0x0000..0x0040;
+
+/// What guest instructions may do to a region declared with
+/// `Processor::declare_region`. Only checked by the operand check; the bus
+/// itself still reads and writes everywhere.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RegionAccess {
+    #[default]
+    ReadWrite,
+    /// ROM.
+    ReadOnly,
+    /// Device registers that cannot be read back.
+    WriteOnly,
+}
+
+impl RegionAccess {
+    pub fn allows(self, write: bool) -> bool {
+        return match self {
+            RegionAccess::ReadWrite => true,
+            RegionAccess::ReadOnly => !write,
+            RegionAccess::WriteOnly => write,
+        };
+    }
+}
+
+/// Opt-in checks that turn likely guest bugs into faults.
+#[derive(Debug, Clone)]
+pub struct StrictMode {
+    vector_page: Range<u16>,
+    /// One flag per byte of `vector_page`: set once the byte was loaded,
+    /// written by the guest, or marked initialized by the host.
+    initialized: Vec<bool>,
+}
+
+impl StrictMode {
+    pub fn new(vector_page: Range<u16>) -> StrictMode {
+        let len = vector_page.len();
+        return StrictMode { vector_page, initialized: vec![false; len] };
+    }
+
+    pub fn mark_initialized(&mut self, start: u16, len: usize) {
+        for offset in 0..len {
+            let addr = start.wrapping_add(offset as u16);
+            if self.vector_page.contains(&addr) {
+                self.initialized[(addr - self.vector_page.start) as usize] = true;
+            }
+        }
+    }
+
+    /// Which bytes of the vector page count as initialized, for snapshots.
+    pub fn initialized(&self) -> &[bool] {
+        return &self.initialized;
+    }
+
+    /// Replaces the initialized flags, e.g. from a snapshot. Flags beyond
+    /// the vector page are ignored.
+    pub fn restore_initialized(&mut self, initialized: &[bool]) {
+        let len = initialized.len().min(self.initialized.len());
+        self.initialized[..len].copy_from_slice(&initialized[..len]);
+    }
+
+    /// True when `addr` is in the vector page and nothing ever put code there.
+    pub fn is_uninitialized_vector(&self, addr: u16) -> bool {
+        return self.vector_page.contains(&addr)
+            && !self.initialized[(addr - self.vector_page.start) as usize];
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use crate::clock::Clock;
+
+/// If the host falls this far behind the target speed, the throttle stops
+/// trying to catch up rather than running flat out until it has.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// Holds a run to a target clock speed by sleeping whenever emulation gets
+/// ahead of real time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throttle {
+    hz: u64,
+    /// Clock time and cycle count that pacing is measured from.
+    anchor: Option<(Duration, u64)>,
+}
+
+impl Throttle {
+    pub fn new(hz: u64) -> Throttle {
+        return Throttle { hz: hz.max(1), anchor: None };
+    }
+
+    pub fn hz(&self) -> u64 {
+        return self.hz;
+    }
+
+    /// Forgets the pacing history, e.g. when a run starts after a pause.
+    pub fn reset(&mut self) {
+        self.anchor = None;
+    }
+
+    /// Sleeps until `clock` has caught up with `cycles` at the target speed.
+    pub fn pace(&mut self, cycles: u64, clock: &dyn Clock) {
+        let now: Duration = clock.now();
+        let (start, start_cycles) = *self.anchor.get_or_insert((now, cycles));
+        let target: Duration = Duration::from_secs_f64((cycles - start_cycles) as f64 / self.hz as f64);
+        let elapsed: Duration = now.saturating_sub(start);
+        if target > elapsed {
+            clock.sleep(target - elapsed);
+        } else if elapsed - target > MAX_LAG {
+            self.anchor = Some((now, cycles));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn test_sleeps_until_real_time_catches_up() {
+        let clock = ManualClock::new();
+        let mut throttle = Throttle::new(2_000_000);
+
+        throttle.pace(0, &clock);
+        throttle.pace(20_000, &clock);
+
+        assert_eq!(clock.now(), Duration::from_millis(10));
+        clock.advance(Duration::from_millis(15));
+        throttle.pace(40_000, &clock);
+        assert_eq!(clock.now(), Duration::from_millis(25));
+    }
+
+    #[test]
+    fn test_stops_catching_up_after_a_long_stall() {
+        let clock = ManualClock::new();
+        let mut throttle = Throttle::new(1_000_000);
+        throttle.pace(0, &clock);
+
+        clock.advance(Duration::from_secs(1));
+        throttle.pace(1000, &clock);
+        throttle.pace(11_000, &clock);
+
+        assert_eq!(clock.now(), Duration::from_millis(1010));
+    }
+}
@@ -0,0 +1,67 @@
+/// Clock cycles taken by each opcode. For conditional calls and returns this
+/// is the cost when the condition is false; taking the branch adds
+/// `CONDITIONAL_TAKEN_PENALTY`. MOV is listed at its register-to-register
+/// cost; a MOV to or from M adds `MOV_MEMORY_PENALTY`.
+pub const CYCLES: [u8; 256] = [
+//  0  1   2   3   4   5   6   7   8  9   a   b   c   d   e   f
+    4, 10, 7,  5,  5,  5,  7,  4,  4, 10, 7,  5,  5,  5,  7,  4,  // 0
+    4, 10, 7,  5,  5,  5,  7,  4,  4, 10, 7,  5,  5,  5,  7,  4,  // 1
+    4, 10, 16, 5,  5,  5,  7,  4,  4, 10, 16, 5,  5,  5,  7,  4,  // 2
+    4, 10, 13, 5,  10, 10, 10, 4,  4, 10, 13, 5,  5,  5,  7,  4,  // 3
+    5, 5,  5,  5,  5,  5,  5,  5,  5, 5,  5,  5,  5,  5,  5,  5,  // 4
+    5, 5,  5,  5,  5,  5,  5,  5,  5, 5,  5,  5,  5,  5,  5,  5,  // 5
+    5, 5,  5,  5,  5,  5,  5,  5,  5, 5,  5,  5,  5,  5,  5,  5,  // 6
+    5, 5,  5,  5,  5,  5,  7,  5,  5, 5,  5,  5,  5,  5,  5,  5,  // 7
+    4, 4,  4,  4,  4,  4,  7,  4,  4, 4,  4,  4,  4,  4,  7,  4,  // 8
+    4, 4,  4,  4,  4,  4,  7,  4,  4, 4,  4,  4,  4,  4,  7,  4,  // 9
+    4, 4,  4,  4,  4,  4,  7,  4,  4, 4,  4,  4,  4,  4,  7,  4,  // a
+    4, 4,  4,  4,  4,  4,  7,  4,  4, 4,  4,  4,  4,  4,  7,  4,  // b
+    5, 10, 10, 10, 11, 11, 7,  11, 5, 10, 10, 10, 11, 17, 7,  11, // c
+    5, 10, 10, 10, 11, 11, 7,  11, 5, 10, 10, 10, 11, 17, 7,  11, // d
+    5, 10, 10, 18, 11, 11, 7,  11, 5, 5,  10, 4,  11, 17, 7,  11, // e
+    5, 10, 10, 4,  11, 11, 7,  11, 5, 5,  10, 4,  11, 17, 7,  11, // f
+];
+
+/// Extra cycles a conditional CALL or RET costs when the branch is taken.
+pub const CONDITIONAL_TAKEN_PENALTY: u8 = 6;
+
+/// Extra cycles MOV r,M and MOV M,r cost for the memory access.
+pub const MOV_MEMORY_PENALTY: u8 = 2;
+
+/// Register code of the M operand, the byte at HL.
+const M: u8 = 0b110;
+
+/// Cycles a MOV opcode takes on top of its `CYCLES` entry: the memory
+/// penalty if either operand is M.
+pub fn mov_penalty(opcode: u8) -> u8 {
+    let (dest, source): (u8, u8) = ((opcode >> 3) & 0b111, opcode & 0b111);
+    return if dest == M || source == M { MOV_MEMORY_PENALTY } else { 0 };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mov_cycles() {
+        let movs = (0x40..0x80u8).filter(|opcode| *opcode != 0x76);
+        let (memory, register): (Vec<u8>, Vec<u8>) = movs.partition(|opcode| opcode & 0b111 == M || (opcode >> 3) & 0b111 == M);
+
+        assert_eq!(memory.len(), 14);
+        let cycles = |opcode: &u8| CYCLES[*opcode as usize] + mov_penalty(*opcode);
+        assert!(memory.iter().all(|opcode| cycles(opcode) == 7));
+        assert!(register.iter().all(|opcode| cycles(opcode) == 5));
+        assert_eq!(CYCLES[0x76], 7);
+    }
+
+    #[test]
+    fn test_stack_cycles() {
+        for pair in 0..4u8 {
+            assert_eq!(CYCLES[(0xc5 | pair << 4) as usize], 11); // PUSH
+            assert_eq!(CYCLES[(0xc1 | pair << 4) as usize], 10); // POP
+        }
+        assert_eq!((CYCLES[0xe3], CYCLES[0xeb]), (18, 4)); // XTHL, XCHG
+        assert_eq!((CYCLES[0xf9], CYCLES[0xe9]), (5, 5)); // SPHL, PCHL
+        assert_eq!((CYCLES[0xcd], CYCLES[0xc9], CYCLES[0xc7]), (17, 10, 11)); // CALL, RET, RST 0
+    }
+}
@@ -0,0 +1,241 @@
+use std::fmt::{self, Debug};
+use std::ops::RangeInclusive;
+
+use crate::disasm;
+
+use super::{ConditionBits, Registers, TimedEvent};
+
+/// JMP, CALL, RET and RST in all their forms, and PCHL.
+pub const TRACE_FLOW: u8 = 0b0001;
+pub const TRACE_IO: u8 = 0b0010;
+/// PUSH, POP, XTHL and SPHL.
+pub const TRACE_STACK: u8 = 0b0100;
+/// Everything else.
+pub const TRACE_DATA: u8 = 0b1000;
+pub const TRACE_ALL: u8 = 0b1111;
+
+/// The `TRACE_*` class of `opcode`.
+pub fn opcode_class(opcode: u8) -> u8 {
+    return match opcode {
+        0xc3 | 0xcb | 0xc9 | 0xd9 | 0xe9 => TRACE_FLOW,
+        0xcd | 0xdd | 0xed | 0xfd => TRACE_FLOW,
+        _ if opcode & 0xc0 == 0xc0 && matches!(opcode & 0b111, 0b000 | 0b010 | 0b100 | 0b111) => TRACE_FLOW,
+        0xd3 | 0xdb => TRACE_IO,
+        0xe3 | 0xf9 => TRACE_STACK,
+        _ if opcode & 0xcb == 0xc1 => TRACE_STACK,
+        _ => TRACE_DATA,
+    };
+}
+
+/// The class named `name`: `flow`, `io`, `stack`, `data` or `all`.
+pub fn parse_class(name: &str) -> Option<u8> {
+    return match name.to_ascii_lowercase().as_str() {
+        "flow" => Some(TRACE_FLOW),
+        "io" => Some(TRACE_IO),
+        "stack" => Some(TRACE_STACK),
+        "data" => Some(TRACE_DATA),
+        "all" => Some(TRACE_ALL),
+        _ => None,
+    };
+}
+
+/// Picks the instructions a `Tracer` sees. It looks only at the PC and
+/// opcode, so instructions it rejects cost no more than the check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// PCs to trace; every PC when empty.
+    pub ranges: Vec<RangeInclusive<u16>>,
+    /// `TRACE_*` bits of the opcode classes to trace.
+    pub classes: u8,
+    /// Trace only every nth instruction that passes the other filters,
+    /// starting with the first.
+    pub every: u64,
+    matched: u64,
+}
+
+impl Default for TraceFilter {
+    fn default() -> TraceFilter {
+        return TraceFilter { ranges: Vec::new(), classes: TRACE_ALL, every: 1, matched: 0 };
+    }
+}
+
+impl TraceFilter {
+    pub fn new() -> TraceFilter {
+        return TraceFilter::default();
+    }
+
+    /// Adds `range` to the PCs traced.
+    pub fn range(mut self, range: RangeInclusive<u16>) -> TraceFilter {
+        self.ranges.push(range);
+        return self;
+    }
+
+    pub fn classes(mut self, classes: u8) -> TraceFilter {
+        self.classes = classes;
+        return self;
+    }
+
+    pub fn every(mut self, every: u64) -> TraceFilter {
+        self.every = every.max(1);
+        return self;
+    }
+
+    /// Whether to trace the instruction at `pc`. Counts it toward sampling
+    /// if it passes the range and class filters.
+    pub fn admit(&mut self, pc: u16, opcode: u8) -> bool {
+        if opcode_class(opcode) & self.classes == 0 {
+            return false;
+        }
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|range| range.contains(&pc)) {
+            return false;
+        }
+        self.matched += 1;
+        return (self.matched - 1).is_multiple_of(self.every.max(1));
+    }
+}
+
+/// An instruction as it was about to execute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Instructions completed before this one.
+    pub instructions: u64,
+    pub cycles: u64,
+    pub pc: u16,
+    /// The opcode and the two bytes after it, enough to disassemble any
+    /// instruction.
+    pub bytes: [u8; 3],
+    pub registers: Registers,
+}
+
+impl fmt::Display for TraceRecord {
+    /// The `--trace` line, e.g.
+    /// `         2  0005  cd 10 00  call 0010h    A=00 B=02 ...`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = disasm::decode(&self.bytes, self.pc);
+        let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        return write!(f, "{:>10}  {:04x}  {:<8}  {:<12}  {}", self.instructions, self.pc, bytes.join(" "), line.text, self.registers);
+    }
+}
+
+impl TraceRecord {
+    /// The `--trace` line with the flags the instruction changed appended,
+    /// for `--trace-flags`.
+    pub fn with_flag_delta(&self, after: &Registers) -> String {
+        let delta = FlagDelta { before: self.registers.flags, after: after.flags };
+        return match delta.is_empty() {
+            true => self.to_string(),
+            false => format!("{}  {}", self, delta),
+        };
+    }
+}
+
+/// Receives the instructions a `TraceFilter` lets through, once each one
+/// has executed: `record` holds the registers before it ran and `after`
+/// the registers it left.
+pub trait Tracer: Debug + Send {
+    fn trace(&mut self, record: &TraceRecord, after: &Registers);
+
+    /// Receives a one-time warning that the trace is not to be trusted from
+    /// `warning.pc` on, such as `Event::UnmodeledAuxCarry`. The event sink
+    /// gets it too. Ignored unless overridden.
+    fn warn(&mut self, _warning: &TimedEvent) {}
+}
+
+/// The flags one instruction changed, shown as e.g. `F:+Z-C` for zero set
+/// and carry cleared, in the order S, Z, A (aux carry), P, C. Shows
+/// nothing when no flag changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlagDelta {
+    pub before: ConditionBits,
+    pub after: ConditionBits,
+}
+
+impl FlagDelta {
+    pub fn is_empty(&self) -> bool {
+        return self.before == self.after;
+    }
+}
+
+impl fmt::Display for FlagDelta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let flags = |bits: &ConditionBits| [bits.sign, bits.zero, bits.aux_carry, bits.parity, bits.carry];
+        write!(f, "F:")?;
+        for ((before, after), name) in flags(&self.before).into_iter().zip(flags(&self.after)).zip(['S', 'Z', 'A', 'P', 'C']) {
+            if before != after {
+                write!(f, "{}{}", if after { '+' } else { '-' }, name)?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use crate::processor::{ProcessorBuilder, RunOutcome};
+
+    #[derive(Debug, Clone, Default)]
+    struct AnnotatingTracer {
+        lines: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Tracer for AnnotatingTracer {
+        fn trace(&mut self, record: &TraceRecord, after: &Registers) {
+            self.lines.lock().unwrap().push(record.with_flag_delta(after));
+        }
+    }
+
+    #[test]
+    fn test_opcode_classes() {
+        let flow: Vec<u8> = (0..=255u8).filter(|opcode| opcode_class(*opcode) == TRACE_FLOW).collect();
+        // JMP, 8 Jcc, CALL, 8 Ccc, RET, 8 Rcc, 8 RST, PCHL, and the
+        // undocumented aliases of JMP, CALL and RET.
+        assert_eq!(flow.len(), 1 + 8 + 1 + 8 + 1 + 8 + 8 + 1 + 5);
+        assert_eq!(opcode_class(0xdb), TRACE_IO);
+        assert_eq!(opcode_class(0xf5), TRACE_STACK);
+        assert_eq!(opcode_class(0xe1), TRACE_STACK);
+        assert_eq!(opcode_class(0xfe), TRACE_DATA);
+        assert_eq!(opcode_class(0x76), TRACE_DATA);
+    }
+
+    #[test]
+    fn test_sampling() {
+        let mut filter = TraceFilter::new().range(0x10..=0x1f).every(3);
+
+        let admitted: Vec<u16> = (0..0x30).filter(|pc| filter.admit(*pc, 0x00)).collect();
+
+        assert_eq!(admitted, vec![0x10, 0x13, 0x16, 0x19, 0x1c, 0x1f]);
+    }
+
+    #[test]
+    fn test_flag_delta_annotations() {
+        let tracer = AnnotatingTracer::default();
+        let mut processor = ProcessorBuilder::new().tracer(Box::new(tracer.clone())).build();
+        processor.load_program(&[
+            0x3e, 0xff, // 0000 MVI A,0FFh
+            0xc6, 0x01, // 0002 ADI 1
+            0x3c,       // 0004 INR A
+            0x47,       // 0005 MOV B,A
+            0xd6, 0x02, // 0006 SUI 2
+            0xb7,       // 0008 ORA A
+            0x76,       // 0009 HLT
+        ]);
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        assert_eq!(*tracer.lines.lock().unwrap(), [
+            "         0  0000  3e ff     mvi a, 0ffh   A=00 B=00 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0000 -----",
+            "         1  0002  c6 01     adi 01h       A=ff B=00 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0002 -----  F:+Z+A+P+C",
+            "         2  0004  3c        inr a         A=00 B=00 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0004 -ZAPC  F:-Z-P",
+            "         3  0005  47        mov b, a      A=01 B=00 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0005 --A-C",
+            "         4  0006  d6 02     sui 02h       A=01 B=01 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0006 --A-C  F:+S+P",
+            "         5  0008  b7        ora a         A=ff B=01 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0008 S-APC  F:-C",
+            "         6  0009  76        hlt           A=ff B=01 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0009 S-AP-",
+        ]);
+    }
+}
@@ -0,0 +1,263 @@
+use std::fmt;
+use std::ops::Range;
+
+use super::Processor;
+
+/// What `Processor::copy_state_from` copies. Start from one of the
+/// constructors and combine with `and`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateSelection {
+    /// A, B, C, D, E, H, L, SP and PC.
+    pub registers: bool,
+    pub flags: bool,
+    pub memory: Vec<Range<u16>>,
+    /// The registers, the flags, the interrupt enable and an EI still to
+    /// take effect, whether the processor is halted, and every byte outside
+    /// the read-only regions.
+    pub all_but_rom: bool,
+}
+
+impl StateSelection {
+    pub fn registers() -> StateSelection {
+        return StateSelection { registers: true, ..Default::default() };
+    }
+
+    pub fn flags() -> StateSelection {
+        return StateSelection { flags: true, ..Default::default() };
+    }
+
+    pub fn memory(ranges: &[Range<u16>]) -> StateSelection {
+        return StateSelection { memory: ranges.to_vec(), ..Default::default() };
+    }
+
+    pub fn all_but_rom() -> StateSelection {
+        return StateSelection { all_but_rom: true, ..Default::default() };
+    }
+
+    /// Everything this selection or `other` selects.
+    pub fn and(mut self, other: StateSelection) -> StateSelection {
+        self.registers |= other.registers;
+        self.flags |= other.flags;
+        self.memory.extend(other.memory);
+        self.all_but_rom |= other.all_but_rom;
+        return self;
+    }
+
+    fn copies_memory(&self) -> bool {
+        return self.all_but_rom || !self.memory.is_empty();
+    }
+}
+
+/// Why `Processor::copy_state_from` refused to copy. Nothing was copied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferError {
+    /// The two processors declare different read-only regions, so the
+    /// same addresses are not the same memory in both. Address spaces are
+    /// always 64K, so the ROMs are what can differ.
+    MapMismatch { ours: Vec<Range<u16>>, theirs: Vec<Range<u16>> },
+    /// `range` was selected, but `addr` in it is in the read-only `region`.
+    IntoRom { range: Range<u16>, addr: u16, region: String },
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ranges = |ranges: &[Range<u16>]| match ranges.is_empty() {
+            true => "none".to_string(),
+            false => ranges.iter().map(|range| format!("{:04x}-{:04x}", range.start, range.end.wrapping_sub(1)))
+                .collect::<Vec<String>>().join(", "),
+        };
+        return match self {
+            TransferError::MapMismatch { ours, theirs } =>
+                write!(f, "memory maps differ: read-only {} here, {} in the source", ranges(ours), ranges(theirs)),
+            TransferError::IntoRom { range, addr, region } => write!(f, "{} includes {:04x} in read-only region {}",
+                ranges(std::slice::from_ref(range)), addr, region),
+        };
+    }
+}
+
+impl Processor {
+    /// Copies what `what` selects from `other`, as the host: the copy is
+    /// not a guest access, so it is not journaled, traced or checked
+    /// against the ROM write policy. Copying memory needs both processors
+    /// to declare the same read-only regions, and selected ranges must
+    /// stay out of them.
+    pub fn copy_state_from(&mut self, other: &Processor, what: StateSelection) -> Result<(), TransferError> {
+        if what.copies_memory() {
+            let (ours, theirs) = (self.rom_ranges(), other.rom_ranges());
+            if ours != theirs {
+                return Err(TransferError::MapMismatch { ours, theirs });
+            }
+            for range in &what.memory {
+                if let Some((addr, region)) = range.clone().find_map(|addr| self.read_only_region(addr).map(|region| (addr, region))) {
+                    return Err(TransferError::IntoRom { range: range.clone(), addr, region: region.to_string() });
+                }
+            }
+        }
+
+        if what.registers || what.all_but_rom {
+            let flags = self.registers.flags;
+            self.registers = other.registers;
+            self.registers.flags = flags;
+        }
+        if what.flags || what.all_but_rom {
+            self.registers.flags = other.registers.flags;
+        }
+        if what.all_but_rom {
+            self.interrupt_enabled = other.interrupt_enabled;
+            self.ei_delay = other.ei_delay;
+            self.halt = other.halt;
+            let mut start: u32 = 0;
+            while start < 0x10000 {
+                let writable = |addr: u32| self.read_only_region(addr as u16).is_none();
+                let end: u32 = (start..0x10000).find(|addr| writable(*addr) != writable(start)).unwrap_or(0x10000);
+                if writable(start) {
+                    self.copy_memory_from(other, start..end);
+                }
+                start = end;
+            }
+        }
+        for range in &what.memory {
+            self.copy_memory_from(other, range.start as u32..range.end as u32);
+        }
+        return Ok(());
+    }
+
+    /// Copies `other`'s bytes in `range`. Strict mode here counts those the
+    /// source's strict mode does as initialized, and leaves the rest as
+    /// they were.
+    fn copy_memory_from(&mut self, other: &Processor, range: Range<u32>) {
+        let bytes: Vec<u8> = range.clone().map(|addr| other.memory.peek(addr as u16)).collect();
+        self.memory.load(range.start as u16, &bytes);
+        if let (Some(ours), Some(theirs)) = (&mut self.strict, &other.strict) {
+            for addr in range.map(|addr| addr as u16).filter(|addr| !theirs.is_uninitialized_vector(*addr)) {
+                ours.mark_initialized(addr, 1);
+            }
+        }
+    }
+
+    /// The read-only regions, sorted.
+    fn rom_ranges(&self) -> Vec<Range<u16>> {
+        let mut ranges: Vec<Range<u16>> = self.regions.iter()
+            .filter(|(_, _, access)| !access.allows(true))
+            .map(|(range, _, _)| range.clone())
+            .collect();
+        ranges.sort_by_key(|range| (range.start, range.end));
+        return ranges;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{make_processor, EmuError, ProcessorBuilder, RegionAccess, RunOutcome, Snapshot};
+
+    /// Calls a routine 32 times that stores twice the counter at HL and
+    /// moves HL on, then halts.
+    const DOUBLER: [u8; 21] = [
+        0x31, 0x00, 0x30, // 0000 LXI SP,3000h
+        0x21, 0x00, 0x20, // 0003 LXI H,2000h
+        0x06, 0x20,       // 0006 MVI B,20h
+        0xcd, 0x10, 0x00, // 0008 CALL 0010h
+        0x05,             // 000b DCR B
+        0xc2, 0x08, 0x00, // 000c JNZ 0008h
+        0x76,             // 000f HLT
+        0x78,             // 0010 MOV A,B
+        0x87,             // 0011 ADD A
+        0x77,             // 0012 MOV M,A
+        0x23,             // 0013 INX H
+        0xc9,             // 0014 RET
+    ];
+
+    /// `DOUBLER` in a ROM of `rom_len` bytes from 0000h.
+    fn with_rom(rom_len: u16) -> Processor {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&DOUBLER);
+        processor.declare_region("ROM", 0x0000..rom_len, RegionAccess::ReadOnly);
+        return processor;
+    }
+
+    #[test]
+    fn test_a_copy_continues_where_the_source_stopped() {
+        let mut reference: Processor = with_rom(0x0100);
+        assert_eq!(reference.run_with_limit(50), RunOutcome::InstructionLimit { instructions: 50 });
+        let mut copy: Processor = with_rom(0x0100);
+
+        copy.copy_state_from(&reference, StateSelection::registers().and(StateSelection::all_but_rom())).unwrap();
+
+        assert_eq!(copy.registers(), reference.registers());
+        assert_eq!(copy.peek(0x2000), 0x40);
+        assert_eq!(reference.run(), RunOutcome::Halted);
+        assert_eq!(copy.run(), RunOutcome::Halted);
+        let (expected, actual): (Snapshot, Snapshot) = (reference.snapshot(), copy.snapshot());
+        assert_eq!((actual.registers, actual.halted), (expected.registers, expected.halted));
+        assert_eq!(actual.memory, expected.memory);
+        assert_eq!(copy.peek(0x201f), 0x02);
+    }
+
+    #[test]
+    fn test_a_copy_right_after_ei_waits_one_instruction() {
+        let program: [u8; 4] = [0xfb, 0x00, 0x00, 0x76]; // EI; NOP; NOP; HLT
+        let mut source: Processor = make_processor();
+        source.load_program(&program);
+        source.step();
+        let mut target: Processor = make_processor();
+        target.load_program(&program);
+
+        target.copy_state_from(&source, StateSelection::all_but_rom()).unwrap();
+
+        for processor in [&mut source, &mut target] {
+            processor.load_at(0x0008, &[0x76]); // RST 1: HLT
+            processor.request_interrupt(1);
+            processor.step();
+            assert_eq!(processor.pc(), 0x0002);
+            processor.step();
+            assert_eq!(processor.pc(), 0x0008);
+        }
+    }
+
+    #[test]
+    fn test_strict_targets_keep_their_own_initialized_flags() {
+        let mut source: Processor = make_processor();
+        source.load_program(&[0xff]); // RST 7, into a vector nothing loaded
+        let mut target: Processor = ProcessorBuilder::new().strict(true).build();
+        target.load_program(&[0xff]);
+
+        target.copy_state_from(&source, StateSelection::all_but_rom()).unwrap();
+
+        assert!(matches!(target.run(), RunOutcome::Fault(EmuError::UninitializedVector { addr: 0x0038, .. })));
+    }
+
+    #[test]
+    fn test_selections_copy_only_what_they_name() {
+        let mut source: Processor = with_rom(0x0100);
+        source.run_with_limit(20);
+        let mut target: Processor = with_rom(0x0100);
+
+        target.copy_state_from(&source, StateSelection::flags()).unwrap();
+        assert_eq!((target.registers().flags, target.registers().pc), (source.registers().flags, 0x0000));
+        let results: Range<u16> = 0x2000..0x2002;
+        target.copy_state_from(&source, StateSelection::memory(&[results])).unwrap();
+        assert_eq!((target.peek(0x2000), target.peek(0x2001), target.peek(0x2002)), (0x40, 0x3e, 0x00));
+        assert_eq!(target.registers().sp, 0x0000);
+    }
+
+    #[test]
+    fn test_incompatible_maps_are_refused() {
+        let mut source: Processor = with_rom(0x0100);
+        source.run_with_limit(20);
+        let mut target: Processor = with_rom(0x0200);
+        let (ours, theirs): (Range<u16>, Range<u16>) = (0x0000..0x0200, 0x0000..0x0100);
+
+        let err = target.copy_state_from(&source, StateSelection::all_but_rom()).unwrap_err();
+        assert_eq!(err, TransferError::MapMismatch { ours: vec![ours], theirs: vec![theirs] });
+        assert_eq!(err.to_string(), "memory maps differ: read-only 0000-01ff here, 0000-00ff in the source");
+        assert_eq!(target.peek(0x2000), 0x00);
+        target.copy_state_from(&source, StateSelection::registers()).unwrap();
+        assert_eq!(target.registers().pc, source.registers().pc);
+
+        let mut target: Processor = with_rom(0x0100);
+        let straddling: Range<u16> = 0x00f0..0x0110;
+        assert_eq!(target.copy_state_from(&source, StateSelection::memory(std::slice::from_ref(&straddling))),
+                   Err(TransferError::IntoRom { range: straddling, addr: 0x00f0, region: "ROM".to_string() }));
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[cfg(feature = "serde-state")]
+use serde::{Deserialize, Serialize};
+
+use super::{EmuError, Processor};
+
+/// IN from a port no device is attached to, which reads
+/// `Processor::unattached_read_value` and is usually a device someone
+/// forgot to attach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-state", derive(Serialize, Deserialize))]
+pub struct UnattachedRead {
+    pub port: u8,
+    /// The IN that first read the port.
+    pub pc: u16,
+    /// Reads of the port in all, the first included.
+    pub reads: u64,
+}
+
+impl fmt::Display for UnattachedRead {
+    /// e.g. `unattached port 02h: first read at 0002, 5 reads`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "unattached port {:02x}h: first read at {:04x}, {} reads", self.port, self.pc, self.reads);
+    }
+}
+
+impl Processor {
+    /// Records reads from ports with no device attached, listed by
+    /// `unattached_reads`, and in strict mode stops the run with
+    /// `EmuError::UnattachedPort` at the first. Reads of a port only count
+    /// while nothing is attached to it.
+    pub fn set_unattached_port_check(&mut self, enabled: bool) {
+        self.unattached = enabled.then(BTreeMap::new);
+    }
+
+    /// Every unattached port read so far, by port number. Empty if the
+    /// check is off.
+    pub fn unattached_reads(&self) -> Vec<UnattachedRead> {
+        return self.unattached.as_ref().map_or_else(Vec::new, |reads| reads.values().copied().collect());
+    }
+
+    /// The last value OUT wrote to each port with no device attached, by
+    /// port number. Always kept, so tests can see a program's output
+    /// without attaching anything.
+    pub fn unattached_writes(&self) -> &BTreeMap<u8, u8> {
+        return &self.unattached_writes;
+    }
+
+    /// Sets what IN from a port with no device reads: 0 unless set. Real
+    /// boards differ; a floating data bus often reads FFh.
+    pub fn set_unattached_read_value(&mut self, value: u8) {
+        self.unattached_read_value = value;
+    }
+
+    pub fn unattached_read_value(&self) -> u8 {
+        return self.unattached_read_value;
+    }
+
+    /// The last value OUT wrote to `port` while nothing was attached to it.
+    pub fn last_unattached_write(&self, port: u8) -> Option<u8> {
+        return self.unattached_writes.get(&port).copied();
+    }
+
+    /// Counts an IN from `port`, which has no device.
+    pub(super) fn note_unattached_read(&mut self, port: u8) {
+        let pc: u16 = self.instruction_pc;
+        let Some(reads) = &mut self.unattached else {
+            return;
+        };
+        reads.entry(port).or_insert(UnattachedRead { port, pc, reads: 0 }).reads += 1;
+        if self.strict.is_some() && self.fault.is_none() {
+            self.fault = Some(EmuError::UnattachedPort { port, pc });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{make_processor, IoContext, IoDevice, ProcessorBuilder, RunOutcome};
+
+    /// Polls status port 02h five times, then halts.
+    const POLL: [u8; 9] = [
+        0x06, 0x05,       // 0000 MVI B,5
+        0xdb, 0x02,       // 0002 IN 02h
+        0x05,             // 0004 DCR B
+        0xc2, 0x02, 0x00, // 0005 JNZ 0002h
+        0x76,             // 0008 HLT
+    ];
+
+    fn polling(builder: ProcessorBuilder) -> Processor {
+        let mut processor: Processor = builder.build();
+        processor.set_unattached_port_check(true);
+        processor.load_program(&POLL);
+        return processor;
+    }
+
+    #[test]
+    fn test_reports_first_pc_and_read_count() {
+        let mut processor: Processor = polling(ProcessorBuilder::new());
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        assert_eq!(processor.unattached_reads(), vec![UnattachedRead { port: 0x02, pc: 0x0002, reads: 5 }]);
+        assert_eq!(processor.unattached_reads()[0].to_string(), "unattached port 02h: first read at 0002, 5 reads");
+    }
+
+    #[test]
+    fn test_off_by_default() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0xdb, 0x02, 0x76]); // IN 02h; HLT
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert!(processor.unattached_reads().is_empty());
+    }
+
+    #[test]
+    fn test_strict_mode_faults_on_the_first_read() {
+        let mut processor: Processor = polling(ProcessorBuilder::new().strict(true));
+
+        assert_eq!(processor.run(), RunOutcome::Fault(EmuError::UnattachedPort { port: 0x02, pc: 0x0002 }));
+        assert_eq!(processor.unattached_reads()[0].reads, 1);
+    }
+
+    #[cfg(feature = "devices")]
+    #[test]
+    fn test_attached_ports_never_report() {
+        use crate::devices::Console;
+
+        let mut processor: Processor = polling(ProcessorBuilder::new().strict(true).device(&[0x02], Box::new(Console::new())));
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert!(processor.unattached_reads().is_empty());
+    }
+
+    #[test]
+    fn test_out_to_unattached_ports_keeps_the_last_value() {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[
+            0x3e, 0x11, // 0000 MVI A,11h
+            0xd3, 0x03, // 0002 OUT 03h
+            0x3c,       // 0004 INR A
+            0xd3, 0x03, // 0005 OUT 03h
+            0xd3, 0x05, // 0007 OUT 05h
+            0x76,       // 0009 HLT
+        ]);
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        assert_eq!(processor.unattached_writes(), &BTreeMap::from([(0x03, 0x12), (0x05, 0x12)]));
+        assert_eq!((processor.last_unattached_write(0x03), processor.last_unattached_write(0x04)), (Some(0x12), None));
+    }
+
+    #[cfg(feature = "devices")]
+    #[test]
+    fn test_out_to_attached_ports_goes_to_the_device() {
+        use crate::devices::Console;
+
+        let mut processor: Processor = ProcessorBuilder::new().device(&[0x01], Box::new(Console::new())).build();
+        processor.load_program(&[0x3e, 0x21, 0xd3, 0x01, 0x76]); // MVI A,21h; OUT 01h; HLT
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+        assert!(processor.unattached_writes().is_empty());
+    }
+
+    /// Answers IN on each port with a value of its own.
+    #[derive(Debug)]
+    struct PortValues(BTreeMap<u8, u8>);
+
+    impl IoDevice for PortValues {
+        fn read(&mut self, port: u16, _ctx: &IoContext) -> u8 {
+            return self.0[&(port as u8)];
+        }
+
+        fn write(&mut self, _port: u16, _value: u8, _ctx: &IoContext) {}
+    }
+
+    /// Reads ports 1, 2 and 3 and adds the first two.
+    const READ_AND_ADD: [u8; 9] = [
+        0xdb, 0x01, // 0000 IN 01h
+        0x47,       // 0002 MOV B,A
+        0xdb, 0x03, // 0003 IN 03h
+        0x4f,       // 0005 MOV C,A
+        0xdb, 0x02, // 0006 IN 02h
+        0x80,       // 0008 ADD B
+    ];
+
+    #[test]
+    fn test_in_reads_the_device_on_each_port() {
+        let values = PortValues(BTreeMap::from([(0x01, 0x21), (0x02, 0x12)]));
+        let mut processor: Processor = ProcessorBuilder::new().device(&[0x01, 0x02], Box::new(values)).build();
+        processor.load_program(&READ_AND_ADD);
+        processor.load_at(READ_AND_ADD.len() as u16, &[0x76]); // HLT
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        assert_eq!((processor.registers().a, processor.registers().b, processor.registers().c), (0x33, 0x21, 0x00));
+    }
+
+    #[test]
+    fn test_unattached_ports_read_the_configured_value() {
+        let values = PortValues(BTreeMap::from([(0x01, 0x21), (0x02, 0x12)]));
+        let mut processor: Processor = ProcessorBuilder::new()
+            .device(&[0x01, 0x02], Box::new(values))
+            .unattached_read_value(0xff)
+            .build();
+        processor.load_program(&READ_AND_ADD);
+        processor.load_at(READ_AND_ADD.len() as u16, &[0x76]); // HLT
+
+        assert_eq!(processor.run(), RunOutcome::Halted);
+
+        assert_eq!((processor.registers().a, processor.registers().c), (0x33, 0xff));
+        assert_eq!(processor.unattached_read_value(), 0xff);
+    }
+}
@@ -0,0 +1,185 @@
+//! Instructions at the top of memory. The program counter wraps from 0xffff
+//! to 0x0000, so operands, return addresses and the next instruction all
+//! come from the bottom of memory. Register pair arithmetic wraps the same
+//! way.
+
+use super::{make_processor, Processor, Registers};
+
+/// A processor with `bytes` at `addr` onwards, wrapping past 0xffff, and
+/// the PC and SP set to run them.
+fn at(addr: u16, bytes: &[u8]) -> Processor {
+    let mut processor = make_processor();
+    for (offset, byte) in bytes.iter().enumerate() {
+        processor.load_at(addr.wrapping_add(offset as u16), &[*byte]);
+    }
+    set_registers(&mut processor, |registers| {
+        registers.pc = addr;
+        registers.sp = 0x8000;
+    });
+    return processor;
+}
+
+fn set_registers(processor: &mut Processor, set: impl FnOnce(&mut Registers)) {
+    let mut registers = processor.registers();
+    set(&mut registers);
+    processor.set_registers(registers);
+}
+
+#[test]
+fn test_lxi_operands_wrap() {
+    for addr in [0xfffd, 0xfffe, 0xffff] {
+        let mut processor = at(addr, &[0x01, 0x34, 0x12]); // LXI B,1234h
+        processor.step();
+        assert_eq!((processor.registers().bc(), processor.pc()), (0x1234, addr.wrapping_add(3)), "at {:04x}", addr);
+    }
+}
+
+#[test]
+fn test_mvi_operand_wraps() {
+    let mut processor = at(0xffff, &[0x06, 0x42]); // MVI B,42h
+    processor.step();
+    assert_eq!((processor.registers().b, processor.pc()), (0x42, 0x0001));
+}
+
+#[test]
+fn test_single_byte_instruction_falls_through_to_zero() {
+    let mut processor = at(0xffff, &[0x04, 0x04]); // INR B; INR B
+    processor.step();
+    assert_eq!(processor.pc(), 0x0000);
+    processor.step();
+    assert_eq!((processor.registers().b, processor.pc()), (2, 0x0001));
+}
+
+#[test]
+fn test_jumps_read_wrapped_targets() {
+    for addr in [0xfffd, 0xfffe, 0xffff] {
+        let mut processor = at(addr, &[0xc3, 0x00, 0x30]); // JMP 3000h
+        processor.step();
+        assert_eq!(processor.pc(), 0x3000, "at {:04x}", addr);
+    }
+
+    let mut processor = at(0xfffe, &[0xca, 0x00, 0x30]); // JZ 3000h, not taken
+    processor.step();
+    assert_eq!(processor.pc(), 0x0001);
+}
+
+#[test]
+fn test_call_returns_to_wrapped_address() {
+    for addr in [0xfffd, 0xfffe, 0xffff] {
+        let mut processor = at(addr, &[0xcd, 0x00, 0x30]); // CALL 3000h
+        processor.load_at(0x3000, &[0xc9]); // RET
+        processor.step();
+        assert_eq!(processor.pc(), 0x3000, "at {:04x}", addr);
+        assert_eq!(processor.call_stack()[0].return_addr, addr.wrapping_add(3));
+        processor.step();
+        assert_eq!((processor.pc(), processor.registers().sp), (addr.wrapping_add(3), 0x8000), "at {:04x}", addr);
+    }
+
+    let mut processor = at(0xfffe, &[0xcc, 0x00, 0x30]); // CZ 3000h, not taken
+    processor.step();
+    assert_eq!((processor.pc(), processor.registers().sp), (0x0001, 0x8000));
+}
+
+#[test]
+fn test_direct_address_operands_wrap() {
+    let mut processor = at(0xfffe, &[0x32, 0x00, 0x20]); // STA 2000h
+    set_registers(&mut processor, |registers| registers.a = 0x5a);
+    processor.step();
+    assert_eq!((processor.peek(0x2000), processor.pc()), (0x5a, 0x0001));
+
+    let mut processor = at(0xffff, &[0x3a, 0x00, 0x20]); // LDA 2000h
+    processor.load_at(0x2000, &[0xa5]);
+    processor.step();
+    assert_eq!((processor.registers().a, processor.pc()), (0xa5, 0x0002));
+
+    let mut processor = at(0xfffd, &[0x2a, 0xff, 0xff]); // LHLD 0ffffh
+    processor.load_at(0x0000, &[0x12]); // the high byte of the word at 0ffffh
+    processor.step();
+    assert_eq!((processor.registers().hl(), processor.pc()), (0x12ff, 0x0000));
+
+    let mut processor = at(0xfffe, &[0x22, 0x00, 0x20]); // SHLD 2000h
+    set_registers(&mut processor, |registers| registers.set_hl(0xbeef));
+    processor.step();
+    assert_eq!((processor.peek(0x2000), processor.peek(0x2001), processor.pc()), (0xef, 0xbe, 0x0001));
+
+    let mut processor = at(0x1000, &[0x22, 0xff, 0xff]); // SHLD 0ffffh
+    set_registers(&mut processor, |registers| registers.set_hl(0xbeef));
+    processor.step();
+    assert_eq!((processor.peek(0xffff), processor.peek(0x0000)), (0xef, 0xbe));
+}
+
+#[test]
+fn test_xthl_wraps_past_the_top_of_the_stack() {
+    let mut processor = at(0x1000, &[0xe3]); // XTHL
+    // L swaps with (SP) and H with (SP+1), which wraps to 0000h.
+    processor.load_at(0xffff, &[0x34]);
+    processor.load_at(0x0000, &[0x12]);
+    set_registers(&mut processor, |registers| {
+        registers.set_sp(0xffff);
+        registers.set_hl(0xbeef);
+    });
+    processor.step();
+    assert_eq!(processor.registers().sp, 0xffff);
+    assert_eq!(processor.registers().hl(), 0x1234);
+    assert_eq!((processor.peek(0xffff), processor.peek(0x0000)), (0xef, 0xbe));
+}
+
+#[test]
+fn test_port_operand_wraps() {
+    let mut processor = at(0xffff, &[0xdb, 0x07]); // IN 7
+    processor.step();
+    assert_eq!(processor.pc(), 0x0001);
+}
+
+#[test]
+fn test_stack_pointer_wraps() {
+    let mut processor = at(0x1000, &[0xc5, 0xc1]); // PUSH B; POP B
+    set_registers(&mut processor, |registers| registers.sp = 0x0000);
+    processor.step();
+    assert_eq!(processor.registers().sp, 0xfffe);
+    processor.step();
+    assert_eq!(processor.registers().sp, 0x0000);
+}
+
+#[test]
+fn test_inx_and_dcx_wrap() {
+    let mut processor = at(0x1000, &[0x03, 0x1b, 0x33]); // INX B; DCX D; INX SP
+    set_registers(&mut processor, |registers| {
+        registers.set_bc(0xffff);
+        registers.set_de(0x0000);
+        registers.set_sp(0xffff);
+        registers.flags.carry = true;
+    });
+    processor.step();
+    assert_eq!(processor.registers().bc(), 0x0000);
+    processor.step();
+    assert_eq!(processor.registers().de(), 0xffff);
+    processor.step();
+    assert_eq!(processor.registers().sp, 0x0000);
+    assert!(processor.registers().flags.carry);
+}
+
+#[test]
+fn test_dad_carries_out_of_bit_15() {
+    let mut processor = at(0x1000, &[0x09]); // DAD B
+    set_registers(&mut processor, |registers| {
+        registers.set_hl(0x8000);
+        registers.set_bc(0x8000);
+    });
+    processor.step();
+    assert_eq!((processor.registers().hl(), processor.registers().flags.carry), (0x0000, true));
+
+    let mut processor = at(0x1000, &[0x29]); // DAD H
+    set_registers(&mut processor, |registers| registers.set_hl(0xffff));
+    processor.step();
+    assert_eq!((processor.registers().hl(), processor.registers().flags.carry), (0xfffe, true));
+
+    let mut processor = at(0x1000, &[0x19]); // DAD D
+    set_registers(&mut processor, |registers| {
+        registers.set_hl(0x7fff);
+        registers.set_de(0x8000);
+        registers.flags.carry = true;
+    });
+    processor.step();
+    assert_eq!((processor.registers().hl(), processor.registers().flags.carry), (0xffff, false));
+}
@@ -0,0 +1,185 @@
+//! Running one program under every quirk profile to see which it expects.
+//! `compare` runs a fresh processor per profile and puts the runs side by
+//! side; profiles that end in the same state with the same output agree,
+//! so a program that only works under some of them splits into groups.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::processor::{Event, EventSink, ProcessorBuilder, Processor, QuirkProfile, RunOutcome, TimedEvent};
+
+/// Instructions each profile runs for unless told otherwise.
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 100_000_000;
+
+/// Keeps every OUT, however many there are.
+#[derive(Debug, Clone, Default)]
+struct Output {
+    writes: Arc<Mutex<Vec<(u8, u8)>>>,
+}
+
+impl EventSink for Output {
+    fn record(&mut self, event: TimedEvent) {
+        if let Event::PortWrite { port, value } = event.event {
+            self.writes.lock().unwrap().push((port, value));
+        }
+    }
+}
+
+/// How the program ended under one profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileRun {
+    pub profile: QuirkProfile,
+    pub outcome: RunOutcome,
+    pub instructions: u64,
+    /// `Snapshot::state_hash` of the final state.
+    pub state_hash: u64,
+    /// Every OUT as port and value, in order.
+    pub output: Vec<(u8, u8)>,
+}
+
+impl ProfileRun {
+    /// Whether `other` ended the same way, whatever its profile.
+    pub fn agrees_with(&self, other: &ProfileRun) -> bool {
+        return self.outcome == other.outcome
+            && self.instructions == other.instructions
+            && self.state_hash == other.state_hash
+            && self.output == other.output;
+    }
+}
+
+/// One run per profile, in the order the profiles were given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileComparison {
+    pub runs: Vec<ProfileRun>,
+}
+
+impl ProfileComparison {
+    /// The profiles grouped by agreement, each group in run order and the
+    /// groups in the order their first run appears.
+    pub fn groups(&self) -> Vec<Vec<QuirkProfile>> {
+        let mut groups: Vec<(&ProfileRun, Vec<QuirkProfile>)> = Vec::new();
+        for run in &self.runs {
+            match groups.iter_mut().find(|(first, _)| first.agrees_with(run)) {
+                Some((_, profiles)) => profiles.push(run.profile),
+                None => groups.push((run, vec![run.profile])),
+            }
+        }
+        return groups.into_iter().map(|(_, profiles)| profiles).collect();
+    }
+
+    /// True when every profile ran the program the same way.
+    pub fn unanimous(&self) -> bool {
+        return self.groups().len() <= 1;
+    }
+}
+
+fn describe(outcome: &RunOutcome) -> String {
+    return match outcome {
+        RunOutcome::Halted => "halted".to_string(),
+        RunOutcome::Stuck { .. } => "stuck".to_string(),
+        RunOutcome::InstructionLimit { .. } => "instruction limit".to_string(),
+        RunOutcome::Fault(err) => format!("fault: {}", err),
+        other => format!("{:?}", other),
+    };
+}
+
+impl fmt::Display for ProfileComparison {
+    /// A row per profile, labelled with its group's letter, then the groups.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let groups: Vec<Vec<QuirkProfile>> = self.groups();
+        let letter = |index: usize| (b'A' + index as u8) as char;
+        writeln!(f, "group  profile  {:<18} {:>12}  {:<16}  output", "outcome", "instructions", "state")?;
+        for run in &self.runs {
+            let group: usize = groups.iter().position(|group| group.contains(&run.profile)).unwrap_or(0);
+            let output: Vec<String> = run.output.iter().map(|(port, value)| format!("{:02x}:{:02x}", port, value)).collect();
+            writeln!(f, "{:<5}  {:<7}  {:<18} {:>12}  {:016x}  {}", letter(group), run.profile, describe(&run.outcome),
+                     run.instructions, run.state_hash, output.join(" "))?;
+        }
+        if groups.len() == 1 {
+            return writeln!(f, "all profiles agree");
+        }
+        for (index, group) in groups.iter().enumerate() {
+            let names: Vec<&str> = group.iter().map(|profile| profile.name()).collect();
+            writeln!(f, "{}: {}", letter(index), names.join(", "))?;
+        }
+        return Ok(());
+    }
+}
+
+/// Runs `program`, loaded at 0, once under each of `profiles` for at most
+/// `DEFAULT_MAX_INSTRUCTIONS`.
+pub fn compare(program: &[u8], profiles: &[QuirkProfile]) -> ProfileComparison {
+    return compare_with_limit(program, profiles, DEFAULT_MAX_INSTRUCTIONS);
+}
+
+/// `compare`, running each profile for at most `max_instructions`.
+pub fn compare_with_limit(program: &[u8], profiles: &[QuirkProfile], max_instructions: u64) -> ProfileComparison {
+    let runs: Vec<ProfileRun> = profiles.iter().map(|profile| {
+        let output = Output::default();
+        let mut processor: Processor = ProcessorBuilder::new().quirks(*profile).build();
+        processor.set_event_sink(Some(Box::new(output.clone())));
+        processor.load_program(program);
+        let outcome: RunOutcome = processor.run_with_limit(max_instructions);
+        let writes: Vec<(u8, u8)> = output.writes.lock().unwrap().clone();
+        return ProfileRun {
+            profile: *profile,
+            outcome,
+            instructions: processor.instruction_count(),
+            state_hash: processor.snapshot().state_hash(),
+            output: writes,
+        };
+    }).collect();
+    return ProfileComparison { runs };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Leaves AC set with an addition, then decimal adjusts the result of
+    /// an ANI whose operands have bit 3 clear and outputs it. Classic
+    /// leaves AC set and the 8085 sets it, so both print 06h; the 8080
+    /// clears it and prints 00h.
+    const AC_AFTER_ANI: [u8; 12] = [
+        0x3e, 0x0f, // 0000 MVI A,0Fh
+        0xc6, 0x01, // 0002 ADI 01h: AC set
+        0x3e, 0x01, // 0004 MVI A,01h
+        0xe6, 0x02, // 0006 ANI 02h: A is 0
+        0x27,       // 0008 DAA
+        0xd3, 0x01, // 0009 OUT 01h
+        0x76,       // 000b HLT
+    ];
+
+    #[test]
+    fn test_splits_profiles_on_aux_carry_after_ani() {
+        let comparison: ProfileComparison = compare(&AC_AFTER_ANI, &QuirkProfile::ALL);
+
+        assert_eq!(comparison.groups(), vec![
+            vec![QuirkProfile::Classic, QuirkProfile::Intel8085],
+            vec![QuirkProfile::Intel8080],
+        ]);
+        assert!(!comparison.unanimous());
+        let outputs: Vec<&[(u8, u8)]> = comparison.runs.iter().map(|run| run.output.as_slice()).collect();
+        assert_eq!(outputs, [&[(0x01, 0x06)][..], &[(0x01, 0x00)], &[(0x01, 0x06)]]);
+        assert!(comparison.runs.iter().all(|run| run.outcome == RunOutcome::Halted));
+
+        let table: String = comparison.to_string();
+        assert!(table.lines().nth(2).unwrap().starts_with("B      i8080    halted"), "{}", table);
+        assert!(table.ends_with("A: classic, i8085\nB: i8080\n"), "{}", table);
+    }
+
+    #[test]
+    fn test_programs_without_the_quirk_agree() {
+        let comparison: ProfileComparison = compare(&[0x3e, 0x2a, 0xd3, 0x01, 0x76], &QuirkProfile::ALL); // MVI A,2Ah; OUT 01h; HLT
+
+        assert_eq!(comparison.groups().len(), 1);
+        assert!(comparison.to_string().ends_with("all profiles agree\n"));
+    }
+
+    #[test]
+    fn test_runs_stop_at_the_limit() {
+        let comparison: ProfileComparison = compare_with_limit(&[0xc3, 0x00, 0x00], &[QuirkProfile::Classic], 10); // JMP 0
+
+        assert_eq!(comparison.runs[0].outcome, RunOutcome::InstructionLimit { instructions: 10 });
+    }
+}
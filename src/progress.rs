@@ -0,0 +1,179 @@
+use std::fmt;
+use std::time::Duration;
+
+/// A point-in-time view of how far a run has got.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Instructions executed since the run started.
+    pub instructions: u64,
+    /// Clock cycles executed since the run started.
+    pub cycles: u64,
+    /// Wall-clock time since the run started.
+    pub elapsed: Duration,
+    /// The instruction bound for the run, if there is one.
+    pub max_instructions: Option<u64>,
+}
+
+impl Progress {
+    /// Emulated clock speed so far, in MHz.
+    pub fn emulated_mhz(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        return self.cycles as f64 / secs / 1_000_000.0;
+    }
+
+    /// Percentage of the instruction bound used, when there is a bound.
+    pub fn percent_complete(&self) -> Option<f64> {
+        let max = self.max_instructions?;
+        if max == 0 {
+            return Some(100.0);
+        }
+        return Some(self.instructions.min(max) as f64 * 100.0 / max as f64);
+    }
+
+    /// Estimated time until the instruction bound is reached, assuming the
+    /// rate so far holds.
+    pub fn eta(&self) -> Option<Duration> {
+        let max = self.max_instructions?;
+        if self.instructions == 0 {
+            return None;
+        }
+        let remaining = max.saturating_sub(self.instructions);
+        let per_instruction = self.elapsed.as_secs_f64() / self.instructions as f64;
+        return Some(Duration::from_secs_f64(per_instruction * remaining as f64));
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instructions, {:.2} MHz, {:.1}s elapsed",
+            self.instructions, self.emulated_mhz(), self.elapsed.as_secs_f64())?;
+        if let (Some(percent), Some(eta)) = (self.percent_complete(), self.eta()) {
+            write!(f, ", {:.1}% complete, ETA {:.1}s", percent, eta.as_secs_f64())?;
+        }
+        return Ok(());
+    }
+}
+
+/// Somewhere to send progress updates: a terminal line, a UI widget, a log.
+pub trait ProgressSink: Send {
+    fn report(&mut self, progress: &Progress);
+
+    /// Called once when the run stops, with the final figures.
+    fn finish(&mut self, progress: &Progress) {
+        self.report(progress);
+    }
+}
+
+/// Rate-limits updates to a `ProgressSink`.
+pub struct ProgressReporter {
+    sink: Box<dyn ProgressSink>,
+    min_interval: Duration,
+    last_report: Option<Duration>,
+}
+
+impl ProgressReporter {
+    /// Creates a reporter that forwards at most one update per `min_interval`.
+    pub fn new(sink: Box<dyn ProgressSink>, min_interval: Duration) -> ProgressReporter {
+        return ProgressReporter { sink, min_interval, last_report: None };
+    }
+
+    /// Forwards `progress` to the sink if enough time has passed since the
+    /// last update. Returns whether it did.
+    pub fn poll(&mut self, progress: &Progress) -> bool {
+        let since = match self.last_report {
+            Some(last) => progress.elapsed.saturating_sub(last),
+            None => progress.elapsed,
+        };
+        if since < self.min_interval {
+            return false;
+        }
+        self.last_report = Some(progress.elapsed);
+        self.sink.report(progress);
+        return true;
+    }
+
+    pub fn finish(&mut self, progress: &Progress) {
+        self.last_report = None;
+        self.sink.finish(progress);
+    }
+}
+
+impl fmt::Debug for ProgressReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_struct("ProgressReporter")
+            .field("min_interval", &self.min_interval)
+            .finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct RecordingSink {
+        reports: Arc<Mutex<Vec<Progress>>>,
+        finished: Arc<Mutex<Option<Progress>>>,
+    }
+
+    impl ProgressSink for RecordingSink {
+        fn report(&mut self, progress: &Progress) {
+            self.reports.lock().unwrap().push(*progress);
+        }
+
+        fn finish(&mut self, progress: &Progress) {
+            *self.finished.lock().unwrap() = Some(*progress);
+        }
+    }
+
+    fn at(millis: u64, instructions: u64, max_instructions: Option<u64>) -> Progress {
+        return Progress {
+            instructions,
+            cycles: instructions * 5,
+            elapsed: Duration::from_millis(millis),
+            max_instructions,
+        };
+    }
+
+    #[test]
+    fn test_update_cadence() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let finished = Arc::new(Mutex::new(None));
+        let sink = RecordingSink { reports: reports.clone(), finished: finished.clone() };
+        let mut reporter = ProgressReporter::new(Box::new(sink), Duration::from_millis(250));
+
+        let polled: Vec<bool> = [0, 100, 260, 300, 509, 510, 2000].iter()
+            .map(|millis| reporter.poll(&at(*millis, millis * 1000, None)))
+            .collect();
+        reporter.finish(&at(2100, 2_100_000, None));
+
+        assert_eq!(polled, vec![false, false, true, false, false, true, true]);
+        let elapsed: Vec<u128> = reports.lock().unwrap().iter().map(|p| p.elapsed.as_millis()).collect();
+        assert_eq!(elapsed, vec![260, 510, 2000]);
+        assert_eq!(finished.lock().unwrap().unwrap().instructions, 2_100_000);
+    }
+
+    #[test]
+    fn test_rates() {
+        let progress = at(2000, 1_000_000, Some(4_000_000));
+
+        assert_eq!(progress.emulated_mhz(), 2.5);
+        assert_eq!(progress.percent_complete(), Some(25.0));
+        assert_eq!(progress.eta(), Some(Duration::from_secs(6)));
+        assert_eq!(progress.to_string(),
+            "1000000 instructions, 2.50 MHz, 2.0s elapsed, 25.0% complete, ETA 6.0s");
+    }
+
+    #[test]
+    fn test_rates_without_bound() {
+        let progress = at(0, 0, None);
+
+        assert_eq!(progress.emulated_mhz(), 0.0);
+        assert_eq!(progress.percent_complete(), None);
+        assert_eq!(progress.eta(), None);
+        assert_eq!(progress.to_string(), "0 instructions, 0.00 MHz, 0.0s elapsed");
+    }
+}
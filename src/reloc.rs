@@ -0,0 +1,170 @@
+//! Best-effort relocation of a binary assembled for one origin so it can run
+//! at another.
+//!
+//! The pass sweeps the image linearly, decoding instructions back to back,
+//! and rewrites the operand of every three-byte instruction (LXI, LHLD, SHLD,
+//! LDA, STA, JMP, CALL and their conditional forms) whose value points
+//! inside the original image. A linear sweep cannot tell code from data, so
+//! data regions must be listed in `RelocOptions::data_ranges`, and every
+//! change is reported for review.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::processor::instruction_length;
+use crate::word::{hi, lo, word};
+
+#[derive(Debug, Clone, Default)]
+pub struct RelocOptions {
+    /// Address ranges, in the original address space, that hold data and
+    /// must not be decoded or rewritten.
+    pub data_ranges: Vec<Range<u16>>,
+}
+
+/// One rewritten operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rewrite {
+    /// Address of the instruction, in the original address space.
+    pub addr: u16,
+    pub old: u16,
+    pub new: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelocResult {
+    pub from: u16,
+    pub to: u16,
+    /// The relocated image, to be loaded at `to`.
+    pub bytes: Vec<u8>,
+    pub rewrites: Vec<Rewrite>,
+    /// Addresses, in the original address space, skipped as data.
+    pub skipped: Vec<u16>,
+}
+
+pub fn relocate(bytes: &[u8], from: u16, to: u16, options: &RelocOptions) -> RelocResult {
+    let mut result = RelocResult { from, to, bytes: bytes.to_vec(), rewrites: Vec::new(), skipped: Vec::new() };
+    let image = from as u32..from as u32 + bytes.len() as u32;
+    let mut offset: usize = 0;
+
+    while offset < bytes.len() {
+        let addr = from.wrapping_add(offset as u16);
+        if options.data_ranges.iter().any(|range| range.contains(&addr)) {
+            result.skipped.push(addr);
+            offset += 1;
+            continue;
+        }
+
+        let len = instruction_length(bytes[offset]) as usize;
+        if len == 3 && offset + 2 < bytes.len() {
+            let old = word(bytes[offset + 2], bytes[offset + 1]);
+            if image.contains(&(old as u32)) {
+                let new = old.wrapping_sub(from).wrapping_add(to);
+                result.bytes[offset + 1] = lo(new);
+                result.bytes[offset + 2] = hi(new);
+                result.rewrites.push(Rewrite { addr, old, new });
+            }
+        }
+        offset += len;
+    }
+
+    return result;
+}
+
+impl fmt::Display for RelocResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "relocated {} bytes from {:#06x} to {:#06x}", self.bytes.len(), self.from, self.to)?;
+        for rewrite in &self.rewrites {
+            writeln!(f, "  {:#06x}: operand {:#06x} -> {:#06x}", rewrite.addr, rewrite.old, rewrite.new)?;
+        }
+
+        let mut runs: Vec<(u16, u16)> = Vec::new();
+        for addr in &self.skipped {
+            match runs.last_mut() {
+                Some((_, end)) if end.wrapping_add(1) == *addr => *end = *addr,
+                _ => runs.push((*addr, *addr)),
+            }
+        }
+        for (start, end) in runs {
+            writeln!(f, "  skipped data {:#06x}-{:#06x}", start, end)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{make_processor, RunOutcome};
+
+    /// Assembled for ORG 0:
+    ///
+    /// ```text
+    /// 0000  lxi sp, 3000h
+    /// 0003  lhld data
+    /// 0006  jmp skip
+    /// 0009  hlt
+    /// 000a  hlt
+    /// 000b  skip: call sub
+    /// 000e  jmp done
+    /// 0011  hlt
+    /// 0012  data: dw 1121h
+    /// 0014  sub: nop
+    /// 0015  inr a
+    /// 0016  ret
+    /// 0017  done: hlt
+    /// ```
+    ///
+    /// The data word decodes as `lxi h, 0011h`, which a sweep that did not
+    /// know about it would rewrite.
+    const PROGRAM: [u8; 24] = [
+        0x31, 0x00, 0x30, 0x2a, 0x12, 0x00, 0xc3, 0x0b, 0x00, 0x76, 0x76, 0xcd,
+        0x14, 0x00, 0xc3, 0x17, 0x00, 0x76, 0x21, 0x11, 0x00, 0x3c, 0xc9, 0x76,
+    ];
+
+    fn options() -> RelocOptions {
+        return RelocOptions { data_ranges: vec![Range { start: 0x0012, end: 0x0014 }] };
+    }
+
+    #[test]
+    fn test_relocate_rewrites_in_image_operands() {
+        let result = relocate(&PROGRAM, 0x0000, 0x4000, &options());
+
+        assert_eq!(result.rewrites, vec![
+            Rewrite { addr: 0x0003, old: 0x0012, new: 0x4012 },
+            Rewrite { addr: 0x0006, old: 0x000b, new: 0x400b },
+            Rewrite { addr: 0x000b, old: 0x0014, new: 0x4014 },
+            Rewrite { addr: 0x000e, old: 0x0017, new: 0x4017 },
+        ]);
+        assert_eq!(result.skipped, vec![0x0012, 0x0013]);
+        assert_eq!(&result.bytes[0x12..0x14], &[0x21, 0x11]);
+        assert_eq!(&result.bytes[0x00..0x03], &[0x31, 0x00, 0x30]);
+        assert_eq!(result.to_string(), "\
+relocated 24 bytes from 0x0000 to 0x4000
+  0x0003: operand 0x0012 -> 0x4012
+  0x0006: operand 0x000b -> 0x400b
+  0x000b: operand 0x0014 -> 0x4014
+  0x000e: operand 0x0017 -> 0x4017
+  skipped data 0x0012-0x0013
+");
+    }
+
+    #[test]
+    fn test_relocate_without_data_ranges_corrupts_data() {
+        let result = relocate(&PROGRAM, 0x0000, 0x4000, &RelocOptions::default());
+
+        assert!(result.rewrites.contains(&Rewrite { addr: 0x0012, old: 0x0011, new: 0x4011 }));
+        assert!(result.skipped.is_empty());
+    }
+
+    #[test]
+    fn test_relocated_program_runs_at_new_origin() {
+        let result = relocate(&PROGRAM, 0x0000, 0x4000, &options());
+        let mut processor = make_processor();
+        processor.load_at(0x4000, &result.bytes);
+        processor.set_pc(0x4000);
+
+        assert_eq!(processor.run_with_limit(100), RunOutcome::Halted);
+        assert_eq!(processor.pc(), 0x4018);
+        assert!(processor.register_summary().starts_with("A=01 B=00 C=00 D=00 E=00 H=11 L=21 SP=3000"));
+    }
+}
@@ -0,0 +1,420 @@
+//! Cutting a failing run down to a test. Given a checkpoint from before
+//! the failure and where it happened, `extract` replays up to a short
+//! window before it and keeps only what that window needs: the registers
+//! at its start, the bytes it executed or read, the values its INs got,
+//! and what the failing instruction should have done. The resulting
+//! `ReproCase` is saved as JSON under `tests/repro/`, where
+//! `tests/repro.rs` runs every case, so it fails until the bug is fixed and
+//! guards against it coming back after.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::processor::{Event, EventLog, InterruptController, IoContext, IoDevice, Processor, ProcessorBuilder, Registers,
+                       RestoreError, RunOutcome, Snapshot, MEMORY_SIZE, SNAPSHOT_VERSION};
+
+/// The case layout this build writes.
+pub const REPRO_VERSION: u32 = 1;
+
+/// Instructions kept before the failing one unless told otherwise.
+pub const DEFAULT_WINDOW: u64 = 32;
+
+/// Where cases live, relative to the crate root.
+pub const REPRO_DIR: &str = "tests/repro";
+
+/// What the failing instruction should have done.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Expectation {
+    /// Left the registers as a reference did, for a lockstep divergence.
+    Registers { registers: Registers },
+    /// Ran without a fault, for a strict-mode fault. Cases expecting this
+    /// run in strict mode.
+    NoFault,
+}
+
+/// Where a run failed and how much of it to keep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    /// The failing instruction, counted from 1 as the instruction count
+    /// once it completes: the first after which the run and its reference
+    /// differ, or for a fault, the count the run stopped at plus one. The
+    /// window ends early at the fault, since some faults stop the
+    /// instruction before it is counted and some after.
+    pub failure: u64,
+    /// Instructions to keep before the failing one. Fewer are kept if the
+    /// snapshot is closer to the failure than that.
+    pub before: u64,
+    pub expected: Expectation,
+}
+
+/// Bytes at consecutive addresses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Segment {
+    pub addr: u16,
+    #[serde(with = "crate::processor::hex_bytes")]
+    pub bytes: Vec<u8>,
+}
+
+/// A value an IN read during the window, replayed in order on that port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortInput {
+    pub port: u8,
+    pub value: u8,
+}
+
+/// A failing run cut down to the instructions around the failure.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReproCase {
+    pub format_version: u32,
+    /// Where the case was cut from, for whoever reads it later.
+    pub origin: String,
+    /// The state at the start of the window.
+    pub registers: Registers,
+    pub halted: bool,
+    pub interrupt_enabled: bool,
+    pub ei_delay: bool,
+    pub interrupts: InterruptController,
+    /// The bytes the window executed or read, as they were at its start.
+    /// Everything else is zero.
+    pub memory: Vec<Segment>,
+    pub inputs: Vec<PortInput>,
+    /// Instructions in the window, the failing one last.
+    pub instructions: u64,
+    pub expected: Expectation,
+    /// What the failing run did instead.
+    pub actual: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReproError {
+    /// The snapshot was taken after the failure.
+    AfterFailure { snapshot: u64, failure: u64 },
+    /// The replay stopped before reaching the window or the failure.
+    Stopped { instructions: u64, outcome: RunOutcome },
+    /// The window ran as expected, so there is nothing to reproduce.
+    NotReproduced,
+    /// The snapshot does not fit the processor it was replayed on.
+    Restore { error: RestoreError },
+}
+
+impl fmt::Display for ReproError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            ReproError::AfterFailure { snapshot, failure } =>
+                write!(f, "snapshot at instruction {} is past the failure at {}", snapshot, failure),
+            ReproError::Stopped { instructions, outcome } =>
+                write!(f, "replay stopped at instruction {}: {:?}", instructions, outcome),
+            ReproError::NotReproduced => write!(f, "the window ran as expected"),
+            ReproError::Restore { error } => write!(f, "{}", error),
+        };
+    }
+}
+
+impl std::error::Error for ReproError {}
+
+/// Cuts the failure `window` describes out of a run resumed from
+/// `snapshot`, on a processor with no devices. A strict-mode fault is
+/// replayed in strict mode.
+pub fn extract(snapshot: &Snapshot, window: &Window) -> Result<ReproCase, ReproError> {
+    let strict: bool = window.expected == Expectation::NoFault;
+    return extract_with(ProcessorBuilder::new().strict(strict).build(), snapshot, window);
+}
+
+/// `extract` on `processor`, set up as the failing run was apart from its
+/// state, e.g. with its hooks and devices attached.
+pub fn extract_with(mut processor: Processor, snapshot: &Snapshot, window: &Window) -> Result<ReproCase, ReproError> {
+    if window.failure <= snapshot.instructions {
+        return Err(ReproError::AfterFailure { snapshot: snapshot.instructions, failure: window.failure });
+    }
+    processor.restore(snapshot).map_err(|error| ReproError::Restore { error })?;
+    let start: u64 = window.failure.saturating_sub(window.before + 1).max(snapshot.instructions);
+    if start > snapshot.instructions {
+        let outcome: RunOutcome = processor.run_with_limit(start - snapshot.instructions);
+        if !matches!(outcome, RunOutcome::InstructionLimit { .. }) {
+            return Err(ReproError::Stopped { instructions: processor.instruction_count(), outcome });
+        }
+    }
+    let initial: Snapshot = processor.snapshot();
+
+    let log: EventLog = EventLog::new(usize::MAX);
+    processor.set_event_sink(Some(Box::new(log.clone())));
+    processor.set_access_tracking(true);
+    // One at a time, so the case runs exactly as many as the window did.
+    let mut instructions: u64 = 0;
+    let mut outcome: RunOutcome = RunOutcome::InstructionLimit { instructions: start };
+    while instructions < window.failure - start && matches!(outcome, RunOutcome::InstructionLimit { .. }) {
+        outcome = processor.run_with_limit(1);
+        if outcome != RunOutcome::Halted {
+            instructions += 1;
+        }
+    }
+    let actual: String = match (&outcome, &window.expected) {
+        (RunOutcome::Fault(err), Expectation::NoFault) => err.to_string(),
+        (RunOutcome::Fault(_), _) => return Err(ReproError::Stopped { instructions: processor.instruction_count(), outcome }),
+        (_, Expectation::NoFault) => return Err(ReproError::NotReproduced),
+        (_, Expectation::Registers { registers }) if processor.registers() == *registers => return Err(ReproError::NotReproduced),
+        _ => processor.registers().to_string(),
+    };
+
+    let access = processor.access_map().expect("access tracking is on");
+    let touched: Vec<u16> = (0..MEMORY_SIZE).map(|addr| addr as u16).filter(|addr| access.executed(*addr) || access.read(*addr)).collect();
+    let inputs: Vec<PortInput> = log.events().into_iter().filter_map(|event| match event.event {
+        Event::PortRead { port, value } => Some(PortInput { port, value }),
+        _ => None,
+    }).collect();
+    return Ok(ReproCase {
+        format_version: REPRO_VERSION,
+        origin: format!("instructions {} to {} resumed from instruction {}", start + 1, window.failure, snapshot.instructions),
+        registers: initial.registers,
+        halted: initial.halted,
+        interrupt_enabled: initial.interrupt_enabled,
+        ei_delay: initial.ei_delay,
+        interrupts: initial.interrupts,
+        memory: segments(&touched, &initial.memory),
+        inputs,
+        instructions,
+        expected: window.expected.clone(),
+        actual,
+    });
+}
+
+/// `addrs`, in increasing order, as runs of consecutive bytes of `memory`.
+fn segments(addrs: &[u16], memory: &[u8]) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for addr in addrs {
+        match segments.last_mut() {
+            Some(segment) if segment.addr as usize + segment.bytes.len() == *addr as usize => segment.bytes.push(memory[*addr as usize]),
+            _ => segments.push(Segment { addr: *addr, bytes: vec![memory[*addr as usize]] }),
+        }
+    }
+    return segments;
+}
+
+/// Answers IN with the values the case recorded, in order for each port,
+/// and 0 once they run out.
+#[derive(Debug, Default)]
+struct Replay {
+    inputs: BTreeMap<u8, VecDeque<u8>>,
+}
+
+impl IoDevice for Replay {
+    fn read(&mut self, port: u16, _ctx: &IoContext) -> u8 {
+        return self.inputs.get_mut(&(port as u8)).and_then(VecDeque::pop_front).unwrap_or(0);
+    }
+
+    fn write(&mut self, _port: u16, _value: u8, _ctx: &IoContext) {}
+}
+
+impl ReproCase {
+    /// A processor as the window starts, strict if the case expects no
+    /// fault, with the recorded inputs attached to their ports.
+    pub fn processor(&self) -> Processor {
+        let mut processor: Processor = ProcessorBuilder::new().strict(self.expected == Expectation::NoFault).build();
+        processor.restore(&Snapshot {
+            format_version: SNAPSHOT_VERSION,
+            registers: self.registers,
+            halted: self.halted,
+            interrupt_enabled: self.interrupt_enabled,
+            interrupts: self.interrupts.clone(),
+            schedule: None,
+            ei_delay: self.ei_delay,
+            ei_seen: false,
+            instructions: 0,
+            cycles: 0,
+            memory: vec![0; MEMORY_SIZE],
+            devices: Vec::new(),
+            strict_initialized: None,
+        }).expect("a new processor has no devices");
+        for segment in &self.memory {
+            processor.load_at(segment.addr, &segment.bytes);
+        }
+        let mut replay: Replay = Replay::default();
+        for input in &self.inputs {
+            replay.inputs.entry(input.port).or_default().push_back(input.value);
+        }
+        let ports: Vec<u8> = replay.inputs.keys().copied().collect();
+        processor.attach_device(&ports, Box::new(replay));
+        return processor;
+    }
+
+    /// Runs the window on `processor`, from `processor()` with whatever a
+    /// test changes, and checks the failing instruction did what was
+    /// expected.
+    pub fn check(&self, processor: &mut Processor) -> Result<(), String> {
+        let outcome: RunOutcome = processor.run_with_limit(self.instructions);
+        if let RunOutcome::Fault(err) = outcome {
+            return Err(format!("faulted: {}", err));
+        }
+        return match &self.expected {
+            Expectation::Registers { registers } if processor.registers() != *registers =>
+                Err(format!("expected {}, got {}", registers, processor.registers())),
+            _ => Ok(()),
+        };
+    }
+}
+
+/// Writes `case` as JSON to `path`, creating its directory.
+pub fn save(case: &ReproCase, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json: String = serde_json::to_string_pretty(case).map_err(io::Error::other)?;
+    return fs::write(path, json + "\n");
+}
+
+pub fn load(path: &Path) -> io::Result<ReproCase> {
+    let json: Vec<u8> = fs::read(path)?;
+    return serde_json::from_slice(&json).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err));
+}
+
+/// Every case in `dir`, by file name. A missing directory has none.
+pub fn load_dir(dir: &Path) -> io::Result<Vec<(PathBuf, ReproCase)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    paths.retain(|path| path.extension().is_some_and(|extension| extension == "json"));
+    paths.sort();
+    return paths.into_iter().map(|path| load(&path).map(|case| (path, case))).collect();
+}
+
+#[cfg(all(test, feature = "asm"))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    use crate::asm;
+    use crate::processor::{make_processor, ExtensionResult, VectorAction};
+
+    /// Sums 1 to 40 into A, then adds the carry at `adjust` twice more.
+    const PROGRAM: &str = "
+            mvi a, 0
+            mvi b, 40
+    sum:    add b
+            dcr b
+            jnz sum
+            in 10h
+            mov c, a
+    adjust: aci 0
+            aci 0
+            hlt";
+
+    /// `adjust`'s address in `PROGRAM`.
+    const ADJUST: u16 = 0x000c;
+
+    /// What a hardware reference would do at `adjust`, injected as a hook
+    /// that sets carry before it. Also the fix a test applies.
+    fn set_carry(processor: &mut Processor) {
+        processor.install_pc_hook(ADJUST, Box::new(|processor: &mut Processor| {
+            let mut registers: Registers = processor.registers();
+            registers.flags.carry = true;
+            processor.set_registers(registers);
+            return VectorAction::FallThrough;
+        }));
+    }
+
+    fn loaded() -> Processor {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&asm::assemble(PROGRAM).unwrap().bytes);
+        return processor;
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir: PathBuf = env::temp_dir().join(format!("i8080-repro-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        return dir;
+    }
+
+    /// Runs `run` and `reference` in lockstep, checkpointing `run` every
+    /// `interval` instructions, and returns the first instruction after
+    /// which they differ, the reference's registers then, and the last
+    /// checkpoint before it.
+    fn lockstep(run: &mut Processor, reference: &mut Processor, interval: u64) -> (u64, Registers, Snapshot) {
+        let mut checkpoint: Snapshot = run.snapshot();
+        loop {
+            if run.instruction_count().is_multiple_of(interval) {
+                checkpoint = run.snapshot();
+            }
+            run.step();
+            reference.step();
+            if run.registers() != reference.registers() {
+                return (run.instruction_count(), reference.registers(), checkpoint);
+            }
+        }
+    }
+
+    #[test]
+    fn test_divergence_case_fails_until_fixed() {
+        let (mut run, mut reference) = (loaded(), loaded());
+        set_carry(&mut reference);
+        let (failure, registers, checkpoint) = lockstep(&mut run, &mut reference, 50);
+        assert_eq!((failure, checkpoint.instructions), (125, 100));
+
+        let window = Window { failure, before: 4, expected: Expectation::Registers { registers } };
+        let case: ReproCase = extract(&checkpoint, &window).unwrap();
+
+        assert_eq!(case.instructions, 5);
+        assert_eq!(case.registers.pc, 0x0005);
+        // The last JNZ falls through without reading its operand.
+        assert_eq!(case.memory, vec![Segment { addr: 0x0005, bytes: vec![0x05, 0xc2] },
+                                     Segment { addr: 0x0009, bytes: vec![0xdb, 0x10, 0x4f, 0xce, 0x00] }]);
+        assert_eq!(case.inputs, vec![PortInput { port: 0x10, value: 0 }]);
+
+        let path: PathBuf = temp_dir("divergence").join("aci.json");
+        save(&case, &path).unwrap();
+        let (loaded_path, loaded_case) = load_dir(path.parent().unwrap()).unwrap().remove(0);
+        assert_eq!((loaded_path, &loaded_case), (path.clone(), &case));
+
+        assert_eq!(loaded_case.check(&mut loaded_case.processor()).unwrap_err(),
+                   format!("expected {}, got {}", registers, case.actual));
+        let mut fixed: Processor = loaded_case.processor();
+        set_carry(&mut fixed);
+        assert_eq!(loaded_case.check(&mut fixed), Ok(()));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_fault_case_fails_until_fixed() {
+        let mut processor: Processor = ProcessorBuilder::new().strict(true).build();
+        processor.load_program(&[
+            0x3e, 0x07, // 0000 MVI A,7
+            0x3d,       // 0002 DCR A
+            0xc2, 0x02, 0x00, // 0003 JNZ 0002h
+            0x08,       // 0006 undocumented NOP
+            0x76,       // 0007 HLT
+        ]);
+        let checkpoint: Snapshot = processor.snapshot();
+        let RunOutcome::Fault(_) = processor.run() else {
+            panic!("expected a fault");
+        };
+        let window = Window { failure: processor.instruction_count() + 1, before: 2, expected: Expectation::NoFault };
+
+        let case: ReproCase = extract(&checkpoint, &window).unwrap();
+
+        assert_eq!(case.actual, "invalid opcode 0x08 at 0x0006 (nop alias)");
+        assert_eq!(case.check(&mut case.processor()), Err(format!("faulted: {}", case.actual)));
+        let mut fixed: Processor = case.processor();
+        fixed.set_opcode_extension(Some(Box::new(|_: &mut Processor, _: u8| ExtensionResult::Handled { cycles: 4 })));
+        assert_eq!(case.check(&mut fixed), Ok(()));
+    }
+
+    #[test]
+    fn test_nothing_to_reproduce() {
+        let mut processor: Processor = loaded();
+        let checkpoint: Snapshot = processor.snapshot();
+        processor.run_with_limit(10);
+        let window = Window { failure: 10, before: 4, expected: Expectation::Registers { registers: processor.registers() } };
+
+        assert_eq!(extract(&checkpoint, &window), Err(ReproError::NotReproduced));
+        assert_eq!(extract(&processor.snapshot(), &window), Err(ReproError::AfterFailure { snapshot: 10, failure: 10 }));
+    }
+}
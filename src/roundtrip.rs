@@ -0,0 +1,206 @@
+//! Disassemble-then-reassemble verification, to keep `disasm` and `asm`
+//! agreeing on syntax.
+
+use std::fmt;
+
+use crate::asm::{self, AsmError};
+use crate::disasm::{self, Line};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoundtripError {
+    /// The disassembly was not accepted by the assembler.
+    Assemble(AsmError),
+    /// The reassembled image differs. Each rendering is the disassembled
+    /// line covering `offset`, or `<end>` if that image is shorter.
+    Mismatch { offset: usize, original: String, reassembled: String },
+}
+
+impl fmt::Display for RoundtripError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            RoundtripError::Assemble(err) => write!(f, "disassembly does not assemble: {}", err),
+            RoundtripError::Mismatch { offset, original, reassembled } => write!(
+                f,
+                "first mismatch at offset 0x{:04x}\n  original:    {}\n  reassembled: {}",
+                offset, original, reassembled),
+        };
+    }
+}
+
+fn rendering(lines: &[Line], origin: u16, offset: usize) -> String {
+    let line = lines.iter().find(|line| {
+        let start: usize = line.addr.wrapping_sub(origin) as usize;
+        return start <= offset && offset < start + line.bytes.len();
+    });
+    return match line {
+        Some(line) => line.to_string(),
+        None => "<end>".to_string(),
+    };
+}
+
+/// Disassembles `bytes` linearly from `origin`, reassembles the source, and
+/// checks the result is byte-for-byte identical.
+pub fn roundtrip(bytes: &[u8], origin: u16) -> Result<(), RoundtripError> {
+    let lines: Vec<Line> = disasm::disassemble(bytes, origin);
+    return reassembles(bytes, origin, &disasm::to_source(&lines, origin), &lines);
+}
+
+/// Like `roundtrip`, for the labelled source `disasm::to_labelled_source`
+/// makes following code from `roots`.
+pub fn roundtrip_labelled(bytes: &[u8], origin: u16, roots: &[u16]) -> Result<(), RoundtripError> {
+    let source: String = disasm::to_labelled_source(bytes, origin, roots);
+    return reassembles(bytes, origin, &source, &disasm::disassemble(bytes, origin));
+}
+
+/// Checks `source` assembles to `bytes`, showing a mismatch as `lines`
+/// have it.
+fn reassembles(bytes: &[u8], origin: u16, source: &str, lines: &[Line]) -> Result<(), RoundtripError> {
+    let reassembled: Vec<u8> = asm::assemble(source).map_err(RoundtripError::Assemble)?.bytes;
+
+    let offset: Option<usize> = bytes.iter().zip(&reassembled).position(|(a, b)| a != b);
+    let offset: usize = match offset {
+        Some(offset) => offset,
+        None if bytes.len() == reassembled.len() => return Ok(()),
+        None => bytes.len().min(reassembled.len()),
+    };
+    return Err(RoundtripError::Mismatch {
+        offset,
+        original: rendering(lines, origin, offset),
+        reassembled: rendering(&disasm::disassemble(&reassembled, origin), origin, offset),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_fixtures_roundtrip() {
+        for entry in fs::read_dir("tests").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "bin") {
+                let bytes: Vec<u8> = fs::read(&path).unwrap();
+                if let Err(err) = roundtrip(&bytes, 0) {
+                    panic!("{}: {}", path.display(), err);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_capitalize_as_labelled_source() {
+        let bytes: Vec<u8> = fs::read("tests/capitalize.bin").unwrap();
+        let source: String = disasm::to_labelled_source(&bytes, 0, &[0]);
+
+        assert_eq!(source.lines().collect::<Vec<&str>>(), [
+            "  org 0000h",
+            "l_0000:",
+            "  lxi sp, 9fffh",
+            "  lxi h, 0026h",
+            "  mvi c, 0eh",
+            "  call l_000c",
+            "  hlt",
+            "l_000c:",
+            "  mov a, c",
+            "  cpi 00h",
+            "  jz l_0025",
+            "  mov a, m",
+            "  cpi 61h",
+            "  jc l_0020",
+            "  cpi 7bh",
+            "  jnc l_0020",
+            "  sui 20h",
+            "  mov m, a",
+            "l_0020:",
+            "  inx h",
+            "  dcr c",
+            "  jmp l_000c",
+            "l_0025:",
+            "  ret",
+            "  db 68h, 65h, 6ch, 6ch, 6fh, 2ch, 20h, 66h",
+            "  db 72h, 69h, 65h, 6eh, 64h, 73h",
+        ]);
+        assert_eq!(asm::assemble(&source).unwrap().bytes, bytes);
+        assert_eq!(roundtrip_labelled(&bytes, 0, &[0]), Ok(()));
+    }
+
+    #[test]
+    fn test_labelled_source_keeps_data_and_overlaps_exact() {
+        let bytes: Vec<u8> = vec![
+            0x2a, 0x0a, 0x01, // 0100 lhld table
+            0xc3, 0x05, 0x01, // 0103 jmp 0105h, into its own operand
+            0x76, 0x00,       // 0106 the rest of lxi b, 0076h at 0105h
+            0xe9,             // 0108 pchl
+            0x76,             // 0109 never reached
+            0x08, 0x01,       // 010a table: the address pchl goes to
+        ];
+        let source: String = disasm::to_labelled_source(&bytes, 0x100, &[0x100, 0x108]);
+
+        assert_eq!(source.lines().collect::<Vec<&str>>(), [
+            "  org 0100h",
+            "l_0100:",
+            "  lhld l_010a",
+            "  jmp 0105h",
+            "  db 76h, 00h",
+            "l_0108:",
+            "  pchl",
+            "  db 76h",
+            "l_010a:",
+            "  dw l_0108",
+        ]);
+        assert_eq!(roundtrip_labelled(&bytes, 0x100, &[0x100, 0x108]), Ok(()));
+    }
+
+    #[test]
+    fn test_fixtures_roundtrip_as_labelled_source() {
+        for entry in fs::read_dir("tests").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "bin") {
+                let bytes: Vec<u8> = fs::read(&path).unwrap();
+                if let Err(err) = roundtrip_labelled(&bytes, 0, &[0]) {
+                    panic!("{}: {}", path.display(), err);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_opcode_roundtrips() {
+        let mut bytes: Vec<u8> = Vec::new();
+        for opcode in 0..=255u8 {
+            bytes.extend_from_slice(&[opcode, 0xa5, 0x5a]);
+        }
+
+        assert_eq!(roundtrip(&bytes, 0x4000), Ok(()));
+    }
+
+    #[test]
+    fn test_generated_programs_roundtrip() {
+        let mut seed: u32 = 0x8080;
+        for _ in 0..50 {
+            let bytes: Vec<u8> = (0..512).map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                return (seed >> 16) as u8;
+            }).collect();
+
+            assert_eq!(roundtrip(&bytes, seed as u16 & 0x7fff), Ok(()));
+            assert_eq!(roundtrip_labelled(&bytes, seed as u16 & 0x7fff, &[seed as u16 & 0x7fff]), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_mismatch_report() {
+        let lines: Vec<Line> = disasm::disassemble(&[0x00, 0x3e, 0x05], 0x100);
+
+        assert_eq!(rendering(&lines, 0x100, 2), "0101  3e 05     mvi a, 05h");
+        assert_eq!(rendering(&lines, 0x100, 3), "<end>");
+        let err = RoundtripError::Mismatch {
+            offset: 2,
+            original: rendering(&lines, 0x100, 2),
+            reassembled: "<end>".to_string(),
+        };
+        assert_eq!(err.to_string(),
+                   "first mismatch at offset 0x0002\n  original:    0101  3e 05     mvi a, 05h\n  reassembled: <end>");
+    }
+}
@@ -0,0 +1,224 @@
+//! The canonical routines in `tests/routines/`, assembled at test time, run
+//! on random inputs and checked against Rust versions of the same
+//! routines. Each test has a fixed seed, so a failure always reproduces.
+
+use std::cmp::Ordering;
+use std::fs;
+
+use crate::asm;
+use crate::corpus::Rng;
+use crate::processor::{Processor, ProcessorBuilder, Registers, RunOutcome};
+
+/// Inputs each routine is run on.
+const CASES: usize = 64;
+
+const STACK_TOP: u16 = 0xf000;
+
+/// Where the tests put the blocks the routines work on.
+const BLOCK_A: u16 = 0x4000;
+const BLOCK_B: u16 = 0x6000;
+
+/// Assembles `tests/routines/<name>.asm` behind a driver that sets up the
+/// stack, calls `name` and halts.
+fn load(name: &str) -> Processor {
+    let routine: String = fs::read_to_string(format!("tests/routines/{}.asm", name)).unwrap();
+    let source: String = format!("  lxi sp, {}\n  call {}\n  hlt\n{}", STACK_TOP, name, routine);
+    let assembly = asm::assemble(&source).unwrap_or_else(|err| panic!("{}.asm: {}", name, err));
+    let mut processor: Processor = ProcessorBuilder::new().build();
+    processor.load_program(&assembly.bytes);
+    return processor;
+}
+
+/// Calls `name` with the registers `setup` gives it and `blocks` loaded,
+/// checking it returns with the stack as it found it.
+fn call(name: &str, setup: impl FnOnce(&mut Registers), blocks: &[(u16, &[u8])]) -> Processor {
+    let mut processor: Processor = load(name);
+    let mut registers: Registers = processor.registers();
+    setup(&mut registers);
+    processor.set_registers(registers);
+    for (addr, bytes) in blocks {
+        processor.load_at(*addr, bytes);
+    }
+    assert_eq!(processor.run_with_limit(2_000_000), RunOutcome::Halted, "{} did not return", name);
+    assert_eq!(processor.registers().sp, STACK_TOP, "{} left the stack unbalanced", name);
+    return processor;
+}
+
+fn peek_block(processor: &Processor, start: u16, len: usize) -> Vec<u8> {
+    return (0..len).map(|offset| processor.peek(start + offset as u16)).collect();
+}
+
+fn random_bytes(rng: &mut Rng, len: usize) -> Vec<u8> {
+    return (0..len).map(|_| rng.next() as u8).collect();
+}
+
+/// A random word, shifted right a random amount so small values turn up
+/// as often as large ones.
+fn random_word(rng: &mut Rng) -> u16 {
+    let shift: u32 = rng.below(16);
+    return rng.next() as u16 >> shift;
+}
+
+/// What memcmp and strcmp return for `ordering`.
+fn compare_result(ordering: Ordering) -> u8 {
+    return match ordering {
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+        Ordering::Less => 0xff,
+    };
+}
+
+/// `value` as `len` bytes of packed BCD, least significant first.
+fn to_bcd(mut value: u64, len: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for _ in 0..len {
+        bytes.push(((value / 10 % 10) << 4 | (value % 10)) as u8);
+        value /= 100;
+    }
+    return bytes;
+}
+
+#[test]
+fn test_mul16() {
+    let mut rng = Rng::new(0x0447_0001);
+    for _ in 0..CASES {
+        let (multiplicand, multiplier): (u16, u16) = (random_word(&mut rng), random_word(&mut rng));
+
+        let processor = call("mul16", |registers| {
+            registers.set_bc(multiplicand);
+            registers.set_de(multiplier);
+        }, &[]);
+
+        assert_eq!(processor.registers().hl(), multiplicand.wrapping_mul(multiplier),
+                   "{} * {}", multiplicand, multiplier);
+    }
+}
+
+#[test]
+fn test_div16() {
+    let mut rng = Rng::new(0x0447_0002);
+    for _ in 0..CASES {
+        let dividend: u16 = random_word(&mut rng);
+        let divisor: u16 = random_word(&mut rng).max(1);
+
+        let processor = call("div16", |registers| {
+            registers.set_bc(dividend);
+            registers.set_de(divisor);
+        }, &[]);
+
+        let registers: Registers = processor.registers();
+        assert_eq!((registers.bc(), registers.hl()), (dividend / divisor, dividend % divisor),
+                   "{} / {}", dividend, divisor);
+    }
+}
+
+#[test]
+fn test_memset() {
+    let mut rng = Rng::new(0x0447_0003);
+    for _ in 0..CASES {
+        let len: usize = rng.below(1024) as usize;
+        let value: u8 = rng.next() as u8;
+        // A byte either side shows the fill stays inside the block.
+        let before: Vec<u8> = random_bytes(&mut rng, len + 2);
+
+        let processor = call("memset", |registers| {
+            registers.a = value;
+            registers.set_bc(len as u16);
+            registers.set_hl(BLOCK_A);
+        }, &[(BLOCK_A - 1, &before)]);
+
+        let mut expected: Vec<u8> = before.clone();
+        expected[1..=len].fill(value);
+        assert_eq!(peek_block(&processor, BLOCK_A - 1, len + 2), expected, "{} bytes of {:02x}", len, value);
+    }
+}
+
+#[test]
+fn test_memcmp() {
+    let mut rng = Rng::new(0x0447_0004);
+    for _ in 0..CASES {
+        let len: usize = rng.below(600) as usize;
+        let left: Vec<u8> = random_bytes(&mut rng, len);
+        let mut right: Vec<u8> = left.clone();
+        if len > 0 && rng.below(3) != 0 {
+            right[rng.below(len as u32) as usize] = rng.next() as u8;
+        }
+
+        let processor = call("memcmp", |registers| {
+            registers.set_bc(len as u16);
+            registers.set_hl(BLOCK_A);
+            registers.set_de(BLOCK_B);
+        }, &[(BLOCK_A, &left), (BLOCK_B, &right)]);
+
+        assert_eq!(processor.registers().a, compare_result(left.cmp(&right)), "{} bytes", len);
+    }
+}
+
+#[test]
+fn test_strcmp() {
+    let mut rng = Rng::new(0x0447_0005);
+    let random_string = |rng: &mut Rng, len: usize| -> Vec<u8> {
+        return (0..len).map(|_| 1 + rng.below(255) as u8).collect();
+    };
+    for _ in 0..CASES {
+        // A shared prefix, long enough at times to need more than 256
+        // trips round the loop, then up to two bytes that may differ.
+        let prefix_len: usize = rng.below(300) as usize;
+        let prefix: Vec<u8> = random_string(&mut rng, prefix_len);
+        let mut left: Vec<u8> = prefix.clone();
+        let left_tail: usize = rng.below(3) as usize;
+        left.extend(random_string(&mut rng, left_tail));
+        let mut right: Vec<u8> = prefix;
+        let right_tail: usize = rng.below(3) as usize;
+        right.extend(random_string(&mut rng, right_tail));
+
+        let processor = call("strcmp", |registers| {
+            registers.set_hl(BLOCK_A);
+            registers.set_de(BLOCK_B);
+        }, &[(BLOCK_A, &[left.as_slice(), &[0]].concat()), (BLOCK_B, &[right.as_slice(), &[0]].concat())]);
+
+        assert_eq!(processor.registers().a, compare_result(left.cmp(&right)), "{:02x?} against {:02x?}", left, right);
+    }
+}
+
+#[test]
+fn test_bcd_add() {
+    let mut rng = Rng::new(0x0447_0006);
+    for _ in 0..CASES {
+        let len: usize = 1 + rng.below(8) as usize;
+        let limit: u64 = 100u64.pow(len as u32);
+        let mut random_number = || (0..len * 2).fold(0, |value, _| value * 10 + rng.below(10) as u64);
+        let (target, source): (u64, u64) = (random_number(), random_number());
+
+        let processor = call("bcd_add", |registers| {
+            registers.b = len as u8;
+            registers.set_hl(BLOCK_A);
+            registers.set_de(BLOCK_B);
+        }, &[(BLOCK_A, &to_bcd(target, len)), (BLOCK_B, &to_bcd(source, len))]);
+
+        let sum: u64 = target + source;
+        assert_eq!(peek_block(&processor, BLOCK_A, len), to_bcd(sum % limit, len), "{} + {}", target, source);
+        assert_eq!(processor.registers().a, (sum >= limit) as u8, "carry out of {} + {}", target, source);
+    }
+}
+
+#[test]
+fn test_bubble_sort() {
+    let mut rng = Rng::new(0x0447_0007);
+    for _ in 0..CASES {
+        let len: usize = rng.below(100) as usize;
+        // Few distinct values some of the time, so there are runs of equal
+        // bytes to get past.
+        let range: u32 = if rng.below(2) == 0 { 4 } else { 256 };
+        let mut before: Vec<u8> = (0..len + 2).map(|_| rng.below(range) as u8).collect();
+
+        let processor = call("bubble_sort", |registers| {
+            registers.b = len as u8;
+            registers.set_hl(BLOCK_A);
+        }, &[(BLOCK_A - 1, &before)]);
+
+        let original: Vec<u8> = before.clone();
+        before[1..=len].sort();
+        assert_eq!(peek_block(&processor, BLOCK_A - 1, len + 2), before, "sorting {:02x?}", original);
+    }
+}
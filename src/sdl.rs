@@ -0,0 +1,121 @@
+//! A window, keyboard and speaker for the Space Invaders machine, built
+//! only with `--features sdl`.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use sdl2::audio::{AudioCVT, AudioFormat, AudioQueue, AudioSpecDesired, AudioSpecWAV};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+
+use intel_8080_emu::clock::SystemClock;
+use intel_8080_emu::invaders::{self, Cabinet, Sound};
+use intel_8080_emu::machine::{Machine, ResetKind};
+use intel_8080_emu::processor::{Processor, Throttle};
+use intel_8080_emu::video::{FrameView, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const SAMPLE_RATE: u32 = 44_100;
+
+/// Lit pixels are drawn white, the rest black.
+const LIT: u8 = 0xff;
+
+/// Sounds as signed 16-bit mono at `SAMPLE_RATE`, from the sample
+/// directory if one was given and it has the file, otherwise a tone.
+struct Sounds {
+    dir: Option<String>,
+    loaded: HashMap<Sound, Vec<i16>>,
+}
+
+impl Sounds {
+    fn new(dir: Option<&str>) -> Sounds {
+        return Sounds { dir: dir.map(str::to_string), loaded: HashMap::new() };
+    }
+
+    fn get(&mut self, sound: Sound) -> &[i16] {
+        let dir: Option<&String> = self.dir.as_ref();
+        return self.loaded.entry(sound).or_insert_with(|| {
+            return dir.and_then(|dir| load_sample(&Path::new(dir).join(sound.sample_file())).ok())
+                .unwrap_or_else(|| sound.tone(SAMPLE_RATE));
+        });
+    }
+}
+
+fn load_sample(path: &Path) -> Result<Vec<i16>, String> {
+    let wav = AudioSpecWAV::load_wav(path)?;
+    let cvt = AudioCVT::new(wav.format, wav.channels, wav.freq, AudioFormat::s16_sys(), 1, SAMPLE_RATE as i32)?;
+    let bytes: Vec<u8> = cvt.convert(wav.buffer().to_vec());
+    return Ok(bytes.chunks_exact(2).map(|pair| i16::from_ne_bytes([pair[0], pair[1]])).collect());
+}
+
+/// Runs `machine` as Space Invaders in a window `scale` times the size of
+/// the screen until it is closed or Escape is pressed, with the DIP
+/// switches set as `settings` name them. F3 is the reset button.
+pub fn run(machine: &mut Machine, scale: u32, samples: Option<&str>, settings: &[(String, String)]) -> Result<(), String> {
+    let context = sdl2::init()?;
+    let window = context.video()?
+        .window("Space Invaders", SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale)
+        .position_centered()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let mut canvas = window.into_canvas().build().map_err(|err| err.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+        .map_err(|err| err.to_string())?;
+
+    let desired = AudioSpecDesired { freq: Some(SAMPLE_RATE as i32), channels: Some(1), samples: None };
+    let speaker: AudioQueue<i16> = context.audio()?.open_queue(None, &desired)?;
+    speaker.resume();
+    let mut sounds = Sounds::new(samples);
+
+    let cabinet = Cabinet::new();
+    for (name, value) in settings {
+        cabinet.set_option(name, value).map_err(|err| err.to_string())?;
+    }
+    machine.processor.attach_device(&cabinet.ports(), Box::new(cabinet.clone()));
+    let mut throttle = Throttle::new(invaders::CPU_HZ);
+    let clock = SystemClock::new();
+    let mut events = context.event_pump()?;
+    let mut pixels: Vec<u8> = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+
+    for frame in 0.. {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return Ok(()),
+                Event::KeyDown { keycode: Some(Keycode::F3), repeat: false, .. } => machine.reset(ResetKind::Soft),
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                    if let Some(control) = invaders::control_for_key(&keycode.name()) {
+                        cabinet.set(control, true);
+                    }
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(control) = invaders::control_for_key(&keycode.name()) {
+                        cabinet.set(control, false);
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        let processor: &mut Processor = &mut machine.processor;
+        invaders::run_frame(processor, frame, &mut throttle, &clock);
+        if processor.is_halted() && !processor.interrupts_enabled() {
+            return Ok(());
+        }
+        for sound in cabinet.take_sounds() {
+            speaker.queue_audio(sounds.get(sound))?;
+        }
+
+        let view = FrameView::of(processor).map_err(|err| err.to_string())?;
+        for (y, row) in pixels.chunks_exact_mut(SCREEN_WIDTH * 3).enumerate() {
+            for (pixel, lit) in row.chunks_exact_mut(3).zip(view.row(y)) {
+                pixel.fill(if lit { LIT } else { 0 });
+            }
+        }
+        texture.update(None, &pixels, SCREEN_WIDTH * 3).map_err(|err| err.to_string())?;
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+    }
+    return Ok(());
+}
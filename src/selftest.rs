@@ -0,0 +1,221 @@
+//! Tiny built-in programs for checking that a build works: `--selftest`
+//! runs each and reports whether it left the machine as expected. They are
+//! built from `Instruction` values, so they need nothing outside the core.
+
+use crate::instruction::{Condition, Instruction, Reg, RegPair, StackPair};
+use crate::processor::{Processor, ProcessorBuilder, RunOutcome};
+use crate::word;
+#[cfg(feature = "machines")]
+use crate::video::{self, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Far more than any program here runs before its HLT.
+const MAX_INSTRUCTIONS: u64 = 100_000;
+
+/// A program, loaded at 0, and a check of the state it halts in.
+pub struct SelfTest {
+    pub name: &'static str,
+    program: fn() -> Vec<Instruction>,
+    check: fn(&Processor) -> Result<(), String>,
+}
+
+impl SelfTest {
+    /// Runs the program on a fresh processor until it halts, then checks
+    /// it. The error says what was wrong.
+    pub fn run(&self) -> Result<(), String> {
+        let mut processor: Processor = ProcessorBuilder::new().build();
+        processor.load_instructions(&(self.program)(), 0).map_err(|err| err.to_string())?;
+        return match processor.run_with_limit(MAX_INSTRUCTIONS) {
+            RunOutcome::Halted => (self.check)(&processor),
+            outcome => Err(format!("stopped without halting: {:?}", outcome)),
+        };
+    }
+}
+
+/// Every built-in test, in the order `--selftest` runs them. The video
+/// test needs the `machines` feature.
+pub fn tests() -> Vec<SelfTest> {
+    let tests: Vec<SelfTest> = vec![
+        SelfTest { name: "arithmetic", program: arithmetic, check: check_arithmetic },
+        SelfTest { name: "stack", program: stack, check: check_stack },
+        SelfTest { name: "branches", program: branches, check: check_branches },
+        SelfTest { name: "memory", program: memory, check: check_memory },
+    ];
+    #[cfg(feature = "machines")]
+    let tests: Vec<SelfTest> = tests.into_iter()
+        .chain([SelfTest { name: "video", program: video_pattern, check: check_video_pattern }])
+        .collect();
+    return tests;
+}
+
+/// `what` was `got` where it should have been `want`.
+fn expect(what: &str, got: u16, want: u16) -> Result<(), String> {
+    if got != want {
+        return Err(format!("{} is {:04x}h, expected {:04x}h", what, got, want));
+    }
+    return Ok(());
+}
+
+/// A 16-bit add, a decimal adjust that carries out, and a subtract that
+/// borrows.
+fn arithmetic() -> Vec<Instruction> {
+    return vec![
+        Instruction::Lxi(RegPair::H, 0x1234), // 0000
+        Instruction::Lxi(RegPair::D, 0x0f00), // 0003
+        Instruction::Dad(RegPair::D),         // 0006
+        Instruction::Mvi(Reg::A, 0x99),       // 0007
+        Instruction::Adi(0x01),               // 0009
+        Instruction::Daa,                     // 000b 99h + 1 = 100 decimal
+        Instruction::Mov(Reg::B, Reg::A),     // 000c
+        Instruction::Mvi(Reg::A, 0x05),       // 000d
+        Instruction::Sui(0x07),               // 000f
+        Instruction::Hlt,                     // 0011
+    ];
+}
+
+fn check_arithmetic(processor: &Processor) -> Result<(), String> {
+    let registers = processor.registers();
+    expect("HL", registers.hl(), 0x2134)?;
+    expect("B after DAA", registers.b as u16, 0x00)?;
+    expect("A", registers.a as u16, 0xfe)?;
+    return expect("carry", registers.flags.carry as u16, 1);
+}
+
+/// Swaps two pairs through the stack, saves and restores A and the flags,
+/// and calls a subroutine. The words left below SP show the stack's byte
+/// order, which round trips alone would not.
+fn stack() -> Vec<Instruction> {
+    return vec![
+        Instruction::Lxi(RegPair::Sp, 0x2000), // 0000
+        Instruction::Lxi(RegPair::B, 0x1234),  // 0003
+        Instruction::Push(StackPair::B),       // 0006
+        Instruction::Lxi(RegPair::D, 0x5678),  // 0007
+        Instruction::Push(StackPair::D),       // 000a
+        Instruction::Pop(StackPair::B),        // 000b
+        Instruction::Pop(StackPair::D),        // 000c
+        Instruction::Mvi(Reg::A, 0x42),        // 000d
+        Instruction::Stc,                      // 000f
+        Instruction::Push(StackPair::Psw),     // 0010
+        Instruction::Xra(Reg::A),              // 0011 clears A and carry
+        Instruction::Pop(StackPair::Psw),      // 0012
+        Instruction::Call(0x0017),             // 0013
+        Instruction::Hlt,                      // 0016
+        Instruction::Inr(Reg::A),              // 0017
+        Instruction::Ret,                      // 0018
+    ];
+}
+
+fn check_stack(processor: &Processor) -> Result<(), String> {
+    let registers = processor.registers();
+    expect("BC", registers.bc(), 0x5678)?;
+    expect("DE", registers.de(), 0x1234)?;
+    expect("A", registers.a as u16, 0x43)?;
+    expect("carry", registers.flags.carry as u16, 1)?;
+    expect("SP", registers.sp, 0x2000)?;
+    let stacked = |addr: u16| word::read_word(|addr| processor.peek(addr), addr);
+    expect("word pushed from DE at 1ffch", stacked(0x1ffc), 0x5678)?;
+    return expect("return address at 1ffeh", stacked(0x1ffe), 0x0016);
+}
+
+/// Adds 10 down to 1 in a loop, then calls a subroutine only if the sum
+/// is 55, which returns early only if it got there.
+fn branches() -> Vec<Instruction> {
+    return vec![
+        Instruction::Lxi(RegPair::Sp, 0x2000),        // 0000
+        Instruction::Mvi(Reg::B, 10),                 // 0003
+        Instruction::Xra(Reg::A),                     // 0005
+        Instruction::Add(Reg::B),                     // 0006
+        Instruction::Dcr(Reg::B),                     // 0007
+        Instruction::Jcond(Condition::Nz, 0x0006),    // 0008
+        Instruction::Cpi(55),                         // 000b
+        Instruction::Mvi(Reg::C, 0),                  // 000d
+        Instruction::Ccond(Condition::Z, 0x0013),     // 000f
+        Instruction::Hlt,                             // 0012
+        Instruction::Inr(Reg::C),                     // 0013
+        Instruction::Rcond(Condition::Nz),            // 0014
+        Instruction::Mvi(Reg::C, 0xee),               // 0015
+        Instruction::Ret,                             // 0017
+    ];
+}
+
+fn check_branches(processor: &Processor) -> Result<(), String> {
+    let registers = processor.registers();
+    expect("A", registers.a as u16, 55)?;
+    expect("B", registers.b as u16, 0)?;
+    expect("C", registers.c as u16, 1)?;
+    return expect("SP", registers.sp, 0x2000);
+}
+
+/// Stores and loads bytes and words every way the 8080 can.
+fn memory() -> Vec<Instruction> {
+    return vec![
+        Instruction::Lxi(RegPair::H, 0x3000), // 0000
+        Instruction::Mvi(Reg::M, 0xa5),       // 0003
+        Instruction::Mov(Reg::A, Reg::M),     // 0005
+        Instruction::Sta(0x3001),             // 0006
+        Instruction::Cma,                     // 0009
+        Instruction::Lxi(RegPair::B, 0x3002), // 000a
+        Instruction::Stax(RegPair::B),        // 000d
+        Instruction::Lxi(RegPair::H, 0xbeef), // 000e
+        Instruction::Shld(0x3004),            // 0011
+        Instruction::Lxi(RegPair::H, 0),      // 0014
+        Instruction::Lhld(0x3004),            // 0017
+        Instruction::Xchg,                    // 001a
+        Instruction::Lda(0x3001),             // 001b
+        Instruction::Hlt,                     // 001e
+    ];
+}
+
+fn check_memory(processor: &Processor) -> Result<(), String> {
+    for (addr, want) in [(0x3000, 0xa5), (0x3001, 0xa5), (0x3002, 0x5a), (0x3004, 0xef), (0x3005, 0xbe)] {
+        expect(&format!("[{:04x}h]", addr), processor.peek(addr) as u16, want)?;
+    }
+    let registers = processor.registers();
+    expect("DE", registers.de(), 0xbeef)?;
+    return expect("A", registers.a as u16, 0xa5);
+}
+
+/// Fills video memory with 01h, which lights every eighth row.
+#[cfg(feature = "machines")]
+fn video_pattern() -> Vec<Instruction> {
+    return vec![
+        Instruction::Lxi(RegPair::H, video::VRAM.start), // 0000
+        Instruction::Mvi(Reg::M, 0x01),                  // 0003
+        Instruction::Inx(RegPair::H),                    // 0005
+        Instruction::Mov(Reg::A, Reg::H),                // 0006
+        Instruction::Cpi((video::VRAM.end >> 8) as u8),  // 0007
+        Instruction::Jcond(Condition::Nz, 0x0003),       // 0009
+        Instruction::Hlt,                                // 000c
+    ];
+}
+
+#[cfg(feature = "machines")]
+fn check_video_pattern(processor: &Processor) -> Result<(), String> {
+    let pixels: Vec<bool> = video::capture(|addr| processor.peek(addr));
+    let wrong: Option<usize> = (0..pixels.len()).find(|index| pixels[*index] != (SCREEN_HEIGHT - 1 - index / SCREEN_WIDTH).is_multiple_of(8));
+    return match wrong {
+        Some(index) => Err(format!("pixel ({}, {}) is wrong", index % SCREEN_WIDTH, index / SCREEN_WIDTH)),
+        None => Ok(()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_built_in_passes() {
+        for test in tests() {
+            assert_eq!(test.run(), Ok(()), "{}", test.name);
+        }
+        assert_eq!(tests().len(), if cfg!(feature = "machines") { 5 } else { 4 });
+    }
+
+    #[test]
+    fn test_failures_say_what_was_wrong() {
+        let halts_at_once = SelfTest { name: "arithmetic", program: || vec![Instruction::Hlt], check: check_arithmetic };
+        assert_eq!(halts_at_once.run(), Err("HL is 0000h, expected 2134h".to_string()));
+
+        let spins = SelfTest { name: "stack", program: || vec![Instruction::Jmp(0)], check: check_stack };
+        assert_eq!(spins.run(), Err("stopped without halting: InstructionLimit { instructions: 100000 }".to_string()));
+    }
+}
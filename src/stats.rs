@@ -0,0 +1,260 @@
+//! Post-run statistics about how a program used the machine.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[cfg(feature = "serde-state")]
+use serde::{Deserialize, Serialize};
+
+use crate::processor::{AccessMap, BranchSite, CycleStats, InterruptTiming, LatencyTracker, Processor, MEMORY_SIZE};
+
+/// Bytes per page in the usage map.
+pub const PAGE_SIZE: usize = 256;
+
+const PAGES: usize = MEMORY_SIZE / PAGE_SIZE;
+
+/// How the guest used one page of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-state", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde-state", serde(rename_all = "lowercase"))]
+pub enum PageUsage {
+    Untouched,
+    /// Executed, and never read or written as data.
+    Code,
+    /// Read or written, and never executed.
+    Data,
+    /// Both executed and read or written.
+    Mixed,
+}
+
+impl PageUsage {
+    fn symbol(self) -> char {
+        return match self {
+            PageUsage::Untouched => '.',
+            PageUsage::Code => 'C',
+            PageUsage::Data => 'D',
+            PageUsage::Mixed => 'M',
+        };
+    }
+}
+
+/// Every page of the address space classified by how the guest used it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-state", derive(Serialize))]
+pub struct UsageMap {
+    pub pages: Vec<PageUsage>,
+}
+
+impl UsageMap {
+    pub fn classify(access: &AccessMap) -> UsageMap {
+        let pages: Vec<PageUsage> = (0..PAGES).map(|page| {
+            let addrs = (page * PAGE_SIZE..(page + 1) * PAGE_SIZE).map(|addr| addr as u16);
+            let code: bool = addrs.clone().any(|addr| access.executed(addr));
+            let data: bool = addrs.clone().any(|addr| access.read(addr) || access.written(addr));
+            return match (code, data) {
+                (false, false) => PageUsage::Untouched,
+                (true, false) => PageUsage::Code,
+                (false, true) => PageUsage::Data,
+                (true, true) => PageUsage::Mixed,
+            };
+        }).collect();
+        return UsageMap { pages };
+    }
+
+    /// The usage of the page holding `addr`.
+    pub fn page_of(&self, addr: u16) -> PageUsage {
+        return self.pages[addr as usize / PAGE_SIZE];
+    }
+
+    #[cfg(feature = "serde-state")]
+    pub fn to_json(&self) -> String {
+        return serde_json::to_string(self).expect("usage maps always serialize");
+    }
+}
+
+impl fmt::Display for UsageMap {
+    /// A 16x16 grid, one row per 4K, e.g. `1000  CC.D............`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "      0123456789abcdef")?;
+        for (row, pages) in self.pages.chunks(16).enumerate() {
+            let symbols: String = pages.iter().map(|usage| usage.symbol()).collect();
+            writeln!(f, "{:04x}  {}", row * 16 * PAGE_SIZE, symbols)?;
+        }
+        return write!(f, "C code  D data  M mixed  . untouched");
+    }
+}
+
+/// Interrupt service times for each source, in cycles.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde-state", derive(Serialize))]
+pub struct InterruptReport {
+    pub sources: Vec<(u8, InterruptTiming)>,
+}
+
+impl InterruptReport {
+    pub fn collect(timing: &LatencyTracker) -> InterruptReport {
+        return InterruptReport { sources: timing.sources().iter().map(|(source, timing)| (*source, *timing)).collect() };
+    }
+}
+
+impl fmt::Display for InterruptReport {
+    /// One row per source, e.g.
+    /// `     4    212           11/11.0/11           45/45.0/45`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "source  taken  latency min/avg/max  handler min/avg/max")?;
+        for (source, timing) in &self.sources {
+            let span = |stats: &CycleStats| format!("{}/{:.1}/{}", stats.min, stats.avg(), stats.max);
+            write!(f, "\n{:>6}  {:>5}  {:>19}  {:>19}", source, timing.latency.count, span(&timing.latency),
+                   span(&timing.handler))?;
+        }
+        return Ok(());
+    }
+}
+
+/// One conditional branch as the report shows it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchRow {
+    pub site: BranchSite,
+    /// The branch instruction, e.g. `jnz 0002h`.
+    pub instruction: String,
+    /// The nearest symbol at or below the site, e.g. `loop+4`.
+    pub symbol: Option<String>,
+}
+
+/// Each conditional branch that ran, most executed first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchReport {
+    pub rows: Vec<BranchRow>,
+}
+
+impl BranchReport {
+    /// The processor's branch stats, named from `symbols` where there are
+    /// any.
+    pub fn collect(processor: &Processor, symbols: &BTreeMap<u16, String>) -> BranchReport {
+        let rows: Vec<BranchRow> = processor.branch_stats().into_iter().map(|site| {
+            let symbol: Option<String> = symbols.range(..=site.pc).next_back().map(|(addr, name)| match site.pc - addr {
+                0 => name.clone(),
+                offset => format!("{}+{}", name, offset),
+            });
+            return BranchRow { site, instruction: processor.describe_instruction(site.pc), symbol };
+        }).collect();
+        return BranchReport { rows };
+    }
+}
+
+impl fmt::Display for BranchReport {
+    /// One row per site, e.g.
+    /// `0010         8   87.5%  jnz 0002h  loop+4`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "site  executed   taken  instruction")?;
+        for row in &self.rows {
+            write!(f, "\n{:04x}  {:>8}  {:>5.1}%  {}", row.site.pc, row.site.executed, row.site.taken_ratio() * 100.0,
+                   row.instruction)?;
+            if let Some(symbol) = &row.symbol {
+                write!(f, "  {}", symbol)?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{make_processor, ACCESS_EXECUTED, ACCESS_READ, ACCESS_WRITTEN};
+    use std::fs;
+
+    #[test]
+    fn test_classify_synthetic_accesses() {
+        let mut access = AccessMap::new();
+        access.mark(0x0000, ACCESS_EXECUTED);
+        access.mark(0x01ff, ACCESS_EXECUTED);
+        access.mark(0x0200, ACCESS_READ);
+        access.mark(0x0310, ACCESS_EXECUTED);
+        access.mark(0x03f0, ACCESS_WRITTEN);
+        access.mark(0xffff, ACCESS_WRITTEN);
+
+        let map = UsageMap::classify(&access);
+
+        assert_eq!(&map.pages[..5], &[PageUsage::Code, PageUsage::Code, PageUsage::Data, PageUsage::Mixed,
+                                      PageUsage::Untouched]);
+        assert_eq!(map.page_of(0xff00), PageUsage::Data);
+        assert_eq!(map.pages.iter().filter(|usage| **usage == PageUsage::Untouched).count(), PAGES - 5);
+    }
+
+    #[test]
+    fn test_grid_and_json() {
+        let mut access = AccessMap::new();
+        access.mark(0x1000, ACCESS_EXECUTED);
+        access.mark(0x1300, ACCESS_READ);
+        let map = UsageMap::classify(&access);
+
+        let grid = map.to_string();
+        let lines: Vec<&str> = grid.lines().collect();
+
+        assert_eq!(lines.len(), 18);
+        assert_eq!(lines[1], "0000  ................");
+        assert_eq!(lines[2], "1000  C..D............");
+        #[cfg(feature = "serde-state")]
+        assert!(map.to_json().starts_with("{\"pages\":[\"untouched\","));
+    }
+
+    #[test]
+    fn test_interrupt_report() {
+        let mut timing = LatencyTracker::new();
+        timing.raised(4, 0);
+        timing.entered(4, 11, 1);
+        timing.returned(56, 0);
+        timing.raised(4, 1000);
+        timing.entered(4, 1029, 1);
+        timing.returned(1074, 0);
+
+        let lines: Vec<String> = InterruptReport::collect(&timing).to_string().lines().map(str::to_string).collect();
+
+        assert_eq!(lines[1], "     4      2           11/20.0/29           45/45.0/45");
+    }
+
+    #[test]
+    fn test_memcpy_usage() {
+        let mut processor = make_processor();
+        processor.load_program(&fs::read("tests/memcpy.bin").unwrap());
+        processor.set_access_tracking(true);
+        processor.run();
+        let access = processor.access_map().unwrap();
+
+        let source = 0x0011..0x0016;
+        let target = 0x0016..0x001b;
+        assert!(source.clone().all(|addr| access.read(addr) && !access.written(addr) && !access.executed(addr)));
+        assert!(target.clone().all(|addr| access.written(addr) && !access.read(addr) && !access.executed(addr)));
+        assert!((0x001b..0x0020).all(|addr| access.flags(addr) == 0));
+        assert!(access.executed(0x0000) && access.executed(0x002d));
+
+        let map = UsageMap::classify(access);
+        assert_eq!(map.page_of(0x0000), PageUsage::Mixed);
+        assert_eq!(map.page_of(0x9f00), PageUsage::Data);
+        assert_eq!(map.pages.iter().filter(|usage| **usage != PageUsage::Untouched).count(), 2);
+    }
+
+    #[test]
+    fn test_branch_report() {
+        let mut processor = make_processor();
+        processor.set_branch_stats(true);
+        processor.load_program(&[
+            0x06, 0x03,       // 0000 MVI B,3
+            0xcc, 0x0a, 0x00, // 0002 CZ 000ah
+            0x05,             // 0005 DCR B
+            0xc2, 0x02, 0x00, // 0006 JNZ 0002h
+            0x76,             // 0009 HLT
+        ]);
+        processor.run();
+        let symbols: BTreeMap<u16, String> = BTreeMap::from([(0x0002, "loop".to_string())]);
+
+        let report: String = BranchReport::collect(&processor, &symbols).to_string();
+
+        assert_eq!(report.lines().collect::<Vec<&str>>(), [
+            "site  executed   taken  instruction",
+            "0002         3    0.0%  cz 000ah  loop",
+            "0006         3   66.7%  jnz 0002h  loop+4",
+        ]);
+    }
+}
@@ -0,0 +1,205 @@
+//! ANSI colour for terminal output. Each method takes what a plain
+//! formatter writes and paints it, leaving the text and its column widths
+//! alone, so plain output stays the default and colour is only ever added
+//! on top of it.
+
+use crate::disasm::Line;
+use crate::monitor::StateDiff;
+use crate::processor::{Registers, SnapshotDiff, TraceRecord};
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CHANGED: &str = "\x1b[1;33m";
+
+/// What `--color` asks for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Colour when writing to a terminal and `NO_COLOR` is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(text: &str) -> Option<ColorChoice> {
+        return match text {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        };
+    }
+}
+
+/// Whether to paint output, and how.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    color: bool,
+}
+
+impl Style {
+    /// Leaves everything as the plain formatters write it.
+    pub const PLAIN: Style = Style { color: false };
+    pub const COLOR: Style = Style { color: true };
+
+    /// The style for a stream that is or is not a `terminal`, with
+    /// `no_color` being whether `NO_COLOR` is set to something.
+    pub fn choose(choice: ColorChoice, terminal: bool, no_color: bool) -> Style {
+        return Style {
+            color: match choice {
+                ColorChoice::Auto => terminal && !no_color,
+                ColorChoice::Always => true,
+                ColorChoice::Never => false,
+            },
+        };
+    }
+
+    pub fn is_color(&self) -> bool {
+        return self.color;
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        return match self.color {
+            true => format!("{}{}{}", code, text, RESET),
+            false => text.to_string(),
+        };
+    }
+
+    /// `B=02->04`, `[2018]=00->04` or `00 -> 42` with the old value dimmed
+    /// and the new one highlighted. Anything else is left alone.
+    fn change(&self, token: &str) -> String {
+        let Some((old, arrow, new)) = ["->", " -> "].iter().rev()
+            .find_map(|arrow| token.split_once(arrow).map(|(old, new)| (old, *arrow, new))) else {
+            return token.to_string();
+        };
+        let (name, old) = old.rsplit_once(['=', ' ']).map_or(("", old), |(name, old)| (&token[..name.len() + 1], old));
+        return format!("{}{}{}{}", name, self.paint(DIM, old), arrow, self.paint(CHANGED, new));
+    }
+
+    /// A flag letter that is set in green, a `-` for one that is clear
+    /// dimmed.
+    fn flag(&self, letter: char) -> String {
+        return match letter {
+            '-' => self.paint(DIM, "-"),
+            letter => self.paint(GREEN, &letter.to_string()),
+        };
+    }
+
+    /// `Registers` as the monitor and trace show them, with set flags
+    /// coloured and clear ones dimmed.
+    pub fn registers(&self, registers: &Registers) -> String {
+        let plain: String = registers.to_string();
+        let (values, flags) = plain.rsplit_once(' ').expect("the flags follow the registers");
+        return format!("{} {}", values, flags.chars().map(|letter| self.flag(letter)).collect::<String>());
+    }
+
+    /// A `StateDiff`, with changed values highlighted and flags that came
+    /// on green and went off red.
+    pub fn state_diff(&self, diff: &StateDiff) -> String {
+        let plain: String = diff.to_string();
+        if diff.is_empty() {
+            return plain;
+        }
+        let flags: usize = diff.flags.len();
+        let first_flag: usize = diff.registers.len();
+        return plain.split(' ').enumerate().map(|(index, token)| match token.split_at(1) {
+            ("+", _) if (first_flag..first_flag + flags).contains(&index) => self.paint(GREEN, token),
+            ("-", _) if (first_flag..first_flag + flags).contains(&index) => self.paint(RED, token),
+            _ => self.change(token),
+        }).collect::<Vec<String>>().join(" ");
+    }
+
+    /// A `SnapshotDiff`, with changed register values highlighted.
+    pub fn snapshot_diff(&self, diff: &SnapshotDiff) -> String {
+        return diff.to_string().lines().map(|line| format!("{}\n", self.change(line))).collect();
+    }
+
+    /// A disassembled line with its bytes dimmed.
+    pub fn line(&self, line: &Line) -> String {
+        let plain: String = line.to_string();
+        let (addr, rest) = plain.split_at(6);
+        let (bytes, text) = rest.split_at(8);
+        return format!("{}{}{}", addr, self.paint(DIM, bytes), text);
+    }
+
+    /// `plain`, a `--trace` line for `record` with or without the flag
+    /// delta after it, with the instruction bytes dimmed and the flags
+    /// coloured as `registers` does.
+    pub fn trace(&self, record: &TraceRecord, plain: &str) -> String {
+        let (line, delta) = plain.split_at(record.to_string().len());
+        let registers: String = record.registers.to_string();
+        let (counts, rest) = line[..line.len() - registers.len()].split_at(18);
+        let (bytes, text) = rest.split_at(8);
+        return format!("{}{}{}{}{}", counts, self.paint(DIM, bytes), text, self.registers(&record.registers), delta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm;
+    use crate::processor::{make_processor, Processor, SnapshotDiff, WriteJournal};
+
+    /// The state after MVI B,42h; MVI A,1; STA 2000h; DCR A, and how it
+    /// differs from the start.
+    fn sample() -> (Processor, StateDiff, SnapshotDiff) {
+        let mut processor: Processor = make_processor();
+        processor.load_program(&[0x06, 0x42, 0x3e, 0x01, 0x32, 0x00, 0x20, 0x3d]);
+        processor.set_write_journal(true);
+        let before = processor.snapshot();
+        for _ in 0..4 {
+            processor.step();
+        }
+        let journal: WriteJournal = processor.take_write_journal();
+        let after = processor.snapshot();
+        let (diff, snapshot_diff) = (StateDiff::between(&before, &after, &journal), before.diff(&after));
+        return (processor, diff, snapshot_diff);
+    }
+
+    #[test]
+    fn test_choose() {
+        assert!(Style::choose(ColorChoice::Auto, true, false).is_color());
+        assert!(!Style::choose(ColorChoice::Auto, true, true).is_color());
+        assert!(!Style::choose(ColorChoice::Auto, false, false).is_color());
+        assert!(Style::choose(ColorChoice::Always, false, true).is_color());
+        assert!(!Style::choose(ColorChoice::Never, true, false).is_color());
+        assert_eq!(ColorChoice::parse("sometimes"), None);
+    }
+
+    #[test]
+    fn test_plain_renderings() {
+        let (processor, diff, snapshot_diff) = sample();
+
+        assert_eq!(Style::PLAIN.registers(&processor.registers()), "A=00 B=42 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0008 -Z-P-");
+        assert_eq!(Style::PLAIN.state_diff(&diff), "B=00->42 PC=0000->0008 +Z +P [2000]=00->01");
+        assert_eq!(Style::PLAIN.snapshot_diff(&snapshot_diff), snapshot_diff.to_string());
+        assert_eq!(Style::PLAIN.line(&disasm::decode(&[0x32, 0x00, 0x20], 0x0005)), "0005  32 00 20  sta 2000h");
+    }
+
+    #[test]
+    fn test_color_renderings() {
+        let (processor, diff, snapshot_diff) = sample();
+
+        assert_eq!(Style::COLOR.registers(&processor.registers()),
+                   "A=00 B=42 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0008 \
+                    \x1b[2m-\x1b[0m\x1b[32mZ\x1b[0m\x1b[2m-\x1b[0m\x1b[32mP\x1b[0m\x1b[2m-\x1b[0m");
+        assert_eq!(Style::COLOR.state_diff(&diff),
+                   "B=\x1b[2m00\x1b[0m->\x1b[1;33m42\x1b[0m PC=\x1b[2m0000\x1b[0m->\x1b[1;33m0008\x1b[0m \
+                    \x1b[32m+Z\x1b[0m \x1b[32m+P\x1b[0m [2000]=\x1b[2m00\x1b[0m->\x1b[1;33m01\x1b[0m");
+        assert_eq!(Style::COLOR.snapshot_diff(&snapshot_diff), "\
+B    \x1b[2m00\x1b[0m -> \x1b[1;33m42\x1b[0m
+F    \x1b[2m02\x1b[0m -> \x1b[1;33m46\x1b[0m
+PC   \x1b[2m0000\x1b[0m -> \x1b[1;33m0008\x1b[0m
+2000       1 byte
+also differs: instructions, cycles
+");
+        assert_eq!(Style::COLOR.line(&disasm::decode(&[0x32, 0x00, 0x20], 0x0005)), "0005  \x1b[2m32 00 20\x1b[0m  sta 2000h");
+        let record = TraceRecord { instructions: 3, cycles: 34, pc: 0x0007, bytes: [0x3d, 0, 0], registers: processor.registers() };
+        assert_eq!(Style::COLOR.trace(&record, &format!("{}  F:+Z", record)),
+                   "         3  0007  \x1b[2m3d      \x1b[0m  dcr a         A=00 B=42 C=00 D=00 E=00 H=00 L=00 SP=0000 PC=0008 \
+                    \x1b[2m-\x1b[0m\x1b[32mZ\x1b[0m\x1b[2m-\x1b[0m\x1b[32mP\x1b[0m\x1b[2m-\x1b[0m  F:+Z");
+    }
+}
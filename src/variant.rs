@@ -0,0 +1,266 @@
+//! Where a program would notice moving between the 8080 and the 8085: the
+//! 8085-only opcodes, the undocumented opcodes the two chips run
+//! differently, the flags `QuirkProfile` tells apart, and instructions that
+//! take a different number of states. `diff_report` finds them in an image
+//! without running it; `check_run` lists the ones a run executes.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::disasm::{self, Line};
+use crate::prescan;
+use crate::processor::Processor;
+
+/// Instructions `check_run` is given by the CLI unless told otherwise.
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 100_000_000;
+
+/// How an instruction differs between the two chips.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difference {
+    /// RIM or SIM, the 8085's interrupt mask and serial line. The 8080 runs
+    /// the opcode as a NOP.
+    Only8085 { mnemonic: &'static str },
+    /// Undocumented on both: the 8080 runs it as an alias of a documented
+    /// instruction, the 8085 as one of its own.
+    Undocumented { i8080: &'static str, i8085: &'static str },
+    /// ANA or ANI: the 8080 sets AC to bit 3 of the two operands ORed
+    /// together, the 8085 always sets it.
+    AuxCarry,
+    /// DAA adjusts by AC, so it differs wherever the AC it sees came from
+    /// ANA or ANI.
+    ReadsAuxCarry,
+    /// The same effect in a different number of states, as (not taken,
+    /// taken); both the same for an unconditional instruction.
+    Timing { i8080: (u8, u8), i8085: (u8, u8) },
+}
+
+impl Difference {
+    /// How `opcode` differs, or None if both chips run it alike.
+    pub fn of(opcode: u8) -> Option<Difference> {
+        let timing = |i8080: (u8, u8), i8085: (u8, u8)| Some(Difference::Timing { i8080, i8085 });
+        let (dst, src): (u8, u8) = ((opcode >> 3) & 0b111, opcode & 0b111);
+        return match opcode {
+            0x20 => Some(Difference::Only8085 { mnemonic: "rim" }),
+            0x30 => Some(Difference::Only8085 { mnemonic: "sim" }),
+            0x08 | 0x10 | 0x18 | 0x28 | 0x38 | 0xcb | 0xd9 | 0xdd | 0xed | 0xfd => Some(Difference::Undocumented {
+                i8080: disasm::alias_template(opcode).and_then(|template| template.split(' ').next()).unwrap_or("nop"),
+                i8085: i8085_undocumented(opcode),
+            }),
+            0xa0..=0xa7 | 0xe6 => Some(Difference::AuxCarry),
+            0x27 => Some(Difference::ReadsAuxCarry),
+            0x76 => timing((7, 7), (5, 5)),
+            0x40..=0x7f if dst != 6 && src != 6 => timing((5, 5), (4, 4)),
+            _ if opcode & 0xc6 == 0x04 && dst != 6 => timing((5, 5), (4, 4)), // INR r, DCR r
+            _ if opcode & 0xc7 == 0x03 => timing((5, 5), (6, 6)), // INX, DCX
+            _ if opcode & 0xcf == 0xc5 => timing((11, 11), (12, 12)), // PUSH
+            _ if opcode & 0xc7 == 0xc7 => timing((11, 11), (12, 12)), // RST
+            _ if opcode & 0xc7 == 0xc0 => timing((5, 11), (6, 12)), // Rcc
+            _ if opcode & 0xc7 == 0xc2 => timing((10, 10), (7, 10)), // Jcc
+            _ if opcode & 0xc7 == 0xc4 => timing((11, 17), (9, 18)), // Ccc
+            0xcd => timing((17, 17), (18, 18)),
+            0xe3 => timing((18, 18), (16, 16)),
+            0xe9 | 0xf9 => timing((5, 5), (6, 6)),
+            _ => None,
+        };
+    }
+}
+
+/// What the 8085 runs one of its undocumented opcodes as.
+fn i8085_undocumented(opcode: u8) -> &'static str {
+    return match opcode {
+        0x08 => "dsub",
+        0x10 => "arhl",
+        0x18 => "rdel",
+        0x28 => "ldhi",
+        0x38 => "ldsi",
+        0xcb => "rstv",
+        0xd9 => "shlx",
+        0xdd => "jnk",
+        0xed => "lhlx",
+        _ => "jk",
+    };
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let states = |(not_taken, taken): (u8, u8)| match not_taken == taken {
+            true => not_taken.to_string(),
+            false => format!("{}/{}", not_taken, taken),
+        };
+        return match self {
+            Difference::Only8085 { mnemonic } => write!(f, "{} on the 8085, nop on the 8080", mnemonic),
+            Difference::Undocumented { i8080, i8085 } => write!(f, "undocumented: {} on the 8085, {} on the 8080", i8085, i8080),
+            Difference::AuxCarry => write!(f, "aux carry: always set on the 8085, bit 3 of the operands ORed on the 8080"),
+            Difference::ReadsAuxCarry => write!(f, "adjusts by aux carry, which ana and ani set differently"),
+            Difference::Timing { i8080, i8085 } => write!(f, "{} states on the 8085, {} on the 8080", states(*i8085), states(*i8080)),
+        };
+    }
+}
+
+/// One instruction that differs between the chips.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariantDiff {
+    pub addr: u16,
+    pub opcode: u8,
+    /// The instruction as the disassembler writes it, e.g. `daa`.
+    pub instruction: String,
+    pub difference: Difference,
+    /// Times it ran, in a report from `check_run`; 0 from a scan.
+    pub executions: u64,
+}
+
+/// The instructions in a program that differ between the chips, in
+/// address order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VariantDiffReport {
+    pub entries: Vec<VariantDiff>,
+}
+
+impl VariantDiffReport {
+    /// The entries that change what the program computes, leaving out
+    /// those that only take longer or shorter.
+    pub fn behavioural(&self) -> impl Iterator<Item = &VariantDiff> {
+        return self.entries.iter().filter(|entry| !matches!(entry.difference, Difference::Timing { .. }));
+    }
+}
+
+impl fmt::Display for VariantDiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let behavioural: usize = self.behavioural().count();
+        writeln!(f, "{} instructions behave differently, {} differ only in timing", behavioural, self.entries.len() - behavioural)?;
+        for entry in &self.entries {
+            write!(f, "  {:04x}  {:02x}  {:<12}  {}", entry.addr, entry.opcode, entry.instruction, entry.difference)?;
+            if entry.executions > 0 {
+                write!(f, " (ran {} times)", entry.executions)?;
+            }
+            writeln!(f)?;
+        }
+        return Ok(());
+    }
+}
+
+fn report(lines: Vec<Line>) -> VariantDiffReport {
+    let entries: Vec<VariantDiff> = lines.into_iter().filter_map(|line| {
+        let difference: Difference = Difference::of(line.bytes[0])?;
+        return Some(VariantDiff { addr: line.addr, opcode: line.bytes[0], instruction: line.text, difference, executions: 0 });
+    }).collect();
+    return VariantDiffReport { entries };
+}
+
+/// Sweeps `program`, loaded at `origin`, as `prescan` does, skipping
+/// padding.
+pub fn diff_report(program: &[u8], origin: u16) -> VariantDiffReport {
+    return report(prescan::sweep(program, origin).0);
+}
+
+/// As `diff_report`, but only for code reachable from `roots`, so data
+/// is not taken for instructions.
+pub fn diff_report_reachable(program: &[u8], origin: u16, roots: &[u16]) -> VariantDiffReport {
+    return report(disasm::disassemble_reachable(program, origin, roots));
+}
+
+/// Steps `processor` until it halts, faults, reaches a breakpoint or has
+/// run `max_instructions`, listing each differing instruction it ran and
+/// how often.
+pub fn check_run(processor: &mut Processor, max_instructions: u64) -> VariantDiffReport {
+    let mut runs: BTreeMap<(u16, u8), u64> = BTreeMap::new();
+    for step in processor.steps().take(max_instructions as usize) {
+        if Difference::of(step.opcode).is_some() {
+            *runs.entry((step.pc, step.opcode)).or_insert(0) += 1;
+        }
+    }
+    let entries: Vec<VariantDiff> = runs.into_iter().map(|((addr, opcode), executions)| VariantDiff {
+        addr,
+        opcode,
+        instruction: processor.describe_instruction(addr),
+        difference: Difference::of(opcode).expect("only differing opcodes are counted"),
+        executions,
+    }).collect();
+    return VariantDiffReport { entries };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::make_processor;
+
+    /// ANI, then RIM, DAA and SIM, then a countdown loop.
+    const PROGRAM: [u8; 13] = [
+        0x3e, 0x0c,       // 0100 MVI A,0Ch
+        0xe6, 0x03,       // 0102 ANI 03h
+        0x20,             // 0104 RIM
+        0x27,             // 0105 DAA
+        0x30,             // 0106 SIM
+        0x06, 0x03,       // 0107 MVI B,3
+        0x05,             // 0109 DCR B
+        0xc2, 0x09, 0x01, // 010a JNZ 0109h
+    ];
+
+    fn entry(addr: u16, opcode: u8, instruction: &str, difference: Difference, executions: u64) -> VariantDiff {
+        return VariantDiff { addr, opcode, instruction: instruction.to_string(), difference, executions };
+    }
+
+    #[test]
+    fn test_scan_lists_both_interpretations() {
+        let report: VariantDiffReport = diff_report(&PROGRAM, 0x0100);
+
+        let dcr = Difference::Timing { i8080: (5, 5), i8085: (4, 4) };
+        let jnz = Difference::Timing { i8080: (10, 10), i8085: (7, 10) };
+        assert_eq!(report.entries, vec![
+            entry(0x0102, 0xe6, "ani 03h", Difference::AuxCarry, 0),
+            entry(0x0104, 0x20, "db 20h", Difference::Only8085 { mnemonic: "rim" }, 0),
+            entry(0x0105, 0x27, "daa", Difference::ReadsAuxCarry, 0),
+            entry(0x0106, 0x30, "db 30h", Difference::Only8085 { mnemonic: "sim" }, 0),
+            entry(0x0109, 0x05, "dcr b", dcr, 0),
+            entry(0x010a, 0xc2, "jnz 0109h", jnz, 0),
+        ]);
+        assert_eq!(report.behavioural().count(), 4);
+        assert_eq!(report.to_string(), "\
+4 instructions behave differently, 2 differ only in timing
+  0102  e6  ani 03h       aux carry: always set on the 8085, bit 3 of the operands ORed on the 8080
+  0104  20  db 20h        rim on the 8085, nop on the 8080
+  0105  27  daa           adjusts by aux carry, which ana and ani set differently
+  0106  30  db 30h        sim on the 8085, nop on the 8080
+  0109  05  dcr b         4 states on the 8085, 5 on the 8080
+  010a  c2  jnz 0109h     7/10 states on the 8085, 10 on the 8080
+");
+    }
+
+    #[test]
+    fn test_undocumented_opcodes_name_both_instructions() {
+        assert_eq!(Difference::of(0xd9), Some(Difference::Undocumented { i8080: "ret", i8085: "shlx" }));
+        assert_eq!(Difference::of(0xfd), Some(Difference::Undocumented { i8080: "call", i8085: "jk" }));
+        assert_eq!(Difference::of(0x10).unwrap().to_string(), "undocumented: arhl on the 8085, nop on the 8080");
+        assert_eq!(Difference::of(0x77), None); // MOV M,A
+        assert_eq!(Difference::of(0x35), None); // DCR M
+        assert_eq!(Difference::of(0xc9), None); // RET
+    }
+
+    #[test]
+    fn test_reachable_scan_skips_data() {
+        let program: [u8; 5] = [
+            0xc3, 0x04, 0x00, // 0000 JMP 0004h
+            0x27,             // 0003 data that looks like DAA
+            0x76,             // 0004 HLT
+        ];
+
+        assert_eq!(diff_report(&program, 0).entries.len(), 2);
+        let report: VariantDiffReport = diff_report_reachable(&program, 0, &[0]);
+        assert_eq!(report.entries, vec![entry(0x0004, 0x76, "hlt", Difference::Timing { i8080: (7, 7), i8085: (5, 5) }, 0)]);
+    }
+
+    #[test]
+    fn test_check_run_counts_what_ran() {
+        let mut program: Vec<u8> = PROGRAM.to_vec();
+        program.push(0x76); // 010d HLT
+        let mut processor: Processor = make_processor();
+        processor.load_at(0x0100, &program);
+        processor.set_pc(0x0100);
+
+        let report: VariantDiffReport = check_run(&mut processor, 1000);
+
+        let runs: Vec<(u16, u64)> = report.entries.iter().map(|entry| (entry.addr, entry.executions)).collect();
+        assert_eq!(runs, [(0x0102, 1), (0x0104, 1), (0x0105, 1), (0x0106, 1), (0x0109, 3), (0x010a, 3), (0x010d, 1)]);
+        assert!(report.to_string().contains("  0109  05  dcr b         4 states on the 8085, 5 on the 8080 (ran 3 times)\n"));
+    }
+}
@@ -0,0 +1,203 @@
+//! The 1-bit framebuffer of Space Invaders-style hardware: video memory at
+//! 2400h holds 224 columns of 256 pixels, one bit each, and the monitor is
+//! turned on its side so the picture is 224 wide and 256 tall.
+
+use std::ops::Range;
+
+use crate::processor::{MemoryViewError, Processor};
+
+pub const VRAM: Range<u16> = 0x2400..0x4000;
+
+pub const SCREEN_WIDTH: usize = 224;
+pub const SCREEN_HEIGHT: usize = 256;
+
+/// A 2 MHz 8080 refreshing the screen 60 times a second.
+pub const CYCLES_PER_FRAME: u64 = 2_000_000 / 60;
+
+/// The picture in video memory, row-major from the top left, `true` for
+/// lit. `peek` reads guest memory.
+pub fn capture(peek: impl Fn(u16) -> u8) -> Vec<bool> {
+    let mut pixels: Vec<bool> = vec![false; SCREEN_WIDTH * SCREEN_HEIGHT];
+    for (offset, addr) in VRAM.enumerate() {
+        let byte: u8 = peek(addr);
+        let x: usize = offset / 32;
+        for bit in 0..8 {
+            // Bit 0 of the first byte in a column is the bottom pixel.
+            let y: usize = SCREEN_HEIGHT - 1 - ((offset % 32) * 8 + bit);
+            pixels[y * SCREEN_WIDTH + x] = byte & (1 << bit) != 0;
+        }
+    }
+    return pixels;
+}
+
+/// The picture in borrowed video memory, expanded a pixel at a time as it
+/// is read rather than into a buffer, for frontends drawing every frame.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameView<'a> {
+    vram: &'a [u8],
+}
+
+impl<'a> FrameView<'a> {
+    /// `vram` is the bytes of `VRAM`, in order.
+    pub fn new(vram: &'a [u8]) -> FrameView<'a> {
+        assert_eq!(vram.len(), VRAM.len(), "video memory is {} bytes", VRAM.len());
+        return FrameView { vram };
+    }
+
+    /// Borrows `processor`'s video memory in place.
+    pub fn of(processor: &'a Processor) -> Result<FrameView<'a>, MemoryViewError> {
+        return Ok(FrameView::new(processor.memory_view(VRAM)?));
+    }
+
+    /// Whether the pixel at `x` from the left and `y` from the top is lit.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        // Bit 0 of the first byte in a column is the bottom pixel.
+        let bit: usize = SCREEN_HEIGHT - 1 - y;
+        return self.vram[x * 32 + bit / 8] & (1 << (bit % 8)) != 0;
+    }
+
+    /// The pixels of row `y` from the top, left to right.
+    pub fn row(self, y: usize) -> impl Iterator<Item = bool> + 'a {
+        // A screen row is one bit from each column, 32 bytes apart.
+        let bit: usize = SCREEN_HEIGHT - 1 - y;
+        let mask: u8 = 1 << (bit % 8);
+        return self.vram[bit / 8..].iter().step_by(32).map(move |byte| byte & mask != 0);
+    }
+
+    /// Every pixel in the order `capture` lists them. Drawing a row at a
+    /// time with `row` is faster still.
+    pub fn pixels(self) -> impl Iterator<Item = bool> + 'a {
+        return (0..SCREEN_HEIGHT).flat_map(move |y| self.row(y));
+    }
+}
+
+/// A stable 64-bit hash of the picture: FNV-1a over `pixels`, as `capture`
+/// lists them, packed eight to a byte with the leftmost pixel in bit 7.
+/// It is taken over the expanded picture rather than video memory, so a
+/// change to how video memory is turned into pixels changes the hash.
+pub fn frame_hash(pixels: &[bool]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for chunk in pixels.chunks(8) {
+        let byte: u8 = chunk.iter().enumerate().fold(0, |byte, (bit, lit)| byte | ((*lit as u8) << (7 - bit)));
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    return hash;
+}
+
+/// Draws `pixels` as text, one character per `cell_width` by `cell_height`
+/// block: `#` if any pixel in the block is lit, otherwise a space. Each row
+/// ends with a newline.
+pub fn to_ascii(pixels: &[bool], cell_width: usize, cell_height: usize) -> String {
+    let columns: usize = SCREEN_WIDTH.div_ceil(cell_width);
+    let rows: usize = SCREEN_HEIGHT.div_ceil(cell_height);
+    let mut text: String = String::with_capacity((columns + 1) * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            let lit: bool = (row * cell_height..((row + 1) * cell_height).min(SCREEN_HEIGHT))
+                .any(|y| (column * cell_width..((column + 1) * cell_width).min(SCREEN_WIDTH))
+                    .any(|x| pixels[y * SCREEN_WIDTH + x]));
+            text.push(if lit { '#' } else { ' ' });
+        }
+        text.push('\n');
+    }
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotated_layout() {
+        let pixels = capture(|addr| match addr {
+            0x2400 => 0x01,
+            0x241f => 0x80,
+            0x3fff => 0x80,
+            _ => 0,
+        });
+
+        let lit: Vec<(usize, usize)> = (0..pixels.len()).filter(|i| pixels[*i])
+            .map(|i| (i % SCREEN_WIDTH, i / SCREEN_WIDTH)).collect();
+        assert_eq!(lit, vec![(0, 0), (223, 0), (0, 255)]);
+    }
+
+    #[test]
+    fn test_ascii_cells() {
+        let mut pixels: Vec<bool> = vec![false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        pixels[0] = true;
+        pixels[SCREEN_WIDTH * 255 + 223] = true;
+
+        let text = to_ascii(&pixels, 8, 16);
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines.len(), 16);
+        assert!(lines.iter().all(|line| line.len() == 28));
+        assert_eq!(lines[0], format!("#{}", " ".repeat(27)));
+        assert_eq!(lines[15], format!("{}#", " ".repeat(27)));
+        assert!(lines[1..15].iter().all(|line| line.trim().is_empty()));
+        assert_eq!(to_ascii(&pixels, 5, 5).lines().next().map(str::len), Some(45));
+    }
+
+    #[test]
+    fn test_frame_hash() {
+        let blank: Vec<bool> = vec![false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut top_left: Vec<bool> = blank.clone();
+        top_left[0] = true;
+        let mut top_right: Vec<bool> = blank.clone();
+        top_right[SCREEN_WIDTH - 1] = true;
+
+        // FNV-1a of 7168 zero bytes.
+        assert_eq!(frame_hash(&blank), 0x9cb6_0f4a_8a4e_5325);
+        assert_eq!(frame_hash(&[true, false, false, false, false, false, false, false]), frame_hash(&[true]));
+        let hashes: Vec<u64> = [&blank, &top_left, &top_right].iter().map(|pixels| frame_hash(pixels)).collect();
+        assert!(hashes[0] != hashes[1] && hashes[1] != hashes[2] && hashes[0] != hashes[2]);
+    }
+
+    #[test]
+    fn test_frame_hash_sees_the_rotation() {
+        // The same byte in a different place in the column is a different
+        // picture, and so is the byte with its bits the other way round.
+        let hash = |addr: u16, byte: u8| frame_hash(&capture(|at| if at == addr { byte } else { 0 }));
+
+        assert_ne!(hash(0x2400, 0x01), hash(0x2401, 0x01));
+        assert_ne!(hash(0x2400, 0x01), hash(0x2400, 0x80));
+        assert_ne!(hash(0x2400, 0x01), hash(0x2420, 0x01));
+    }
+
+    /// A processor whose program sets a few pixels of video memory.
+    fn drawn() -> Processor {
+        let mut processor = Processor::default();
+        processor.load_program(&[
+            0x3e, 0x81,       // 0000 MVI A,81h
+            0x32, 0x00, 0x24, // 0002 STA 2400h
+            0x32, 0x3f, 0x30, // 0005 STA 303Fh
+            0x76,             // 0008 HLT
+        ]);
+        return processor;
+    }
+
+    #[test]
+    fn test_frame_view_matches_capture() {
+        let mut processor = drawn();
+        processor.run();
+
+        let view: Vec<bool> = FrameView::of(&processor).unwrap().pixels().collect();
+
+        assert_eq!(view, capture(|addr| processor.peek(addr)));
+        assert_eq!(view.iter().filter(|lit| **lit).count(), 4);
+    }
+
+    #[test]
+    fn test_memory_view_aliases_memory() {
+        let mut processor = drawn();
+        assert!(!FrameView::of(&processor).unwrap().pixel(0, 255));
+
+        processor.run();
+
+        let view = FrameView::of(&processor).unwrap();
+        assert!(view.pixel(0, 255) && view.pixel(0, 248));
+        assert_eq!(processor.memory_view(VRAM).unwrap().as_ptr(), FrameView::of(&processor).unwrap().vram.as_ptr());
+        assert_eq!(processor.memory_view(0x2400..0x2401).unwrap(), &[0x81]);
+    }
+}
@@ -0,0 +1,91 @@
+//! 16-bit values in the 8080's little-endian layout. Everything that splits
+//! or joins words goes through these so the byte order lives in one place.
+
+/// Low byte of `value`.
+pub fn lo(value: u16) -> u8 {
+    return value as u8;
+}
+
+/// High byte of `value`.
+pub fn hi(value: u16) -> u8 {
+    return (value >> 8) as u8;
+}
+
+/// Joins a high and low byte, e.g. `word(h, l)` for HL.
+pub fn word(high: u8, low: u8) -> u16 {
+    return ((high as u16) << 8) | low as u16;
+}
+
+/// Reads the little-endian word at `addr`, low byte first. The high byte
+/// wraps from 0xffff to 0x0000.
+pub fn read_word(mut read: impl FnMut(u16) -> u8, addr: u16) -> u16 {
+    let low: u8 = read(addr);
+    let high: u8 = read(addr.wrapping_add(1));
+    return word(high, low);
+}
+
+/// Writes `value` little-endian at `addr`, low byte first, wrapping like
+/// `read_word`.
+pub fn write_word(mut write: impl FnMut(u16, u8), addr: u16, value: u16) {
+    write(addr, lo(value));
+    write(addr.wrapping_add(1), hi(value));
+}
+
+/// Parses `0x1f`, `1fh` or decimal.
+pub fn parse_number(text: &str) -> Option<u16> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+    if let Some(hex) = lower.strip_suffix('h') {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+    return lower.parse::<u16>().ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("0x2018"), Some(0x2018));
+        assert_eq!(parse_number("2018h"), Some(0x2018));
+        assert_eq!(parse_number("12"), Some(12));
+        assert_eq!(parse_number("zz"), None);
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        assert_eq!(lo(0x1234), 0x34);
+        assert_eq!(hi(0x1234), 0x12);
+        assert_eq!(word(0x12, 0x34), 0x1234);
+        assert_eq!(word(hi(0xbeef), lo(0xbeef)), 0xbeef);
+    }
+
+    #[test]
+    fn test_read_and_write_order() {
+        let mut memory: Vec<u8> = vec![0; 0x10000];
+        let mut order: Vec<u16> = Vec::new();
+
+        write_word(|addr, value| {
+            order.push(addr);
+            memory[addr as usize] = value;
+        }, 0x2000, 0xabcd);
+
+        assert_eq!(order, vec![0x2000, 0x2001]);
+        assert_eq!(&memory[0x2000..0x2002], &[0xcd, 0xab]);
+        assert_eq!(read_word(|addr| memory[addr as usize], 0x2000), 0xabcd);
+    }
+
+    #[test]
+    fn test_wraps_at_top_of_memory() {
+        let mut memory: Vec<u8> = vec![0; 0x10000];
+
+        write_word(|addr, value| memory[addr as usize] = value, 0xffff, 0x1234);
+
+        assert_eq!(memory[0xffff], 0x34);
+        assert_eq!(memory[0x0000], 0x12);
+        assert_eq!(read_word(|addr| memory[addr as usize], 0xffff), 0x1234);
+    }
+}
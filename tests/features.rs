@@ -0,0 +1,51 @@
+#![allow(clippy::needless_return)]
+
+//! Builds and runs the library's tests with no default features and with
+//! each feature on its own, so a module or test reaching for one its
+//! feature does not enable shows up here rather than in someone's minimal
+//! build. Warnings count, since an import only one feature uses is the
+//! usual slip. Uses its own target directory so it does not wait on the
+//! build running it. Slow, so it only runs when asked for:
+//! `cargo test --test features -- --ignored`.
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// The combinations tested, beyond the default ones `cargo test` builds.
+const MATRIX: &[&str] = &[
+    "",
+    "core",
+    "debugger",
+    "disasm",
+    "asm",
+    "cpm",
+    "machines",
+    "devices",
+    "serde-state",
+    "async",
+    "disasm asm",
+    "machines serde-state",
+    "debugger devices serde-state",
+];
+
+fn run_tests(features: &str) -> Output {
+    let manifest_dir: &Path = Path::new(env!("CARGO_MANIFEST_DIR"));
+    return Command::new(env!("CARGO"))
+        .args(["test", "--lib", "--quiet", "--no-default-features", "--features", features])
+        .current_dir(manifest_dir)
+        .env("CARGO_TARGET_DIR", manifest_dir.join("target").join("features"))
+        .env("RUSTFLAGS", "-D warnings")
+        .output()
+        .expect("cargo runs");
+}
+
+#[test]
+#[ignore = "builds the library once per combination; run with --ignored"]
+fn test_feature_combinations_pass() {
+    let failed: Vec<String> = MATRIX.iter().filter_map(|features| {
+        let output: Output = run_tests(features);
+        return (!output.status.success())
+            .then(|| format!("--features \"{}\":\n{}{}", features, String::from_utf8_lossy(&output.stderr), String::from_utf8_lossy(&output.stdout)));
+    }).collect();
+    assert!(failed.is_empty(), "{}", failed.join("\n"));
+}
@@ -0,0 +1,44 @@
+#![allow(clippy::needless_return)]
+
+//! Uses nothing but the prelude, so this stops compiling if the prelude
+//! stops being enough to run a program, break in it and attach a device.
+
+use intel_8080_emu::prelude::*;
+
+/// Reads back the last byte written to it.
+#[derive(Debug, Default)]
+struct Latch {
+    value: u8,
+}
+
+impl IoDevice for Latch {
+    fn read(&mut self, _port: u16, _ctx: &IoContext) -> u8 {
+        return self.value;
+    }
+
+    fn write(&mut self, _port: u16, value: u8, _ctx: &IoContext) {
+        self.value = value;
+    }
+}
+
+#[test]
+fn test_prelude_is_enough_to_run_break_and_attach() {
+    let mut processor: Processor = ProcessorBuilder::new().device(&[0x10], Box::new(Latch::default())).build();
+    processor.load_program(&[
+        0x3e, 0x2a, // 0000 MVI A,2Ah
+        0xd3, 0x10, // 0002 OUT 10h
+        0xaf,       // 0004 XRA A
+        0xdb, 0x10, // 0005 IN 10h
+        0x76,       // 0007 HLT
+    ]);
+    processor.add_breakpoint(0x0005);
+
+    assert_eq!(processor.run(), RunOutcome::Breakpoint { pc: 0x0005 });
+    let registers: Registers = processor.registers();
+    assert_eq!(registers.a, 0);
+    assert!(registers.flags.zero);
+
+    let step: StepInfo = processor.steps().on_breakpoint(BreakpointPolicy::Yield).next().unwrap();
+    assert_eq!((step.pc, step.after.a), (0x0005, 0x2a));
+    assert_eq!(processor.run(), RunOutcome::Halted);
+}
@@ -0,0 +1,17 @@
+#![allow(clippy::needless_return)]
+
+//! Runs every case `intel_8080_emu repro` wrote to `tests/repro/`. A case
+//! fails until the bug it was cut from is fixed, then keeps it fixed.
+
+use std::path::{Path, PathBuf};
+
+use intel_8080_emu::repro::{self, ReproCase, REPRO_DIR};
+
+#[test]
+fn test_repro_cases() {
+    let cases: Vec<(PathBuf, ReproCase)> = repro::load_dir(&Path::new(env!("CARGO_MANIFEST_DIR")).join(REPRO_DIR)).unwrap();
+    let failed: Vec<String> = cases.iter().filter_map(|(path, case)| {
+        return case.check(&mut case.processor()).err().map(|err| format!("{}: {}", path.display(), err));
+    }).collect();
+    assert!(failed.is_empty(), "{}", failed.join("\n"));
+}
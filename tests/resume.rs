@@ -0,0 +1,91 @@
+#![allow(clippy::needless_return)]
+
+//! Checkpoints a run with `intel_8080_emu`, resumes it, and checks it ends
+//! where the same run without the break does, or that `repro` refuses it.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command, Output};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("i8080-cli-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    return dir;
+}
+
+/// The final state `--json` prints.
+fn final_state(args: &[&str]) -> String {
+    let output: Output = Command::new(env!("CARGO_BIN_EXE_intel_8080_emu")).args(args).output().unwrap();
+    let stdout: String = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(output.status.success(), "{:?}: {}{}", args, stdout, String::from_utf8_lossy(&output.stderr));
+    return stdout.lines().last().unwrap_or_default().to_string();
+}
+
+/// Runs `program` with `options` for `total` instructions, then again
+/// checkpointing after `at` and resuming the rest, and returns both final
+/// states.
+fn uninterrupted_and_resumed(name: &str, program: &[u8], options: &[&str], at: u64, total: u64) -> (String, String) {
+    let dir: PathBuf = temp_dir(name);
+    let program_path: PathBuf = dir.join("program.bin");
+    fs::write(&program_path, program).unwrap();
+    let program_path: &str = program_path.to_str().unwrap();
+    let checkpoints: PathBuf = dir.join("checkpoints");
+    let (at, total, rest) = (at.to_string(), total.to_string(), (total - at).to_string());
+
+    let uninterrupted: String = final_state(&[options, &["--json", "--max-instructions", &total, program_path]].concat());
+    final_state(&[options, &["--json", "--max-instructions", &at, "--checkpoint-dir", checkpoints.to_str().unwrap(),
+                             "--checkpoint-interval", &at, program_path]].concat());
+    let checkpoint: PathBuf = fs::read_dir(&checkpoints).unwrap().map(|entry| entry.unwrap().path()).max().unwrap();
+    let resumed: String = final_state(&[&["resume"], options, &["--json", "--max-instructions", &rest, checkpoint.to_str().unwrap()]].concat());
+    fs::remove_dir_all(&dir).unwrap();
+    return (uninterrupted, resumed);
+}
+
+#[test]
+fn test_resume_with_seed_continues_the_entropy_stream() {
+    let program: [u8; 8] = [
+        0xdb, 0x50,       // 0000 IN 50h
+        0x32, 0x00, 0x20, // 0002 STA 2000h
+        0xc3, 0x00, 0x00, // 0005 JMP 0000h
+    ];
+
+    let (uninterrupted, resumed) = uninterrupted_and_resumed("seed", &program, &["--seed", "7"], 20, 30);
+
+    assert_eq!(resumed, uninterrupted);
+}
+
+#[test]
+fn test_resume_with_device_specs_keeps_their_state() {
+    let program: [u8; 10] = [
+        0xd3, 0xf4,       // 0000 OUT F4h: latch the instruction count
+        0xdb, 0xf4,       // 0002 IN F4h
+        0x32, 0x00, 0x20, // 0004 STA 2000h
+        0xc3, 0x00, 0x00, // 0007 JMP 0000h
+    ];
+    let options: [&str; 4] = ["--device", "perf_counters", "--device", "rtc:epoch=1983-06-01T09:00:00Z"];
+
+    // The checkpoint falls between the OUT that latches and the IN that reads.
+    let (uninterrupted, resumed) = uninterrupted_and_resumed("devices", &program, &options, 17, 19);
+
+    assert_eq!(resumed, uninterrupted);
+    assert!(uninterrupted.contains(r#""a":16"#), "{}", uninterrupted);
+}
+
+#[test]
+fn test_repro_refuses_a_checkpoint_with_devices() {
+    let dir: PathBuf = temp_dir("repro");
+    let program_path: PathBuf = dir.join("program.bin");
+    fs::write(&program_path, [0xdb, 0x50, 0xc3, 0x00, 0x00]).unwrap(); // IN 50h; JMP 0000h
+    let checkpoints: PathBuf = dir.join("checkpoints");
+    final_state(&["--seed", "7", "--json", "--max-instructions", "4", "--checkpoint-dir", checkpoints.to_str().unwrap(),
+                  program_path.to_str().unwrap()]);
+    let checkpoint: PathBuf = fs::read_dir(&checkpoints).unwrap().map(|entry| entry.unwrap().path()).max().unwrap();
+
+    let output: Output = Command::new(env!("CARGO_BIN_EXE_intel_8080_emu")).arg("repro").arg(&checkpoint).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).ends_with("was taken with 1 devices attached; repro replays without devices\n"));
+}
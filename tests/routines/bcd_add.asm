@@ -0,0 +1,19 @@
+  ; b: number of bytes in each number, at least 1
+  ; de: source number
+  ; hl: target number, replaced by the sum
+  ; numbers are packed BCD, least significant byte first
+  ; returns a = the carry out of the top digit
+bcd_add:
+  ora     a           ;No carry into the first digit
+bcd_add_loop:
+  ldax    de
+  adc     m
+  daa                 ;Correct the binary sum to two decimal digits
+  mov     m, a
+  inx     hl          ;inx and dcr leave carry alone
+  inx     de
+  dcr     b
+  jnz     bcd_add_loop
+  mvi     a, 0
+  aci     0           ;a = carry
+  ret
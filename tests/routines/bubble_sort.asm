@@ -0,0 +1,39 @@
+  ; b: number of bytes
+  ; hl: the bytes, sorted in place, smallest first
+  ; each pass swaps neighbours that are out of order, carrying the largest
+  ; byte left to the end, so the next pass can stop one byte earlier
+bubble_sort:
+  shld    bubble_sort_base
+  mov     a, b        ;Nothing to do for fewer than 2 bytes
+  cpi     2
+  rc
+bubble_sort_pass:
+  lhld    bubble_sort_base
+  mov     d, h        ;de: this byte, hl: the next one
+  mov     e, l
+  inx     hl
+  mov     c, b        ;Comparisons this pass
+  dcr     c
+bubble_sort_compare:
+  ldax    de
+  cmp     m
+  jc      bubble_sort_next  ;Already in order
+  jz      bubble_sort_next
+  push    psw         ;Swap the two bytes
+  mov     a, m
+  stax    de
+  pop     psw
+  mov     m, a
+bubble_sort_next:
+  inx     hl
+  inx     de
+  dcr     c
+  jnz     bubble_sort_compare
+  dcr     b           ;The largest byte is now at the end
+  mov     a, b
+  cpi     2
+  jnc     bubble_sort_pass
+  ret
+
+bubble_sort_base:
+  dw 0
@@ -0,0 +1,39 @@
+  ; bc = bc / de, hl = bc % de, for de other than 0
+  ; restoring division: shifts the dividend out of bc into hl a bit at a
+  ; time, and shifts a 1 into bc each time de fits into hl
+div16:
+  lxi     hl, 0
+  mvi     a, 16       ;Bits left in the dividend
+div16_loop:
+  push    psw         ;Save the count
+  mov     a, c        ;Shift bc left, top bit into carry
+  add     a
+  mov     c, a
+  mov     a, b
+  adc     a
+  mov     b, a
+  mov     a, l        ;Shift hl left, taking the carry
+  adc     a
+  mov     l, a
+  mov     a, h
+  adc     a
+  mov     h, a
+  jc      div16_subtract  ;A 17 bit remainder is always at least de
+  mov     a, l        ;Compare hl with de
+  sub     e
+  mov     a, h
+  sbb     d
+  jc      div16_next  ;de does not fit
+div16_subtract:
+  mov     a, l        ;hl = hl - de
+  sub     e
+  mov     l, a
+  mov     a, h
+  sbb     d
+  mov     h, a
+  inr     c           ;Set the quotient bit just shifted in
+div16_next:
+  pop     psw
+  dcr     a
+  jnz     div16_loop
+  ret
@@ -0,0 +1,10 @@
+; guest_log: logs the NUL-terminated string at HL with severity A
+; (0 debug, 1 info, 2 warn, 3 error) through the guest log device at
+; ports e0h-e1h. Preserves every register but A.
+guest_log:
+    out 0e0h        ; severity
+    mov a, l
+    out 0e1h        ; string address, low byte first
+    mov a, h
+    out 0e1h        ; the high byte sends the message
+    ret
@@ -0,0 +1,20 @@
+  ; bc: number of bytes to compare
+  ; de, hl: the two blocks
+  ; returns a = 0 if they match, otherwise 1 if the first differing byte
+  ; is greater in the hl block and 0ffh if it is less
+memcmp:
+  mov     a, b        ;Done when bc is zero
+  ora     c
+  rz                  ;a is already 0
+  ldax    de
+  cmp     m           ;Carry if the hl byte is greater
+  jnz     memcmp_differ
+  inx     hl
+  inx     de
+  dcx     bc
+  jmp     memcmp
+memcmp_differ:
+  mvi     a, 1        ;mvi leaves the flags alone
+  rc
+  mvi     a, 0ffh
+  ret
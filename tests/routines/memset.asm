@@ -0,0 +1,13 @@
+  ; a: value to store
+  ; bc: number of bytes to fill
+  ; hl: target block
+memset:
+  mov     e, a        ;Keep the value, a is needed to test bc
+memset_loop:
+  mov     a, b        ;Done when bc is zero
+  ora     c
+  rz
+  mov     m, e
+  inx     hl
+  dcx     bc
+  jmp     memset_loop
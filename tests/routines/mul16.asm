@@ -0,0 +1,22 @@
+  ; hl = bc * de, modulo 10000h
+  ; shifts the multiplier out of de a bit at a time, most significant
+  ; first, doubling hl each time and adding bc for each set bit
+mul16:
+  lxi     hl, 0
+  mvi     a, 16       ;Bits left in the multiplier
+mul16_loop:
+  push    psw         ;Save the count
+  dad     hl          ;Double the product so far
+  mov     a, e        ;Shift de left, top bit into carry
+  add     a
+  mov     e, a
+  mov     a, d
+  adc     a
+  mov     d, a
+  jnc     mul16_next  ;Skip the add for a clear bit
+  dad     bc
+mul16_next:
+  pop     psw
+  dcr     a
+  jnz     mul16_loop
+  ret
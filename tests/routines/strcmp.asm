@@ -0,0 +1,17 @@
+  ; de, hl: the two strings, each ending in a 0 byte
+  ; returns a = 0 if they match, otherwise 1 if the first differing byte
+  ; is greater in the hl string and 0ffh if it is less
+strcmp:
+  ldax    de
+  cmp     m           ;Carry if the hl byte is greater
+  jnz     strcmp_differ
+  ora     a           ;Both strings end here if the byte is 0
+  rz
+  inx     hl
+  inx     de
+  jmp     strcmp
+strcmp_differ:
+  mvi     a, 1        ;mvi leaves the flags alone
+  rc
+  mvi     a, 0ffh
+  ret
@@ -0,0 +1,17 @@
+#![allow(clippy::needless_return)]
+
+//! Runs `intel_8080_emu --selftest` as a user would.
+
+use std::process::{Command, Output};
+
+#[test]
+fn test_selftest_passes() {
+    let output: Output = Command::new(env!("CARGO_BIN_EXE_intel_8080_emu")).arg("--selftest").output().unwrap();
+    let stdout: String = String::from_utf8_lossy(&output.stdout).into_owned();
+
+    assert!(output.status.success(), "{}", stdout);
+    for name in ["arithmetic", "stack", "branches", "memory", "video"] {
+        assert!(stdout.contains(&format!("PASS {}\n", name)), "{}", stdout);
+    }
+    assert!(!stdout.contains("FAIL"), "{}", stdout);
+}